@@ -98,7 +98,7 @@ fn parse_derive(s: Structure) -> TokenStream {
 
     // extern crate parse_derive;
     let name = &s.ast().ident;
-    let real_impl = quote! {
+    quote! {
         impl #impl_gen Parse<#parse_lt> for #name #ty_gen #where_clause {
             fn approx_file_size() -> usize {
                 #size_body
@@ -112,19 +112,6 @@ fn parse_derive(s: Structure) -> TokenStream {
                 (#buf_var, val)
             }
         }
-    };
-
-    use proc_macro2::{Ident, Span};
-    // Use hygene
-    let const_name = Ident::new(
-        &("_DERIVE_Parse_a_FOR_".to_string() + &name.to_string()),
-        Span::call_site(),
-    );
-    quote! {
-        #[allow(non_upper_case_globals)]
-        const #const_name: () = {
-            #real_impl
-        };
     }
 }
 
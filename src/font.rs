@@ -6,29 +6,84 @@ use tables::loca::Loca;
 use tables::glyf::Glyph;
 use tables::hmtx::HMTX;
 use tables::vmtx::VMTX;
+use tables::feat::AatFeatureInfo;
+use tables::graphite_feat::GraphiteFeature;
+use tables::fvar::{NamedInstance, Variation, VariationAxis};
+use tables::layout::{LayoutInfo, Tag};
 use tables::{ParseTableError, ParseTableErrorInner, PrimaryTable};
 use render::*;
 use render::compositor::{GlyphPlacementMetrics, RenderedText, TextRenderMetrics};
-use image::GrayImage;
-use math::Affine;
+use image::{GrayImage, Rgba, RgbaImage};
+use math::{Affine, Point};
+use tables::glyf::Coordinate;
 
 // TODO: Canonical glyph_id type
 
+/// The index of the strike to draw `ppem` from: the smallest that is at
+/// least as big, so it only needs shrinking, or else the biggest
+fn best_strike<I: Iterator<Item = u16>>(strike_ppems: I, ppem: u16) -> Option<usize> {
+    let strike_ppems: Vec<u16> = strike_ppems.collect();
+    let bigger = strike_ppems.iter().enumerate()
+        .filter(|&(_, &strike_ppem)| strike_ppem >= ppem)
+        .min_by_key(|&(_, &strike_ppem)| strike_ppem);
+    bigger.or_else(|| strike_ppems.iter().enumerate().max_by_key(|&(_, &strike_ppem)| strike_ppem))
+        .map(|(idx, _)| idx)
+}
+
+/// Resize a bitmap glyph and its bearings by `scale`
+fn scale_color_bitmap(glyph: ColorBitmapGlyph, scale: f32) -> ColorBitmapGlyph {
+    use image::{imageops, FilterType};
+
+    if scale == 1. {
+        return glyph;
+    }
+    let width = (glyph.image.width() as f32 * scale).round().max(1.) as u32;
+    let height = (glyph.image.height() as f32 * scale).round().max(1.) as u32;
+    ColorBitmapGlyph {
+        image: imageops::resize(&glyph.image, width, height, FilterType::Triangle),
+        left_bearing: glyph.left_bearing * scale,
+        top_bearing: glyph.top_bearing * scale,
+    }
+}
+
 pub struct Font<'file> {
     buf: &'file [u8],
     pub(crate) font_dir: FontDirectory<'file>,
+    /// Normalized variation coordinates, one per `fvar` axis. Empty means
+    /// the default instance.
+    pub(crate) coords: Vec<f32>,
 }
 
 impl<'a> Font<'a> {
     pub fn from_buffer(buf: &'a [u8]) -> Result<Font<'a>, ReadFontError> {
         let font_dir = parse_font_directory(buf)?.1;
 
-        let font = Font { buf, font_dir };
+        let font = Font {
+            buf,
+            font_dir,
+            coords: Vec::new(),
+        };
 
         Ok(font)
     }
 
 
+    /// Every table's tag and data, in directory order
+    pub(crate) fn tables(&self) -> Vec<([u8; 4], &'a [u8])> {
+        use byteorder::{BigEndian, ByteOrder};
+
+        (0..self.font_dir.offsets.num_tables as usize)
+            .map(|idx| {
+                let record = &self.font_dir.table_dir_start[idx * 16..];
+                let mut tag = [0; 4];
+                tag.copy_from_slice(&record[..4]);
+                let offset = BigEndian::read_u32(&record[8..]) as usize;
+                let length = BigEndian::read_u32(&record[12..]) as usize;
+                (tag, &self.buf[offset..offset + length])
+            })
+            .collect()
+    }
+
     fn get_table_slice<T: PrimaryTable>(&self) -> Option<&'a [u8]> {
         self.font_dir
             .table_record::<T>()
@@ -39,13 +94,139 @@ impl<'a> Font<'a> {
 
     pub fn get_glyph_for_id(&self, glyph_id: u32) -> Option<Glyph<'a>> {
         use tables::glyf::Glyf;
+        if self.font_dir.table_record::<Glyf>().is_none() {
+            return self.cff_glyph(glyph_id);
+        }
         let loca: Loca = self.get_table()?;
         let glyf: Glyf = self.get_table()?;
 
         let glyph_offset = loca.at(glyph_id as usize)?;
         println!("\tGlyph_offset = {}", glyph_offset);
 
-        glyf.at_offset(glyph_offset as usize)
+        let mut glyph = glyf.at_offset(glyph_offset as usize)?;
+        glyph.deltas = self.glyph_deltas(glyph_id, &glyph);
+        glyph.glyph_id = Some(glyph_id);
+        Some(glyph)
+    }
+
+    /// A glyph from the `CFF ` or `CFF2` table, for fonts with PostScript
+    /// outlines
+    fn cff_glyph(&self, glyph_id: u32) -> Option<Glyph<'a>> {
+        use tables::cff::Cff;
+        use tables::cff2::Cff2;
+        let cff: Option<Cff> = self.get_table();
+        let commands = match cff {
+            Some(cff) => cff.glyph_outline(glyph_id)?,
+            None => {
+                let cff2: Cff2 = self.get_table()?;
+                cff2.glyph_outline(glyph_id, &self.coords)?
+            },
+        };
+        let mut glyph = Glyph::from_commands(commands)?;
+        glyph.glyph_id = Some(glyph_id);
+        Some(glyph)
+    }
+
+    /// The `gvar` deltas for the glyph's points (or component offsets),
+    /// followed by its four phantom points
+    fn glyph_deltas(&self, glyph_id: u32, glyph: &Glyph<'a>) -> Option<Vec<(f32, f32)>> {
+        use tables::glyf::Description;
+        use tables::gvar::Gvar;
+
+        if self.coords.is_empty() {
+            return None;
+        }
+        let gvar: Gvar = self.get_table()?;
+
+        let (mut points, contour_ends): (Vec<_>, Vec<_>) = match glyph.desc {
+            Description::Simple(ref simple) => (
+                simple.coordinates().map(|coord| (coord.x as f32, coord.y as f32)).collect(),
+                simple.contour_end_points().map(|end| end as usize).collect(),
+            ),
+            // Component offsets are never interpolated
+            Description::Composite(ref composite) => (
+                composite.coordinates()
+                    .map(|(_, affine)| (affine.translation[0], affine.translation[1]))
+                    .collect(),
+                Vec::new(),
+            ),
+            Description::Cubic(_) => return None,
+        };
+        points.extend_from_slice(&self.phantom_points(glyph_id, glyph));
+
+        gvar.glyph_deltas(glyph_id, &self.coords, &points, &contour_ends)
+    }
+
+    /// The horizontal origin and advance, then the vertical origin and
+    /// advance, of an unvaried glyph
+    pub(crate) fn phantom_points(&self, glyph_id: u32, glyph: &Glyph<'a>) -> [(f32, f32); 4] {
+        let (left_bearing, advance_width) = match self.get_table() {
+            Some(hmtx) => {
+                let hmtx: HMTX = hmtx;
+                let left_bearing = hmtx.metrics_for_glyph(glyph_id).left_bearing.0;
+                (left_bearing as f32, hmtx.advance_width(glyph_id).0 as f32)
+            },
+            None => (glyph.header.x_min as f32, 0.),
+        };
+        let (top_bearing, advance_height) = match self.get_table() {
+            Some(vmtx) => {
+                let vmtx: VMTX = vmtx;
+                let metrics = vmtx.metrics_for_glyph(glyph_id);
+                let advance_height = metrics.advance_height.map_or(0., |advance| advance.0 as f32);
+                (metrics.top_bearing.0 as f32, advance_height)
+            },
+            None => (0., 0.),
+        };
+
+        let left = glyph.header.x_min as f32 - left_bearing;
+        let top = glyph.header.y_max as f32 + top_bearing;
+        [(left, 0.), (left + advance_width, 0.), (0., top), (0., top - advance_height)]
+    }
+
+    /// The glyph's bounding box with variations applied, as
+    /// `[x_min, y_min, x_max, y_max]`
+    pub(crate) fn glyph_bounds(&self, glyph: &Glyph<'a>) -> [i16; 4] {
+        use tables::glyf::Description;
+
+        let header = [glyph.header.x_min, glyph.header.y_min, glyph.header.x_max, glyph.header.y_max];
+        if self.coords.is_empty() {
+            return header;
+        }
+
+        let mut points = Vec::new();
+        match glyph.desc {
+            Description::Simple(ref simple) => {
+                let deltas = glyph.deltas.as_ref().map(|deltas| &deltas[..]);
+                for (idx, coord) in simple.coordinates().enumerate() {
+                    let coord = vary_coordinate(coord, deltas.and_then(|deltas| deltas.get(idx)));
+                    points.push(Point { x: coord.x as f32, y: coord.y as f32 });
+                }
+            },
+            Description::Composite(ref composite) => {
+                for (idx, (sub_idx, sub_affine)) in composite.coordinates().enumerate() {
+                    let sub_affine = vary_offset(sub_affine, glyph.deltas.as_ref().and_then(|deltas| deltas.get(idx)));
+                    let sub_glyph = match self.get_glyph_for_id(sub_idx as u32) {
+                        Some(sub_glyph) => sub_glyph,
+                        None => continue,
+                    };
+                    let sub_bounds = self.glyph_bounds(&sub_glyph);
+                    let (x_min, y_min, x_max, y_max) = (sub_bounds[0], sub_bounds[1], sub_bounds[2], sub_bounds[3]);
+                    for &corner in &[(x_min, y_min), (x_min, y_max), (x_max, y_min), (x_max, y_max)] {
+                        points.push(sub_affine * corner);
+                    }
+                }
+            },
+            Description::Cubic(_) => (),
+        }
+
+        if points.is_empty() {
+            return header;
+        }
+        let x_min = points.iter().map(|point| point.x).fold(::std::f32::INFINITY, f32::min);
+        let y_min = points.iter().map(|point| point.y).fold(::std::f32::INFINITY, f32::min);
+        let x_max = points.iter().map(|point| point.x).fold(::std::f32::NEG_INFINITY, f32::max);
+        let y_max = points.iter().map(|point| point.y).fold(::std::f32::NEG_INFINITY, f32::max);
+        [x_min.floor() as i16, y_min.floor() as i16, x_max.ceil() as i16, y_max.ceil() as i16]
     }
 
     pub fn get_glyph(&self, code_point: char) -> Option<Glyph<'a>> {
@@ -70,73 +251,307 @@ impl<'a> Font<'a> {
         }
     }
 
-    pub fn render_glyph(&self, glyph: Glyph<'a>, size: usize) -> GrayImage {
+    /// Renders the glyph at `size` pixels per em, with its origin moved by
+    /// `offset` pixels, e.g. `[0.25, 0.]` for a pen a quarter of the way
+    /// into a pixel. The image's bottom left corner is the pixel the
+    /// glyph's `(x_min, y_min)` falls in, counting from the origin's pixel.
+    /// Uses the font's embedded bitmap for that size instead of the outline
+    /// if it has one. Bitmaps can't be moved, and are placed by their own
+    /// bearings (see `embedded_bitmap_glyph`).
+    pub fn render_glyph(&self, glyph: Glyph<'a>, size: usize, offset: [f32; 2]) -> GrayImage {
+        self.render_glyph_with_fill_rule(glyph, size, offset, FillRule::NonZero)
+    }
+
+    /// Like `render_glyph`, filling the outline with `fill_rule`. Font
+    /// outlines are meant to be non-zero, but ones converted from elsewhere
+    /// may not be.
+    pub fn render_glyph_with_fill_rule(&self, glyph: Glyph<'a>, size: usize, offset: [f32; 2],
+                                       fill_rule: FillRule) -> GrayImage {
         use tables::head::Head;
+        use image::imageops::flip_vertical;
 
-        let width = glyph.header.x_max - glyph.header.x_min;
-        let height = glyph.header.y_max - glyph.header.y_min;
-        let x_shift = -glyph.header.x_min;
-        let y_shift = -glyph.header.y_min;
-        let affine = Affine::translation(x_shift, y_shift);
+        if let Some(bitmap) = glyph.glyph_id.and_then(|glyph_id| self.embedded_bitmap(glyph_id, size as u16)) {
+            // Rows go up from the bottom, like rasterized outlines
+            return flip_vertical(&bitmap);
+        }
 
         let head: Head = self.get_table().unwrap();
         let scale = size as f32 / head.units_per_em as f32;
-        let affine = Affine::scale(scale, scale) * affine;
-        // let width = (width as f32 * scale).ceil();
-        // let height = (height as f32 * scale).ceil();
-        let width = {
-            let w: FontUnit<_> = width.into();
-            w.to_pixels_using_font(self, size).ceil()
-        };
-        let height = {
-            let h: FontUnit<_> = height.into();
-            h.to_pixels_using_font(self, size).ceil()
-        };
+        let bounds = self.glyph_bounds(&glyph);
+        // Whole pixels, so the fraction of the offset stays in the image
+        let x_min = (bounds[0] as f32 * scale + offset[0]).floor();
+        let y_min = (bounds[1] as f32 * scale + offset[1]).floor();
+        let x_max = (bounds[2] as f32 * scale + offset[0]).ceil();
+        let y_max = (bounds[3] as f32 * scale + offset[1]).ceil();
+        let affine = Affine::translation(offset[0] - x_min, offset[1] - y_min) * Affine::scale(scale, scale);
+        let (width, height) = (x_max - x_min, y_max - y_min);
 
         println!("Raster (w, h) = ({}, {})", width as u32, height as u32);
-        let mut raster = FillInRaster::new(width as u32, height as u32);
+        let mut raster = CoverageRaster::with_fill_rule(width as u32, height as u32, fill_rule);
 
         self.render_glyph_inner(&mut raster, affine, glyph);
 
         raster.into_dynamic().to_luma()
     }
 
+    /// Renders the glyph in black and white at `ppem`, grid-fitted by its
+    /// instructions. Strokes thinner than a pixel are kept the way the
+    /// instructions ask with `SCANCTRL` and `SCANTYPE`. Row 0 is the bottom.
+    /// `None` if the glyph can't be hinted.
+    pub fn render_glyph_monochrome(&self, glyph_id: u32, ppem: u16) -> Option<GrayImage> {
+        use hinting::{Hinter, InterpreterVersion};
+
+        let mut hinter = Hinter::new(self, ppem, InterpreterVersion::V35)?;
+        Some(hinter.hint_glyph(glyph_id)?.render_monochrome())
+    }
+
+    /// The glyph's bitmap from the `EBLC`/`EBDT` strike for `ppem`, or
+    /// scaled from another strike if `EBSC` says to. Row 0 is the top.
+    pub fn embedded_bitmap(&self, glyph_id: u32, ppem: u16) -> Option<GrayImage> {
+        self.embedded_bitmap_glyph(glyph_id, ppem).map(|glyph| glyph.image)
+    }
+
+    /// Like `embedded_bitmap`, with the strike's bearings
+    pub fn embedded_bitmap_glyph(&self, glyph_id: u32, ppem: u16) -> Option<BitmapGlyph> {
+        use tables::ebdt::Ebdt;
+        use tables::eblc::Eblc;
+        use tables::ebsc::Ebsc;
+        use image::{imageops, FilterType};
+
+        let eblc: Eblc = self.get_table()?;
+        let ebdt: Ebdt = self.get_table()?;
+        let strikes = eblc.strikes();
+        let strike_bitmap = |ppem: u16| strikes.iter()
+            .filter(|strike| strike.ppem_y as u16 == ppem)
+            .filter_map(|strike| ebdt.glyph_bitmap(&eblc, strike, glyph_id as u16))
+            .next()
+            .map(|bitmap| BitmapGlyph {
+                image: bitmap.to_image(),
+                left_bearing: bitmap.metrics.hori_bearing_x as f32,
+                top_bearing: bitmap.metrics.hori_bearing_y as f32,
+            });
+
+        if let Some(glyph) = strike_bitmap(ppem) {
+            return Some(glyph);
+        }
+        let ebsc: Ebsc = self.get_table()?;
+        let scale = ebsc.scales().into_iter().find(|scale| scale.ppem_y as u16 == ppem)?;
+        if scale.substitute_ppem_x == 0 || scale.substitute_ppem_y == 0 {
+            return None;
+        }
+        let glyph = strike_bitmap(scale.substitute_ppem_y as u16)?;
+        let scaled = |len: u32, to: u8, from: u8| ((len * to as u32) as f32 / from as f32).round().max(1.) as u32;
+        let width = scaled(glyph.image.width(), scale.ppem_x, scale.substitute_ppem_x);
+        let height = scaled(glyph.image.height(), scale.ppem_y, scale.substitute_ppem_y);
+        Some(BitmapGlyph {
+            image: imageops::resize(&glyph.image, width, height, FilterType::Nearest),
+            left_bearing: glyph.left_bearing * scale.ppem_x as f32 / scale.substitute_ppem_x as f32,
+            top_bearing: glyph.top_bearing * scale.ppem_y as f32 / scale.substitute_ppem_y as f32,
+        })
+    }
+
+    /// The glyph's color image from `CBDT` or `sbix`, scaled from the
+    /// strike closest to `ppem`
+    pub fn color_bitmap(&self, glyph_id: u32, ppem: u16) -> Option<ColorBitmapGlyph> {
+        self.cbdt_bitmap(glyph_id, ppem).or_else(|| self.sbix_bitmap(glyph_id, ppem))
+    }
+
+    fn cbdt_bitmap(&self, glyph_id: u32, ppem: u16) -> Option<ColorBitmapGlyph> {
+        use tables::cbdt::Cbdt;
+        use tables::cblc::Cblc;
+        use image::{load_from_memory_with_format, ImageFormat};
+
+        let cblc: Cblc = self.get_table()?;
+        let cbdt: Cbdt = self.get_table()?;
+        let strikes: Vec<_> = cblc.strikes().into_iter()
+            .filter(|strike| cblc.glyph_location(strike, glyph_id as u16).is_some())
+            .collect();
+        let strike = &strikes[best_strike(strikes.iter().map(|strike| strike.ppem_y as u16), ppem)?];
+        let (metrics, png) = cbdt.glyph_png(&cblc, strike, glyph_id as u16)?;
+        let image = load_from_memory_with_format(png, ImageFormat::PNG).ok()?.to_rgba();
+
+        let scale = ppem as f32 / strike.ppem_y as f32;
+        Some(scale_color_bitmap(ColorBitmapGlyph {
+            image,
+            left_bearing: metrics.hori_bearing_x as f32,
+            top_bearing: metrics.hori_bearing_y as f32,
+        }, scale))
+    }
+
+    fn sbix_bitmap(&self, glyph_id: u32, ppem: u16) -> Option<ColorBitmapGlyph> {
+        use tables::maxp::MaxP;
+        use tables::sbix::Sbix;
+        use image::{load_from_memory_with_format, ImageFormat};
+
+        let sbix: Sbix = self.get_table()?;
+        let maxp: MaxP = self.get_table()?;
+        let strikes: Vec<_> = sbix.strikes(maxp.num_glyphs).into_iter()
+            .filter_map(|strike| strike.glyph(glyph_id as u16).map(|glyph| (strike.ppem, glyph)))
+            .collect();
+        let (strike_ppem, ref glyph) = strikes[best_strike(strikes.iter().map(|strike| strike.0), ppem)?];
+        let format = match &glyph.graphic_type {
+            b"png " => ImageFormat::PNG,
+            b"jpg " => ImageFormat::JPEG,
+            b"tiff" => ImageFormat::TIFF,
+            _ => return None,
+        };
+        let image = load_from_memory_with_format(glyph.data, format).ok()?.to_rgba();
+
+        let scale = ppem as f32 / strike_ppem as f32;
+        let top_bearing = glyph.origin_offset_y as f32 + image.height() as f32;
+        Some(scale_color_bitmap(ColorBitmapGlyph {
+            image,
+            left_bearing: glyph.origin_offset_x as f32,
+            top_bearing,
+        }, scale))
+    }
+
+    /// Renders the glyph's `COLR` paint graph, or its layers for version 0
+    /// glyphs, with colors from `CPAL` palette `palette`. Glyphs that aren't
+    /// in `COLR` are drawn from the `SVG ` table instead. Row 0 is the top.
+    ///
+    /// `foreground` is used where the font asks for the text color. Returns
+    /// `None` if the glyph isn't a color glyph.
+    pub fn render_color_glyph(&self, glyph_id: u32, size: usize, palette: usize, foreground: Rgba<u8>) -> Option<RgbaImage> {
+        self.render_colr_glyph(glyph_id, size, palette, foreground)
+            .or_else(|| self.render_svg_glyph(glyph_id, size, foreground))
+    }
+
+    /// Renders the glyph's document from the `SVG ` table. Row 0 is the top.
+    ///
+    /// Only paths and basic shapes filled with solid colors are drawn.
+    /// `currentColor` is `foreground`.
+    pub fn render_svg_glyph(&self, glyph_id: u32, size: usize, foreground: Rgba<u8>) -> Option<RgbaImage> {
+        use tables::head::Head;
+        use tables::svg::Svg;
+
+        let svg: Svg = self.get_table()?;
+        let glyph = svg.glyph_document(glyph_id as u16)?;
+        let head: Head = self.get_table()?;
+        let scale = size as f32 / head.units_per_em as f32;
+        ::svg::render(&glyph.document, &glyph.element_id, scale, foreground)
+    }
+
+    fn render_colr_glyph(&self, glyph_id: u32, size: usize, palette: usize, foreground: Rgba<u8>) -> Option<RgbaImage> {
+        use tables::colr::{Colr, FOREGROUND_PALETTE_INDEX};
+        use tables::cpal::Cpal;
+        use tables::head::Head;
+        use image::imageops::flip_vertical;
+        use paint::Painter;
+
+        let colr: Colr = self.get_table()?;
+        let cpal: Cpal = self.get_table()?;
+        let colors = cpal.colors(palette)?;
+        if let Some(image) = Painter::render(self, &colr, glyph_id as u16, size, colors.clone(), foreground) {
+            return Some(image);
+        }
+        let layers: Vec<(Glyph<'a>, Rgba<u8>)> = colr.layers(glyph_id as u16)?.into_iter()
+            .filter_map(|layer| {
+                let color = if layer.palette_index == FOREGROUND_PALETTE_INDEX {
+                    foreground
+                } else {
+                    colors.get(layer.palette_index as usize)?.to_rgba()
+                };
+                Some((self.get_glyph_for_id(layer.glyph_id as u32)?, color))
+            })
+            .collect();
+
+        // The image covers every layer
+        let bounds = layers.iter()
+            .map(|&(ref glyph, _)| self.glyph_bounds(glyph))
+            .fold(None, |acc: Option<[i16; 4]>, bounds| Some(match acc {
+                Some(acc) => [acc[0].min(bounds[0]), acc[1].min(bounds[1]), acc[2].max(bounds[2]), acc[3].max(bounds[3])],
+                None => bounds,
+            }));
+        let bounds = match bounds {
+            Some(bounds) => bounds,
+            None => return Some(RgbaImage::new(0, 0)),
+        };
+        let head: Head = self.get_table()?;
+        let scale = size as f32 / head.units_per_em as f32;
+        let affine = Affine::scale(scale, scale) * Affine::translation(-bounds[0], -bounds[1]);
+        let width = ((bounds[2] - bounds[0]) as f32 * scale).ceil() as u32;
+        let height = ((bounds[3] - bounds[1]) as f32 * scale).ceil() as u32;
+
+        let mut image = RgbaImage::new(width, height);
+        for (glyph, color) in layers {
+            let mut raster = CoverageRaster::new(width, height);
+            self.render_glyph_inner(&mut raster, affine, glyph);
+            let coverage = flip_vertical(&raster.into_dynamic().to_luma());
+            fill_over(&mut image, &coverage, color);
+        }
+        Some(image)
+    }
+
+    /// How much of each pixel the glyph's outline covers, from 0 to 1,
+    /// after mapping it with `affine`. Rows are in the order `affine` puts
+    /// them.
+    pub(crate) fn glyph_coverage(&self, glyph_id: u32, affine: Affine, width: u32, height: u32) -> Vec<f32> {
+        let mut raster = CoverageRaster::new(width, height);
+        if let Some(glyph) = self.get_glyph_for_id(glyph_id) {
+            self.render_glyph_inner(&mut raster, affine, glyph);
+        }
+        raster.coverage()
+    }
+
     fn render_glyph_inner(&self, raster: &mut impl Raster, affine: Affine, glyph: Glyph<'a>) {
-        use tables::glyf::{Coordinate, Description};
+        use tables::glyf::Description;
 
+        let deltas = glyph.deltas;
+        let delta = |idx: usize| deltas.as_ref().and_then(|deltas| deltas.get(idx));
         match glyph.desc {
             Description::Simple(glyph) => {
+                let mut point_idx = 0;
                 for contour in glyph.contours() {
-                    for (start, end) in FlattenedDrawCommands::from_coordinates(contour.into_iter()) {
-                        raster.add_line(affine * start, affine * end);
+                    let contour: Vec<Coordinate> = contour.into_iter()
+                        .map(|coord| {
+                            let coord = vary_coordinate(coord, delta(point_idx));
+                            point_idx += 1;
+                            coord
+                        })
+                        .collect();
+                    // Flatten in pixels, so curves get as many lines as they need there
+                    let commands = DrawCommands::from_coordinates(contour.into_iter())
+                        .map(|command| command.transform(affine));
+                    for (start, end) in FlattenedDrawCommands::from_commands(commands) {
+                        raster.add_line(start, end);
                     }
                 }
             },
             Description::Composite(glyph) => {
                 use tables::glyf::Glyf;
                 use tables::loca::Loca;
-                for (sub_idx, sub_affine) in glyph.coordinates() {
+                for (idx, (sub_idx, sub_affine)) in glyph.coordinates().enumerate() {
                     let glyf: Glyf = self.get_table().unwrap();
                     let loca: Loca = self.get_table().unwrap();
                     let offset = loca.at(sub_idx).unwrap();
-                    let sub_glyph = glyf.at_offset(offset as usize).unwrap();
+                    let mut sub_glyph = glyf.at_offset(offset as usize).unwrap();
+                    sub_glyph.deltas = self.glyph_deltas(sub_idx as u32, &sub_glyph);
 
+                    let sub_affine = vary_offset(sub_affine, delta(idx));
                     self.render_glyph_inner(raster, affine * sub_affine, sub_glyph);
                 }
             },
+            Description::Cubic(commands) => {
+                let commands = commands.into_iter().map(|command| command.transform(affine));
+                for (start, end) in FlattenedDrawCommands::from_commands(commands) {
+                    raster.add_line(start, end);
+                }
+            },
         };
     }
 
     pub fn text_render_metrics(&self) -> Option<TextRenderMetrics> {
+        use tables::mvar;
         use tables::os2::OS2;
         if let Some(os2) = self.get_table() {
             let os2: OS2 = os2;
             let base = os2.base_table;
 
             let trm = TextRenderMetrics {
-                ascent: base.s_typo_ascender,
-                descent: base.s_typo_descender,
-                line_gap: base.s_typo_line_gap,
+                ascent: self.vary_metric(base.s_typo_ascender, mvar::HORIZ_ASCENDER),
+                descent: self.vary_metric(base.s_typo_descender, mvar::HORIZ_DESCENDER),
+                line_gap: self.vary_metric(base.s_typo_line_gap, mvar::HORIZ_LINE_GAP),
             };
 
             println!("trm: {:#?}", trm);
@@ -147,17 +562,181 @@ impl<'a> Font<'a> {
         unimplemented!()
     }
 
-    pub fn placement_metrics(&self, code_point: char, size: usize) -> Option<GlyphPlacementMetrics> {
-        use tables::head::Head;
+    /// How much the font-wide metric named `tag` (e.g. `mvar::X_HEIGHT`)
+    /// changes at this instance, from `MVAR`
+    pub fn metric_variation(&self, tag: Tag) -> f32 {
+        use tables::mvar::MVAR;
+
+        if self.coords.is_empty() {
+            return 0.;
+        }
+        let mvar: Option<MVAR> = self.get_table();
+        mvar.map_or(0., |mvar| mvar.delta(tag, &self.coords))
+    }
+
+    fn vary_metric(&self, value: FontUnit<i16>, tag: Tag) -> FontUnit<i16> {
+        FontUnit((value.0 as f32 + self.metric_variation(tag)).round() as i16)
+    }
+
+    /// The glyph's advance width at this instance
+    pub fn advance_width(&self, glyph_id: u32) -> Option<FontUnit<u16>> {
+        use tables::hvar::HVAR;
+
+        let hmtx: HMTX = self.get_table()?;
+        let advance = hmtx.advance_width(glyph_id);
+        if self.coords.is_empty() {
+            return Some(advance);
+        }
+
+        let hvar: Option<HVAR> = self.get_table();
+        let delta = match hvar {
+            Some(hvar) => hvar.advance_width_delta(glyph_id, &self.coords),
+            // Without `HVAR` the phantom points have to be moved
+            None => self.phantom_advance_delta(glyph_id).unwrap_or(0.),
+        };
+        Some(FontUnit((advance.0 as f32 + delta).round().max(0.) as u16))
+    }
+
+    /// How far `gvar` moves the glyph's advance width, from its horizontal
+    /// phantom points
+    fn phantom_advance_delta(&self, glyph_id: u32) -> Option<f32> {
+        use tables::gvar::Gvar;
+
+        let deltas = match self.get_glyph_for_id(glyph_id) {
+            Some(glyph) => glyph.deltas?,
+            // Glyphs without outlines only have the phantom points
+            None => {
+                let gvar: Gvar = self.get_table()?;
+                gvar.glyph_deltas(glyph_id, &self.coords, &[(0., 0.); 4], &[])?
+            },
+        };
+        let phantom = &deltas[deltas.len().checked_sub(4)?..];
+        Some(phantom[1].0 - phantom[0].0)
+    }
+
+    /// The scripts, language systems, features and lookups in `GSUB`
+    pub fn gsub_info(&self) -> Option<LayoutInfo> {
+        use tables::gsub::GSUB;
+        use tables::name::Name;
+        let gsub: GSUB = self.get_table()?;
+        let name: Option<Name> = self.get_table();
+        gsub.info(name.as_ref())
+    }
+
+    /// The scripts, language systems, features and lookups in `GPOS`
+    pub fn gpos_info(&self) -> Option<LayoutInfo> {
+        use tables::gpos::GPOS;
+        use tables::name::Name;
+        let gpos: GPOS = self.get_table()?;
+        let name: Option<Name> = self.get_table();
+        gpos.info(name.as_ref())
+    }
+
+    /// The AAT features and settings in `feat`, with their names
+    pub fn feat_info(&self) -> Option<Vec<AatFeatureInfo>> {
+        use tables::feat::Feat;
+        use tables::name::Name;
+        let feat: Feat = self.get_table()?;
+        let name: Option<Name> = self.get_table();
+        Some(feat.info(name.as_ref()))
+    }
+
+    /// The features the Graphite rules check, from `Feat`. Pass their ids as
+    /// `Feature` tags to change them when shaping.
+    pub fn graphite_features(&self) -> Option<Vec<GraphiteFeature>> {
+        use tables::graphite_feat::GraphiteFeat;
+        let feat: GraphiteFeat = self.get_table()?;
+        Some(feat.features())
+    }
+
+    /// The variation axes in `fvar`, with their names
+    pub fn variation_axes(&self) -> Option<Vec<VariationAxis>> {
+        use tables::fvar::Fvar;
+        use tables::name::Name;
+        let fvar: Fvar = self.get_table()?;
+        let name: Option<Name> = self.get_table();
+        Some(fvar.axes(name.as_ref()))
+    }
+
+    /// The named instances in `fvar`, with their names
+    pub fn named_instances(&self) -> Option<Vec<NamedInstance>> {
+        use tables::fvar::Fvar;
+        use tables::name::Name;
+        let fvar: Fvar = self.get_table()?;
+        let name: Option<Name> = self.get_table();
+        Some(fvar.instances(name.as_ref()))
+    }
+
+    /// The normalized coordinates of this instance, in `fvar` axis order.
+    /// Empty for the default instance.
+    pub fn normalized_coords(&self) -> &[f32] {
+        &self.coords
+    }
+
+    /// A view of the font at the user space coordinates in `variations`, e.g.
+    /// `wght=650`. Axes that aren't mentioned are at their default.
+    ///
+    /// Returns `None` if the font isn't variable.
+    pub fn with_variations(&self, variations: &[Variation]) -> Option<Font<'a>> {
+        let axes = self.variation_axes()?;
+        let user_coords: Vec<f32> = axes.iter()
+            .map(|axis| {
+                variations.iter()
+                    .rev()
+                    .find(|variation| variation.tag == axis.tag)
+                    .map(|variation| variation.value)
+                    .unwrap_or(axis.default_value)
+            })
+            .collect();
+        self.at_user_coords(&axes, &user_coords)
+    }
+
+    /// A view of the font at the `index`th named instance
+    pub fn with_named_instance(&self, index: usize) -> Option<Font<'a>> {
+        let axes = self.variation_axes()?;
+        let instance = self.named_instances()?.into_iter().nth(index)?;
+        self.at_user_coords(&axes, &instance.coordinates)
+    }
+
+    fn at_user_coords(&self, axes: &[VariationAxis], user_coords: &[f32]) -> Option<Font<'a>> {
+        use tables::avar::{map_coordinate, Avar};
+
+        let avar: Option<Avar> = self.get_table();
+        let segment_maps = avar.map(|avar| avar.segment_maps());
+        let coords = axes.iter()
+            .zip(user_coords)
+            .enumerate()
+            .map(|(idx, (axis, &value))| {
+                let coord = axis.normalize(value);
+                match segment_maps.as_ref().and_then(|maps| maps.get(idx)) {
+                    Some(map) => map_coordinate(map, coord),
+                    None => coord,
+                }
+            })
+            .collect();
+
+        let mut font = Font::from_buffer(self.buf).ok()?;
+        font.coords = coords;
+        Some(font)
+    }
 
+    pub fn placement_metrics(&self, code_point: char, size: usize) -> Option<GlyphPlacementMetrics> {
         let glyph_id = self.get_glyph_id(code_point)?;
+        self.placement_metrics_for_glyph_id(glyph_id, size)
+    }
+
+    /// Like `placement_metrics`, for glyphs that don't come straight from a
+    /// character (e.g. the output of `shape::shape`)
+    pub fn placement_metrics_for_glyph_id(&self, glyph_id: u32, size: usize) -> Option<GlyphPlacementMetrics> {
+        use tables::head::Head;
 
         let glyph = self.get_glyph_for_id(glyph_id)?;
-        let width: FontUnit<_> = (glyph.header.x_max - glyph.header.x_min).into();
-        let height: FontUnit<_> = (glyph.header.y_max - glyph.header.y_min).into();
+        let bounds = self.glyph_bounds(&glyph);
+        let width: FontUnit<_> = (bounds[2] - bounds[0]).into();
+        let height: FontUnit<_> = (bounds[3] - bounds[1]).into();
         let shift: [FontUnit<_>; 2] = {
-            let x_shift = -glyph.header.x_min;
-            let y_shift = -glyph.header.y_min;
+            let x_shift = -bounds[0];
+            let y_shift = -bounds[1];
             let affine = Affine::translation(x_shift, y_shift);
 
             // let head: Head = self.get_table().unwrap();
@@ -168,11 +747,11 @@ impl<'a> Font<'a> {
             [trans[0].into(), trans[1].into()]
         };
 
-        let horiz_metrics = {
+        let mut horiz_metrics = {
             let hmtx: HMTX = self.get_table()?;
             hmtx.metrics_for_glyph(glyph_id)
         };
-        let (top_bearing, vert_advance) = if let Some(vmtx) = self.get_table() {
+        let (mut top_bearing, mut vert_advance) = if let Some(vmtx) = self.get_table() {
             let vmtx: VMTX = vmtx;
             let vm = vmtx.metrics_for_glyph(glyph_id);
             (vm.top_bearing, vm.advance_height)
@@ -181,12 +760,60 @@ impl<'a> Font<'a> {
             (height - shift[1].map(|s| s.floor() as i16), None)
         };
 
+        // Move the phantom points to find the varied metrics
+        if let Some(ref deltas) = glyph.deltas {
+            let phantom = self.phantom_points(glyph_id, &glyph);
+            let deltas = &deltas[deltas.len() - 4..];
+            let varied: Vec<(f32, f32)> = phantom.iter()
+                .zip(deltas)
+                .map(|(point, delta)| (point.0 + delta.0, point.1 + delta.1))
+                .collect();
+
+            horiz_metrics.left_bearing = FontUnit(bounds[0] - varied[0].0.round() as i16);
+            horiz_metrics.advance_width = Some(FontUnit((varied[1].0 - varied[0].0).round().max(0.) as u16));
+            if vert_advance.is_some() {
+                top_bearing = FontUnit(varied[2].1.round() as i16 - bounds[3]);
+                vert_advance = Some(FontUnit((varied[2].1 - varied[3].1).round().max(0.) as u16));
+            }
+        }
+
+        // `HVAR` and `VVAR` take precedence over the phantom points
+        if !self.coords.is_empty() {
+            use tables::hvar::HVAR;
+            use tables::vvar::VVAR;
+
+            if let Some(hvar) = self.get_table() {
+                let hvar: HVAR = hvar;
+                let hmtx: HMTX = self.get_table()?;
+                let default = hmtx.metrics_for_glyph(glyph_id);
+                let advance = hmtx.advance_width(glyph_id).0 as f32 + hvar.advance_width_delta(glyph_id, &self.coords);
+                horiz_metrics.advance_width = Some(FontUnit(advance.round().max(0.) as u16));
+                if let Some(delta) = hvar.lsb_delta(glyph_id, &self.coords) {
+                    horiz_metrics.left_bearing = FontUnit((default.left_bearing.0 as f32 + delta).round() as i16);
+                }
+            }
+            if let (Some(vvar), Some(vmtx)) = (self.get_table(), self.get_table()) {
+                let vvar: VVAR = vvar;
+                let vmtx: VMTX = vmtx;
+                let default = vmtx.metrics_for_glyph(glyph_id);
+                if let Some(advance) = default.advance_height {
+                    let advance = advance.0 as f32 + vvar.advance_height_delta(glyph_id, &self.coords);
+                    vert_advance = Some(FontUnit(advance.round().max(0.) as u16));
+                }
+                if let Some(delta) = vvar.tsb_delta(glyph_id, &self.coords) {
+                    top_bearing = FontUnit((default.top_bearing.0 as f32 + delta).round() as i16);
+                }
+            }
+        }
+
         let placement_metrics = GlyphPlacementMetrics {
             shift,
             left_bearing: horiz_metrics.left_bearing,
             top_bearing,
             horiz_advance: horiz_metrics.advance_width,
             vert_advance,
+            bitmap_bearings: self.embedded_bitmap_glyph(glyph_id, size as u16)
+                .map(|glyph| [glyph.left_bearing, glyph.top_bearing]),
         };
 
         Some(placement_metrics)
@@ -194,6 +821,29 @@ impl<'a> Font<'a> {
 
 }
 
+/// Move a point by its variation delta
+pub(crate) fn vary_coordinate(coord: Coordinate, delta: Option<&(f32, f32)>) -> Coordinate {
+    match delta {
+        Some(&(dx, dy)) => Coordinate {
+            on_curve: coord.on_curve,
+            x: (coord.x as f32 + dx).round() as i16,
+            y: (coord.y as f32 + dy).round() as i16,
+        },
+        None => coord,
+    }
+}
+
+/// Move a component by its variation delta
+pub(crate) fn vary_offset(affine: Affine, delta: Option<&(f32, f32)>) -> Affine {
+    match delta {
+        Some(&(dx, dy)) => Affine {
+            square: affine.square,
+            translation: [(affine.translation[0] + dx).round(), (affine.translation[1] + dy).round()],
+        },
+        None => affine,
+    }
+}
+
 pub trait GetTable<T> {
     fn get_table(&self) -> Option<T> ;
 }
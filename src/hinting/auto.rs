@@ -0,0 +1,380 @@
+//! A light autohinter for fonts without usable instructions. Only moves
+//! points vertically: horizontal edges of stems and of the blue zones
+//! (baseline, x-height, cap height) go on the pixel grid, and everything
+//! between them is stretched to fit.
+
+use font::{vary_coordinate, vary_offset, Font, GetTable};
+use image::GrayImage;
+use math::{Affine, Point};
+use render::{CoverageRaster, DrawCommand, DrawCommands, FlattenedDrawCommands, Raster};
+use tables::glyf::{Coordinate, Description};
+use super::render_commands;
+
+/// How deep composite glyphs can nest
+const MAX_COMPONENT_DEPTH: usize = 16;
+/// Segments at most this steep (dy / dx) count as horizontal
+const MAX_EDGE_SLOPE: f32 = 0.14;
+/// Overshoots shorter than this, in pixels, are flattened onto the zone
+const OVERSHOOT_THRESHOLD: f32 = 0.75;
+
+/// A height many glyphs line up on, with the height round glyphs
+/// overshoot to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlueZone {
+    pub reference: f32,
+    pub overshoot: f32,
+}
+
+impl BlueZone {
+    /// Whether `y` (in font units) is in the zone, give or take `fuzz`
+    fn contains(&self, y: f32, fuzz: f32) -> bool {
+        let low = self.reference.min(self.overshoot) - fuzz;
+        let high = self.reference.max(self.overshoot) + fuzz;
+        y >= low && y <= high
+    }
+}
+
+/// A horizontal part of an outline, in font units
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    y: f32,
+    /// Whether the ink is below the edge
+    top: bool,
+    x_min: f32,
+    x_max: f32,
+}
+
+/// Grid-fits outlines at one size without using their instructions
+pub struct AutoHinter<'a: 'f, 'f> {
+    font: &'f Font<'a>,
+    /// Pixels per font unit
+    scale: f32,
+    units_per_em: f32,
+    blue_zones: Vec<BlueZone>,
+}
+
+impl<'a, 'f> AutoHinter<'a, 'f> {
+    pub fn new(font: &'f Font<'a>, ppem: u16) -> Option<AutoHinter<'a, 'f>> {
+        use tables::head::Head;
+
+        let head: Head = font.get_table()?;
+        let units_per_em = head.units_per_em as f32;
+        let mut hinter = AutoHinter {
+            font,
+            scale: ppem as f32 / units_per_em,
+            units_per_em,
+            blue_zones: Vec::new(),
+        };
+        hinter.blue_zones = hinter.find_blue_zones();
+        Some(hinter)
+    }
+
+    pub fn blue_zones(&self) -> &[BlueZone] {
+        &self.blue_zones
+    }
+
+    /// The baseline, x-height and cap height, measured from glyphs with
+    /// flat and round tops and bottoms. Zones the font doesn't have the
+    /// glyphs for are left out.
+    fn find_blue_zones(&self) -> Vec<BlueZone> {
+        // Reference (flat) glyphs, overshooting (round) glyphs, and whether
+        // to measure the top
+        let zones: [(&str, &str, bool); 3] = [
+            ("HIxz", "Oo", false),
+            ("xzvw", "oe", true),
+            ("HIET", "OC", true),
+        ];
+        let mut blue_zones = Vec::new();
+        for &(flat, round, top) in &zones {
+            let measure = |chars: &str| {
+                let heights: Vec<f32> = chars.chars()
+                    .filter_map(|code_point| self.font.get_glyph_id(code_point))
+                    .filter_map(|glyph_id| self.font.get_glyph_for_id(glyph_id))
+                    .map(|glyph| {
+                        let bounds = self.font.glyph_bounds(&glyph);
+                        (if top { bounds[3] } else { bounds[1] }) as f32
+                    })
+                    .collect();
+                if heights.is_empty() {
+                    None
+                } else {
+                    Some(heights.iter().sum::<f32>() / heights.len() as f32)
+                }
+            };
+            if let Some(reference) = measure(flat) {
+                let overshoot = measure(round).unwrap_or(reference);
+                blue_zones.push(BlueZone { reference, overshoot });
+            }
+        }
+        blue_zones
+    }
+
+    /// The glyph's outline in pixels, with y going up and the horizontal
+    /// origin at 0
+    pub fn hint_glyph(&self, glyph_id: u32) -> Option<Vec<DrawCommand>> {
+        let mut outline = Vec::new();
+        self.outline(glyph_id, Affine::scale(1., 1.), 0, &mut outline)?;
+
+        let anchors = self.anchors(&self.edges(&outline));
+        let scale = self.scale;
+        let fit = |point: Point| Point { x: point.x * scale, y: fit_height(&anchors, point.y, scale) };
+        Some(outline.into_iter()
+            .map(|command| match command {
+                DrawCommand::Line(a, b) => DrawCommand::Line(fit(a), fit(b)),
+                DrawCommand::Curve(a, b, c) => DrawCommand::Curve(fit(a), fit(b), fit(c)),
+                DrawCommand::CubicCurve(a, b, c, d) => DrawCommand::CubicCurve(fit(a), fit(b), fit(c), fit(d)),
+            })
+            .collect())
+    }
+
+    /// Rasterize the hinted glyph into an image just big enough for it
+    pub fn render_glyph(&self, glyph_id: u32) -> Option<GrayImage> {
+        self.hint_glyph(glyph_id).map(|commands| render_commands(commands, CoverageRaster::new))
+    }
+
+    /// Collect the glyph's outline in font units
+    fn outline(&self, glyph_id: u32, affine: Affine, depth: usize, commands: &mut Vec<DrawCommand>) -> Option<()> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return None;
+        }
+        let glyph = self.font.get_glyph_for_id(glyph_id)?;
+        let deltas = glyph.deltas;
+        let delta = |idx: usize| deltas.as_ref().and_then(|deltas| deltas.get(idx));
+        match glyph.desc {
+            Description::Simple(simple) => {
+                let mut point_idx = 0;
+                for contour in simple.contours() {
+                    let contour: Vec<Coordinate> = contour.into_iter()
+                        .map(|coord| {
+                            let coord = vary_coordinate(coord, delta(point_idx));
+                            point_idx += 1;
+                            coord
+                        })
+                        .collect();
+                    commands.extend(DrawCommands::from_coordinates(contour.into_iter())
+                        .map(|command| command.transform(affine)));
+                }
+            },
+            Description::Composite(composite) => {
+                for (idx, (sub_idx, sub_affine)) in composite.coordinates().enumerate() {
+                    let sub_affine = vary_offset(sub_affine, delta(idx));
+                    self.outline(sub_idx as u32, affine * sub_affine, depth + 1, commands)?;
+                }
+            },
+            Description::Cubic(cubic) => {
+                commands.extend(cubic.into_iter().map(|command| command.transform(affine)));
+            },
+        }
+        Some(())
+    }
+
+    /// The outline's horizontal edges. Runs of nearly flat segments make
+    /// up one edge, at their highest (or lowest) point.
+    fn edges(&self, outline: &[DrawCommand]) -> Vec<Edge> {
+        let lines: Vec<(Point, Point)> = FlattenedDrawCommands::from_commands(outline.iter().cloned()).collect();
+
+        // Outer contours of TrueType glyphs go clockwise, and of CFF ones
+        // counter-clockwise
+        let area: f32 = lines.iter().map(|&(a, b)| a.x * b.y - b.x * a.y).sum();
+        let clockwise = area < 0.;
+        let min_length = self.units_per_em / 50.;
+
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut run: Option<(Edge, f32, Point)> = None;
+        let finish = |run: Option<(Edge, f32, Point)>, edges: &mut Vec<Edge>| {
+            if let Some((edge, length, _)) = run {
+                if length >= min_length {
+                    edges.push(edge);
+                }
+            }
+        };
+        for &(start, end) in &lines {
+            let (dx, dy) = (end.x - start.x, end.y - start.y);
+            if dx == 0. || (dy / dx).abs() > MAX_EDGE_SLOPE {
+                finish(run.take(), &mut edges);
+                continue;
+            }
+            let top = (dx > 0.) == clockwise;
+            let continues = match run {
+                Some((edge, _, last)) => edge.top == top && last == start,
+                None => false,
+            };
+            if !continues {
+                finish(run.take(), &mut edges);
+                let y = start.y;
+                run = Some((Edge { y, top, x_min: start.x, x_max: start.x }, 0., start));
+            }
+            if let Some((ref mut edge, ref mut length, ref mut last)) = run {
+                edge.y = if top { edge.y.max(end.y) } else { edge.y.min(end.y) };
+                edge.x_min = edge.x_min.min(end.x);
+                edge.x_max = edge.x_max.max(end.x);
+                *length += dx.abs();
+                *last = end;
+            }
+        }
+        finish(run, &mut edges);
+        edges
+    }
+
+    /// Where edges (in font units) end up (in pixels), sorted by height
+    fn anchors(&self, edges: &[Edge]) -> Vec<(f32, f32)> {
+        let scale = self.scale;
+        let fuzz = self.units_per_em / 100.;
+        let mut hinted: Vec<Option<f32>> = edges.iter()
+            .map(|edge| self.blue_zones.iter()
+                .find(|zone| zone.contains(edge.y, fuzz))
+                .map(|zone| {
+                    let reference = (zone.reference * scale).round();
+                    let overshoot = (edge.y - zone.reference) * scale;
+                    if overshoot.abs() < OVERSHOOT_THRESHOLD {
+                        reference
+                    } else {
+                        reference + overshoot.round()
+                    }
+                }))
+            .collect();
+
+        // Stems are a bottom edge and the closest top edge over it, and keep
+        // a whole number of pixels between them
+        let max_stem = self.units_per_em / 4.;
+        for (bottom_idx, bottom) in edges.iter().enumerate().filter(|&(_, edge)| !edge.top) {
+            let top_idx = edges.iter()
+                .enumerate()
+                .filter(|&(_, edge)| edge.top && edge.y > bottom.y && edge.y - bottom.y <= max_stem)
+                .filter(|&(_, edge)| edge.x_min < bottom.x_max && edge.x_max > bottom.x_min)
+                .min_by(|a, b| a.1.y.partial_cmp(&b.1.y).unwrap())
+                .map(|(idx, _)| idx);
+            let top_idx = match top_idx {
+                Some(top_idx) => top_idx,
+                None => continue,
+            };
+            let width = ((edges[top_idx].y - bottom.y) * scale).round().max(1.);
+            match (hinted[bottom_idx], hinted[top_idx]) {
+                (Some(_), Some(_)) => (),
+                (Some(low), None) => hinted[top_idx] = Some(low + width),
+                (None, Some(high)) => hinted[bottom_idx] = Some(high - width),
+                (None, None) => {
+                    let center = (bottom.y + edges[top_idx].y) / 2. * scale;
+                    let low = (center - width / 2.).round();
+                    hinted[bottom_idx] = Some(low);
+                    hinted[top_idx] = Some(low + width);
+                },
+            }
+        }
+
+        let mut anchors: Vec<(f32, f32)> = edges.iter()
+            .zip(hinted)
+            .map(|(edge, hinted)| (edge.y, hinted.unwrap_or_else(|| (edge.y * scale).round())))
+            .collect();
+        anchors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // Drop edges that would cross the ones under them
+        let mut kept: Vec<(f32, f32)> = Vec::new();
+        for anchor in anchors {
+            match kept.last() {
+                Some(&(y, hinted)) if anchor.0 == y || anchor.1 < hinted => continue,
+                _ => kept.push(anchor),
+            }
+        }
+        kept
+    }
+}
+
+/// Move a height (in font units) to pixels, stretching between anchors
+fn fit_height(anchors: &[(f32, f32)], y: f32, scale: f32) -> f32 {
+    let above = anchors.iter().position(|&(anchor, _)| anchor >= y);
+    match above {
+        None => match anchors.last() {
+            Some(&(anchor, hinted)) => hinted + (y - anchor) * scale,
+            None => y * scale,
+        },
+        Some(0) => {
+            let (anchor, hinted) = anchors[0];
+            hinted + (y - anchor) * scale
+        },
+        Some(idx) => {
+            let (low, low_hinted) = anchors[idx - 1];
+            let (high, high_hinted) = anchors[idx];
+            low_hinted + (y - low) / (high - low) * (high_hinted - low_hinted)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::{font_buf, load_font_buf, ROBOTO};
+
+    fn heights(commands: &[DrawCommand]) -> Vec<f32> {
+        commands.iter()
+            .flat_map(|command| match *command {
+                DrawCommand::Line(a, b) => vec![a, b],
+                DrawCommand::Curve(a, _, c) => vec![a, c],
+                DrawCommand::CubicCurve(a, _, _, d) => vec![a, d],
+            })
+            .map(|point| point.y)
+            .collect()
+    }
+
+    #[test]
+    fn blue_zones() {
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let hinter = AutoHinter::new(&font, 12).unwrap();
+        let zones = hinter.blue_zones();
+        assert_eq!(zones.len(), 3);
+        // The baseline, with 'O' dipping under it
+        assert_eq!(zones[0].reference, 0.);
+        assert!(zones[0].overshoot < 0.);
+        assert!(zones[1].reference < zones[2].reference);
+        assert!(zones[2].overshoot > zones[2].reference);
+    }
+
+    #[test]
+    fn snaps_heights_and_keeps_widths() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let hinter = AutoHinter::new(&font, 13).unwrap();
+
+        let glyph_id = font.get_glyph_id('E').unwrap();
+        let commands = hinter.hint_glyph(glyph_id).unwrap();
+        // The baseline, the top and both sides of each bar are on the grid
+        let mut flat: Vec<f32> = commands.iter()
+            .filter_map(|command| match *command {
+                DrawCommand::Line(a, b) if a.y == b.y => Some(a.y),
+                _ => None,
+            })
+            .collect();
+        flat.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        flat.dedup();
+        assert_eq!(flat.len(), 6);
+        assert!(flat.iter().all(|&y| y == y.round()));
+        assert_eq!(flat[0], 0.);
+
+        // Widths are only scaled
+        let glyph = font.get_glyph_for_id(glyph_id).unwrap();
+        let x_max = commands.iter()
+            .map(|command| match *command {
+                DrawCommand::Line(a, b) => a.x.max(b.x),
+                _ => ::std::f32::NEG_INFINITY,
+            })
+            .fold(::std::f32::NEG_INFINITY, f32::max);
+        assert!((x_max - glyph.header.x_max as f32 * 13. / 2048.).abs() < 0.001);
+    }
+
+    #[test]
+    fn flattens_small_overshoots() {
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let hinter = AutoHinter::new(&font, 11).unwrap();
+
+        let top = |code_point| {
+            let commands = hinter.hint_glyph(font.get_glyph_id(code_point).unwrap()).unwrap();
+            heights(&commands).into_iter().fold(::std::f32::NEG_INFINITY, f32::max)
+        };
+        assert_eq!(top('x'), top('x').round());
+        assert_eq!(top('o'), top('x'));
+
+        let image = hinter.render_glyph(font.get_glyph_id('o').unwrap()).unwrap();
+        assert!(image.pixels().any(|pixel| pixel.data[0] > 0));
+    }
+}
@@ -0,0 +1,626 @@
+//! Decoding TrueType instructions into something readable
+
+use std::fmt;
+
+use super::interpreter::instruction_length;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+/// Which engine compensation a distance gets. Always none here, but
+/// programs still say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceType {
+    Grey,
+    Black,
+    White,
+    Reserved,
+}
+
+impl DistanceType {
+    fn from_bits(bits: u8) -> DistanceType {
+        match bits & 3 {
+            0 => DistanceType::Grey,
+            1 => DistanceType::Black,
+            2 => DistanceType::White,
+            _ => DistanceType::Reserved,
+        }
+    }
+}
+
+impl fmt::Display for DistanceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            DistanceType::Grey => "grey",
+            DistanceType::Black => "black",
+            DistanceType::White => "white",
+            DistanceType::Reserved => "3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The flags of MDRP and MIRP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveFlags {
+    pub set_rp0: bool,
+    pub min_distance: bool,
+    pub round: bool,
+    pub distance_type: DistanceType,
+}
+
+/// A TrueType instruction, with the flags packed into its opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Svtca(Axis),
+    Spvtca(Axis),
+    Sfvtca(Axis),
+    /// `true` for the line's perpendicular
+    Spvtl(bool),
+    Sfvtl(bool),
+    Spvfs,
+    Sfvfs,
+    Gpv,
+    Gfv,
+    Sfvtpv,
+    Isect,
+    Srp0,
+    Srp1,
+    Srp2,
+    Szp0,
+    Szp1,
+    Szp2,
+    Szps,
+    Sloop,
+    Rtg,
+    Rthg,
+    Smd,
+    Else,
+    Jmpr,
+    Scvtci,
+    Sswci,
+    Ssw,
+    Dup,
+    Pop,
+    Clear,
+    Swap,
+    Depth,
+    Cindex,
+    Mindex,
+    Alignpts,
+    Utp,
+    Loopcall,
+    Call,
+    Fdef,
+    Endf,
+    Mdap { round: bool },
+    Iup(Axis),
+    /// `true` to use rp1 (and zp0) rather than rp2 (and zp1)
+    Shp(bool),
+    Shc(bool),
+    Shz(bool),
+    Shpix,
+    Ip,
+    Msirp { set_rp0: bool },
+    Alignrp,
+    Rtdg,
+    Miap { round: bool },
+    Npushb,
+    Npushw,
+    Ws,
+    Rs,
+    Wcvtp,
+    Rcvt,
+    /// `true` for the original position
+    Gc(bool),
+    Scfs,
+    /// `true` to measure the original positions
+    Md(bool),
+    Mppem,
+    Mps,
+    Flipon,
+    Flipoff,
+    Debug,
+    Lt,
+    Lteq,
+    Gt,
+    Gteq,
+    Eq,
+    Neq,
+    Odd,
+    Even,
+    If,
+    Eif,
+    And,
+    Or,
+    Not,
+    Deltap1,
+    Sdb,
+    Sds,
+    Add,
+    Sub,
+    Div,
+    Mul,
+    Abs,
+    Neg,
+    Floor,
+    Ceiling,
+    Round(DistanceType),
+    Nround(DistanceType),
+    Wcvtf,
+    Deltap2,
+    Deltap3,
+    Deltac1,
+    Deltac2,
+    Deltac3,
+    Sround,
+    S45round,
+    Jrot,
+    Jrof,
+    Roff,
+    Rutg,
+    Rdtg,
+    Sangw,
+    Aa,
+    Flippt,
+    Fliprgon,
+    Fliprgoff,
+    Scanctrl,
+    Sdpvtl(bool),
+    Getinfo,
+    Idef,
+    Roll,
+    Max,
+    Min,
+    Scantype,
+    Instctrl,
+    Getvariation,
+    /// How many values follow
+    Pushb(u8),
+    Pushw(u8),
+    Mdrp(MoveFlags),
+    Mirp(MoveFlags),
+    /// Not defined, unless the font defines it with IDEF
+    Unknown(u8),
+}
+
+impl Opcode {
+    pub fn from_byte(byte: u8) -> Opcode {
+        let axis = if byte & 1 == 1 { Axis::X } else { Axis::Y };
+        let flag = byte & 1 == 1;
+        match byte {
+            0x00 | 0x01 => Opcode::Svtca(axis),
+            0x02 | 0x03 => Opcode::Spvtca(axis),
+            0x04 | 0x05 => Opcode::Sfvtca(axis),
+            0x06 | 0x07 => Opcode::Spvtl(flag),
+            0x08 | 0x09 => Opcode::Sfvtl(flag),
+            0x0A => Opcode::Spvfs,
+            0x0B => Opcode::Sfvfs,
+            0x0C => Opcode::Gpv,
+            0x0D => Opcode::Gfv,
+            0x0E => Opcode::Sfvtpv,
+            0x0F => Opcode::Isect,
+            0x10 => Opcode::Srp0,
+            0x11 => Opcode::Srp1,
+            0x12 => Opcode::Srp2,
+            0x13 => Opcode::Szp0,
+            0x14 => Opcode::Szp1,
+            0x15 => Opcode::Szp2,
+            0x16 => Opcode::Szps,
+            0x17 => Opcode::Sloop,
+            0x18 => Opcode::Rtg,
+            0x19 => Opcode::Rthg,
+            0x1A => Opcode::Smd,
+            0x1B => Opcode::Else,
+            0x1C => Opcode::Jmpr,
+            0x1D => Opcode::Scvtci,
+            0x1E => Opcode::Sswci,
+            0x1F => Opcode::Ssw,
+            0x20 => Opcode::Dup,
+            0x21 => Opcode::Pop,
+            0x22 => Opcode::Clear,
+            0x23 => Opcode::Swap,
+            0x24 => Opcode::Depth,
+            0x25 => Opcode::Cindex,
+            0x26 => Opcode::Mindex,
+            0x27 => Opcode::Alignpts,
+            0x29 => Opcode::Utp,
+            0x2A => Opcode::Loopcall,
+            0x2B => Opcode::Call,
+            0x2C => Opcode::Fdef,
+            0x2D => Opcode::Endf,
+            0x2E | 0x2F => Opcode::Mdap { round: flag },
+            0x30 | 0x31 => Opcode::Iup(axis),
+            0x32 | 0x33 => Opcode::Shp(flag),
+            0x34 | 0x35 => Opcode::Shc(flag),
+            0x36 | 0x37 => Opcode::Shz(flag),
+            0x38 => Opcode::Shpix,
+            0x39 => Opcode::Ip,
+            0x3A | 0x3B => Opcode::Msirp { set_rp0: flag },
+            0x3C => Opcode::Alignrp,
+            0x3D => Opcode::Rtdg,
+            0x3E | 0x3F => Opcode::Miap { round: flag },
+            0x40 => Opcode::Npushb,
+            0x41 => Opcode::Npushw,
+            0x42 => Opcode::Ws,
+            0x43 => Opcode::Rs,
+            0x44 => Opcode::Wcvtp,
+            0x45 => Opcode::Rcvt,
+            0x46 | 0x47 => Opcode::Gc(flag),
+            0x48 => Opcode::Scfs,
+            0x49 | 0x4A => Opcode::Md(byte == 0x4A),
+            0x4B => Opcode::Mppem,
+            0x4C => Opcode::Mps,
+            0x4D => Opcode::Flipon,
+            0x4E => Opcode::Flipoff,
+            0x4F => Opcode::Debug,
+            0x50 => Opcode::Lt,
+            0x51 => Opcode::Lteq,
+            0x52 => Opcode::Gt,
+            0x53 => Opcode::Gteq,
+            0x54 => Opcode::Eq,
+            0x55 => Opcode::Neq,
+            0x56 => Opcode::Odd,
+            0x57 => Opcode::Even,
+            0x58 => Opcode::If,
+            0x59 => Opcode::Eif,
+            0x5A => Opcode::And,
+            0x5B => Opcode::Or,
+            0x5C => Opcode::Not,
+            0x5D => Opcode::Deltap1,
+            0x5E => Opcode::Sdb,
+            0x5F => Opcode::Sds,
+            0x60 => Opcode::Add,
+            0x61 => Opcode::Sub,
+            0x62 => Opcode::Div,
+            0x63 => Opcode::Mul,
+            0x64 => Opcode::Abs,
+            0x65 => Opcode::Neg,
+            0x66 => Opcode::Floor,
+            0x67 => Opcode::Ceiling,
+            0x68..=0x6B => Opcode::Round(DistanceType::from_bits(byte)),
+            0x6C..=0x6F => Opcode::Nround(DistanceType::from_bits(byte)),
+            0x70 => Opcode::Wcvtf,
+            0x71 => Opcode::Deltap2,
+            0x72 => Opcode::Deltap3,
+            0x73 => Opcode::Deltac1,
+            0x74 => Opcode::Deltac2,
+            0x75 => Opcode::Deltac3,
+            0x76 => Opcode::Sround,
+            0x77 => Opcode::S45round,
+            0x78 => Opcode::Jrot,
+            0x79 => Opcode::Jrof,
+            0x7A => Opcode::Roff,
+            0x7C => Opcode::Rutg,
+            0x7D => Opcode::Rdtg,
+            0x7E => Opcode::Sangw,
+            0x7F => Opcode::Aa,
+            0x80 => Opcode::Flippt,
+            0x81 => Opcode::Fliprgon,
+            0x82 => Opcode::Fliprgoff,
+            0x85 => Opcode::Scanctrl,
+            0x86 | 0x87 => Opcode::Sdpvtl(flag),
+            0x88 => Opcode::Getinfo,
+            0x89 => Opcode::Idef,
+            0x8A => Opcode::Roll,
+            0x8B => Opcode::Max,
+            0x8C => Opcode::Min,
+            0x8D => Opcode::Scantype,
+            0x8E => Opcode::Instctrl,
+            0x91 => Opcode::Getvariation,
+            0xB0..=0xB7 => Opcode::Pushb(byte - 0xAF),
+            0xB8..=0xBF => Opcode::Pushw(byte - 0xB7),
+            0xC0..=0xFF => {
+                let flags = MoveFlags {
+                    set_rp0: byte & 0x10 != 0,
+                    min_distance: byte & 0x08 != 0,
+                    round: byte & 0x04 != 0,
+                    distance_type: DistanceType::from_bits(byte),
+                };
+                if byte < 0xE0 { Opcode::Mdrp(flags) } else { Opcode::Mirp(flags) }
+            },
+            _ => Opcode::Unknown(byte),
+        }
+    }
+
+    /// The mnemonic, without flags
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Opcode::Svtca(_) => "SVTCA",
+            Opcode::Spvtca(_) => "SPVTCA",
+            Opcode::Sfvtca(_) => "SFVTCA",
+            Opcode::Spvtl(_) => "SPVTL",
+            Opcode::Sfvtl(_) => "SFVTL",
+            Opcode::Spvfs => "SPVFS",
+            Opcode::Sfvfs => "SFVFS",
+            Opcode::Gpv => "GPV",
+            Opcode::Gfv => "GFV",
+            Opcode::Sfvtpv => "SFVTPV",
+            Opcode::Isect => "ISECT",
+            Opcode::Srp0 => "SRP0",
+            Opcode::Srp1 => "SRP1",
+            Opcode::Srp2 => "SRP2",
+            Opcode::Szp0 => "SZP0",
+            Opcode::Szp1 => "SZP1",
+            Opcode::Szp2 => "SZP2",
+            Opcode::Szps => "SZPS",
+            Opcode::Sloop => "SLOOP",
+            Opcode::Rtg => "RTG",
+            Opcode::Rthg => "RTHG",
+            Opcode::Smd => "SMD",
+            Opcode::Else => "ELSE",
+            Opcode::Jmpr => "JMPR",
+            Opcode::Scvtci => "SCVTCI",
+            Opcode::Sswci => "SSWCI",
+            Opcode::Ssw => "SSW",
+            Opcode::Dup => "DUP",
+            Opcode::Pop => "POP",
+            Opcode::Clear => "CLEAR",
+            Opcode::Swap => "SWAP",
+            Opcode::Depth => "DEPTH",
+            Opcode::Cindex => "CINDEX",
+            Opcode::Mindex => "MINDEX",
+            Opcode::Alignpts => "ALIGNPTS",
+            Opcode::Utp => "UTP",
+            Opcode::Loopcall => "LOOPCALL",
+            Opcode::Call => "CALL",
+            Opcode::Fdef => "FDEF",
+            Opcode::Endf => "ENDF",
+            Opcode::Mdap { .. } => "MDAP",
+            Opcode::Iup(_) => "IUP",
+            Opcode::Shp(_) => "SHP",
+            Opcode::Shc(_) => "SHC",
+            Opcode::Shz(_) => "SHZ",
+            Opcode::Shpix => "SHPIX",
+            Opcode::Ip => "IP",
+            Opcode::Msirp { .. } => "MSIRP",
+            Opcode::Alignrp => "ALIGNRP",
+            Opcode::Rtdg => "RTDG",
+            Opcode::Miap { .. } => "MIAP",
+            Opcode::Npushb => "NPUSHB",
+            Opcode::Npushw => "NPUSHW",
+            Opcode::Ws => "WS",
+            Opcode::Rs => "RS",
+            Opcode::Wcvtp => "WCVTP",
+            Opcode::Rcvt => "RCVT",
+            Opcode::Gc(_) => "GC",
+            Opcode::Scfs => "SCFS",
+            Opcode::Md(_) => "MD",
+            Opcode::Mppem => "MPPEM",
+            Opcode::Mps => "MPS",
+            Opcode::Flipon => "FLIPON",
+            Opcode::Flipoff => "FLIPOFF",
+            Opcode::Debug => "DEBUG",
+            Opcode::Lt => "LT",
+            Opcode::Lteq => "LTEQ",
+            Opcode::Gt => "GT",
+            Opcode::Gteq => "GTEQ",
+            Opcode::Eq => "EQ",
+            Opcode::Neq => "NEQ",
+            Opcode::Odd => "ODD",
+            Opcode::Even => "EVEN",
+            Opcode::If => "IF",
+            Opcode::Eif => "EIF",
+            Opcode::And => "AND",
+            Opcode::Or => "OR",
+            Opcode::Not => "NOT",
+            Opcode::Deltap1 => "DELTAP1",
+            Opcode::Sdb => "SDB",
+            Opcode::Sds => "SDS",
+            Opcode::Add => "ADD",
+            Opcode::Sub => "SUB",
+            Opcode::Div => "DIV",
+            Opcode::Mul => "MUL",
+            Opcode::Abs => "ABS",
+            Opcode::Neg => "NEG",
+            Opcode::Floor => "FLOOR",
+            Opcode::Ceiling => "CEILING",
+            Opcode::Round(_) => "ROUND",
+            Opcode::Nround(_) => "NROUND",
+            Opcode::Wcvtf => "WCVTF",
+            Opcode::Deltap2 => "DELTAP2",
+            Opcode::Deltap3 => "DELTAP3",
+            Opcode::Deltac1 => "DELTAC1",
+            Opcode::Deltac2 => "DELTAC2",
+            Opcode::Deltac3 => "DELTAC3",
+            Opcode::Sround => "SROUND",
+            Opcode::S45round => "S45ROUND",
+            Opcode::Jrot => "JROT",
+            Opcode::Jrof => "JROF",
+            Opcode::Roff => "ROFF",
+            Opcode::Rutg => "RUTG",
+            Opcode::Rdtg => "RDTG",
+            Opcode::Sangw => "SANGW",
+            Opcode::Aa => "AA",
+            Opcode::Flippt => "FLIPPT",
+            Opcode::Fliprgon => "FLIPRGON",
+            Opcode::Fliprgoff => "FLIPRGOFF",
+            Opcode::Scanctrl => "SCANCTRL",
+            Opcode::Sdpvtl(_) => "SDPVTL",
+            Opcode::Getinfo => "GETINFO",
+            Opcode::Idef => "IDEF",
+            Opcode::Roll => "ROLL",
+            Opcode::Max => "MAX",
+            Opcode::Min => "MIN",
+            Opcode::Scantype => "SCANTYPE",
+            Opcode::Instctrl => "INSTCTRL",
+            Opcode::Getvariation => "GETVARIATION",
+            Opcode::Pushb(_) => "PUSHB",
+            Opcode::Pushw(_) => "PUSHW",
+            Opcode::Mdrp(_) => "MDRP",
+            Opcode::Mirp(_) => "MIRP",
+            Opcode::Unknown(_) => "UNKNOWN",
+        }
+    }
+
+    /// What goes between the brackets after the name
+    fn flags(&self) -> Option<String> {
+        let axis = |axis: Axis| match axis {
+            Axis::X => "x",
+            Axis::Y => "y",
+        };
+        let pick = |flag: bool, set: &str, unset: &str| if flag { set } else { unset }.to_string();
+        let flags = match *self {
+            Opcode::Svtca(a) | Opcode::Spvtca(a) | Opcode::Sfvtca(a) | Opcode::Iup(a) => axis(a).to_string(),
+            Opcode::Spvtl(perpendicular) | Opcode::Sfvtl(perpendicular) | Opcode::Sdpvtl(perpendicular) =>
+                pick(perpendicular, "perp", "par"),
+            Opcode::Mdap { round } | Opcode::Miap { round } => pick(round, "rnd", ""),
+            Opcode::Shp(rp1) | Opcode::Shc(rp1) | Opcode::Shz(rp1) => pick(rp1, "rp1", "rp2"),
+            Opcode::Msirp { set_rp0 } => pick(set_rp0, "rp0", ""),
+            Opcode::Gc(original) | Opcode::Md(original) => pick(original, "orig", "cur"),
+            Opcode::Round(distance_type) | Opcode::Nround(distance_type) => distance_type.to_string(),
+            Opcode::Pushb(count) | Opcode::Pushw(count) => count.to_string(),
+            Opcode::Mdrp(flags) | Opcode::Mirp(flags) => {
+                let mut names = Vec::new();
+                if flags.set_rp0 {
+                    names.push("rp0".to_string());
+                }
+                if flags.min_distance {
+                    names.push("min".to_string());
+                }
+                if flags.round {
+                    names.push("rnd".to_string());
+                }
+                names.push(flags.distance_type.to_string());
+                names.join(",")
+            },
+            Opcode::Unknown(byte) => format!("0x{:02X}", byte),
+            _ => return None,
+        };
+        Some(flags)
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.flags() {
+            Some(flags) => write!(f, "{}[{}]", self.name(), flags),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Where it starts in the program
+    pub offset: usize,
+    pub opcode: Opcode,
+    /// The values pushed by the PUSH instructions
+    pub data: Vec<i32>,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode)?;
+        for value in &self.data {
+            write!(f, " {}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode a program, like a glyph's instructions or `fpgm` or `prep`.
+/// `None` if push data runs past the end.
+pub fn decode(code: &[u8]) -> Option<Vec<Instruction>> {
+    let mut instructions = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = Opcode::from_byte(code[pc]);
+        let len = instruction_length(code, pc)?;
+        let data = match opcode {
+            Opcode::Npushb => code[pc + 2..pc + len].iter().map(|&byte| byte as i32).collect(),
+            Opcode::Pushb(_) => code[pc + 1..pc + len].iter().map(|&byte| byte as i32).collect(),
+            Opcode::Npushw | Opcode::Pushw(_) => {
+                let start = if opcode == Opcode::Npushw { pc + 2 } else { pc + 1 };
+                code[start..pc + len].chunks(2)
+                    .map(|word| ((word[0] as i8 as i32) << 8) | word[1] as i32)
+                    .collect()
+            },
+            _ => Vec::new(),
+        };
+        instructions.push(Instruction { offset: pc, opcode, data });
+        pc += len;
+    }
+    Some(instructions)
+}
+
+/// A listing of the program with one instruction per line, each with its
+/// offset, and the bodies of IF, FDEF and IDEF indented
+pub fn disassemble(code: &[u8]) -> Option<String> {
+    let mut text = String::new();
+    let mut depth = 0;
+    for instruction in decode(code)? {
+        let (before, after) = match instruction.opcode {
+            Opcode::If | Opcode::Fdef | Opcode::Idef => (0, 1),
+            Opcode::Else => (-1, 1),
+            Opcode::Eif | Opcode::Endf => (-1, 0),
+            _ => (0, 0),
+        };
+        depth = (depth + before).max(0);
+        text.push_str(&format!("{:5}: {}{}\n", instruction.offset, "  ".repeat(depth as usize), instruction));
+        depth += after;
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_push_data_and_flags() {
+        let code = [0xB1, 1, 200, 0x41, 1, 0xFF, 0xFE, 0xF5, 0x2F, 0x30, 0x68];
+        let instructions = decode(&code).unwrap();
+        assert_eq!(instructions.len(), 6);
+        assert_eq!(instructions[0].opcode, Opcode::Pushb(2));
+        assert_eq!(instructions[0].data, vec![1, 200]);
+        assert_eq!(instructions[1].data, vec![-2]);
+        assert_eq!(instructions[2].offset, 7);
+        assert_eq!(instructions[2].opcode, Opcode::Mirp(MoveFlags {
+            set_rp0: true,
+            min_distance: false,
+            round: true,
+            distance_type: DistanceType::Black,
+        }));
+
+        let names: Vec<String> = instructions.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(names, vec!["PUSHB[2] 1 200", "NPUSHW -2", "MIRP[rp0,rnd,black]", "MDAP[rnd]", "IUP[y]", "ROUND[grey]"]);
+        assert_eq!(Opcode::from_byte(0xE4).to_string(), "MIRP[rnd,grey]");
+        assert_eq!(Opcode::from_byte(0x2E).to_string(), "MDAP[]");
+
+        // Runs off the end
+        assert_eq!(decode(&[0xB2, 1]), None);
+    }
+
+    #[test]
+    fn disassemble_font_programs() {
+        use font::{Font, GetTable};
+        use tables::fpgm::FontProgram;
+        use tables::glyf::Description;
+        use test_utils::font_buf;
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let fpgm: FontProgram = font.get_table().unwrap();
+        let text = disassemble(fpgm.instructions()).unwrap();
+        assert!(text.lines().next().unwrap().starts_with("    0: "));
+        // Function bodies are indented
+        let fdef = text.lines().position(|line| line.ends_with("FDEF")).unwrap();
+        assert!(text.lines().nth(fdef + 1).unwrap().contains(":   "));
+
+        let glyph = font.get_glyph('H').unwrap();
+        let instructions = match glyph.desc {
+            Description::Simple(ref simple) => decode(simple.instructions()).unwrap(),
+            _ => panic!("Should be a simple glyph"),
+        };
+        assert!(!instructions.is_empty());
+        assert!(instructions.iter().all(|instruction| match instruction.opcode {
+            Opcode::Unknown(_) => false,
+            _ => true,
+        }));
+    }
+}
@@ -0,0 +1,1612 @@
+//! The virtual machine that runs TrueType instructions. Follows FreeType's
+//! interpreter, including its v40 "backward compatibility" rules.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::InterpreterVersion;
+use render::DropoutMode;
+
+/// A 26.6 fixed point position, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Point26 {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+}
+
+impl Point26 {
+    fn sub(self, other: Point26) -> Point26 {
+        Point26 { x: self.x - other.x, y: self.y - other.y }
+    }
+}
+
+/// A unit vector in 2.14 fixed point
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Vector {
+    x: i32,
+    y: i32,
+}
+
+const X_AXIS: Vector = Vector { x: 0x4000, y: 0 };
+const Y_AXIS: Vector = Vector { x: 0, y: 0x4000 };
+
+const TOUCHED_X: u8 = 1;
+const TOUCHED_Y: u8 = 2;
+
+pub(crate) const TWILIGHT_ZONE: usize = 0;
+pub(crate) const GLYPH_ZONE: usize = 1;
+
+/// Stops programs that never end
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+const MAX_CALL_DEPTH: usize = 64;
+const MAX_STACK_SIZE: usize = 65536;
+
+/// Points that instructions move around
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Zone {
+    /// Scaled, but not grid-fitted
+    pub(crate) original: Vec<Point26>,
+    pub(crate) current: Vec<Point26>,
+    touched: Vec<u8>,
+    pub(crate) on_curve: Vec<bool>,
+    /// The index of the last point of each contour
+    pub(crate) contour_ends: Vec<usize>,
+}
+
+impl Zone {
+    pub(crate) fn new(points: Vec<Point26>, on_curve: Vec<bool>, contour_ends: Vec<usize>) -> Zone {
+        Zone {
+            original: points.clone(),
+            touched: vec![0; points.len()],
+            current: points,
+            on_curve,
+            contour_ends,
+        }
+    }
+
+    fn twilight(len: usize) -> Zone {
+        Zone::new(vec![Point26::default(); len], vec![false; len], Vec::new())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RoundState {
+    ToHalfGrid,
+    ToGrid,
+    ToDoubleGrid,
+    DownToGrid,
+    UpToGrid,
+    Off,
+    Super { period: i32, phase: i32, threshold: i32 },
+    /// Super rounding on a grid rotated 45 degrees
+    Super45 { period: i32, phase: i32, threshold: i32 },
+}
+
+#[derive(Debug, Clone)]
+struct GraphicsState {
+    auto_flip: bool,
+    control_value_cut_in: i32,
+    delta_base: i32,
+    delta_shift: i32,
+    dual_vector: Vector,
+    freedom_vector: Vector,
+    projection_vector: Vector,
+    instruct_control: i32,
+    loop_count: i32,
+    minimum_distance: i32,
+    round_state: RoundState,
+    rp0: usize,
+    rp1: usize,
+    rp2: usize,
+    /// Whether dropout control is on, from `SCANCTRL`
+    scan_control: bool,
+    /// The dropout rule, from `SCANTYPE`
+    scan_type: i32,
+    single_width_cut_in: i32,
+    single_width_value: i32,
+    zp0: usize,
+    zp1: usize,
+    zp2: usize,
+}
+
+impl Default for GraphicsState {
+    fn default() -> GraphicsState {
+        GraphicsState {
+            auto_flip: true,
+            // 17/16 of a pixel
+            control_value_cut_in: 68,
+            delta_base: 9,
+            delta_shift: 3,
+            dual_vector: X_AXIS,
+            freedom_vector: X_AXIS,
+            projection_vector: X_AXIS,
+            instruct_control: 0,
+            loop_count: 1,
+            minimum_distance: 64,
+            round_state: RoundState::ToGrid,
+            rp0: 0,
+            rp1: 0,
+            rp2: 0,
+            scan_control: false,
+            scan_type: 0,
+            single_width_cut_in: 0,
+            single_width_value: 0,
+            zp0: GLYPH_ZONE,
+            zp1: GLYPH_ZONE,
+            zp2: GLYPH_ZONE,
+        }
+    }
+}
+
+// INSTCTRL flags
+const INHIBIT_GLYPH_PROGRAMS: i32 = 1;
+const IGNORE_CVT_PROGRAM_STATE: i32 = 2;
+const NATIVE_CLEARTYPE: i32 = 4;
+
+/// `a * b / c`, rounded
+fn mul_div(a: i32, b: i32, c: i32) -> i32 {
+    if c == 0 {
+        return if (a < 0) != (b < 0) { -0x7FFF_FFFF } else { 0x7FFF_FFFF };
+    }
+    let product = a as i64 * b as i64;
+    let negative = (product < 0) != (c < 0);
+    let (product, c) = (product.abs(), (c as i64).abs());
+    let result = (product + c / 2) / c;
+    (if negative { -result } else { result }) as i32
+}
+
+/// Multiply by a 2.14 number
+fn mul14(a: i32, b: i32) -> i32 {
+    mul_div(a, b, 0x4000)
+}
+
+/// A unit vector pointing along `(dx, dy)`
+fn normalize(dx: i32, dy: i32) -> Vector {
+    if dx == 0 && dy == 0 {
+        return X_AXIS;
+    }
+    let len = (dx as f64).hypot(dy as f64);
+    Vector {
+        x: (dx as f64 * 16384. / len).round() as i32,
+        y: (dy as f64 * 16384. / len).round() as i32,
+    }
+}
+
+/// The size of the instruction at `pc`, including pushed data
+pub(crate) fn instruction_length(code: &[u8], pc: usize) -> Option<usize> {
+    let opcode = *code.get(pc)?;
+    let len = match opcode {
+        // NPUSHB, NPUSHW
+        0x40 => 2 + *code.get(pc + 1)? as usize,
+        0x41 => 2 + *code.get(pc + 1)? as usize * 2,
+        // PUSHB, PUSHW
+        0xB0..=0xB7 => 1 + (opcode - 0xAF) as usize,
+        0xB8..=0xBF => 1 + (opcode - 0xB7) as usize * 2,
+        _ => 1,
+    };
+    if pc + len > code.len() {
+        None
+    } else {
+        Some(len)
+    }
+}
+
+/// The position after the ELSE (when `stop_at_else`) or EIF closing the
+/// IF that `pc` is in
+fn skip_branch(code: &[u8], mut pc: usize, stop_at_else: bool) -> Option<usize> {
+    let mut nesting = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        pc += instruction_length(code, pc)?;
+        match opcode {
+            // IF
+            0x58 => nesting += 1,
+            // ELSE
+            0x1B if nesting == 0 && stop_at_else => return Some(pc),
+            // EIF
+            0x59 => {
+                if nesting == 0 {
+                    return Some(pc);
+                }
+                nesting -= 1;
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The position of the ENDF closing the definition that starts at `pc`
+fn find_definition_end(code: &[u8], mut pc: usize) -> Option<usize> {
+    while pc < code.len() {
+        if code[pc] == 0x2D {
+            return Some(pc);
+        }
+        pc += instruction_length(code, pc)?;
+    }
+    None
+}
+
+/// Everything the instructions can read and change
+pub(crate) struct Machine {
+    version: InterpreterVersion,
+    ppem: u16,
+    /// Font units to 26.6, in 16.16
+    scale: i64,
+    gs: GraphicsState,
+    /// What glyph programs start with, left by `prep`
+    default_gs: GraphicsState,
+    zones: [Zone; 2],
+    cvt: Vec<i32>,
+    storage: Vec<i32>,
+    stack: Vec<i32>,
+    functions: HashMap<i32, Rc<Vec<u8>>>,
+    instruction_defs: HashMap<u8, Rc<Vec<u8>>>,
+    /// Projection of the freedom vector onto the projection vector
+    f_dot_p: i32,
+    in_cvt_program: bool,
+    is_composite: bool,
+    iup_x_called: bool,
+    iup_y_called: bool,
+    instruction_count: usize,
+}
+
+impl Machine {
+    pub(crate) fn new(version: InterpreterVersion, ppem: u16, units_per_em: u16, cvt: &[i16],
+                      storage_size: usize, twilight_size: usize) -> Machine {
+        let scale = ((ppem as i64 * 64) << 16) / units_per_em.max(1) as i64;
+        let mut machine = Machine {
+            version,
+            ppem,
+            scale,
+            gs: GraphicsState::default(),
+            default_gs: GraphicsState::default(),
+            zones: [Zone::twilight(twilight_size), Zone::default()],
+            cvt: Vec::new(),
+            storage: vec![0; storage_size],
+            stack: Vec::new(),
+            functions: HashMap::new(),
+            instruction_defs: HashMap::new(),
+            f_dot_p: 0x4000,
+            in_cvt_program: false,
+            is_composite: false,
+            iup_x_called: false,
+            iup_y_called: false,
+            instruction_count: 0,
+        };
+        machine.cvt = cvt.iter().map(|&value| machine.scale(value as i32)).collect();
+        machine
+    }
+
+    /// Font units to 26.6 pixels
+    pub(crate) fn scale(&self, value: i32) -> i32 {
+        ((value as i64 * self.scale + 0x8000) >> 16) as i32
+    }
+
+    /// Run `fpgm`, which defines functions
+    pub(crate) fn run_font_program(&mut self, code: &[u8]) -> Option<()> {
+        self.gs = GraphicsState::default();
+        self.run(code)
+    }
+
+    /// Run `prep`, which sets up the control values and the graphics
+    /// state the glyph programs start with
+    pub(crate) fn run_cvt_program(&mut self, code: &[u8]) -> Option<()> {
+        self.gs = GraphicsState::default();
+        self.in_cvt_program = true;
+        let result = self.run(code);
+        self.in_cvt_program = false;
+        self.default_gs = self.gs.clone();
+        result
+    }
+
+    /// Run a glyph's instructions over its points, returning the moved
+    /// points. The control values, storage and twilight zone go back to
+    /// how `prep` left them afterwards.
+    pub(crate) fn run_glyph_program(&mut self, zone: Zone, code: &[u8], is_composite: bool) -> Option<Zone> {
+        self.gs = if self.default_gs.instruct_control & IGNORE_CVT_PROGRAM_STATE != 0 {
+            GraphicsState::default()
+        } else {
+            // These are reset for every glyph
+            GraphicsState {
+                zp0: GLYPH_ZONE,
+                zp1: GLYPH_ZONE,
+                zp2: GLYPH_ZONE,
+                projection_vector: X_AXIS,
+                freedom_vector: X_AXIS,
+                dual_vector: X_AXIS,
+                round_state: RoundState::ToGrid,
+                loop_count: 1,
+                ..self.default_gs.clone()
+            }
+        };
+        if code.is_empty() || self.default_gs.instruct_control & INHIBIT_GLYPH_PROGRAMS != 0 {
+            return Some(zone);
+        }
+        let saved = (self.cvt.clone(), self.storage.clone(), self.zones[TWILIGHT_ZONE].clone());
+        self.zones[GLYPH_ZONE] = zone;
+        self.is_composite = is_composite;
+        self.iup_x_called = false;
+        self.iup_y_called = false;
+
+        let result = self.run(code);
+
+        let zone = ::std::mem::replace(&mut self.zones[GLYPH_ZONE], Zone::default());
+        self.cvt = saved.0;
+        self.storage = saved.1;
+        self.zones[TWILIGHT_ZONE] = saved.2;
+        result.map(|_| zone)
+    }
+
+    fn run(&mut self, code: &[u8]) -> Option<()> {
+        self.stack.clear();
+        self.instruction_count = 0;
+        self.update_f_dot_p();
+        self.execute(code, 0)
+    }
+
+    /// How the glyph last run asked to be rasterized in monochrome
+    pub(crate) fn dropout_mode(&self) -> DropoutMode {
+        if self.gs.scan_control {
+            DropoutMode::from_scan_type(self.gs.scan_type)
+        } else {
+            DropoutMode::Off
+        }
+    }
+
+    /// Whether `SCANCTRL` with `flags` turns dropout control on. The low
+    /// byte is a ppem threshold, where 0xFF means always and 0 never. Glyphs
+    /// are never rotated or stretched here.
+    fn scan_control(&self, flags: i32) -> bool {
+        let threshold = flags & 0xFF;
+        match threshold {
+            0xFF => return true,
+            0 => return false,
+            _ => (),
+        }
+        let small = self.ppem as i32 <= threshold;
+        let mut scan_control = self.gs.scan_control;
+        if flags & 0x100 != 0 && small {
+            scan_control = true;
+        }
+        if flags & 0x800 != 0 && !small {
+            scan_control = false;
+        }
+        // Off unless rotated, or unless stretched
+        if flags & 0x3000 != 0 {
+            scan_control = false;
+        }
+        scan_control
+    }
+
+    /// In v40, unless the font says it knows about ClearType, horizontal
+    /// moves are ignored and nothing moves after both IUPs
+    fn backward_compatibility(&self) -> bool {
+        self.version == InterpreterVersion::V40 && self.gs.instruct_control & NATIVE_CLEARTYPE == 0
+    }
+
+    fn post_iup(&self) -> bool {
+        self.iup_x_called && self.iup_y_called
+    }
+
+    fn pop(&mut self) -> Option<i32> {
+        self.stack.pop()
+    }
+
+    fn pop_point(&mut self) -> Option<usize> {
+        let point = self.pop()?;
+        if point < 0 {
+            None
+        } else {
+            Some(point as usize)
+        }
+    }
+
+    fn pop_zone(&mut self) -> Option<usize> {
+        match self.pop()? {
+            0 => Some(TWILIGHT_ZONE),
+            1 => Some(GLYPH_ZONE),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, value: i32) -> Option<()> {
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return None;
+        }
+        self.stack.push(value);
+        Some(())
+    }
+
+    fn current(&self, zone: usize, point: usize) -> Option<Point26> {
+        self.zones[zone].current.get(point).cloned()
+    }
+
+    fn original(&self, zone: usize, point: usize) -> Option<Point26> {
+        self.zones[zone].original.get(point).cloned()
+    }
+
+    fn project(&self, vector: Point26) -> i32 {
+        let pv = self.gs.projection_vector;
+        ((vector.x as i64 * pv.x as i64 + vector.y as i64 * pv.y as i64 + 0x2000) >> 14) as i32
+    }
+
+    fn dual_project(&self, vector: Point26) -> i32 {
+        let dv = self.gs.dual_vector;
+        ((vector.x as i64 * dv.x as i64 + vector.y as i64 * dv.y as i64 + 0x2000) >> 14) as i32
+    }
+
+    fn update_f_dot_p(&mut self) {
+        let (fv, pv) = (self.gs.freedom_vector, self.gs.projection_vector);
+        let f_dot_p = ((fv.x as i64 * pv.x as i64 + fv.y as i64 * pv.y as i64) >> 14) as i32;
+        // Nearly perpendicular vectors would move points way too far
+        self.f_dot_p = if f_dot_p.abs() < 0x400 { 0x4000 } else { f_dot_p };
+    }
+
+    /// How far to move along the freedom vector to change the projected
+    /// position by `distance`
+    fn displacement(&self, distance: i32) -> (i32, i32) {
+        let fv = self.gs.freedom_vector;
+        (mul_div(distance, fv.x, self.f_dot_p), mul_div(distance, fv.y, self.f_dot_p))
+    }
+
+    fn shift_point(&mut self, zone: usize, point: usize, dx: i32, dy: i32, touch: bool) -> Option<()> {
+        let fv = self.gs.freedom_vector;
+        let backward_compatibility = self.backward_compatibility();
+        let post_iup = self.post_iup();
+        let zone = &mut self.zones[zone];
+        if point >= zone.current.len() {
+            return None;
+        }
+        if fv.x != 0 {
+            if !backward_compatibility {
+                zone.current[point].x += dx;
+            }
+            if touch {
+                zone.touched[point] |= TOUCHED_X;
+            }
+        }
+        if fv.y != 0 {
+            if !(backward_compatibility && post_iup) {
+                zone.current[point].y += dy;
+            }
+            if touch {
+                zone.touched[point] |= TOUCHED_Y;
+            }
+        }
+        Some(())
+    }
+
+    /// Move the point along the freedom vector until its projection has
+    /// changed by `distance`
+    fn move_point(&mut self, zone: usize, point: usize, distance: i32) -> Option<()> {
+        let (dx, dy) = self.displacement(distance);
+        self.shift_point(zone, point, dx, dy, true)
+    }
+
+    /// Move the point's original position along the freedom vector
+    fn move_original(&mut self, zone: usize, point: usize, distance: i32) -> Option<()> {
+        let (dx, dy) = self.displacement(distance);
+        let original = self.zones[zone].original.get_mut(point)?;
+        original.x += dx;
+        original.y += dy;
+        Some(())
+    }
+
+    fn round(&self, distance: i32) -> i32 {
+        let clamp = |value: i32| if distance >= 0 { value.max(0) } else { value.min(0) };
+        match self.gs.round_state {
+            RoundState::ToGrid => if distance >= 0 {
+                clamp((distance + 32) & !63)
+            } else {
+                clamp(-((32 - distance) & !63))
+            },
+            RoundState::ToHalfGrid => if distance >= 0 {
+                clamp((distance & !63) + 32)
+            } else {
+                clamp(-(((-distance) & !63) + 32))
+            },
+            RoundState::ToDoubleGrid => if distance >= 0 {
+                clamp((distance + 16) & !31)
+            } else {
+                clamp(-((16 - distance) & !31))
+            },
+            RoundState::DownToGrid => if distance >= 0 {
+                clamp(distance & !63)
+            } else {
+                clamp(-((-distance) & !63))
+            },
+            RoundState::UpToGrid => if distance >= 0 {
+                clamp((distance + 63) & !63)
+            } else {
+                clamp(-((63 - distance) & !63))
+            },
+            RoundState::Off => distance,
+            RoundState::Super { period, phase, threshold } => if distance >= 0 {
+                let value = ((distance - phase + threshold) & -period) + phase;
+                if value < 0 { phase } else { value }
+            } else {
+                let value = -(((threshold - phase - distance) & -period) + phase);
+                if value > 0 { -phase } else { value }
+            },
+            RoundState::Super45 { period, phase, threshold } => if distance >= 0 {
+                let value = (distance - phase + threshold) / period * period + phase;
+                if value < 0 { phase } else { value }
+            } else {
+                let value = -((threshold - phase - distance) / period * period + phase);
+                if value > 0 { -phase } else { value }
+            },
+        }
+    }
+
+    /// The state set by SROUND and S45ROUND
+    fn super_round(selector: i32, grid_period: i32, diagonal: bool) -> RoundState {
+        let period = match (selector >> 6) & 3 {
+            0 => grid_period / 2,
+            2 => grid_period * 2,
+            _ => grid_period,
+        };
+        let phase = match (selector >> 4) & 3 {
+            0 => 0,
+            1 => period / 4,
+            2 => period / 2,
+            _ => period * 3 / 4,
+        };
+        let threshold = if selector & 15 == 0 {
+            period - 1
+        } else {
+            ((selector & 15) - 4) * period / 8
+        };
+        if diagonal {
+            RoundState::Super45 { period, phase, threshold }
+        } else {
+            RoundState::Super { period, phase, threshold }
+        }
+    }
+
+    fn cvt_value(&self, idx: i32) -> i32 {
+        if idx < 0 {
+            return 0;
+        }
+        self.cvt.get(idx as usize).cloned().unwrap_or(0)
+    }
+
+    /// Run the instructions in `code`
+    fn execute(&mut self, code: &[u8], depth: usize) -> Option<()> {
+        if depth > MAX_CALL_DEPTH {
+            return None;
+        }
+        let mut pc = 0;
+        while pc < code.len() {
+            self.instruction_count += 1;
+            if self.instruction_count > MAX_INSTRUCTIONS {
+                return None;
+            }
+            let opcode = code[pc];
+            let len = instruction_length(code, pc)?;
+            let start = pc;
+            pc += len;
+
+            match opcode {
+                // SVTCA, SPVTCA, SFVTCA
+                0x00..=0x05 => {
+                    let axis = if opcode & 1 == 1 { X_AXIS } else { Y_AXIS };
+                    if opcode < 0x04 {
+                        self.gs.projection_vector = axis;
+                        self.gs.dual_vector = axis;
+                    }
+                    if opcode < 0x02 || opcode >= 0x04 {
+                        self.gs.freedom_vector = axis;
+                    }
+                    self.update_f_dot_p();
+                },
+                // SPVTL, SFVTL
+                0x06..=0x09 => {
+                    let (p2, p1) = (self.pop_point()?, self.pop_point()?);
+                    let (zp1, zp2) = (self.gs.zp1, self.gs.zp2);
+                    let delta = self.current(zp1, p1)?.sub(self.current(zp2, p2)?);
+                    let vector = if opcode & 1 == 1 {
+                        normalize(-delta.y, delta.x)
+                    } else {
+                        normalize(delta.x, delta.y)
+                    };
+                    if opcode < 0x08 {
+                        self.gs.projection_vector = vector;
+                        self.gs.dual_vector = vector;
+                    } else {
+                        self.gs.freedom_vector = vector;
+                    }
+                    self.update_f_dot_p();
+                },
+                // SPVFS, SFVFS
+                0x0A | 0x0B => {
+                    let (y, x) = (self.pop()?, self.pop()?);
+                    let vector = normalize(x, y);
+                    if opcode == 0x0A {
+                        self.gs.projection_vector = vector;
+                        self.gs.dual_vector = vector;
+                    } else {
+                        self.gs.freedom_vector = vector;
+                    }
+                    self.update_f_dot_p();
+                },
+                // GPV, GFV
+                0x0C | 0x0D => {
+                    let vector = if opcode == 0x0C { self.gs.projection_vector } else { self.gs.freedom_vector };
+                    self.push(vector.x)?;
+                    self.push(vector.y)?;
+                },
+                // SFVTPV
+                0x0E => {
+                    self.gs.freedom_vector = self.gs.projection_vector;
+                    self.update_f_dot_p();
+                },
+                // ISECT
+                0x0F => self.intersect()?,
+                // SRP0, SRP1, SRP2
+                0x10 => self.gs.rp0 = self.pop_point()?,
+                0x11 => self.gs.rp1 = self.pop_point()?,
+                0x12 => self.gs.rp2 = self.pop_point()?,
+                // SZP0, SZP1, SZP2, SZPS
+                0x13 => self.gs.zp0 = self.pop_zone()?,
+                0x14 => self.gs.zp1 = self.pop_zone()?,
+                0x15 => self.gs.zp2 = self.pop_zone()?,
+                0x16 => {
+                    let zone = self.pop_zone()?;
+                    self.gs.zp0 = zone;
+                    self.gs.zp1 = zone;
+                    self.gs.zp2 = zone;
+                },
+                // SLOOP
+                0x17 => self.gs.loop_count = self.pop()?,
+                // RTG, RTHG
+                0x18 => self.gs.round_state = RoundState::ToGrid,
+                0x19 => self.gs.round_state = RoundState::ToHalfGrid,
+                // SMD
+                0x1A => self.gs.minimum_distance = self.pop()?,
+                // ELSE, reached at the end of the IF branch
+                0x1B => pc = skip_branch(code, pc, false)?,
+                // JMPR
+                0x1C => {
+                    let offset = self.pop()?;
+                    pc = (start as i64 + offset as i64) as usize;
+                },
+                // SCVTCI, SSWCI, SSW
+                0x1D => self.gs.control_value_cut_in = self.pop()?,
+                0x1E => self.gs.single_width_cut_in = self.pop()?,
+                0x1F => {
+                    let value = self.pop()?;
+                    self.gs.single_width_value = self.scale(value);
+                },
+                // DUP
+                0x20 => {
+                    let value = *self.stack.last()?;
+                    self.push(value)?;
+                },
+                // POP
+                0x21 => {
+                    self.pop()?;
+                },
+                // CLEAR
+                0x22 => self.stack.clear(),
+                // SWAP
+                0x23 => {
+                    let (b, a) = (self.pop()?, self.pop()?);
+                    self.push(b)?;
+                    self.push(a)?;
+                },
+                // DEPTH
+                0x24 => {
+                    let depth = self.stack.len() as i32;
+                    self.push(depth)?;
+                },
+                // CINDEX, MINDEX
+                0x25 | 0x26 => {
+                    let idx = self.pop()?;
+                    let len = self.stack.len();
+                    if idx < 1 || idx as usize > len {
+                        return None;
+                    }
+                    let value = if opcode == 0x25 {
+                        self.stack[len - idx as usize]
+                    } else {
+                        self.stack.remove(len - idx as usize)
+                    };
+                    self.push(value)?;
+                },
+                // ALIGNPTS
+                0x27 => {
+                    let (p2, p1) = (self.pop_point()?, self.pop_point()?);
+                    let (zp0, zp1) = (self.gs.zp0, self.gs.zp1);
+                    let distance = self.project(self.current(zp0, p2)?.sub(self.current(zp1, p1)?)) / 2;
+                    self.move_point(zp1, p1, distance)?;
+                    self.move_point(zp0, p2, -distance)?;
+                },
+                // UTP
+                0x29 => {
+                    let point = self.pop_point()?;
+                    let fv = self.gs.freedom_vector;
+                    let zone = &mut self.zones[self.gs.zp0];
+                    let touched = zone.touched.get_mut(point)?;
+                    if fv.x != 0 {
+                        *touched &= !TOUCHED_X;
+                    }
+                    if fv.y != 0 {
+                        *touched &= !TOUCHED_Y;
+                    }
+                },
+                // LOOPCALL, CALL
+                0x2A | 0x2B => {
+                    let function = self.pop()?;
+                    let count = if opcode == 0x2A { self.pop()? } else { 1 };
+                    let body = self.functions.get(&function)?.clone();
+                    // Each call counts, so a huge loop over an empty
+                    // function still runs out
+                    self.instruction_count = self.instruction_count.saturating_add(count.max(0) as usize);
+                    if self.instruction_count > MAX_INSTRUCTIONS {
+                        return None;
+                    }
+                    for _ in 0..count {
+                        self.execute(&body, depth + 1)?;
+                    }
+                },
+                // FDEF
+                0x2C => {
+                    let function = self.pop()?;
+                    let end = find_definition_end(code, pc)?;
+                    self.functions.insert(function, Rc::new(code[pc..end].to_vec()));
+                    pc = end + 1;
+                },
+                // ENDF
+                0x2D => return Some(()),
+                // MDAP
+                0x2E | 0x2F => {
+                    let point = self.pop_point()?;
+                    let zp0 = self.gs.zp0;
+                    let distance = if opcode & 1 == 1 {
+                        let position = self.project(self.current(zp0, point)?);
+                        self.round(position) - position
+                    } else {
+                        0
+                    };
+                    self.move_point(zp0, point, distance)?;
+                    self.gs.rp0 = point;
+                    self.gs.rp1 = point;
+                },
+                // IUP
+                0x30 | 0x31 => self.interpolate_untouched(opcode & 1 == 1),
+                // SHP, SHC, SHZ
+                0x32..=0x37 => {
+                    let (zone, reference) = if opcode & 1 == 1 {
+                        (self.gs.zp0, self.gs.rp1)
+                    } else {
+                        (self.gs.zp1, self.gs.rp2)
+                    };
+                    let distance = self.project(self.current(zone, reference)?.sub(self.original(zone, reference)?));
+                    let (dx, dy) = self.displacement(distance);
+                    match opcode {
+                        0x32 | 0x33 => {
+                            let zp2 = self.gs.zp2;
+                            for _ in 0..self.gs.loop_count {
+                                let point = self.pop_point()?;
+                                self.shift_point(zp2, point, dx, dy, true)?;
+                            }
+                            self.gs.loop_count = 1;
+                        },
+                        0x34 | 0x35 => {
+                            let contour = self.pop_point()?;
+                            let zp2 = self.gs.zp2;
+                            let ends = &self.zones[zp2].contour_ends;
+                            let end = *ends.get(contour)?;
+                            let start = if contour == 0 { 0 } else { ends[contour - 1] + 1 };
+                            for point in start..=end {
+                                if zone != zp2 || point != reference {
+                                    self.shift_point(zp2, point, dx, dy, true)?;
+                                }
+                            }
+                        },
+                        _ => {
+                            let target = self.pop_zone()?;
+                            // Phantom points stay put
+                            let limit = match self.zones[target].contour_ends.last() {
+                                Some(&end) => end + 1,
+                                None => self.zones[target].current.len(),
+                            };
+                            for point in 0..limit {
+                                if zone != target || point != reference {
+                                    self.shift_point(target, point, dx, dy, false)?;
+                                }
+                            }
+                        },
+                    }
+                },
+                // SHPIX
+                0x38 => {
+                    let amount = self.pop()?;
+                    let fv = self.gs.freedom_vector;
+                    let (dx, dy) = (mul14(amount, fv.x), mul14(amount, fv.y));
+                    let zp2 = self.gs.zp2;
+                    let in_twilight = self.gs.zp0 == TWILIGHT_ZONE || self.gs.zp1 == TWILIGHT_ZONE || zp2 == TWILIGHT_ZONE;
+                    for _ in 0..self.gs.loop_count {
+                        let point = self.pop_point()?;
+                        if self.backward_compatibility() {
+                            let touched_y = self.zones[zp2].touched.get(point).map_or(false, |&touched| touched & TOUCHED_Y != 0);
+                            if in_twilight || (!self.post_iup() && ((self.is_composite && fv.y != 0) || touched_y)) {
+                                self.shift_point(zp2, point, 0, dy, true)?;
+                            }
+                        } else {
+                            self.shift_point(zp2, point, dx, dy, true)?;
+                        }
+                    }
+                    self.gs.loop_count = 1;
+                },
+                // IP
+                0x39 => self.interpolate_point()?,
+                // MSIRP
+                0x3A | 0x3B => {
+                    let distance = self.pop()?;
+                    let point = self.pop_point()?;
+                    let (zp0, zp1, rp0) = (self.gs.zp0, self.gs.zp1, self.gs.rp0);
+                    if zp1 == TWILIGHT_ZONE {
+                        let origin = self.original(zp0, rp0)?;
+                        *self.zones[zp1].original.get_mut(point)? = origin;
+                        self.move_original(zp1, point, distance)?;
+                        self.zones[zp1].current[point] = self.zones[zp1].original[point];
+                    }
+                    let current = self.project(self.current(zp1, point)?.sub(self.current(zp0, rp0)?));
+                    self.move_point(zp1, point, distance - current)?;
+                    self.gs.rp1 = rp0;
+                    self.gs.rp2 = point;
+                    if opcode & 1 == 1 {
+                        self.gs.rp0 = point;
+                    }
+                },
+                // ALIGNRP
+                0x3C => {
+                    let (zp0, zp1, rp0) = (self.gs.zp0, self.gs.zp1, self.gs.rp0);
+                    for _ in 0..self.gs.loop_count {
+                        let point = self.pop_point()?;
+                        let distance = self.project(self.current(zp0, rp0)?.sub(self.current(zp1, point)?));
+                        self.move_point(zp1, point, distance)?;
+                    }
+                    self.gs.loop_count = 1;
+                },
+                // RTDG
+                0x3D => self.gs.round_state = RoundState::ToDoubleGrid,
+                // MIAP
+                0x3E | 0x3F => {
+                    let cvt = self.pop()?;
+                    let point = self.pop_point()?;
+                    let mut distance = self.cvt_value(cvt);
+                    let zp0 = self.gs.zp0;
+                    if zp0 == TWILIGHT_ZONE {
+                        let fv = self.gs.freedom_vector;
+                        let position = Point26 { x: mul14(distance, fv.x), y: mul14(distance, fv.y) };
+                        *self.zones[zp0].original.get_mut(point)? = position;
+                        self.zones[zp0].current[point] = position;
+                    }
+                    let original = self.project(self.current(zp0, point)?);
+                    if opcode & 1 == 1 {
+                        if (distance - original).abs() > self.gs.control_value_cut_in {
+                            distance = original;
+                        }
+                        distance = self.round(distance);
+                    }
+                    self.move_point(zp0, point, distance - original)?;
+                    self.gs.rp0 = point;
+                    self.gs.rp1 = point;
+                },
+                // NPUSHB, NPUSHW, PUSHB, PUSHW
+                0x40 | 0x41 | 0xB0..=0xBF => {
+                    let (data_start, words) = match opcode {
+                        0x40 => (start + 2, false),
+                        0x41 => (start + 2, true),
+                        0xB0..=0xB7 => (start + 1, false),
+                        _ => (start + 1, true),
+                    };
+                    let data = &code[data_start..pc];
+                    if words {
+                        for word in data.chunks(2) {
+                            self.push((word[0] as i8 as i32) << 8 | word[1] as i32)?;
+                        }
+                    } else {
+                        for &byte in data {
+                            self.push(byte as i32)?;
+                        }
+                    }
+                },
+                // WS
+                0x42 => {
+                    let (value, idx) = (self.pop()?, self.pop()?);
+                    if let Some(slot) = self.storage.get_mut(idx as usize) {
+                        *slot = value;
+                    }
+                },
+                // RS
+                0x43 => {
+                    let idx = self.pop()?;
+                    let value = self.storage.get(idx as usize).cloned().unwrap_or(0);
+                    self.push(value)?;
+                },
+                // WCVTP, WCVTF
+                0x44 | 0x70 => {
+                    let (value, idx) = (self.pop()?, self.pop()?);
+                    let value = if opcode == 0x70 { self.scale(value) } else { value };
+                    if let Some(slot) = self.cvt.get_mut(idx as usize) {
+                        *slot = value;
+                    }
+                },
+                // RCVT
+                0x45 => {
+                    let idx = self.pop()?;
+                    let value = self.cvt_value(idx);
+                    self.push(value)?;
+                },
+                // GC
+                0x46 | 0x47 => {
+                    let point = self.pop_point()?;
+                    let zp2 = self.gs.zp2;
+                    let value = if opcode & 1 == 1 {
+                        self.dual_project(self.original(zp2, point)?)
+                    } else {
+                        self.project(self.current(zp2, point)?)
+                    };
+                    self.push(value)?;
+                },
+                // SCFS
+                0x48 => {
+                    let (value, point) = (self.pop()?, self.pop_point()?);
+                    let zp2 = self.gs.zp2;
+                    let current = self.project(self.current(zp2, point)?);
+                    self.move_point(zp2, point, value - current)?;
+                    if zp2 == TWILIGHT_ZONE {
+                        self.zones[zp2].original[point] = self.zones[zp2].current[point];
+                    }
+                },
+                // MD
+                0x49 | 0x4A => {
+                    let (p2, p1) = (self.pop_point()?, self.pop_point()?);
+                    let (zp0, zp1) = (self.gs.zp0, self.gs.zp1);
+                    let distance = if opcode & 1 == 1 {
+                        self.project(self.current(zp0, p1)?.sub(self.current(zp1, p2)?))
+                    } else {
+                        self.dual_project(self.original(zp0, p1)?.sub(self.original(zp1, p2)?))
+                    };
+                    self.push(distance)?;
+                },
+                // MPPEM, MPS
+                0x4B | 0x4C => {
+                    let ppem = self.ppem as i32;
+                    self.push(ppem)?;
+                },
+                // FLIPON, FLIPOFF
+                0x4D => self.gs.auto_flip = true,
+                0x4E => self.gs.auto_flip = false,
+                // DEBUG
+                0x4F => {
+                    self.pop()?;
+                },
+                // LT, LTEQ, GT, GTEQ, EQ, NEQ
+                0x50..=0x55 => {
+                    let (b, a) = (self.pop()?, self.pop()?);
+                    let result = match opcode {
+                        0x50 => a < b,
+                        0x51 => a <= b,
+                        0x52 => a > b,
+                        0x53 => a >= b,
+                        0x54 => a == b,
+                        _ => a != b,
+                    };
+                    self.push(result as i32)?;
+                },
+                // ODD, EVEN
+                0x56 | 0x57 => {
+                    let value = self.pop()?;
+                    let value = self.round(value) & 127;
+                    self.push((value == if opcode == 0x56 { 64 } else { 0 }) as i32)?;
+                },
+                // IF
+                0x58 => {
+                    if self.pop()? == 0 {
+                        pc = skip_branch(code, pc, true)?;
+                    }
+                },
+                // EIF
+                0x59 => (),
+                // AND, OR
+                0x5A | 0x5B => {
+                    let (b, a) = (self.pop()? != 0, self.pop()? != 0);
+                    self.push(if opcode == 0x5A { a && b } else { a || b } as i32)?;
+                },
+                // NOT
+                0x5C => {
+                    let value = self.pop()?;
+                    self.push((value == 0) as i32)?;
+                },
+                // DELTAP1, DELTAP2, DELTAP3
+                0x5D | 0x71 | 0x72 => {
+                    let range = match opcode { 0x5D => 0, 0x71 => 16, _ => 32 };
+                    self.delta_points(range)?;
+                },
+                // SDB, SDS
+                0x5E => self.gs.delta_base = self.pop()?,
+                0x5F => self.gs.delta_shift = self.pop()?.max(0).min(6),
+                // ADD, SUB, DIV, MUL
+                0x60..=0x63 => {
+                    let (b, a) = (self.pop()?, self.pop()?);
+                    let result = match opcode {
+                        0x60 => a.wrapping_add(b),
+                        0x61 => a.wrapping_sub(b),
+                        0x62 => {
+                            if b == 0 {
+                                return None;
+                            }
+                            mul_div(a, 64, b)
+                        },
+                        _ => mul_div(a, b, 64),
+                    };
+                    self.push(result)?;
+                },
+                // ABS, NEG, FLOOR, CEILING
+                0x64..=0x67 => {
+                    let value = self.pop()?;
+                    let result = match opcode {
+                        0x64 => value.abs(),
+                        0x65 => -value,
+                        0x66 => value & !63,
+                        _ => (value + 63) & !63,
+                    };
+                    self.push(result)?;
+                },
+                // ROUND, NROUND. There is no engine compensation.
+                0x68..=0x6F => {
+                    let value = self.pop()?;
+                    let value = if opcode < 0x6C { self.round(value) } else { value };
+                    self.push(value)?;
+                },
+                // DELTAC1, DELTAC2, DELTAC3
+                0x73..=0x75 => {
+                    let range = (opcode as i32 - 0x73) * 16;
+                    self.delta_cvt(range)?;
+                },
+                // SROUND, S45ROUND
+                0x76 => {
+                    let selector = self.pop()?;
+                    self.gs.round_state = Machine::super_round(selector, 64, false);
+                },
+                0x77 => {
+                    let selector = self.pop()?;
+                    // 64 / sqrt(2)
+                    self.gs.round_state = Machine::super_round(selector, 45, true);
+                },
+                // JROT, JROF
+                0x78 | 0x79 => {
+                    let (condition, offset) = (self.pop()?, self.pop()?);
+                    if (condition != 0) == (opcode == 0x78) {
+                        pc = (start as i64 + offset as i64) as usize;
+                    }
+                },
+                // ROFF, RUTG, RDTG
+                0x7A => self.gs.round_state = RoundState::Off,
+                0x7C => self.gs.round_state = RoundState::UpToGrid,
+                0x7D => self.gs.round_state = RoundState::DownToGrid,
+                // SANGW, AA
+                0x7E | 0x7F => {
+                    self.pop()?;
+                },
+                // FLIPPT
+                0x80 => {
+                    let zp0 = self.gs.zp0;
+                    let ignore = self.backward_compatibility() && self.post_iup();
+                    for _ in 0..self.gs.loop_count {
+                        let point = self.pop_point()?;
+                        let on_curve = self.zones[zp0].on_curve.get_mut(point)?;
+                        if !ignore {
+                            *on_curve = !*on_curve;
+                        }
+                    }
+                    self.gs.loop_count = 1;
+                },
+                // FLIPRGON, FLIPRGOFF
+                0x81 | 0x82 => {
+                    let (high, low) = (self.pop_point()?, self.pop_point()?);
+                    let zp0 = self.gs.zp0;
+                    if high < low || high >= self.zones[zp0].on_curve.len() {
+                        return None;
+                    }
+                    if !(self.backward_compatibility() && self.post_iup()) {
+                        for on_curve in &mut self.zones[zp0].on_curve[low..=high] {
+                            *on_curve = opcode == 0x81;
+                        }
+                    }
+                },
+                // SCANCTRL
+                0x85 => {
+                    let flags = self.pop()?;
+                    self.gs.scan_control = self.scan_control(flags);
+                },
+                // SCANTYPE
+                0x8D => {
+                    let scan_type = self.pop()?;
+                    if scan_type >= 0 {
+                        self.gs.scan_type = scan_type & 0xFFFF;
+                    }
+                },
+                // SDPVTL
+                0x86 | 0x87 => {
+                    let (p2, p1) = (self.pop_point()?, self.pop_point()?);
+                    let (zp1, zp2) = (self.gs.zp1, self.gs.zp2);
+                    let original = self.original(zp1, p1)?.sub(self.original(zp2, p2)?);
+                    let current = self.current(zp1, p1)?.sub(self.current(zp2, p2)?);
+                    let vector = |delta: Point26| if opcode & 1 == 1 {
+                        normalize(-delta.y, delta.x)
+                    } else {
+                        normalize(delta.x, delta.y)
+                    };
+                    self.gs.dual_vector = vector(original);
+                    self.gs.projection_vector = vector(current);
+                    self.update_f_dot_p();
+                },
+                // GETINFO
+                0x88 => {
+                    let selector = self.pop()?;
+                    let info = self.info(selector);
+                    self.push(info)?;
+                },
+                // IDEF
+                0x89 => {
+                    let defined = self.pop()?;
+                    let end = find_definition_end(code, pc)?;
+                    self.instruction_defs.insert(defined as u8, Rc::new(code[pc..end].to_vec()));
+                    pc = end + 1;
+                },
+                // ROLL
+                0x8A => {
+                    let len = self.stack.len();
+                    if len < 3 {
+                        return None;
+                    }
+                    let value = self.stack.remove(len - 3);
+                    self.push(value)?;
+                },
+                // MAX, MIN
+                0x8B | 0x8C => {
+                    let (b, a) = (self.pop()?, self.pop()?);
+                    self.push(if opcode == 0x8B { a.max(b) } else { a.min(b) })?;
+                },
+                // INSTCTRL, only allowed in `prep`
+                0x8E => {
+                    let (selector, value) = (self.pop()?, self.pop()?);
+                    if self.in_cvt_program && selector >= 1 && selector <= 3 {
+                        let flag = 1 << (selector - 1);
+                        self.gs.instruct_control &= !flag;
+                        if value != 0 {
+                            self.gs.instruct_control |= flag;
+                        }
+                    }
+                },
+                // MDRP
+                0xC0..=0xDF => self.move_direct_relative(opcode)?,
+                // MIRP
+                0xE0..=0xFF => self.move_indirect_relative(opcode)?,
+                _ => {
+                    let body = self.instruction_defs.get(&opcode)?.clone();
+                    self.execute(&body, depth + 1)?;
+                },
+            }
+        }
+        Some(())
+    }
+
+    fn info(&self, selector: i32) -> i32 {
+        let mut info = 0;
+        if selector & 1 != 0 {
+            info |= match self.version {
+                InterpreterVersion::V35 => 35,
+                InterpreterVersion::V40 => 40,
+            };
+        }
+        // Glyphs are never rotated or stretched
+        match self.version {
+            InterpreterVersion::V35 => {
+                // Grayscale rendering
+                if selector & 32 != 0 {
+                    info |= 1 << 12;
+                }
+            },
+            InterpreterVersion::V40 => {
+                // Subpixel hinting, positioning, symmetrical smoothing and
+                // grayscale ClearType
+                for &(asked, bit) in &[(64, 13), (1024, 17), (2048, 18), (4096, 19)] {
+                    if selector & asked != 0 {
+                        info |= 1 << bit;
+                    }
+                }
+            },
+        }
+        info
+    }
+
+    /// ISECT: put a point where two lines cross
+    fn intersect(&mut self) -> Option<()> {
+        let (b1, b0) = (self.pop_point()?, self.pop_point()?);
+        let (a1, a0) = (self.pop_point()?, self.pop_point()?);
+        let point = self.pop_point()?;
+        let (zp0, zp1, zp2) = (self.gs.zp0, self.gs.zp1, self.gs.zp2);
+        let (a0, a1) = (self.current(zp1, a0)?, self.current(zp1, a1)?);
+        let (b0, b1) = (self.current(zp0, b0)?, self.current(zp0, b1)?);
+
+        let a = a1.sub(a0);
+        let b = b1.sub(b0);
+        let discriminant = a.x as i64 * -b.y as i64 + a.y as i64 * b.x as i64;
+        let dot_product = a.x as i64 * b.x as i64 + a.y as i64 * b.y as i64;
+        let position = if 19 * discriminant.abs() > dot_product.abs() {
+            let d = b0.sub(a0);
+            let value = d.x as i64 * -b.y as i64 + d.y as i64 * b.x as i64;
+            Point26 {
+                x: a0.x + (value * a.x as i64 / discriminant) as i32,
+                y: a0.y + (value * a.y as i64 / discriminant) as i32,
+            }
+        } else {
+            // Parallel, so use the middle
+            Point26 {
+                x: (a0.x + a1.x + b0.x + b1.x) / 4,
+                y: (a0.y + a1.y + b0.y + b1.y) / 4,
+            }
+        };
+        let zone = &mut self.zones[zp2];
+        *zone.current.get_mut(point)? = position;
+        zone.touched[point] |= TOUCHED_X | TOUCHED_Y;
+        Some(())
+    }
+
+    /// IP: keep points' relative positions between rp1 and rp2
+    fn interpolate_point(&mut self) -> Option<()> {
+        let (zp0, zp1, zp2) = (self.gs.zp0, self.gs.zp1, self.gs.zp2);
+        let (rp1, rp2) = (self.gs.rp1, self.gs.rp2);
+        let original1 = self.original(zp0, rp1)?;
+        let current1 = self.current(zp0, rp1)?;
+        let original_range = self.dual_project(self.original(zp1, rp2)?.sub(original1));
+        let current_range = self.project(self.current(zp1, rp2)?.sub(current1));
+
+        for _ in 0..self.gs.loop_count {
+            let point = self.pop_point()?;
+            let original = self.dual_project(self.original(zp2, point)?.sub(original1));
+            let current = self.project(self.current(zp2, point)?.sub(current1));
+            let distance = if original == 0 {
+                0
+            } else if original_range == 0 {
+                original
+            } else {
+                mul_div(original, current_range, original_range)
+            };
+            self.move_point(zp2, point, distance - current)?;
+        }
+        self.gs.loop_count = 1;
+        Some(())
+    }
+
+    /// IUP: move untouched points of each contour like the touched points
+    /// around them
+    fn interpolate_untouched(&mut self, x_axis: bool) {
+        if self.backward_compatibility() {
+            if self.post_iup() {
+                return;
+            }
+            if x_axis {
+                self.iup_x_called = true;
+            } else {
+                self.iup_y_called = true;
+            }
+        }
+
+        let flag = if x_axis { TOUCHED_X } else { TOUCHED_Y };
+        let coord = |point: Point26| if x_axis { point.x } else { point.y };
+        let zone = &mut self.zones[GLYPH_ZONE];
+        let mut start = 0;
+        for end in zone.contour_ends.clone() {
+            if end >= zone.current.len() {
+                break;
+            }
+            let touched: Vec<usize> = (start..=end).filter(|&point| zone.touched[point] & flag != 0).collect();
+            for (idx, &first) in touched.iter().enumerate() {
+                // The untouched points after `first`, up to the next touched
+                // one (wrapping around the contour)
+                let next = touched[(idx + 1) % touched.len()];
+                let (original1, current1) = (coord(zone.original[first]), coord(zone.current[first]));
+                let (original2, current2) = (coord(zone.original[next]), coord(zone.current[next]));
+                let (low, high) = if original1 <= original2 {
+                    ((original1, current1), (original2, current2))
+                } else {
+                    ((original2, current2), (original1, current1))
+                };
+
+                let mut point = if first == end { start } else { first + 1 };
+                while point != next {
+                    let original = coord(zone.original[point]);
+                    let moved = if original <= low.0 {
+                        original + low.1 - low.0
+                    } else if original >= high.0 {
+                        original + high.1 - high.0
+                    } else {
+                        low.1 + mul_div(original - low.0, high.1 - low.1, high.0 - low.0)
+                    };
+                    if x_axis {
+                        zone.current[point].x = moved;
+                    } else {
+                        zone.current[point].y = moved;
+                    }
+                    point = if point == end { start } else { point + 1 };
+                }
+            }
+            start = end + 1;
+        }
+    }
+
+    /// The ppem a DELTA argument applies at, and how far it moves
+    fn delta_step(&self, argument: i32, range: i32) -> (i32, i32) {
+        let ppem = self.gs.delta_base + range + ((argument >> 4) & 15);
+        let mut magnitude = (argument & 15) - 8;
+        if magnitude >= 0 {
+            magnitude += 1;
+        }
+        (ppem, magnitude * (1 << (6 - self.gs.delta_shift)))
+    }
+
+    fn delta_points(&mut self, range: i32) -> Option<()> {
+        let count = self.pop()?;
+        let zp0 = self.gs.zp0;
+        for _ in 0..count {
+            let (point, argument) = (self.pop_point()?, self.pop()?);
+            let (ppem, distance) = self.delta_step(argument, range);
+            if ppem != self.ppem as i32 || point >= self.zones[zp0].current.len() {
+                continue;
+            }
+            if self.backward_compatibility() {
+                let touched_y = self.zones[zp0].touched[point] & TOUCHED_Y != 0;
+                let fv_y = self.gs.freedom_vector.y != 0;
+                if !self.post_iup() && ((self.is_composite && fv_y) || touched_y) {
+                    self.move_point(zp0, point, distance)?;
+                }
+            } else {
+                self.move_point(zp0, point, distance)?;
+            }
+        }
+        Some(())
+    }
+
+    fn delta_cvt(&mut self, range: i32) -> Option<()> {
+        let count = self.pop()?;
+        for _ in 0..count {
+            let (idx, argument) = (self.pop()?, self.pop()?);
+            let (ppem, distance) = self.delta_step(argument, range);
+            if ppem == self.ppem as i32 {
+                if let Some(value) = self.cvt.get_mut(idx as usize) {
+                    *value += distance;
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Apply the minimum distance flag of MDRP and MIRP
+    fn keep_minimum_distance(&self, distance: i32, original: i32) -> i32 {
+        let minimum = self.gs.minimum_distance;
+        if original >= 0 {
+            distance.max(minimum)
+        } else {
+            distance.min(-minimum)
+        }
+    }
+
+    /// MDRP: keep the original distance from rp0
+    fn move_direct_relative(&mut self, opcode: u8) -> Option<()> {
+        let point = self.pop_point()?;
+        let (zp0, zp1, rp0) = (self.gs.zp0, self.gs.zp1, self.gs.rp0);
+        let mut original = self.dual_project(self.original(zp1, point)?.sub(self.original(zp0, rp0)?));
+
+        let single_width = self.gs.single_width_value;
+        if self.gs.single_width_cut_in > 0 && (original - single_width).abs() < self.gs.single_width_cut_in {
+            original = if original >= 0 { single_width } else { -single_width };
+        }
+        let mut distance = if opcode & 0x04 != 0 { self.round(original) } else { original };
+        if opcode & 0x08 != 0 {
+            distance = self.keep_minimum_distance(distance, original);
+        }
+
+        let current = self.project(self.current(zp1, point)?.sub(self.current(zp0, rp0)?));
+        self.move_point(zp1, point, distance - current)?;
+        self.gs.rp1 = rp0;
+        self.gs.rp2 = point;
+        if opcode & 0x10 != 0 {
+            self.gs.rp0 = point;
+        }
+        Some(())
+    }
+
+    /// MIRP: move to a control value distance from rp0
+    fn move_indirect_relative(&mut self, opcode: u8) -> Option<()> {
+        let cvt = self.pop()?;
+        let point = self.pop_point()?;
+        let (zp0, zp1, rp0) = (self.gs.zp0, self.gs.zp1, self.gs.rp0);
+        let mut cvt_distance = self.cvt_value(cvt);
+
+        let single_width = self.gs.single_width_value;
+        if (cvt_distance - single_width).abs() < self.gs.single_width_cut_in {
+            cvt_distance = if cvt_distance >= 0 { single_width } else { -single_width };
+        }
+        if zp1 == TWILIGHT_ZONE {
+            let origin = self.original(zp0, rp0)?;
+            let fv = self.gs.freedom_vector;
+            let position = Point26 {
+                x: origin.x + mul14(cvt_distance, fv.x),
+                y: origin.y + mul14(cvt_distance, fv.y),
+            };
+            *self.zones[zp1].original.get_mut(point)? = position;
+            self.zones[zp1].current[point] = position;
+        }
+
+        let original = self.dual_project(self.original(zp1, point)?.sub(self.original(zp0, rp0)?));
+        let current = self.project(self.current(zp1, point)?.sub(self.current(zp0, rp0)?));
+        if self.gs.auto_flip && (original ^ cvt_distance) < 0 {
+            cvt_distance = -cvt_distance;
+        }
+        let mut distance = if opcode & 0x04 != 0 {
+            if zp0 == zp1 && (cvt_distance - original).abs() > self.gs.control_value_cut_in {
+                cvt_distance = original;
+            }
+            self.round(cvt_distance)
+        } else {
+            cvt_distance
+        };
+        if opcode & 0x08 != 0 {
+            distance = self.keep_minimum_distance(distance, original);
+        }
+
+        self.move_point(zp1, point, distance - current)?;
+        self.gs.rp1 = rp0;
+        if opcode & 0x10 != 0 {
+            self.gs.rp0 = point;
+        }
+        self.gs.rp2 = point;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Machine {
+        // 1 unit is 1/64 of a pixel
+        Machine::new(InterpreterVersion::V35, 16, 1024, &[100, 64], 4, 4)
+    }
+
+    /// A square from (0, 0) to (40, 40) (in 26.6), plus a point in the
+    /// middle of the bottom edge
+    fn square() -> Zone {
+        let points = [(0, 0), (20, 0), (40, 0), (40, 40), (0, 40)]
+            .iter()
+            .map(|&(x, y)| Point26 { x, y })
+            .collect();
+        Zone::new(points, vec![true; 5], vec![4])
+    }
+
+    #[test]
+    fn arithmetic_and_control_flow() {
+        let mut machine = machine();
+        // 2 3 ADD, 128 64 DIV, IF 7 ELSE 8 EIF, 1 JROT over a push
+        let code = [
+            0xB1, 2, 3, 0x60,
+            0xB1, 128, 64, 0x62,
+            0xB0, 0, 0x58, 0xB0, 7, 0x1B, 0xB0, 8, 0x59,
+            0xB1, 3, 1, 0x78, 0xB0, 9,
+            // Function 0 doubles the top
+            0xB0, 0, 0x2C, 0x20, 0x60, 0x2D,
+            0xB1, 5, 0, 0x2B,
+        ];
+        machine.run_font_program(&code).unwrap();
+        assert_eq!(machine.stack, vec![5, 128, 8, 10]);
+    }
+
+    #[test]
+    fn loopcall_counts_calls() {
+        let mut machine = machine();
+        // Function 0 adds 1. 0 3 0 LOOPCALL
+        let code = [0xB0, 0, 0x2C, 0xB0, 1, 0x60, 0x2D, 0xB2, 0, 3, 0, 0x2A];
+        machine.run_font_program(&code).unwrap();
+        assert_eq!(machine.stack, vec![3]);
+
+        // Function 1 is empty. 0x7FFF 0x7FFF MUL is about 16 million calls.
+        let code = [0xB0, 1, 0x2C, 0x2D, 0xB9, 0x7F, 0xFF, 0x7F, 0xFF, 0x63, 0xB0, 1, 0x2A];
+        assert_eq!(machine.run_font_program(&code), None);
+    }
+
+    #[test]
+    fn rounding_and_cvt() {
+        let mut machine = machine();
+        // The control values scale by 16 / 1024
+        assert_eq!(machine.cvt, vec![100 * 64 * 16 / 1024, 64]);
+        // 90 ROUND, RTHG 90 ROUND, RDTG 90 ROUND, 0xC8 SROUND 90 ROUND
+        let code = [
+            0xB0, 90, 0x68,
+            0x19, 0xB0, 90, 0x68,
+            0x7D, 0xB0, 90, 0x68,
+            0xB0, 0x48, 0x76, 0xB0, 90, 0x68,
+        ];
+        machine.run_font_program(&code).unwrap();
+        assert_eq!(machine.stack, vec![64, 96, 64, 64]);
+    }
+
+    #[test]
+    fn move_and_interpolate() {
+        let mut machine = machine();
+        // Round point 3 to the grid on the x axis, keep 4's distance from
+        // 0 rounded, then interpolate the rest. Then the same for 0 and 4
+        // on the y axis.
+        let code = [
+            0x01,
+            0xB0, 3, 0x2F,
+            0xB0, 0, 0x2E, 0xB0, 4, 0xC4,
+            0x31,
+            0x00,
+            0xB0, 0, 0x2F, 0xB0, 4, 0x2F,
+            0x30,
+        ];
+        let zone = machine.run_glyph_program(square(), &code, false).unwrap();
+        let points: Vec<(i32, i32)> = zone.current.iter().map(|point| (point.x, point.y)).collect();
+        assert_eq!(points, vec![(0, 0), (32, 0), (64, 0), (64, 64), (0, 64)]);
+        assert_eq!(zone.original[3], Point26 { x: 40, y: 40 });
+    }
+
+    #[test]
+    fn v40_ignores_horizontal_moves() {
+        let mut machine = Machine::new(InterpreterVersion::V40, 16, 1024, &[], 0, 0);
+        // Round point 3 on both axes, then GETINFO for the version
+        let code = [0x01, 0xB0, 3, 0x2F, 0x00, 0xB0, 3, 0x2F, 0xB0, 1, 0x88];
+        let zone = machine.run_glyph_program(square(), &code, false).unwrap();
+        assert_eq!(zone.current[3], Point26 { x: 40, y: 64 });
+        assert_eq!(machine.stack, vec![40]);
+    }
+
+    #[test]
+    fn scan_control_and_type() {
+        // ppem 16
+        let mut machine = machine();
+        assert!(machine.scan_control(0x1FF));
+        assert!(!machine.scan_control(0x100));
+        assert!(machine.scan_control(0x114));
+        assert!(!machine.scan_control(0x10A));
+        assert!(!machine.scan_control(0x1114));
+        machine.gs.scan_control = true;
+        assert!(machine.scan_control(0x814));
+        assert!(!machine.scan_control(0x80A));
+
+        // 0x114 SCANCTRL 4 SCANTYPE in `prep` carries on into glyphs
+        let prep = [0xB8, 0x01, 0x14, 0x85, 0xB0, 4, 0x8D];
+        machine.run_cvt_program(&prep).unwrap();
+        machine.run_glyph_program(square(), &[], false).unwrap();
+        assert_eq!(machine.dropout_mode(), DropoutMode::Smart);
+        // A glyph can pick its own rule, which doesn't last
+        machine.run_glyph_program(square(), &[0xB0, 1, 0x8D], false).unwrap();
+        assert_eq!(machine.dropout_mode(), DropoutMode::SimpleNoStubs);
+        machine.run_glyph_program(square(), &[0xB0, 0, 0x85], false).unwrap();
+        assert_eq!(machine.dropout_mode(), DropoutMode::Off);
+        machine.run_glyph_program(square(), &[], false).unwrap();
+        assert_eq!(machine.dropout_mode(), DropoutMode::Smart);
+    }
+
+    #[test]
+    fn instruction_lengths() {
+        let code = [0x40, 2, 1, 2, 0x41, 1, 0, 5, 0xB2, 1, 2, 3, 0xB9, 0, 1, 0, 2, 0x20];
+        let mut pc = 0;
+        let mut lengths = Vec::new();
+        while pc < code.len() {
+            let len = instruction_length(&code, pc).unwrap();
+            lengths.push(len);
+            pc += len;
+        }
+        assert_eq!(lengths, vec![4, 4, 4, 5, 1]);
+        assert_eq!(instruction_length(&[0x40, 5, 1], 0), None);
+    }
+}
@@ -0,0 +1,399 @@
+//! Grid-fitting outlines by running their TrueType instructions
+
+pub mod auto;
+pub mod disasm;
+mod interpreter;
+
+use font::{vary_coordinate, vary_offset, Font, GetTable};
+use image::GrayImage;
+use math::{Affine, Point};
+use render::{CoverageRaster, DrawCommand, DropoutMode, FillInRaster, FillRule, FlattenedDrawCommands, Raster};
+use tables::glyf::{Description, Glyph};
+use self::interpreter::{Machine, Point26, Zone};
+
+/// How deep composite glyphs can nest
+const MAX_COMPONENT_DEPTH: usize = 16;
+
+/// Which of FreeType's TrueType interpreters to behave like
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpreterVersion {
+    /// Instructions move points in both directions, as on old Windows
+    V35,
+    /// Subpixel hinting. Only vertical moves count, unless the font says it
+    /// was made for ClearType.
+    V40,
+}
+
+/// Runs a font's instructions at one size. The font and control value
+/// programs run once when it is made.
+pub struct Hinter<'a: 'f, 'f> {
+    font: &'f Font<'a>,
+    version: InterpreterVersion,
+    machine: Machine,
+}
+
+impl<'a, 'f> Hinter<'a, 'f> {
+    /// `None` if the font can't be hinted, or its `fpgm` or `prep` fails
+    pub fn new(font: &'f Font<'a>, ppem: u16, version: InterpreterVersion) -> Option<Hinter<'a, 'f>> {
+        use tables::cvt::Cvt;
+        use tables::fpgm::FontProgram;
+        use tables::head::Head;
+        use tables::maxp::MaxP;
+        use tables::prep::ControlValueProgram;
+
+        let head: Head = font.get_table()?;
+        let maxp: MaxP = font.get_table()?;
+        let maxp = maxp.version_1_ext()?;
+        let cvt: Option<Cvt> = font.get_table();
+        let cvt = cvt.map_or(Vec::new(), |cvt| cvt.values());
+
+        // Four more twilight points, like FreeType
+        let mut machine = Machine::new(version, ppem, head.units_per_em, &cvt,
+                                       maxp.max_storage as usize, maxp.max_twilight_points as usize + 4);
+        let fpgm: Option<FontProgram> = font.get_table();
+        if let Some(fpgm) = fpgm {
+            machine.run_font_program(fpgm.instructions())?;
+        }
+        let prep: Option<ControlValueProgram> = font.get_table();
+        if let Some(prep) = prep {
+            machine.run_cvt_program(prep.instructions())?;
+        }
+
+        Some(Hinter { font, version, machine })
+    }
+
+    /// The grid-fitted outline of the glyph. If its instructions fail, the
+    /// points are only scaled.
+    pub fn hint_glyph(&mut self, glyph_id: u32) -> Option<HintedGlyph> {
+        let zone = self.load_glyph(glyph_id, 0)?;
+
+        // Put the horizontal origin at 0
+        let len = zone.current.len();
+        let origin = zone.current[len - 4].x;
+        let to_point = |point: Point26| Point {
+            x: (point.x - origin) as f32 / 64.,
+            y: point.y as f32 / 64.,
+        };
+        let points = zone.current[..len - 4].iter().cloned().map(&to_point).collect();
+        let phantom = &zone.current[len - 4..];
+        Some(HintedGlyph {
+            points,
+            on_curve: zone.on_curve[..len - 4].to_vec(),
+            contour_ends: zone.contour_ends,
+            phantom_points: [to_point(phantom[0]), to_point(phantom[1]), to_point(phantom[2]), to_point(phantom[3])],
+            dropout_mode: self.machine.dropout_mode(),
+        })
+    }
+
+    /// The glyph's hinted points, followed by its four phantom points
+    fn load_glyph(&mut self, glyph_id: u32, depth: usize) -> Option<Zone> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return None;
+        }
+        let glyph = self.font.get_glyph_for_id(glyph_id)?;
+        let phantom = self.phantom_points(glyph_id, &glyph);
+
+        match glyph.desc {
+            Description::Simple(ref simple) => {
+                let deltas = glyph.deltas.as_ref().map(|deltas| &deltas[..]);
+                let mut points = Vec::new();
+                let mut on_curve = Vec::new();
+                for (idx, coord) in simple.coordinates().enumerate() {
+                    let coord = vary_coordinate(coord, deltas.and_then(|deltas| deltas.get(idx)));
+                    points.push(Point26 {
+                        x: self.machine.scale(coord.x as i32),
+                        y: self.machine.scale(coord.y as i32),
+                    });
+                    on_curve.push(coord.on_curve);
+                }
+                points.extend_from_slice(&phantom);
+                on_curve.extend_from_slice(&[true; 4]);
+                let contour_ends = simple.contour_end_points().map(|end| end as usize).collect();
+
+                let zone = Zone::new(points, on_curve, contour_ends);
+                Some(self.run_instructions(zone, simple.instructions(), false))
+            },
+            Description::Composite(ref composite) => {
+                let (flags, instructions) = composite.hinting_info();
+                let mut points = Vec::new();
+                let mut on_curve = Vec::new();
+                let mut contour_ends = Vec::new();
+                let mut metrics = phantom;
+
+                for (idx, (sub_idx, sub_affine)) in composite.coordinates().enumerate() {
+                    let sub_affine = vary_offset(sub_affine, glyph.deltas.as_ref().and_then(|deltas| deltas.get(idx)));
+                    let sub_zone = match self.load_glyph(sub_idx as u32, depth + 1) {
+                        Some(sub_zone) => sub_zone,
+                        None => continue,
+                    };
+                    let (round_offset, use_my_metrics) = flags.get(idx).cloned().unwrap_or((false, false));
+
+                    let mut dx = self.machine.scale(sub_affine.translation[0] as i32);
+                    let mut dy = self.machine.scale(sub_affine.translation[1] as i32);
+                    if round_offset {
+                        // v40 only grid-fits vertically
+                        if self.version == InterpreterVersion::V35 {
+                            dx = (dx + 32) & !63;
+                        }
+                        dy = (dy + 32) & !63;
+                    }
+
+                    let start = points.len();
+                    let len = sub_zone.current.len() - 4;
+                    let square = Affine { square: sub_affine.square, translation: [0., 0.] };
+                    for point in &sub_zone.current[..len] {
+                        let moved = square * Point { x: point.x as f32, y: point.y as f32 };
+                        points.push(Point26 { x: moved.x.round() as i32 + dx, y: moved.y.round() as i32 + dy });
+                    }
+                    on_curve.extend_from_slice(&sub_zone.on_curve[..len]);
+                    contour_ends.extend(sub_zone.contour_ends.iter().map(|end| end + start));
+                    if use_my_metrics {
+                        let sub_phantom = &sub_zone.current[len..];
+                        metrics = [sub_phantom[0], sub_phantom[1], sub_phantom[2], sub_phantom[3]];
+                    }
+                }
+                points.extend_from_slice(&metrics);
+                on_curve.extend_from_slice(&[true; 4]);
+
+                // The hinted components are the starting point for the
+                // composite's own instructions
+                let zone = Zone::new(points, on_curve, contour_ends);
+                Some(self.run_instructions(zone, instructions, true))
+            },
+            Description::Cubic(_) => None,
+        }
+    }
+
+    /// Scaled phantom points, with the advances on the grid
+    fn phantom_points(&self, glyph_id: u32, glyph: &Glyph<'a>) -> [Point26; 4] {
+        let mut phantom = self.font.phantom_points(glyph_id, glyph);
+        if let Some(ref deltas) = glyph.deltas {
+            if deltas.len() >= 4 {
+                for (point, delta) in phantom.iter_mut().zip(&deltas[deltas.len() - 4..]) {
+                    point.0 += delta.0;
+                    point.1 += delta.1;
+                }
+            }
+        }
+        let scale = |(x, y): (f32, f32)| Point26 {
+            x: self.machine.scale(x.round() as i32),
+            y: self.machine.scale(y.round() as i32),
+        };
+        let round = |value: i32| (value + 32) & !63;
+        let mut points = [scale(phantom[0]), scale(phantom[1]), scale(phantom[2]), scale(phantom[3])];
+        points[0].x = round(points[0].x);
+        points[1].x = round(points[1].x);
+        points[2].y = round(points[2].y);
+        points[3].y = round(points[3].y);
+        points
+    }
+
+    fn run_instructions(&mut self, zone: Zone, instructions: &[u8], is_composite: bool) -> Zone {
+        match self.machine.run_glyph_program(zone.clone(), instructions, is_composite) {
+            Some(hinted) => hinted,
+            None => zone,
+        }
+    }
+}
+
+/// A glyph outline fitted to the pixel grid. Positions are in pixels, with
+/// the horizontal origin at 0 and y going up.
+#[derive(Debug, Clone)]
+pub struct HintedGlyph {
+    pub points: Vec<Point>,
+    pub on_curve: Vec<bool>,
+    /// The index of the last point of each contour
+    pub contour_ends: Vec<usize>,
+    /// The horizontal origin and advance, then the vertical origin and
+    /// advance
+    pub phantom_points: [Point; 4],
+    /// What the instructions asked for with `SCANCTRL` and `SCANTYPE`
+    pub dropout_mode: DropoutMode,
+}
+
+impl HintedGlyph {
+    pub fn advance_width(&self) -> f32 {
+        self.phantom_points[1].x - self.phantom_points[0].x
+    }
+
+    /// The outline as lines and quadratic curves
+    pub fn draw_commands(&self) -> Vec<DrawCommand> {
+        let mut commands = Vec::new();
+        let mut start = 0;
+        for &end in &self.contour_ends {
+            if end >= self.points.len() || end < start {
+                break;
+            }
+            contour_commands(&self.points[start..=end], &self.on_curve[start..=end], &mut commands);
+            start = end + 1;
+        }
+        commands
+    }
+
+    /// Rasterize the outline into an image just big enough for it
+    pub fn render(&self) -> GrayImage {
+        render_commands(self.draw_commands(), CoverageRaster::new)
+    }
+
+    /// Rasterize the outline in black and white, with the dropout control
+    /// the instructions asked for
+    pub fn render_monochrome(&self) -> GrayImage {
+        let dropout_mode = self.dropout_mode;
+        render_commands(self.draw_commands(), |width, height| {
+            FillInRaster::with_dropout_mode(width, height, FillRule::NonZero, dropout_mode)
+        })
+    }
+}
+
+/// Rasterize an outline in pixels into an image just big enough for it
+fn render_commands<R: Raster, F: FnOnce(u32, u32) -> R>(commands: Vec<DrawCommand>, new_raster: F) -> GrayImage {
+    let lines: Vec<(Point, Point)> = FlattenedDrawCommands::from_commands(commands.into_iter()).collect();
+    if lines.is_empty() {
+        return GrayImage::new(0, 0);
+    }
+    let x_min = lines.iter().map(|line| line.0.x).fold(::std::f32::INFINITY, f32::min).floor();
+    let y_min = lines.iter().map(|line| line.0.y).fold(::std::f32::INFINITY, f32::min).floor();
+    let x_max = lines.iter().map(|line| line.0.x).fold(::std::f32::NEG_INFINITY, f32::max).ceil();
+    let y_max = lines.iter().map(|line| line.0.y).fold(::std::f32::NEG_INFINITY, f32::max).ceil();
+
+    let affine = Affine::translation(-x_min, -y_min);
+    let mut raster = new_raster((x_max - x_min) as u32, (y_max - y_min) as u32);
+    for (start, end) in lines {
+        raster.add_line(affine * start, affine * end);
+    }
+    raster.into_dynamic().to_luma()
+}
+
+/// Turn one contour of on- and off-curve points into draw commands
+fn contour_commands(points: &[Point], on_curve: &[bool], commands: &mut Vec<DrawCommand>) {
+    let len = points.len();
+    // Start on an on-curve point, or between two off-curve points
+    let (first, start, mut control) = match on_curve.iter().position(|&on| on) {
+        Some(idx) => (idx, points[idx], None),
+        None => (0, points[len - 1].lerp_to(points[0], 0.5), Some(points[0])),
+    };
+    let mut current = start;
+    for step in 1..len + if control.is_some() { 0 } else { 1 } {
+        let idx = (first + step) % len;
+        let point = points[idx];
+        match (on_curve[idx], control.take()) {
+            (true, Some(off)) => {
+                commands.push(DrawCommand::Curve(current, off, point));
+                current = point;
+            },
+            (true, None) => {
+                commands.push(DrawCommand::Line(current, point));
+                current = point;
+            },
+            (false, Some(off)) => {
+                let middle = off.lerp_to(point, 0.5);
+                commands.push(DrawCommand::Curve(current, off, middle));
+                current = middle;
+                control = Some(point);
+            },
+            (false, None) => control = Some(point),
+        }
+    }
+    // Close the contour
+    match control {
+        Some(off) => commands.push(DrawCommand::Curve(current, off, start)),
+        None => if current != start {
+            commands.push(DrawCommand::Line(current, start));
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::font_buf;
+
+    fn h_glyph(font: &Font, ppem: u16, version: InterpreterVersion) -> HintedGlyph {
+        let glyph_id = font.get_glyph_id('H').unwrap();
+        let mut hinter = Hinter::new(font, ppem, version).unwrap();
+        hinter.hint_glyph(glyph_id).unwrap()
+    }
+
+    #[test]
+    fn grid_fits_stems_and_heights() {
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let glyph = h_glyph(&font, 12, InterpreterVersion::V35);
+
+        let top = glyph.points.iter().map(|point| point.y).fold(::std::f32::NEG_INFINITY, f32::max);
+        assert_eq!(top, top.round());
+        assert_eq!(glyph.advance_width(), glyph.advance_width().round());
+        assert_eq!(glyph.phantom_points[0].x, 0.);
+        // The stems are on pixel edges
+        assert!(glyph.points.iter().all(|point| point.x == point.x.round()));
+
+        let image = glyph.render();
+        assert!(image.pixels().any(|pixel| pixel.data[0] > 0));
+    }
+
+    #[test]
+    fn subpixel_keeps_horizontal_positions() {
+        use tables::head::Head;
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let v35 = h_glyph(&font, 12, InterpreterVersion::V35);
+        let v40 = h_glyph(&font, 12, InterpreterVersion::V40);
+
+        // Horizontal distances are only scaled
+        let head: Head = font.get_table().unwrap();
+        let scale = 12. / head.units_per_em as f32;
+        let glyph = font.get_glyph('H').unwrap();
+        let coords: Vec<_> = match glyph.desc {
+            Description::Simple(ref simple) => simple.coordinates().collect(),
+            _ => panic!("Should be a simple glyph"),
+        };
+        for (point, coord) in v40.points.iter().zip(&coords) {
+            let expected = (coord.x - coords[0].x) as f32 * scale;
+            assert!((point.x - v40.points[0].x - expected).abs() < 1. / 32.);
+        }
+
+        // Heights are still fitted
+        let top = |glyph: &HintedGlyph| glyph.points.iter().map(|point| point.y).fold(::std::f32::NEG_INFINITY, f32::max);
+        assert_eq!(top(&v40), top(&v40).round());
+        assert_eq!(top(&v35), top(&v40));
+    }
+
+    #[test]
+    fn dropout_control_keeps_strokes() {
+        use test_utils::{load_font_buf, SERIF};
+
+        let buf = load_font_buf(SERIF);
+        let font = Font::from_buffer(&buf).unwrap();
+        let glyph_id = font.get_glyph_id('A').unwrap();
+        let mut hinter = Hinter::new(&font, 9, InterpreterVersion::V35).unwrap();
+        let mut glyph = hinter.hint_glyph(glyph_id).unwrap();
+        assert_eq!(glyph.dropout_mode, DropoutMode::SimpleNoStubs);
+
+        let rows_with_ink = |image: &GrayImage| (0..image.height())
+            .filter(|&y| (0..image.width()).any(|x| image.get_pixel(x, y).data[0] > 0))
+            .count() as u32;
+        let kept = glyph.render_monochrome();
+        assert_eq!(font.render_glyph_monochrome(glyph_id, 9).unwrap().into_raw(), kept.clone().into_raw());
+        glyph.dropout_mode = DropoutMode::Off;
+        let dropped = glyph.render_monochrome();
+        // The left diagonal breaks without it
+        assert_eq!(rows_with_ink(&kept), kept.height());
+        assert!(rows_with_ink(&dropped) < dropped.height());
+        // Only pixels are added
+        assert!(kept.pixels().zip(dropped.pixels()).all(|(kept, dropped)| kept.data[0] >= dropped.data[0]));
+    }
+
+    #[test]
+    fn contours_starting_off_curve() {
+        let points = [Point { x: 0., y: 0. }, Point { x: 2., y: 0. }, Point { x: 2., y: 2. }, Point { x: 0., y: 2. }];
+        let mut commands = Vec::new();
+        contour_commands(&points, &[false; 4], &mut commands);
+        assert_eq!(commands.len(), 4);
+        assert_eq!(commands[0], DrawCommand::Curve(Point { x: 0., y: 1. }, points[0], Point { x: 1., y: 0. }));
+        match commands[3] {
+            DrawCommand::Curve(_, _, end) => assert_eq!(end, Point { x: 0., y: 1. }),
+            _ => panic!("Should be a curve"),
+        }
+    }
+}
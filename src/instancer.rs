@@ -0,0 +1,475 @@
+//! Turning a variable font into a static font at one location in its design
+//! space, for software that can't handle variations.
+
+use byteorder::{BigEndian, ByteOrder};
+use font::{Font, GetTable};
+use tables::fvar::{Variation, VariationAxis};
+use tables::glyf::{Coordinate, Description, Glyph};
+use tables::hmtx::HMTX;
+use tables::layout::Tag;
+use tables::loca::Loca;
+use tables::maxp::MaxP;
+use tables::name::Name;
+
+/// Tables that only matter for variable fonts
+const VARIATION_TABLES: &[&[u8; 4]] = &[b"avar", b"cvar", b"fvar", b"gvar", b"HVAR", b"MVAR", b"VVAR", b"STAT"];
+
+/// `MVAR` value tags and where they live, as (tag, table, byte offset)
+const METRIC_FIELDS: &[(&[u8; 4], &[u8; 4], usize)] = &[
+    (b"hcrs", b"hhea", 18),
+    (b"hcrn", b"hhea", 20),
+    (b"hcof", b"hhea", 22),
+    (b"vcrs", b"vhea", 18),
+    (b"vcrn", b"vhea", 20),
+    (b"vcof", b"vhea", 22),
+    (b"sbxs", b"OS/2", 10),
+    (b"sbys", b"OS/2", 12),
+    (b"sbxo", b"OS/2", 14),
+    (b"sbyo", b"OS/2", 16),
+    (b"spxs", b"OS/2", 18),
+    (b"spys", b"OS/2", 20),
+    (b"spxo", b"OS/2", 22),
+    (b"spyo", b"OS/2", 24),
+    (b"strs", b"OS/2", 26),
+    (b"stro", b"OS/2", 28),
+    (b"hasc", b"OS/2", 68),
+    (b"hdsc", b"OS/2", 70),
+    (b"hlgp", b"OS/2", 72),
+    (b"hcla", b"OS/2", 74),
+    (b"hcld", b"OS/2", 76),
+    (b"xhgt", b"OS/2", 86),
+    (b"cpht", b"OS/2", 88),
+    (b"undo", b"post", 8),
+    (b"unds", b"post", 10),
+];
+
+// Byte offsets of the fields that get rewritten
+const HEAD_CHECK_SUM_ADJUSTMENT: usize = 8;
+const HEAD_X_MIN: usize = 36;
+const HEAD_INDEX_TO_LOC_FORMAT: usize = 50;
+const HHEA_ADVANCE_MAX: usize = 10;
+const HHEA_NUM_METRICS: usize = 34;
+const OS2_WEIGHT_CLASS: usize = 4;
+const OS2_WIDTH_CLASS: usize = 6;
+
+/// `OS/2.usWidthClass` values and the `wdth` percentages they stand for
+const WIDTH_CLASSES: &[(u16, f32)] = &[
+    (1, 50.), (2, 62.5), (3, 75.), (4, 87.5), (5, 100.), (6, 112.5), (7, 125.), (8, 150.), (9, 200.),
+];
+
+/// A static TrueType font of `font` at the user space location in
+/// `variations`. Axes that aren't mentioned stay at their default.
+///
+/// Outlines get their `gvar` deltas, metrics their `HVAR`, `VVAR` and
+/// `MVAR` deltas, the names and `OS/2` classes describe the instance, and
+/// the variation tables are dropped.
+///
+/// Returns `None` if the font isn't a variable TrueType font.
+pub fn instantiate<'a>(font: &Font<'a>, variations: &[Variation]) -> Option<Vec<u8>> {
+    let axes = font.variation_axes()?;
+    let instance = font.with_variations(variations)?;
+    let user_coords: Vec<f32> = axes.iter()
+        .map(|axis| {
+            let value = variations.iter()
+                .rev()
+                .find(|variation| variation.tag == axis.tag)
+                .map_or(axis.default_value, |variation| variation.value);
+            value.max(axis.min_value).min(axis.max_value)
+        })
+        .collect();
+
+    let maxp: MaxP = font.get_table()?;
+    let num_glyphs = maxp.num_glyphs as u32;
+    let (glyf, loca, bounds) = instance_glyphs(&instance, num_glyphs)?;
+    let (hmtx, advance_max) = instance_hmtx(&instance, num_glyphs)?;
+
+    let mut tables: Vec<([u8; 4], Vec<u8>)> = font.tables().into_iter()
+        .filter(|&(tag, _)| !VARIATION_TABLES.contains(&&tag))
+        .map(|(tag, data)| (tag, data.to_vec()))
+        .collect();
+    let vertical = instance_vmtx(&instance, num_glyphs);
+
+    for entry in &mut tables {
+        let tag = entry.0;
+        let data = &mut entry.1;
+        match &tag {
+            b"glyf" => *data = glyf.clone(),
+            b"loca" => *data = loca.clone(),
+            b"hmtx" => *data = hmtx.clone(),
+            b"head" => {
+                BigEndian::write_u32(&mut data[HEAD_CHECK_SUM_ADJUSTMENT..], 0);
+                for (idx, &bound) in bounds.iter().enumerate() {
+                    BigEndian::write_i16(&mut data[HEAD_X_MIN + idx * 2..], bound);
+                }
+                // Always write long offsets
+                BigEndian::write_i16(&mut data[HEAD_INDEX_TO_LOC_FORMAT..], 1);
+            },
+            b"hhea" => {
+                BigEndian::write_u16(&mut data[HHEA_ADVANCE_MAX..], advance_max);
+                BigEndian::write_u16(&mut data[HHEA_NUM_METRICS..], num_glyphs as u16);
+            },
+            b"vmtx" => if let Some((ref vmtx, _)) = vertical {
+                *data = vmtx.clone();
+            },
+            b"vhea" => if let Some((_, advance_max)) = vertical {
+                BigEndian::write_u16(&mut data[HHEA_ADVANCE_MAX..], advance_max);
+                BigEndian::write_u16(&mut data[HHEA_NUM_METRICS..], num_glyphs as u16);
+            },
+            b"OS/2" => {
+                for (axis, &value) in axes.iter().zip(&user_coords) {
+                    if axis.tag == Tag::new(b"wght") {
+                        let weight = value.round().max(1.).min(1000.) as u16;
+                        BigEndian::write_u16(&mut data[OS2_WEIGHT_CLASS..], weight);
+                    } else if axis.tag == Tag::new(b"wdth") {
+                        BigEndian::write_u16(&mut data[OS2_WIDTH_CLASS..], width_class(value));
+                    }
+                }
+            },
+            b"name" => {
+                let name: Name = font.get_table()?;
+                *data = name.encode_with(&instance_names(font, &name, &axes, &user_coords));
+            },
+            _ => (),
+        }
+
+        for &(mvar_tag, table, offset) in METRIC_FIELDS {
+            if table != &tag || offset + 2 > data.len() {
+                continue;
+            }
+            let delta = instance.metric_variation(Tag::new(mvar_tag)).round() as i16;
+            let value = BigEndian::read_i16(&data[offset..]);
+            BigEndian::write_i16(&mut data[offset..], value.wrapping_add(delta));
+        }
+    }
+
+    let mut font = write_font(&tables);
+    // The whole font sums to 0xB1B0AFBA
+    if let Some(head_offset) = table_offset(&font, b"head") {
+        let adjustment = 0xB1B0_AFBAu32.wrapping_sub(check_sum(&font));
+        BigEndian::write_u32(&mut font[head_offset + HEAD_CHECK_SUM_ADJUSTMENT..], adjustment);
+    }
+    Some(font)
+}
+
+/// `glyf` and long `loca` for the instance, and the bounding box of every
+/// glyph
+fn instance_glyphs<'a>(instance: &Font<'a>, num_glyphs: u32) -> Option<(Vec<u8>, Vec<u8>, [i16; 4])> {
+    let loca: Loca = instance.get_table()?;
+    let raw_glyf = instance.tables().into_iter()
+        .find(|&(tag, _)| &tag == b"glyf")
+        .map(|(_, data)| data)?;
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs as usize + 1);
+    let mut font_bounds: Option<[i16; 4]> = None;
+    for glyph_id in 0..num_glyphs {
+        offsets.push(glyf.len() as u32);
+        let (start, end) = match loca.range(glyph_id as usize) {
+            Some(range) => range,
+            None => continue,
+        };
+        let glyph = instance.get_glyph_for_id(glyph_id)?;
+        let bounds = instance.glyph_bounds(&glyph);
+        font_bounds = Some(match font_bounds {
+            Some(font_bounds) => [
+                font_bounds[0].min(bounds[0]),
+                font_bounds[1].min(bounds[1]),
+                font_bounds[2].max(bounds[2]),
+                font_bounds[3].max(bounds[3]),
+            ],
+            None => bounds,
+        });
+
+        match encode_glyph(&glyph, bounds) {
+            Some(encoded) => glyf.extend(encoded),
+            None => glyf.extend_from_slice(&raw_glyf[start as usize..end as usize]),
+        }
+        while glyf.len() % 4 != 0 {
+            glyf.push(0);
+        }
+    }
+    offsets.push(glyf.len() as u32);
+
+    let mut loca = vec![0; offsets.len() * 4];
+    for (idx, &offset) in offsets.iter().enumerate() {
+        BigEndian::write_u32(&mut loca[idx * 4..], offset);
+    }
+    Some((glyf, loca, font_bounds.unwrap_or([0; 4])))
+}
+
+/// The glyph with its deltas applied, `None` if it doesn't vary
+fn encode_glyph<'a>(glyph: &Glyph<'a>, bounds: [i16; 4]) -> Option<Vec<u8>> {
+    let deltas = glyph.deltas.as_ref()?;
+    let moved = |idx: usize, x: f32, y: f32| {
+        let delta = deltas.get(idx).cloned().unwrap_or((0., 0.));
+        ((x + delta.0).round() as i16, (y + delta.1).round() as i16)
+    };
+    let encoded = match glyph.desc {
+        Description::Simple(ref simple) => {
+            let coords: Vec<Coordinate> = simple.coordinates()
+                .enumerate()
+                .map(|(idx, coord)| {
+                    let (x, y) = moved(idx, coord.x as f32, coord.y as f32);
+                    Coordinate { on_curve: coord.on_curve, x, y }
+                })
+                .collect();
+            simple.encode(&coords)
+        },
+        Description::Composite(ref composite) => {
+            let offsets: Vec<(i16, i16)> = composite.coordinates()
+                .enumerate()
+                .map(|(idx, (_, affine))| moved(idx, affine.translation[0], affine.translation[1]))
+                .collect();
+            composite.encode(bounds, &offsets)
+        },
+        Description::Cubic(_) => return None,
+    };
+    Some(encoded)
+}
+
+/// `hmtx` with a long metric for every glyph, and the largest advance
+fn instance_hmtx<'a>(instance: &Font<'a>, num_glyphs: u32) -> Option<(Vec<u8>, u16)> {
+    let hmtx: HMTX = instance.get_table()?;
+    let mut buf = vec![0; num_glyphs as usize * 4];
+    let mut advance_max = 0;
+    for glyph_id in 0..num_glyphs {
+        let (advance, left_bearing) = match instance.placement_metrics_for_glyph_id(glyph_id, 0) {
+            Some(metrics) => (
+                metrics.horiz_advance.unwrap_or_else(|| hmtx.advance_width(glyph_id)).0,
+                metrics.left_bearing.0,
+            ),
+            // Glyphs without outlines, which can still move their phantom
+            // points
+            None => (
+                instance.advance_width(glyph_id).unwrap_or_else(|| hmtx.advance_width(glyph_id)).0,
+                hmtx.metrics_for_glyph(glyph_id).left_bearing.0,
+            ),
+        };
+        advance_max = advance_max.max(advance);
+        BigEndian::write_u16(&mut buf[glyph_id as usize * 4..], advance);
+        BigEndian::write_i16(&mut buf[glyph_id as usize * 4 + 2..], left_bearing);
+    }
+    Some((buf, advance_max))
+}
+
+/// `vmtx` with a long metric for every glyph, and the largest advance
+fn instance_vmtx<'a>(instance: &Font<'a>, num_glyphs: u32) -> Option<(Vec<u8>, u16)> {
+    use tables::vmtx::VMTX;
+
+    let vmtx: VMTX = instance.get_table()?;
+    let mut buf = vec![0; num_glyphs as usize * 4];
+    let mut advance_max = 0;
+    let mut last_advance = 0;
+    for glyph_id in 0..num_glyphs {
+        let default = vmtx.metrics_for_glyph(glyph_id);
+        let (advance, top_bearing) = match instance.placement_metrics_for_glyph_id(glyph_id, 0) {
+            Some(metrics) => (metrics.vert_advance.map(|advance| advance.0), metrics.top_bearing.0),
+            None => (default.advance_height.map(|advance| advance.0), default.top_bearing.0),
+        };
+        // Glyphs past the long metrics share the last advance
+        let advance = advance.unwrap_or(last_advance);
+        last_advance = advance;
+        advance_max = advance_max.max(advance);
+        BigEndian::write_u16(&mut buf[glyph_id as usize * 4..], advance);
+        BigEndian::write_i16(&mut buf[glyph_id as usize * 4 + 2..], top_bearing);
+    }
+    Some((buf, advance_max))
+}
+
+fn width_class(width: f32) -> u16 {
+    WIDTH_CLASSES.iter()
+        .min_by(|a, b| (a.1 - width).abs().partial_cmp(&(b.1 - width).abs()).unwrap())
+        .map_or(5, |&(class, _)| class)
+}
+
+/// The subfamily, full and PostScript names of the instance. Locations of
+/// named instances use their names, others are described by their axis
+/// values.
+fn instance_names<'a>(font: &Font<'a>, name: &Name<'a>, axes: &[VariationAxis], user_coords: &[f32]) -> Vec<(u16, String)> {
+    const FAMILY: u16 = 1;
+    const SUBFAMILY: u16 = 2;
+    const FULL_NAME: u16 = 4;
+    const POSTSCRIPT_NAME: u16 = 6;
+    const TYPOGRAPHIC_FAMILY: u16 = 16;
+    const TYPOGRAPHIC_SUBFAMILY: u16 = 17;
+
+    let family = name.string(TYPOGRAPHIC_FAMILY)
+        .or_else(|| name.string(FAMILY))
+        .unwrap_or_default();
+    let named = font.named_instances()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|instance| instance.coordinates == user_coords);
+
+    let subfamily = named.as_ref()
+        .and_then(|instance| instance.name.clone())
+        .unwrap_or_else(|| {
+            axes.iter()
+                .zip(user_coords)
+                .filter(|&(axis, &value)| value != axis.default_value)
+                .map(|(axis, value)| format!("{}{}", String::from_utf8_lossy(&axis.tag.0).trim(), value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+    let subfamily = if subfamily.is_empty() { "Regular".to_string() } else { subfamily };
+    let postscript_name = named.as_ref()
+        .and_then(|instance| instance.postscript_name_id)
+        .and_then(|name_id| name.string(name_id))
+        .unwrap_or_else(|| {
+            let postscript: String = format!("{}-{}", family, subfamily).chars()
+                .filter(|ch| ch.is_ascii_graphic() && !"[](){}<>/%".contains(*ch))
+                .collect();
+            postscript.chars().take(63).collect()
+        });
+
+    let mut names = vec![
+        (FAMILY, family.clone()),
+        (SUBFAMILY, subfamily.clone()),
+        (FULL_NAME, format!("{} {}", family, subfamily)),
+        (POSTSCRIPT_NAME, postscript_name),
+    ];
+    if name.string(TYPOGRAPHIC_FAMILY).is_some() {
+        names.push((TYPOGRAPHIC_FAMILY, family));
+        names.push((TYPOGRAPHIC_SUBFAMILY, subfamily));
+    }
+    names
+}
+
+/// Sum of the data as big endian `u32`s, zero padded
+fn check_sum(data: &[u8]) -> u32 {
+    data.chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            BigEndian::read_u32(&word)
+        })
+        .fold(0, u32::wrapping_add)
+}
+
+fn table_offset(font: &[u8], tag: &[u8; 4]) -> Option<usize> {
+    let num_tables = BigEndian::read_u16(&font[4..]) as usize;
+    (0..num_tables)
+        .map(|idx| &font[12 + idx * 16..])
+        .find(|record| &record[..4] == tag)
+        .map(|record| BigEndian::read_u32(&record[8..]) as usize)
+}
+
+/// Serialize a TrueType font, with the table directory sorted by tag and
+/// each table's checksum filled in. `head.checkSumAdjustment` is left as is.
+pub(crate) fn write_font(tables: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut tables: Vec<&([u8; 4], Vec<u8>)> = tables.iter().collect();
+    tables.sort_by_key(|&&(tag, _)| tag);
+
+    let num_tables = tables.len() as u16;
+    let entry_selector = (0..16).rev().find(|&power| 1 << power <= num_tables).unwrap_or(0);
+    let search_range = (1u16 << entry_selector) * 16;
+
+    let mut font = vec![0; 12 + tables.len() * 16];
+    // PostScript outlines are flagged with 'OTTO'
+    let has_cff = tables.iter().any(|&&(tag, _)| &tag == b"CFF " || &tag == b"CFF2");
+    BigEndian::write_u32(&mut font, if has_cff { 0x4F54_544F } else { 0x0001_0000 });
+    BigEndian::write_u16(&mut font[4..], num_tables);
+    BigEndian::write_u16(&mut font[6..], search_range);
+    BigEndian::write_u16(&mut font[8..], entry_selector);
+    BigEndian::write_u16(&mut font[10..], num_tables * 16 - search_range);
+
+    for (idx, &&(tag, ref data)) in tables.iter().enumerate() {
+        let offset = font.len();
+        let record = 12 + idx * 16;
+        font[record..record + 4].copy_from_slice(&tag);
+        BigEndian::write_u32(&mut font[record + 4..], check_sum(data));
+        BigEndian::write_u32(&mut font[record + 8..], offset as u32);
+        BigEndian::write_u32(&mut font[record + 12..], data.len() as u32);
+        font.extend_from_slice(data);
+        while font.len() % 4 != 0 {
+            font.push(0);
+        }
+    }
+    font
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tables::fvar::tests::build_fvar;
+    use tables::gvar::tests::build_gvar;
+    use tables::mvar::tests::build_mvar;
+    use test_utils::{font_buf, with_tables};
+
+    #[test]
+    fn static_instance() {
+        use tables::glyf::Description;
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let maxp: MaxP = font.get_table().unwrap();
+        let glyph_id = font.get_glyph_id('I').unwrap();
+        let coords = |font: &Font| match font.get_glyph_for_id(glyph_id).unwrap().desc {
+            Description::Simple(glyph) => glyph.coordinates().collect::<Vec<_>>(),
+            _ => panic!("Should be simple"),
+        };
+        let default_coords = coords(&font);
+        let default_advance = font.advance_width(glyph_id).unwrap().0;
+        let default_ascent = font.text_render_metrics().unwrap().ascent.0;
+
+        let mut deltas = vec![(20, -10); default_coords.len()];
+        deltas.extend_from_slice(&[(0, 0), (40, 0), (0, 0), (0, 0)]);
+        let gvar = build_gvar(maxp.num_glyphs, glyph_id as u16, 16384, None, &deltas);
+        let buf = with_tables(&buf, &[
+            (b"fvar", build_fvar()),
+            (b"gvar", gvar),
+            (b"MVAR", build_mvar((100, 0), (0, 0))),
+        ]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let static_buf = instantiate(&font, &[Variation::new(b"wght", 650.)]).unwrap();
+        assert_eq!(check_sum(&static_buf), 0xB1B0_AFBA);
+        let instance = Font::from_buffer(&static_buf).unwrap();
+        assert!(instance.variation_axes().is_none());
+
+        let expected: Vec<Coordinate> = default_coords.iter()
+            .map(|coord| Coordinate { on_curve: coord.on_curve, x: coord.x + 10, y: coord.y - 5 })
+            .collect();
+        assert_eq!(coords(&instance), expected);
+        assert_eq!(instance.advance_width(glyph_id).unwrap().0, default_advance + 20);
+        assert_eq!(instance.text_render_metrics().unwrap().ascent.0, default_ascent + 50);
+
+        // Other glyphs are unchanged
+        let other = font.get_glyph_id('H').unwrap();
+        let default_font = Font::from_buffer(&buf).unwrap();
+        assert_eq!(instance.advance_width(other), default_font.advance_width(other));
+
+        let name: Name = instance.get_table().unwrap();
+        assert_eq!(name.string(2), Some("wght650".to_string()));
+        let family = name.string(1).unwrap();
+        assert_eq!(name.string(4), Some(format!("{} wght650", family)));
+    }
+
+    #[test]
+    fn empty_glyph_advance() {
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let maxp: MaxP = font.get_table().unwrap();
+        let space = font.get_glyph_id(' ').unwrap();
+        let default_advance = font.advance_width(space).unwrap().0;
+
+        let gvar = build_gvar(maxp.num_glyphs, space as u16, 16384, None, &[(0, 0), (-60, 0), (0, 0), (0, 0)]);
+        let buf = with_tables(&buf, &[(b"fvar", build_fvar()), (b"gvar", gvar)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let static_buf = instantiate(&font, &[Variation::new(b"wght", 650.)]).unwrap();
+        let instance = Font::from_buffer(&static_buf).unwrap();
+        assert!(instance.get_glyph_for_id(space).is_none());
+        assert_eq!(instance.advance_width(space).unwrap().0, default_advance - 30);
+    }
+
+    #[test]
+    fn named_instance_names() {
+        let buf = with_tables(&font_buf(), &[(b"fvar", build_fvar())]);
+        let font = Font::from_buffer(&buf).unwrap();
+        let static_buf = instantiate(&font, &[Variation::new(b"wght", 400.)]).unwrap();
+        let instance = Font::from_buffer(&static_buf).unwrap();
+        let name: Name = instance.get_table().unwrap();
+        assert_eq!(name.string(2), Some("Regular".to_string()));
+    }
+}
@@ -54,6 +54,7 @@ extern crate itertools;
 
 extern crate image;
 extern crate imageproc;
+extern crate inflate;
 
 #[macro_use]
 extern crate bitflags;
@@ -69,11 +70,17 @@ pub mod font;
 pub mod tables;
 pub mod render;
 pub mod math;
+pub mod shape;
+pub mod instancer;
+pub mod hinting;
+mod paint;
+mod svg;
 
 #[cfg(test)]
 pub(crate) mod test_utils {
     pub const SANS_MONO: &'static str = "fonts/DejaVuSansMono.ttf";
     pub const ROBOTO: &'static str = "fonts/Roboto-Regular.ttf";
+    pub const SERIF: &'static str = "fonts/DejaVuSerif.ttf";
 
     pub fn font_buf() -> Vec<u8> {
         load_font_buf(SANS_MONO)
@@ -91,4 +98,30 @@ pub(crate) mod test_utils {
 
         data
     }
+
+    /// Append big endian values, for building tables in tests
+    pub fn push_u16(buf: &mut Vec<u8>, val: u16) {
+        buf.push((val >> 8) as u8);
+        buf.push(val as u8);
+    }
+
+    pub fn push_u32(buf: &mut Vec<u8>, val: u32) {
+        push_u16(buf, (val >> 16) as u16);
+        push_u16(buf, val as u16);
+    }
+
+    /// Copy of the font in `base` with `tables` added, replacing any with
+    /// the same tag
+    pub fn with_tables(base: &[u8], tables: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        use font::Font;
+        use instancer::write_font;
+
+        let font = Font::from_buffer(base).unwrap();
+        let mut entries: Vec<([u8; 4], Vec<u8>)> = font.tables().into_iter()
+            .filter(|&(tag, _)| tables.iter().all(|&(new_tag, _)| *new_tag != tag))
+            .map(|(tag, data)| (tag, data.to_vec()))
+            .collect();
+        entries.extend(tables.iter().map(|&(tag, ref data)| (*tag, data.clone())));
+        write_font(&entries)
+    }
 }
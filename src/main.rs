@@ -59,35 +59,62 @@ fn main() {
         let first_msg = "Hello,";
         let second_msg = "World!";
 
-        for ch in first_msg.chars() {
-            let glyph = font.get_glyph(ch).unwrap();
-
-            let ch_bitmap = font.render_glyph(glyph, size);
-            // let ch_bitmap = flip_vertical(&ch_bitmap);
-
-            let placement_metrics = font.placement_metrics(ch, size).expect("Couldn't get placement metrics");
-
-            rend_txt.add_glyph(ch_bitmap, placement_metrics);
-        }
+        add_shaped_str(&font, &mut rend_txt, first_msg, size);
 
         rend_txt.newline();
 
-        for ch in second_msg.chars() {
-            let glyph = font.get_glyph(ch).unwrap();
-
-            let ch_bitmap = font.render_glyph(glyph, size);
-            // let ch_bitmap = flip_vertical(&ch_bitmap);
-
-            let placement_metrics = font.placement_metrics(ch, size).expect("Couldn't get placement metrics");
-
-            rend_txt.add_glyph(ch_bitmap, placement_metrics);
-        }
+        add_shaped_str(&font, &mut rend_txt, second_msg, size);
 
         const img_file: &str = "RASTER_RESULT.bmp";
         rend_txt.img.save(img_file).unwrap();
     }
 }
 
+fn add_shaped_str<'a>(font: &Font<'a>, rend_txt: &mut toy_ttf::render::compositor::RenderedText,
+                      text: &str, size: usize) {
+    use image::GrayImage;
+    use toy_ttf::parse::primitives::FontUnit;
+    use toy_ttf::render::compositor::GlyphPlacementMetrics;
+    use toy_ttf::shape::shape;
+    use toy_ttf::tables::layout::{Tag, DEFAULT_LANGUAGE};
+
+    let shaped = shape(font, text, Tag::new(b"latn"), DEFAULT_LANGUAGE, &[]);
+    for shaped_glyph in shaped.glyphs {
+        // Use the shaped advance, which includes kerning
+        let horiz_advance = Some(FontUnit(shaped_glyph.x_advance.max(0) as u16));
+
+        let glyph = match font.get_glyph_for_id(shaped_glyph.glyph_id) {
+            Some(glyph) => glyph,
+            // Nothing to draw, as for a space, but the pen still moves
+            None => {
+                let placement_metrics = GlyphPlacementMetrics {
+                    shift: [FontUnit(0.), FontUnit(0.)],
+                    left_bearing: FontUnit(0),
+                    top_bearing: FontUnit(0),
+                    horiz_advance,
+                    vert_advance: None,
+                    bitmap_bearings: None,
+                };
+                rend_txt.add_glyph(GrayImage::new(0, 0), placement_metrics);
+                continue;
+            },
+        };
+
+        let mut placement_metrics = font.placement_metrics_for_glyph_id(shaped_glyph.glyph_id, size)
+            .expect("Couldn't get placement metrics");
+        placement_metrics.horiz_advance = horiz_advance;
+        // `shift` is minus where the glyph sits, so moving it by the shaped
+        // offsets takes them away
+        placement_metrics.shift[0] = FontUnit(placement_metrics.shift[0].0 - shaped_glyph.x_offset as f32);
+        placement_metrics.shift[1] = FontUnit(placement_metrics.shift[1].0 - shaped_glyph.y_offset as f32);
+
+        let offset = rend_txt.subpixel_offset(&placement_metrics);
+        let ch_bitmap = font.render_glyph(glyph, size, offset);
+
+        rend_txt.add_glyph(ch_bitmap, placement_metrics);
+    }
+}
+
 fn draw_str_renderedtext<'a>(font: &Font<'a>, text: &str) {
     use toy_ttf::render::compositor::*;
     use toy_ttf::tables::hhea::HHEA;
@@ -103,11 +130,12 @@ fn draw_str_renderedtext<'a>(font: &Font<'a>, text: &str) {
     for ch in text.chars() {
         let glyph = font.get_glyph(ch).unwrap();
 
-        let ch_bitmap = font.render_glyph(glyph, size);
-        // let ch_bitmap = flip_vertical(&ch_bitmap);
-
         let placement_metrics = font.placement_metrics(ch, size).expect("Couldn't get placement metrics");
 
+        let offset = rend_txt.subpixel_offset(&placement_metrics);
+        let ch_bitmap = font.render_glyph(glyph, size, offset);
+        // let ch_bitmap = flip_vertical(&ch_bitmap);
+
         rend_txt.add_glyph(ch_bitmap, placement_metrics);
     }
 
@@ -128,7 +156,7 @@ fn draw_str<'a>(font: &Font<'a>, text: &str) {
 
         // let ch_dyn = raster.into_dynamic();
         // let ch_bitmap = ch_dyn.to_luma();
-        let ch_bitmap = font.render_glyph(glyph, size);
+        let ch_bitmap = font.render_glyph(glyph, size, [0., 0.]);
 
         let ch_bitmap = flip_vertical(&ch_bitmap);
 
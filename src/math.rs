@@ -84,6 +84,33 @@ impl Affine {
             translation: [0., 0.],
         }
     }
+
+    /// Counter-clockwise rotation by `angle` radians
+    pub fn rotation(angle: f32) -> Affine {
+        let (sin, cos) = angle.sin_cos();
+        Affine {
+            square: [[cos, -sin], [sin, cos]],
+            translation: [0., 0.],
+        }
+    }
+
+    /// The transform that undoes this one. `None` if it flattens the plane.
+    pub fn inverse(&self) -> Option<Affine> {
+        let [[a, b], [c, d]] = self.square;
+        let det = a * d - b * c;
+        if det.abs() < ::std::f32::EPSILON {
+            return None;
+        }
+        let square = [[d / det, -b / det], [-c / det, a / det]];
+        let [tx, ty] = self.translation;
+        Some(Affine {
+            square,
+            translation: [
+                -(square[0][0] * tx + square[0][1] * ty),
+                -(square[1][0] * tx + square[1][1] * ty),
+            ],
+        })
+    }
 }
 
 impl<T: Into<Point>> Mul<T> for Affine {
@@ -239,6 +266,15 @@ impl LineSegment {
 mod tests {
     use super::*;
 
+    #[test]
+    fn affine_inverse() {
+        let affine = Affine::translation(3., -2.) * Affine::rotation(0.5) * Affine::scale(2., 4.);
+        let inverse = affine.inverse().unwrap();
+        let point = inverse * (affine * Point { x: 5., y: 7. });
+        assert!((point.x - 5.).abs() < 1e-4 && (point.y - 7.).abs() < 1e-4);
+        assert_eq!(Affine::scale(0., 1.).inverse(), None);
+    }
+
     #[test]
     fn line_segment_horiz_line_intersect() {
         let ls: LineSegment = ((-1., -1.).into(),(1., 1.).into()).into();
@@ -0,0 +1,681 @@
+//! Drawing `COLR` version 1 paint graphs. Every paint fills a whole canvas
+//! of premultiplied colors, which its parent clips, blends or stacks.
+
+use font::{Font, GetTable};
+use image::{Rgba, RgbaImage};
+use math::{Affine, Point};
+use tables::colr::{ColorLine, Colr, CompositeMode, Extend, Paint, FOREGROUND_PALETTE_INDEX};
+use tables::cpal::ColorRecord;
+
+/// Color glyphs can paint each other, so cap how deep the paints nest and
+/// how many get drawn in all
+const MAX_PAINT_DEPTH: usize = 128;
+const MAX_PAINTS: usize = 10_000;
+/// Every paint fills its own canvas, so cap how big that is
+const MAX_CANVAS_PIXELS: u64 = 1 << 22;
+
+/// Premultiplied RGBA, each from 0 to 1
+type Color = [f32; 4];
+
+const TRANSPARENT: Color = [0.; 4];
+
+pub(crate) struct Painter<'f, 'a: 'f> {
+    font: &'f Font<'a>,
+    colr: &'f Colr<'a>,
+    palette: Vec<ColorRecord>,
+    foreground: Rgba<u8>,
+    width: u32,
+    height: u32,
+    /// Font units to pixels, with y going down
+    device: Affine,
+}
+
+impl<'f, 'a: 'f> Painter<'f, 'a> {
+    /// Paint the glyph at `size` pixels per em. Row 0 is the top. `None` if
+    /// the glyph doesn't have a paint graph, or it's too big to paint.
+    pub(crate) fn render(font: &'f Font<'a>, colr: &'f Colr<'a>, glyph_id: u16, size: usize,
+                         palette: Vec<ColorRecord>, foreground: Rgba<u8>) -> Option<RgbaImage> {
+        use tables::head::Head;
+
+        let coords = font.normalized_coords();
+        let paint = colr.base_glyph_paint(glyph_id, coords)?;
+        let head: Head = font.get_table()?;
+        let scale = size as f32 / head.units_per_em as f32;
+        let bounds = colr.clip_box(glyph_id, coords).or_else(|| {
+            let mut remaining = MAX_PAINTS;
+            paint_bounds(font, colr, &paint, Affine::scale(1., 1.), 0, &mut remaining)
+        });
+        let bounds = match bounds {
+            Some(bounds) => bounds,
+            None => return Some(RgbaImage::new(0, 0)),
+        };
+
+        let x_min = (bounds[0] * scale).floor();
+        let y_max = (bounds[3] * scale).ceil();
+        let width = ((bounds[2] * scale).ceil() - x_min).max(0.) as u32;
+        let height = (y_max - (bounds[1] * scale).floor()).max(0.) as u32;
+        if width as u64 * height as u64 > MAX_CANVAS_PIXELS {
+            return None;
+        }
+        let painter = Painter {
+            font,
+            colr,
+            palette,
+            foreground,
+            width,
+            height,
+            device: Affine {
+                square: [[scale, 0.], [0., -scale]],
+                translation: [-x_min, y_max],
+            },
+        };
+        let mut remaining = MAX_PAINTS;
+        let canvas = painter.paint(&paint, Affine::scale(1., 1.), 0, &mut remaining);
+        Some(RgbaImage::from_fn(width, height, |x, y| to_rgba(canvas[(y * width + x) as usize])))
+    }
+
+    fn blank(&self) -> Vec<Color> {
+        vec![TRANSPARENT; (self.width * self.height) as usize]
+    }
+
+    /// Paint into a new canvas. `transform` takes the paint's coordinates to
+    /// the base glyph's. Paints past the limits are left blank.
+    fn paint(&self, paint: &Paint, transform: Affine, depth: usize, remaining: &mut usize) -> Vec<Color> {
+        if depth > MAX_PAINT_DEPTH || *remaining == 0 {
+            return self.blank();
+        }
+        *remaining -= 1;
+
+        match *paint {
+            Paint::ColrLayers(ref layers) => {
+                let mut canvas = self.blank();
+                for layer in layers {
+                    let layer = self.paint(layer, transform, depth + 1, remaining);
+                    for (dest, source) in canvas.iter_mut().zip(layer) {
+                        *dest = composite(source, *dest, CompositeMode::SourceOver);
+                    }
+                }
+                canvas
+            },
+            Paint::Solid { palette_index, alpha } => {
+                vec![self.color(palette_index, alpha); (self.width * self.height) as usize]
+            },
+            Paint::LinearGradient { ref color_line, p0, p1, p2 } => {
+                self.gradient(transform, color_line, |point| linear_offset(p0, p1, p2, point))
+            },
+            Paint::RadialGradient { ref color_line, c0, r0, c1, r1 } => {
+                self.gradient(transform, color_line, |point| radial_offset(c0, r0, c1, r1, point))
+            },
+            Paint::SweepGradient { ref color_line, center, start_angle, end_angle } => {
+                self.gradient(transform, color_line, |point| sweep_offset(center, start_angle, end_angle, point))
+            },
+            Paint::Glyph { glyph_id, ref paint } => {
+                let coverage = self.font.glyph_coverage(glyph_id as u32, self.device * transform, self.width, self.height);
+                let mut canvas = self.paint(paint, transform, depth + 1, remaining);
+                for (color, coverage) in canvas.iter_mut().zip(coverage) {
+                    for channel in color.iter_mut() {
+                        *channel *= coverage;
+                    }
+                }
+                canvas
+            },
+            Paint::ColrGlyph(glyph_id) => {
+                let coords = self.font.normalized_coords();
+                let paint = match self.colr.base_glyph_paint(glyph_id, coords) {
+                    Some(paint) => paint,
+                    None => return self.blank(),
+                };
+                let mut canvas = self.paint(&paint, transform, depth + 1, remaining);
+                if let Some(clip) = self.colr.clip_box(glyph_id, coords) {
+                    self.clip_to_box(&mut canvas, transform, clip);
+                }
+                canvas
+            },
+            Paint::Transform { affine, ref paint } => self.paint(paint, transform * affine, depth + 1, remaining),
+            Paint::Composite { ref source, mode, ref backdrop } => {
+                let mut canvas = self.paint(backdrop, transform, depth + 1, remaining);
+                let source = self.paint(source, transform, depth + 1, remaining);
+                for (dest, source) in canvas.iter_mut().zip(source) {
+                    *dest = composite(source, *dest, mode);
+                }
+                canvas
+            },
+        }
+    }
+
+    /// A palette color, or the foreground, with its alpha scaled by `alpha`
+    fn color(&self, palette_index: u16, alpha: f32) -> Color {
+        let rgba = if palette_index == FOREGROUND_PALETTE_INDEX {
+            self.foreground
+        } else {
+            match self.palette.get(palette_index as usize) {
+                Some(color) => color.to_rgba(),
+                None => return TRANSPARENT,
+            }
+        };
+        let alpha = (rgba.data[3] as f32 / 255. * alpha).max(0.).min(1.);
+        [
+            rgba.data[0] as f32 / 255. * alpha,
+            rgba.data[1] as f32 / 255. * alpha,
+            rgba.data[2] as f32 / 255. * alpha,
+            alpha,
+        ]
+    }
+
+    /// Fill the canvas with the color line, sampled at each pixel's center
+    /// at the offset `offset_at` gives for that point in paint space
+    fn gradient<F: Fn(Point) -> Option<f32>>(&self, transform: Affine, color_line: &ColorLine, offset_at: F) -> Vec<Color> {
+        let inverse = match (self.device * transform).inverse() {
+            Some(inverse) => inverse,
+            None => return self.blank(),
+        };
+        let mut canvas = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let point = inverse * Point { x: x as f32 + 0.5, y: y as f32 + 0.5 };
+                canvas.push(offset_at(point).map_or(TRANSPARENT, |offset| self.color_at(color_line, offset)));
+            }
+        }
+        canvas
+    }
+
+    fn color_at(&self, color_line: &ColorLine, offset: f32) -> Color {
+        let stops = &color_line.stops;
+        let (first, last) = match (stops.first(), stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return TRANSPARENT,
+        };
+
+        // Repeating and reflecting happen over the span of the stops
+        let span = last.offset - first.offset;
+        let offset = if span > 0. {
+            let t = (offset - first.offset) / span;
+            let t = match color_line.extend {
+                Extend::Pad => t,
+                Extend::Repeat => t - t.floor(),
+                Extend::Reflect => {
+                    let t = t - (t / 2.).floor() * 2.;
+                    if t > 1. { 2. - t } else { t }
+                },
+            };
+            first.offset + t * span
+        } else {
+            offset
+        };
+
+        if offset <= first.offset {
+            return self.color(first.palette_index, first.alpha);
+        }
+        for pair in stops.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            if offset <= end.offset {
+                let amount = if end.offset > start.offset {
+                    (offset - start.offset) / (end.offset - start.offset)
+                } else {
+                    1.
+                };
+                let (from, to) = (self.color(start.palette_index, start.alpha), self.color(end.palette_index, end.alpha));
+                let mut color = TRANSPARENT;
+                for idx in 0..4 {
+                    color[idx] = from[idx] + (to[idx] - from[idx]) * amount;
+                }
+                return color;
+            }
+        }
+        self.color(last.palette_index, last.alpha)
+    }
+
+    /// Clear everything outside of `clip`, a box in the coordinates
+    /// `transform` maps from
+    fn clip_to_box(&self, canvas: &mut [Color], transform: Affine, clip: [f32; 4]) {
+        let inverse = match (self.device * transform).inverse() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let point = inverse * Point { x: x as f32 + 0.5, y: y as f32 + 0.5 };
+                if point.x < clip[0] || point.y < clip[1] || point.x > clip[2] || point.y > clip[3] {
+                    canvas[(y * self.width + x) as usize] = TRANSPARENT;
+                }
+            }
+        }
+    }
+}
+
+/// The box the paint draws in, in the coordinates `transform` maps to.
+/// `None` if it isn't bounded by any glyphs.
+fn paint_bounds(font: &Font, colr: &Colr, paint: &Paint, transform: Affine, depth: usize,
+                remaining: &mut usize) -> Option<[f32; 4]> {
+    if depth > MAX_PAINT_DEPTH || *remaining == 0 {
+        return None;
+    }
+    *remaining -= 1;
+
+    let union = |a: Option<[f32; 4]>, b: Option<[f32; 4]>| match (a, b) {
+        (Some(a), Some(b)) => Some([a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])]),
+        (a, None) => a,
+        (None, b) => b,
+    };
+    match *paint {
+        Paint::ColrLayers(ref layers) => layers.iter()
+            .map(|layer| paint_bounds(font, colr, layer, transform, depth + 1, remaining))
+            .fold(None, union),
+        Paint::Glyph { glyph_id, .. } => {
+            let glyph = font.get_glyph_for_id(glyph_id as u32)?;
+            let bounds = font.glyph_bounds(&glyph);
+            let bounds = [bounds[0] as f32, bounds[1] as f32, bounds[2] as f32, bounds[3] as f32];
+            Some(transform_box(transform, bounds))
+        },
+        Paint::ColrGlyph(glyph_id) => {
+            let coords = font.normalized_coords();
+            match colr.clip_box(glyph_id, coords) {
+                Some(clip) => Some(transform_box(transform, clip)),
+                None => paint_bounds(font, colr, &colr.base_glyph_paint(glyph_id, coords)?, transform, depth + 1,
+                                     remaining),
+            }
+        },
+        Paint::Transform { affine, ref paint } => paint_bounds(font, colr, paint, transform * affine, depth + 1,
+                                                                remaining),
+        Paint::Composite { ref source, ref backdrop, .. } => {
+            let source = paint_bounds(font, colr, source, transform, depth + 1, remaining);
+            union(source, paint_bounds(font, colr, backdrop, transform, depth + 1, remaining))
+        },
+        _ => None,
+    }
+}
+
+/// The bounding box of `bounds` after transforming it
+fn transform_box(transform: Affine, bounds: [f32; 4]) -> [f32; 4] {
+    let corners = [(bounds[0], bounds[1]), (bounds[0], bounds[3]), (bounds[2], bounds[1]), (bounds[2], bounds[3])];
+    let corners: Vec<Point> = corners.iter().map(|&corner| transform * corner).collect();
+    [
+        corners.iter().map(|point| point.x).fold(::std::f32::INFINITY, f32::min),
+        corners.iter().map(|point| point.y).fold(::std::f32::INFINITY, f32::min),
+        corners.iter().map(|point| point.x).fold(::std::f32::NEG_INFINITY, f32::max),
+        corners.iter().map(|point| point.y).fold(::std::f32::NEG_INFINITY, f32::max),
+    ]
+}
+
+fn linear_offset(p0: Point, p1: Point, p2: Point, point: Point) -> Option<f32> {
+    // The gradient runs along p0 to p1, projected onto the normal of p0 to
+    // p2 so that the color bands run parallel to p0 to p2
+    let normal = (p2.y - p0.y, -(p2.x - p0.x));
+    let (dx, dy) = (p1.x - p0.x, p1.y - p0.y);
+    let normal_length = normal.0 * normal.0 + normal.1 * normal.1;
+    let (dx, dy) = if normal_length > 0. {
+        let projection = (dx * normal.0 + dy * normal.1) / normal_length;
+        (normal.0 * projection, normal.1 * projection)
+    } else {
+        (dx, dy)
+    };
+    let length = dx * dx + dy * dy;
+    if length == 0. {
+        return None;
+    }
+    Some(((point.x - p0.x) * dx + (point.y - p0.y) * dy) / length)
+}
+
+/// The biggest `t` where `point` is on the circle interpolated between the
+/// start and end circles, with a radius that isn't negative
+fn radial_offset(c0: Point, r0: f32, c1: Point, r1: f32, point: Point) -> Option<f32> {
+    let (cdx, cdy) = (c1.x - c0.x, c1.y - c0.y);
+    let (pdx, pdy) = (point.x - c0.x, point.y - c0.y);
+    let dr = r1 - r0;
+    // |point - c(t)| = r(t), as a t^2 - 2 b t + c = 0
+    let a = cdx * cdx + cdy * cdy - dr * dr;
+    let b = pdx * cdx + pdy * cdy + r0 * dr;
+    let c = pdx * pdx + pdy * pdy - r0 * r0;
+    let radius_ok = |t: f32| r0 + t * dr >= 0.;
+
+    if a.abs() < 1e-6 {
+        if b == 0. {
+            return None;
+        }
+        let t = c / (2. * b);
+        return if radius_ok(t) { Some(t) } else { None };
+    }
+    let discriminant = b * b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (t1, t2) = ((b + root) / a, (b - root) / a);
+    let (larger, smaller) = if t1 > t2 { (t1, t2) } else { (t2, t1) };
+    if radius_ok(larger) {
+        Some(larger)
+    } else if radius_ok(smaller) {
+        Some(smaller)
+    } else {
+        None
+    }
+}
+
+fn sweep_offset(center: Point, start_angle: f32, end_angle: f32, point: Point) -> Option<f32> {
+    if start_angle == end_angle {
+        return None;
+    }
+    let angle = (point.y - center.y).atan2(point.x - center.x).to_degrees();
+    let angle = if angle < 0. { angle + 360. } else { angle };
+    Some((angle - start_angle) / (end_angle - start_angle))
+}
+
+fn to_rgba(color: Color) -> Rgba<u8> {
+    let alpha = color[3].max(0.).min(1.);
+    let channel = |value: f32| if alpha > 0. {
+        (value / alpha * 255.).round().max(0.).min(255.) as u8
+    } else {
+        0
+    };
+    Rgba { data: [channel(color[0]), channel(color[1]), channel(color[2]), (alpha * 255.).round() as u8] }
+}
+
+/// Combine premultiplied `source` and `backdrop` colors
+fn composite(source: Color, backdrop: Color, mode: CompositeMode) -> Color {
+    use tables::colr::CompositeMode::*;
+
+    let (source_alpha, backdrop_alpha) = (source[3], backdrop[3]);
+    let porter_duff = |source_factor: f32, backdrop_factor: f32| {
+        let mut color = TRANSPARENT;
+        for idx in 0..4 {
+            color[idx] = source[idx] * source_factor + backdrop[idx] * backdrop_factor;
+        }
+        color
+    };
+    match mode {
+        Clear => TRANSPARENT,
+        Source => source,
+        Destination => backdrop,
+        SourceOver => porter_duff(1., 1. - source_alpha),
+        DestinationOver => porter_duff(1. - backdrop_alpha, 1.),
+        SourceIn => porter_duff(backdrop_alpha, 0.),
+        DestinationIn => porter_duff(0., source_alpha),
+        SourceOut => porter_duff(1. - backdrop_alpha, 0.),
+        DestinationOut => porter_duff(0., 1. - source_alpha),
+        SourceAtop => porter_duff(backdrop_alpha, 1. - source_alpha),
+        DestinationAtop => porter_duff(1. - backdrop_alpha, source_alpha),
+        Xor => porter_duff(1. - backdrop_alpha, 1. - source_alpha),
+        Plus => {
+            let mut color = porter_duff(1., 1.);
+            for channel in color.iter_mut() {
+                *channel = channel.min(1.);
+            }
+            color
+        },
+        _ => blend(source, backdrop, mode),
+    }
+}
+
+/// The W3C compositing spec's blend modes, which mix the colors where both
+/// are drawn
+fn blend(source: Color, backdrop: Color, mode: CompositeMode) -> Color {
+    let unpremultiply = |color: Color| if color[3] > 0. {
+        [color[0] / color[3], color[1] / color[3], color[2] / color[3]]
+    } else {
+        [0.; 3]
+    };
+    let (cs, cb) = (unpremultiply(source), unpremultiply(backdrop));
+    let mixed = match mode {
+        CompositeMode::Hue => set_lum(set_sat(cs, sat(cb)), lum(cb)),
+        CompositeMode::Saturation => set_lum(set_sat(cb, sat(cs)), lum(cb)),
+        CompositeMode::Color => set_lum(cs, lum(cb)),
+        CompositeMode::Luminosity => set_lum(cb, lum(cs)),
+        _ => [
+            blend_channel(mode, cb[0], cs[0]),
+            blend_channel(mode, cb[1], cs[1]),
+            blend_channel(mode, cb[2], cs[2]),
+        ],
+    };
+
+    let (source_alpha, backdrop_alpha) = (source[3], backdrop[3]);
+    let mut color = TRANSPARENT;
+    for idx in 0..3 {
+        color[idx] = source[idx] * (1. - backdrop_alpha) + backdrop[idx] * (1. - source_alpha)
+            + source_alpha * backdrop_alpha * mixed[idx];
+    }
+    color[3] = source_alpha + backdrop_alpha - source_alpha * backdrop_alpha;
+    color
+}
+
+fn blend_channel(mode: CompositeMode, cb: f32, cs: f32) -> f32 {
+    use tables::colr::CompositeMode::*;
+
+    let multiply = |cb: f32, cs: f32| cb * cs;
+    let screen = |cb: f32, cs: f32| cb + cs - cb * cs;
+    let hard_light = |cb: f32, cs: f32| if cs <= 0.5 {
+        multiply(cb, 2. * cs)
+    } else {
+        screen(cb, 2. * cs - 1.)
+    };
+    match mode {
+        Screen => screen(cb, cs),
+        Overlay => hard_light(cs, cb),
+        Darken => cb.min(cs),
+        Lighten => cb.max(cs),
+        ColorDodge => if cb == 0. {
+            0.
+        } else if cs >= 1. {
+            1.
+        } else {
+            (cb / (1. - cs)).min(1.)
+        },
+        ColorBurn => if cb >= 1. {
+            1.
+        } else if cs == 0. {
+            0.
+        } else {
+            1. - ((1. - cb) / cs).min(1.)
+        },
+        HardLight => hard_light(cb, cs),
+        SoftLight => if cs <= 0.5 {
+            cb - (1. - 2. * cs) * cb * (1. - cb)
+        } else {
+            let d = if cb <= 0.25 { ((16. * cb - 12.) * cb + 4.) * cb } else { cb.sqrt() };
+            cb + (2. * cs - 1.) * (d - cb)
+        },
+        Difference => (cb - cs).abs(),
+        Exclusion => cb + cs - 2. * cb * cs,
+        _ => multiply(cb, cs),
+    }
+}
+
+fn lum(color: [f32; 3]) -> f32 {
+    0.3 * color[0] + 0.59 * color[1] + 0.11 * color[2]
+}
+
+fn clip_color(color: [f32; 3]) -> [f32; 3] {
+    let l = lum(color);
+    let min = color[0].min(color[1]).min(color[2]);
+    let max = color[0].max(color[1]).max(color[2]);
+    let mut color = color;
+    for channel in color.iter_mut() {
+        if min < 0. {
+            *channel = l + (*channel - l) * l / (l - min);
+        }
+        if max > 1. {
+            *channel = l + (*channel - l) * (1. - l) / (max - l);
+        }
+    }
+    color
+}
+
+fn set_lum(color: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(color);
+    clip_color([color[0] + d, color[1] + d, color[2] + d])
+}
+
+fn sat(color: [f32; 3]) -> f32 {
+    color[0].max(color[1]).max(color[2]) - color[0].min(color[1]).min(color[2])
+}
+
+fn set_sat(color: [f32; 3], s: f32) -> [f32; 3] {
+    let min = color[0].min(color[1]).min(color[2]);
+    let max = color[0].max(color[1]).max(color[2]);
+    if max <= min {
+        return [0.; 3];
+    }
+    [
+        (color[0] - min) * s / (max - min),
+        (color[1] - min) * s / (max - min),
+        (color[2] - min) * s / (max - min),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tables::colr::tests::{build_colr_v1, color_line, f2dot14, paint_composite, paint_glyph, paint_solid,
+                              paint_with_child};
+    use tables::cpal::tests::build_cpal;
+    use test_utils::{font_buf, with_tables};
+
+    const RED: Rgba<u8> = Rgba { data: [255, 0, 0, 255] };
+
+    /// Render each paint as a base glyph clipped to the em square, 16 pixels
+    /// per em. Palette entry 0 is red and 1 is blue.
+    fn render(paints: Vec<Vec<u8>>) -> Vec<RgbaImage> {
+        let font_buf = font_buf();
+        let base_glyphs: Vec<_> = paints.into_iter().enumerate()
+            .map(|(idx, paint)| (idx as u16 + 1, paint))
+            .collect();
+        let last = base_glyphs.len() as u16;
+        let colr = build_colr_v1(&base_glyphs, &[], &[(1, last, [0, 0, 2048, 2048])], &[]);
+        let cpal = build_cpal(&[(0, 0xFFFF, vec![[255, 0, 0, 255], [0, 0, 255, 255]])], &[]);
+        let buf = with_tables(&font_buf, &[(b"COLR", colr), (b"CPAL", cpal)]);
+        let font = Font::from_buffer(&buf).unwrap();
+        (1..last + 1)
+            .map(|glyph_id| font.render_color_glyph(glyph_id as u32, 16, 0, RED).unwrap())
+            .collect()
+    }
+
+    fn assert_near(pixel: &Rgba<u8>, expected: [u8; 4]) {
+        let near = pixel.data.iter().zip(&expected).all(|(&a, &b)| (a as i32 - b as i32).abs() <= 1);
+        assert!(near, "{:?} isn't near {:?}", pixel.data, expected);
+    }
+
+    fn glyph_id(c: char) -> u16 {
+        let font_buf = font_buf();
+        let font = Font::from_buffer(&font_buf).unwrap();
+        font.get_glyph_id(c).unwrap() as u16
+    }
+
+    #[test]
+    fn gradient_offsets() {
+        let point = |x, y| Point { x, y };
+        assert_eq!(linear_offset(point(0., 0.), point(100., 0.), point(0., 100.), point(50., 7.)), Some(0.5));
+        // Bands run along p0 to p2
+        let skewed = linear_offset(point(0., 0.), point(100., 0.), point(100., 100.), point(50., 50.)).unwrap();
+        assert!(skewed.abs() < 1e-6);
+        assert_eq!(linear_offset(point(0., 0.), point(0., 0.), point(0., 100.), point(1., 1.)), None);
+
+        assert_eq!(radial_offset(point(0., 0.), 0., point(0., 0.), 100., point(0., 50.)), Some(0.5));
+        assert_eq!(radial_offset(point(0., 0.), 50., point(0., 0.), 100., point(0., 150.)), Some(2.));
+
+        assert_eq!(sweep_offset(point(0., 0.), 0., 360., point(0., 10.)), Some(0.25));
+        assert_eq!(sweep_offset(point(0., 0.), 180., 360., point(0., -10.)), Some(0.5));
+    }
+
+    #[test]
+    fn composite_modes() {
+        let half_red = [0.5, 0., 0., 0.5];
+        let blue = [0., 0., 1., 1.];
+        assert_eq!(composite(half_red, blue, CompositeMode::SourceOver), [0.5, 0., 0.5, 1.]);
+        assert_eq!(composite(half_red, blue, CompositeMode::DestinationOver), blue);
+        assert_eq!(composite(half_red, blue, CompositeMode::DestinationOut), [0., 0., 0.5, 0.5]);
+        assert_eq!(composite(half_red, blue, CompositeMode::Xor), [0., 0., 0.5, 0.5]);
+        assert_eq!(composite([1., 1., 1., 1.], [0.5, 0.25, 0., 1.], CompositeMode::Multiply), [0.5, 0.25, 0., 1.]);
+        assert_eq!(composite([1., 0., 0., 1.], blue, CompositeMode::Screen), [1., 0., 1., 1.]);
+        assert_eq!(composite([1., 0., 0., 1.], [0., 0., 0., 0.], CompositeMode::Darken), [1., 0., 0., 1.]);
+        // Takes its luminosity from the source
+        let color = composite([1., 1., 1., 1.], blue, CompositeMode::Luminosity);
+        assert_eq!(color, [1., 1., 1., 1.]);
+    }
+
+    #[test]
+    fn gradients() {
+        let stops = color_line(0, &[(0., 0, 1.), (1., 1, 1.)], None);
+        let images = render(vec![
+            paint_with_child(4, &[0, 0, 2048, 0, 0, 2048], stops.clone()),
+            paint_with_child(6, &[1024, 1024, 0, 1024, 1024, 1024], stops.clone()),
+            paint_with_child(8, &[1024, 1024, f2dot14(0.), f2dot14(1.)], stops),
+        ]);
+        for image in &images {
+            assert_eq!(image.dimensions(), (16, 16));
+        }
+
+        let linear = &images[0];
+        assert_near(linear.get_pixel(0, 4), [248, 0, 8, 255]);
+        assert_near(linear.get_pixel(15, 4), [8, 0, 248, 255]);
+        assert_near(linear.get_pixel(7, 12), [135, 0, 120, 255]);
+
+        let radial = &images[1];
+        let center = radial.get_pixel(8, 8).data;
+        assert!(center[0] > 200 && center[2] < 50);
+        // Padded past the end circle
+        assert_near(radial.get_pixel(0, 0), [0, 0, 255, 255]);
+
+        // Half a turn counter-clockwise from the right of the center, so
+        // the bottom half is padded
+        let sweep = &images[2];
+        assert!(sweep.get_pixel(15, 7).data[0] > 240);
+        assert_near(sweep.get_pixel(8, 0), [133, 0, 122, 255]);
+        assert_near(sweep.get_pixel(8, 15), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn glyphs_transforms_and_composites() {
+        let glyph = || paint_glyph(glyph_id('L'), paint_solid(1, 1.));
+        let images = render(vec![
+            glyph(),
+            paint_with_child(14, &[128, 256], glyph()),
+            paint_composite(glyph(), 8, paint_solid(0, 1.)),
+        ]);
+
+        let plain = &images[0];
+        // Anti-aliased edges
+        assert!(plain.pixels().any(|pixel| pixel.data[3] > 0 && pixel.data[3] < 255));
+        assert!(plain.pixels().any(|pixel| pixel.data == [0, 0, 255, 255]));
+        assert_near(plain.get_pixel(15, 0), [0, 0, 0, 0]);
+
+        // A pixel right and two up
+        let moved = &images[1];
+        for y in 2..16 {
+            for x in 0..15 {
+                assert_eq!(moved.get_pixel(x + 1, y - 2), plain.get_pixel(x, y));
+            }
+        }
+
+        // The L is cut out of the red
+        let cut_out = &images[2];
+        for (plain, cut_out) in plain.pixels().zip(cut_out.pixels()) {
+            assert_eq!(cut_out.data[3], 255 - plain.data[3]);
+            if cut_out.data[3] > 0 {
+                assert_eq!(&cut_out.data[..3], &[255, 0, 0]);
+            }
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let colr_glyph = |glyph_id: u16| vec![11, (glyph_id >> 8) as u8, glyph_id as u8];
+        // Each glyph paints the next one twice, so the paints double with
+        // every glyph
+        let mut paints: Vec<_> = (2..40)
+            .map(|next| paint_composite(colr_glyph(next), 3, colr_glyph(next)))
+            .collect();
+        paints.push(paint_solid(0, 1.));
+        // And one that paints itself
+        paints.push(colr_glyph(40));
+        let images = render(paints);
+        assert_eq!(images[0].dimensions(), (16, 16));
+        assert!(images[39].pixels().all(|pixel| pixel.data == [0; 4]));
+
+        // A clip box too big to paint
+        let font_buf = font_buf();
+        let colr = build_colr_v1(&[(1, paint_solid(0, 1.))], &[], &[(1, 1, [-32768, -32768, 32767, 32767])], &[]);
+        let cpal = build_cpal(&[(0, 0xFFFF, vec![[255, 0, 0, 255]])], &[]);
+        let buf = with_tables(&font_buf, &[(b"COLR", colr), (b"CPAL", cpal)]);
+        let font = Font::from_buffer(&buf).unwrap();
+        assert!(font.render_color_glyph(1, 128, 0, RED).is_none());
+    }
+}
@@ -47,6 +47,22 @@ impl<'a, T: Parse<'a>> BufView<'a, T> {
         use std::marker::PhantomData;
         BufView(self.0, PhantomData)
     }
+    /// Parse a `U` that starts `offset` bytes into the view.
+    ///
+    /// Most tables reference their subtables by an offset from the start of
+    /// the table, so this is usually called on a view of the whole table.
+    pub fn at_offset<U: Parse<'a>>(&self, offset: usize) -> U {
+        U::parse(&self.0[offset..]).1
+    }
+    /// Like `at_offset`, but `None` if a `U` doesn't fit there. Use this
+    /// when the offset was read from the font.
+    pub fn checked_at_offset<U: Parse<'a>>(&self, offset: usize) -> Option<U> {
+        let buf = self.0.get(offset..)?;
+        if buf.len() < U::approx_file_size() {
+            return None;
+        }
+        Some(U::parse(buf).1)
+    }
 }
 impl<'a, T: Parse<'a>> fmt::Debug for BufView<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -79,7 +95,12 @@ impl<'a, T: Parse<'a>> DynArr<'a, T> {
         let (before, after) = self.0.split_at(sized_idx);
         (DynArr(before, PhantomData), DynArr(after, PhantomData))
     }
-    pub fn binary_search_by<F>(&self, mut f: F) -> Option<T>
+    pub fn binary_search_by<F>(&self, f: F) -> Option<T>
+        where F: FnMut(&T) -> Ordering {
+        self.binary_search_idx_by(f).map(|idx| self.at(idx))
+    }
+    /// Like `binary_search_by`, but returns the index of the found item
+    pub fn binary_search_idx_by<F>(&self, mut f: F) -> Option<usize>
         where F: FnMut(&T) -> Ordering {
         // Adapted from rust std's slice binary_search_by function
         let mut size = self.len();
@@ -95,9 +116,8 @@ impl<'a, T: Parse<'a>> DynArr<'a, T> {
             size -= half;
         }
 
-        let found_item = self.at(left);
-        let cmp = f(&found_item);
-        if cmp == Ordering::Equal { Some(found_item) } else { None }
+        let cmp = f(&self.at(left));
+        if cmp == Ordering::Equal { Some(left) } else { None }
     }
 }
 impl<'a, T: Parse<'a>> Parse<'a> for DynArr<'a, T> {
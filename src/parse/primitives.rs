@@ -117,6 +117,13 @@ impl<'a> Parse<'a> for F2Dot14 {
 #[derive(Debug, Parse, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Fixed(pub i16, pub i16);
 
+impl Fixed {
+    pub fn to_f32(&self) -> f32 {
+        // The fraction is unsigned
+        self.0 as f32 + (self.1 as u16) as f32 / 65536.
+    }
+}
+
 // impl<'a> Parse<'a> for &'a [u8] {
 //     fn approx_file_size() -> usize {
 //         0
@@ -1,8 +1,8 @@
 use itertools::Itertools;
-use image::{ImageBuffer, Luma, GrayImage, DynamicImage, RgbImage};
+use image::{ImageBuffer, Luma, GrayImage, DynamicImage, RgbImage, Rgba, RgbaImage};
 use imageproc::drawing::draw_antialiased_line_segment_mut; // TODO: Pick ONE draw_line func
 use imageproc::drawing::draw_line_segment_mut;
-use math::{LineSegment, Matrix, Point};
+use math::{Affine, LineSegment, Matrix, Point};
 use tables::glyf::{Coordinate, SimpleCoordinates};
 
 type GrayDirectedImage = ImageBuffer<Luma<i16>, Vec<i16>>;
@@ -31,6 +31,10 @@ pub mod compositor {
         pub line_gap: FontUnit<i16>,
     }
 
+    /// How many positions within a pixel the pen is rounded to, along each
+    /// axis. Glyphs only need rendering once per phase.
+    pub const SUBPIXEL_PHASES: u32 = 4;
+
     /// Abstraction over a string of text to render, who's characters have been
     /// turned into bitmaps.
     pub struct RenderedText {
@@ -41,10 +45,11 @@ pub mod compositor {
         descent: i16,
         /// Distance that must be placed between two lines of text.
         line_gap: i16,
-        /// x-coordinate of the "pen". Right is positive
-        pen_x: u32,
+        /// x-coordinate of the "pen", in pixels. Right is positive. Kept
+        /// fractional so advances don't round away.
+        pen_x: f32,
         /// y-coordinate of the "pen". Down is positive
-        pen_y: u32,
+        pen_y: f32,
         /// What direction are we writing the text?
         text_direction: TextDirection,
         /// How big to render things
@@ -64,10 +69,10 @@ pub mod compositor {
                 ascent,
                 descent,
                 line_gap,
-                pen_x: 0,
+                pen_x: 0.,
                 // Ensures that we don't have to worry about exending the string's
                 // bitmap upward, just downward on newline
-                pen_y: ascent as u32, // `ascent` should be a positive value
+                pen_y: ascent as f32, // `ascent` should be a positive value
                 text_direction,
                 point_size,
                 units_per_em,
@@ -80,85 +85,97 @@ pub mod compositor {
             Self::new_horizontal(render_metrics, point_size, units_per_em, TextDirection::Right)
         }
 
+        /// Where the glyph's origin will fall within a pixel, rounded to one
+        /// of `SUBPIXEL_PHASES` steps. Render the glyph with this offset
+        /// before passing it to `add_glyph`.
+        pub fn subpixel_offset(&self, placement_metrics: &GlyphPlacementMetrics) -> [f32; 2] {
+            let (x, y) = self.origin(placement_metrics);
+            // Up is positive for glyphs
+            [x - x.floor(), y.ceil() - y]
+        }
+
+        /// `glyph_bmp` should come from `Font::render_glyph` with the offset
+        /// from `subpixel_offset`
         pub fn add_glyph(&mut self, glyph_bmp: GrayImage, placement_metrics: GlyphPlacementMetrics) {
             use image::{GenericImage, imageops::flip_vertical};
             use self::TextDirection::{Left, Right, Up, Down};
             let glyph_bmp = flip_vertical(&glyph_bmp);
-        // pub shift: [FontUnit<f32>; 2],
-        // pub left_bearing: FontUnit<i16>,
-        // pub top_bearing: FontUnit<i16>,
-        // pub horiz_advance: Option<FontUnit<u16>>,
-        // pub vert_advance: Option<FontUnit<u16>>,
-            let top_bearing = self.scale_fu(placement_metrics.top_bearing) as u32;
-            let left_bearing = self.scale_fu(placement_metrics.left_bearing) as u32;
-
-            let horiz_advance = placement_metrics.horiz_advance
-                .map(|ha| self.scale_fu(ha) as u32)
-                .map(|ha| ha.max(left_bearing + glyph_bmp.width()));
-            let vert_advance = placement_metrics.vert_advance
-                .map(|va| self.scale_fu(va) as u32)
-                .map(|va| va.max(glyph_bmp.height() - top_bearing));
-
-            println!("bmp_dims: {:?}, advances: {:?}\tbearings: {:?}",
+
+            let horiz_advance = self.horiz_advance(&placement_metrics);
+            let vert_advance = placement_metrics.vert_advance.map_or(0., |va| self.to_pixels(va));
+            let (origin_x, origin_y) = self.origin(&placement_metrics);
+            let offset = self.subpixel_offset(&placement_metrics);
+            // `render_glyph` puts the image's bottom left corner in the pixel
+            // with the glyph's (x_min, y_min). Bitmaps can't be moved, so
+            // they go by whole pixels.
+            let (left, bottom) = match placement_metrics.bitmap_bearings {
+                Some([left_bearing, top_bearing]) => {
+                    (left_bearing.round(), top_bearing.round() - glyph_bmp.height() as f32)
+                },
+                None => (
+                    (offset[0] - self.to_pixels(placement_metrics.shift[0])).floor(),
+                    (offset[1] - self.to_pixels(placement_metrics.shift[1])).floor(),
+                ),
+            };
+
+            println!("bmp_dims: {:?}, advances: {:?}\tedges: {:?}",
                      glyph_bmp.dimensions(),
                      (horiz_advance, vert_advance),
-                     (left_bearing, top_bearing));
+                     (left, bottom));
             println!("pen: {:?}", (self.pen_x, self.pen_y));
 
-            match &self.text_direction {
-                Left => {
-                    self.pen_x = self.pen_x.wrapping_sub(horiz_advance.unwrap_or(0));
-                },
-                Up => {
-                    unimplemented!()
-                },
-                _ => (),
-            }
-
             let (place_x, place_y) = match &self.text_direction {
                 Left | Right => {
-                    // Subtract top_bearing since positive is downward
-                    // and we want the top edge
-                    (self.pen_x.wrapping_add(left_bearing), self.pen_y - top_bearing)
+                    // Subtract since positive is downward and we want the
+                    // top edge
+                    (origin_x.floor() + left, origin_y.ceil() - bottom - glyph_bmp.height() as f32)
                 },
                 Up | Down => {
                     unimplemented!()
                 }
             };
+            let pen_end_x = match &self.text_direction {
+                Left => self.pen_x - horiz_advance,
+                _ => self.pen_x + horiz_advance,
+            };
 
-            let (width, height) = self.img.dimensions();
-            assert!(horiz_advance.is_none() || horiz_advance.unwrap() >= glyph_bmp.width() + left_bearing);
-            assert!(vert_advance.is_none() || vert_advance.unwrap() >= glyph_bmp.height() + top_bearing);
+            // Right to left text, and glyphs sticking out past the start,
+            // grow the canvas to the left or top, so everything already drawn
+            // has to move over by `orig_x` and `orig_y`
+            let orig_x = (-place_x.min(pen_end_x)).max(0.).ceil() as u32;
+            let orig_y = (-place_y).max(0.).ceil() as u32;
+            let place_x = (place_x + orig_x as f32) as u32;
+            let place_y = (place_y + orig_y as f32) as u32;
+            let pen_end_x = pen_end_x + orig_x as f32;
+            self.pen_y += orig_y as f32;
 
-            let width = width.max(self.pen_x + horiz_advance.unwrap_or(
-                    glyph_bmp.width() + left_bearing));
-            let height = height.max(self.pen_y + vert_advance.unwrap_or(
-                    (glyph_bmp.height() as i32 - top_bearing as i32).abs() as u32));
+            let (width, height) = self.img.dimensions();
+            let width = (width + orig_x)
+                .max(place_x + glyph_bmp.width())
+                .max(pen_end_x.ceil() as u32);
+            let height = (height + orig_y)
+                .max(place_y + glyph_bmp.height())
+                .max((self.pen_y + vert_advance).ceil() as u32);
 
             let mut new_img = GrayImage::new(width, height);
 
-            let (orig_x, orig_y) = match &self.text_direction {
-                Right => (0, 0),
-                _ => unimplemented!(),
-            };
-
             println!("Canvas now {:?} (from {:?})", new_img.dimensions(), self.img.dimensions());
             println!("Placing old at {:?}", (orig_x, orig_y));
             println!("Placing new at {:?}", (place_x, place_y));
 
+            new_img.copy_from(&self.img, orig_x, orig_y);
+            // Neighbouring glyphs can share the pixels at their edges
+            for (x, y, pixel) in glyph_bmp.enumerate_pixels() {
+                let dest = new_img.get_pixel_mut(place_x + x, place_y + y);
+                dest.data[0] = dest.data[0].saturating_add(pixel.data[0]);
+            }
 
-            let cp1 = new_img.copy_from(&self.img, orig_x, orig_y);
-
-            let cp2 = new_img.copy_from(&glyph_bmp, place_x, place_y);
-            println!("cp status: {:?}, {:?}", cp1, cp2);
-
-            // Needs to be wrapping?
-            self.pen_x += horiz_advance.unwrap_or(0);
-            self.pen_y += vert_advance.unwrap_or(0);
+            self.pen_x = pen_end_x;
+            self.pen_y += vert_advance;
 
             self.img = new_img;
 
-            draw_baseline(&mut self.img, self.pen_y);
+            draw_baseline(&mut self.img, self.pen_y as u32);
         }
         pub fn newline(&mut self) {
             use image::GenericImage;
@@ -167,7 +184,7 @@ pub mod compositor {
             let (width, height) = self.img.dimensions();
             let mut new_img = GrayImage::new(width, height + b2b_dist);
 
-            let y_above_newline = self.pen_y + self.descent.abs() as u32;
+            let y_above_newline = self.pen_y.ceil() as u32 + self.descent.abs() as u32;
 
             {
                 let above_newline = self.img.sub_image(0, 0,
@@ -183,23 +200,41 @@ pub mod compositor {
                 new_img.copy_from(&below_newline, 0, y_above_newline + b2b_dist);
             }
 
-            self.pen_x = 0;
-            self.pen_y += b2b_dist;
+            self.pen_x = match &self.text_direction {
+                TextDirection::Left => width as f32,
+                _ => 0.,
+            };
+            self.pen_y += b2b_dist as f32;
             self.img = new_img;
 
-            draw_baseline(&mut self.img, self.pen_y);
+            draw_baseline(&mut self.img, self.pen_y as u32);
         }
 
         fn baseline_to_baseline_dist(&self) -> u32 {
             (self.ascent - self.descent + self.line_gap) as u32
         }
 
-        fn scale_fu<T: Into<f32> + FromF32>(&self, units: FontUnit<T>) -> T {
-            let pixels = units.to_pixels(self.units_per_em, self.point_size);
-            T::from_f32(pixels)
+        /// Where the glyph's origin goes: the pen, after moving it back over
+        /// the glyph for right to left text, rounded to a subpixel phase
+        fn origin(&self, placement_metrics: &GlyphPlacementMetrics) -> (f32, f32) {
+            let x = match &self.text_direction {
+                TextDirection::Left => self.pen_x - self.horiz_advance(placement_metrics),
+                _ => self.pen_x,
+            };
+            let phases = SUBPIXEL_PHASES as f32;
+            ((x * phases).round() / phases, (self.pen_y * phases).round() / phases)
+        }
+
+        fn horiz_advance(&self, placement_metrics: &GlyphPlacementMetrics) -> f32 {
+            placement_metrics.horiz_advance.map_or(0., |ha| self.to_pixels(ha))
+        }
+
+        fn to_pixels<T: Into<f32>>(&self, units: FontUnit<T>) -> f32 {
+            units.to_pixels(self.units_per_em, self.point_size)
         }
     }
 
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum TextDirection {
         Left,
         Right,
@@ -219,13 +254,14 @@ pub mod compositor {
     /// * `right_bearing = placement_metrics.Advance::Width - width`
     ///
     /// * `bottom_bearing = placement_metrics.Advance::Height - height`
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct GlyphPlacementMetrics {
         /// How much `img` is shifted away from (0, 0)
         ///
         /// (Since glyphs are meant to "rest" on the baseline)
         ///
-        /// Is this needed?
+        /// This is minus the glyph's `(x_min, y_min)`, which `RenderedText`
+        /// places images by.
         pub shift: [FontUnit<f32>; 2],
         /// Horizontal distance from origin to `img` (left edge of bbox)
         pub left_bearing: FontUnit<i16>,
@@ -235,27 +271,58 @@ pub mod compositor {
         pub horiz_advance: Option<FontUnit<u16>>,
         /// After drawing a glyph you move the "pen" this amount down
         pub vert_advance: Option<FontUnit<u16>>,
+        /// For glyphs drawn from an embedded bitmap, pixels from the origin
+        /// to the image's left edge and from the baseline up to its top
+        /// edge. These place the image instead of `shift`.
+        pub bitmap_bearings: Option<[f32; 2]>,
     }
 
-    trait FromF32 {
-        fn from_f32(val: f32) -> Self;
-    }
-    macro_rules! impl_from_f32 {
-        ($($prim:ty),*) => {
-            $(
-                impl FromF32 for $prim {
-                    fn from_f32(val: f32) -> $prim {
-                        val.floor() as $prim
-                    }
-                }
-             )*
-        }
-    }
-    impl_from_f32!{
-        i8, i16, i32, isize,
-        u8, u16, u32, usize
+}
+
+/// A glyph drawn from an `EBDT` bitmap
+#[derive(Debug, Clone)]
+pub struct BitmapGlyph {
+    /// Row 0 is the top
+    pub image: GrayImage,
+    /// Pixels from the origin to the image's left edge
+    pub left_bearing: f32,
+    /// Pixels from the baseline up to the image's top edge
+    pub top_bearing: f32,
+}
+
+/// A glyph drawn from a color bitmap, like an emoji
+#[derive(Debug, Clone)]
+pub struct ColorBitmapGlyph {
+    /// Row 0 is the top
+    pub image: RgbaImage,
+    /// Pixels from the origin to the image's left edge
+    pub left_bearing: f32,
+    /// Pixels from the baseline up to the image's top edge
+    pub top_bearing: f32,
+}
+
+/// Paint `color` over `image` where `coverage` is set, blending by the
+/// coverage and the color's alpha
+pub fn fill_over(image: &mut RgbaImage, coverage: &GrayImage, color: Rgba<u8>) {
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let alpha = coverage.get_pixel(x, y).data[0] as f32 / 255. * color.data[3] as f32 / 255.;
+        *pixel = blend_over(*pixel, color, alpha);
     }
+}
 
+/// Source-over compositing of `color` with opacity `alpha` onto `dest`,
+/// neither premultiplied
+pub(crate) fn blend_over(dest: Rgba<u8>, color: Rgba<u8>, alpha: f32) -> Rgba<u8> {
+    if alpha <= 0. {
+        return dest;
+    }
+    let dest_alpha = dest.data[3] as f32 / 255.;
+    let out_alpha = alpha + dest_alpha * (1. - alpha);
+    let channel = |idx: usize| {
+        let value = (color.data[idx] as f32 * alpha + dest.data[idx] as f32 * dest_alpha * (1. - alpha)) / out_alpha;
+        value.round() as u8
+    };
+    Rgba { data: [channel(0), channel(1), channel(2), (out_alpha * 255.).round() as u8] }
 }
 
 pub trait Raster {
@@ -264,37 +331,136 @@ pub trait Raster {
     fn into_dynamic(self) -> DynamicImage;
 }
 
+/// Which parts of overlapping or nested contours are filled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillRule {
+    /// Anywhere the contours wind around at all. TrueType and CFF outlines
+    /// use this.
+    NonZero,
+    /// Anywhere the contours wind around an odd number of times
+    EvenOdd,
+}
+
+impl FillRule {
+    fn is_inside(self, winding: isize) -> bool {
+        match self {
+            FillRule::NonZero => winding != 0,
+            FillRule::EvenOdd => winding % 2 != 0,
+        }
+    }
+
+    /// Coverage from the signed area that contours add up to in a pixel
+    fn coverage(self, area: f32) -> f32 {
+        let area = area.abs();
+        match self {
+            FillRule::NonZero => area.min(1.),
+            FillRule::EvenOdd => {
+                let area = area % 2.;
+                if area > 1. { 2. - area } else { area }
+            },
+        }
+    }
+}
+
+/// How a monochrome raster keeps strokes that fall between pixel centers.
+/// These are the TrueType `SCANTYPE` rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropoutMode {
+    /// Only pixels with their centers inside are on
+    Off,
+    /// Turn on the left or bottom pixel of a gap
+    Simple,
+    /// `Simple`, except at the ends of strokes
+    SimpleNoStubs,
+    /// Turn on the pixel nearest the middle of the stroke
+    Smart,
+    /// `Smart`, except at the ends of strokes
+    SmartNoStubs,
+}
+
+impl DropoutMode {
+    /// The rule picked by a `SCANTYPE` value. 2, 3 and anything else
+    /// unknown turn dropout control off.
+    pub fn from_scan_type(scan_type: i32) -> DropoutMode {
+        match scan_type {
+            0 => DropoutMode::Simple,
+            1 => DropoutMode::SimpleNoStubs,
+            4 => DropoutMode::Smart,
+            5 => DropoutMode::SmartNoStubs,
+            _ => DropoutMode::Off,
+        }
+    }
+
+    fn skips_stubs(self) -> bool {
+        self == DropoutMode::SimpleNoStubs || self == DropoutMode::SmartNoStubs
+    }
+}
+
+/// Monochrome: a pixel is on if its center is inside the outline
 pub struct FillInRaster {
     windings: Matrix<isize>,
-    lines: Vec<LineSegment>,
+    /// Every line in the order they were added, so contours can be followed
+    lines: Vec<(Point, Point)>,
+    fill_rule: FillRule,
+    dropout_mode: DropoutMode,
 }
 
-impl Raster for FillInRaster {
-    fn new(width: u32, height: u32) -> Self {
+impl FillInRaster {
+    pub fn with_fill_rule(width: u32, height: u32, fill_rule: FillRule) -> FillInRaster {
+        FillInRaster::with_dropout_mode(width, height, fill_rule, DropoutMode::Off)
+    }
+
+    pub fn with_dropout_mode(width: u32, height: u32, fill_rule: FillRule, dropout_mode: DropoutMode) -> FillInRaster {
         FillInRaster {
             windings: Matrix::new(width, height),
             lines: Vec::new(),
+            fill_rule,
+            dropout_mode,
         }
     }
 
+    /// Pixels to turn on so strokes thinner than a pixel don't vanish,
+    /// found along the rows and then the columns
+    fn dropouts(&self, is_on: &[bool]) -> Vec<(usize, usize)> {
+        let (width, height) = (self.windings.width, self.windings.height);
+        let mut pixels = find_dropouts(&self.lines, height, width, self.fill_rule, self.dropout_mode,
+                                       |row, x| is_on[x + row * width]);
+
+        let transposed: Vec<_> = self.lines.iter()
+            .map(|&(start, end)| (Point { x: start.y, y: start.x }, Point { x: end.y, y: end.x }))
+            .collect();
+        let columns = find_dropouts(&transposed, width, height, self.fill_rule, self.dropout_mode,
+                                    |column, y| is_on[column + y * width]);
+        pixels.extend(columns.into_iter().map(|(column, y)| (y, column)));
+        pixels
+    }
+}
+
+impl Raster for FillInRaster {
+    fn new(width: u32, height: u32) -> Self {
+        FillInRaster::with_fill_rule(width, height, FillRule::NonZero)
+    }
+
     fn add_line(&mut self, start: Point, end: Point) {
-        // Horizontal lines screw up intersection finding code
-        if start.y != end.y {
-            self.lines.push((start, end).into());
-        }
+        self.lines.push((start, end));
     }
 
     fn into_dynamic(mut self) -> DynamicImage {
         use std::u8;
-        // TODO: Handle dropouts
+
+        // Horizontal lines screw up intersection finding code
+        let lines: Vec<LineSegment> = self.lines.iter()
+            .filter(|&&(start, end)| start.y != end.y)
+            .map(|&line| line.into())
+            .collect();
 
         // Just asking to be parallellized
         for (y, row) in self.windings.data.chunks_mut(self.windings.width).enumerate() {
             let y = y as f32 + 0.5; // Scanline is at center of a pixel
-            for line in self.lines.iter() {
+            for line in lines.iter() {
                 if let Some(x) = line.horiz_line_intersects(y) {
                     let wind_val = line.winding_value() as isize;
-                    let x = x.round() as usize;
+                    let x = (x.round().max(0.) as usize).min(row.len());
                     for winding in &mut row[x..] {
                         *winding += wind_val;
                     }
@@ -302,8 +468,17 @@ impl Raster for FillInRaster {
             }
         }
 
-        let img_data = self.windings.data.into_iter()
-            .map(|wind_val| wind_val.abs().min(1))
+        let fill_rule = self.fill_rule;
+        let mut is_on: Vec<bool> = self.windings.data.iter()
+            .map(|&wind_val| fill_rule.is_inside(wind_val))
+            .collect();
+        if self.dropout_mode != DropoutMode::Off {
+            // Only pixels on from their centers stop a dropout
+            for (row, x) in self.dropouts(&is_on) {
+                is_on[x + row * self.windings.width] = true;
+            }
+        }
+        let img_data = is_on.into_iter()
             .map(|pix_on| pix_on as u8 * u8::MAX)
             .collect();
 
@@ -315,6 +490,223 @@ impl Raster for FillInRaster {
     }
 }
 
+/// Dropouts along scanlines that go across x, at the center of each row.
+/// A dropout is where the outline goes in and out again between two pixel
+/// centers without either of them being on. Gives `(row, x)` of the pixels
+/// to turn on.
+fn find_dropouts<F>(lines: &[(Point, Point)], rows: usize, width: usize, fill_rule: FillRule,
+                    dropout_mode: DropoutMode, is_on: F) -> Vec<(usize, usize)>
+    where F: Fn(usize, usize) -> bool
+{
+    let extents = monotonic_extents(lines);
+    let segments: Vec<(usize, LineSegment)> = lines.iter()
+        .enumerate()
+        .filter(|&(_, &(start, end))| start.y != end.y)
+        .map(|(idx, &line)| (idx, line.into()))
+        .collect();
+
+    let mut pixels = Vec::new();
+    if width == 0 {
+        return pixels;
+    }
+    for row in 0..rows {
+        let y = row as f32 + 0.5;
+        let mut crossings: Vec<(f32, isize, usize)> = segments.iter()
+            .filter_map(|&(idx, ref line)| {
+                line.horiz_line_intersects(y).map(|x| (x, line.winding_value() as isize, idx))
+            })
+            .collect();
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+
+        let mut winding = 0;
+        let mut span_start = None;
+        for (x, wind_val, idx) in crossings {
+            let was_inside = fill_rule.is_inside(winding);
+            winding += wind_val;
+            match (was_inside, fill_rule.is_inside(winding)) {
+                (false, true) => span_start = Some((x, idx)),
+                (true, false) => {
+                    let (start, start_idx) = match span_start.take() {
+                        Some(start) => start,
+                        None => continue,
+                    };
+                    // A pixel center is inside the span
+                    if (start - 0.5).ceil() <= (x - 0.5).floor() {
+                        continue;
+                    }
+                    // The pixels on either side of the gap
+                    let left = (x - 0.5).floor() as isize;
+                    let right = left + 1;
+                    let on = |pixel: isize| pixel >= 0 && (pixel as usize) < width && is_on(row, pixel as usize);
+                    if on(left) || on(right) {
+                        continue;
+                    }
+                    if dropout_mode.skips_stubs() {
+                        let (left_edge, right_edge) = (extents[start_idx], extents[idx]);
+                        // Both sides of the stroke stop before the next
+                        // scanline up or down
+                        let top_stub = left_edge.1 < y + 1. && right_edge.1 < y + 1.;
+                        let bottom_stub = left_edge.0 > y - 1. && right_edge.0 > y - 1.;
+                        if top_stub || bottom_stub {
+                            continue;
+                        }
+                    }
+                    let pixel = match dropout_mode {
+                        DropoutMode::Smart | DropoutMode::SmartNoStubs => ((start + x) / 2.).floor() as isize,
+                        _ => left,
+                    };
+                    let pixel = pixel.max(0).min(width as isize - 1) as usize;
+                    pixels.push((row, pixel));
+                },
+                _ => (),
+            }
+        }
+    }
+    pixels
+}
+
+/// The lowest and highest y of the run of lines going the same way up or
+/// down that each line is part of. A run can wrap around the end of its
+/// contour.
+fn monotonic_extents(lines: &[(Point, Point)]) -> Vec<(f32, f32)> {
+    /// The last run of a contour carries on into the first if they go the
+    /// same way
+    fn join_ends(runs: &mut [(f32, f32, i8)], first: usize) {
+        let last = runs.len().saturating_sub(1);
+        if last > first && runs[first].2 == runs[last].2 {
+            let joined = (runs[first].0.min(runs[last].0), runs[first].1.max(runs[last].1), runs[first].2);
+            runs[first] = joined;
+            runs[last] = joined;
+        }
+    }
+
+    // Lowest y, highest y and which way
+    let mut runs: Vec<(f32, f32, i8)> = Vec::new();
+    let mut run_of = Vec::with_capacity(lines.len());
+    let mut contour_start = 0;
+    let mut prev_end = None;
+    for &(start, end) in lines {
+        let direction = if end.y > start.y { 1 } else if end.y < start.y { -1 } else { 0 };
+        let new_contour = prev_end != Some(start);
+        if new_contour {
+            join_ends(&mut runs, contour_start);
+            contour_start = runs.len();
+        }
+        let turns = runs.last().map_or(false, |run| run.2 != 0 && direction != 0 && run.2 != direction);
+        if new_contour || turns {
+            runs.push((start.y, start.y, 0));
+        }
+        let run = runs.last_mut().expect("Should have a run");
+        run.0 = run.0.min(end.y);
+        run.1 = run.1.max(end.y);
+        if direction != 0 {
+            run.2 = direction;
+        }
+        run_of.push(runs.len() - 1);
+        prev_end = Some(end);
+    }
+    join_ends(&mut runs, contour_start);
+    run_of.into_iter().map(|run| (runs[run].0, runs[run].1)).collect()
+}
+
+/// Anti-aliases exactly: each line adds the signed area it covers to the
+/// pixels it crosses, and the area carries on to the right until another
+/// line takes it away.
+pub struct CoverageRaster {
+    width: usize,
+    height: usize,
+    /// Changes in coverage, with an extra column per row for lines on the
+    /// right edge
+    deltas: Vec<f32>,
+    fill_rule: FillRule,
+}
+
+impl CoverageRaster {
+    pub fn with_fill_rule(width: u32, height: u32, fill_rule: FillRule) -> CoverageRaster {
+        CoverageRaster {
+            width: width as usize,
+            height: height as usize,
+            deltas: vec![0.; (width as usize + 1) * height as usize],
+            fill_rule,
+        }
+    }
+
+    /// How much of each pixel is covered, from 0 to 1
+    pub fn coverage(self) -> Vec<f32> {
+        let mut coverage = Vec::with_capacity(self.width * self.height);
+        for row in self.deltas.chunks(self.width + 1) {
+            let mut area = 0.;
+            for delta in &row[..self.width] {
+                area += delta;
+                coverage.push(self.fill_rule.coverage(area));
+            }
+        }
+        coverage
+    }
+
+    /// Add the part of a line inside row `row`, from `x_a` to `x_b`.
+    /// `height` is how far it goes down the row, negative if it goes up.
+    fn add_row_segment(&mut self, row: usize, x_a: f32, x_b: f32, height: f32) {
+        let max_x = self.width as f32;
+        let x_min = x_a.min(x_b).max(0.).min(max_x);
+        let x_max = x_a.max(x_b).max(0.).min(max_x);
+        let row = &mut self.deltas[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+
+        // Each pixel the segment crosses gets the part of the area left of
+        // the segment's piece in it. The rest goes to the next pixel.
+        let mut x = x_min;
+        loop {
+            let column = x.floor();
+            let piece_end = (column + 1.).min(x_max);
+            let share = if x_max > x_min { (piece_end - x) / (x_max - x_min) } else { 1. };
+            let area = height * share;
+            let middle = (x + piece_end) / 2. - column;
+            let idx = column as usize;
+            row[idx] += area * (1. - middle);
+            if let Some(next) = row.get_mut(idx + 1) {
+                *next += area * middle;
+            }
+            if piece_end >= x_max {
+                break;
+            }
+            x = piece_end;
+        }
+    }
+}
+
+impl Raster for CoverageRaster {
+    fn new(width: u32, height: u32) -> Self {
+        CoverageRaster::with_fill_rule(width, height, FillRule::NonZero)
+    }
+
+    fn add_line(&mut self, start: Point, end: Point) {
+        if start.y == end.y {
+            return;
+        }
+        let (direction, top, bottom) = if start.y < end.y { (1., start, end) } else { (-1., end, start) };
+        let dx_dy = (bottom.x - top.x) / (bottom.y - top.y);
+        let x_at = |y: f32| top.x + (y - top.y) * dx_dy;
+
+        let y_end = bottom.y.min(self.height as f32);
+        let mut y = top.y.max(0.);
+        while y < y_end {
+            let row = y.floor();
+            let next_y = (row + 1.).min(y_end);
+            self.add_row_segment(row as usize, x_at(y), x_at(next_y), (next_y - y) * direction);
+            y = next_y;
+        }
+    }
+
+    fn into_dynamic(self) -> DynamicImage {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let img_data = self.coverage().into_iter()
+            .map(|coverage| (coverage * 255.).round() as u8)
+            .collect();
+        let img = GrayImage::from_vec(width, height, img_data)
+            .expect("Couldn't re-create GrayImage");
+        DynamicImage::ImageLuma8(img)
+    }
+}
 
 pub struct OutlineRaster(pub GrayImage);
 impl Raster for OutlineRaster {
@@ -592,28 +984,34 @@ impl Raster for BadFilledRaster {
 //     }
 // }
 
+/// How far flattened curves may stray from the real ones, in pixels
+pub const FLATTENING_TOLERANCE: f32 = 0.25;
+
+/// Stop halving curves after this many times, in case of huge or broken
+/// coordinates
+const MAX_SUBDIVISIONS: u32 = 16;
+
+/// Length of `a - 2b + c`, which is how much a curve bends
+fn second_difference(a: Point, b: Point, c: Point) -> f32 {
+    let x = a.x - 2. * b.x + c.x;
+    let y = a.y - 2. * b.y + c.y;
+    (x * x + y * y).sqrt()
+}
+
+/// Lines within `tolerance` of a quadratic curve. The curve is halved until
+/// each piece is flat enough, so tight bends get more lines than gentle
+/// ones.
 pub struct CurveLines {
-    start: Point,
-    off_curve: Point,
-    end: Point,
-    prev: Point,
-    num_points: u32,
-    i: u32
+    tolerance: f32,
+    /// Pieces left to flatten, the next one last, and how many times each
+    /// has been halved
+    pending: Vec<([Point; 3], u32)>,
 }
 impl CurveLines {
-    pub fn new(start: Point, off_curve: Point, end: Point) -> CurveLines {
-        let dist1 = start.distance_to(off_curve);
-        let dist2 = end.distance_to(off_curve);
-        let num_points = dist1 + dist2 + 2.;
-        let num_points = num_points.floor() as u32;
-
+    pub fn new(start: Point, off_curve: Point, end: Point, tolerance: f32) -> CurveLines {
         CurveLines {
-            start,
-            off_curve,
-            end,
-            prev: start,
-            num_points,
-            i: 0,
+            tolerance,
+            pending: vec![([start, off_curve, end], 0)],
         }
     }
 }
@@ -621,30 +1019,60 @@ impl CurveLines {
 impl Iterator for CurveLines {
     type Item = (Point, Point);
     fn next(&mut self) -> Option<Self::Item> {
-        // p(t) = (1-t)^2*p0 + 2*t(1-t)*p1 + t^2*p2
-        if self.i >= self.num_points {
-            return None;
-        }
-        self.i += 1;
+        loop {
+            let ([start, off_curve, end], depth) = self.pending.pop()?;
+            // The farthest the curve gets from the line between its ends
+            let deviation = second_difference(start, off_curve, end) / 4.;
+            if deviation <= self.tolerance || depth >= MAX_SUBDIVISIONS {
+                return Some((start, end));
+            }
 
-        if self.i == self.num_points {
-            return Some((self.prev, self.end));
+            let p1 = start.lerp_to(off_curve, 0.5);
+            let p2 = off_curve.lerp_to(end, 0.5);
+            let middle = p1.lerp_to(p2, 0.5);
+            self.pending.push(([middle, p2, end], depth + 1));
+            self.pending.push(([start, p1, middle], depth + 1));
         }
+    }
+}
 
+/// Lines within `tolerance` of a cubic curve, like `CurveLines`
+pub struct CubicCurveLines {
+    tolerance: f32,
+    pending: Vec<([Point; 4], u32)>,
+}
+impl CubicCurveLines {
+    pub fn new(start: Point, control1: Point, control2: Point, end: Point, tolerance: f32) -> CubicCurveLines {
+        CubicCurveLines {
+            tolerance,
+            pending: vec![([start, control1, control2, end], 0)],
+        }
+    }
+}
 
-        let t = (self.num_points as f32).recip() * self.i as f32;
-
-        let p1 = self.start.lerp_to(self.off_curve, t);
-        let p2 = self.off_curve.lerp_to(self.end, t);
-        let p = p1.lerp_to(p2, t);
-        // let p1 = self.start * (1. - t) * (1. - t);
-        // let p2 = self.off_curve * 2. * t * (1. - t);
-        // let p3 = self.end * t * t;
-        // let p = p1 + p2 + p3;
+impl Iterator for CubicCurveLines {
+    type Item = (Point, Point);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ([start, control1, control2, end], depth) = self.pending.pop()?;
+            // A bound on how far the curve gets from the line between its
+            // ends
+            let deviation = 0.75 * second_difference(start, control1, control2)
+                .max(second_difference(control1, control2, end));
+            if deviation <= self.tolerance || depth >= MAX_SUBDIVISIONS {
+                return Some((start, end));
+            }
 
-        let segment = (self.prev, p);
-        self.prev = p;
-        Some(segment)
+            // de Casteljau
+            let p1 = start.lerp_to(control1, 0.5);
+            let p2 = control1.lerp_to(control2, 0.5);
+            let p3 = control2.lerp_to(end, 0.5);
+            let p12 = p1.lerp_to(p2, 0.5);
+            let p23 = p2.lerp_to(p3, 0.5);
+            let middle = p12.lerp_to(p23, 0.5);
+            self.pending.push(([middle, p23, p3, end], depth + 1));
+            self.pending.push(([start, p1, p12, middle], depth + 1));
+        }
     }
 }
 
@@ -736,21 +1164,51 @@ impl<I: Iterator<Item=Coordinate>> Iterator for DrawCommands<I> {
 pub enum DrawCommand {
     Line(Point, Point),
     Curve(Point, Point, Point),
+    /// Start, two control points, end. From CFF outlines.
+    CubicCurve(Point, Point, Point, Point),
 }
 
-pub struct FlattenedDrawCommands<I: Iterator<Item=Coordinate>> {
-    inner: DrawCommands<I>,
+impl DrawCommand {
+    /// Move every point of the command
+    pub fn transform(self, affine: Affine) -> DrawCommand {
+        match self {
+            DrawCommand::Line(a, b) => DrawCommand::Line(affine * a, affine * b),
+            DrawCommand::Curve(a, b, c) => DrawCommand::Curve(affine * a, affine * b, affine * c),
+            DrawCommand::CubicCurve(a, b, c, d) =>
+                DrawCommand::CubicCurve(affine * a, affine * b, affine * c, affine * d),
+        }
+    }
+}
+
+/// Draw commands as lines. Curves are flattened to `FLATTENING_TOLERANCE`
+/// unless given another tolerance, so commands should be in pixels.
+pub struct FlattenedDrawCommands<I: Iterator<Item=DrawCommand>> {
+    inner: I,
+    tolerance: f32,
     current_curve: Option<CurveLines>,
+    current_cubic: Option<CubicCurveLines>,
 }
-impl<I: Iterator<Item = Coordinate>> FlattenedDrawCommands<I> {
-    pub fn from_coordinates(coords: I) -> FlattenedDrawCommands<I> {
+impl<I: Iterator<Item = Coordinate>> FlattenedDrawCommands<DrawCommands<I>> {
+    pub fn from_coordinates(coords: I) -> FlattenedDrawCommands<DrawCommands<I>> {
+        FlattenedDrawCommands::from_commands(DrawCommands::from_coordinates(coords))
+    }
+}
+impl<I: Iterator<Item = DrawCommand>> FlattenedDrawCommands<I> {
+    pub fn from_commands(commands: I) -> FlattenedDrawCommands<I> {
+        FlattenedDrawCommands::with_tolerance(commands, FLATTENING_TOLERANCE)
+    }
+
+    /// Flatten curves to within `tolerance` of the real ones
+    pub fn with_tolerance(commands: I, tolerance: f32) -> FlattenedDrawCommands<I> {
         FlattenedDrawCommands {
-            inner: DrawCommands::from_coordinates(coords),
+            inner: commands,
+            tolerance,
             current_curve: None,
+            current_cubic: None,
         }
     }
 }
-impl<I: Iterator<Item=Coordinate>> Iterator for FlattenedDrawCommands<I> {
+impl<I: Iterator<Item=DrawCommand>> Iterator for FlattenedDrawCommands<I> {
     type Item = (Point, Point);
     fn next(&mut self) -> Option<Self::Item> {
         let curve_line = self.current_curve.as_mut().and_then(|inner| inner.next());
@@ -759,16 +1217,28 @@ impl<I: Iterator<Item=Coordinate>> Iterator for FlattenedDrawCommands<I> {
         } else {
             self.current_curve = None;
         }
+        let cubic_line = self.current_cubic.as_mut().and_then(|inner| inner.next());
+        if cubic_line.is_some() {
+            return cubic_line;
+        } else {
+            self.current_cubic = None;
+        }
         let dc = self.inner.next()?;
 
         match dc {
             DrawCommand::Line(start, end) => Some((start, end)),
             DrawCommand::Curve(start, off_curve, end) => {
-                let mut curve = CurveLines::new(start, off_curve, end);
+                let mut curve = CurveLines::new(start, off_curve, end, self.tolerance);
                 let segment = curve.next();
                 self.current_curve = Some(curve);
                 segment
             }
+            DrawCommand::CubicCurve(start, control1, control2, end) => {
+                let mut curve = CubicCurveLines::new(start, control1, control2, end, self.tolerance);
+                let segment = curve.next();
+                self.current_cubic = Some(curve);
+                segment
+            }
         }
     }
 }
@@ -827,6 +1297,224 @@ mod tests {
         }
     }
 
+    #[test]
+    fn coverage_raster() {
+        let polygon = |raster: &mut CoverageRaster, points: &[(f32, f32)]| {
+            for idx in 0..points.len() {
+                raster.add_line(points[idx].into(), points[(idx + 1) % points.len()].into());
+            }
+        };
+
+        // Half-covered columns on either side
+        let mut raster = CoverageRaster::new(4, 2);
+        polygon(&mut raster, &[(0.5, 0.), (2.5, 0.), (2.5, 2.), (0.5, 2.)]);
+        assert_eq!(raster.coverage(), vec![0.5, 1., 0.5, 0., 0.5, 1., 0.5, 0.]);
+
+        // Wound the other way, with a diagonal across two pixels
+        let mut raster = CoverageRaster::new(3, 1);
+        polygon(&mut raster, &[(0., 0.), (0., 1.), (2., 1.), (0., 0.)]);
+        assert_eq!(raster.coverage(), vec![0.75, 0.25, 0.]);
+
+        // Clipped on every side, so the left edge carries on
+        let mut raster = CoverageRaster::new(2, 2);
+        polygon(&mut raster, &[(-3., -1.), (1.5, -1.), (1.5, 1.5), (-3., 1.5)]);
+        assert_eq!(raster.coverage(), vec![1., 0.5, 0.5, 0.25]);
+
+        // Overlapping contours don't cover more than the whole pixel
+        let mut raster = CoverageRaster::new(1, 1);
+        polygon(&mut raster, &[(0., 0.), (1., 0.), (1., 1.), (0., 1.)]);
+        polygon(&mut raster, &[(0., 0.), (1., 0.), (1., 1.), (0., 1.)]);
+        let image = raster.into_dynamic().to_luma();
+        assert_eq!(image.into_raw(), vec![255]);
+    }
+
+    #[test]
+    fn fill_rules() {
+        /// A square in a square, both going the same way
+        fn nested<R: Raster>(raster: &mut R) {
+            for &(min, max) in &[(0., 3.), (1., 2.)] {
+                let corners = [(min, min), (max, min), (max, max), (min, max)];
+                for idx in 0..4 {
+                    raster.add_line(corners[idx].into(), corners[(idx + 1) % 4].into());
+                }
+            }
+        }
+        let middle_row = |image: DynamicImage| image.to_luma().into_raw()[3..6].to_vec();
+
+        for &(fill_rule, middle) in &[(FillRule::NonZero, 255), (FillRule::EvenOdd, 0)] {
+            let mut raster = FillInRaster::with_fill_rule(3, 3, fill_rule);
+            nested(&mut raster);
+            assert_eq!(middle_row(raster.into_dynamic()), vec![255, middle, 255]);
+
+            let mut raster = CoverageRaster::with_fill_rule(3, 3, fill_rule);
+            nested(&mut raster);
+            assert_eq!(middle_row(raster.into_dynamic()), vec![255, middle, 255]);
+        }
+
+        // Partly covered by both squares
+        let mut raster = CoverageRaster::with_fill_rule(1, 1, FillRule::EvenOdd);
+        for &(min, max) in &[(0., 1.), (0.5, 1.)] {
+            let corners = [(min, 0.), (max, 0.), (max, 1.), (min, 1.)];
+            for idx in 0..4 {
+                raster.add_line(corners[idx].into(), corners[(idx + 1) % 4].into());
+            }
+        }
+        assert_eq!(raster.coverage(), vec![0.5]);
+    }
+
+    #[test]
+    fn dropout_control() {
+        /// Rectangles from `(x_min, y_min)` to `(x_max, y_max)`, as 0 or 1
+        /// per pixel
+        fn render(width: u32, height: u32, mode: DropoutMode, rects: &[(f32, f32, f32, f32)]) -> Vec<u8> {
+            let mut raster = FillInRaster::with_dropout_mode(width, height, FillRule::NonZero, mode);
+            for &(x_min, y_min, x_max, y_max) in rects {
+                let corners = [(x_min, y_min), (x_max, y_min), (x_max, y_max), (x_min, y_max)];
+                for idx in 0..4 {
+                    raster.add_line(corners[idx].into(), corners[(idx + 1) % 4].into());
+                }
+            }
+            raster.into_dynamic().to_luma().into_raw().into_iter().map(|pixel| pixel / 255).collect()
+        }
+
+        // A stem between the centers of columns 1 and 2, nearer 2
+        let stem = [(2.05, 0., 2.45, 3.)];
+        assert_eq!(render(4, 3, DropoutMode::Off, &stem), vec![0; 12]);
+        assert_eq!(render(4, 3, DropoutMode::Simple, &stem), vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(render(4, 3, DropoutMode::Smart, &stem), vec![0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0]);
+        // Its ends stop before the scanlines past them
+        assert_eq!(render(4, 3, DropoutMode::SmartNoStubs, &stem), vec![0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+        let tall_stem = [(2.05, -1., 2.45, 4.)];
+        assert_eq!(render(4, 3, DropoutMode::SimpleNoStubs, &tall_stem), vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+
+        // Bars are found along the columns
+        let bar = [(0., 1.05, 4., 1.45)];
+        assert_eq!(render(4, 3, DropoutMode::Simple, &bar), vec![1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(render(4, 3, DropoutMode::Smart, &bar), vec![0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(render(4, 3, DropoutMode::SmartNoStubs, &bar), vec![0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0]);
+
+        // Nothing is added next to a pixel that's already on
+        let touching = [(1.6, 0., 1.9, 1.), (2.4, 0., 3.6, 1.)];
+        assert_eq!(render(4, 1, DropoutMode::Simple, &touching), vec![0, 0, 1, 1]);
+
+        assert_eq!(DropoutMode::from_scan_type(1), DropoutMode::SimpleNoStubs);
+        assert_eq!(DropoutMode::from_scan_type(3), DropoutMode::Off);
+    }
+
+    #[test]
+    fn render_glyph_anti_aliased() {
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let image = font.render_glyph(font.get_glyph('o').unwrap(), 20, [0., 0.]);
+        assert!(image.pixels().any(|pixel| pixel.data[0] == 0));
+        assert!(image.pixels().any(|pixel| pixel.data[0] == 255));
+        assert!(image.pixels().any(|pixel| pixel.data[0] > 0 && pixel.data[0] < 255));
+        // The counter winds the other way, so either rule leaves it empty
+        let even_odd = font.render_glyph_with_fill_rule(font.get_glyph('o').unwrap(), 20, [0., 0.], FillRule::EvenOdd);
+        assert_eq!(even_odd.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn subpixel_offsets() {
+        use tables::head::Head;
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let head: Head = font.get_table().unwrap();
+        let scale = 20. / head.units_per_em as f32;
+        let glyph = || font.get_glyph('l').unwrap();
+        let (x_min, y_min) = (glyph().header.x_min as f32 * scale, glyph().header.y_min as f32 * scale);
+        // From the origin, the left edge of the stem and the top of the
+        // glyph above it, counting partly covered pixels
+        let edges = |offset: [f32; 2]| {
+            let image = font.render_glyph(glyph(), 20, offset);
+            let coverage = |x: u32, y: u32| image.get_pixel(x, y).data[0] as f32 / 255.;
+            let row = image.height() / 2;
+            let first = (0..image.width()).find(|&x| coverage(x, row) > 0.).unwrap();
+            let column = first + 1;
+            let last = (0..image.height()).rev().find(|&y| coverage(column, y) > 0.).unwrap();
+            [(x_min + offset[0]).floor() + first as f32 + 1. - coverage(first, row),
+             (y_min + offset[1]).floor() + last as f32 + coverage(column, last)]
+        };
+
+        let start = edges([0., 0.]);
+        for &offset in &[[0.5, 0.], [0.25, 0.75], [1.75, -0.5]] {
+            let moved = edges(offset);
+            assert!((moved[0] - start[0] - offset[0]).abs() < 0.02, "{:?} to {:?}", start, moved);
+            assert!((moved[1] - start[1] - offset[1]).abs() < 0.02, "{:?} to {:?}", start, moved);
+        }
+        // Whole pixels only move where the image goes
+        let image = font.render_glyph(glyph(), 20, [0.5, 0.]).into_raw();
+        assert_eq!(font.render_glyph(glyph(), 20, [2.5, -3.]).into_raw(), image);
+    }
+
+    #[test]
+    fn compositor_keeps_fractions() {
+        use tables::head::Head;
+        use self::compositor::*;
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let head: Head = font.get_table().unwrap();
+        let mut text = RenderedText::new_left_to_right(font.text_render_metrics().unwrap(), 13, head.units_per_em);
+
+        // Each advance is 1233 / 2048 * 13 = 7.83 pixels
+        let mut offsets = Vec::new();
+        for _ in 0..4 {
+            let glyph_id = font.get_glyph_id('i').unwrap();
+            let mut metrics = font.placement_metrics_for_glyph_id(glyph_id, 13).unwrap();
+            metrics.horiz_advance = font.advance_width(glyph_id);
+            let offset = text.subpixel_offset(&metrics);
+            offsets.push(offset[0]);
+            assert_eq!(offset[1], 0.);
+            text.add_glyph(font.render_glyph(font.get_glyph('i').unwrap(), 13, offset), metrics);
+        }
+        assert_eq!(offsets, vec![0., 0.75, 0.75, 0.5]);
+        assert_eq!(text.img.width(), 32);
+    }
+
+    #[test]
+    fn flattening_tolerance() {
+        let point = |x, y| Point { x, y };
+        // The distance from `p` to the nearest line
+        let distance = |lines: &[(Point, Point)], p: Point| lines.iter()
+            .map(|&(a, b)| {
+                let (dx, dy) = (b.x - a.x, b.y - a.y);
+                let t = (((p.x - a.x) * dx + (p.y - a.y) * dy) / (dx * dx + dy * dy)).max(0.).min(1.);
+                p.distance_to(a.lerp_to(b, t))
+            })
+            .fold(::std::f32::INFINITY, f32::min);
+
+        let quad = DrawCommand::Curve(point(0., 0.), point(500., 500.), point(1000., 0.));
+        let cubic = DrawCommand::CubicCurve(point(0., 0.), point(0., 800.), point(300., -400.), point(1000., 0.));
+        for &command in &[quad, cubic] {
+            let lines: Vec<_> = FlattenedDrawCommands::from_commands(Some(command).into_iter()).collect();
+            assert!(lines.len() <= 128, "{} lines", lines.len());
+            assert_eq!(lines[0].0, point(0., 0.));
+            assert_eq!(lines[lines.len() - 1].1, point(1000., 0.));
+            for idx in 0..=1000 {
+                let t = idx as f32 / 1000.;
+                let on_curve = match command {
+                    DrawCommand::Curve(a, b, c) => a.lerp_to(b, t).lerp_to(b.lerp_to(c, t), t),
+                    DrawCommand::CubicCurve(a, b, c, d) => {
+                        let (ab, bc, cd) = (a.lerp_to(b, t), b.lerp_to(c, t), c.lerp_to(d, t));
+                        ab.lerp_to(bc, t).lerp_to(bc.lerp_to(cd, t), t)
+                    },
+                    _ => unreachable!(),
+                };
+                assert!(distance(&lines, on_curve) <= FLATTENING_TOLERANCE + 1e-3);
+            }
+        }
+
+        // Small curves need fewer lines, and straight ones only one
+        let count = |scale: f32| FlattenedDrawCommands::from_commands(Some(quad.transform(Affine::scale(scale, scale))).into_iter()).count();
+        assert!(count(0.01) < count(0.1) && count(0.1) < count(1.));
+        assert_eq!(count(0.001), 1);
+        let straight = DrawCommand::CubicCurve(point(0., 0.), point(10., 10.), point(20., 20.), point(30., 30.));
+        assert_eq!(FlattenedDrawCommands::from_commands(Some(straight).into_iter()).count(), 1);
+        assert!(FlattenedDrawCommands::with_tolerance(Some(quad).into_iter(), 0.01).count() > count(1.));
+    }
+
     #[test]
     fn curve_iterator() {
         let expecteds = &[
@@ -843,7 +1531,7 @@ mod tests {
         let p2 = (4., 4.).into();
         let off = (0., 2.).into();
 
-        let curve = CurveLines::new(p1, off, p2);
+        let curve = CurveLines::new(p1, off, p2, 0.05);
         for (actual, &expected) in curve.zip(expecteds) {
             assert_eq!(actual, expected);
         }
@@ -0,0 +1,782 @@
+// Glyph processing with Apple's `morx` and `kerx` tables, for fonts that
+// don't have `GSUB` and `GPOS`.
+// https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6morx.html
+
+use render::compositor::TextDirection;
+use tables::aat::{Entry, StateTable, CLASS_END_OF_TEXT, DELETED_GLYPH, DONT_ADVANCE};
+use tables::ankr::Ankr;
+use tables::gdef::GDEF;
+use tables::kerx::{self, Kerx};
+use tables::morx::{self, ContextualSubtable, InsertionSubtable, LigatureSubtable, Morx,
+                   SubtableKind};
+use super::Feature;
+use super::buffer::{AttachKind, Attachment, Buffer};
+
+/// OpenType features and the AAT (feature type, on setting, off setting)
+/// they correspond to
+const FEATURE_MAPPINGS: &[(&[u8; 4], u16, u16, u16)] = &[
+    (b"afrc", 11, 1, 0),
+    (b"c2pc", 38, 2, 0),
+    (b"c2sc", 38, 1, 0),
+    (b"calt", 36, 0, 1),
+    (b"case", 33, 0, 1),
+    (b"clig", 1, 18, 19),
+    (b"cpsp", 33, 2, 3),
+    (b"dlig", 1, 4, 5),
+    (b"frac", 11, 2, 0),
+    (b"hlig", 1, 20, 21),
+    (b"liga", 1, 2, 3),
+    (b"lnum", 21, 1, 2),
+    (b"onum", 21, 0, 2),
+    (b"ordn", 10, 3, 0),
+    (b"pcap", 37, 2, 0),
+    (b"pnum", 6, 1, 4),
+    (b"rlig", 1, 0, 1),
+    (b"smcp", 37, 1, 0),
+    (b"subs", 10, 2, 0),
+    (b"sups", 10, 1, 0),
+    (b"swsh", 36, 2, 3),
+    (b"tnum", 6, 0, 4),
+    (b"zero", 14, 4, 5),
+];
+
+/// AAT feature type for stylistic sets, `ssNN` is setting `2 * NN`
+const STYLISTIC_ALTERNATIVES: u16 = 35;
+
+/// The AAT (feature type, setting) for a feature the user turned on or off
+fn aat_setting(feature: &Feature) -> Option<(u16, u16)> {
+    let tag = feature.tag.0;
+    if &tag[..2] == b"ss" {
+        let set = ((tag[2] as char).to_digit(10)? * 10 + (tag[3] as char).to_digit(10)?) as u16;
+        let on = 2 * set;
+        return Some((STYLISTIC_ALTERNATIVES, if feature.value != 0 { on } else { on + 1 }));
+    }
+    FEATURE_MAPPINGS.iter()
+        .find(|&&(ot_tag, _, _, _)| ot_tag == &tag)
+        .map(|&(_, feature_type, on, off)| {
+            (feature_type, if feature.value != 0 { on } else { off })
+        })
+}
+
+/// Something that runs as a state machine reads the glyphs
+trait Driver {
+    /// Number of 16-bit values in each entry after the new state and flags
+    const DATA_LEN: usize;
+
+    /// Act on an entry. `idx` is the current glyph, which may be one past
+    /// the end of the buffer for the end of text. Glyphs can be inserted, in
+    /// which case `idx` is moved to keep pointing at the right glyph.
+    fn transition(&mut self, buffer: &mut Buffer, idx: &mut usize, entry: &Entry);
+}
+
+fn drive<D: Driver>(machine: &StateTable, driver: &mut D, buffer: &mut Buffer) {
+    let mut state = 0;
+    let mut idx = 0;
+    // Broken fonts could loop forever without advancing
+    let mut ops_left = buffer.len() * 16 + 64;
+    loop {
+        let class = if idx < buffer.len() {
+            machine.class(buffer.info[idx].glyph_id)
+        } else {
+            CLASS_END_OF_TEXT
+        };
+        let entry = machine.entry(state, class, D::DATA_LEN);
+        driver.transition(buffer, &mut idx, &entry);
+        state = entry.new_state;
+
+        if idx >= buffer.len() {
+            break;
+        }
+        if entry.flags & DONT_ADVANCE == 0 || ops_left == 0 {
+            idx += 1;
+        } else {
+            ops_left -= 1;
+        }
+    }
+}
+
+/// Reverse the buffer, keeping attachments pointing at the same glyphs
+fn reverse(buffer: &mut Buffer) {
+    buffer.reverse();
+    let last = buffer.len().saturating_sub(1);
+    for pos in &mut buffer.pos {
+        if let Some(ref mut attach) = pos.attach {
+            attach.to = last - attach.to;
+        }
+    }
+}
+
+/// Run every `morx` chain over the glyphs
+pub(crate) fn substitute<'a>(morx: &Morx<'a>, gdef: Option<&GDEF<'a>>, features: &[Feature],
+                             buffer: &mut Buffer) {
+    // Chain flags can't be changed for part of the text
+    let settings: Vec<(u16, u16)> = features.iter()
+        .filter(|feature| feature.start == 0 && feature.end == ::std::usize::MAX)
+        .filter_map(aat_setting)
+        .collect();
+
+    for chain in morx.chains() {
+        let mut flags = chain.default_flags;
+        for &setting in &settings {
+            for entry in chain.features() {
+                if (entry.feature_type, entry.feature_setting) == setting {
+                    flags = (flags & entry.disable_flags) | entry.enable_flags;
+                }
+            }
+        }
+
+        for subtable in chain.subtables() {
+            if subtable.sub_feature_flags & flags == 0 {
+                continue;
+            }
+            if subtable.coverage & morx::COVERAGE_VERTICAL != 0 &&
+                subtable.coverage & morx::COVERAGE_ANY_ORIENTATION == 0 {
+                continue;
+            }
+
+            // Subtables run in layout order unless they say otherwise
+            let descending = subtable.coverage & morx::COVERAGE_DESCENDING != 0;
+            let reversed = if subtable.coverage & morx::COVERAGE_LOGICAL != 0 {
+                descending
+            } else {
+                descending != (buffer.direction == TextDirection::Left)
+            };
+            if reversed {
+                buffer.reverse();
+            }
+
+            match subtable.kind {
+                SubtableKind::Rearrangement(machine) => {
+                    drive(&machine, &mut RearrangementDriver { start: 0, end: 0 }, buffer);
+                },
+                SubtableKind::Contextual(table) => {
+                    let mut driver = ContextualDriver { table: &table, gdef, mark: None };
+                    drive(&table.machine, &mut driver, buffer);
+                },
+                SubtableKind::Ligature(table) => {
+                    let mut driver = LigatureDriver { table: &table, gdef, stack: Vec::new() };
+                    drive(&table.machine, &mut driver, buffer);
+                },
+                SubtableKind::Noncontextual(lookup) => {
+                    for info in &mut buffer.info {
+                        if info.glyph_id == DELETED_GLYPH {
+                            continue;
+                        }
+                        if let Some(glyph_id) = lookup.value_u16(info.glyph_id) {
+                            info.set_glyph(glyph_id, gdef);
+                        }
+                    }
+                },
+                SubtableKind::Insertion(table) => {
+                    let mut driver = InsertionDriver { table: &table, gdef, mark: None };
+                    drive(&table.machine, &mut driver, buffer);
+                },
+                SubtableKind::Unknown => (),
+            }
+
+            if reversed {
+                buffer.reverse();
+            }
+        }
+    }
+
+    buffer.info.retain(|info| info.glyph_id != DELETED_GLYPH);
+}
+
+struct RearrangementDriver {
+    start: usize,
+    end: usize,
+}
+
+const MARK_FIRST: u16 = 0x8000;
+const MARK_LAST: u16 = 0x2000;
+const VERB: u16 = 0x000F;
+
+impl Driver for RearrangementDriver {
+    const DATA_LEN: usize = 0;
+
+    fn transition(&mut self, buffer: &mut Buffer, idx: &mut usize, entry: &Entry) {
+        if entry.flags & MARK_FIRST != 0 {
+            self.start = *idx;
+        }
+        if entry.flags & MARK_LAST != 0 {
+            self.end = (*idx + 1).min(buffer.len());
+        }
+
+        let verb = entry.flags & VERB;
+        if verb == 0 || self.start >= self.end {
+            return;
+        }
+        // (glyphs from the start, glyphs from the end) that swap places.
+        // 3 means two glyphs that are also reversed.
+        let (left, right) = match verb {
+            1 => (1, 0),
+            2 => (0, 1),
+            3 => (1, 1),
+            4 => (2, 0),
+            5 => (3, 0),
+            6 => (0, 2),
+            7 => (0, 3),
+            8 => (1, 2),
+            9 => (1, 3),
+            10 => (2, 1),
+            11 => (3, 1),
+            12 => (2, 2),
+            13 => (3, 2),
+            14 => (2, 3),
+            _ => (3, 3),
+        };
+        let left_len = if left == 3 { 2 } else { left };
+        let right_len = if right == 3 { 2 } else { right };
+        let (start, end) = (self.start, self.end);
+        if end - start < left_len + right_len {
+            return;
+        }
+
+        buffer.merge_clusters(start, end);
+        let mut left_glyphs: Vec<_> = buffer.info[start..(start + left_len)].to_vec();
+        let mut right_glyphs: Vec<_> = buffer.info[(end - right_len)..end].to_vec();
+        if left == 3 {
+            left_glyphs.reverse();
+        }
+        if right == 3 {
+            right_glyphs.reverse();
+        }
+        let middle: Vec<_> = buffer.info[(start + left_len)..(end - right_len)].to_vec();
+        let rearranged = right_glyphs.into_iter()
+            .chain(middle.into_iter())
+            .chain(left_glyphs.into_iter());
+        for (info, new_info) in buffer.info[start..end].iter_mut().zip(rearranged) {
+            *info = new_info;
+        }
+    }
+}
+
+struct ContextualDriver<'a: 'b, 'b> {
+    table: &'b ContextualSubtable<'a>,
+    gdef: Option<&'b GDEF<'a>>,
+    mark: Option<usize>,
+}
+
+const SET_MARK: u16 = 0x8000;
+
+impl<'a, 'b> Driver for ContextualDriver<'a, 'b> {
+    const DATA_LEN: usize = 2;
+
+    fn transition(&mut self, buffer: &mut Buffer, idx: &mut usize, entry: &Entry) {
+        let mark_index = entry.data(0);
+        let current_index = entry.data(1);
+
+        if let Some(mark) = self.mark {
+            if mark_index != 0xFFFF && mark < buffer.len() {
+                let glyph_id = buffer.info[mark].glyph_id;
+                if let Some(glyph_id) = self.table.substitute(mark_index, glyph_id) {
+                    buffer.info[mark].set_glyph(glyph_id, self.gdef);
+                }
+            }
+        }
+        if current_index != 0xFFFF && buffer.len() > 0 {
+            // At the end of text this changes the last glyph
+            let current = (*idx).min(buffer.len() - 1);
+            let glyph_id = buffer.info[current].glyph_id;
+            if let Some(glyph_id) = self.table.substitute(current_index, glyph_id) {
+                buffer.info[current].set_glyph(glyph_id, self.gdef);
+            }
+        }
+        if entry.flags & SET_MARK != 0 {
+            self.mark = Some(*idx);
+        }
+    }
+}
+
+struct LigatureDriver<'a: 'b, 'b> {
+    table: &'b LigatureSubtable<'a>,
+    gdef: Option<&'b GDEF<'a>>,
+    /// Indices of the components matched so far
+    stack: Vec<usize>,
+}
+
+const SET_COMPONENT: u16 = 0x8000;
+const PERFORM_ACTION: u16 = 0x2000;
+const MAX_COMPONENTS: usize = 64;
+
+impl<'a, 'b> Driver for LigatureDriver<'a, 'b> {
+    const DATA_LEN: usize = 1;
+
+    fn transition(&mut self, buffer: &mut Buffer, idx: &mut usize, entry: &Entry) {
+        if entry.flags & SET_COMPONENT != 0 {
+            // The same glyph is seen again when the entry doesn't advance
+            if self.stack.last() == Some(&*idx) {
+                self.stack.pop();
+            }
+            self.stack.push(*idx);
+            if self.stack.len() > MAX_COMPONENTS {
+                self.stack.remove(0);
+            }
+        }
+
+        if entry.flags & PERFORM_ACTION == 0 || self.stack.is_empty() {
+            return;
+        }
+
+        let mut action_idx = entry.data(0) as usize;
+        let mut cursor = self.stack.len();
+        let mut ligature_idx: u32 = 0;
+        loop {
+            if cursor == 0 {
+                self.stack.clear();
+                break;
+            }
+            cursor -= 1;
+            let pos = self.stack[cursor];
+            if pos >= buffer.len() {
+                break;
+            }
+
+            let action = self.table.action(action_idx);
+            // The offset is a signed 30 bit number
+            let mut offset = action & 0x3FFF_FFFF;
+            if offset & 0x2000_0000 != 0 {
+                offset |= 0xC000_0000;
+            }
+            let component_idx = (buffer.info[pos].glyph_id as i32).wrapping_add(offset as i32);
+            if component_idx < 0 {
+                break;
+            }
+            ligature_idx += self.table.component(component_idx as usize) as u32;
+
+            if action & (morx::LIG_ACTION_STORE | morx::LIG_ACTION_LAST) != 0 {
+                let ligature = self.table.ligature(ligature_idx as usize);
+                buffer.info[pos].set_glyph(ligature, self.gdef);
+                let lig_end = self.stack[self.stack.len() - 1] + 1;
+                // The rest of the components are deleted, leaving the
+                // ligature on the stack so it can be part of another one
+                while self.stack.len() - 1 > cursor {
+                    let component = self.stack.pop().unwrap();
+                    if component < buffer.len() {
+                        buffer.info[component].glyph_id = DELETED_GLYPH;
+                    }
+                }
+                buffer.merge_clusters(pos, lig_end);
+            }
+
+            action_idx += 1;
+            if action & morx::LIG_ACTION_LAST != 0 {
+                break;
+            }
+        }
+    }
+}
+
+struct InsertionDriver<'a: 'b, 'b> {
+    table: &'b InsertionSubtable<'a>,
+    gdef: Option<&'b GDEF<'a>>,
+    mark: Option<usize>,
+}
+
+const CURRENT_INSERT_BEFORE: u16 = 0x0800;
+const MARKED_INSERT_BEFORE: u16 = 0x0400;
+const CURRENT_INSERT_COUNT: u16 = 0x03E0;
+const MARKED_INSERT_COUNT: u16 = 0x001F;
+
+impl<'a, 'b> InsertionDriver<'a, 'b> {
+    /// Insert copies of the glyph at `template` with new glyph ids at `at`
+    fn insert(&self, buffer: &mut Buffer, template: usize, at: usize, glyphs: Vec<u16>) {
+        let template = buffer.info[template].clone();
+        for (i, glyph_id) in glyphs.into_iter().enumerate() {
+            let mut info = template.clone();
+            info.set_glyph(glyph_id, self.gdef);
+            buffer.info.insert(at + i, info);
+        }
+    }
+}
+
+impl<'a, 'b> Driver for InsertionDriver<'a, 'b> {
+    const DATA_LEN: usize = 2;
+
+    fn transition(&mut self, buffer: &mut Buffer, idx: &mut usize, entry: &Entry) {
+        let current_index = entry.data(0);
+        let marked_index = entry.data(1);
+
+        if let Some(mark) = self.mark {
+            if marked_index != 0xFFFF && mark < buffer.len() {
+                let count = (entry.flags & MARKED_INSERT_COUNT) as usize;
+                let glyphs = self.table.glyphs(marked_index, count);
+                let at = if entry.flags & MARKED_INSERT_BEFORE != 0 { mark } else { mark + 1 };
+                self.insert(buffer, mark, at, glyphs);
+                if at <= *idx {
+                    *idx += count;
+                }
+            }
+        }
+
+        if current_index != 0xFFFF && buffer.len() > 0 {
+            let count = ((entry.flags & CURRENT_INSERT_COUNT) >> 5) as usize;
+            let glyphs = self.table.glyphs(current_index, count);
+            let before = entry.flags & CURRENT_INSERT_BEFORE != 0;
+            let at = if before || *idx >= buffer.len() { *idx } else { *idx + 1 };
+            let template = (*idx).min(buffer.len() - 1);
+            self.insert(buffer, template, at, glyphs);
+            // Without DontAdvance the inserted glyphs are skipped over,
+            // otherwise the next glyph processed is the first one after the
+            // insertion point
+            if entry.flags & DONT_ADVANCE == 0 {
+                *idx += count;
+            }
+        }
+
+        if entry.flags & SET_MARK != 0 {
+            self.mark = Some(*idx);
+        }
+    }
+}
+
+/// Apply every horizontal `kerx` subtable
+pub(crate) fn position<'a>(kerx: &Kerx<'a>, ankr: Option<&Ankr<'a>>, buffer: &mut Buffer) {
+    // Kerning is between glyphs in visual order
+    let reversed = buffer.direction == TextDirection::Left;
+    if reversed {
+        reverse(buffer);
+    }
+
+    for subtable in kerx.subtables() {
+        if subtable.coverage & (kerx::COVERAGE_VERTICAL | kerx::COVERAGE_VARIATION) != 0 {
+            continue;
+        }
+        let cross_stream = subtable.coverage & kerx::COVERAGE_CROSS_STREAM != 0;
+
+        match subtable.kind {
+            kerx::SubtableKind::Contextual(ref table) => {
+                let mut driver = KerxContextualDriver {
+                    table,
+                    tuple_count: subtable.tuple_count,
+                    cross_stream,
+                    stack: Vec::new(),
+                };
+                drive(&table.machine, &mut driver, buffer);
+            },
+            kerx::SubtableKind::Anchor(ref table) => {
+                let mut driver = AnchorDriver { table, ankr, mark: None };
+                drive(&table.machine, &mut driver, buffer);
+            },
+            _ => kern_pairs(&subtable, cross_stream, buffer),
+        }
+    }
+
+    if reversed {
+        reverse(buffer);
+    }
+}
+
+/// Kern each pair of glyphs next to each other, skipping marks
+fn kern_pairs(subtable: &kerx::Subtable, cross_stream: bool, buffer: &mut Buffer) {
+    let mut left = match buffer.info.iter().position(|info| !info.is_mark()) {
+        Some(left) => left,
+        None => return,
+    };
+    for right in (left + 1)..buffer.len() {
+        if buffer.info[right].is_mark() {
+            continue;
+        }
+        let kerning = subtable.pair_kerning(buffer.info[left].glyph_id, buffer.info[right].glyph_id);
+        match kerning {
+            Some(kerning) if cross_stream => buffer.pos[right].y_offset += kerning,
+            Some(kerning) => buffer.pos[left].x_advance += kerning,
+            None => return,
+        }
+        left = right;
+    }
+}
+
+struct KerxContextualDriver<'a: 'b, 'b> {
+    table: &'b kerx::ContextualSubtable<'a>,
+    tuple_count: u32,
+    cross_stream: bool,
+    /// Glyphs waiting to be kerned
+    stack: Vec<usize>,
+}
+
+const PUSH: u16 = 0x8000;
+const RESET: u16 = 0x2000;
+const MAX_STACK: usize = 8;
+
+impl<'a, 'b> Driver for KerxContextualDriver<'a, 'b> {
+    const DATA_LEN: usize = 1;
+
+    fn transition(&mut self, buffer: &mut Buffer, idx: &mut usize, entry: &Entry) {
+        if entry.flags & RESET != 0 {
+            self.stack.clear();
+        }
+        if entry.flags & PUSH != 0 {
+            if self.stack.len() == MAX_STACK {
+                self.stack.clear();
+            }
+            self.stack.push(*idx);
+        }
+
+        let value_idx = entry.data(0);
+        if value_idx == 0xFFFF {
+            return;
+        }
+        // Values are popped off along with the glyphs they kern, until an
+        // odd value ends the list
+        let stride = self.tuple_count.max(1) as usize;
+        let mut value_idx = value_idx as usize;
+        while let Some(glyph) = self.stack.pop() {
+            let value = self.table.value(value_idx) as i32;
+            value_idx += stride;
+            if glyph >= buffer.len() {
+                continue;
+            }
+            let last = value & 1 != 0;
+            let value = value & !1;
+            let pos = &mut buffer.pos[glyph];
+            if self.cross_stream {
+                // Resets any cross-stream kerning
+                if value == -0x8000 {
+                    pos.y_offset = 0;
+                } else {
+                    pos.y_offset += value;
+                }
+            } else {
+                pos.x_advance += value;
+                pos.x_offset += value;
+            }
+            if last {
+                break;
+            }
+        }
+    }
+}
+
+struct AnchorDriver<'a: 'b, 'b> {
+    table: &'b kerx::AnchorSubtable<'a>,
+    ankr: Option<&'b Ankr<'a>>,
+    mark: Option<usize>,
+}
+
+const MARK: u16 = 0x8000;
+
+impl<'a, 'b> AnchorDriver<'a, 'b> {
+    /// How far to move the current glyph to line it up with the marked one
+    fn offset(&self, buffer: &Buffer, mark: usize, current: usize, action_idx: usize)
+              -> Option<(i32, i32)> {
+        match self.table.action_type()? {
+            // Needs the points of the glyph outlines
+            kerx::AnchorAction::ControlPoints => None,
+            kerx::AnchorAction::AnchorPoints => {
+                let ankr = self.ankr?;
+                let mark_point = self.table.data(action_idx);
+                let current_point = self.table.data(action_idx + 1);
+                let (mark_x, mark_y) = ankr.anchor(buffer.info[mark].glyph_id, mark_point)?;
+                let (x, y) = ankr.anchor(buffer.info[current].glyph_id, current_point)?;
+                Some((mark_x as i32 - x as i32, mark_y as i32 - y as i32))
+            },
+            kerx::AnchorAction::Coordinates => {
+                let value = |i| self.table.data(action_idx + i) as i16 as i32;
+                Some((value(0) - value(2), value(1) - value(3)))
+            },
+        }
+    }
+}
+
+impl<'a, 'b> Driver for AnchorDriver<'a, 'b> {
+    const DATA_LEN: usize = 1;
+
+    fn transition(&mut self, buffer: &mut Buffer, idx: &mut usize, entry: &Entry) {
+        let action_idx = entry.data(0);
+        if let Some(mark) = self.mark {
+            if action_idx != 0xFFFF && mark < buffer.len() && *idx < buffer.len() {
+                if let Some((x, y)) = self.offset(buffer, mark, *idx, action_idx as usize) {
+                    let pos = &mut buffer.pos[*idx];
+                    pos.x_offset = x;
+                    pos.y_offset = y;
+                    pos.attach = Some(Attachment {
+                        to: mark,
+                        kind: AttachKind::Mark,
+                    });
+                }
+            }
+        }
+        if entry.flags & MARK != 0 {
+            self.mark = Some(*idx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::Font;
+    use parse::Parse;
+    use tables::aat::tests::{single_lookup, state_table};
+    use test_utils::{load_font_buf, push_u16, push_u32, ROBOTO};
+
+    /// Wrap (coverage, sub-feature flags, body) subtables in a one chain `morx`
+    fn build_morx(default_flags: u32, features: &[(u16, u16, u32, u32)],
+                  subtables: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let chain_length = 16 + features.len() * 12 +
+            subtables.iter().map(|subtable| 12 + subtable.2.len()).sum::<usize>();
+        let mut buf = Vec::new();
+        push_u16(&mut buf, 2);
+        push_u16(&mut buf, 0);
+        push_u32(&mut buf, 1);
+        push_u32(&mut buf, default_flags);
+        push_u32(&mut buf, chain_length as u32);
+        push_u32(&mut buf, features.len() as u32);
+        push_u32(&mut buf, subtables.len() as u32);
+        for &(feature_type, setting, enable, disable) in features {
+            push_u16(&mut buf, feature_type);
+            push_u16(&mut buf, setting);
+            push_u32(&mut buf, enable);
+            push_u32(&mut buf, disable);
+        }
+        for &(coverage, flags, ref body) in subtables {
+            push_u32(&mut buf, 12 + body.len() as u32);
+            push_u32(&mut buf, coverage);
+            push_u32(&mut buf, flags);
+            buf.extend(body);
+        }
+        buf
+    }
+
+    /// Offsets in a subtable header are u32s after the state table header
+    fn set_offset(buf: &mut Vec<u8>, idx: usize, offset: usize) {
+        let mut bytes = Vec::new();
+        push_u32(&mut bytes, offset as u32);
+        let start = 16 + idx * 4;
+        buf[start..(start + 4)].copy_from_slice(&bytes);
+    }
+
+    fn glyph(font: &Font, ch: char) -> u16 {
+        font.get_glyph_id(ch).unwrap() as u16
+    }
+
+    fn glyph_ids(buffer: &Buffer) -> Vec<u16> {
+        buffer.info.iter().map(|info| info.glyph_id).collect()
+    }
+
+    #[test]
+    fn ligature_and_noncontextual() {
+        let font_buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&font_buf).unwrap();
+        let (f, i, a, b, lig) = (glyph(&font, 'f'), glyph(&font, 'i'), glyph(&font, 'a'),
+                                 glyph(&font, 'b'), glyph(&font, 'z'));
+
+        // Classes 4 and 5 are f and i. State 2 is after an f.
+        let mut ligature = state_table(12, &[(f, 4), (i, 5)], 6, &[
+            &[0, 0, 0, 0, 1, 0],
+            &[0, 0, 0, 0, 1, 0],
+            &[0, 0, 0, 0, 1, 2],
+        ], &[
+            &[0, 0, 0],
+            &[2, SET_COMPONENT, 0],
+            &[0, SET_COMPONENT | PERFORM_ACTION, 0],
+        ]);
+        // The actions pop the i then the f, each picks component 0 and the
+        // ligature is the first one
+        let actions = ligature.len();
+        let to_component = |glyph: u16| (-(glyph as i32) as u32) & 0x3FFF_FFFF;
+        push_u32(&mut ligature, to_component(i));
+        push_u32(&mut ligature, morx::LIG_ACTION_LAST | to_component(f));
+        let components = ligature.len();
+        push_u16(&mut ligature, 0);
+        let ligatures = ligature.len();
+        push_u16(&mut ligature, lig);
+        set_offset(&mut ligature, 0, actions);
+        set_offset(&mut ligature, 1, components);
+        set_offset(&mut ligature, 2, ligatures);
+
+        let noncontextual = single_lookup(&[(a, b)]);
+
+        // Ligatures off clears flag 1
+        let morx_buf = build_morx(0b11, &[(1, 3, 0, !1)], &[
+            (2, 0b01, ligature),
+            (4, 0b10, noncontextual),
+        ]);
+        let morx = Morx::parse(&morx_buf).1;
+
+        let mut buffer = Buffer::from_text(&font, None, "fia", TextDirection::Right);
+        substitute(&morx, None, &[], &mut buffer);
+        assert_eq!(glyph_ids(&buffer), vec![lig, b]);
+        assert_eq!(buffer.info[0].cluster, 0);
+        assert_eq!(buffer.info[1].cluster, 2);
+
+        let mut buffer = Buffer::from_text(&font, None, "fia", TextDirection::Right);
+        substitute(&morx, None, &[Feature::new(b"liga", 0)], &mut buffer);
+        assert_eq!(glyph_ids(&buffer), vec![f, i, b]);
+    }
+
+    #[test]
+    fn rearrangement_and_insertion() {
+        let font_buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&font_buf).unwrap();
+        let (a, b, c) = (glyph(&font, 'a'), glyph(&font, 'b'), glyph(&font, 'c'));
+
+        // Mark the a as the first glyph and the c as the last, then Ax => xA
+        let rearrangement = state_table(0, &[(a, 4), (c, 5)], 6, &[
+            &[0, 0, 0, 0, 1, 0],
+            &[0, 0, 0, 0, 1, 0],
+            &[0, 3, 0, 0, 1, 2],
+        ], &[
+            &[0, 0],
+            &[2, MARK_FIRST],
+            &[0, MARK_LAST | 1],
+            &[2, 0],
+        ]);
+        let morx_buf = build_morx(1, &[], &[(0, 1, rearrangement)]);
+        let morx = Morx::parse(&morx_buf).1;
+        let mut buffer = Buffer::from_text(&font, None, "abc", TextDirection::Right);
+        substitute(&morx, None, &[], &mut buffer);
+        assert_eq!(glyph_ids(&buffer), vec![b, c, a]);
+        assert!(buffer.info.iter().all(|info| info.cluster == 0));
+
+        // Insert a b after every a
+        let mut insertion = state_table(4, &[(a, 4)], 5, &[
+            &[0, 0, 0, 0, 1],
+            &[0, 0, 0, 0, 1],
+        ], &[
+            &[0, 0, 0xFFFF, 0xFFFF],
+            &[0, 1 << 5, 0, 0xFFFF],
+        ]);
+        let actions = insertion.len();
+        push_u16(&mut insertion, b);
+        set_offset(&mut insertion, 0, actions);
+        let morx_buf = build_morx(1, &[], &[(5, 1, insertion)]);
+        let morx = Morx::parse(&morx_buf).1;
+        let mut buffer = Buffer::from_text(&font, None, "aca", TextDirection::Right);
+        substitute(&morx, None, &[], &mut buffer);
+        assert_eq!(glyph_ids(&buffer), vec![a, b, c, a, b]);
+        assert_eq!(buffer.info[1].cluster, 0);
+    }
+
+    #[test]
+    fn kerx_pairs() {
+        let font_buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&font_buf).unwrap();
+        let (a, v) = (glyph(&font, 'A'), glyph(&font, 'V'));
+
+        let mut buf = Vec::new();
+        push_u16(&mut buf, 2);
+        push_u16(&mut buf, 0);
+        push_u32(&mut buf, 1);
+        // Format 0 with one pair
+        push_u32(&mut buf, 12 + 16 + 6);
+        push_u32(&mut buf, 0);
+        push_u32(&mut buf, 0);
+        for &val in &[1, 6, 0, 0] {
+            push_u32(&mut buf, val);
+        }
+        push_u16(&mut buf, a);
+        push_u16(&mut buf, v);
+        push_u16(&mut buf, (-100i16) as u16);
+        let kerx = Kerx::parse(&buf).1;
+
+        let mut buffer = Buffer::from_text(&font, None, "AVA", TextDirection::Right);
+        buffer.init_positions(&font);
+        let advances: Vec<i32> = buffer.pos.iter().map(|pos| pos.x_advance).collect();
+        position(&kerx, None, &mut buffer);
+        assert_eq!(buffer.pos[0].x_advance, advances[0] - 100);
+        assert_eq!(buffer.pos[1].x_advance, advances[1]);
+    }
+}
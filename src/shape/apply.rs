@@ -0,0 +1,588 @@
+use parse::DynArr;
+use render::compositor::TextDirection;
+use tables::gdef::{GDEF, GlyphClass};
+use tables::gpos::{Anchor, MarkAttachPos, PosSubtable, ValueRecord};
+use tables::gsub::{Ligature, SubstSubtable};
+use tables::layout::{ContextMatch, Coverage, Lookup, LookupFlag, LookupList,
+                     SequenceContext, SequenceLookupRecord};
+use super::buffer::{AttachKind, Attachment, Buffer, GlyphInfo};
+use super::plan::LookupMap;
+
+/// Contextual lookups can call other lookups, this stops runaway recursion
+const MAX_NESTING_LEVEL: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TableKind {
+    Subst,
+    Pos,
+}
+
+enum Subtable<'a> {
+    Subst(SubstSubtable<'a>),
+    Pos(PosSubtable<'a>),
+}
+
+/// The parts of a lookup that decide which glyphs it sees
+#[derive(Clone, Copy)]
+struct LookupProps {
+    flag: LookupFlag,
+    mark_filtering_set: Option<u16>,
+}
+
+impl LookupProps {
+    fn of(lookup: &Lookup) -> LookupProps {
+        LookupProps {
+            flag: lookup.lookup_flag,
+            mark_filtering_set: lookup.mark_filtering_set(),
+        }
+    }
+}
+
+/// Which part of a contextual rule a glyph is being matched against
+#[derive(Clone, Copy, PartialEq)]
+enum Part {
+    Backtrack,
+    Input,
+    Lookahead,
+}
+
+/// Applies the lookups of a GSUB or GPOS table to a buffer
+pub(crate) struct ApplyContext<'b, 'a: 'b> {
+    kind: TableKind,
+    lookups: LookupList<'a>,
+    gdef: Option<&'b GDEF<'a>>,
+    buffer: &'b mut Buffer,
+    lookup_mask: u32,
+    lookup_value: u32,
+    nesting_level: u8,
+}
+
+impl<'b, 'a: 'b> ApplyContext<'b, 'a> {
+    pub fn new(kind: TableKind, lookups: LookupList<'a>, gdef: Option<&'b GDEF<'a>>,
+               buffer: &'b mut Buffer) -> ApplyContext<'b, 'a> {
+        ApplyContext {
+            kind,
+            lookups,
+            gdef,
+            buffer,
+            lookup_mask: 0,
+            lookup_value: 0,
+            nesting_level: 0,
+        }
+    }
+
+    fn subtables(&self, lookup: &Lookup<'a>) -> Vec<Subtable<'a>> {
+        let lookup_type = lookup.lookup_type;
+        let kind = self.kind;
+        lookup.subtables()
+            .filter_map(|subtable| match kind {
+                TableKind::Subst => SubstSubtable::new(lookup_type, subtable).map(Subtable::Subst),
+                TableKind::Pos => PosSubtable::new(lookup_type, subtable).map(Subtable::Pos),
+            })
+            .collect()
+    }
+
+    /// Apply a lookup to every glyph of the buffer that has the lookup's mask
+    pub fn apply_lookup(&mut self, lookup_map: &LookupMap) {
+        let lookup = match self.lookups.lookup(lookup_map.index) {
+            Some(lookup) => lookup,
+            None => return,
+        };
+        self.lookup_mask = lookup_map.mask;
+        self.lookup_value = lookup_map.value;
+        let props = LookupProps::of(&lookup);
+        let subtables = self.subtables(&lookup);
+
+        let is_reverse = subtables.iter().any(|subtable| match subtable {
+            Subtable::Subst(SubstSubtable::ReverseChainSingle(_)) => true,
+            _ => false,
+        });
+        if is_reverse {
+            // Reverse chaining lookups go from the end of the buffer to the start
+            let mut idx = self.buffer.len();
+            while idx > 0 {
+                idx -= 1;
+                if self.should_apply_at(idx, props) {
+                    self.apply_subtables_at(&subtables, idx, props);
+                }
+            }
+            return;
+        }
+
+        let mut idx = 0;
+        while idx < self.buffer.len() {
+            if self.should_apply_at(idx, props) {
+                let len_before = self.buffer.len();
+                if let Some(next) = self.apply_subtables_at(&subtables, idx, props) {
+                    // Make sure we always make progress
+                    idx = if next > idx || self.buffer.len() < len_before { next } else { idx + 1 };
+                    continue;
+                }
+            }
+            idx += 1;
+        }
+    }
+
+    /// Apply a lookup once at a particular position, for contextual lookups
+    fn apply_lookup_at(&mut self, lookup_index: u16, idx: usize) -> bool {
+        if self.nesting_level >= MAX_NESTING_LEVEL || idx >= self.buffer.len() {
+            return false;
+        }
+        let lookup = match self.lookups.lookup(lookup_index) {
+            Some(lookup) => lookup,
+            None => return false,
+        };
+        let props = LookupProps::of(&lookup);
+        if self.should_skip(&self.buffer.info[idx], props) {
+            return false;
+        }
+        let subtables = self.subtables(&lookup);
+
+        self.nesting_level += 1;
+        let applied = self.apply_subtables_at(&subtables, idx, props).is_some();
+        self.nesting_level -= 1;
+        applied
+    }
+
+    /// Returns the index to continue from if a subtable applied
+    fn apply_subtables_at(&mut self, subtables: &[Subtable<'a>], idx: usize,
+                          props: LookupProps) -> Option<usize> {
+        for subtable in subtables {
+            let next = match subtable {
+                Subtable::Subst(subtable) => self.apply_subst(subtable, idx, props),
+                Subtable::Pos(subtable) => self.apply_pos(subtable, idx, props),
+            };
+            if next.is_some() {
+                return next;
+            }
+        }
+        None
+    }
+
+    fn should_apply_at(&self, idx: usize, props: LookupProps) -> bool {
+        let info = &self.buffer.info[idx];
+        info.mask & self.lookup_mask != 0 && !self.should_skip(info, props)
+    }
+
+    /// Whether the lookup flags say to ignore this glyph
+    fn should_skip(&self, info: &GlyphInfo, props: LookupProps) -> bool {
+        let flag = props.flag;
+        match info.class {
+            GlyphClass::Base => flag.contains(LookupFlag::IGNORE_BASE_GLYPHS),
+            GlyphClass::Ligature => flag.contains(LookupFlag::IGNORE_LIGATURES),
+            GlyphClass::Mark => {
+                if flag.contains(LookupFlag::IGNORE_MARKS) {
+                    return true;
+                }
+                if let Some(set) = props.mark_filtering_set {
+                    return match self.gdef {
+                        Some(gdef) => !gdef.is_in_mark_glyph_set(set, info.glyph_id),
+                        None => false,
+                    };
+                }
+                let attach_type = flag.mark_attachment_type();
+                attach_type != 0 && attach_type != info.mark_attach_class
+            },
+            _ => false,
+        }
+    }
+
+    /// The next glyph after `idx` that the lookup doesn't ignore
+    fn next_glyph(&self, idx: usize, props: LookupProps) -> Option<usize> {
+        ((idx + 1)..self.buffer.len())
+            .find(|&next| !self.should_skip(&self.buffer.info[next], props))
+    }
+
+    /// The closest glyph before `idx` that the lookup doesn't ignore
+    fn prev_glyph(&self, idx: usize, props: LookupProps) -> Option<usize> {
+        (0..idx).rev()
+            .find(|&prev| !self.should_skip(&self.buffer.info[prev], props))
+    }
+
+    fn glyph(&self, idx: usize) -> u16 {
+        self.buffer.info[idx].glyph_id
+    }
+
+    /// Match the glyphs around `idx` against a rule.
+    ///
+    /// `matches` is given the part of the rule, the index into that part, and
+    /// the glyph. The first input glyph (at `idx`) is assumed to already match.
+    ///
+    /// Returns the positions of the input glyphs.
+    fn match_rule<F>(&self, idx: usize, lens: (usize, usize, usize), props: LookupProps,
+                     mut matches: F) -> Option<Vec<usize>>
+        where F: FnMut(Part, usize, u16) -> bool {
+        let (backtrack_len, input_len, lookahead_len) = lens;
+
+        let mut positions = vec![idx];
+        let mut pos = idx;
+        for input_idx in 0..input_len {
+            pos = self.next_glyph(pos, props)?;
+            // Input glyphs all have to be subject to the lookup
+            if self.buffer.info[pos].mask & self.lookup_mask == 0 {
+                return None;
+            }
+            if !matches(Part::Input, input_idx, self.glyph(pos)) {
+                return None;
+            }
+            positions.push(pos);
+        }
+
+        let mut pos = idx;
+        for backtrack_idx in 0..backtrack_len {
+            pos = self.prev_glyph(pos, props)?;
+            if !matches(Part::Backtrack, backtrack_idx, self.glyph(pos)) {
+                return None;
+            }
+        }
+
+        let mut pos = *positions.last().unwrap();
+        for lookahead_idx in 0..lookahead_len {
+            pos = self.next_glyph(pos, props)?;
+            if !matches(Part::Lookahead, lookahead_idx, self.glyph(pos)) {
+                return None;
+            }
+        }
+
+        Some(positions)
+    }
+
+    fn apply_context(&mut self, context: &SequenceContext<'a>, idx: usize,
+                     props: LookupProps) -> Option<usize> {
+        let first = self.glyph(idx);
+        let coverage_idx = context.coverage().index(first)?;
+
+        let (positions, lookups) = match context.match_kind()? {
+            ContextMatch::Coverages { table, backtrack, input, lookahead, lookups } => {
+                let coverage_at = |offsets: &DynArr<'a, u16>, idx: usize| -> Coverage<'a> {
+                    table.at_offset(offsets.at(idx) as usize)
+                };
+                // The first input coverage was already checked
+                let lens = (backtrack.len(), input.len().saturating_sub(1), lookahead.len());
+                let positions = self.match_rule(idx, lens, props, |part, part_idx, glyph| {
+                    let coverage = match part {
+                        Part::Backtrack => coverage_at(&backtrack, part_idx),
+                        Part::Input => coverage_at(&input, part_idx + 1),
+                        Part::Lookahead => coverage_at(&lookahead, part_idx),
+                    };
+                    coverage.contains(glyph)
+                })?;
+                (positions, lookups)
+            },
+            ContextMatch::Glyphs => {
+                let rules = context.rules(coverage_idx, 0);
+                rules.into_iter()
+                    .filter_map(|rule| {
+                        let lens = (rule.backtrack.len(), rule.input.len(), rule.lookahead.len());
+                        self.match_rule(idx, lens, props, |part, part_idx, glyph| {
+                            let expected = match part {
+                                Part::Backtrack => rule.backtrack.at(part_idx),
+                                Part::Input => rule.input.at(part_idx),
+                                Part::Lookahead => rule.lookahead.at(part_idx),
+                            };
+                            expected == glyph
+                        }).map(|positions| (positions, rule.lookups.clone()))
+                    })
+                    .next()?
+            },
+            ContextMatch::Classes { backtrack, input, lookahead } => {
+                let rules = context.rules(coverage_idx, input.class(first));
+                rules.into_iter()
+                    .filter_map(|rule| {
+                        let lens = (rule.backtrack.len(), rule.input.len(), rule.lookahead.len());
+                        self.match_rule(idx, lens, props, |part, part_idx, glyph| {
+                            match part {
+                                Part::Backtrack => rule.backtrack.at(part_idx) == backtrack.class(glyph),
+                                Part::Input => rule.input.at(part_idx) == input.class(glyph),
+                                Part::Lookahead => rule.lookahead.at(part_idx) == lookahead.class(glyph),
+                            }
+                        }).map(|positions| (positions, rule.lookups.clone()))
+                    })
+                    .next()?
+            },
+        };
+
+        Some(self.apply_nested(positions, lookups))
+    }
+
+    /// Apply the nested lookups of a matched contextual rule.
+    ///
+    /// Returns the index after the end of the (possibly changed) input.
+    fn apply_nested(&mut self, mut positions: Vec<usize>,
+                    records: DynArr<'a, SequenceLookupRecord>) -> usize {
+        let mut end = *positions.last().unwrap() as isize + 1;
+        for record in records {
+            let seq_idx = record.sequence_index as usize;
+            if seq_idx >= positions.len() {
+                continue;
+            }
+            let len_before = self.buffer.len() as isize;
+            if !self.apply_lookup_at(record.lookup_list_index, positions[seq_idx]) {
+                continue;
+            }
+
+            // Substitutions can change the number of glyphs, so shift what
+            // comes after
+            let delta = self.buffer.len() as isize - len_before;
+            if delta != 0 {
+                for pos in positions.iter_mut().skip(seq_idx + 1) {
+                    *pos = (*pos as isize + delta).max(0) as usize;
+                }
+                end += delta;
+            }
+        }
+        end.max(0) as usize
+    }
+
+    fn apply_subst(&mut self, subtable: &SubstSubtable<'a>, idx: usize,
+                   props: LookupProps) -> Option<usize> {
+        match subtable {
+            SubstSubtable::Single(single) => {
+                let glyph = single.substitute(self.glyph(idx))?;
+                self.replace_glyph(idx, glyph);
+                Some(idx + 1)
+            },
+            SubstSubtable::Multiple(multiple) => {
+                let glyphs: Vec<u16> = multiple.substitute(self.glyph(idx))?.collect();
+                let num_glyphs = glyphs.len();
+                let template = self.buffer.info[idx].clone();
+                let new_infos: Vec<GlyphInfo> = glyphs.into_iter()
+                    .map(|glyph| {
+                        let mut info = template.clone();
+                        info.set_glyph(glyph, self.gdef);
+                        info
+                    })
+                    .collect();
+                self.buffer.info.splice(idx..(idx + 1), new_infos);
+                Some(idx + num_glyphs)
+            },
+            SubstSubtable::Alternate(alternate) => {
+                let alternates = alternate.alternates(self.glyph(idx))?;
+                // A feature value of `n` picks the `n`th alternate
+                let alt_idx = (self.lookup_value as usize).checked_sub(1)?;
+                if alt_idx >= alternates.len() {
+                    return None;
+                }
+                self.replace_glyph(idx, alternates.at(alt_idx));
+                Some(idx + 1)
+            },
+            SubstSubtable::Ligature(ligature) => {
+                let first = self.glyph(idx);
+                for lig in ligature.ligatures(first) {
+                    if let Some(positions) = self.match_ligature(&lig, idx, props) {
+                        self.ligate(&lig, positions);
+                        return Some(idx + 1);
+                    }
+                }
+                None
+            },
+            SubstSubtable::Context(context) | SubstSubtable::ChainContext(context) => {
+                self.apply_context(context, idx, props)
+            },
+            SubstSubtable::ReverseChainSingle(reverse) => {
+                let coverage_idx = reverse.coverage().index(self.glyph(idx))?;
+                let backtrack = reverse.backtrack_coverages();
+                let lookahead = reverse.lookahead_coverages();
+                let lens = (backtrack.len(), 0, lookahead.len());
+                self.match_rule(idx, lens, props, |part, part_idx, glyph| {
+                    match part {
+                        Part::Backtrack => backtrack[part_idx].contains(glyph),
+                        Part::Lookahead => lookahead[part_idx].contains(glyph),
+                        Part::Input => true,
+                    }
+                })?;
+                let glyph = reverse.substitute(coverage_idx)?;
+                self.replace_glyph(idx, glyph);
+                Some(idx + 1)
+            },
+        }
+    }
+
+    fn replace_glyph(&mut self, idx: usize, glyph: u16) {
+        let gdef = self.gdef;
+        self.buffer.info[idx].set_glyph(glyph, gdef);
+    }
+
+    fn match_ligature(&self, lig: &Ligature<'a>, idx: usize, props: LookupProps) -> Option<Vec<usize>> {
+        let components = lig.components();
+        let lens = (0, components.len(), 0);
+        self.match_rule(idx, lens, props, |_, part_idx, glyph| components.at(part_idx) == glyph)
+    }
+
+    /// Replace the matched components with the ligature glyph.
+    ///
+    /// Marks that were skipped between components stay where they are, but
+    /// remember which component they belonged to.
+    fn ligate(&mut self, lig: &Ligature<'a>, positions: Vec<usize>) {
+        let first = positions[0];
+        let last = *positions.last().unwrap();
+        let lig_id = self.buffer.next_lig_id();
+        let num_comps = positions.len() as u8;
+
+        self.buffer.merge_clusters(first, last + 1);
+
+        let mut component = 1;
+        for idx in (first + 1)..(last + 1) {
+            if positions.contains(&idx) {
+                component += 1;
+            } else if self.buffer.info[idx].is_mark() {
+                let info = &mut self.buffer.info[idx];
+                info.lig_id = lig_id;
+                info.lig_component = component;
+            }
+        }
+
+        let gdef = self.gdef;
+        {
+            let info = &mut self.buffer.info[first];
+            info.set_glyph(lig.ligature_glyph, gdef);
+            if gdef.is_none() {
+                info.class = GlyphClass::Ligature;
+            }
+            info.lig_id = lig_id;
+            info.lig_num_comps = num_comps;
+            info.lig_component = 0;
+        }
+
+        for &idx in positions[1..].iter().rev() {
+            self.buffer.info.remove(idx);
+        }
+    }
+
+    fn apply_pos(&mut self, subtable: &PosSubtable<'a>, idx: usize,
+                 props: LookupProps) -> Option<usize> {
+        match subtable {
+            PosSubtable::Single(single) => {
+                let value = single.adjustment(self.glyph(idx))?;
+                self.adjust(idx, value);
+                Some(idx + 1)
+            },
+            PosSubtable::Pair(pair) => {
+                if !pair.coverage().contains(self.glyph(idx)) {
+                    return None;
+                }
+                let second = self.next_glyph(idx, props)?;
+                if self.buffer.info[second].mask & self.lookup_mask == 0 {
+                    return None;
+                }
+                let (value1, value2, has_second) = pair.adjustment(self.glyph(idx), self.glyph(second))?;
+                self.adjust(idx, value1);
+                self.adjust(second, value2);
+                // If the second glyph was adjusted it can't start another pair
+                Some(if has_second { second + 1 } else { second })
+            },
+            PosSubtable::Cursive(cursive) => {
+                let (entry, _) = cursive.entry_exit(self.glyph(idx))?;
+                let entry = entry?;
+                let prev = self.prev_glyph(idx, props)?;
+                let (_, exit) = cursive.entry_exit(self.glyph(prev))?;
+                let exit = exit?;
+                self.attach_cursive(prev, idx, exit, entry, props);
+                Some(idx + 1)
+            },
+            PosSubtable::MarkToBase(mark_base) => {
+                let (mark_class, mark_anchor) = mark_base.mark(self.glyph(idx))?;
+                // Bases are found by skipping all marks, whatever the lookup flags say
+                let base = (0..idx).rev()
+                    .find(|&prev| !self.buffer.info[prev].is_mark())?;
+                let base_anchor = mark_base.target_anchor(self.glyph(base), mark_class)?;
+                self.attach_mark(idx, base, mark_anchor, base_anchor);
+                Some(idx + 1)
+            },
+            PosSubtable::MarkToLigature(mark_lig) => {
+                self.apply_mark_to_ligature(mark_lig, idx)
+            },
+            PosSubtable::MarkToMark(mark_mark) => {
+                let (mark_class, mark_anchor) = mark_mark.mark(self.glyph(idx))?;
+                let prev = self.prev_glyph(idx, props)?;
+                let (mark_info, prev_info) = (&self.buffer.info[idx], &self.buffer.info[prev]);
+                if !prev_info.is_mark() {
+                    return None;
+                }
+                // Both marks have to belong to the same ligature component
+                if mark_info.lig_id != prev_info.lig_id
+                    || mark_info.lig_component != prev_info.lig_component {
+                    return None;
+                }
+                let mark2_anchor = mark_mark.target_anchor(self.glyph(prev), mark_class)?;
+                self.attach_mark(idx, prev, mark_anchor, mark2_anchor);
+                Some(idx + 1)
+            },
+            PosSubtable::Context(context) | PosSubtable::ChainContext(context) => {
+                self.apply_context(context, idx, props)
+            },
+        }
+    }
+
+    fn apply_mark_to_ligature(&mut self, mark_lig: &MarkAttachPos<'a>, idx: usize) -> Option<usize> {
+        let (mark_class, mark_anchor) = mark_lig.mark(self.glyph(idx))?;
+        let lig = (0..idx).rev()
+            .find(|&prev| !self.buffer.info[prev].is_mark())?;
+
+        let (mark_info, lig_info) = (&self.buffer.info[idx], &self.buffer.info[lig]);
+        // Marks that were between components go on their component, others on the last one
+        let component = if lig_info.lig_id != 0 && lig_info.lig_id == mark_info.lig_id
+            && mark_info.lig_component > 0 {
+            mark_info.lig_component as u16 - 1
+        } else {
+            ::std::u16::MAX
+        };
+        let (lig_anchor, _) = mark_lig.ligature_anchor(self.glyph(lig), component, mark_class)?;
+        self.attach_mark(idx, lig, mark_anchor, lig_anchor);
+        Some(idx + 1)
+    }
+
+    fn adjust(&mut self, idx: usize, value: ValueRecord) {
+        let pos = &mut self.buffer.pos[idx];
+        pos.x_offset += value.x_placement as i32;
+        pos.y_offset += value.y_placement as i32;
+        pos.x_advance += value.x_advance as i32;
+        pos.y_advance += value.y_advance as i32;
+    }
+
+    fn attach_mark(&mut self, mark: usize, target: usize, mark_anchor: Anchor, target_anchor: Anchor) {
+        let pos = &mut self.buffer.pos[mark];
+        pos.x_offset = target_anchor.x as i32 - mark_anchor.x as i32;
+        pos.y_offset = target_anchor.y as i32 - mark_anchor.y as i32;
+        pos.attach = Some(Attachment {
+            to: target,
+            kind: AttachKind::Mark,
+        });
+    }
+
+    /// Line up the exit anchor of `prev` with the entry anchor of `next`
+    fn attach_cursive(&mut self, prev: usize, next: usize, exit: Anchor, entry: Anchor,
+                      props: LookupProps) {
+        match self.buffer.direction {
+            TextDirection::Left => {
+                let d = exit.x as i32 + self.buffer.pos[prev].x_offset;
+                self.buffer.pos[prev].x_advance -= d;
+                self.buffer.pos[prev].x_offset -= d;
+                self.buffer.pos[next].x_advance = entry.x as i32 + self.buffer.pos[next].x_offset;
+            },
+            _ => {
+                self.buffer.pos[prev].x_advance = exit.x as i32 + self.buffer.pos[prev].x_offset;
+                let d = entry.x as i32 + self.buffer.pos[next].x_offset;
+                self.buffer.pos[next].x_advance -= d;
+                self.buffer.pos[next].x_offset -= d;
+            },
+        }
+
+        // The glyph that is the child is moved vertically to meet its parent
+        let (child, parent, y_offset) = if props.flag.contains(LookupFlag::RIGHT_TO_LEFT) {
+            (prev, next, entry.y as i32 - exit.y as i32)
+        } else {
+            (next, prev, exit.y as i32 - entry.y as i32)
+        };
+        // Don't make a loop
+        if self.buffer.pos[parent].attach.map(|attach| attach.to) == Some(child) {
+            self.buffer.pos[parent].attach = None;
+        }
+        let pos = &mut self.buffer.pos[child];
+        pos.y_offset = y_offset;
+        pos.attach = Some(Attachment {
+            to: parent,
+            kind: AttachKind::Cursive,
+        });
+    }
+}
@@ -0,0 +1,295 @@
+// Shaping for the Arabic script.
+// https://docs.microsoft.com/en-us/typography/script-development/arabic
+
+use tables::gdef::GlyphClass;
+use tables::layout::Tag;
+use super::buffer::Buffer;
+use super::plan::{PlanBuilder, ShapePlan};
+
+/// How a character connects to its neighbours, from Unicode's
+/// `ArabicShaping.txt`. Nothing in the Arabic blocks is left joining.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum JoiningType {
+    /// Doesn't join
+    NonJoining,
+    /// Only joins to the character on its right (before it)
+    RightJoining,
+    /// Joins on both sides
+    DualJoining,
+    /// Makes the characters on both sides join, e.g. tatweel and ZWJ
+    JoinCausing,
+    /// Ignored when working out joining, e.g. marks
+    Transparent,
+}
+
+use self::JoiningType::*;
+
+/// (first, last, joining type), sorted by code point.
+///
+/// Characters that aren't listed are `NonJoining`, unless they are marks.
+const JOINING_TYPES: &[(u32, u32, JoiningType)] = &[
+    (0x0610, 0x061A, Transparent),
+    (0x061C, 0x061C, Transparent),
+    (0x0620, 0x0620, DualJoining),
+    (0x0622, 0x0625, RightJoining),
+    (0x0626, 0x0626, DualJoining),
+    (0x0627, 0x0627, RightJoining),
+    (0x0628, 0x0628, DualJoining),
+    (0x0629, 0x0629, RightJoining),
+    (0x062A, 0x062E, DualJoining),
+    (0x062F, 0x0632, RightJoining),
+    (0x0633, 0x063F, DualJoining),
+    (0x0640, 0x0640, JoinCausing),
+    (0x0641, 0x0647, DualJoining),
+    (0x0648, 0x0648, RightJoining),
+    (0x0649, 0x064A, DualJoining),
+    (0x064B, 0x065F, Transparent),
+    (0x066E, 0x066F, DualJoining),
+    (0x0670, 0x0670, Transparent),
+    (0x0671, 0x0673, RightJoining),
+    (0x0675, 0x0677, RightJoining),
+    (0x0678, 0x0687, DualJoining),
+    (0x0688, 0x0699, RightJoining),
+    (0x069A, 0x06BF, DualJoining),
+    (0x06C0, 0x06C0, RightJoining),
+    (0x06C1, 0x06C2, DualJoining),
+    (0x06C3, 0x06CB, RightJoining),
+    (0x06CC, 0x06CC, DualJoining),
+    (0x06CD, 0x06CD, RightJoining),
+    (0x06CE, 0x06CE, DualJoining),
+    (0x06CF, 0x06CF, RightJoining),
+    (0x06D0, 0x06D1, DualJoining),
+    (0x06D2, 0x06D3, RightJoining),
+    (0x06D5, 0x06D5, RightJoining),
+    (0x06D6, 0x06DC, Transparent),
+    (0x06DF, 0x06E4, Transparent),
+    (0x06E7, 0x06E8, Transparent),
+    (0x06EA, 0x06ED, Transparent),
+    (0x06EE, 0x06EF, RightJoining),
+    (0x06FA, 0x06FC, DualJoining),
+    (0x06FF, 0x06FF, DualJoining),
+    (0x0750, 0x0758, DualJoining),
+    (0x0759, 0x075B, RightJoining),
+    (0x075C, 0x076A, DualJoining),
+    (0x076B, 0x076C, RightJoining),
+    (0x076D, 0x0770, DualJoining),
+    (0x0771, 0x0771, RightJoining),
+    (0x0772, 0x0772, DualJoining),
+    (0x0773, 0x0774, RightJoining),
+    (0x0775, 0x0777, DualJoining),
+    (0x0778, 0x0779, RightJoining),
+    (0x077A, 0x077F, DualJoining),
+    (0x08A0, 0x08A9, DualJoining),
+    (0x08AA, 0x08AC, RightJoining),
+    (0x08AE, 0x08AE, RightJoining),
+    (0x08AF, 0x08B0, DualJoining),
+    (0x08B1, 0x08B2, RightJoining),
+    (0x08B3, 0x08B4, DualJoining),
+    (0x08B6, 0x08B8, DualJoining),
+    (0x08B9, 0x08B9, RightJoining),
+    (0x08BA, 0x08BD, DualJoining),
+    (0x08D3, 0x08E1, Transparent),
+    (0x08E3, 0x08FF, Transparent),
+    (0x200D, 0x200D, JoinCausing),
+];
+
+/// The joining type of a character, if Unicode gives it one
+pub(crate) fn joining_type(ch: char) -> Option<JoiningType> {
+    use std::cmp::Ordering;
+    let code_point = ch as u32;
+    JOINING_TYPES
+        .binary_search_by(|&(first, last, _)| {
+            if last < code_point {
+                Ordering::Less
+            } else if first > code_point {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .ok()
+        .map(|idx| JOINING_TYPES[idx].2)
+}
+
+impl JoiningType {
+    /// Whether it connects to the character after it (to its left)
+    fn joins_forward(&self) -> bool {
+        match self {
+            DualJoining | JoinCausing => true,
+            _ => false,
+        }
+    }
+
+    /// Whether it connects to the character before it (to its right)
+    fn joins_backward(&self) -> bool {
+        match self {
+            DualJoining | RightJoining | JoinCausing => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Form {
+    Isolated,
+    Initial,
+    Medial,
+    Final,
+}
+
+impl Form {
+    fn tag(&self) -> Tag {
+        match self {
+            Form::Isolated => Tag::new(b"isol"),
+            Form::Initial => Tag::new(b"init"),
+            Form::Medial => Tag::new(b"medi"),
+            Form::Final => Tag::new(b"fina"),
+        }
+    }
+}
+
+/// Each form feature is applied on its own, in this order
+const FORM_FEATURES: &[&[u8; 4]] = &[b"isol", b"fina", b"medi", b"init"];
+
+pub(crate) fn collect_features(builder: &mut PlanBuilder) {
+    for tag in FORM_FEATURES {
+        builder.add_feature(Tag::new(tag), false);
+        builder.add_gsub_pause(None);
+    }
+    // Required ligatures (e.g. lam-alef) need the forms to be decided
+    builder.add_global_features(&[b"rlig"]);
+    builder.add_gsub_pause(None);
+    builder.add_global_features(&[b"calt"]);
+    builder.add_gsub_pause(None);
+    builder.add_global_features(&[b"mset"]);
+}
+
+/// Work out the form of each character from its neighbours and enable the
+/// feature for that form on it.
+pub(crate) fn setup_masks(plan: &ShapePlan, buffer: &mut Buffer) {
+    let types: Vec<Option<JoiningType>> = buffer.info.iter()
+        .map(|info| joining_type(info.codepoint))
+        .collect();
+
+    let mut forms: Vec<Option<Form>> = vec![None; buffer.len()];
+    // Index of the last character that wasn't transparent
+    let mut prev: Option<usize> = None;
+    for idx in 0..buffer.len() {
+        let joining = match types[idx] {
+            Some(Transparent) => continue,
+            // Marks that aren't in the table are transparent too
+            None if buffer.info[idx].is_mark() => continue,
+            Some(joining) => joining,
+            None => NonJoining,
+        };
+
+        let joins_prev = prev
+            .map(|prev| types[prev].map(|t| t.joins_forward()).unwrap_or(false))
+            .unwrap_or(false);
+        if joins_prev && joining.joins_backward() {
+            let prev = prev.unwrap();
+            forms[prev] = match forms[prev] {
+                Some(Form::Isolated) => Some(Form::Initial),
+                Some(Form::Final) => Some(Form::Medial),
+                form => form,
+            };
+            forms[idx] = Some(Form::Final);
+        } else {
+            forms[idx] = Some(Form::Isolated);
+        }
+
+        // Characters that don't have forms are left alone
+        if joining == NonJoining || joining == JoinCausing {
+            forms[idx] = None;
+        }
+        prev = Some(idx);
+    }
+
+    for (info, form) in buffer.info.iter_mut().zip(forms.into_iter()) {
+        if let Some(form) = form {
+            info.mask |= plan.mask(form.tag());
+        }
+    }
+
+    // Without GDEF, marks are only known from their joining type
+    for (info, joining) in buffer.info.iter_mut().zip(types.into_iter()) {
+        if info.class == GlyphClass::Unclassified && joining == Some(Transparent) {
+            info.class = GlyphClass::Mark;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::Font;
+    use shape::{shape, Feature};
+    use tables::layout::DEFAULT_LANGUAGE;
+    use test_utils::load_font_buf;
+
+    #[test]
+    fn joining_types() {
+        assert_eq!(joining_type('\u{0628}'), Some(DualJoining)); // Beh
+        assert_eq!(joining_type('\u{0627}'), Some(RightJoining)); // Alef
+        assert_eq!(joining_type('\u{064E}'), Some(Transparent)); // Fatha
+        assert_eq!(joining_type('\u{0640}'), Some(JoinCausing)); // Tatweel
+        assert_eq!(joining_type('a'), None);
+    }
+
+    #[test]
+    fn contextual_forms() {
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        let arab = Tag::new(b"arab");
+
+        // Beh beh beh
+        let text = "\u{0628}\u{0628}\u{0628}";
+        let shaped = shape(&font, text, arab, DEFAULT_LANGUAGE, &[]);
+        let unshaped = shape(&font, text, arab, DEFAULT_LANGUAGE, &[
+            Feature::new(b"init", 0),
+            Feature::new(b"medi", 0),
+            Feature::new(b"fina", 0),
+        ]);
+        assert_eq!(shaped.glyphs.len(), 3);
+
+        // All three forms are different glyphs
+        let ids: Vec<u32> = shaped.glyphs.iter().map(|glyph| glyph.glyph_id).collect();
+        assert!(ids[0] != ids[1] && ids[1] != ids[2] && ids[0] != ids[2]);
+        assert!(unshaped.glyphs.iter().all(|glyph| glyph.glyph_id == unshaped.glyphs[0].glyph_id));
+
+        // Visual order: the final form is on the left, the initial on the right
+        assert_eq!(shaped.glyphs[0].cluster, 4);
+        assert_eq!(shaped.glyphs[2].cluster, 0);
+    }
+
+    #[test]
+    fn right_joining_breaks() {
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        let arab = Tag::new(b"arab");
+
+        // Alef doesn't join to the following beh, so both are isolated/final
+        let alef_beh = shape(&font, "\u{0627}\u{0628}", arab, DEFAULT_LANGUAGE, &[]);
+        let beh = shape(&font, "\u{0628}", arab, DEFAULT_LANGUAGE, &[]);
+        assert_eq!(alef_beh.glyphs[0].glyph_id, beh.glyphs[0].glyph_id);
+    }
+
+    #[test]
+    fn lam_alef_and_marks() {
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        let arab = Tag::new(b"arab");
+
+        // Lam alef is a required ligature
+        let shaped = shape(&font, "\u{0644}\u{0627}", arab, DEFAULT_LANGUAGE, &[]);
+        assert_eq!(shaped.glyphs.len(), 1);
+
+        // Beh with a fatha, the fatha doesn't break the joining with the next beh
+        let shaped = shape(&font, "\u{0628}\u{064E}\u{0628}", arab, DEFAULT_LANGUAGE, &[]);
+        let plain = shape(&font, "\u{0628}\u{0628}", arab, DEFAULT_LANGUAGE, &[]);
+        assert_eq!(shaped.glyphs.len(), 3);
+        assert_eq!(shaped.glyphs[0].glyph_id, plain.glyphs[0].glyph_id);
+        assert_eq!(shaped.glyphs[2].glyph_id, plain.glyphs[1].glyph_id);
+        assert_eq!(shaped.glyphs[1].x_advance, 0);
+    }
+}
@@ -0,0 +1,237 @@
+use font::Font;
+use render::compositor::TextDirection;
+use tables::gdef::{GDEF, GlyphClass};
+use super::ShapedGlyph;
+
+/// Set on every glyph, used by features that are applied everywhere
+pub(crate) const MASK_GLOBAL: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub(crate) struct GlyphInfo {
+    pub glyph_id: u16,
+    /// Byte offset into the source text of the first character this glyph
+    /// came from
+    pub cluster: u32,
+    /// The character this glyph was mapped from. Substituted glyphs keep the
+    /// character of the glyph they replaced.
+    pub codepoint: char,
+    /// Which features apply to this glyph
+    pub mask: u32,
+    pub class: GlyphClass,
+    pub mark_attach_class: u16,
+    /// Non-zero for ligatures and for marks that sat between the components
+    /// of a ligature when it was formed
+    pub lig_id: u8,
+    /// For marks inside a ligature, the (1-based) component they follow
+    pub lig_component: u8,
+    /// For ligatures, the number of components that formed it
+    pub lig_num_comps: u8,
+    /// Set by complex shapers to group characters, e.g. Indic syllables
+    pub syllable: u8,
+    /// Shaper-specific character category
+    pub category: u8,
+    /// Shaper-specific position, used when reordering
+    pub position: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AttachKind {
+    Mark,
+    Cursive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Attachment {
+    /// Index of the glyph this one is positioned relative to
+    pub to: usize,
+    pub kind: AttachKind,
+}
+
+/// A glyph's position, in font units
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct GlyphPosition {
+    pub x_advance: i32,
+    pub y_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub attach: Option<Attachment>,
+}
+
+/// Working state while shaping a run of text.
+///
+/// Glyphs are kept in logical order until shaping is done.
+pub(crate) struct Buffer {
+    pub info: Vec<GlyphInfo>,
+    pub pos: Vec<GlyphPosition>,
+    pub direction: TextDirection,
+    next_lig_id: u8,
+}
+
+impl Buffer {
+    pub fn from_text<'a>(font: &Font<'a>, gdef: Option<&GDEF<'a>>, text: &str,
+                         direction: TextDirection) -> Buffer {
+        let info = text.char_indices()
+            .map(|(cluster, codepoint)| {
+                // Unmapped characters get `.notdef`
+                let glyph_id = font.get_glyph_id(codepoint).unwrap_or(0) as u16;
+                let mut info = GlyphInfo {
+                    glyph_id: 0,
+                    cluster: cluster as u32,
+                    codepoint,
+                    mask: MASK_GLOBAL,
+                    class: GlyphClass::Unclassified,
+                    mark_attach_class: 0,
+                    lig_id: 0,
+                    lig_component: 0,
+                    lig_num_comps: 0,
+                    syllable: 0,
+                    category: 0,
+                    position: 0,
+                };
+                info.set_glyph(glyph_id, gdef);
+                info
+            })
+            .collect();
+
+        Buffer {
+            info,
+            pos: Vec::new(),
+            direction,
+            next_lig_id: 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.info.len()
+    }
+
+    pub fn next_lig_id(&mut self) -> u8 {
+        let id = self.next_lig_id;
+        // Zero means "not part of a ligature"
+        self.next_lig_id = self.next_lig_id.wrapping_add(1).max(1);
+        id
+    }
+
+    /// Give every glyph in `start..end` the smallest cluster in the range
+    pub fn merge_clusters(&mut self, start: usize, end: usize) {
+        let end = end.min(self.info.len());
+        if end <= start + 1 {
+            return;
+        }
+        let cluster = self.info[start..end].iter()
+            .map(|info| info.cluster)
+            .min()
+            .unwrap();
+        for info in &mut self.info[start..end] {
+            info.cluster = cluster;
+        }
+    }
+
+    /// Start every glyph off with its advance from `hmtx`, varied for the
+    /// font's instance
+    pub fn init_positions<'a>(&mut self, font: &Font<'a>) {
+        self.pos = self.info.iter()
+            .map(|info| {
+                let x_advance = font.advance_width(info.glyph_id as u32)
+                    .map(|advance| advance.0 as i32)
+                    .unwrap_or(0);
+                GlyphPosition {
+                    x_advance,
+                    ..Default::default()
+                }
+            })
+            .collect();
+    }
+
+    /// Marks shouldn't move the pen, they are positioned relative to their base
+    pub fn zero_mark_advances(&mut self) {
+        for (info, pos) in self.info.iter().zip(self.pos.iter_mut()) {
+            if info.class == GlyphClass::Mark {
+                pos.x_advance = 0;
+                pos.y_advance = 0;
+            }
+        }
+    }
+
+    /// Turn attachments into offsets relative to the pen position of the
+    /// attached glyph.
+    pub fn propagate_attachment_offsets(&mut self) {
+        let mut done = vec![false; self.pos.len()];
+        for idx in 0..self.pos.len() {
+            self.propagate_attachment(idx, &mut done);
+        }
+    }
+
+    fn propagate_attachment(&mut self, idx: usize, done: &mut Vec<bool>) {
+        if done[idx] {
+            return;
+        }
+        done[idx] = true;
+
+        let attach = match self.pos[idx].attach {
+            Some(attach) if attach.to < self.pos.len() && attach.to != idx => attach,
+            _ => return,
+        };
+        let to = attach.to;
+        self.propagate_attachment(to, done);
+
+        let parent = self.pos[to];
+        match attach.kind {
+            AttachKind::Cursive => {
+                self.pos[idx].y_offset += parent.y_offset;
+            },
+            AttachKind::Mark => {
+                self.pos[idx].x_offset += parent.x_offset;
+                self.pos[idx].y_offset += parent.y_offset;
+                // Offsets are relative to where the pen is when the mark is
+                // drawn, so undo the advances of everything in between
+                if to < idx {
+                    let advances: i32 = match self.direction {
+                        TextDirection::Left => self.pos[(to + 1)..(idx + 1)].iter()
+                            .map(|pos| -pos.x_advance)
+                            .sum(),
+                        _ => self.pos[to..idx].iter()
+                            .map(|pos| pos.x_advance)
+                            .sum(),
+                    };
+                    self.pos[idx].x_offset -= advances;
+                }
+            },
+        }
+    }
+
+    /// Switch logical order to visual order for right-to-left text
+    pub fn reverse(&mut self) {
+        self.info.reverse();
+        self.pos.reverse();
+    }
+
+    pub fn into_glyphs(self) -> Vec<ShapedGlyph> {
+        self.info.into_iter()
+            .zip(self.pos.into_iter())
+            .map(|(info, pos)| ShapedGlyph {
+                glyph_id: info.glyph_id as u32,
+                cluster: info.cluster,
+                x_advance: pos.x_advance,
+                y_advance: pos.y_advance,
+                x_offset: pos.x_offset,
+                y_offset: pos.y_offset,
+            })
+            .collect()
+    }
+}
+
+impl GlyphInfo {
+    /// Replace the glyph, updating the properties that come from GDEF
+    pub fn set_glyph<'a>(&mut self, glyph_id: u16, gdef: Option<&GDEF<'a>>) {
+        self.glyph_id = glyph_id;
+        if let Some(gdef) = gdef {
+            self.class = gdef.glyph_class(glyph_id);
+            self.mark_attach_class = gdef.mark_attach_class(glyph_id);
+        }
+    }
+
+    pub fn is_mark(&self) -> bool {
+        self.class == GlyphClass::Mark
+    }
+}
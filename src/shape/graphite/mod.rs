@@ -0,0 +1,1052 @@
+//! Shaping with SIL's Graphite tables.
+//!
+//! Follows graphite2: every glyph is a slot in a linked list, and each pass
+//! of the `Silf` subtable runs a state machine over the slots to find rules
+//! that match. The first rule whose constraint passes has its action run,
+//! and both constraints and actions are bytecode run by the machine in `vm`.
+//! After the substitution passes the slots get positions, and positioning
+//! passes adjust them by setting slot attributes like shifts and
+//! attachments.
+
+use std::ops::{Add, Sub};
+
+use font::{Font, GetTable};
+use render::compositor::TextDirection;
+use tables::glat::{Glat, Gloc};
+use tables::glyf::Glyf;
+use tables::graphite_feat::GraphiteFeat;
+use tables::hhea::HHEA;
+use tables::hmtx::HMTX;
+use tables::loca::Loca;
+use tables::maxp::MaxP;
+use tables::silf::{Pass, Rule, Silf, Subtable, PASS_REVERSE};
+use super::{Feature, ShapedGlyph};
+
+mod vm;
+
+/// The tables Graphite shaping uses, `Silf` is the only one that's required
+pub(crate) struct GraphiteTables<'a> {
+    pub silf: Silf<'a>,
+    pub glat: Option<Glat<'a>>,
+    pub gloc: Option<Gloc<'a>>,
+    pub feat: Option<GraphiteFeat<'a>>,
+}
+
+impl<'a> GraphiteTables<'a> {
+    pub fn from_font(font: &Font<'a>) -> Option<GraphiteTables<'a>> {
+        Some(GraphiteTables {
+            silf: font.get_table()?,
+            glat: font.get_table(),
+            gloc: font.get_table(),
+            feat: font.get_table(),
+        })
+    }
+}
+
+// Slot attributes
+const ATTR_ADV_X: u8 = 0;
+const ATTR_ADV_Y: u8 = 1;
+const ATTR_ATT_TO: u8 = 2;
+const ATTR_ATT_X: u8 = 3;
+const ATTR_ATT_Y: u8 = 4;
+const ATTR_ATT_WITH_X: u8 = 8;
+const ATTR_ATT_WITH_Y: u8 = 9;
+const ATTR_ATT_LEVEL: u8 = 13;
+const ATTR_DIR: u8 = 16;
+const ATTR_POS_X: u8 = 18;
+const ATTR_POS_Y: u8 = 19;
+const ATTR_SHIFT_X: u8 = 20;
+const ATTR_SHIFT_Y: u8 = 21;
+/// The first user attribute, from before there were several
+const ATTR_USER_V1: u8 = 22;
+const ATTR_MEASURE_SOL: u8 = 23;
+const ATTR_MEASURE_EOL: u8 = 24;
+const ATTR_USER: u8 = 55;
+
+// Glyph metrics
+const METRIC_LSB: u8 = 0;
+const METRIC_RSB: u8 = 1;
+const METRIC_BB_TOP: u8 = 2;
+const METRIC_BB_BOTTOM: u8 = 3;
+const METRIC_BB_LEFT: u8 = 4;
+const METRIC_BB_RIGHT: u8 = 5;
+const METRIC_BB_HEIGHT: u8 = 6;
+const METRIC_BB_WIDTH: u8 = 7;
+const METRIC_ADV_WIDTH: u8 = 8;
+const METRIC_ADV_HEIGHT: u8 = 9;
+const METRIC_ASCENT: u8 = 10;
+const METRIC_DESCENT: u8 = 11;
+
+/// Most slots the state machine looks at for one match
+const MAX_SLOTS: usize = 64;
+/// How many times longer than the text the glyphs are allowed to get
+const MAX_GROWTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Position {
+    x: i32,
+    y: i32,
+}
+
+impl Position {
+    fn new(x: i32, y: i32) -> Position {
+        Position { x, y }
+    }
+}
+
+impl Add for Position {
+    type Output = Position;
+    fn add(self, other: Position) -> Position {
+        Position::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Position {
+    type Output = Position;
+    fn sub(self, other: Position) -> Position {
+        Position::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    glyph_id: u16,
+    /// For pseudo glyphs, the glyph that is drawn
+    real_glyph_id: u16,
+    /// Index of the character the slot came from
+    original: usize,
+    /// The range of characters the slot represents
+    before: usize,
+    after: usize,
+    advance: Position,
+    shift: Position,
+    /// Where on the parent the slot is attached
+    attach: Position,
+    /// Where on this slot the parent is attached
+    with: Position,
+    att_level: u8,
+    parent: Option<usize>,
+    /// First attached slot, the rest are its siblings
+    child: Option<usize>,
+    sibling: Option<usize>,
+    user_attrs: Vec<i16>,
+    deleted: bool,
+    position: Position,
+    /// For base slots, how far the whole cluster moves the pen
+    cluster_advance: i32,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// The slots a rule matched. The first entry is the slot before the match,
+/// so rules can look one slot back.
+pub(super) struct SlotMap {
+    slots: Vec<Option<usize>>,
+    /// Number of slots matched before the current one
+    context: usize,
+    /// Rules can match again at the same place until it moves past this
+    highwater: Option<usize>,
+    highpassed: bool,
+    /// How many more slots can be inserted
+    max_size: isize,
+}
+
+impl SlotMap {
+    fn reset(&mut self, prev: Option<usize>, context: usize) {
+        self.slots.clear();
+        self.slots.push(prev);
+        self.context = context;
+    }
+
+    fn size(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn set_highwater(&mut self, slot: Option<usize>) {
+        self.highwater = slot;
+        self.highpassed = false;
+    }
+}
+
+struct Segment<'s, 'a: 's> {
+    tables: &'s GraphiteTables<'a>,
+    subtable: &'s Subtable<'a>,
+    hmtx: Option<HMTX<'a>>,
+    loca: Option<Loca<'a>>,
+    glyf: Option<Glyf<'a>>,
+    num_glyphs: u16,
+    ascent: i32,
+    descent: i32,
+    slots: Vec<Slot>,
+    first: Option<usize>,
+    last: Option<usize>,
+    /// Each character's feature set
+    char_features: Vec<usize>,
+    feature_sets: Vec<Vec<i32>>,
+    rtl: bool,
+    /// The slots are in the opposite order of `rtl`
+    reversed: bool,
+}
+
+/// Shape `text` with the font's first `Silf` subtable, `None` if the rules
+/// can't be run.
+///
+/// Glyphs are returned in visual order.
+pub(crate) fn shape<'a>(font: &Font<'a>, tables: &GraphiteTables<'a>, text: &str,
+                        direction: TextDirection, features: &[Feature]) -> Option<Vec<ShapedGlyph>> {
+    let subtable = tables.silf.first_subtable()?;
+    let hhea: Option<HHEA> = font.get_table();
+    let maxp: Option<MaxP> = font.get_table();
+    let mut seg = Segment {
+        tables,
+        subtable: &subtable,
+        hmtx: font.get_table(),
+        loca: font.get_table(),
+        glyf: font.get_table(),
+        num_glyphs: maxp.map(|maxp| maxp.num_glyphs).unwrap_or(0),
+        ascent: hhea.as_ref().map(|hhea| hhea.ascent.0 as i32).unwrap_or(0),
+        descent: hhea.as_ref().map(|hhea| -hhea.descent.0 as i32).unwrap_or(0),
+        slots: Vec::new(),
+        first: None,
+        last: None,
+        char_features: Vec::new(),
+        feature_sets: Vec::new(),
+        rtl: direction == TextDirection::Left,
+        reversed: false,
+    };
+
+    let offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+    seg.init_features(&offsets, features);
+    for (idx, character) in text.chars().enumerate() {
+        let glyph_id = match font.get_glyph_id(character) {
+            Some(glyph_id) if glyph_id != 0 => glyph_id as u16,
+            _ => seg.subtable.pseudo_glyph(character).unwrap_or(0),
+        };
+        let slot = seg.new_slot(glyph_id, idx);
+        seg.link_after(seg.last, slot);
+    }
+
+    seg.run_passes()?;
+    seg.position_slots();
+
+    Some(seg.output(&offsets))
+}
+
+impl<'s, 'a: 's> Segment<'s, 'a> {
+    fn init_features(&mut self, offsets: &[usize], features: &[Feature]) {
+        let defs = self.tables.feat.as_ref()
+            .map(|feat| feat.features())
+            .unwrap_or_default();
+        for &offset in offsets {
+            let values: Vec<i32> = defs.iter()
+                .map(|def| {
+                    features.iter()
+                        .rev()
+                        .find(|feature| {
+                            let id = feature.tag.0.iter()
+                                .fold(0u32, |id, &byte| (id << 8) | byte as u32);
+                            id == def.id && feature.start <= offset && offset < feature.end
+                        })
+                        .map(|feature| feature.value as i32)
+                        .unwrap_or(def.default_value as i32)
+                })
+                .collect();
+            let set = match self.feature_sets.iter().position(|set| *set == values) {
+                Some(set) => set,
+                None => {
+                    self.feature_sets.push(values);
+                    self.feature_sets.len() - 1
+                },
+            };
+            self.char_features.push(set);
+        }
+    }
+
+    fn feature(&self, character: usize, feature: u8) -> i32 {
+        self.char_features.get(character)
+            .and_then(|&set| self.feature_sets[set].get(feature as usize))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    fn set_feature(&mut self, character: usize, feature: u8, value: i32) {
+        if let Some(&set) = self.char_features.get(character) {
+            if let Some(val) = self.feature_sets[set].get_mut(feature as usize) {
+                *val = value;
+            }
+        }
+    }
+
+    fn glyph_attr(&self, glyph_id: u16, attr: u16) -> i32 {
+        match (&self.tables.glat, &self.tables.gloc) {
+            (Some(glat), Some(gloc)) if attr < gloc.num_attribs() =>
+                glat.attribute(gloc, glyph_id, attr) as i32,
+            _ => 0,
+        }
+    }
+
+    fn advance_width(&self, glyph_id: u16) -> i32 {
+        if glyph_id >= self.num_glyphs {
+            return 0;
+        }
+        self.hmtx.as_ref()
+            .map(|hmtx| hmtx.advance_width(glyph_id as u32).0 as i32)
+            .unwrap_or(0)
+    }
+
+    /// (x_min, y_min, x_max, y_max) of the glyph's outline
+    fn bounding_box(&self, glyph_id: u16) -> (i32, i32, i32, i32) {
+        if glyph_id >= self.num_glyphs {
+            return (0, 0, 0, 0);
+        }
+        let offset = self.loca.as_ref().and_then(|loca| loca.at(glyph_id as usize));
+        match (offset, &self.glyf) {
+            (Some(offset), Some(glyf)) => glyf.at_offset(offset as usize)
+                .map(|glyph| {
+                    let header = glyph.header;
+                    (header.x_min as i32, header.y_min as i32,
+                     header.x_max as i32, header.y_max as i32)
+                })
+                .unwrap_or((0, 0, 0, 0)),
+            _ => (0, 0, 0, 0),
+        }
+    }
+
+    /// A metric of the slot's glyph. A non-zero `attr_level` asks for the
+    /// metric of the cluster, which uses the glyph the cluster is attached
+    /// to.
+    fn glyph_metric(&self, slot: usize, metric: u8, attr_level: u8) -> i32 {
+        let mut slot = slot;
+        if attr_level > 0 {
+            while let Some(parent) = self.slots[slot].parent {
+                slot = parent;
+            }
+        }
+        let glyph_id = self.slots[slot].glyph_id;
+        let (x_min, y_min, x_max, y_max) = self.bounding_box(glyph_id);
+        match metric {
+            METRIC_LSB | METRIC_BB_LEFT => x_min,
+            METRIC_RSB => self.advance_width(glyph_id) - x_max,
+            METRIC_BB_TOP => y_max,
+            METRIC_BB_BOTTOM => y_min,
+            METRIC_BB_RIGHT => x_max,
+            METRIC_BB_HEIGHT => y_max - y_min,
+            METRIC_BB_WIDTH => x_max - x_min,
+            METRIC_ADV_WIDTH => self.advance_width(glyph_id),
+            METRIC_ADV_HEIGHT => 0,
+            METRIC_ASCENT => self.ascent,
+            METRIC_DESCENT => self.descent,
+            _ => 0,
+        }
+    }
+
+    fn new_slot(&mut self, glyph_id: u16, original: usize) -> usize {
+        self.slots.push(Slot {
+            glyph_id: 0,
+            real_glyph_id: 0,
+            original,
+            before: original,
+            after: original,
+            advance: Position::default(),
+            shift: Position::default(),
+            attach: Position::default(),
+            with: Position::default(),
+            att_level: 0,
+            parent: None,
+            child: None,
+            sibling: None,
+            user_attrs: vec![0; self.subtable.num_user_attrs as usize],
+            deleted: false,
+            position: Position::default(),
+            cluster_advance: 0,
+            prev: None,
+            next: None,
+        });
+        let idx = self.slots.len() - 1;
+        self.set_glyph(idx, glyph_id);
+        idx
+    }
+
+    /// Change the slot's glyph, which resets its advance
+    fn set_glyph(&mut self, slot: usize, glyph_id: u16) {
+        let mut real = self.glyph_attr(glyph_id, self.subtable.attr_pseudo as u16) as u16;
+        if real > self.num_glyphs {
+            real = 0;
+        }
+        let advance = self.advance_width(if real != 0 { real } else { glyph_id });
+        let slot = &mut self.slots[slot];
+        slot.glyph_id = glyph_id;
+        slot.real_glyph_id = real;
+        slot.advance = Position::new(advance, 0);
+    }
+
+    fn next(&self, slot: usize) -> Option<usize> {
+        self.slots[slot].next
+    }
+
+    fn prev(&self, slot: usize) -> Option<usize> {
+        self.slots[slot].prev
+    }
+
+    /// Put `slot` in the list after `after`, or at the start if it's `None`
+    fn link_after(&mut self, after: Option<usize>, slot: usize) {
+        let next = match after {
+            Some(after) => self.slots[after].next,
+            None => self.first,
+        };
+        self.slots[slot].prev = after;
+        self.slots[slot].next = next;
+        match after {
+            Some(after) => self.slots[after].next = Some(slot),
+            None => self.first = Some(slot),
+        }
+        match next {
+            Some(next) => self.slots[next].prev = Some(slot),
+            None => self.last = Some(slot),
+        }
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = (self.slots[slot].prev, self.slots[slot].next);
+        match prev {
+            Some(prev) => self.slots[prev].next = next,
+            None => self.first = next,
+        }
+        match next {
+            Some(next) => self.slots[next].prev = prev,
+            None => self.last = prev,
+        }
+    }
+
+    fn reverse_slots(&mut self) {
+        let mut current = self.first;
+        while let Some(slot) = current {
+            let slot = &mut self.slots[slot];
+            current = slot.next;
+            ::std::mem::swap(&mut slot.prev, &mut slot.next);
+        }
+        ::std::mem::swap(&mut self.first, &mut self.last);
+        self.reversed = !self.reversed;
+    }
+
+    /// Whether the slots are currently in right to left order
+    fn current_rtl(&self) -> bool {
+        self.rtl != self.reversed
+    }
+
+    /// Add `child` to the slots attached to `parent`
+    fn add_child(&mut self, parent: usize, child: usize) -> bool {
+        if parent == child {
+            return false;
+        }
+        let mut current = match self.slots[parent].child {
+            None => {
+                self.slots[parent].child = Some(child);
+                return true;
+            },
+            Some(first) => first,
+        };
+        loop {
+            if current == child {
+                return true;
+            }
+            match self.slots[current].sibling {
+                Some(sibling) => current = sibling,
+                None => {
+                    self.slots[current].sibling = Some(child);
+                    return true;
+                },
+            }
+        }
+    }
+
+    fn remove_child(&mut self, parent: usize, child: usize) {
+        let sibling = self.slots[child].sibling.take();
+        if self.slots[parent].child == Some(child) {
+            self.slots[parent].child = sibling;
+            return;
+        }
+        let mut current = self.slots[parent].child;
+        while let Some(slot) = current {
+            if self.slots[slot].sibling == Some(child) {
+                self.slots[slot].sibling = sibling;
+                return;
+            }
+            current = self.slots[slot].sibling;
+        }
+    }
+
+    /// Remove the slot from the list and from any attachments
+    fn delete(&mut self, slot: usize) {
+        self.slots[slot].deleted = true;
+        self.unlink(slot);
+        if let Some(parent) = self.slots[slot].parent.take() {
+            self.remove_child(parent, slot);
+        }
+        let mut child = self.slots[slot].child.take();
+        while let Some(attached) = child {
+            child = self.slots[attached].sibling.take();
+            self.slots[attached].parent = None;
+        }
+    }
+
+    fn get_attr(&self, slot: usize, attr: u8, index: u8) -> i32 {
+        let slot = &self.slots[slot];
+        match attr {
+            ATTR_ADV_X => slot.advance.x,
+            ATTR_ADV_Y => slot.advance.y,
+            ATTR_ATT_TO => slot.parent.is_some() as i32,
+            ATTR_ATT_X => slot.attach.x,
+            ATTR_ATT_Y => slot.attach.y,
+            ATTR_ATT_WITH_X => slot.with.x,
+            ATTR_ATT_WITH_Y => slot.with.y,
+            ATTR_ATT_LEVEL => slot.att_level as i32,
+            ATTR_DIR => self.rtl as i32,
+            ATTR_POS_X => slot.position.x,
+            ATTR_POS_Y => slot.position.y,
+            ATTR_SHIFT_X => slot.shift.x,
+            ATTR_SHIFT_Y => slot.shift.y,
+            ATTR_MEASURE_SOL | ATTR_MEASURE_EOL => -1,
+            ATTR_USER_V1 => slot.user_attrs.get(0).cloned().unwrap_or(0) as i32,
+            ATTR_USER => slot.user_attrs.get(index as usize).cloned().unwrap_or(0) as i32,
+            _ => 0,
+        }
+    }
+
+    /// Set a slot attribute. For `ATTR_ATT_TO`, `value` is the index in
+    /// `map` of the slot to attach to and `index` is the slot's own index.
+    fn set_attr(&mut self, slot: usize, attr: u8, index: u8, value: i32, map: &SlotMap) {
+        let value = value as i16 as i32;
+        match attr {
+            ATTR_ADV_X => self.slots[slot].advance.x = value,
+            ATTR_ADV_Y => self.slots[slot].advance.y = value,
+            ATTR_ATT_TO => self.attach(slot, index, value as u16 as usize, map),
+            ATTR_ATT_X => self.slots[slot].attach.x = value,
+            ATTR_ATT_Y => self.slots[slot].attach.y = value,
+            ATTR_ATT_WITH_X => self.slots[slot].with.x = value,
+            ATTR_ATT_WITH_Y => self.slots[slot].with.y = value,
+            ATTR_ATT_LEVEL => self.slots[slot].att_level = value as u8,
+            ATTR_SHIFT_X => self.slots[slot].shift.x = value,
+            ATTR_SHIFT_Y => self.slots[slot].shift.y = value,
+            ATTR_USER_V1 => if let Some(user) = self.slots[slot].user_attrs.get_mut(0) {
+                *user = value as i16;
+            },
+            ATTR_USER => if let Some(user) = self.slots[slot].user_attrs.get_mut(index as usize) {
+                *user = value as i16;
+            },
+            // Positions are calculated, and the rest aren't used here
+            _ => (),
+        }
+    }
+
+    fn attach(&mut self, slot: usize, index: u8, to: usize, map: &SlotMap) {
+        let other = match map.slots.get(to + 1) {
+            Some(&Some(other)) => other,
+            _ => return,
+        };
+        if other == slot || Some(other) == self.slots[slot].parent {
+            return;
+        }
+        if let Some(parent) = self.slots[slot].parent.take() {
+            self.remove_child(parent, slot);
+        }
+        // Don't make cycles
+        let mut ancestor = Some(other);
+        let mut depth = 0;
+        while let Some(current) = ancestor {
+            if current == slot || depth > 100 {
+                return;
+            }
+            depth += 1;
+            ancestor = self.slots[current].parent;
+        }
+        if !self.add_child(other, slot) {
+            return;
+        }
+        self.slots[slot].parent = Some(other);
+        // Attach the parent's right edge to this slot's left edge, or the
+        // other way around when attaching to a later slot
+        if (self.subtable.direction & 1 != 0) != (to > index as usize) {
+            self.slots[slot].with = Position::new(self.slots[slot].advance.x, 0);
+        } else {
+            self.slots[slot].attach = Position::new(self.slots[other].advance.x, 0);
+        }
+    }
+
+    fn run_passes(&mut self) -> Option<()> {
+        let bidi_pass = self.subtable.bidi_pass as usize;
+        let subtable = self.subtable;
+        let silf_rtl = subtable.direction & 1 != 0;
+        for idx in 0..subtable.passes.len() {
+            if idx == bidi_pass && self.current_rtl() != silf_rtl {
+                self.reverse_slots();
+            }
+            if idx == subtable.pos_pass as usize {
+                // Positioning passes start from the laid out glyphs
+                self.position_slots();
+            }
+            let pass = &subtable.passes[idx];
+            let reverse = subtable.bidi_pass == 0xFF &&
+                self.current_rtl() != (silf_rtl != (pass.flags & PASS_REVERSE != 0));
+            self.run_pass(pass, reverse)?;
+        }
+        Some(())
+    }
+
+    fn run_pass(&mut self, pass: &Pass<'a>, reverse: bool) -> Option<()> {
+        let mut map = SlotMap {
+            slots: Vec::with_capacity(MAX_SLOTS + 1),
+            context: 0,
+            highwater: None,
+            highpassed: false,
+            max_size: (self.slots.len() * MAX_GROWTH) as isize,
+        };
+        if self.first.is_none() || !self.test_pass_constraint(pass, &mut map)? {
+            return Some(());
+        }
+        if reverse {
+            self.reverse_slots();
+        }
+        if pass.rules.is_empty() {
+            return Some(());
+        }
+
+        let mut current = self.first;
+        map.set_highwater(current.and_then(|slot| self.next(slot)));
+        let max_loop = pass.max_rule_loop as i32;
+        let mut loops = max_loop;
+        // Rules that keep inserting can otherwise run forever
+        let mut ops_left = self.slots.len() * MAX_GROWTH * (max_loop as usize + 1);
+        while let Some(slot) = current {
+            ops_left = ops_left.checked_sub(1)?;
+            current = self.find_and_do_rule(pass, slot, &mut map)?;
+            if let Some(slot) = current {
+                let at_highwater = Some(slot) == map.highwater || map.highpassed;
+                if !at_highwater {
+                    loops -= 1;
+                }
+                if at_highwater || loops == 0 {
+                    if loops == 0 {
+                        current = map.highwater;
+                    }
+                    loops = max_loop;
+                    if let Some(slot) = current {
+                        let next = self.next(slot);
+                        map.set_highwater(next);
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn test_pass_constraint(&mut self, pass: &Pass<'a>, map: &mut SlotMap) -> Option<bool> {
+        if pass.constraint.is_empty() {
+            return Some(true);
+        }
+        map.reset(None, 0);
+        map.slots.push(self.first);
+        let mut idx = 1;
+        vm::run(self, map, pass.constraint, &mut idx).map(|ret| ret != 0)
+    }
+
+    /// Run the first rule that matches at `slot`, returning the slot to
+    /// continue from
+    fn find_and_do_rule(&mut self, pass: &Pass<'a>, slot: usize,
+                        map: &mut SlotMap) -> Option<Option<usize>> {
+        if let Some(rules) = self.run_state_machine(pass, slot, map) {
+            for rule in rules {
+                let rule = &pass.rules[rule as usize];
+                if self.test_constraint(rule, map)? {
+                    let (delta, mut out) = self.do_action(rule, slot, map)?;
+                    if let Some(deleted) = out.filter(|&out| self.slots[out].deleted) {
+                        out = self.prev(deleted);
+                    }
+                    return Some(self.adjust_slot(delta, out, map));
+                }
+            }
+        }
+        Some(self.next(slot))
+    }
+
+    /// Fill the slot map starting from the pre-context of `slot` and return
+    /// the rules that matched, longest first
+    fn run_state_machine(&self, pass: &Pass<'a>, slot: usize, map: &mut SlotMap) -> Option<Vec<u16>> {
+        let mut start = slot;
+        let mut context = 0;
+        while context < pass.max_pre_context {
+            match self.prev(start) {
+                Some(prev) => {
+                    start = prev;
+                    context += 1;
+                },
+                None => break,
+            }
+        }
+        map.reset(self.prev(start), context as usize);
+        if context < pass.min_pre_context {
+            return None;
+        }
+
+        let mut rules: Vec<u16> = Vec::new();
+        let mut state = pass.start_state(context);
+        let mut current = Some(start);
+        let mut free_slots = MAX_SLOTS;
+        while let Some(slot) = current {
+            map.slots.push(current);
+            free_slots -= 1;
+            let glyph_id = self.slots[slot].glyph_id;
+            let column = if glyph_id > self.subtable.max_glyph_id {
+                None
+            } else {
+                pass.column(glyph_id)
+            };
+            let column = match column {
+                Some(column) if free_slots != 0 && pass.is_transitional(state) => column,
+                _ => {
+                    if free_slots == 0 {
+                        return None;
+                    }
+                    return Some(sort_rules(pass, rules));
+                },
+            };
+            state = pass.transition(state, column);
+            rules.extend_from_slice(pass.rules_for_state(state));
+            current = self.next(slot);
+            if state == 0 {
+                break;
+            }
+        }
+        map.slots.push(current);
+        Some(sort_rules(pass, rules))
+    }
+
+    fn test_constraint(&mut self, rule: &Rule<'a>, map: &mut SlotMap) -> Option<bool> {
+        let context = map.context as isize;
+        let pre_context = rule.pre_context as isize;
+        let sort = rule.sort as usize;
+        if context < pre_context || sort == 0 ||
+            sort as isize + context - pre_context > map.size() as isize {
+            return Some(false);
+        }
+        let start = 1 + (context - pre_context) as usize;
+        if map.slots[start + sort - 1].is_none() {
+            return Some(false);
+        }
+        if rule.constraint.is_empty() {
+            return Some(true);
+        }
+        for offset in 0..sort {
+            let mut idx = start + offset;
+            if map.slots[idx].is_none() {
+                continue;
+            }
+            if vm::run(self, map, rule.constraint, &mut idx)? == 0 {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    /// Run the rule's action, returning how many slots to move and the slot
+    /// to move from
+    fn do_action(&mut self, rule: &Rule<'a>, slot: usize,
+                 map: &mut SlotMap) -> Option<(i32, Option<usize>)> {
+        if rule.action.is_empty() {
+            return Some((0, Some(slot)));
+        }
+        let mut idx = 1 + map.context;
+        map.highpassed = false;
+        let delta = vm::run(self, map, rule.action, &mut idx)?;
+        Some((delta, map.slots.get(idx).cloned().unwrap_or(None)))
+    }
+
+    fn adjust_slot(&mut self, mut delta: i32, mut slot: Option<usize>, map: &mut SlotMap) -> Option<usize> {
+        if slot.is_none() {
+            if map.highpassed || slot == map.highwater {
+                slot = self.last;
+                delta += 1;
+                if map.highwater.is_none() || map.highwater == slot {
+                    map.highpassed = false;
+                }
+            } else {
+                slot = self.first;
+                delta -= 1;
+            }
+        }
+        while delta < 0 {
+            delta += 1;
+            slot = match slot {
+                Some(current) => self.prev(current),
+                None => break,
+            };
+            if map.highpassed && map.highwater == slot {
+                map.highpassed = false;
+            }
+        }
+        while delta > 0 {
+            delta -= 1;
+            let current = match slot {
+                Some(current) => current,
+                None => break,
+            };
+            if Some(current) == map.highwater {
+                map.highpassed = true;
+            }
+            slot = self.next(current);
+        }
+        slot
+    }
+
+    /// Give every slot its position. Clusters are laid out from the start
+    /// of the text, which is the right for right to left text.
+    fn position_slots(&mut self) {
+        let reorder = self.reversed;
+        if reorder {
+            self.reverse_slots();
+        }
+        let mut pen = Position::default();
+        let mut current = if self.rtl { self.last } else { self.first };
+        while let Some(slot) = current {
+            if self.slots[slot].parent.is_none() {
+                let mut cluster_min = pen.x;
+                let next = self.finalise(slot, pen, &mut cluster_min, 0);
+                self.slots[slot].cluster_advance = next.x - pen.x;
+                pen = next;
+            }
+            current = if self.rtl { self.prev(slot) } else { self.next(slot) };
+        }
+        if reorder {
+            self.reverse_slots();
+        }
+    }
+
+    /// Position the slot and everything attached to it, returning where the
+    /// pen ends up
+    fn finalise(&mut self, slot: usize, base: Position, cluster_min: &mut i32, depth: usize) -> Position {
+        if depth > 100 {
+            return Position::default();
+        }
+        let shift = {
+            let shift = self.slots[slot].shift;
+            Position::new(if self.rtl { -shift.x } else { shift.x }, shift.y)
+        };
+        let advance = self.slots[slot].advance;
+        let parent = self.slots[slot].parent;
+        let mut position = base + shift;
+        let mut res = if parent.is_none() {
+            *cluster_min = position.x;
+            base + advance
+        } else {
+            position = position + self.slots[slot].attach - self.slots[slot].with;
+            if (advance.x > 0 || position.x < 0) && position.x < *cluster_min {
+                *cluster_min = position.x;
+            }
+            let end = if advance.x > 0 { position.x + advance.x - shift.x } else { 0 };
+            Position::new(end, 0)
+        };
+        self.slots[slot].position = position;
+
+        if let Some(child) = self.slots[slot].child.filter(|&child| self.slots[child].parent == Some(slot)) {
+            let child_res = self.finalise(child, position, cluster_min, depth + 1);
+            if (parent.is_none() || advance.x > 0) && child_res.x > res.x {
+                res = child_res;
+            }
+        }
+        if parent.is_some() {
+            if let Some(sibling) = self.slots[slot].sibling.filter(|&sibling| self.slots[sibling].parent == parent) {
+                let sibling_res = self.finalise(sibling, base, cluster_min, depth + 1);
+                if sibling_res.x > res.x {
+                    res = sibling_res;
+                }
+            }
+        }
+
+        // Move the cluster so nothing hangs off the left of it
+        if parent.is_none() && *cluster_min < base.x {
+            let adjust = position.x - *cluster_min;
+            res.x += adjust;
+            self.slots[slot].position.x += adjust;
+            if let Some(child) = self.slots[slot].child {
+                self.shift_attached(child, adjust, 0);
+            }
+        }
+        res
+    }
+
+    fn shift_attached(&mut self, slot: usize, adjust: i32, depth: usize) {
+        if depth > 100 {
+            return;
+        }
+        self.slots[slot].position.x += adjust;
+        if let Some(child) = self.slots[slot].child {
+            self.shift_attached(child, adjust, depth + 1);
+        }
+        if let Some(sibling) = self.slots[slot].sibling {
+            self.shift_attached(sibling, adjust, depth + 1);
+        }
+    }
+
+    /// The positioned glyphs in visual order. `offsets` are the byte offsets
+    /// of the characters.
+    fn output(&self, offsets: &[usize]) -> Vec<ShapedGlyph> {
+        let mut order = Vec::new();
+        let mut current = self.first;
+        while let Some(slot) = current {
+            order.push(slot);
+            current = self.next(slot);
+        }
+        if self.current_rtl() {
+            order.reverse();
+        }
+
+        let mut pen = 0;
+        order.into_iter()
+            .map(|idx| {
+                let slot = &self.slots[idx];
+                let x_advance = if slot.parent.is_none() { slot.cluster_advance } else { 0 };
+                let glyph = ShapedGlyph {
+                    glyph_id: if slot.real_glyph_id != 0 { slot.real_glyph_id } else { slot.glyph_id } as u32,
+                    cluster: offsets.get(slot.before.min(slot.after)).cloned().unwrap_or(0) as u32,
+                    x_advance,
+                    y_advance: 0,
+                    x_offset: slot.position.x - pen,
+                    y_offset: slot.position.y,
+                };
+                pen += x_advance;
+                glyph
+            })
+            .collect()
+    }
+}
+
+/// Rules are tried longest first, then in the order they are in the font
+fn sort_rules(pass: &Pass, mut rules: Vec<u16>) -> Vec<u16> {
+    rules.retain(|&rule| (rule as usize) < pass.rules.len());
+    rules.sort_by(|&a, &b| {
+        pass.rules[b as usize].sort.cmp(&pass.rules[a as usize].sort).then(a.cmp(&b))
+    });
+    rules.dedup();
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse::Parse;
+    use tables::silf::tests::{build_silf, TestRule};
+    use test_utils::{load_font_buf, push_u16, push_u32, ROBOTO};
+
+    fn tables<'a>(silf: &'a [u8], feat: Option<&'a [u8]>) -> GraphiteTables<'a> {
+        GraphiteTables {
+            silf: Silf::parse(silf).1,
+            glat: None,
+            gloc: None,
+            feat: feat.map(|feat| GraphiteFeat::parse(feat).1),
+        }
+    }
+
+    fn glyph_ids(glyphs: &[ShapedGlyph]) -> Vec<u32> {
+        glyphs.iter().map(|glyph| glyph.glyph_id).collect()
+    }
+
+    #[test]
+    fn substitution_with_feature() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let gid = |c| font.get_glyph_id(c).unwrap();
+        let (a, b, c, e) = (gid('a'), gid('b'), gid('c'), gid('e'));
+
+        // a > e / _ b, only while feature 0 is on
+        let silf = build_silf(&[&[e as u16]], &[(&[TestRule {
+            glyphs: &[a as u16, b as u16],
+            // CNTXT_ITEM 0 { PUSH_FEAT 0 0 } POP_RET
+            constraint: &[34, 0, 3, 43, 0, 0, 48],
+            // PUT_GLYPH 0, NEXT, RET_ZERO
+            action: &[59, 0, 0, 25, 49],
+        }], 0)], 1);
+        let mut feat = Vec::new();
+        push_u32(&mut feat, 0x0002_0000);
+        push_u16(&mut feat, 1);
+        push_u16(&mut feat, 0);
+        push_u32(&mut feat, 0);
+        feat.extend_from_slice(b"altA");
+        push_u16(&mut feat, 2);
+        push_u16(&mut feat, 0);
+        push_u32(&mut feat, 28);
+        push_u16(&mut feat, 0);
+        push_u16(&mut feat, 256);
+        for &val in &[1, 257, 0, 258] {
+            push_u16(&mut feat, val);
+        }
+        let tables = tables(&silf, Some(&feat));
+        assert_eq!(tables.feat.as_ref().unwrap().features()[0].default_value, 1);
+
+        let shaped = shape(&font, &tables, "abac", TextDirection::Right, &[]).unwrap();
+        assert_eq!(glyph_ids(&shaped), vec![e, b, a, c]);
+        let clusters: Vec<u32> = shaped.iter().map(|glyph| glyph.cluster).collect();
+        assert_eq!(clusters, vec![0, 1, 2, 3]);
+
+        let off = Feature::new(b"altA", 0);
+        let shaped = shape(&font, &tables, "abac", TextDirection::Right, &[off]).unwrap();
+        assert_eq!(glyph_ids(&shaped), vec![a, b, a, c]);
+    }
+
+    #[test]
+    fn delete_and_insert() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let gid = |c| font.get_glyph_id(c).unwrap();
+        let (a, b, c, e) = (gid('a'), gid('b'), gid('c'), gid('e'));
+
+        let silf = build_silf(&[&[e as u16]], &[(&[
+            // b b > b _
+            TestRule {
+                glyphs: &[b as u16, b as u16],
+                constraint: &[],
+                // NEXT, DELETE, NEXT, RET_ZERO
+                action: &[25, 32, 25, 49],
+            },
+            // _ c > e c
+            TestRule {
+                glyphs: &[c as u16],
+                constraint: &[],
+                // INSERT, PUT_GLYPH 0, NEXT, NEXT, RET_ZERO
+                action: &[31, 59, 0, 0, 25, 25, 49],
+            },
+        ], 0)], 1);
+        let tables = tables(&silf, None);
+
+        let shaped = shape(&font, &tables, "abbc", TextDirection::Right, &[]).unwrap();
+        assert_eq!(glyph_ids(&shaped), vec![a, b, e, c]);
+        assert_eq!(shaped[3].cluster, 3);
+    }
+
+    #[test]
+    fn attachment() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let gid = |c| font.get_glyph_id(c).unwrap();
+        let (a, b) = (gid('a'), gid('b'));
+
+        // Attach b to a, 100 units right of a's origin and 50 down
+        let silf = build_silf(&[], &[(&[TestRule {
+            glyphs: &[a as u16, b as u16],
+            constraint: &[],
+            // NEXT, PUSH_BYTE -1, ATTR_SET_SLOT AttTo, PUSH_SHORT 100,
+            // ATTR_SET AttX, PUSH_SHORT -50, ATTR_SET AttY, RET_ZERO
+            action: &[25, 1, 0xFF, 38, 2, 3, 0, 100, 35, 3, 3, 0xFF, 0xCE, 35, 4, 49],
+        }], 0)], 0);
+        let tables = tables(&silf, None);
+
+        let plain = shape(&font, &tables, "ba", TextDirection::Right, &[]).unwrap();
+        let shaped = shape(&font, &tables, "abab", TextDirection::Right, &[]).unwrap();
+        assert_eq!(glyph_ids(&shaped), vec![a, b, a, b]);
+        assert_eq!(shaped[1].x_advance, 0);
+        assert_eq!(shaped[0].x_advance + shaped[1].x_offset, 100);
+        assert_eq!(shaped[1].y_offset, -50);
+        // b hangs past a, so the cluster is as wide as both
+        assert_eq!(shaped[0].x_advance, 100 + plain[0].x_advance);
+        assert_eq!(shaped[2].x_offset, 0);
+    }
+}
@@ -0,0 +1,527 @@
+//! The machine that runs Graphite's rule constraints and actions.
+//!
+//! Code is a stack machine over signed 32-bit values. It runs with a current
+//! slot, the "input slot", and a position in the slot map. Slot references
+//! in the code are offsets from that position.
+
+use super::{Segment, SlotMap, ATTR_ATT_TO, ATTR_POS_X, ATTR_POS_Y};
+
+const STACK_SIZE: usize = 1024;
+
+const NOP: u8 = 0;
+const PUSH_BYTE: u8 = 1;
+const PUSH_BYTE_U: u8 = 2;
+const PUSH_SHORT: u8 = 3;
+const PUSH_SHORT_U: u8 = 4;
+const PUSH_LONG: u8 = 5;
+const ADD: u8 = 6;
+const SUB: u8 = 7;
+const MUL: u8 = 8;
+const DIV: u8 = 9;
+const MIN: u8 = 10;
+const MAX: u8 = 11;
+const NEG: u8 = 12;
+const TRUNC8: u8 = 13;
+const TRUNC16: u8 = 14;
+const COND: u8 = 15;
+const AND: u8 = 16;
+const OR: u8 = 17;
+const NOT: u8 = 18;
+const EQUAL: u8 = 19;
+const NOT_EQ: u8 = 20;
+const LESS: u8 = 21;
+const GTR: u8 = 22;
+const LESS_EQ: u8 = 23;
+const GTR_EQ: u8 = 24;
+const NEXT: u8 = 25;
+const COPY_NEXT: u8 = 27;
+const PUT_GLYPH_8BIT_OBS: u8 = 28;
+const PUT_SUBS_8BIT_OBS: u8 = 29;
+const PUT_COPY: u8 = 30;
+const INSERT: u8 = 31;
+const DELETE: u8 = 32;
+const ASSOC: u8 = 33;
+const CNTXT_ITEM: u8 = 34;
+const ATTR_SET: u8 = 35;
+const ATTR_ADD: u8 = 36;
+const ATTR_SUB: u8 = 37;
+const ATTR_SET_SLOT: u8 = 38;
+const IATTR_SET_SLOT: u8 = 39;
+const PUSH_SLOT_ATTR: u8 = 40;
+const PUSH_GLYPH_ATTR_OBS: u8 = 41;
+const PUSH_GLYPH_METRIC: u8 = 42;
+const PUSH_FEAT: u8 = 43;
+const PUSH_ATT_TO_GATTR_OBS: u8 = 44;
+const PUSH_ATT_TO_GLYPH_METRIC: u8 = 45;
+const PUSH_ISLOT_ATTR: u8 = 46;
+const POP_RET: u8 = 48;
+const RET_ZERO: u8 = 49;
+const RET_TRUE: u8 = 50;
+const IATTR_SET: u8 = 51;
+const IATTR_ADD: u8 = 52;
+const IATTR_SUB: u8 = 53;
+const PUSH_PROC_STATE: u8 = 54;
+const PUSH_VERSION: u8 = 55;
+const PUT_SUBS: u8 = 56;
+const PUT_GLYPH: u8 = 59;
+const PUSH_GLYPH_ATTR: u8 = 60;
+const PUSH_ATT_TO_GLYPH_ATTR: u8 = 61;
+const BITOR: u8 = 62;
+const BITAND: u8 = 63;
+const BITNOT: u8 = 64;
+const SETBITS: u8 = 65;
+const SET_FEAT: u8 = 66;
+
+struct Machine<'c> {
+    code: &'c [u8],
+    ip: usize,
+    stack: Vec<i32>,
+    /// Index in the slot map of the current position
+    map: usize,
+    /// The slot being worked on. Usually the slot at `map`, but inserting and
+    /// deleting move it without moving `map`.
+    is: Option<usize>,
+    /// Index in the slot map of the slot the rule matched at
+    map_base: usize,
+    /// The slots have been positioned during this run
+    positioned: bool,
+}
+
+impl<'c> Machine<'c> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.code.get(self.ip)?;
+        self.ip += 1;
+        Some(byte)
+    }
+
+    fn signed_byte(&mut self) -> Option<i8> {
+        self.byte().map(|byte| byte as i8)
+    }
+
+    fn short(&mut self) -> Option<u16> {
+        Some(((self.byte()? as u16) << 8) | self.byte()? as u16)
+    }
+
+    fn push(&mut self, val: i32) -> Option<()> {
+        if self.stack.len() >= STACK_SIZE {
+            return None;
+        }
+        self.stack.push(val);
+        Some(())
+    }
+
+    fn pop(&mut self) -> Option<i32> {
+        self.stack.pop()
+    }
+
+    fn binary_op<F: Fn(i32, i32) -> Option<i32>>(&mut self, op: F) -> Option<()> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let res = op(a, b)?;
+        self.push(res)
+    }
+
+    fn is(&self) -> Option<usize> {
+        self.is
+    }
+
+    /// The slot `offset` places from the current position in the map
+    fn slot_at(&self, map: &SlotMap, offset: i8) -> Option<usize> {
+        let idx = self.map as isize + offset as isize;
+        if idx < 0 {
+            return None;
+        }
+        map.slots.get(idx as usize).cloned().unwrap_or(None)
+    }
+
+    fn position_for<'s, 'a>(&mut self, seg: &mut Segment<'s, 'a>, attr: u8) {
+        if (attr == ATTR_POS_X || attr == ATTR_POS_Y) && !self.positioned {
+            seg.position_slots();
+            self.positioned = true;
+        }
+    }
+}
+
+/// Run `code` with `map` the index in the slot map of the current slot.
+/// Returns the value the code returned, or `None` if it's invalid.
+///
+/// `map` is left where the code moved it.
+pub(super) fn run<'s, 'a>(seg: &mut Segment<'s, 'a>, slot_map: &mut SlotMap, code: &[u8],
+                          map: &mut usize) -> Option<i32> {
+    let mut m = Machine {
+        code,
+        ip: 0,
+        stack: Vec::new(),
+        map: *map,
+        is: slot_map.slots.get(*map).cloned().unwrap_or(None),
+        map_base: 1 + slot_map.context,
+        positioned: false,
+    };
+    let ret = execute(&mut m, seg, slot_map);
+    // Write the input slot back so the caller sees where the action ended
+    if let Some(entry) = slot_map.slots.get_mut(m.map) {
+        *entry = m.is;
+    }
+    *map = m.map;
+    ret
+}
+
+fn execute<'s, 'a>(m: &mut Machine, seg: &mut Segment<'s, 'a>, slot_map: &mut SlotMap) -> Option<i32> {
+    while let Some(op) = m.byte() {
+        match op {
+            NOP => (),
+            PUSH_BYTE => {
+                let val = m.signed_byte()? as i32;
+                m.push(val)?;
+            },
+            PUSH_BYTE_U => {
+                let val = m.byte()? as i32;
+                m.push(val)?;
+            },
+            PUSH_SHORT => {
+                let val = m.short()? as i16 as i32;
+                m.push(val)?;
+            },
+            PUSH_SHORT_U => {
+                let val = m.short()? as i32;
+                m.push(val)?;
+            },
+            PUSH_LONG => {
+                let high = m.short()? as u32;
+                let low = m.short()? as u32;
+                m.push(((high << 16) | low) as i32)?;
+            },
+            ADD => m.binary_op(|a, b| Some(a.wrapping_add(b)))?,
+            SUB => m.binary_op(|a, b| Some(a.wrapping_sub(b)))?,
+            MUL => m.binary_op(|a, b| Some(a.wrapping_mul(b)))?,
+            DIV => m.binary_op(|a, b| a.checked_div(b))?,
+            MIN => m.binary_op(|a, b| Some(a.min(b)))?,
+            MAX => m.binary_op(|a, b| Some(a.max(b)))?,
+            NEG => {
+                let val = m.pop()?;
+                m.push(val.wrapping_neg())?;
+            },
+            TRUNC8 => {
+                let val = m.pop()?;
+                m.push(val & 0xFF)?;
+            },
+            TRUNC16 => {
+                let val = m.pop()?;
+                m.push(val & 0xFFFF)?;
+            },
+            COND => {
+                let otherwise = m.pop()?;
+                let then = m.pop()?;
+                let cond = m.pop()?;
+                m.push(if cond != 0 { then } else { otherwise })?;
+            },
+            AND => m.binary_op(|a, b| Some((a != 0 && b != 0) as i32))?,
+            OR => m.binary_op(|a, b| Some((a != 0 || b != 0) as i32))?,
+            NOT => {
+                let val = m.pop()?;
+                m.push((val == 0) as i32)?;
+            },
+            EQUAL => m.binary_op(|a, b| Some((a == b) as i32))?,
+            NOT_EQ => m.binary_op(|a, b| Some((a != b) as i32))?,
+            LESS => m.binary_op(|a, b| Some((a < b) as i32))?,
+            GTR => m.binary_op(|a, b| Some((a > b) as i32))?,
+            LESS_EQ => m.binary_op(|a, b| Some((a <= b) as i32))?,
+            GTR_EQ => m.binary_op(|a, b| Some((a >= b) as i32))?,
+            NEXT => {
+                if m.map > slot_map.size() {
+                    return None;
+                }
+                if let Some(is) = m.is() {
+                    if Some(is) == slot_map.highwater {
+                        slot_map.highpassed = true;
+                    }
+                    m.is = seg.next(is);
+                }
+                m.map += 1;
+            },
+            COPY_NEXT => {
+                if let Some(is) = m.is() {
+                    m.is = seg.next(is);
+                }
+                m.map += 1;
+            },
+            PUT_GLYPH_8BIT_OBS => {
+                let class = m.byte()? as u16;
+                let glyph = seg.subtable.classes.glyph(class, 0);
+                seg.set_glyph(m.is()?, glyph);
+            },
+            PUT_SUBS_8BIT_OBS => {
+                let slot_ref = m.signed_byte()?;
+                let input = m.byte()? as u16;
+                let output = m.byte()? as u16;
+                substitute(m, seg, slot_map, slot_ref, input, output)?;
+            },
+            PUT_COPY => {
+                let slot_ref = m.signed_byte()?;
+                let is = m.is()?;
+                if !seg.slots[is].deleted {
+                    if let Some(source) = m.slot_at(slot_map, slot_ref).filter(|&source| source != is) {
+                        if seg.slots[is].parent.is_some() || seg.slots[is].child.is_some() {
+                            return None;
+                        }
+                        let mut copy = seg.slots[source].clone();
+                        copy.prev = seg.slots[is].prev;
+                        copy.next = seg.slots[is].next;
+                        copy.child = None;
+                        copy.sibling = None;
+                        seg.slots[is] = copy;
+                        if let Some(parent) = seg.slots[is].parent {
+                            seg.add_child(parent, is);
+                        }
+                    }
+                }
+                seg.slots[is].deleted = false;
+            },
+            INSERT => {
+                slot_map.max_size -= 1;
+                if slot_map.max_size <= 0 {
+                    return None;
+                }
+                insert(m, seg, slot_map);
+            },
+            DELETE => {
+                let is = m.is()?;
+                if seg.slots[is].deleted {
+                    return None;
+                }
+                let next = seg.next(is);
+                let prev = seg.prev(is);
+                seg.delete(is);
+                if Some(is) == slot_map.highwater {
+                    slot_map.set_highwater(next);
+                }
+                if prev.is_some() {
+                    m.is = prev;
+                }
+            },
+            ASSOC => {
+                let count = m.byte()?;
+                let mut range: Option<(usize, usize)> = None;
+                for _ in 0..count {
+                    let slot_ref = m.signed_byte()?;
+                    if let Some(slot) = m.slot_at(slot_map, slot_ref) {
+                        let slot = &seg.slots[slot];
+                        range = Some(match range {
+                            Some((before, after)) => (before.min(slot.before), after.max(slot.after)),
+                            None => (slot.before, slot.after),
+                        });
+                    }
+                }
+                if let Some((before, after)) = range {
+                    let is = m.is()?;
+                    seg.slots[is].before = before;
+                    seg.slots[is].after = after;
+                }
+            },
+            CNTXT_ITEM => {
+                // The code for one slot of a rule, skipped when it's run for
+                // another slot
+                let offset = m.signed_byte()? as isize;
+                let skip = m.byte()? as usize;
+                if m.map_base as isize + offset != m.map as isize {
+                    m.ip += skip;
+                    m.push(1)?;
+                }
+            },
+            ATTR_SET => {
+                let attr = m.byte()?;
+                let val = m.pop()?;
+                seg.set_attr(m.is()?, attr, 0, val, slot_map);
+            },
+            ATTR_ADD | ATTR_SUB => {
+                let attr = m.byte()?;
+                let val = m.pop()?;
+                m.position_for(seg, attr);
+                let is = m.is()?;
+                let current = seg.get_attr(is, attr, 0);
+                let res = if op == ATTR_ADD { current.wrapping_add(val) } else { current.wrapping_sub(val) };
+                seg.set_attr(is, attr, 0, res, slot_map);
+            },
+            ATTR_SET_SLOT => {
+                let attr = m.byte()?;
+                let offset = if attr == ATTR_ATT_TO { m.map as i32 - 1 } else { 0 };
+                let val = m.pop()? + offset;
+                seg.set_attr(m.is()?, attr, offset as u8, val, slot_map);
+            },
+            IATTR_SET_SLOT => {
+                let attr = m.byte()?;
+                let index = m.byte()?;
+                let offset = if attr == ATTR_ATT_TO { m.map as i32 - 1 } else { 0 };
+                let val = m.pop()? + offset;
+                seg.set_attr(m.is()?, attr, index, val, slot_map);
+            },
+            PUSH_SLOT_ATTR => {
+                let attr = m.byte()?;
+                let slot_ref = m.signed_byte()?;
+                m.position_for(seg, attr);
+                if let Some(slot) = m.slot_at(slot_map, slot_ref) {
+                    m.push(seg.get_attr(slot, attr, 0))?;
+                }
+            },
+            PUSH_ISLOT_ATTR => {
+                let attr = m.byte()?;
+                let slot_ref = m.signed_byte()?;
+                let index = m.byte()?;
+                m.position_for(seg, attr);
+                if let Some(slot) = m.slot_at(slot_map, slot_ref) {
+                    m.push(seg.get_attr(slot, attr, index))?;
+                }
+            },
+            PUSH_GLYPH_ATTR_OBS | PUSH_ATT_TO_GATTR_OBS => {
+                let attr = m.byte()? as u16;
+                let slot_ref = m.signed_byte()?;
+                push_glyph_attr(m, seg, slot_map, attr, slot_ref, op == PUSH_ATT_TO_GATTR_OBS)?;
+            },
+            PUSH_GLYPH_ATTR | PUSH_ATT_TO_GLYPH_ATTR => {
+                let attr = m.short()?;
+                let slot_ref = m.signed_byte()?;
+                push_glyph_attr(m, seg, slot_map, attr, slot_ref, op == PUSH_ATT_TO_GLYPH_ATTR)?;
+            },
+            PUSH_GLYPH_METRIC | PUSH_ATT_TO_GLYPH_METRIC => {
+                let metric = m.byte()?;
+                let slot_ref = m.signed_byte()?;
+                let attr_level = m.byte()?;
+                if let Some(mut slot) = m.slot_at(slot_map, slot_ref) {
+                    if op == PUSH_ATT_TO_GLYPH_METRIC {
+                        slot = seg.slots[slot].parent.unwrap_or(slot);
+                    }
+                    m.push(seg.glyph_metric(slot, metric, attr_level))?;
+                }
+            },
+            PUSH_FEAT => {
+                let feature = m.byte()?;
+                let slot_ref = m.signed_byte()?;
+                if let Some(slot) = m.slot_at(slot_map, slot_ref) {
+                    let val = seg.feature(seg.slots[slot].original, feature);
+                    m.push(val)?;
+                }
+            },
+            SET_FEAT => {
+                let feature = m.byte()?;
+                let slot_ref = m.signed_byte()?;
+                if let Some(slot) = m.slot_at(slot_map, slot_ref) {
+                    let val = m.pop()?;
+                    let original = seg.slots[slot].original;
+                    seg.set_feature(original, feature, val);
+                }
+            },
+            POP_RET => return m.pop(),
+            RET_ZERO => return Some(0),
+            RET_TRUE => return Some(1),
+            IATTR_SET => {
+                let attr = m.byte()?;
+                let index = m.byte()?;
+                let val = m.pop()?;
+                seg.set_attr(m.is()?, attr, index, val, slot_map);
+            },
+            IATTR_ADD | IATTR_SUB => {
+                let attr = m.byte()?;
+                let index = m.byte()?;
+                let val = m.pop()?;
+                m.position_for(seg, attr);
+                let is = m.is()?;
+                let current = seg.get_attr(is, attr, index);
+                let res = if op == IATTR_ADD { current.wrapping_add(val) } else { current.wrapping_sub(val) };
+                seg.set_attr(is, attr, index, res, slot_map);
+            },
+            PUSH_PROC_STATE => {
+                m.byte()?;
+                m.push(1)?;
+            },
+            PUSH_VERSION => m.push(0x0003_0000)?,
+            PUT_SUBS => {
+                let slot_ref = m.signed_byte()?;
+                let input = m.short()?;
+                let output = m.short()?;
+                substitute(m, seg, slot_map, slot_ref, input, output)?;
+            },
+            PUT_GLYPH => {
+                let class = m.short()?;
+                let glyph = seg.subtable.classes.glyph(class, 0);
+                seg.set_glyph(m.is()?, glyph);
+            },
+            BITOR => m.binary_op(|a, b| Some(a | b))?,
+            BITAND => m.binary_op(|a, b| Some(a & b))?,
+            BITNOT => {
+                let val = m.pop()?;
+                m.push(!val)?;
+            },
+            SETBITS => {
+                let mask = m.short()? as i32;
+                let value = m.short()? as i32;
+                let val = m.pop()?;
+                m.push((val & !mask) | value)?;
+            },
+            // Includes the opcodes graphite2 doesn't implement either
+            _ => return None,
+        }
+    }
+    Some(m.stack.last().cloned().unwrap_or(0))
+}
+
+/// Replace the input slot's glyph with the glyph in `output` at the index
+/// the referenced slot's glyph has in `input`
+fn substitute<'s, 'a>(m: &mut Machine, seg: &mut Segment<'s, 'a>, slot_map: &SlotMap,
+                      slot_ref: i8, input: u16, output: u16) -> Option<()> {
+    if let Some(slot) = m.slot_at(slot_map, slot_ref) {
+        let classes = &seg.subtable.classes;
+        let glyph = classes.index(input, seg.slots[slot].glyph_id)
+            .map(|index| classes.glyph(output, index))
+            .unwrap_or(0);
+        seg.set_glyph(m.is()?, glyph);
+    }
+    Some(())
+}
+
+fn push_glyph_attr<'s, 'a>(m: &mut Machine, seg: &Segment<'s, 'a>, slot_map: &SlotMap, attr: u16,
+                           slot_ref: i8, attached_to: bool) -> Option<()> {
+    if let Some(mut slot) = m.slot_at(slot_map, slot_ref) {
+        if attached_to {
+            slot = seg.slots[slot].parent.unwrap_or(slot);
+        }
+        m.push(seg.glyph_attr(seg.slots[slot].glyph_id, attr))?;
+    }
+    Some(())
+}
+
+/// Insert a new slot before the input slot
+fn insert<'s, 'a>(m: &mut Machine, seg: &mut Segment<'s, 'a>, slot_map: &mut SlotMap) {
+    // Deleted slots aren't in the list any more
+    let mut before = m.is();
+    while let Some(slot) = before.filter(|&slot| seg.slots[slot].deleted) {
+        before = seg.next(slot);
+    }
+    let original = match before {
+        Some(slot) => seg.slots[slot].original,
+        None => seg.last.map(|last| seg.slots[last].original).unwrap_or(0),
+    };
+    let new = seg.new_slot(0, original);
+    let prev = match before {
+        Some(slot) => seg.prev(slot),
+        None => seg.last,
+    };
+    seg.link_after(prev, new);
+
+    // The new slot sits between the characters of its neighbours
+    let (char_before, char_after) = match (prev, before) {
+        (Some(prev), Some(next)) => (seg.slots[prev].after, seg.slots[next].before),
+        (None, Some(next)) => (seg.slots[next].before, seg.slots[next].before),
+        (Some(prev), None) => (seg.slots[prev].before, seg.slots[prev].after),
+        (None, None) => (0, 0),
+    };
+    seg.slots[new].before = char_before;
+    seg.slots[new].after = char_after;
+
+    if m.is() == slot_map.highwater {
+        slot_map.highpassed = false;
+    }
+    m.is = Some(new);
+    if m.map != 0 {
+        m.map -= 1;
+    }
+}
@@ -0,0 +1,596 @@
+// Shaping for the Indic scripts, currently only Devanagari.
+// https://docs.microsoft.com/en-us/typography/script-development/devanagari
+//
+// Text is split into syllables, then each syllable is reordered so that the
+// glyphs are in the order the font's lookups expect: pre-base matras go in
+// front of the consonants, and reph moves from the start to after the base.
+
+use font::{Font, GetTable};
+use tables::gsub::GSUB;
+use tables::layout::Tag;
+use super::Shaper;
+use super::buffer::{Buffer, GlyphInfo};
+use super::plan::{PlanBuilder, ShapePlan};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub(crate) enum Category {
+    Other = 0,
+    Consonant,
+    /// Consonant that can form a reph or below-base form
+    Ra,
+    Vowel,
+    Matra,
+    Nukta,
+    Halant,
+    /// Anusvara, candrabindu, visarga, and other syllable modifiers
+    Modifier,
+    Zwj,
+    Zwnj,
+    /// Characters that can stand in for a consonant, like the dotted circle
+    Placeholder,
+}
+
+/// Where a character ends up in its syllable, in the order they are sorted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub(crate) enum Position {
+    RaToBecomeReph = 1,
+    PreMatra,
+    PreConsonant,
+    BaseConsonant,
+    AboveMatra,
+    BelowConsonant,
+    BelowMatra,
+    PostConsonant,
+    PostMatra,
+    SyllableModifier,
+    End,
+}
+
+impl Category {
+    fn from_u8(val: u8) -> Category {
+        use self::Category::*;
+        [Other, Consonant, Ra, Vowel, Matra, Nukta, Halant, Modifier, Zwj, Zwnj, Placeholder]
+            .get(val as usize)
+            .cloned()
+            .unwrap_or(Other)
+    }
+
+    fn is_consonant(&self) -> bool {
+        match self {
+            Category::Consonant | Category::Ra | Category::Placeholder => true,
+            _ => false,
+        }
+    }
+}
+
+impl Position {
+    fn from_u8(val: u8) -> Position {
+        use self::Position::*;
+        [RaToBecomeReph, PreMatra, PreConsonant, BaseConsonant, AboveMatra, BelowConsonant,
+         BelowMatra, PostConsonant, PostMatra, SyllableModifier, End]
+            .get((val as usize).wrapping_sub(1))
+            .cloned()
+            .unwrap_or(End)
+    }
+}
+
+/// Where a script puts the reph after reordering
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RephPosition {
+    /// After the base and below-base forms, before post-base forms
+    BeforePost,
+    /// At the end of the syllable, before syllable modifiers, as in Telugu
+    /// and Kannada
+    #[allow(dead_code)]
+    BeforeModifiers,
+}
+
+/// Everything that differs between the Indic scripts
+#[derive(Debug)]
+pub(crate) struct IndicConfig {
+    /// Tag used by the old shaping spec
+    pub script: Tag,
+    /// Tag used by the new shaping spec, preferred if the font has it
+    pub new_script: Tag,
+    pub reph_position: RephPosition,
+    /// Category and matra position of a character in the script's block
+    pub categorize: fn(char) -> Option<(Category, Position)>,
+}
+
+impl PartialEq for IndicConfig {
+    fn eq(&self, other: &IndicConfig) -> bool {
+        self.script == other.script
+    }
+}
+
+pub(crate) const DEVANAGARI: IndicConfig = IndicConfig {
+    script: Tag(*b"deva"),
+    new_script: Tag(*b"dev2"),
+    reph_position: RephPosition::BeforePost,
+    categorize: devanagari_category,
+};
+
+const CONFIGS: &[&IndicConfig] = &[&DEVANAGARI];
+
+pub(crate) fn config_for_script(script: Tag) -> Option<&'static IndicConfig> {
+    CONFIGS.iter()
+        .find(|config| config.script == script || config.new_script == script)
+        .cloned()
+}
+
+fn devanagari_category(ch: char) -> Option<(Category, Position)> {
+    use self::Category::*;
+    use self::Position::*;
+    let cat = match ch as u32 {
+        0x0900..=0x0903 => (Modifier, SyllableModifier),
+        0x0904..=0x0914 => (Vowel, End),
+        0x0930 => (Ra, BaseConsonant),
+        0x0915..=0x0939 => (Consonant, BaseConsonant),
+        0x093A => (Matra, AboveMatra),
+        0x093B => (Matra, PostMatra),
+        0x093C => (Nukta, End),
+        0x093E => (Matra, PostMatra),
+        0x093F => (Matra, PreMatra),
+        0x0940 => (Matra, PostMatra),
+        0x0941..=0x0944 => (Matra, BelowMatra),
+        0x0945..=0x0948 => (Matra, AboveMatra),
+        0x0949..=0x094C => (Matra, PostMatra),
+        0x094D => (Halant, End),
+        0x094E => (Matra, PreMatra),
+        0x094F => (Matra, PostMatra),
+        0x0951..=0x0954 => (Modifier, SyllableModifier),
+        0x0955 => (Matra, AboveMatra),
+        0x0956..=0x0957 => (Matra, BelowMatra),
+        0x0958..=0x095F => (Consonant, BaseConsonant),
+        0x0960..=0x0961 => (Vowel, End),
+        0x0962..=0x0963 => (Matra, BelowMatra),
+        0x0972..=0x0977 => (Vowel, End),
+        0x0978..=0x097F => (Consonant, BaseConsonant),
+        _ => return None,
+    };
+    Some(cat)
+}
+
+/// Categories of characters shared by all the Indic scripts
+fn common_category(ch: char) -> Option<(Category, Position)> {
+    match ch {
+        '\u{200C}' => Some((Category::Zwnj, Position::End)),
+        '\u{200D}' => Some((Category::Zwj, Position::End)),
+        '\u{25CC}' | '\u{00A0}' => Some((Category::Placeholder, Position::BaseConsonant)),
+        _ => None,
+    }
+}
+
+fn category(info: &GlyphInfo) -> Category {
+    Category::from_u8(info.category)
+}
+
+fn position(info: &GlyphInfo) -> Position {
+    Position::from_u8(info.position)
+}
+
+/// Scripts can be in the font under their old or new tag
+pub(crate) fn layout_script<'a>(font: &Font<'a>, config: &IndicConfig) -> Tag {
+    let gsub: Option<GSUB> = font.get_table();
+    let has_new = gsub
+        .and_then(|gsub| gsub.script_list())
+        .map(|scripts| scripts.script(config.new_script).is_some())
+        .unwrap_or(false);
+    if has_new { config.new_script } else { config.script }
+}
+
+/// Features that form conjuncts and the like, each applied in its own stage
+const BASIC_FEATURES: &[(&[u8; 4], bool)] = &[
+    (b"nukt", true),
+    (b"akhn", true),
+    (b"rphf", false),
+    (b"rkrf", true),
+    (b"pref", false),
+    (b"blwf", false),
+    (b"abvf", false),
+    (b"half", false),
+    (b"pstf", false),
+    (b"vatu", true),
+    (b"cjct", true),
+];
+
+/// Presentation features, applied together once the syllables are in order
+const OTHER_FEATURES: &[&[u8; 4]] = &[b"init", b"pres", b"abvs", b"blws", b"psts", b"haln"];
+
+pub(crate) fn collect_features(builder: &mut PlanBuilder) {
+    builder.add_gsub_pause(Some(initial_reordering));
+    for &(tag, global) in BASIC_FEATURES {
+        builder.add_feature(Tag::new(tag), global);
+        builder.add_gsub_pause(None);
+    }
+    builder.add_gsub_pause(Some(final_reordering));
+    builder.add_global_features(OTHER_FEATURES);
+}
+
+/// Categorize each character and split the text into syllables
+pub(crate) fn setup_masks(config: &IndicConfig, buffer: &mut Buffer) {
+    for info in buffer.info.iter_mut() {
+        let (cat, pos) = common_category(info.codepoint)
+            .or_else(|| (config.categorize)(info.codepoint))
+            .unwrap_or((Category::Other, Position::End));
+        info.category = cat as u8;
+        info.position = pos as u8;
+    }
+
+    let mut syllable: u8 = 0;
+    let mut start = 0;
+    while start < buffer.len() {
+        let (end, kind) = find_syllable(&buffer.info, start);
+        syllable = syllable.wrapping_add(1).max(1);
+        for info in &mut buffer.info[start..end] {
+            info.syllable = syllable;
+        }
+        // Only consonant syllables are reordered, flag the rest
+        if kind != SyllableKind::Consonant {
+            for info in &mut buffer.info[start..end] {
+                info.position = Position::End as u8;
+            }
+        }
+        start = end;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SyllableKind {
+    Consonant,
+    Vowel,
+    /// Starts with something that should have had a consonant before it
+    Broken,
+    NonIndic,
+}
+
+/// Returns the end of the syllable starting at `start`
+fn find_syllable(infos: &[GlyphInfo], start: usize) -> (usize, SyllableKind) {
+    let cat = |idx: usize| infos.get(idx).map(category);
+    let mut idx = start;
+
+    let kind = match cat(idx).unwrap() {
+        c if c.is_consonant() => {
+            // (C N? (H (ZWJ|ZWNJ)? | ZWJ H))* C N?
+            loop {
+                idx += 1;
+                if cat(idx) == Some(Category::Nukta) {
+                    idx += 1;
+                }
+                let next = match (cat(idx), cat(idx + 1), cat(idx + 2)) {
+                    (Some(Category::Halant), Some(Category::Zwj), _)
+                    | (Some(Category::Halant), Some(Category::Zwnj), _)
+                    | (Some(Category::Zwj), Some(Category::Halant), _) => idx + 2,
+                    (Some(Category::Halant), _, _) => idx + 1,
+                    _ => break,
+                };
+                idx = next;
+                match cat(idx) {
+                    Some(c) if c.is_consonant() => (),
+                    // Ends in a dead consonant
+                    _ => break,
+                }
+            }
+            SyllableKind::Consonant
+        },
+        Category::Vowel => {
+            idx += 1;
+            if cat(idx) == Some(Category::Nukta) {
+                idx += 1;
+            }
+            SyllableKind::Vowel
+        },
+        Category::Matra | Category::Nukta | Category::Halant | Category::Modifier => {
+            SyllableKind::Broken
+        },
+        _ => return (start + 1, SyllableKind::NonIndic),
+    };
+
+    // Matras, then syllable modifiers
+    while let Some(Category::Matra) = cat(idx) {
+        idx += 1;
+        if cat(idx) == Some(Category::Nukta) {
+            idx += 1;
+        }
+    }
+    if kind != SyllableKind::Broken && cat(idx) == Some(Category::Halant) {
+        idx += 1;
+    }
+    while let Some(Category::Modifier) = cat(idx) {
+        idx += 1;
+    }
+    // A lone nukta or halant still makes a syllable
+    (idx.max(start + 1), kind)
+}
+
+/// The range of each syllable in the buffer
+fn syllables(buffer: &Buffer) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < buffer.len() {
+        let syllable = buffer.info[start].syllable;
+        let end = (start..buffer.len())
+            .find(|&idx| buffer.info[idx].syllable != syllable)
+            .unwrap_or(buffer.len());
+        ranges.push((start, end));
+        start = end;
+    }
+    ranges
+}
+
+fn is_consonant_syllable(buffer: &Buffer, start: usize) -> bool {
+    category(&buffer.info[start]).is_consonant() && position(&buffer.info[start]) != Position::End
+}
+
+/// Before the basic features: find the base consonant, mark the reph, move
+/// pre-base matras to the start, and enable the basic features on the glyphs
+/// they apply to.
+fn initial_reordering(plan: &ShapePlan, buffer: &mut Buffer) {
+    for (start, end) in syllables(buffer) {
+        if is_consonant_syllable(buffer, start) {
+            reorder_consonant_syllable(plan, buffer, start, end);
+        }
+    }
+}
+
+fn reorder_consonant_syllable(plan: &ShapePlan, buffer: &mut Buffer, start: usize, end: usize) {
+    let cats: Vec<Category> = buffer.info[start..end].iter().map(category).collect();
+    let cat = |idx: usize| cats[idx - start];
+
+    // Ra, Halant at the start becomes a reph if there is a consonant after it
+    let has_reph = end - start >= 3
+        && cat(start) == Category::Ra
+        && cat(start + 1) == Category::Halant
+        && cat(start + 2) != Category::Zwj
+        && (start + 2..end).any(|idx| cat(idx).is_consonant());
+    let limit = if has_reph { start + 2 } else { start };
+
+    // The base is the last consonant that doesn't take a below-base form
+    let mut base = limit;
+    for idx in (limit..end).rev() {
+        if !cat(idx).is_consonant() {
+            continue;
+        }
+        // Ra after a halant takes its below-base form (rakaar), unless it is
+        // the only consonant left
+        let is_rakaar = cat(idx) == Category::Ra && idx > limit + 1
+            && cat(idx - 1) == Category::Halant
+            && (idx + 1 == end || cat(idx + 1) != Category::Halant);
+        if is_rakaar {
+            buffer.info[idx].position = Position::BelowConsonant as u8;
+            continue;
+        }
+        base = idx;
+        break;
+    }
+
+    for idx in start..end {
+        let info = &buffer.info[idx];
+        let pos = if idx < limit {
+            Position::RaToBecomeReph
+        } else if idx < base {
+            match cat(idx) {
+                Category::Matra => position(info),
+                _ => Position::PreConsonant,
+            }
+        } else if idx == base {
+            Position::BaseConsonant
+        } else {
+            match cat(idx) {
+                Category::Matra | Category::Modifier => position(info),
+                Category::Ra if position(info) == Position::BelowConsonant => Position::BelowConsonant,
+                c if c.is_consonant() => Position::PostConsonant,
+                // Nukta and halant go with whatever they follow
+                _ => position(&buffer.info[idx - 1]).min(Position::PostConsonant).max(Position::BaseConsonant),
+            }
+        };
+        buffer.info[idx].position = pos as u8;
+    }
+
+    // Enable the basic features on the parts of the syllable they shape
+    let rphf = plan.mask(Tag::new(b"rphf"));
+    let half = plan.mask(Tag::new(b"half"));
+    let post_base = plan.mask(Tag::new(b"blwf")) | plan.mask(Tag::new(b"abvf"))
+        | plan.mask(Tag::new(b"pstf")) | plan.mask(Tag::new(b"pref"));
+    for idx in start..end {
+        let info = &mut buffer.info[idx];
+        info.mask |= if idx < limit {
+            rphf
+        } else if idx < base {
+            half
+        } else if idx > base {
+            post_base
+        } else {
+            0
+        };
+    }
+
+    // Pre-base matras move to the front of the syllable, after the reph
+    let mut sorted: Vec<GlyphInfo> = buffer.info[start..end].to_vec();
+    sorted.sort_by_key(|info| match position(info) {
+        Position::RaToBecomeReph => 0,
+        Position::PreMatra => 1,
+        _ => 2,
+    });
+    buffer.info.splice(start..end, sorted);
+    buffer.merge_clusters(start, end);
+}
+
+/// After the basic features: put pre-base matras and the reph in their final
+/// places now that we know which conjuncts formed.
+fn final_reordering(plan: &ShapePlan, buffer: &mut Buffer) {
+    let config = match plan.shaper {
+        Shaper::Indic(config) => config,
+        _ => return,
+    };
+    for (start, end) in syllables(buffer) {
+        if position(&buffer.info[start]) == Position::End {
+            continue;
+        }
+        let base = match find_base(buffer, start, end) {
+            Some(base) => base,
+            None => continue,
+        };
+        let base = move_pre_base_matras(buffer, start, base);
+        move_reph(config, buffer, start, end, base);
+        buffer.merge_clusters(start, end);
+    }
+}
+
+fn find_base(buffer: &Buffer, start: usize, end: usize) -> Option<usize> {
+    (start..end)
+        .find(|&idx| position(&buffer.info[idx]) == Position::BaseConsonant)
+        // The base was part of a conjunct with the consonants before it
+        .or_else(|| (start..end).rev()
+            .find(|&idx| position(&buffer.info[idx]) == Position::PreConsonant))
+}
+
+/// If a dead consonant didn't form a half form, the matra goes after it.
+///
+/// Returns the index of the base after moving.
+fn move_pre_base_matras(buffer: &mut Buffer, start: usize, base: usize) -> usize {
+    let matra = match (start..base).find(|&idx| position(&buffer.info[idx]) == Position::PreMatra) {
+        Some(matra) => matra,
+        None => return base,
+    };
+    let last_halant = (matra + 1..base)
+        .rev()
+        .find(|&idx| category(&buffer.info[idx]) == Category::Halant);
+    if let Some(halant) = last_halant {
+        let info = buffer.info.remove(matra);
+        buffer.info.insert(halant, info);
+    }
+    base
+}
+
+fn move_reph(config: &IndicConfig, buffer: &mut Buffer, start: usize, end: usize, base: usize) {
+    if position(&buffer.info[start]) != Position::RaToBecomeReph {
+        return;
+    }
+    // If rphf didn't apply, the Ra and Halant are still separate glyphs
+    if start + 1 < end && position(&buffer.info[start + 1]) == Position::RaToBecomeReph {
+        return;
+    }
+
+    let is_target = |info: &GlyphInfo| match config.reph_position {
+        RephPosition::BeforePost => position(info) >= Position::PostConsonant,
+        RephPosition::BeforeModifiers => position(info) == Position::SyllableModifier,
+    };
+    let target = (base + 1..end)
+        .find(|&idx| is_target(&buffer.info[idx]))
+        .unwrap_or(end);
+
+    let reph = buffer.info.remove(start);
+    buffer.info.insert(target - 1, reph);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use render::compositor::TextDirection;
+    use shape::build_plan;
+    use test_utils::load_font_buf;
+
+    fn reordered(text: &str) -> String {
+        // The font doesn't matter, only the order of the characters is checked
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        let plan = build_plan(&font, DEVANAGARI.script, Tag::new(b"dflt"), &[]);
+        let mut buffer = Buffer::from_text(&font, None, text, plan.direction);
+        setup_masks(&DEVANAGARI, &mut buffer);
+        initial_reordering(&plan, &mut buffer);
+        final_reordering(&plan, &mut buffer);
+        buffer.info.iter().map(|info| info.codepoint).collect()
+    }
+
+    #[test]
+    fn syllable_boundaries() {
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        // नमस्ते: na | ma | sa halant ta e-matra
+        let mut buffer = Buffer::from_text(&font, None, "नमस्ते", TextDirection::Right);
+        setup_masks(&DEVANAGARI, &mut buffer);
+        let ids: Vec<u8> = buffer.info.iter().map(|info| info.syllable).collect();
+        assert_eq!(ids, vec![1, 2, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn pre_base_matra() {
+        // कि: ka i-matra, the matra goes first
+        assert_eq!(reordered("\u{0915}\u{093F}"), "\u{093F}\u{0915}");
+        // स्कि: without a half form the dead consonant is shown first
+        assert_eq!(reordered("\u{0938}\u{094D}\u{0915}\u{093F}"),
+                   "\u{0938}\u{094D}\u{093F}\u{0915}");
+
+        // With a half form the matra goes before the whole conjunct
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        let plan = build_plan(&font, DEVANAGARI.script, Tag::new(b"dflt"), &[]);
+        let mut buffer = Buffer::from_text(&font, None, "\u{0938}\u{094D}\u{0915}\u{093F}",
+                                           plan.direction);
+        setup_masks(&DEVANAGARI, &mut buffer);
+        initial_reordering(&plan, &mut buffer);
+        // Pretend half turned Sa, Halant into a single glyph
+        buffer.info.remove(2);
+        final_reordering(&plan, &mut buffer);
+        let order: String = buffer.info.iter().map(|info| info.codepoint).collect();
+        assert_eq!(order, "\u{093F}\u{0938}\u{0915}");
+    }
+
+    #[test]
+    fn reph_moves_after_base() {
+        // र्क: the reph can't form with this font, so it stays in place
+        assert_eq!(reordered("\u{0930}\u{094D}\u{0915}"), "\u{0930}\u{094D}\u{0915}");
+
+        // Once the Ra and Halant ligate it moves after the base
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        let plan = build_plan(&font, DEVANAGARI.script, Tag::new(b"dflt"), &[]);
+        let mut buffer = Buffer::from_text(&font, None, "\u{0930}\u{094D}\u{0915}\u{0902}",
+                                           plan.direction);
+        setup_masks(&DEVANAGARI, &mut buffer);
+        initial_reordering(&plan, &mut buffer);
+        // Pretend rphf turned Ra, Halant into a single glyph
+        buffer.info.remove(1);
+        final_reordering(&plan, &mut buffer);
+        let order: String = buffer.info.iter().map(|info| info.codepoint).collect();
+        assert_eq!(order, "\u{0915}\u{0930}\u{0902}");
+    }
+
+    #[test]
+    fn reph_positions() {
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        let plan = build_plan(&font, DEVANAGARI.script, Tag::new(b"dflt"), &[]);
+        let reph_after = |config: &IndicConfig| {
+            // र्का + anusvara
+            let mut buffer = Buffer::from_text(&font, None, "\u{0930}\u{094D}\u{0915}\u{093E}\u{0902}",
+                                               plan.direction);
+            setup_masks(config, &mut buffer);
+            initial_reordering(&plan, &mut buffer);
+            // Pretend rphf turned Ra, Halant into a single glyph
+            buffer.info.remove(1);
+            move_reph(config, &mut buffer, 0, 4, 1);
+            buffer.info.iter().map(|info| info.codepoint).collect::<String>()
+        };
+
+        assert_eq!(reph_after(&DEVANAGARI), "\u{0915}\u{0930}\u{093E}\u{0902}");
+        let at_end = IndicConfig { reph_position: RephPosition::BeforeModifiers, ..DEVANAGARI };
+        assert_eq!(reph_after(&at_end), "\u{0915}\u{093E}\u{0930}\u{0902}");
+    }
+
+    #[test]
+    fn shape_word() {
+        use shape::shape;
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+        // हिन्दी: the i-matra is moved in front of ha, both in one cluster
+        let shaped = shape(&font, "\u{0939}\u{093F}\u{0928}\u{094D}\u{0926}\u{0940}",
+                           Tag::new(b"deva"), Tag::new(b"dflt"), &[]);
+        assert_eq!(shaped.glyphs.len(), 6);
+        assert_eq!(shaped.glyphs[0].cluster, 0);
+        assert_eq!(shaped.glyphs[1].cluster, 0);
+    }
+}
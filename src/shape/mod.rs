@@ -0,0 +1,311 @@
+//! Turn a string into positioned glyphs using the font's `GSUB` and `GPOS`
+//! tables.
+//!
+//! Loosely follows the way HarfBuzz does it: characters are mapped to glyphs,
+//! features are collected into a plan of lookups for the script and language,
+//! substitutions are run, glyphs get their advances from `hmtx`, then
+//! positioning lookups adjust them.
+//!
+//! Fonts that only have Apple's `morx` and `kerx` tables are run through
+//! their state machines instead, and fonts with Graphite's `Silf` table are
+//! shaped by running its rules.
+
+use font::{Font, GetTable};
+use render::compositor::TextDirection;
+use tables::ankr::Ankr;
+use tables::kerx::Kerx;
+use tables::layout::Tag;
+use tables::morx::Morx;
+
+mod aat;
+mod apply;
+mod arabic;
+mod buffer;
+mod graphite;
+mod indic;
+mod plan;
+
+use self::buffer::Buffer;
+use self::plan::{PlanBuilder, ShapePlan};
+
+/// Turn a feature on or off, either for the whole text or a range of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Feature {
+    pub tag: Tag,
+    /// 0 turns the feature off, 1 turns it on. For alternate substitutions
+    /// this picks which alternate to use.
+    pub value: u32,
+    /// Byte offset into the text where the feature starts
+    pub start: usize,
+    /// Byte offset into the text where the feature ends (exclusive)
+    pub end: usize,
+}
+
+impl Feature {
+    /// A feature for the whole text
+    pub fn new(tag: &[u8; 4], value: u32) -> Feature {
+        Feature::with_range(tag, value, 0, ::std::usize::MAX)
+    }
+
+    pub fn with_range(tag: &[u8; 4], value: u32, start: usize, end: usize) -> Feature {
+        Feature {
+            tag: Tag::new(tag),
+            value,
+            start,
+            end,
+        }
+    }
+}
+
+/// A glyph after shaping. All values are in font units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapedGlyph {
+    pub glyph_id: u32,
+    /// Byte offset into the text of the first character that became this glyph
+    pub cluster: u32,
+    /// How far to move the pen after drawing the glyph
+    pub x_advance: i32,
+    pub y_advance: i32,
+    /// Where to draw the glyph relative to the pen
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+/// The output of shaping, glyphs are in visual order (left to right).
+///
+/// A `RenderedText` for right to left text places glyphs from right to left,
+/// so give it the glyphs in reverse.
+#[derive(Debug)]
+pub struct GlyphBuffer {
+    pub glyphs: Vec<ShapedGlyph>,
+    pub direction: TextDirection,
+}
+
+impl GlyphBuffer {
+    /// Sum of the advances
+    pub fn advance_width(&self) -> i32 {
+        self.glyphs.iter().map(|glyph| glyph.x_advance).sum()
+    }
+}
+
+/// Script-specific shaping logic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Shaper {
+    Default,
+    Arabic,
+    Indic(&'static indic::IndicConfig),
+}
+
+impl Shaper {
+    fn for_script(script: Tag) -> Shaper {
+        if let Some(config) = indic::config_for_script(script) {
+            return Shaper::Indic(config);
+        }
+        match &script.0 {
+            b"arab" => Shaper::Arabic,
+            _ => Shaper::Default,
+        }
+    }
+
+    /// The script tag to look for in the font
+    fn layout_script<'a>(&self, font: &Font<'a>, script: Tag) -> Tag {
+        match self {
+            Shaper::Indic(config) => indic::layout_script(font, config),
+            _ => script,
+        }
+    }
+
+    fn collect_features(&self, builder: &mut PlanBuilder, direction: TextDirection) {
+        match direction {
+            TextDirection::Left => builder.add_global_features(&[b"rtla", b"rtlm"]),
+            _ => builder.add_global_features(&[b"ltra", b"ltrm"]),
+        }
+        builder.add_global_features(&[b"ccmp", b"locl"]);
+        builder.add_gsub_pause(None);
+
+        match self {
+            Shaper::Default => (),
+            Shaper::Arabic => arabic::collect_features(builder),
+            Shaper::Indic(_) => indic::collect_features(builder),
+        }
+
+        builder.add_global_features(&[b"rlig", b"rclt", b"calt", b"clig", b"liga"]);
+        builder.add_global_features(&[b"kern", b"mark", b"mkmk", b"curs", b"dist", b"abvm", b"blwm"]);
+    }
+
+    /// Enable the shaper's non-global features on the glyphs that need them
+    fn setup_masks(&self, plan: &ShapePlan, buffer: &mut Buffer) {
+        match self {
+            Shaper::Default => (),
+            Shaper::Arabic => arabic::setup_masks(plan, buffer),
+            Shaper::Indic(config) => indic::setup_masks(config, buffer),
+        }
+    }
+}
+
+/// Scripts that are written right to left
+const RTL_SCRIPTS: &[&[u8; 4]] = &[
+    b"arab", b"hebr", b"syrc", b"thaa", b"nko ", b"adlm", b"mand", b"samr",
+    b"rohg", b"yezi",
+];
+
+fn direction_for_script(script: Tag) -> TextDirection {
+    if RTL_SCRIPTS.iter().any(|&tag| Tag::new(tag) == script) {
+        TextDirection::Left
+    } else {
+        TextDirection::Right
+    }
+}
+
+fn build_plan<'a>(font: &Font<'a>, script: Tag, language: Tag,
+                  features: &[Feature]) -> ShapePlan<'a> {
+    let direction = direction_for_script(script);
+    let shaper = Shaper::for_script(script);
+    let mut builder = PlanBuilder::new();
+    // Variation alternates come before everything else
+    builder.add_global_features(&[b"rvrn"]);
+    builder.add_gsub_pause(None);
+    shaper.collect_features(&mut builder, direction);
+    let script = shaper.layout_script(font, script);
+    builder.compile(font, script, language, direction, shaper, features)
+}
+
+/// Shape `text` written in `script` and `language` (OpenType tags, e.g.
+/// `latn` and `dflt`).
+///
+/// `features` are applied on top of the defaults for the script.
+pub fn shape<'a>(font: &Font<'a>, text: &str, script: Tag, language: Tag,
+                 features: &[Feature]) -> GlyphBuffer {
+    // Graphite fonts only shape correctly with their own rules
+    if let Some(tables) = graphite::GraphiteTables::from_font(font) {
+        let direction = direction_for_script(script);
+        if let Some(glyphs) = graphite::shape(font, &tables, text, direction, features) {
+            return GlyphBuffer { glyphs, direction };
+        }
+    }
+
+    let plan = build_plan(font, script, language, features);
+
+    let mut buffer = Buffer::from_text(font, plan.gdef.as_ref(), text, plan.direction);
+    plan.setup_masks(&mut buffer);
+    plan.shaper.setup_masks(&plan, &mut buffer);
+    // AAT tables are only used if there's no OpenType equivalent
+    let morx: Option<Morx> = if plan.gsub.is_none() { font.get_table() } else { None };
+    match morx {
+        Some(morx) => aat::substitute(&morx, plan.gdef.as_ref(), features, &mut buffer),
+        None => plan.substitute(&mut buffer),
+    }
+
+    buffer.init_positions(font);
+    buffer.zero_mark_advances();
+    plan.position(&mut buffer);
+    let kerning_off = features.iter()
+        .any(|feature| feature.tag == Tag::new(b"kern") && feature.value == 0 &&
+             feature.start == 0 && feature.end == ::std::usize::MAX);
+    if plan.gpos.is_none() && !kerning_off {
+        let kerx: Option<Kerx> = font.get_table();
+        if let Some(kerx) = kerx {
+            let ankr: Option<Ankr> = font.get_table();
+            aat::position(&kerx, ankr.as_ref(), &mut buffer);
+        }
+    }
+    buffer.propagate_attachment_offsets();
+
+    if plan.direction == TextDirection::Left {
+        buffer.reverse();
+    }
+
+    GlyphBuffer {
+        direction: plan.direction,
+        glyphs: buffer.into_glyphs(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tables::layout::DEFAULT_LANGUAGE;
+    use test_utils::{load_font_buf, ROBOTO};
+
+    fn latn() -> Tag {
+        Tag::new(b"latn")
+    }
+
+    #[test]
+    fn ligature() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let f = font.get_glyph_id('f').unwrap();
+
+        let shaped = shape(&font, "fi", latn(), DEFAULT_LANGUAGE, &[]);
+        assert_eq!(shaped.glyphs.len(), 1);
+        assert_ne!(shaped.glyphs[0].glyph_id, f);
+        assert_eq!(shaped.glyphs[0].cluster, 0);
+
+        let shaped = shape(&font, "fi", latn(), DEFAULT_LANGUAGE, &[Feature::new(b"liga", 0)]);
+        assert_eq!(shaped.glyphs.len(), 2);
+        assert_eq!(shaped.glyphs[0].glyph_id, f);
+        assert_eq!(shaped.glyphs[1].cluster, 1);
+    }
+
+    #[test]
+    fn kerning() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let kerned = shape(&font, "AV", latn(), DEFAULT_LANGUAGE, &[]);
+        let unkerned = shape(&font, "AV", latn(), DEFAULT_LANGUAGE, &[Feature::new(b"kern", 0)]);
+        assert!(kerned.advance_width() < unkerned.advance_width());
+
+        // Only kern the first pair
+        let ranged = shape(&font, "AVAV", latn(), DEFAULT_LANGUAGE, &[
+            Feature::new(b"kern", 0),
+            Feature::with_range(b"kern", 1, 0, 2),
+        ]);
+        let kerned = shape(&font, "AVAV", latn(), DEFAULT_LANGUAGE, &[]);
+        assert!(kerned.advance_width() < ranged.advance_width());
+    }
+
+    #[test]
+    fn mark_attachment() {
+        let buf = load_font_buf("fonts/DejaVuSans.ttf");
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let shaped = shape(&font, "a\u{301}", latn(), DEFAULT_LANGUAGE, &[]);
+        assert_eq!(shaped.glyphs.len(), 2);
+        let mark = shaped.glyphs[1];
+        assert_eq!(mark.x_advance, 0);
+        // The mark is moved back over the base
+        assert!(mark.x_offset < 0);
+    }
+
+    #[test]
+    fn varied_space() {
+        use tables::fvar::Variation;
+        use tables::fvar::tests::build_fvar;
+        use tables::gvar::tests::build_gvar;
+        use tables::hmtx::HMTX;
+        use tables::maxp::MaxP;
+        use test_utils::{font_buf, with_tables};
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let maxp: MaxP = font.get_table().unwrap();
+        let hmtx: HMTX = font.get_table().unwrap();
+        let space = font.get_glyph_id(' ').unwrap();
+        assert!(font.get_glyph_for_id(space).is_none());
+        let advance = hmtx.advance_width(space).0 as i32;
+
+        // Only the phantom points, with the advance growing by 100
+        let gvar = build_gvar(maxp.num_glyphs, space as u16, 16384, None, &[(0, 0), (100, 0), (0, 0), (0, 0)]);
+        let buf = with_tables(&buf, &[(b"fvar", build_fvar()), (b"gvar", gvar)]);
+        let font = Font::from_buffer(&buf).unwrap();
+        let bold = font.with_variations(&[Variation::new(b"wght", 900.)]).unwrap();
+
+        let shaped = shape(&bold, "a b", latn(), DEFAULT_LANGUAGE, &[]);
+        assert_eq!(shaped.glyphs[1].glyph_id, space);
+        assert_eq!(shaped.glyphs[1].x_advance, advance + 100);
+        let shaped = shape(&font, "a b", latn(), DEFAULT_LANGUAGE, &[]);
+        assert_eq!(shaped.glyphs[1].x_advance, advance);
+    }
+}
@@ -0,0 +1,343 @@
+use font::{Font, GetTable};
+use render::compositor::TextDirection;
+use tables::gdef::GDEF;
+use tables::gpos::GPOS;
+use tables::gsub::GSUB;
+use tables::layout::{LayoutTable, Tag};
+use super::{Feature, Shaper};
+use super::buffer::{Buffer, MASK_GLOBAL};
+
+/// Run between two stages of lookups, e.g. to reorder glyphs
+pub(crate) type PauseFn = fn(&ShapePlan, &mut Buffer);
+
+const GSUB_IDX: usize = 0;
+
+struct FeatureInfo {
+    tag: Tag,
+    /// Whether the feature starts out enabled for every glyph
+    global: bool,
+    value: u32,
+    /// Stage in GSUB and GPOS that the feature's lookups belong to
+    stage: [usize; 2],
+    /// (start, end, enabled), as byte ranges of the source text
+    ranges: Vec<(usize, usize, bool)>,
+    /// Turned off everywhere by the user
+    disabled: bool,
+}
+
+/// Collects the features a shaper wants, in order, before they are looked up
+/// in the font
+pub(crate) struct PlanBuilder {
+    features: Vec<FeatureInfo>,
+    current_stage: [usize; 2],
+    pauses: [Vec<Option<PauseFn>>; 2],
+}
+
+impl PlanBuilder {
+    pub fn new() -> PlanBuilder {
+        PlanBuilder {
+            features: Vec::new(),
+            current_stage: [0, 0],
+            pauses: [Vec::new(), Vec::new()],
+        }
+    }
+
+    /// Non-global features are only applied to glyphs the shaper enables them on.
+    ///
+    /// Adding a feature again keeps the stage it was first added in.
+    pub fn add_feature(&mut self, tag: Tag, global: bool) {
+        if let Some(info) = self.features.iter_mut().find(|info| info.tag == tag) {
+            info.global |= global;
+            return;
+        }
+        self.features.push(FeatureInfo {
+            tag,
+            global,
+            value: 1,
+            stage: self.current_stage,
+            ranges: Vec::new(),
+            disabled: false,
+        });
+    }
+
+    pub fn add_global_features(&mut self, tags: &[&[u8; 4]]) {
+        for tag in tags {
+            self.add_feature(Tag::new(tag), true);
+        }
+    }
+
+    /// Lookups of features added after a pause are only applied once all
+    /// lookups before it have finished.
+    pub fn add_gsub_pause(&mut self, pause: Option<PauseFn>) {
+        self.pauses[GSUB_IDX].push(pause);
+        self.current_stage[GSUB_IDX] += 1;
+    }
+
+    fn add_user_features(&mut self, user_features: &[Feature]) {
+        for feature in user_features {
+            let global = feature.start == 0 && feature.end == ::std::usize::MAX;
+            let existing = self.features.iter().position(|info| info.tag == feature.tag);
+            let idx = match existing {
+                Some(idx) => idx,
+                None => {
+                    self.add_feature(feature.tag, false);
+                    self.features.len() - 1
+                },
+            };
+            let info = &mut self.features[idx];
+            if global {
+                info.global = feature.value != 0;
+                info.disabled = feature.value == 0;
+                info.ranges.clear();
+            } else {
+                info.disabled = false;
+                info.ranges.push((feature.start, feature.end, feature.value != 0));
+            }
+            if feature.value != 0 {
+                info.value = feature.value;
+            }
+        }
+    }
+
+    pub fn compile<'a>(mut self, font: &Font<'a>, script: Tag, language: Tag,
+                       direction: TextDirection, shaper: Shaper,
+                       user_features: &[Feature]) -> ShapePlan<'a> {
+        self.add_user_features(user_features);
+
+        let gsub: Option<GSUB> = font.get_table();
+        let gpos: Option<GPOS> = font.get_table();
+        let gdef: Option<GDEF> = font.get_table();
+
+        let mut stages: Vec<Vec<Stage>> = (0..2)
+            .map(|table_idx| {
+                let num_stages = self.current_stage[table_idx] + 1;
+                (0..num_stages)
+                    .map(|stage| Stage {
+                        lookups: Vec::new(),
+                        pause: self.pauses[table_idx].get(stage).cloned().unwrap_or(None),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut features = Vec::new();
+        let mut ranges = Vec::new();
+        let mut global_mask = MASK_GLOBAL;
+        let mut next_bit = 1;
+
+        {
+            let tables: [Option<&LayoutTable<'a>>; 2] = [
+                gsub.as_ref().map(|gsub| &**gsub),
+                gpos.as_ref().map(|gpos| &**gpos),
+            ];
+            let lang_systems: Vec<_> = tables.iter()
+                .map(|table| table.and_then(|table| table.lang_sys(script, language)))
+                .collect();
+
+            // The required feature is always applied first
+            for table_idx in 0..2 {
+                if let (Some(table), Some(lang_sys)) = (tables[table_idx], lang_systems[table_idx].as_ref()) {
+                    let required = lang_sys.required_feature_index().and_then(|feature_idx| {
+                        let feature_list = table.feature_list()?;
+                        feature_list.feature_at(&feature_list.record(feature_idx as usize))
+                    });
+                    if let Some(feature) = required {
+                        for lookup_idx in feature.lookup_indices() {
+                            stages[table_idx][0].lookups.push(LookupMap {
+                                index: lookup_idx,
+                                mask: MASK_GLOBAL,
+                                value: 1,
+                            });
+                        }
+                    }
+                }
+            }
+
+            for info in self.features.iter() {
+                if info.disabled {
+                    continue;
+                }
+
+                let found: Vec<_> = (0..2)
+                    .map(|table_idx| match (tables[table_idx], lang_systems[table_idx].as_ref()) {
+                        (Some(table), Some(lang_sys)) => table.find_feature(lang_sys, info.tag),
+                        _ => None,
+                    })
+                    .collect();
+                if found.iter().all(|feature| feature.is_none()) {
+                    continue;
+                }
+
+                let mask = if info.global && info.ranges.is_empty() {
+                    MASK_GLOBAL
+                } else if next_bit < 32 {
+                    let mask = 1 << next_bit;
+                    next_bit += 1;
+                    if info.global {
+                        global_mask |= mask;
+                    }
+                    mask
+                } else {
+                    // Ran out of bits, can't control this feature per glyph
+                    continue;
+                };
+                features.push((info.tag, mask));
+                for &(start, end, enabled) in info.ranges.iter() {
+                    ranges.push(FeatureRange { mask, start, end, enabled });
+                }
+
+                for (table_idx, feature) in found.into_iter().enumerate() {
+                    let feature = match feature {
+                        Some(feature) => feature,
+                        None => continue,
+                    };
+                    for lookup_idx in feature.lookup_indices() {
+                        stages[table_idx][info.stage[table_idx]].lookups.push(LookupMap {
+                            index: lookup_idx,
+                            mask,
+                            value: info.value,
+                        });
+                    }
+                }
+            }
+        }
+
+        // Within a stage lookups are applied in lookup list order
+        for table_stages in stages.iter_mut() {
+            for stage in table_stages.iter_mut() {
+                stage.lookups.sort_by_key(|lookup| lookup.index);
+                let mut merged: Vec<LookupMap> = Vec::with_capacity(stage.lookups.len());
+                for lookup in stage.lookups.drain(..) {
+                    match merged.last_mut() {
+                        Some(ref mut last) if last.index == lookup.index => {
+                            last.mask |= lookup.mask;
+                            continue;
+                        },
+                        _ => (),
+                    }
+                    merged.push(lookup);
+                }
+                stage.lookups = merged;
+            }
+        }
+
+        let gpos_stages = stages.pop().unwrap();
+        let gsub_stages = stages.pop().unwrap();
+        ShapePlan {
+            direction,
+            shaper,
+            gsub,
+            gpos,
+            gdef,
+            gsub_stages,
+            gpos_stages,
+            features,
+            ranges,
+            global_mask,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LookupMap {
+    pub index: u16,
+    pub mask: u32,
+    /// The feature's value, used to pick from alternates
+    pub value: u32,
+}
+
+pub(crate) struct Stage {
+    pub lookups: Vec<LookupMap>,
+    /// Run after the stage's lookups
+    pub pause: Option<PauseFn>,
+}
+
+struct FeatureRange {
+    mask: u32,
+    start: usize,
+    end: usize,
+    enabled: bool,
+}
+
+/// Which lookups to apply, and to which glyphs, for a script and language
+pub(crate) struct ShapePlan<'a> {
+    pub direction: TextDirection,
+    pub shaper: Shaper,
+    pub gsub: Option<GSUB<'a>>,
+    pub gpos: Option<GPOS<'a>>,
+    pub gdef: Option<GDEF<'a>>,
+    pub gsub_stages: Vec<Stage>,
+    pub gpos_stages: Vec<Stage>,
+    features: Vec<(Tag, u32)>,
+    ranges: Vec<FeatureRange>,
+    global_mask: u32,
+}
+
+impl<'a> ShapePlan<'a> {
+    /// The mask bit for a feature, or 0 if the font doesn't have it
+    pub fn mask(&self, tag: Tag) -> u32 {
+        self.features.iter()
+            .find(|&&(feature_tag, _)| feature_tag == tag)
+            .map(|&(_, mask)| mask)
+            .unwrap_or(0)
+    }
+
+    /// Turn on the global features, then apply the user's feature ranges
+    pub fn setup_masks(&self, buffer: &mut Buffer) {
+        for info in buffer.info.iter_mut() {
+            info.mask = self.global_mask;
+        }
+        for range in self.ranges.iter() {
+            for info in buffer.info.iter_mut() {
+                let cluster = info.cluster as usize;
+                if range.start <= cluster && cluster < range.end {
+                    if range.enabled {
+                        info.mask |= range.mask;
+                    } else {
+                        info.mask &= !range.mask;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn substitute(&self, buffer: &mut Buffer) {
+        use super::apply::{ApplyContext, TableKind};
+        let lookups = match self.gsub.as_ref().and_then(|gsub| gsub.lookup_list()) {
+            Some(lookups) => lookups,
+            None => return,
+        };
+        for stage in self.gsub_stages.iter() {
+            {
+                let mut ctx = ApplyContext::new(TableKind::Subst, lookups.clone(),
+                                                self.gdef.as_ref(), buffer);
+                for lookup in stage.lookups.iter() {
+                    ctx.apply_lookup(lookup);
+                }
+            }
+            if let Some(pause) = stage.pause {
+                pause(self, buffer);
+            }
+        }
+    }
+
+    pub fn position(&self, buffer: &mut Buffer) {
+        use super::apply::{ApplyContext, TableKind};
+        let lookups = match self.gpos.as_ref().and_then(|gpos| gpos.lookup_list()) {
+            Some(lookups) => lookups,
+            None => return,
+        };
+        for stage in self.gpos_stages.iter() {
+            {
+                let mut ctx = ApplyContext::new(TableKind::Pos, lookups.clone(),
+                                                self.gdef.as_ref(), buffer);
+                for lookup in stage.lookups.iter() {
+                    ctx.apply_lookup(lookup);
+                }
+            }
+            if let Some(pause) = stage.pause {
+                pause(self, buffer);
+            }
+        }
+    }
+}
@@ -0,0 +1,871 @@
+//! A small renderer for the documents in the `SVG ` table. It fills paths
+//! and basic shapes with solid colors, which covers most glyph documents.
+//! Strokes, text, masks and filters are skipped, and gradients are filled
+//! with the color of their first stop.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use image::{Rgba, RgbaImage};
+use math::{Affine, Point};
+use render::{blend_over, CoverageRaster, DrawCommand, FillRule, FlattenedDrawCommands, Raster, FLATTENING_TOLERANCE};
+
+/// `use` elements can refer to each other, so cap how deep that goes
+const MAX_DEPTH: usize = 32;
+
+const BLACK: Rgba<u8> = Rgba { data: [0, 0, 0, 255] };
+
+/// Elements that never draw anything directly
+const NOT_RENDERED: &[&str] = &[
+    "defs", "linearGradient", "radialGradient", "clipPath", "mask", "symbol", "pattern", "filter",
+    "style", "title", "desc", "metadata", "text", "line",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// A presentation attribute, which the `style` attribute overrides
+    fn property(&self, name: &str) -> Option<&str> {
+        let from_style = self.attribute("style").and_then(|style| {
+            style.split(';')
+                .filter_map(|declaration| {
+                    let mut parts = declaration.splitn(2, ':');
+                    Some((parts.next()?.trim(), parts.next()?.trim()))
+                })
+                .filter(|&(key, _)| key == name)
+                .last()
+                .map(|(_, value)| value)
+        });
+        from_style.or_else(|| self.attribute(name)).map(str::trim)
+    }
+
+    fn href(&self) -> Option<&str> {
+        let href = self.attribute("href").or_else(|| self.attribute("xlink:href"))?;
+        if href.starts_with('#') {
+            Some(&href[1..])
+        } else {
+            None
+        }
+    }
+
+    fn length(&self, name: &str) -> f32 {
+        self.attribute(name).and_then(|value| Numbers::new(value).next()).unwrap_or(0.)
+    }
+
+    fn collect_ids<'e>(&'e self, ids: &mut HashMap<&'e str, &'e Element>) {
+        if let Some(id) = self.attribute("id") {
+            ids.entry(id).or_insert(self);
+        }
+        for child in &self.children {
+            child.collect_ids(ids);
+        }
+    }
+}
+
+/// A filled shape, in document units
+struct Fill {
+    lines: Vec<(Point, Point)>,
+    fill_rule: FillRule,
+    color: Rgba<u8>,
+    alpha: f32,
+}
+
+/// What elements inherit from their parents
+#[derive(Clone, Copy)]
+struct State {
+    transform: Affine,
+    /// `None` for `fill="none"`
+    fill: Option<Rgba<u8>>,
+    fill_rule: FillRule,
+    fill_opacity: f32,
+    opacity: f32,
+    /// Whether this is inside the element being rendered
+    drawing: bool,
+}
+
+struct Renderer<'e> {
+    ids: HashMap<&'e str, &'e Element>,
+    element_id: &'e str,
+    foreground: Rgba<u8>,
+    /// How far flattened curves can stray, in document units
+    tolerance: f32,
+    fills: Vec<Fill>,
+}
+
+/// Render the element with id `element_id`, or the whole document if it
+/// doesn't have one. Document units are font units with y going down,
+/// which `scale` takes to pixels. Row 0 is the top.
+pub(crate) fn render(document: &str, element_id: &str, scale: f32, foreground: Rgba<u8>) -> Option<RgbaImage> {
+    let root = parse_xml(document)?;
+    let mut ids = HashMap::new();
+    root.collect_ids(&mut ids);
+    let state = State {
+        transform: Affine::scale(1., 1.),
+        fill: Some(BLACK),
+        fill_rule: FillRule::NonZero,
+        fill_opacity: 1.,
+        opacity: 1.,
+        drawing: !ids.contains_key(element_id),
+    };
+    let mut renderer = Renderer {
+        ids,
+        element_id,
+        foreground,
+        tolerance: FLATTENING_TOLERANCE / scale,
+        fills: Vec::new(),
+    };
+    renderer.element(&root, state, 0);
+
+    let points = || renderer.fills.iter().flat_map(|fill| fill.lines.iter().map(|line| line.0));
+    if points().next().is_none() {
+        return Some(RgbaImage::new(0, 0));
+    }
+    let x_min = (points().map(|point| point.x).fold(::std::f32::INFINITY, f32::min) * scale).floor();
+    let y_min = (points().map(|point| point.y).fold(::std::f32::INFINITY, f32::min) * scale).floor();
+    let x_max = (points().map(|point| point.x).fold(::std::f32::NEG_INFINITY, f32::max) * scale).ceil();
+    let y_max = (points().map(|point| point.y).fold(::std::f32::NEG_INFINITY, f32::max) * scale).ceil();
+    let (width, height) = ((x_max - x_min) as u32, (y_max - y_min) as u32);
+    let device = Affine::translation(-x_min, -y_min) * Affine::scale(scale, scale);
+
+    let mut image = RgbaImage::new(width, height);
+    for fill in &renderer.fills {
+        let mut raster = CoverageRaster::with_fill_rule(width, height, fill.fill_rule);
+        for &(start, end) in &fill.lines {
+            raster.add_line(device * start, device * end);
+        }
+        for (pixel, coverage) in image.pixels_mut().zip(raster.coverage()) {
+            if coverage > 0. {
+                *pixel = blend_over(*pixel, fill.color, coverage * fill.alpha);
+            }
+        }
+    }
+    Some(image)
+}
+
+impl<'e> Renderer<'e> {
+    fn element(&mut self, element: &'e Element, mut state: State, depth: usize) {
+        if depth > MAX_DEPTH || NOT_RENDERED.contains(&element.name.as_str()) {
+            return;
+        }
+        if element.property("display") == Some("none") {
+            return;
+        }
+        if element.attribute("id") == Some(self.element_id) {
+            state.drawing = true;
+        }
+        if let Some(transform) = element.attribute("transform") {
+            state.transform = state.transform * parse_transform(transform);
+        }
+        if let Some(fill) = element.property("fill") {
+            state.fill = self.paint(fill, state.fill);
+        }
+        match element.property("fill-rule") {
+            Some("nonzero") => state.fill_rule = FillRule::NonZero,
+            Some("evenodd") => state.fill_rule = FillRule::EvenOdd,
+            _ => (),
+        }
+        if let Some(opacity) = element.property("fill-opacity") {
+            state.fill_opacity = parse_opacity(opacity);
+        }
+        if let Some(opacity) = element.property("opacity") {
+            // Group opacity, as if each shape were drawn separately
+            state.opacity *= parse_opacity(opacity);
+        }
+
+        let outline = match element.name.as_str() {
+            "use" => {
+                if let Some(target) = element.href().and_then(|id| self.ids.get(id).cloned()) {
+                    state.transform = state.transform * Affine::translation(element.length("x"), element.length("y"));
+                    self.element(target, state, depth + 1);
+                }
+                return;
+            },
+            "path" => parse_path(element.attribute("d").unwrap_or("")),
+            "rect" => rect(element),
+            "circle" => {
+                let r = element.length("r");
+                ellipse(element.length("cx"), element.length("cy"), r, r)
+            },
+            "ellipse" => ellipse(element.length("cx"), element.length("cy"), element.length("rx"), element.length("ry")),
+            "polygon" | "polyline" => polygon(element.attribute("points").unwrap_or("")),
+            _ => {
+                for child in &element.children {
+                    self.element(child, state, depth + 1);
+                }
+                return;
+            },
+        };
+
+        let color = match state.fill {
+            Some(color) if state.drawing => color,
+            _ => return,
+        };
+        let transform = state.transform;
+        self.fills.push(Fill {
+            lines: FlattenedDrawCommands::with_tolerance(
+                outline.into_iter().map(|command| command.transform(transform)),
+                self.tolerance,
+            ).collect(),
+            fill_rule: state.fill_rule,
+            color,
+            alpha: color.data[3] as f32 / 255. * state.fill_opacity * state.opacity,
+        });
+    }
+
+    /// A `fill` value. Gradients and other paint servers use the color of
+    /// their first stop.
+    fn paint(&self, value: &str, inherited: Option<Rgba<u8>>) -> Option<Rgba<u8>> {
+        match value {
+            "none" | "transparent" => None,
+            "inherit" => inherited,
+            "currentColor" => Some(self.foreground),
+            _ if value.starts_with("url(") => {
+                let id = value[4..].trim_start_matches('#').split(')').next().unwrap_or("").trim();
+                let mut server = self.ids.get(id).cloned();
+                // Stops can come from another gradient
+                for _ in 0..MAX_DEPTH {
+                    let current = server?;
+                    if let Some(stop) = current.children.iter().find(|child| child.name == "stop") {
+                        let color = stop.property("stop-color").map_or(Some(BLACK), |color| {
+                            if color == "currentColor" { Some(self.foreground) } else { parse_color(color) }
+                        });
+                        let opacity = stop.property("stop-opacity").map_or(1., parse_opacity);
+                        return color.map(|mut color| {
+                            color.data[3] = (color.data[3] as f32 * opacity).round() as u8;
+                            color
+                        });
+                    }
+                    server = current.href().and_then(|id| self.ids.get(id).cloned());
+                }
+                None
+            },
+            _ => parse_color(value).or(inherited),
+        }
+    }
+}
+
+fn parse_opacity(value: &str) -> f32 {
+    let value = value.trim();
+    let opacity = if value.ends_with('%') {
+        value[..value.len() - 1].parse::<f32>().map(|percent| percent / 100.)
+    } else {
+        value.parse()
+    };
+    opacity.unwrap_or(1.).max(0.).min(1.)
+}
+
+fn parse_color(value: &str) -> Option<Rgba<u8>> {
+    const NAMED: &[(&str, [u8; 3])] = &[
+        ("black", [0, 0, 0]), ("white", [255, 255, 255]), ("red", [255, 0, 0]), ("lime", [0, 255, 0]),
+        ("green", [0, 128, 0]), ("blue", [0, 0, 255]), ("yellow", [255, 255, 0]), ("cyan", [0, 255, 255]),
+        ("aqua", [0, 255, 255]), ("magenta", [255, 0, 255]), ("fuchsia", [255, 0, 255]),
+        ("gray", [128, 128, 128]), ("grey", [128, 128, 128]), ("silver", [192, 192, 192]),
+        ("maroon", [128, 0, 0]), ("olive", [128, 128, 0]), ("navy", [0, 0, 128]), ("purple", [128, 0, 128]),
+        ("teal", [0, 128, 128]), ("orange", [255, 165, 0]), ("brown", [165, 42, 42]), ("pink", [255, 192, 203]),
+        ("gold", [255, 215, 0]),
+    ];
+
+    let value = value.trim();
+    let [red, green, blue] = if value.starts_with('#') {
+        let hex = &value[1..];
+        let digit = |idx: usize| u8::from_str_radix(hex.get(idx..idx + 1)?, 16).ok();
+        match hex.len() {
+            3 => [digit(0)? * 17, digit(1)? * 17, digit(2)? * 17],
+            6 => [digit(0)? * 16 + digit(1)?, digit(2)? * 16 + digit(3)?, digit(4)? * 16 + digit(5)?],
+            _ => return None,
+        }
+    } else if value.starts_with("rgb(") && value.ends_with(')') {
+        let mut channels = value[4..value.len() - 1].split(',').map(|channel| {
+            let channel = channel.trim();
+            let value = if channel.ends_with('%') {
+                channel[..channel.len() - 1].parse::<f32>().ok()? * 2.55
+            } else {
+                channel.parse::<f32>().ok()?
+            };
+            Some(value.round().max(0.).min(255.) as u8)
+        });
+        [channels.next()??, channels.next()??, channels.next()??]
+    } else {
+        NAMED.iter().find(|&&(name, _)| name.eq_ignore_ascii_case(value))?.1
+    };
+    Some(Rgba { data: [red, green, blue, 255] })
+}
+
+/// A `transform` attribute's list of transforms, combined
+fn parse_transform(value: &str) -> Affine {
+    let mut affine = Affine::scale(1., 1.);
+    for part in value.split(')') {
+        let mut parts = part.splitn(2, '(');
+        let name = parts.next().unwrap_or("").trim().trim_start_matches(',').trim();
+        let args: Vec<f32> = Numbers::new(parts.next().unwrap_or("")).collect();
+        let arg = |idx: usize| args.get(idx).cloned().unwrap_or(0.);
+        let transform = match (name, args.len()) {
+            ("matrix", 6) => Affine {
+                square: [[arg(0), arg(2)], [arg(1), arg(3)]],
+                translation: [arg(4), arg(5)],
+            },
+            ("translate", 1) | ("translate", 2) => Affine::translation(arg(0), arg(1)),
+            ("scale", 1) => Affine::scale(arg(0), arg(0)),
+            ("scale", 2) => Affine::scale(arg(0), arg(1)),
+            // Clockwise on screen, since y goes down
+            ("rotate", 1) => Affine::rotation(arg(0).to_radians()),
+            ("rotate", 3) => Affine::translation(arg(1), arg(2)) * Affine::rotation(arg(0).to_radians())
+                * Affine::translation(-arg(1), -arg(2)),
+            ("skewX", 1) => Affine::shear(arg(0).to_radians().tan(), 0.),
+            ("skewY", 1) => Affine::shear(0., arg(0).to_radians().tan()),
+            _ => continue,
+        };
+        affine = affine * transform;
+    }
+    affine
+}
+
+/// The numbers in path data and other lists, which can be run together,
+/// e.g. `1.5.5-2`
+struct Numbers<'s> {
+    text: &'s [u8],
+    pos: usize,
+}
+
+impl<'s> Numbers<'s> {
+    fn new(text: &'s str) -> Numbers<'s> {
+        Numbers { text: text.as_bytes(), pos: 0 }
+    }
+
+    fn skip_separators(&mut self) {
+        while self.pos < self.text.len() && (self.text[self.pos].is_ascii_whitespace() || self.text[self.pos] == b',') {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_separators();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn at_number(&mut self) -> bool {
+        match self.peek() {
+            Some(byte) => byte.is_ascii_digit() || byte == b'-' || byte == b'+' || byte == b'.',
+            None => false,
+        }
+    }
+
+    /// Arc flags are single digits that don't need separating
+    fn flag(&mut self) -> Option<bool> {
+        let flag = match self.peek()? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(flag)
+    }
+}
+
+impl<'s> Iterator for Numbers<'s> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if !self.at_number() {
+            return None;
+        }
+        let start = self.pos;
+        let digits = |text: &[u8], mut pos: usize| {
+            while pos < text.len() && text[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            pos
+        };
+        let mut pos = start;
+        if self.text[pos] == b'-' || self.text[pos] == b'+' {
+            pos += 1;
+        }
+        pos = digits(self.text, pos);
+        if self.text.get(pos) == Some(&b'.') {
+            pos = digits(self.text, pos + 1);
+        }
+        if let Some(&b'e') | Some(&b'E') = self.text.get(pos) {
+            let mut exponent = pos + 1;
+            if let Some(&b'-') | Some(&b'+') = self.text.get(exponent) {
+                exponent += 1;
+            }
+            let end = digits(self.text, exponent);
+            if end > exponent {
+                pos = end;
+            }
+        }
+        self.pos = pos;
+        ::std::str::from_utf8(&self.text[start..pos]).ok()?.parse().ok()
+    }
+}
+
+/// Builds outlines out of `DrawCommand`s, closing every subpath since
+/// they're only filled
+struct Outline {
+    commands: Vec<DrawCommand>,
+    start: Point,
+    current: Point,
+}
+
+impl Outline {
+    fn new() -> Outline {
+        let origin = Point { x: 0., y: 0. };
+        Outline { commands: Vec::new(), start: origin, current: origin }
+    }
+
+    fn move_to(&mut self, point: Point) {
+        self.close();
+        self.start = point;
+        self.current = point;
+    }
+
+    fn line_to(&mut self, point: Point) {
+        self.commands.push(DrawCommand::Line(self.current, point));
+        self.current = point;
+    }
+
+    fn quad_to(&mut self, control: Point, point: Point) {
+        self.commands.push(DrawCommand::Curve(self.current, control, point));
+        self.current = point;
+    }
+
+    fn cubic_to(&mut self, control1: Point, control2: Point, point: Point) {
+        self.commands.push(DrawCommand::CubicCurve(self.current, control1, control2, point));
+        self.current = point;
+    }
+
+    /// An elliptical arc, as in SVG path data
+    fn arc_to(&mut self, radii: (f32, f32), rotation: f32, large_arc: bool, sweep: bool, end: Point) {
+        let start = self.current;
+        let (mut rx, mut ry) = (radii.0.abs(), radii.1.abs());
+        if rx == 0. || ry == 0. || start == end {
+            return self.line_to(end);
+        }
+        // https://www.w3.org/TR/SVG/implnote.html#ArcConversionEndpointToCenter
+        let (sin, cos) = rotation.to_radians().sin_cos();
+        let (dx, dy) = ((start.x - end.x) / 2., (start.y - end.y) / 2.);
+        let (x1, y1) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+        let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+        if lambda > 1. {
+            rx *= lambda.sqrt();
+            ry *= lambda.sqrt();
+        }
+        let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+        let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+        let mut root = (numerator / denominator).max(0.).sqrt();
+        if large_arc == sweep {
+            root = -root;
+        }
+        let (cx1, cy1) = (root * rx * y1 / ry, -root * ry * x1 / rx);
+        let center = Point {
+            x: cos * cx1 - sin * cy1 + (start.x + end.x) / 2.,
+            y: sin * cx1 + cos * cy1 + (start.y + end.y) / 2.,
+        };
+        let angle = |ux: f32, uy: f32| uy.atan2(ux);
+        let theta = angle((x1 - cx1) / rx, (y1 - cy1) / ry);
+        let mut delta = angle((-x1 - cx1) / rx, (-y1 - cy1) / ry) - theta;
+        if sweep && delta < 0. {
+            delta += 2. * PI;
+        } else if !sweep && delta > 0. {
+            delta -= 2. * PI;
+        }
+
+        // Cubics are close enough for up to a quarter turn
+        let segments = (delta.abs() / (PI / 2.)).ceil().max(1.) as usize;
+        let step = delta / segments as f32;
+        let handle = 4. / 3. * (step / 4.).tan();
+        let on_ellipse = |angle: f32| {
+            let (sin_a, cos_a) = angle.sin_cos();
+            let (x, y) = (rx * cos_a, ry * sin_a);
+            let (dx, dy) = (-rx * sin_a, ry * cos_a);
+            let rotate = |x: f32, y: f32| (cos * x - sin * y, sin * x + cos * y);
+            let (x, y) = rotate(x, y);
+            (Point { x: center.x + x, y: center.y + y }, rotate(dx, dy))
+        };
+        for idx in 0..segments {
+            let (angle1, angle2) = (theta + step * idx as f32, theta + step * (idx + 1) as f32);
+            let ((p1, d1), (p2, d2)) = (on_ellipse(angle1), on_ellipse(angle2));
+            let p2 = if idx + 1 == segments { end } else { p2 };
+            self.cubic_to(
+                Point { x: p1.x + d1.0 * handle, y: p1.y + d1.1 * handle },
+                Point { x: p2.x - d2.0 * handle, y: p2.y - d2.1 * handle },
+                p2,
+            );
+        }
+    }
+
+    fn close(&mut self) {
+        if self.current != self.start {
+            let start = self.start;
+            self.line_to(start);
+        }
+    }
+
+    fn finish(mut self) -> Vec<DrawCommand> {
+        self.close();
+        self.commands
+    }
+}
+
+fn parse_path(data: &str) -> Vec<DrawCommand> {
+    let mut outline = Outline::new();
+    let mut numbers = Numbers::new(data);
+    // For the smooth curve commands
+    let mut last_cubic_control = None;
+    let mut last_quad_control = None;
+    let mut command = None;
+
+    loop {
+        match numbers.peek() {
+            Some(byte) if byte.is_ascii_alphabetic() => {
+                numbers.pos += 1;
+                command = Some(byte);
+            },
+            Some(_) if command.is_some() && numbers.at_number() => (),
+            _ => break,
+        }
+        let letter = command.unwrap();
+        let relative = letter.is_ascii_lowercase();
+        let current = outline.current;
+        let point = |numbers: &mut Numbers| -> Option<Point> {
+            let (x, y) = (numbers.next()?, numbers.next()?);
+            Some(if relative { Point { x: current.x + x, y: current.y + y } } else { Point { x, y } })
+        };
+        let reflect = |control: Option<Point>| match control {
+            Some(control) => Point { x: 2. * current.x - control.x, y: 2. * current.y - control.y },
+            None => current,
+        };
+
+        let (mut cubic_control, mut quad_control) = (None, None);
+        let done = match letter.to_ascii_uppercase() {
+            b'M' => point(&mut numbers).map(|point| {
+                outline.move_to(point);
+                // More pairs are line segments
+                command = Some(if relative { b'l' } else { b'L' });
+            }),
+            b'L' => point(&mut numbers).map(|point| outline.line_to(point)),
+            b'H' => numbers.next().map(|x| {
+                let x = if relative { current.x + x } else { x };
+                outline.line_to(Point { x, y: current.y });
+            }),
+            b'V' => numbers.next().map(|y| {
+                let y = if relative { current.y + y } else { y };
+                outline.line_to(Point { x: current.x, y });
+            }),
+            b'C' => (|| {
+                let (control1, control2, end) = (point(&mut numbers)?, point(&mut numbers)?, point(&mut numbers)?);
+                outline.cubic_to(control1, control2, end);
+                cubic_control = Some(control2);
+                Some(())
+            })(),
+            b'S' => (|| {
+                let (control2, end) = (point(&mut numbers)?, point(&mut numbers)?);
+                outline.cubic_to(reflect(last_cubic_control), control2, end);
+                cubic_control = Some(control2);
+                Some(())
+            })(),
+            b'Q' => (|| {
+                let (control, end) = (point(&mut numbers)?, point(&mut numbers)?);
+                outline.quad_to(control, end);
+                quad_control = Some(control);
+                Some(())
+            })(),
+            b'T' => point(&mut numbers).map(|end| {
+                let control = reflect(last_quad_control);
+                outline.quad_to(control, end);
+                quad_control = Some(control);
+            }),
+            b'A' => (|| {
+                let (rx, ry, rotation) = (numbers.next()?, numbers.next()?, numbers.next()?);
+                let (large_arc, sweep) = (numbers.flag()?, numbers.flag()?);
+                let end = point(&mut numbers)?;
+                outline.arc_to((rx, ry), rotation, large_arc, sweep, end);
+                Some(())
+            })(),
+            b'Z' => {
+                outline.close();
+                outline.current = outline.start;
+                command = None;
+                Some(())
+            },
+            _ => None,
+        };
+        if done.is_none() {
+            break;
+        }
+        last_cubic_control = cubic_control;
+        last_quad_control = quad_control;
+    }
+    outline.finish()
+}
+
+fn rect(element: &Element) -> Vec<DrawCommand> {
+    let (x, y) = (element.length("x"), element.length("y"));
+    let (width, height) = (element.length("width"), element.length("height"));
+    if width <= 0. || height <= 0. {
+        return Vec::new();
+    }
+    // A missing radius is the same as the other one
+    let (rx, ry) = match (element.attribute("rx").map(|_| element.length("rx")),
+                          element.attribute("ry").map(|_| element.length("ry"))) {
+        (Some(rx), Some(ry)) => (rx, ry),
+        (Some(r), None) | (None, Some(r)) => (r, r),
+        (None, None) => (0., 0.),
+    };
+    let (rx, ry) = (rx.max(0.).min(width / 2.), ry.max(0.).min(height / 2.));
+
+    let mut outline = Outline::new();
+    let point = |x, y| Point { x, y };
+    outline.move_to(point(x + rx, y));
+    outline.line_to(point(x + width - rx, y));
+    outline.arc_to((rx, ry), 0., false, true, point(x + width, y + ry));
+    outline.line_to(point(x + width, y + height - ry));
+    outline.arc_to((rx, ry), 0., false, true, point(x + width - rx, y + height));
+    outline.line_to(point(x + rx, y + height));
+    outline.arc_to((rx, ry), 0., false, true, point(x, y + height - ry));
+    outline.line_to(point(x, y + ry));
+    outline.arc_to((rx, ry), 0., false, true, point(x + rx, y));
+    outline.finish()
+}
+
+fn ellipse(cx: f32, cy: f32, rx: f32, ry: f32) -> Vec<DrawCommand> {
+    if rx <= 0. || ry <= 0. {
+        return Vec::new();
+    }
+    let mut outline = Outline::new();
+    outline.move_to(Point { x: cx + rx, y: cy });
+    outline.arc_to((rx, ry), 0., false, true, Point { x: cx - rx, y: cy });
+    outline.arc_to((rx, ry), 0., false, true, Point { x: cx + rx, y: cy });
+    outline.finish()
+}
+
+fn polygon(points: &str) -> Vec<DrawCommand> {
+    let mut outline = Outline::new();
+    let mut numbers = Numbers::new(points);
+    let mut first = true;
+    while let (Some(x), Some(y)) = (numbers.next(), numbers.next()) {
+        if first {
+            outline.move_to(Point { x, y });
+            first = false;
+        } else {
+            outline.line_to(Point { x, y });
+        }
+    }
+    outline.finish()
+}
+
+/// Just enough XML for SVG documents: elements and attributes. Text,
+/// comments, processing instructions and doctypes are skipped.
+fn parse_xml(text: &str) -> Option<Element> {
+    let mut stack = vec![Element { name: String::new(), attributes: Vec::new(), children: Vec::new() }];
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let skip_past = |rest: &str, end: &str| rest.find(end).map(|idx| idx + end.len());
+        if rest.starts_with("<!--") {
+            rest = &rest[skip_past(rest, "-->")?..];
+        } else if rest.starts_with("<![CDATA[") {
+            rest = &rest[skip_past(rest, "]]>")?..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = &rest[skip_past(rest, ">")?..];
+        } else if rest.starts_with("</") {
+            rest = &rest[skip_past(rest, ">")?..];
+            if stack.len() < 2 {
+                return None;
+            }
+            let element = stack.pop()?;
+            stack.last_mut()?.children.push(element);
+        } else {
+            let (element, self_closing, after) = parse_tag(&rest[1..])?;
+            rest = after;
+            if self_closing {
+                stack.last_mut()?.children.push(element);
+            } else {
+                stack.push(element);
+            }
+        }
+    }
+    // Be forgiving about unclosed elements
+    while stack.len() > 1 {
+        let element = stack.pop()?;
+        stack.last_mut()?.children.push(element);
+    }
+    stack.pop()?.children.into_iter().find(|element| element.name == "svg")
+}
+
+/// A start tag, after its `<`. Returns the element, whether the tag closes
+/// itself, and what's after it.
+fn parse_tag(text: &str) -> Option<(Element, bool, &str)> {
+    let is_name_end = |c: char| c.is_whitespace() || c == '/' || c == '>';
+    let name_end = text.find(is_name_end)?;
+    // Drop namespace prefixes, e.g. `svg:path`
+    let name = text[..name_end].rsplit(':').next()?.to_string();
+    let mut element = Element { name, attributes: Vec::new(), children: Vec::new() };
+    let mut rest = &text[name_end..];
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with("/>") {
+            return Some((element, true, &rest[2..]));
+        }
+        if rest.starts_with('>') {
+            return Some((element, false, &rest[1..]));
+        }
+        let key_end = rest.find(|c: char| c == '=' || c.is_whitespace() || c == '/' || c == '>')?;
+        if key_end == 0 {
+            return None;
+        }
+        let key = rest[..key_end].to_string();
+        rest = rest[key_end..].trim_start();
+        if !rest.starts_with('=') {
+            // An attribute without a value
+            element.attributes.push((key, String::new()));
+            continue;
+        }
+        rest = rest[1..].trim_start();
+        let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value_end = rest[1..].find(quote)? + 1;
+        let value = rest[1..value_end]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&");
+        element.attributes.push((key, value));
+        rest = &rest[value_end + 1..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba { data: [255, 0, 0, 255] };
+
+    #[test]
+    fn xml() {
+        let root = parse_xml(r#"<?xml version="1.0"?>
+            <!DOCTYPE svg>
+            <svg xmlns="http://www.w3.org/2000/svg" >
+                <!-- <path id="commented"/> -->
+                <g id='glyph1' fill="red"><path d="M0 0" /></g>
+                <svg:rect width="1&amp;2"></svg:rect>text
+            </svg>"#).unwrap();
+        assert_eq!(root.name, "svg");
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(root.children[0].attribute("id"), Some("glyph1"));
+        assert_eq!(root.children[0].children[0].attribute("d"), Some("M0 0"));
+        assert_eq!(root.children[1].name, "rect");
+        assert_eq!(root.children[1].attribute("width"), Some("1&2"));
+        assert_eq!(parse_xml("<g></g>"), None);
+        assert_eq!(parse_xml("<svg></g></svg>"), None);
+    }
+
+    #[test]
+    fn numbers_and_colors() {
+        assert_eq!(Numbers::new("1.5.5-2,3e2 -.5e-1").collect::<Vec<_>>(), vec![1.5, 0.5, -2., 300., -0.05]);
+        let mut numbers = Numbers::new("10 110 5");
+        assert_eq!((numbers.next(), numbers.flag(), numbers.flag(), numbers.next()),
+                   (Some(10.), Some(true), Some(true), Some(0.)));
+
+        assert_eq!(parse_color("#f80"), Some(Rgba { data: [255, 136, 0, 255] }));
+        assert_eq!(parse_color("#0A0b0C"), Some(Rgba { data: [10, 11, 12, 255] }));
+        assert_eq!(parse_color("rgb(10, 50%, 300)"), Some(Rgba { data: [10, 128, 255, 255] }));
+        assert_eq!(parse_color("Navy"), Some(Rgba { data: [0, 0, 128, 255] }));
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(parse_color("chartreuse-ish"), None);
+    }
+
+    #[test]
+    fn transforms() {
+        let point = |affine: Affine| affine * Point { x: 1., y: 2. };
+        assert_eq!(point(parse_transform("translate(10)")), Point { x: 11., y: 2. });
+        assert_eq!(point(parse_transform("translate(10, 20) scale(2)")), Point { x: 12., y: 24. });
+        assert_eq!(point(parse_transform("matrix(1 0 0 -1 0 5)")), Point { x: 1., y: 3. });
+        let rotated = point(parse_transform("rotate(90 1 1)"));
+        assert!((rotated.x - 0.).abs() < 1e-5 && (rotated.y - 1.).abs() < 1e-5);
+        assert_eq!(point(parse_transform("bogus(1) skewX(45)")).x, 3.);
+    }
+
+    #[test]
+    fn path_data() {
+        let point = |x, y| Point { x, y };
+        assert_eq!(parse_path("M0 0 10 0v10h-10z"), vec![
+            DrawCommand::Line(point(0., 0.), point(10., 0.)),
+            DrawCommand::Line(point(10., 0.), point(10., 10.)),
+            DrawCommand::Line(point(10., 10.), point(0., 10.)),
+            DrawCommand::Line(point(0., 10.), point(0., 0.)),
+        ]);
+        // Unclosed subpaths are closed, and the smooth curve reflects the
+        // last control point
+        assert_eq!(parse_path("M0,0Q5,5 10,0T20,0"), vec![
+            DrawCommand::Curve(point(0., 0.), point(5., 5.), point(10., 0.)),
+            DrawCommand::Curve(point(10., 0.), point(15., -5.), point(20., 0.)),
+            DrawCommand::Line(point(20., 0.), point(0., 0.)),
+        ]);
+        // Half a circle in two quarters
+        let arc = parse_path("M0 0A5 5 0 0 1 10 0");
+        assert_eq!(arc.len(), 3);
+        match arc[0] {
+            DrawCommand::CubicCurve(_, _, _, end) => {
+                assert!((end.x - 5.).abs() < 1e-4 && (end.y + 5.).abs() < 1e-4);
+            },
+            command => panic!("Expected a cubic, got {:?}", command),
+        }
+        // Stops at the first error
+        assert_eq!(parse_path("M0 0L10 10L5").len(), 2);
+    }
+
+    #[test]
+    fn render_glyph_element() {
+        let document = r##"<svg xmlns:xlink="http://www.w3.org/1999/xlink">
+            <defs>
+                <linearGradient id="base"><stop offset="0" stop-color="#00f" stop-opacity="0.5"/></linearGradient>
+                <linearGradient id="grad" xlink:href="#base"/>
+                <rect id="square" width="100" height="100"/>
+            </defs>
+            <g id="glyph1" transform="translate(0, -200)">
+                <rect width="200" height="200" fill="red"/>
+                <circle cx="100" cy="100" r="50" style="fill: currentColor"/>
+            </g>
+            <g id="glyph2" fill="url(#grad)">
+                <use xlink:href="#square" x="-50" y="-100"/>
+                <path d="M0 0h50v-50z" fill="none"/>
+            </g>
+        </svg>"##;
+        let foreground = Rgba { data: [0, 255, 0, 255] };
+
+        let image = render(document, "glyph1", 0.1, foreground).unwrap();
+        assert_eq!(image.dimensions(), (20, 20));
+        assert_eq!(image.get_pixel(1, 1), &RED);
+        assert_eq!(image.get_pixel(10, 10), &foreground);
+        // Anti-aliased around the circle
+        assert!(image.pixels().any(|pixel| pixel.data[0] > 0 && pixel.data[1] > 0));
+
+        let image = render(document, "glyph2", 0.1, foreground).unwrap();
+        assert_eq!(image.dimensions(), (10, 10));
+        assert_eq!(image.get_pixel(5, 5).data, [0, 0, 255, 128]);
+
+        // Only the ring is filled
+        let ring = r#"<svg><path id="glyph4" fill-rule="evenodd" d="M0 0h30v30h-30zM10 10h10v10h-10z"/></svg>"#;
+        let image = render(ring, "glyph4", 0.1, foreground).unwrap();
+        assert_eq!(image.dimensions(), (3, 3));
+        assert_eq!(image.get_pixel(0, 1).data, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 1).data, [0, 0, 0, 0]);
+
+        // Everything, when the element isn't there
+        let image = render(document, "glyph3", 0.1, foreground).unwrap();
+        assert_eq!(image.dimensions(), (25, 20));
+        assert_eq!(render("<svg/>", "glyph1", 1., foreground).unwrap().dimensions(), (0, 0));
+        assert!(render("<g/>", "glyph1", 1., foreground).is_none());
+    }
+}
@@ -0,0 +1,300 @@
+use parse::{BufView, Parse};
+use std::cmp::Ordering;
+
+// Structures shared by Apple's Advanced Typography tables
+// https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6Tables.html
+
+/// Glyph id used for glyphs that have been deleted but are still in the
+/// buffer while a state machine runs
+pub(crate) const DELETED_GLYPH: u16 = 0xFFFF;
+
+/// Classes that every state table has
+pub(crate) const CLASS_END_OF_TEXT: u16 = 0;
+pub(crate) const CLASS_OUT_OF_BOUNDS: u16 = 1;
+pub(crate) const CLASS_DELETED_GLYPH: u16 = 2;
+
+/// Set on an entry to process the current glyph again with the new state
+pub(crate) const DONT_ADVANCE: u16 = 0x4000;
+
+/// Maps glyphs to values. Values are 2 or 4 bytes depending on which table
+/// the lookup is in.
+#[derive(Debug, Parse, Clone)]
+pub(crate) struct Lookup<'a>(BufView<'a, u8>);
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct BinSrchHeader {
+    unit_size: u16,
+    n_units: u16,
+    search_range: u16,
+    entry_selector: u16,
+    range_shift: u16,
+}
+
+/// Where the units of a binary searched lookup start
+const UNITS_START: usize = 2 + 10;
+
+impl<'a> Lookup<'a> {
+    pub fn value_u16(&self, glyph_id: u16) -> Option<u16> {
+        self.value(glyph_id, 2).map(|val| val as u16)
+    }
+
+    /// The value for the glyph, or `None` if the lookup doesn't have one.
+    /// `value_size` is the size of each value in bytes.
+    pub fn value(&self, glyph_id: u16, value_size: usize) -> Option<u32> {
+        let format: u16 = self.0.at_offset(0);
+        match format {
+            // Simple array, indexed by glyph id
+            0 => {
+                let offset = 2 + glyph_id as usize * value_size;
+                if offset + value_size > (self.0).0.len() {
+                    return None;
+                }
+                Some(self.read_value(offset, value_size))
+            },
+            // Segments of glyphs that share a value
+            2 => {
+                let unit = self.search_units(|unit| self.cmp_segment(unit, glyph_id))?;
+                Some(self.read_value(unit + 4, value_size))
+            },
+            // Segments of glyphs, each with its own value
+            4 => {
+                let unit = self.search_units(|unit| self.cmp_segment(unit, glyph_id))?;
+                let first: u16 = self.0.at_offset(unit + 2);
+                let values_offset: u16 = self.0.at_offset(unit + 4);
+                let offset = values_offset as usize + (glyph_id - first) as usize * value_size;
+                Some(self.read_value(offset, value_size))
+            },
+            // Single glyphs, sorted
+            6 => {
+                let unit = self.search_units(|unit| {
+                    let glyph: u16 = self.0.at_offset(unit);
+                    glyph.cmp(&glyph_id)
+                })?;
+                Some(self.read_value(unit + 2, value_size))
+            },
+            // Array of a range of glyphs
+            8 => {
+                let first: u16 = self.0.at_offset(2);
+                let count: u16 = self.0.at_offset(4);
+                if glyph_id < first || glyph_id - first >= count {
+                    return None;
+                }
+                Some(self.read_value(6 + (glyph_id - first) as usize * value_size, value_size))
+            },
+            // Like format 8, with the value size given by the lookup
+            10 => {
+                let unit_size: u16 = self.0.at_offset(2);
+                let first: u16 = self.0.at_offset(4);
+                let count: u16 = self.0.at_offset(6);
+                if glyph_id < first || glyph_id - first >= count {
+                    return None;
+                }
+                let unit_size = unit_size as usize;
+                Some(self.read_value(8 + (glyph_id - first) as usize * unit_size, unit_size))
+            },
+            _ => None,
+        }
+    }
+
+    fn read_value(&self, offset: usize, size: usize) -> u32 {
+        match size {
+            1 => self.0.at_offset::<u8>(offset) as u32,
+            2 => self.0.at_offset::<u16>(offset) as u32,
+            8 => self.0.at_offset::<u64>(offset) as u32,
+            _ => self.0.at_offset::<u32>(offset),
+        }
+    }
+
+    /// Compare a (last glyph, first glyph) segment unit to a glyph
+    fn cmp_segment(&self, unit: usize, glyph_id: u16) -> Ordering {
+        let last: u16 = self.0.at_offset(unit);
+        let first: u16 = self.0.at_offset(unit + 2);
+        if glyph_id < first {
+            Ordering::Greater
+        } else if last < glyph_id {
+            Ordering::Less
+        } else {
+            Ordering::Equal
+        }
+    }
+
+    /// Binary search the units of formats 2, 4 and 6, returning the offset
+    /// of the matching unit
+    fn search_units<F>(&self, mut f: F) -> Option<usize>
+        where F: FnMut(usize) -> Ordering {
+        let header: BinSrchHeader = self.0.at_offset(2);
+        let unit_size = header.unit_size as usize;
+        let mut size = header.n_units as usize;
+        // The last unit may be a 0xFFFF terminator, which never matches
+        // anything a real glyph would look for
+        if size == 0 {
+            return None;
+        }
+        let mut left = 0;
+        while size > 1 {
+            let half = size / 2;
+            let mid = left + half;
+            let cmp = f(UNITS_START + mid * unit_size);
+            left = if cmp == Ordering::Greater { left } else { mid };
+            size -= half;
+        }
+
+        let unit = UNITS_START + left * unit_size;
+        if f(unit) == Ordering::Equal { Some(unit) } else { None }
+    }
+}
+
+/// The extended state table used by `morx` and `kerx`.
+///
+/// Glyphs are mapped to classes, and each (state, class) pair picks an entry
+/// that says what to do and which state to go to next.
+#[derive(Debug, Parse, Clone)]
+pub(crate) struct StateTable<'a> {
+    table: BufView<'a, u8>,
+    n_classes: u32,
+    class_table_offset: u32,
+    state_array_offset: u32,
+    entry_table_offset: u32,
+}
+
+/// What to do on a transition. `data` holds the extra per-table values.
+#[derive(Debug, Clone)]
+pub(crate) struct Entry<'a> {
+    pub new_state: u16,
+    pub flags: u16,
+    data: BufView<'a, u8>,
+}
+
+impl<'a> Entry<'a> {
+    /// The `idx`th 16-bit value after the flags
+    pub fn data(&self, idx: usize) -> u16 {
+        self.data.at_offset(idx * 2)
+    }
+}
+
+impl<'a> StateTable<'a> {
+    /// A view starting at the state table header. Offsets in the subtable
+    /// headers are relative to this.
+    pub fn table(&self) -> BufView<'a, u8> {
+        self.table.clone()
+    }
+
+    pub fn class(&self, glyph_id: u16) -> u16 {
+        if glyph_id == DELETED_GLYPH {
+            return CLASS_DELETED_GLYPH;
+        }
+        let lookup: Lookup = self.table.at_offset(self.class_table_offset as usize);
+        lookup.value_u16(glyph_id).unwrap_or(CLASS_OUT_OF_BOUNDS)
+    }
+
+    /// The entry for the state and class. Each entry has `data_len` 16-bit
+    /// values after the new state and flags.
+    pub fn entry(&self, state: u16, class: u16, data_len: usize) -> Entry<'a> {
+        let class = if (class as u32) < self.n_classes { class } else { CLASS_OUT_OF_BOUNDS };
+        let row = self.state_array_offset as usize + state as usize * self.n_classes as usize * 2;
+        let entry_idx: u16 = self.table.at_offset(row + class as usize * 2);
+        let entry_offset = self.entry_table_offset as usize + entry_idx as usize * (4 + 2 * data_len);
+        let entry: BufView<u8> = self.table.at_offset(entry_offset);
+        Entry {
+            new_state: entry.at_offset(0),
+            flags: entry.at_offset(2),
+            data: entry.at_offset(4),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// A format 8 lookup of 16-bit values starting at `first`
+    pub fn trimmed_lookup(first: u16, values: &[u16]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_u16(&mut buf, 8);
+        push_u16(&mut buf, first);
+        push_u16(&mut buf, values.len() as u16);
+        for &val in values {
+            push_u16(&mut buf, val);
+        }
+        buf
+    }
+
+    /// A format 6 lookup of (glyph, value) pairs
+    pub fn single_lookup(values: &[(u16, u16)]) -> Vec<u8> {
+        let mut sorted = values.to_vec();
+        sorted.sort();
+        let mut buf = Vec::new();
+        for &val in &[6, 4, sorted.len() as u16, 0, 0, 0] {
+            push_u16(&mut buf, val);
+        }
+        for &(glyph, val) in &sorted {
+            push_u16(&mut buf, glyph);
+            push_u16(&mut buf, val);
+        }
+        buf
+    }
+
+    /// Build a state table header with its class lookup, state array and
+    /// entries (new state, flags, data...) laid out after `extra_header`
+    /// bytes of subtable-specific header. `classes` are (glyph, class).
+    pub fn state_table(extra_header: usize, classes: &[(u16, u16)], n_classes: u32,
+                       states: &[&[u16]], entries: &[&[u16]]) -> Vec<u8> {
+        let class_table = single_lookup(classes);
+
+        let class_offset = 16 + extra_header;
+        let states_offset = class_offset + class_table.len();
+        let entries_offset = states_offset + states.len() * n_classes as usize * 2;
+
+        let mut buf = Vec::new();
+        push_u32(&mut buf, n_classes);
+        push_u32(&mut buf, class_offset as u32);
+        push_u32(&mut buf, states_offset as u32);
+        push_u32(&mut buf, entries_offset as u32);
+        buf.extend(::std::iter::repeat(0).take(extra_header));
+        buf.extend(class_table);
+        for row in states {
+            assert_eq!(row.len(), n_classes as usize);
+            for &entry in row.iter() {
+                push_u16(&mut buf, entry);
+            }
+        }
+        for entry in entries {
+            for &val in entry.iter() {
+                push_u16(&mut buf, val);
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn lookup_formats() {
+        let trimmed = trimmed_lookup(10, &[5, 6, 7]);
+        let lookup = Lookup::parse(&trimmed).1;
+        assert_eq!(lookup.value_u16(9), None);
+        assert_eq!(lookup.value_u16(11), Some(6));
+        assert_eq!(lookup.value_u16(13), None);
+
+        // Format 2: glyphs 3..=5 -> 1, 8..=9 -> 2
+        let mut segments = Vec::new();
+        for &val in &[2, 6, 3, 0, 0, 0] {
+            push_u16(&mut segments, val);
+        }
+        for &(last, first, val) in &[(5, 3, 1), (9, 8, 2), (0xFFFF, 0xFFFF, 0)] {
+            push_u16(&mut segments, last);
+            push_u16(&mut segments, first);
+            push_u16(&mut segments, val);
+        }
+        let lookup = Lookup::parse(&segments).1;
+        assert_eq!(lookup.value_u16(4), Some(1));
+        assert_eq!(lookup.value_u16(9), Some(2));
+        assert_eq!(lookup.value_u16(7), None);
+
+        // Format 6: single glyphs
+        let single = single_lookup(&[(7, 70), (3, 30)]);
+        let lookup = Lookup::parse(&single).1;
+        assert_eq!(lookup.value_u16(7), Some(70));
+        assert_eq!(lookup.value_u16(5), None);
+    }
+}
@@ -0,0 +1,38 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::aat::Lookup;
+
+// https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6ankr.html
+
+/// Anchor points, used by `kerx` to attach glyphs to each other
+#[allow(dead_code)]
+#[derive(Debug, Parse, Clone)]
+pub struct Ankr<'a> {
+    table: BufView<'a, u8>,
+    version: u16,
+    flags: u16,
+    lookup_table_offset: u32,
+    glyph_data_table_offset: u32,
+}
+
+impl<'a> PrimaryTable for Ankr<'a> {
+    fn tag() -> TableTag {
+        TableTag::AnchorPoint
+    }
+}
+
+impl<'a> Ankr<'a> {
+    /// The (x, y) of the glyph's `idx`th anchor point, in font units
+    pub fn anchor(&self, glyph_id: u16, idx: u16) -> Option<(i16, i16)> {
+        let lookup: Lookup = self.table.at_offset(self.lookup_table_offset as usize);
+        let offset = lookup.value_u16(glyph_id)?;
+        let glyph_data: BufView<u8> = self.table
+            .at_offset(self.glyph_data_table_offset as usize + offset as usize);
+        let num_points: u32 = glyph_data.at_offset(0);
+        if idx as u32 >= num_points {
+            return None;
+        }
+        let point = 4 + idx as usize * 4;
+        Some((glyph_data.at_offset(point), glyph_data.at_offset(point + 2)))
+    }
+}
@@ -0,0 +1,108 @@
+use parse::{BufView, Parse};
+use parse::primitives::F2Dot14;
+use tables::{PrimaryTable, TableTag};
+use tables::fvar::round_to_f2dot14;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/avar
+
+/// Remaps normalized coordinates so the design space isn't linear along an
+/// axis
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Avar<'a> {
+    major_version: u16,
+    minor_version: u16,
+    reserved: u16,
+    axis_count: u16,
+    segment_maps: BufView<'a, u8>,
+}
+
+impl<'a> PrimaryTable for Avar<'a> {
+    fn tag() -> TableTag {
+        TableTag::AxisVariation
+    }
+}
+
+#[derive(Debug, Parse)]
+struct AxisValueMap {
+    from_coordinate: F2Dot14,
+    to_coordinate: F2Dot14,
+}
+
+impl<'a> Avar<'a> {
+    /// Each axis' (from, to) pairs, sorted by `from`
+    pub fn segment_maps(&self) -> Vec<Vec<(f32, f32)>> {
+        let mut offset = 0;
+        (0..self.axis_count)
+            .map(|_| {
+                let count: u16 = self.segment_maps.at_offset(offset);
+                let map = (0..count as usize)
+                    .map(|idx| {
+                        let pair: AxisValueMap = self.segment_maps.at_offset(offset + 2 + idx * 4);
+                        ((pair.from_coordinate).0, (pair.to_coordinate).0)
+                    })
+                    .collect();
+                offset += 2 + count as usize * 4;
+                map
+            })
+            .collect()
+    }
+
+    /// Remap a normalized coordinate on the `axis`th axis
+    pub fn map(&self, axis: usize, coord: f32) -> f32 {
+        match self.segment_maps().get(axis) {
+            Some(map) => map_coordinate(map, coord),
+            None => coord,
+        }
+    }
+}
+
+/// Piecewise linear interpolation between the map's points
+pub(crate) fn map_coordinate(map: &[(f32, f32)], coord: f32) -> f32 {
+    // Maps without the required -1, 0 and 1 entries are ignored
+    if map.len() < 3 {
+        return coord;
+    }
+    for (idx, &(from, to)) in map.iter().enumerate() {
+        if coord == from {
+            return to;
+        }
+        if coord < from {
+            if idx == 0 {
+                return to;
+            }
+            let (prev_from, prev_to) = map[idx - 1];
+            let mapped = prev_to + (to - prev_to) * (coord - prev_from) / (from - prev_from);
+            return round_to_f2dot14(mapped);
+        }
+    }
+    map[map.len() - 1].1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::push_u16;
+
+    #[test]
+    fn segment_map() {
+        let mut avar = Vec::new();
+        for &val in &[1, 0, 0, 2] {
+            push_u16(&mut avar, val);
+        }
+        // wght: 0.5 moves to 0.25
+        push_u16(&mut avar, 4);
+        for &val in &[-16384i16, -16384, 0, 0, 8192, 4096, 16384, 16384] {
+            push_u16(&mut avar, val as u16);
+        }
+        // wdth has no map
+        push_u16(&mut avar, 0);
+
+        let avar = Avar::parse(&avar).1;
+        assert_eq!(avar.map(0, 0.5), 0.25);
+        assert_eq!(avar.map(0, 0.75), 0.625);
+        assert_eq!(avar.map(0, -0.5), -0.5);
+        assert_eq!(avar.map(1, 0.5), 0.5);
+        assert_eq!(avar.map(2, 0.5), 0.5);
+    }
+}
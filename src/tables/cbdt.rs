@@ -0,0 +1,116 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::cblc::Cblc;
+use tables::eblc::{BigGlyphMetrics, BitmapSize, GlyphLocation, SmallGlyphMetrics};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/cbdt
+
+/// The PNG glyph images for the strikes in `CBLC`
+#[derive(Debug, Parse)]
+pub struct Cbdt<'a>(BufView<'a, u8>);
+
+impl<'a> PrimaryTable for Cbdt<'a> {
+    fn tag() -> TableTag {
+        TableTag::ColorBitmapData
+    }
+}
+
+impl<'a> Cbdt<'a> {
+    /// The glyph's metrics and PNG data
+    pub fn glyph_png(&self, cblc: &Cblc, strike: &BitmapSize, glyph_id: u16) -> Option<(BigGlyphMetrics, &'a [u8])> {
+        let GlyphLocation { image_format, offset, length, metrics } = cblc.glyph_location(strike, glyph_id)?;
+        let data = self.0 .0.get(offset..offset + length)?;
+
+        let (data, metrics) = match image_format {
+            17 => {
+                if data.len() < SmallGlyphMetrics::approx_file_size() {
+                    return None;
+                }
+                let (data, metrics) = SmallGlyphMetrics::parse(data);
+                (data, metrics.into())
+            },
+            18 => {
+                if data.len() < BigGlyphMetrics::approx_file_size() {
+                    return None;
+                }
+                BigGlyphMetrics::parse(data)
+            },
+            19 => (data, metrics?),
+            _ => return None,
+        };
+        let length = u32::parse(data.get(..4)?).1 as usize;
+        Some((metrics, data.get(4..4 + length)?))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tables::ebdt::tests::build_strike;
+    use tables::eblc::tests::big_metrics;
+    use test_utils::push_u32;
+
+    /// A PNG of a `width` by `height` image of one RGBA color
+    pub fn png(width: u32, height: u32, color: [u8; 4]) -> Vec<u8> {
+        use image::ColorType;
+        use image::png::PNGEncoder;
+
+        let pixels: Vec<u8> = (0..width * height).flat_map(|_| color.iter().cloned()).collect();
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png).encode(&pixels, width, height, ColorType::RGBA(8)).unwrap();
+        png
+    }
+
+    #[test]
+    fn png_formats() {
+        let mut format17 = vec![2, 3, 0, 2, 4];
+        push_u32(&mut format17, 3);
+        format17.extend_from_slice(b"abc");
+        let mut format18 = big_metrics(5, 6);
+        push_u32(&mut format18, 2);
+        format18.extend_from_slice(b"de");
+        let mut short = big_metrics(5, 6);
+        push_u32(&mut short, 10);
+        short.extend_from_slice(b"fg");
+        let (cblc, cbdt) = build_strike(20, 32, 4, &[(17, format17), (18, format18), (18, short), (1, vec![0; 6])]);
+        let cblc = Cblc::parse(&cblc).1;
+        let cbdt = Cbdt::parse(&cbdt).1;
+        let strike = &cblc.strikes()[0];
+
+        let (metrics, png) = cbdt.glyph_png(&cblc, strike, 4).unwrap();
+        assert_eq!((metrics.width, metrics.height, metrics.hori_advance), (3, 2, 4));
+        assert_eq!(png, b"abc");
+        let (metrics, png) = cbdt.glyph_png(&cblc, strike, 5).unwrap();
+        assert_eq!(metrics, BigGlyphMetrics::parse(&big_metrics(5, 6)).1);
+        assert_eq!(png, b"de");
+        assert_eq!(cbdt.glyph_png(&cblc, strike, 6), None);
+        assert_eq!(cbdt.glyph_png(&cblc, strike, 7), None);
+    }
+
+    #[test]
+    fn font_color_bitmap() {
+        use font::Font;
+        use test_utils::{font_buf, with_tables};
+
+        let font_buf = font_buf();
+        let glyph_id = Font::from_buffer(&font_buf).unwrap().get_glyph_id('A').unwrap();
+        let png = png(8, 6, [255, 0, 0, 255]);
+        // Sits 2 pixels right of the origin with its top 5 above the baseline
+        let mut image = vec![6, 8, 2, 5, 10];
+        push_u32(&mut image, png.len() as u32);
+        image.extend(png);
+        let (cblc, cbdt) = build_strike(20, 32, glyph_id as u16, &[(17, image)]);
+        let buf = with_tables(&font_buf, &[(b"CBLC", cblc), (b"CBDT", cbdt)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let glyph = font.color_bitmap(glyph_id, 20).unwrap();
+        assert_eq!(glyph.image.dimensions(), (8, 6));
+        assert_eq!(glyph.image.get_pixel(3, 3).data, [255, 0, 0, 255]);
+        assert_eq!((glyph.left_bearing, glyph.top_bearing), (2., 5.));
+
+        let glyph = font.color_bitmap(glyph_id, 10).unwrap();
+        assert_eq!(glyph.image.dimensions(), (4, 3));
+        assert_eq!((glyph.left_bearing, glyph.top_bearing), (1., 2.5));
+        assert!(font.color_bitmap(glyph_id + 1, 20).is_none());
+    }
+}
@@ -0,0 +1,33 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::eblc::{self, BitmapSize, GlyphLocation};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/cblc
+
+/// Where the color glyph images of each strike are in `CBDT`. Laid out
+/// like `EBLC`.
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Cblc<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    num_sizes: u32,
+}
+
+impl<'a> PrimaryTable for Cblc<'a> {
+    fn tag() -> TableTag {
+        TableTag::ColorBitmapLocation
+    }
+}
+
+impl<'a> Cblc<'a> {
+    pub fn strikes(&self) -> Vec<BitmapSize> {
+        eblc::strikes(self.table.0, self.num_sizes)
+    }
+
+    /// Where the glyph's image is in `CBDT`, if the strike has it
+    pub fn glyph_location(&self, strike: &BitmapSize, glyph_id: u16) -> Option<GlyphLocation> {
+        eblc::glyph_location(self.table.0, strike, glyph_id)
+    }
+}
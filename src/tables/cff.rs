@@ -0,0 +1,1151 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use render::DrawCommand;
+use math::{Affine, Point};
+use tables::variation_store::ItemVariationStore;
+
+// http://wwwimages.adobe.com/content/dam/acom/en/devnet/font/pdfs/5176.CFF.pdf
+// http://wwwimages.adobe.com/content/dam/acom/en/devnet/font/pdfs/5177.Type2.pdf
+
+/// PostScript outlines, in `OTTO` fonts
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Cff<'a> {
+    table: BufView<'a, u8>,
+    major_version: u8,
+    minor_version: u8,
+    header_size: u8,
+    offset_size: u8,
+}
+
+impl<'a> PrimaryTable for Cff<'a> {
+    fn tag() -> TableTag {
+        TableTag::CompactFontFormat
+    }
+}
+
+/// An array of variable sized objects
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Index<'a> {
+    count: usize,
+    offset_size: usize,
+    offsets: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> Index<'a> {
+    /// Parse an INDEX with a `count_size` byte count (2 in CFF, 4 in CFF2),
+    /// and return it with the bytes after it
+    pub(crate) fn parse(buf: &'a [u8], count_size: usize) -> Option<(Index<'a>, &'a [u8])> {
+        let count = read_uint(buf.get(..count_size)?);
+        if count == 0 {
+            let empty = Index { count: 0, offset_size: 1, offsets: &[], data: &[] };
+            return Some((empty, &buf[count_size..]));
+        }
+        let offset_size = *buf.get(count_size)? as usize;
+        let offsets_start = count_size + 1;
+        let offsets = buf.get(offsets_start..offsets_start + (count + 1) * offset_size)?;
+        // Offsets start at 1
+        let data_size = read_uint(&offsets[count * offset_size..]).checked_sub(1)?;
+        let data_start = offsets_start + offsets.len();
+        let data = buf.get(data_start..data_start + data_size)?;
+        Some((Index { count, offset_size, offsets, data }, &buf[data_start + data_size..]))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> Option<&'a [u8]> {
+        if idx >= self.count {
+            return None;
+        }
+        let offset = |idx: usize| read_uint(&self.offsets[idx * self.offset_size..(idx + 1) * self.offset_size]);
+        let start = offset(idx).checked_sub(1)?;
+        let end = offset(idx + 1).checked_sub(1)?;
+        self.data.get(start..end)
+    }
+}
+
+/// Big endian unsigned integer of any size up to 4 bytes
+fn read_uint(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |val, &byte| val << 8 | byte as usize)
+}
+
+/// The operators and operands of a DICT. Two byte operators are
+/// `1200 + second byte`.
+pub(crate) fn parse_dict(buf: &[u8]) -> Vec<(u16, Vec<f32>)> {
+    let mut entries = Vec::new();
+    let mut operands = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let b0 = buf[pos];
+        pos += 1;
+        let byte = |pos: usize| buf.get(pos).cloned().unwrap_or(0) as i32;
+        match b0 {
+            // 22 to 24 are only used in CFF2
+            0..=27 => {
+                let op = if b0 == 12 {
+                    pos += 1;
+                    1200 + byte(pos - 1) as u16
+                } else {
+                    b0 as u16
+                };
+                entries.push((op, operands.clone()));
+                operands.clear();
+            },
+            28 => {
+                operands.push((byte(pos) << 8 | byte(pos + 1)) as i16 as f32);
+                pos += 2;
+            },
+            29 => {
+                operands.push((byte(pos) << 24 | byte(pos + 1) << 16 | byte(pos + 2) << 8 | byte(pos + 3)) as f32);
+                pos += 4;
+            },
+            30 => {
+                let (len, val) = parse_real(&buf[pos..]);
+                operands.push(val);
+                pos += len;
+            },
+            32..=246 => operands.push(b0 as f32 - 139.),
+            247..=250 => {
+                operands.push(((b0 as i32 - 247) * 256 + byte(pos) + 108) as f32);
+                pos += 1;
+            },
+            251..=254 => {
+                operands.push((-(b0 as i32 - 251) * 256 - byte(pos) - 108) as f32);
+                pos += 1;
+            },
+            // Reserved
+            _ => (),
+        }
+    }
+    entries
+}
+
+/// A real number operand, stored as nibbles. Returns the number of bytes
+/// used and the value.
+fn parse_real(buf: &[u8]) -> (usize, f32) {
+    let mut text = String::new();
+    for (idx, &byte) in buf.iter().enumerate() {
+        for &nibble in &[byte >> 4, byte & 0x0F] {
+            match nibble {
+                0..=9 => text.push((b'0' + nibble) as char),
+                0xA => text.push('.'),
+                0xB => text.push('E'),
+                0xC => text.push_str("E-"),
+                0xE => text.push('-'),
+                0xF => return (idx + 1, text.parse().unwrap_or(0.)),
+                _ => (),
+            }
+        }
+    }
+    (buf.len(), text.parse().unwrap_or(0.))
+}
+
+pub(crate) fn dict_value(dict: &[(u16, Vec<f32>)], op: u16) -> Option<&[f32]> {
+    dict.iter()
+        .find(|&&(entry_op, _)| entry_op == op)
+        .map(|&(_, ref operands)| &operands[..])
+}
+
+// Top DICT operators
+const CHARSET: u16 = 15;
+pub(crate) const CHAR_STRINGS: u16 = 17;
+pub(crate) const PRIVATE: u16 = 18;
+const CHARSTRING_TYPE: u16 = 1206;
+const ROS: u16 = 1230;
+pub(crate) const FD_ARRAY: u16 = 1236;
+pub(crate) const FD_SELECT: u16 = 1237;
+// Private DICT operators
+pub(crate) const SUBRS: u16 = 19;
+
+/// The Top DICT values used to find glyphs. Offsets are from the start of
+/// the table.
+struct TopDict {
+    charset: usize,
+    char_strings: usize,
+    charstring_type: u8,
+    /// Size and offset
+    private: Option<(usize, usize)>,
+    cid_keyed: bool,
+    fd_array: Option<usize>,
+    fd_select: Option<usize>,
+}
+
+/// The INDEXes following the header
+struct Indexes<'a> {
+    names: Index<'a>,
+    top_dicts: Index<'a>,
+    // Holds the names of non-standard strings. Unused.
+    _strings: Index<'a>,
+    global_subrs: Index<'a>,
+}
+
+/// The arguments of an `endchar` that builds an accented glyph out of
+/// two others
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Seac {
+    adx: f32,
+    ady: f32,
+    base_char: u8,
+    accent_char: u8,
+}
+
+impl<'a> Cff<'a> {
+    fn indexes(&self) -> Option<Indexes<'a>> {
+        let buf = self.table.0.get(self.header_size as usize..)?;
+        let (names, buf) = Index::parse(buf, 2)?;
+        let (top_dicts, buf) = Index::parse(buf, 2)?;
+        let (strings, buf) = Index::parse(buf, 2)?;
+        let (global_subrs, _) = Index::parse(buf, 2)?;
+        Some(Indexes { names, top_dicts, _strings: strings, global_subrs })
+    }
+
+    fn top_dict(&self) -> Option<TopDict> {
+        let dict = parse_dict(self.indexes()?.top_dicts.get(0)?);
+        let offset = |op| dict_value(&dict, op).and_then(|vals| vals.first().map(|&val| val as usize));
+        Some(TopDict {
+            charset: offset(CHARSET).unwrap_or(0),
+            char_strings: offset(CHAR_STRINGS)?,
+            charstring_type: offset(CHARSTRING_TYPE).unwrap_or(2) as u8,
+            private: dict_value(&dict, PRIVATE)
+                .and_then(|vals| if vals.len() == 2 { Some((vals[0] as usize, vals[1] as usize)) } else { None }),
+            cid_keyed: dict_value(&dict, ROS).is_some(),
+            fd_array: offset(FD_ARRAY),
+            fd_select: offset(FD_SELECT),
+        })
+    }
+
+    /// The PostScript name of the font
+    pub fn font_name(&self) -> Option<String> {
+        let name = self.indexes()?.names.get(0)?;
+        Some(name.iter().map(|&byte| byte as char).collect())
+    }
+
+    /// Whether glyphs are identified by CID instead of by name
+    pub fn is_cid_keyed(&self) -> bool {
+        self.top_dict().map_or(false, |top| top.cid_keyed)
+    }
+
+    pub fn num_glyphs(&self) -> usize {
+        self.char_strings().map_or(0, |char_strings| char_strings.len())
+    }
+
+    fn char_strings(&self) -> Option<Index<'a>> {
+        let top = self.top_dict()?;
+        Index::parse(self.table.0.get(top.char_strings..)?, 2).map(|(index, _)| index)
+    }
+
+    /// The glyph's SID (CID in CID-keyed fonts), from the charset
+    pub fn charset_id(&self, glyph_id: u32) -> Option<u16> {
+        let top = self.top_dict()?;
+        let num_glyphs = self.num_glyphs() as u32;
+        if glyph_id >= num_glyphs {
+            return None;
+        }
+        if glyph_id == 0 {
+            return Some(0);
+        }
+        match top.charset {
+            // ISOAdobe, where glyph ids and SIDs match
+            0 => Some(glyph_id as u16),
+            // Expert and ExpertSubset aren't supported
+            1 | 2 => None,
+            offset => {
+                let charset: BufView<u8> = self.table.at_offset(offset);
+                let format: u8 = charset.at_offset(0);
+                if format == 0 {
+                    return Some(charset.at_offset(1 + (glyph_id as usize - 1) * 2));
+                }
+                // Ranges of consecutive ids, with an 8 or 16 bit count of
+                // ids after the first
+                let count_size = if format == 1 { 1 } else { 2 };
+                let mut pos = 1;
+                let mut range_start_glyph = 1;
+                while range_start_glyph < num_glyphs {
+                    let first: u16 = charset.at_offset(pos);
+                    let left = if count_size == 1 {
+                        charset.at_offset::<u8>(pos + 2) as u32
+                    } else {
+                        charset.at_offset::<u16>(pos + 2) as u32
+                    };
+                    if glyph_id <= range_start_glyph + left {
+                        return Some(first + (glyph_id - range_start_glyph) as u16);
+                    }
+                    range_start_glyph += left + 1;
+                    pos += 2 + count_size;
+                }
+                None
+            },
+        }
+    }
+
+    /// The glyph with the SID in the charset
+    fn glyph_id_for_sid(&self, sid: u16) -> Option<u32> {
+        (0..self.num_glyphs() as u32).find(|&glyph_id| self.charset_id(glyph_id) == Some(sid))
+    }
+
+    /// Which Font DICT of a CID-keyed font the glyph uses
+    pub fn font_dict_index(&self, glyph_id: u32) -> Option<u8> {
+        let top = self.top_dict()?;
+        fd_select(self.table.at_offset(top.fd_select?), glyph_id)
+    }
+
+    /// The local subroutines of the Private DICT the glyph uses
+    fn local_subrs(&self, top: &TopDict, glyph_id: u32) -> Option<Index<'a>> {
+        let (size, offset) = if top.cid_keyed {
+            let fd_array = Index::parse(self.table.0.get(top.fd_array?..)?, 2)?.0;
+            let font_dict = parse_dict(fd_array.get(self.font_dict_index(glyph_id)? as usize)?);
+            let private = dict_value(&font_dict, PRIVATE)?;
+            if private.len() != 2 {
+                return None;
+            }
+            (private[0] as usize, private[1] as usize)
+        } else {
+            top.private?
+        };
+        let private = parse_dict(self.table.0.get(offset..offset + size)?);
+        // Relative to the Private DICT
+        let subrs = *dict_value(&private, SUBRS)?.first()? as usize;
+        Index::parse(self.table.0.get(offset + subrs..)?, 2).map(|(index, _)| index)
+    }
+
+    fn run_charstring(&self, glyph_id: u32) -> Option<(Vec<DrawCommand>, Option<Seac>)> {
+        let top = self.top_dict()?;
+        if top.charstring_type != 2 {
+            return None;
+        }
+        let code = self.char_strings()?.get(glyph_id as usize)?;
+        let mut interpreter = Interpreter::new(self.indexes()?.global_subrs, self.local_subrs(&top, glyph_id));
+        interpreter.run(code)?;
+        Some((interpreter.path.commands, interpreter.seac))
+    }
+
+    /// The glyph's outline, in font units
+    pub fn glyph_outline(&self, glyph_id: u32) -> Option<Vec<DrawCommand>> {
+        let (commands, seac) = self.run_charstring(glyph_id)?;
+        let seac = match seac {
+            Some(seac) => seac,
+            None => return Some(commands),
+        };
+
+        // Accented glyphs are drawn from StandardEncoding character codes
+        let component = |code: u8| standard_encoding_sid(code).and_then(|sid| self.glyph_id_for_sid(sid));
+        let mut commands = self.run_charstring(component(seac.base_char)?)?.0;
+        let accent = self.run_charstring(component(seac.accent_char)?)?.0;
+        let shift = Affine::translation(seac.adx, seac.ady);
+        commands.extend(accent.into_iter().map(|command| command.transform(shift)));
+        Some(commands)
+    }
+}
+
+/// Look up the glyph's Font DICT in an FDSelect
+pub(crate) fn fd_select(fd_select: BufView<u8>, glyph_id: u32) -> Option<u8> {
+    let format: u8 = fd_select.at_offset(0);
+    match format {
+        0 => Some(fd_select.at_offset(1 + glyph_id as usize)),
+        // Ranges of glyphs, where the last range is followed by a sentinel
+        // glyph id. Format 4 (CFF2 only) has bigger fields.
+        3 | 4 => {
+            let (range_count, pos, glyph_size, range_size) = if format == 3 {
+                (fd_select.at_offset::<u16>(1) as usize, 3, 2, 3)
+            } else {
+                (fd_select.at_offset::<u32>(1) as usize, 5, 4, 6)
+            };
+            let glyph_at = |offset: usize| if glyph_size == 2 {
+                fd_select.at_offset::<u16>(offset) as u32
+            } else {
+                fd_select.at_offset::<u32>(offset)
+            };
+            for idx in 0..range_count {
+                let first = glyph_at(pos + idx * range_size);
+                let next = glyph_at(pos + (idx + 1) * range_size);
+                if glyph_id >= first && glyph_id < next {
+                    let fd_offset = pos + idx * range_size + glyph_size;
+                    return Some(if format == 3 {
+                        fd_select.at_offset(fd_offset)
+                    } else {
+                        fd_select.at_offset::<u16>(fd_offset) as u8
+                    });
+                }
+            }
+            None
+        },
+        _ => None,
+    }
+}
+
+/// The SID of a character code in StandardEncoding
+fn standard_encoding_sid(code: u8) -> Option<u16> {
+    // The codes after ASCII that have a glyph, in SID order
+    const UPPER_CODES: [u8; 54] = [
+        161, 162, 163, 164, 165, 166, 167, 168, 169, 170, 171, 172, 173, 174, 175, 177, 178, 179,
+        180, 182, 183, 184, 185, 186, 187, 188, 189, 191, 193, 194, 195, 196, 197, 198, 199, 200,
+        202, 203, 205, 206, 207, 208, 225, 227, 232, 233, 234, 235, 241, 245, 248, 249, 250, 251,
+    ];
+    match code {
+        32..=126 => Some(code as u16 - 31),
+        _ => UPPER_CODES.iter().position(|&upper| upper == code).map(|idx| 96 + idx as u16),
+    }
+}
+
+/// Subroutine numbers are stored biased so more of them fit in one byte
+pub(crate) fn subr_bias(count: usize) -> i32 {
+    if count < 1240 {
+        107
+    } else if count < 33900 {
+        1131
+    } else {
+        32768
+    }
+}
+
+const MAX_SUBR_DEPTH: usize = 10;
+const TRANSIENT_ARRAY_SIZE: usize = 32;
+
+/// Builds the outline from relative moves
+pub(crate) struct Path {
+    pub(crate) commands: Vec<DrawCommand>,
+    start: Point,
+    current: Point,
+    open: bool,
+}
+
+impl Path {
+    pub(crate) fn new() -> Path {
+        let origin = Point { x: 0., y: 0. };
+        Path {
+            commands: Vec::new(),
+            start: origin,
+            current: origin,
+            open: false,
+        }
+    }
+
+    pub(crate) fn move_by(&mut self, dx: f32, dy: f32) {
+        self.close();
+        self.current = Point { x: self.current.x + dx, y: self.current.y + dy };
+        self.start = self.current;
+    }
+
+    pub(crate) fn line_by(&mut self, dx: f32, dy: f32) {
+        let end = Point { x: self.current.x + dx, y: self.current.y + dy };
+        self.commands.push(DrawCommand::Line(self.current, end));
+        self.current = end;
+        self.open = true;
+    }
+
+    /// Each point is relative to the one before it
+    pub(crate) fn curve_by(&mut self, dx1: f32, dy1: f32, dx2: f32, dy2: f32, dx3: f32, dy3: f32) {
+        let control1 = Point { x: self.current.x + dx1, y: self.current.y + dy1 };
+        let control2 = Point { x: control1.x + dx2, y: control1.y + dy2 };
+        let end = Point { x: control2.x + dx3, y: control2.y + dy3 };
+        self.commands.push(DrawCommand::CubicCurve(self.current, control1, control2, end));
+        self.current = end;
+        self.open = true;
+    }
+
+    /// Contours are closed implicitly
+    pub(crate) fn close(&mut self) {
+        if self.open && self.current != self.start {
+            self.commands.push(DrawCommand::Line(self.current, self.start));
+        }
+        self.current = self.start;
+        self.open = false;
+    }
+}
+
+/// The variation store and location used by CFF2 `blend`s
+pub(crate) struct Blend<'a> {
+    store: Option<ItemVariationStore<'a>>,
+    coords: Vec<f32>,
+    vsindex: u16,
+    /// Scalars of the current `vsindex`'s regions
+    scalars: Option<Vec<f32>>,
+}
+
+impl<'a> Blend<'a> {
+    pub(crate) fn new(store: Option<ItemVariationStore<'a>>, coords: &[f32], vsindex: u16) -> Blend<'a> {
+        Blend {
+            store,
+            coords: coords.to_vec(),
+            vsindex,
+            scalars: None,
+        }
+    }
+
+    fn scalars(&mut self) -> Option<&[f32]> {
+        if self.scalars.is_none() {
+            let scalars = self.store.as_ref()?.region_scalars(self.vsindex, &self.coords)?;
+            self.scalars = Some(scalars);
+        }
+        self.scalars.as_ref().map(|scalars| &scalars[..])
+    }
+}
+
+/// Runs Type 2 (and CFF2) charstrings. Hints are skipped.
+pub(crate) struct Interpreter<'a> {
+    global_subrs: Index<'a>,
+    local_subrs: Option<Index<'a>>,
+    stack: Vec<f32>,
+    transient: [f32; TRANSIENT_ARRAY_SIZE],
+    stem_count: usize,
+    width_parsed: bool,
+    seac: Option<Seac>,
+    /// Only set for CFF2
+    blend: Option<Blend<'a>>,
+    pub(crate) path: Path,
+}
+
+impl<'a> Interpreter<'a> {
+    pub(crate) fn new(global_subrs: Index<'a>, local_subrs: Option<Index<'a>>) -> Interpreter<'a> {
+        Interpreter {
+            global_subrs,
+            local_subrs,
+            stack: Vec::new(),
+            transient: [0.; TRANSIENT_ARRAY_SIZE],
+            stem_count: 0,
+            width_parsed: false,
+            seac: None,
+            blend: None,
+            path: Path::new(),
+        }
+    }
+
+    /// An interpreter for CFF2 charstrings, which have no widths and can
+    /// `blend`
+    pub(crate) fn with_blend(global_subrs: Index<'a>, local_subrs: Option<Index<'a>>, blend: Blend<'a>) -> Interpreter<'a> {
+        Interpreter {
+            width_parsed: true,
+            blend: Some(blend),
+            ..Interpreter::new(global_subrs, local_subrs)
+        }
+    }
+
+    pub(crate) fn run(&mut self, code: &[u8]) -> Option<()> {
+        self.execute(code, 0)?;
+        self.path.close();
+        Some(())
+    }
+
+    /// The first stack-clearing operator may have the advance width under
+    /// its arguments. Advances come from `hmtx`, so it is dropped.
+    fn check_width(&mut self, has_width: bool) {
+        if !self.width_parsed && has_width && !self.stack.is_empty() {
+            self.stack.remove(0);
+        }
+        self.width_parsed = true;
+    }
+
+    fn pop(&mut self) -> Option<f32> {
+        self.stack.pop()
+    }
+
+    fn call_subr(&mut self, subrs: Option<Index<'a>>, depth: usize) -> Option<bool> {
+        let subrs = subrs?;
+        let idx = self.pop()? as i32 + subr_bias(subrs.len());
+        if idx < 0 || depth >= MAX_SUBR_DEPTH {
+            return None;
+        }
+        let code = subrs.get(idx as usize)?;
+        self.execute(code, depth + 1)
+    }
+
+    /// Returns whether `endchar` was reached
+    fn execute(&mut self, code: &[u8], depth: usize) -> Option<bool> {
+        let mut pos = 0;
+        while pos < code.len() {
+            let b0 = code[pos];
+            pos += 1;
+            let byte = |pos: usize| code.get(pos).cloned().unwrap_or(0) as i32;
+            match b0 {
+                28 => {
+                    self.stack.push((byte(pos) << 8 | byte(pos + 1)) as i16 as f32);
+                    pos += 2;
+                },
+                32..=246 => self.stack.push(b0 as f32 - 139.),
+                247..=250 => {
+                    self.stack.push(((b0 as i32 - 247) * 256 + byte(pos) + 108) as f32);
+                    pos += 1;
+                },
+                251..=254 => {
+                    self.stack.push((-(b0 as i32 - 251) * 256 - byte(pos) - 108) as f32);
+                    pos += 1;
+                },
+                // 16.16 fixed
+                255 => {
+                    let val = byte(pos) << 24 | byte(pos + 1) << 16 | byte(pos + 2) << 8 | byte(pos + 3);
+                    self.stack.push(val as f32 / 65536.);
+                    pos += 4;
+                },
+                // callsubr
+                10 => {
+                    let subrs = self.local_subrs;
+                    if self.call_subr(subrs, depth)? {
+                        return Some(true);
+                    }
+                },
+                // callgsubr
+                29 => {
+                    let subrs = Some(self.global_subrs);
+                    if self.call_subr(subrs, depth)? {
+                        return Some(true);
+                    }
+                },
+                // return
+                11 => return Some(false),
+                // vsindex
+                15 if self.blend.is_some() => {
+                    let vsindex = self.pop()?;
+                    let blend = self.blend.as_mut().unwrap();
+                    blend.vsindex = vsindex as u16;
+                    blend.scalars = None;
+                },
+                // blend
+                16 if self.blend.is_some() => self.blend()?,
+                // endchar
+                14 => {
+                    let len = self.stack.len();
+                    self.check_width(len == 1 || len == 5);
+                    if self.stack.len() == 4 {
+                        self.seac = Some(Seac {
+                            adx: self.stack[0],
+                            ady: self.stack[1],
+                            base_char: self.stack[2] as u8,
+                            accent_char: self.stack[3] as u8,
+                        });
+                    }
+                    self.stack.clear();
+                    return Some(true);
+                },
+                // hintmask, cntrmask
+                19 | 20 => {
+                    // Arguments are an implied vstem
+                    self.stems();
+                    pos += (self.stem_count + 7) / 8;
+                },
+                12 => {
+                    let b1 = byte(pos);
+                    pos += 1;
+                    self.escape_operator(b1 as u8)?;
+                },
+                op => self.path_operator(op),
+            }
+        }
+        Some(false)
+    }
+
+    /// Replace each of the top `n` default values and their deltas with
+    /// the value at the current location
+    fn blend(&mut self) -> Option<()> {
+        let count = self.pop()? as usize;
+        let scalars = self.blend.as_mut()?.scalars()?.to_vec();
+        let total = count * (scalars.len() + 1);
+        if self.stack.len() < total {
+            return None;
+        }
+        let start = self.stack.len() - total;
+        for idx in 0..count {
+            let deltas = start + count + idx * scalars.len();
+            let delta: f32 = scalars.iter()
+                .enumerate()
+                .map(|(region, &scalar)| self.stack[deltas + region] * scalar)
+                .sum();
+            self.stack[start + idx] += delta;
+        }
+        self.stack.truncate(start + count);
+        Some(())
+    }
+
+    /// hstem, vstem, hstemhm, vstemhm
+    fn stems(&mut self) {
+        let len = self.stack.len();
+        self.check_width(len % 2 == 1);
+        self.stem_count += self.stack.len() / 2;
+        self.stack.clear();
+    }
+
+    fn path_operator(&mut self, op: u8) {
+        let len = self.stack.len();
+        match op {
+            1 | 3 | 18 | 23 => return self.stems(),
+            // rmoveto
+            21 => {
+                self.check_width(len > 2);
+                if self.stack.len() >= 2 {
+                    self.path.move_by(self.stack[0], self.stack[1]);
+                }
+            },
+            // hmoveto
+            22 => {
+                self.check_width(len > 1);
+                if let Some(&dx) = self.stack.first() {
+                    self.path.move_by(dx, 0.);
+                }
+            },
+            // vmoveto
+            4 => {
+                self.check_width(len > 1);
+                if let Some(&dy) = self.stack.first() {
+                    self.path.move_by(0., dy);
+                }
+            },
+            _ => line_and_curve_operator(&mut self.path, op, &self.stack),
+        }
+        self.stack.clear();
+    }
+
+    fn escape_operator(&mut self, op: u8) -> Option<()> {
+        match op {
+            // hflex, flex, hflex1, flex1
+            34..=37 => {
+                flex_operator(&mut self.path, op, &self.stack);
+                self.stack.clear();
+            },
+            // and
+            3 => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push((a != 0. && b != 0.) as u8 as f32);
+            },
+            // or
+            4 => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push((a != 0. || b != 0.) as u8 as f32);
+            },
+            // not
+            5 => {
+                let a = self.pop()?;
+                self.stack.push((a == 0.) as u8 as f32);
+            },
+            // abs
+            9 => {
+                let a = self.pop()?;
+                self.stack.push(a.abs());
+            },
+            // add
+            10 => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push(a + b);
+            },
+            // sub
+            11 => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push(a - b);
+            },
+            // div
+            12 => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push(if b == 0. { 0. } else { a / b });
+            },
+            // neg
+            14 => {
+                let a = self.pop()?;
+                self.stack.push(-a);
+            },
+            // eq
+            15 => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push((a == b) as u8 as f32);
+            },
+            // drop
+            18 => {
+                self.pop()?;
+            },
+            // put
+            20 => {
+                let (idx, val) = (self.pop()? as usize, self.pop()?);
+                *self.transient.get_mut(idx)? = val;
+            },
+            // get
+            21 => {
+                let idx = self.pop()? as usize;
+                let val = *self.transient.get(idx)?;
+                self.stack.push(val);
+            },
+            // ifelse
+            22 => {
+                let (v2, v1, s2, s1) = (self.pop()?, self.pop()?, self.pop()?, self.pop()?);
+                self.stack.push(if v1 <= v2 { s1 } else { s2 });
+            },
+            // random. Always the same so rendering is repeatable.
+            23 => self.stack.push(0.5),
+            // mul
+            24 => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push(a * b);
+            },
+            // sqrt
+            26 => {
+                let a = self.pop()?;
+                self.stack.push(a.max(0.).sqrt());
+            },
+            // dup
+            27 => {
+                let a = *self.stack.last()?;
+                self.stack.push(a);
+            },
+            // exch
+            28 => {
+                let len = self.stack.len();
+                if len < 2 {
+                    return None;
+                }
+                self.stack.swap(len - 1, len - 2);
+            },
+            // index
+            29 => {
+                let idx = self.pop()?;
+                let len = self.stack.len();
+                let idx = if idx < 0. { 0 } else { idx as usize };
+                if idx >= len {
+                    return None;
+                }
+                let val = self.stack[len - 1 - idx];
+                self.stack.push(val);
+            },
+            // roll
+            30 => {
+                let (shift, count) = (self.pop()? as i32, self.pop()? as usize);
+                let len = self.stack.len();
+                if count > len {
+                    return None;
+                }
+                if count > 0 {
+                    let shift = ((shift % count as i32) + count as i32) as usize % count;
+                    self.stack[len - count..].rotate_right(shift);
+                }
+            },
+            // dotsection and reserved operators
+            _ => self.stack.clear(),
+        }
+        Some(())
+    }
+}
+
+/// The line and curve operators, which take their arguments from the
+/// bottom of the stack
+pub(crate) fn line_and_curve_operator(path: &mut Path, op: u8, args: &[f32]) {
+    let len = args.len();
+    match op {
+        // rlineto
+        5 => for pair in args.chunks(2).filter(|pair| pair.len() == 2) {
+            path.line_by(pair[0], pair[1]);
+        },
+        // hlineto, vlineto
+        6 | 7 => for (idx, &delta) in args.iter().enumerate() {
+            if (idx % 2 == 0) == (op == 6) {
+                path.line_by(delta, 0.);
+            } else {
+                path.line_by(0., delta);
+            }
+        },
+        // rrcurveto
+        8 => for curve in args.chunks(6).filter(|curve| curve.len() == 6) {
+            path.curve_by(curve[0], curve[1], curve[2], curve[3], curve[4], curve[5]);
+        },
+        // rcurveline
+        24 if len >= 8 => {
+            for curve in args[..len - 2].chunks(6).filter(|curve| curve.len() == 6) {
+                path.curve_by(curve[0], curve[1], curve[2], curve[3], curve[4], curve[5]);
+            }
+            path.line_by(args[len - 2], args[len - 1]);
+        },
+        // rlinecurve
+        25 if len >= 8 => {
+            for pair in args[..len - 6].chunks(2).filter(|pair| pair.len() == 2) {
+                path.line_by(pair[0], pair[1]);
+            }
+            let curve = &args[len - 6..];
+            path.curve_by(curve[0], curve[1], curve[2], curve[3], curve[4], curve[5]);
+        },
+        // vvcurveto
+        26 => {
+            let (dx1, args) = if len % 2 == 1 { (args[0], &args[1..]) } else { (0., args) };
+            for (idx, curve) in args.chunks(4).filter(|curve| curve.len() == 4).enumerate() {
+                let dx1 = if idx == 0 { dx1 } else { 0. };
+                path.curve_by(dx1, curve[0], curve[1], curve[2], 0., curve[3]);
+            }
+        },
+        // hhcurveto
+        27 => {
+            let (dy1, args) = if len % 2 == 1 { (args[0], &args[1..]) } else { (0., args) };
+            for (idx, curve) in args.chunks(4).filter(|curve| curve.len() == 4).enumerate() {
+                let dy1 = if idx == 0 { dy1 } else { 0. };
+                path.curve_by(curve[0], dy1, curve[1], curve[2], curve[3], 0.);
+            }
+        },
+        // vhcurveto, hvcurveto. Curves alternate between starting
+        // horizontal and vertical, the last may end off the axis.
+        30 | 31 => {
+            let mut horizontal = op == 31;
+            let mut idx = 0;
+            while idx + 4 <= len {
+                let last = if len - idx == 5 { args[idx + 4] } else { 0. };
+                let curve = &args[idx..idx + 4];
+                if horizontal {
+                    path.curve_by(curve[0], 0., curve[1], curve[2], last, curve[3]);
+                } else {
+                    path.curve_by(0., curve[0], curve[1], curve[2], curve[3], last);
+                }
+                horizontal = !horizontal;
+                idx += 4;
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Flex curves, drawn as two plain curves
+pub(crate) fn flex_operator(path: &mut Path, op: u8, args: &[f32]) {
+    let arg = |idx: usize| args.get(idx).cloned().unwrap_or(0.);
+    match op {
+        // hflex
+        34 => {
+            path.curve_by(arg(0), 0., arg(1), arg(2), arg(3), 0.);
+            path.curve_by(arg(4), 0., arg(5), -arg(2), arg(6), 0.);
+        },
+        // flex
+        35 => {
+            path.curve_by(arg(0), arg(1), arg(2), arg(3), arg(4), arg(5));
+            path.curve_by(arg(6), arg(7), arg(8), arg(9), arg(10), arg(11));
+        },
+        // hflex1
+        36 => {
+            path.curve_by(arg(0), arg(1), arg(2), arg(3), arg(4), 0.);
+            path.curve_by(arg(5), 0., arg(6), arg(7), arg(8), -(arg(1) + arg(3) + arg(7)));
+        },
+        // flex1. The last point is on the axis the flex moves further along.
+        37 => {
+            let dx: f32 = (0..5).map(|idx| arg(idx * 2)).sum();
+            let dy: f32 = (0..5).map(|idx| arg(idx * 2 + 1)).sum();
+            path.curve_by(arg(0), arg(1), arg(2), arg(3), arg(4), arg(5));
+            if dx.abs() > dy.abs() {
+                path.curve_by(arg(6), arg(7), arg(8), arg(9), arg(10), -dy);
+            } else {
+                path.curve_by(arg(6), arg(7), arg(8), arg(9), -dx, arg(10));
+            }
+        },
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// A charstring number
+    pub fn num(val: i16) -> Vec<u8> {
+        vec![28, (val >> 8) as u8, val as u8]
+    }
+
+    /// Charstring bytes from numbers and operators
+    pub fn charstring(parts: &[&[i16]], ops: &[&[u8]]) -> Vec<u8> {
+        let mut code = Vec::new();
+        for (nums, op) in parts.iter().zip(ops.iter()) {
+            for &val in nums.iter() {
+                code.extend(num(val));
+            }
+            code.extend_from_slice(op);
+        }
+        code
+    }
+
+    pub fn build_index(items: &[Vec<u8>]) -> Vec<u8> {
+        build_index_sized(items, 2)
+    }
+
+    /// An INDEX with a `count_size` byte count
+    pub fn build_index_sized(items: &[Vec<u8>], count_size: usize) -> Vec<u8> {
+        let mut index = Vec::new();
+        if count_size == 4 {
+            push_u32(&mut index, items.len() as u32);
+        } else {
+            push_u16(&mut index, items.len() as u16);
+        }
+        if items.is_empty() {
+            return index;
+        }
+        index.push(4);
+        let mut offset = 1u32;
+        for item in items.iter().map(Some).chain(Some(None)) {
+            index.extend_from_slice(&[(offset >> 24) as u8, (offset >> 16) as u8, (offset >> 8) as u8, offset as u8]);
+            offset += item.map_or(0, |item| item.len() as u32);
+        }
+        for item in items {
+            index.extend_from_slice(item);
+        }
+        index
+    }
+
+    /// A 5 byte DICT integer, so offsets can be written before they are
+    /// known
+    pub fn dict_int(val: u32) -> Vec<u8> {
+        vec![29, (val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]
+    }
+
+    /// A name-keyed CFF with the ISOAdobe charset
+    pub fn build_cff(char_strings: &[Vec<u8>], global_subrs: &[Vec<u8>], local_subrs: &[Vec<u8>]) -> Vec<u8> {
+        let names = build_index(&[b"Test".to_vec()]);
+        let strings = build_index(&[]);
+        let global_subrs = build_index(global_subrs);
+        let char_strings = build_index(char_strings);
+        let local_subrs = build_index(local_subrs);
+
+        // CharStrings, Private (size 6: one operand and operator), then
+        // Subrs right after the Private DICT
+        let top_dict_size = 6 + 11;
+        let top_dicts_size = 2 + 1 + 8 + top_dict_size;
+        let char_strings_offset = 4 + names.len() + top_dicts_size + strings.len() + global_subrs.len();
+        let private_offset = char_strings_offset + char_strings.len();
+        let mut top_dict = dict_int(char_strings_offset as u32);
+        top_dict.push(CHAR_STRINGS as u8);
+        top_dict.extend(dict_int(6));
+        top_dict.extend(dict_int(private_offset as u32));
+        top_dict.push(PRIVATE as u8);
+        let mut private = dict_int(6);
+        private.push(SUBRS as u8);
+
+        let mut cff = vec![1, 0, 4, 4];
+        cff.extend(names);
+        cff.extend(build_index(&[top_dict]));
+        cff.extend(strings);
+        cff.extend(global_subrs);
+        cff.extend(char_strings);
+        cff.extend(private);
+        cff.extend(local_subrs);
+        cff
+    }
+
+    #[test]
+    fn index_and_dict() {
+        let index = build_index(&[vec![1, 2], vec![], vec![3]]);
+        let (index, rest) = Index::parse(&index, 2).unwrap();
+        assert_eq!(rest.len(), 0);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.get(0), Some(&[1, 2][..]));
+        assert_eq!(index.get(1), Some(&[][..]));
+        assert_eq!(index.get(2), Some(&[3][..]));
+        assert_eq!(index.get(3), None);
+
+        // 100 -2.25 BlueValues, -1000 12 7 FontMatrix
+        let dict = [239, 0x1E, 0xE2, 0xA2, 0x5F, 6, 0xFE, 0x7C, 12, 7];
+        let dict = parse_dict(&dict);
+        assert_eq!(dict, vec![(6, vec![100., -2.25]), (1207, vec![-1000.])]);
+    }
+
+    #[test]
+    fn charstring_outlines() {
+        // A width, then a 100 by 200 box
+        let square = charstring(
+            &[&[50, 10, 20], &[100, 200, -100], &[]],
+            &[&[21], &[6], &[14]],
+        );
+        // The same box drawn by a local subroutine, and a curve from a
+        // global one
+        let subr_box = charstring(&[&[10, 20], &[-107], &[]], &[&[21], &[10], &[14]]);
+        let local = charstring(&[&[100, 200, -100]], &[&[6, 11]]);
+        let curve = charstring(&[&[0, 0], &[-107], &[]], &[&[21], &[29], &[14]]);
+        let global = charstring(&[&[10, 0, 20, 30, 0, 40]], &[&[8, 11]]);
+        let cff = build_cff(&[square, subr_box, curve], &[global], &[local]);
+        let cff = Cff::parse(&cff).1;
+
+        assert_eq!(cff.font_name(), Some("Test".to_string()));
+        assert_eq!(cff.num_glyphs(), 3);
+        assert!(!cff.is_cid_keyed());
+        assert_eq!(cff.charset_id(2), Some(2));
+
+        let point = |x, y| Point { x, y };
+        let expected = vec![
+            DrawCommand::Line(point(10., 20.), point(110., 20.)),
+            DrawCommand::Line(point(110., 20.), point(110., 220.)),
+            DrawCommand::Line(point(110., 220.), point(10., 220.)),
+            DrawCommand::Line(point(10., 220.), point(10., 20.)),
+        ];
+        assert_eq!(cff.glyph_outline(0).unwrap(), expected);
+        assert_eq!(cff.glyph_outline(1).unwrap(), expected);
+        assert_eq!(cff.glyph_outline(2).unwrap(), vec![
+            DrawCommand::CubicCurve(point(0., 0.), point(10., 0.), point(30., 30.), point(30., 70.)),
+            DrawCommand::Line(point(30., 70.), point(0., 0.)),
+        ]);
+    }
+
+    #[test]
+    fn hint_masks_and_arithmetic() {
+        // Two stems and a hintmask byte, then 3 * 4 and 10 - 4 with the
+        // transient array and stack operators
+        let mut code = charstring(&[&[0, 10, 50, 10]], &[&[1]]);
+        code.extend_from_slice(&[19, 0xC0]);
+        code.extend(charstring(
+            &[&[3, 4], &[0], &[10, 4], &[0], &[]],
+            &[&[12, 24], &[12, 20], &[12, 11], &[12, 21, 12, 28], &[21]],
+        ));
+        code.extend(charstring(&[&[5]], &[&[7, 14]]));
+        let cff = build_cff(&[code], &[], &[]);
+        let cff = Cff::parse(&cff).1;
+
+        let point = |x, y| Point { x, y };
+        assert_eq!(cff.glyph_outline(0).unwrap(), vec![
+            DrawCommand::Line(point(12., 6.), point(12., 11.)),
+            DrawCommand::Line(point(12., 11.), point(12., 6.)),
+        ]);
+    }
+
+    #[test]
+    fn accented_glyph() {
+        // Glyph 34 is 'A' (SID 34) and 40 is 'G', in ISOAdobe
+        let mut char_strings = vec![charstring(&[&[]], &[&[14]]); 41];
+        char_strings[34] = charstring(&[&[0, 0], &[100], &[]], &[&[21], &[6], &[14]]);
+        char_strings[40] = charstring(&[&[5, 5], &[10], &[]], &[&[21], &[7], &[14]]);
+        char_strings[1] = charstring(&[&[500, 200, 300, b'A' as i16, b'G' as i16]], &[&[14]]);
+        let cff = build_cff(&char_strings, &[], &[]);
+        let cff = Cff::parse(&cff).1;
+
+        let point = |x, y| Point { x, y };
+        assert_eq!(cff.glyph_outline(1).unwrap(), vec![
+            DrawCommand::Line(point(0., 0.), point(100., 0.)),
+            DrawCommand::Line(point(100., 0.), point(0., 0.)),
+            DrawCommand::Line(point(205., 305.), point(205., 315.)),
+            DrawCommand::Line(point(205., 315.), point(205., 305.)),
+        ]);
+        assert_eq!(standard_encoding_sid(b' '), Some(1));
+        assert_eq!(standard_encoding_sid(161), Some(96));
+        assert_eq!(standard_encoding_sid(251), Some(149));
+        assert_eq!(standard_encoding_sid(128), None);
+    }
+
+    #[test]
+    fn render_postscript_font() {
+        use font::{Font, GetTable};
+        use instancer::write_font;
+        use tables::maxp::MaxP;
+        use test_utils::font_buf;
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let maxp: MaxP = font.get_table().unwrap();
+        let glyph_id = font.get_glyph_id('I').unwrap();
+
+        // Swap the TrueType outlines for a CFF with a box for 'I'
+        let mut char_strings = vec![charstring(&[&[]], &[&[14]]); maxp.num_glyphs as usize];
+        char_strings[glyph_id as usize] = charstring(
+            &[&[100, 0], &[400, 1000, -400], &[]],
+            &[&[21], &[6], &[14]],
+        );
+        let mut tables: Vec<([u8; 4], Vec<u8>)> = font.tables().into_iter()
+            .filter(|&(tag, _)| &tag != b"glyf" && &tag != b"loca")
+            .map(|(tag, data)| (tag, data.to_vec()))
+            .collect();
+        tables.push((*b"CFF ", build_cff(&char_strings, &[], &[])));
+        let buf = write_font(&tables);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let glyph = font.get_glyph('I').unwrap();
+        assert_eq!((glyph.header.x_min, glyph.header.y_min, glyph.header.x_max, glyph.header.y_max), (100, 0, 500, 1000));
+        let image = font.render_glyph(glyph, 20, [0., 0.]);
+        assert!(image.width() > 0 && image.height() > 0);
+        assert!(image.pixels().any(|pixel| pixel.data[0] > 0));
+        assert!(font.get_glyph(' ').is_none());
+    }
+}
@@ -0,0 +1,235 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::cff::{self, dict_value, parse_dict, Blend, Index, Interpreter};
+use tables::variation_store::ItemVariationStore;
+use render::DrawCommand;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/cff2
+
+/// PostScript outlines for variable fonts
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Cff2<'a> {
+    table: BufView<'a, u8>,
+    major_version: u8,
+    minor_version: u8,
+    header_size: u8,
+    top_dict_length: u16,
+}
+
+impl<'a> PrimaryTable for Cff2<'a> {
+    fn tag() -> TableTag {
+        TableTag::CompactFontFormat2
+    }
+}
+
+// Top DICT operators
+const VSTORE: u16 = 24;
+// Private DICT operators
+const VSINDEX: u16 = 22;
+
+/// The Top DICT values used to find glyphs. Offsets are from the start of
+/// the table.
+struct TopDict {
+    char_strings: usize,
+    fd_array: usize,
+    fd_select: Option<usize>,
+    variation_store: Option<usize>,
+}
+
+impl<'a> Cff2<'a> {
+    fn top_dict(&self) -> Option<TopDict> {
+        let start = self.header_size as usize;
+        let dict = parse_dict(self.table.0.get(start..start + self.top_dict_length as usize)?);
+        let offset = |op| dict_value(&dict, op).and_then(|vals| vals.first().map(|&val| val as usize));
+        Some(TopDict {
+            char_strings: offset(cff::CHAR_STRINGS)?,
+            fd_array: offset(cff::FD_ARRAY)?,
+            fd_select: offset(cff::FD_SELECT),
+            variation_store: offset(VSTORE),
+        })
+    }
+
+    /// The global subroutines follow the Top DICT
+    fn global_subrs(&self) -> Option<Index<'a>> {
+        let start = self.header_size as usize + self.top_dict_length as usize;
+        Index::parse(self.table.0.get(start..)?, 4).map(|(index, _)| index)
+    }
+
+    fn char_strings(&self) -> Option<Index<'a>> {
+        let top = self.top_dict()?;
+        Index::parse(self.table.0.get(top.char_strings..)?, 4).map(|(index, _)| index)
+    }
+
+    pub fn num_glyphs(&self) -> usize {
+        self.char_strings().map_or(0, |char_strings| char_strings.len())
+    }
+
+    /// The deltas used by `blend`
+    pub fn variation_store(&self) -> Option<ItemVariationStore<'a>> {
+        let offset = self.top_dict()?.variation_store?;
+        // Preceded by its length
+        let store = self.table.0.get(offset + 2..)?;
+        Some(ItemVariationStore::parse(store).1)
+    }
+
+    /// Which Font DICT the glyph uses
+    pub fn font_dict_index(&self, glyph_id: u32) -> Option<u8> {
+        match self.top_dict()?.fd_select {
+            Some(offset) => cff::fd_select(self.table.at_offset(offset), glyph_id),
+            // Only required with more than one Font DICT
+            None => Some(0),
+        }
+    }
+
+    /// The local subroutines and default `vsindex` of the glyph's Private
+    /// DICT
+    fn private_dict(&self, glyph_id: u32) -> Option<(Option<Index<'a>>, u16)> {
+        let top = self.top_dict()?;
+        let fd_array = Index::parse(self.table.0.get(top.fd_array..)?, 4)?.0;
+        let font_dict = parse_dict(fd_array.get(self.font_dict_index(glyph_id)? as usize)?);
+        let private = dict_value(&font_dict, cff::PRIVATE)?;
+        if private.len() != 2 {
+            return None;
+        }
+        let (size, offset) = (private[0] as usize, private[1] as usize);
+        let private = parse_dict(self.table.0.get(offset..offset + size)?);
+
+        let vsindex = dict_value(&private, VSINDEX)
+            .and_then(|vals| vals.first().cloned())
+            .unwrap_or(0.) as u16;
+        // Relative to the Private DICT
+        let subrs = dict_value(&private, cff::SUBRS)
+            .and_then(|vals| vals.first().cloned())
+            .and_then(|subrs| self.table.0.get(offset + subrs as usize..))
+            .and_then(|buf| Index::parse(buf, 4))
+            .map(|(index, _)| index);
+        Some((subrs, vsindex))
+    }
+
+    /// The glyph's outline, in font units, at the normalized variation
+    /// `coords`. Empty `coords` is the default instance.
+    pub fn glyph_outline(&self, glyph_id: u32, coords: &[f32]) -> Option<Vec<DrawCommand>> {
+        let code = self.char_strings()?.get(glyph_id as usize)?;
+        let (local_subrs, vsindex) = self.private_dict(glyph_id)?;
+        let blend = Blend::new(self.variation_store(), coords, vsindex);
+        let mut interpreter = Interpreter::with_blend(self.global_subrs()?, local_subrs, blend);
+        interpreter.run(code)?;
+        Some(interpreter.path.commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::Point;
+    use tables::cff::tests::{build_index_sized, charstring, dict_int};
+    use tables::variation_store::tests::build_store;
+
+    /// A CFF2 with one Font DICT, local subroutines, and a variation store
+    /// over one axis with an item per entry of `deltas`
+    fn build_cff2(char_strings: &[Vec<u8>], local_subrs: &[Vec<u8>], deltas: &[(i16, i16)]) -> Vec<u8> {
+        let global_subrs = build_index_sized(&[], 4);
+        let store = build_store(deltas);
+        let char_strings = build_index_sized(char_strings, 4);
+        let local_subrs = build_index_sized(local_subrs, 4);
+
+        // CharStrings, vstore and FDArray
+        let top_dict_size = 6 + 6 + 7;
+        let store_offset = 5 + top_dict_size + global_subrs.len();
+        let char_strings_offset = store_offset + 2 + store.len();
+        let fd_array_offset = char_strings_offset + char_strings.len();
+        // One Font DICT with the Private DICT's size and offset
+        let fd_array_size = 4 + 1 + 8 + 11;
+        let private_offset = fd_array_offset + fd_array_size;
+
+        let mut top_dict = dict_int(char_strings_offset as u32);
+        top_dict.push(cff::CHAR_STRINGS as u8);
+        top_dict.extend(dict_int(store_offset as u32));
+        top_dict.push(VSTORE as u8);
+        top_dict.extend(dict_int(fd_array_offset as u32));
+        top_dict.extend_from_slice(&[12, 36]);
+        let mut font_dict = dict_int(6);
+        font_dict.extend(dict_int(private_offset as u32));
+        font_dict.push(cff::PRIVATE as u8);
+        // Subrs right after the Private DICT
+        let mut private = dict_int(6);
+        private.push(cff::SUBRS as u8);
+
+        let mut cff2 = vec![2, 0, 5, 0, top_dict_size as u8];
+        cff2.extend(top_dict);
+        cff2.extend(global_subrs);
+        cff2.extend_from_slice(&[(store.len() >> 8) as u8, store.len() as u8]);
+        cff2.extend(store);
+        cff2.extend(char_strings);
+        cff2.extend(build_index_sized(&[font_dict], 4));
+        cff2.extend(private);
+        cff2.extend(local_subrs);
+        cff2
+    }
+
+    /// A triangle whose width blends between 80 and 120, drawn by a
+    /// subroutine
+    fn blended_triangle() -> Vec<u8> {
+        let code = charstring(&[&[0, 0], &[-107]], &[&[21], &[10]]);
+        let subr = charstring(&[&[100, 20, -20, 1], &[200]], &[&[16], &[6]]);
+        build_cff2(&[code], &[subr], &[(0, 0)])
+    }
+
+    #[test]
+    fn blended_outlines() {
+        let cff2 = blended_triangle();
+        let cff2 = Cff2::parse(&cff2).1;
+        assert_eq!(cff2.num_glyphs(), 1);
+        assert_eq!(cff2.font_dict_index(0), Some(0));
+
+        let width = |coords: &[f32]| match cff2.glyph_outline(0, coords).unwrap()[0] {
+            DrawCommand::Line(start, end) => {
+                assert_eq!(start, Point { x: 0., y: 0. });
+                end.x
+            },
+            _ => panic!("Should be a line"),
+        };
+        assert_eq!(width(&[]), 100.);
+        assert_eq!(width(&[1.]), 120.);
+        assert_eq!(width(&[0.5]), 110.);
+        assert_eq!(width(&[-1.]), 80.);
+        assert_eq!(cff2.glyph_outline(0, &[]).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn render_variable_postscript_font() {
+        use font::{Font, GetTable};
+        use instancer::write_font;
+        use tables::fvar::Variation;
+        use tables::fvar::tests::build_fvar;
+        use tables::maxp::MaxP;
+        use test_utils::font_buf;
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let maxp: MaxP = font.get_table().unwrap();
+        let glyph_id = font.get_glyph_id('I').unwrap() as usize;
+
+        // Swap the TrueType outlines for a CFF2 where 'I' gets wider with
+        // weight
+        let mut char_strings = vec![Vec::new(); maxp.num_glyphs as usize];
+        char_strings[glyph_id] = charstring(&[&[0, 0], &[-107]], &[&[21], &[10]]);
+        let subr = charstring(&[&[100, 20, -20, 1], &[200]], &[&[16], &[6]]);
+        let mut tables: Vec<([u8; 4], Vec<u8>)> = font.tables().into_iter()
+            .filter(|&(tag, _)| &tag != b"glyf" && &tag != b"loca")
+            .map(|(tag, data)| (tag, data.to_vec()))
+            .collect();
+        tables.push((*b"CFF2", build_cff2(&char_strings, &[subr], &[(0, 0)])));
+        tables.push((*b"fvar", build_fvar()));
+        let buf = write_font(&tables);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let x_max = |font: &Font| font.get_glyph('I').unwrap().header.x_max;
+        assert_eq!(x_max(&font), 100);
+        let bold = font.with_variations(&[Variation::new(b"wght", 900.)]).unwrap();
+        assert_eq!(x_max(&bold), 120);
+        let image = bold.render_glyph(bold.get_glyph('I').unwrap(), 20, [0., 0.]);
+        assert!(image.pixels().any(|pixel| pixel.data[0] > 0));
+    }
+}
@@ -0,0 +1,798 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::variation_store::{DeltaSetIndexMap, ItemVariationStore};
+use math::{Affine, Point};
+use std::f32::consts::PI;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/colr
+
+/// Palette index that means the text's foreground color
+pub const FOREGROUND_PALETTE_INDEX: u16 = 0xFFFF;
+/// Var index base for values that don't vary
+const NO_VARIATION_INDEX: u32 = 0xFFFF_FFFF;
+/// Paint graphs can share subgraphs, so a small table can expand to a huge
+/// tree. Cap how many paints one glyph turns into.
+const MAX_PAINTS: usize = 10_000;
+const MAX_PAINT_DEPTH: usize = 64;
+
+/// Color glyphs made of layers of other glyphs
+#[derive(Debug, Parse)]
+pub struct Colr<'a> {
+    table: BufView<'a, u8>,
+    version: u16,
+    num_base_glyph_records: u16,
+    base_glyph_records_offset: u32,
+    layer_records_offset: u32,
+    num_layer_records: u16,
+}
+
+impl<'a> PrimaryTable for Colr<'a> {
+    fn tag() -> TableTag {
+        TableTag::ColorTable
+    }
+}
+
+#[derive(Debug, Parse)]
+struct BaseGlyphRecord {
+    glyph_id: u16,
+    first_layer_index: u16,
+    num_layers: u16,
+}
+
+/// A glyph outline to fill with a palette color
+#[derive(Debug, Parse, Clone, Copy, PartialEq, Eq)]
+pub struct LayerRecord {
+    pub glyph_id: u16,
+    /// An entry in the `CPAL` palette, or `FOREGROUND_PALETTE_INDEX`
+    pub palette_index: u16,
+}
+
+impl<'a> Colr<'a> {
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// The glyph's layers, bottom first. `None` if it isn't a color glyph.
+    pub fn layers(&self, glyph_id: u16) -> Option<Vec<LayerRecord>> {
+        let record_size = BaseGlyphRecord::approx_file_size();
+        let start = self.base_glyph_records_offset as usize;
+        let records = self.table.0.get(start..start + self.num_base_glyph_records as usize * record_size)?;
+        let records: Vec<BaseGlyphRecord> = records.chunks(record_size)
+            .map(|record| BaseGlyphRecord::parse(record).1)
+            .collect();
+        let record = &records[records.binary_search_by_key(&glyph_id, |record| record.glyph_id).ok()?];
+
+        let first = record.first_layer_index as usize;
+        if first + record.num_layers as usize > self.num_layer_records as usize {
+            return None;
+        }
+        let layer_size = LayerRecord::approx_file_size();
+        let start = self.layer_records_offset as usize + first * layer_size;
+        let layers = self.table.0.get(start..start + record.num_layers as usize * layer_size)?;
+        Some(layers.chunks(layer_size).map(|layer| LayerRecord::parse(layer).1).collect())
+    }
+}
+
+/// What a color line does past its first and last stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extend {
+    Pad,
+    Repeat,
+    Reflect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorStop {
+    pub offset: f32,
+    /// An entry in the `CPAL` palette, or `FOREGROUND_PALETTE_INDEX`
+    pub palette_index: u16,
+    pub alpha: f32,
+}
+
+/// The colors of a gradient
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorLine {
+    pub extend: Extend,
+    /// Sorted by offset
+    pub stops: Vec<ColorStop>,
+}
+
+/// How `PaintComposite` combines its source with its backdrop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompositeMode {
+    Clear,
+    Source,
+    Destination,
+    SourceOver,
+    DestinationOver,
+    SourceIn,
+    DestinationIn,
+    SourceOut,
+    DestinationOut,
+    SourceAtop,
+    DestinationAtop,
+    Xor,
+    Plus,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Multiply,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl CompositeMode {
+    fn from_u8(mode: u8) -> Option<CompositeMode> {
+        use self::CompositeMode::*;
+        let modes = [
+            Clear, Source, Destination, SourceOver, DestinationOver, SourceIn,
+            DestinationIn, SourceOut, DestinationOut, SourceAtop, DestinationAtop,
+            Xor, Plus, Screen, Overlay, Darken, Lighten, ColorDodge, ColorBurn,
+            HardLight, SoftLight, Difference, Exclusion, Multiply, Hue, Saturation,
+            Color, Luminosity,
+        ];
+        modes.get(mode as usize).cloned()
+    }
+}
+
+/// A node of a `COLR` version 1 paint graph, with any variations applied.
+/// Coordinates are in font units.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Paint {
+    /// Paints from the layer list, bottom first
+    ColrLayers(Vec<Paint>),
+    Solid {
+        palette_index: u16,
+        alpha: f32,
+    },
+    /// Runs from `p0` to `p1`, skewed so the color bands are parallel to
+    /// `p0` to `p2`
+    LinearGradient {
+        color_line: ColorLine,
+        p0: Point,
+        p1: Point,
+        p2: Point,
+    },
+    /// Interpolates between two circles
+    RadialGradient {
+        color_line: ColorLine,
+        c0: Point,
+        r0: f32,
+        c1: Point,
+        r1: f32,
+    },
+    /// Angles are in degrees, counter-clockwise from the positive x axis
+    SweepGradient {
+        color_line: ColorLine,
+        center: Point,
+        start_angle: f32,
+        end_angle: f32,
+    },
+    /// `paint`, clipped to the glyph's outline
+    Glyph {
+        glyph_id: u16,
+        paint: Box<Paint>,
+    },
+    /// Another base glyph's paint graph
+    ColrGlyph(u16),
+    /// Translations, scales, rotations and skews all end up as this
+    Transform {
+        affine: Affine,
+        paint: Box<Paint>,
+    },
+    Composite {
+        source: Box<Paint>,
+        mode: CompositeMode,
+        backdrop: Box<Paint>,
+    },
+}
+
+/// How a value that variations can move is stored, which sets the units
+/// of its deltas
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    FWord,
+    UFWord,
+    F2Dot14,
+    Fixed,
+}
+
+impl Field {
+    fn delta_scale(self) -> f32 {
+        match self {
+            Field::FWord | Field::UFWord => 1.,
+            Field::F2Dot14 => 16384.,
+            Field::Fixed => 65536.,
+        }
+    }
+}
+
+/// Reads a table's fields in order
+struct Fields<'b> {
+    buf: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Fields<'b> {
+    fn new(buf: &'b [u8], offset: usize) -> Option<Fields<'b>> {
+        Some(Fields { buf: buf.get(offset..)?, pos: 0 })
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'b [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|bytes| u16::parse(bytes).1)
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.bytes(3).map(|bytes| (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|bytes| u32::parse(bytes).1)
+    }
+
+    fn value(&mut self, field: Field) -> Option<f32> {
+        Some(match field {
+            Field::FWord => self.u16()? as i16 as f32,
+            Field::UFWord => self.u16()? as f32,
+            Field::F2Dot14 => self.u16()? as i16 as f32 / 16384.,
+            Field::Fixed => self.u32()? as i32 as f32 / 65536.,
+        })
+    }
+}
+
+impl<'a> Colr<'a> {
+    /// The glyph's version 1 paint graph, varied to the normalized `coords`.
+    /// `None` if it doesn't have one.
+    pub fn base_glyph_paint(&self, glyph_id: u16, coords: &[f32]) -> Option<Paint> {
+        let list = self.v1_offset(0)?;
+        let count = Fields::new(self.table.0, list)?.u32()? as usize;
+        let record = |idx: usize| {
+            let mut fields = Fields::new(self.table.0, list + 4 + idx * 6)?;
+            Some((fields.u16()?, fields.u32()? as usize))
+        };
+
+        // Records are sorted by glyph ID
+        let (mut low, mut high) = (0, count);
+        while low < high {
+            let mid = (low + high) / 2;
+            let (record_glyph, paint_offset) = record(mid)?;
+            if record_glyph == glyph_id {
+                let mut remaining = MAX_PAINTS;
+                return self.paint(list + paint_offset, coords, 0, &mut remaining);
+            } else if record_glyph < glyph_id {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        None
+    }
+
+    /// The box the glyph's paint is clipped to, as `[x_min, y_min, x_max,
+    /// y_max]` in font units
+    pub fn clip_box(&self, glyph_id: u16, coords: &[f32]) -> Option<[f32; 4]> {
+        let list = self.v1_offset(2)?;
+        let count = Fields::new(self.table.0, list + 1)?.u32()? as usize;
+        for idx in 0..count {
+            let mut clip = Fields::new(self.table.0, list + 5 + idx * 7)?;
+            let (start, end, offset) = (clip.u16()?, clip.u16()?, clip.u24()?);
+            if start <= glyph_id && glyph_id <= end {
+                let mut clip_box = Fields::new(self.table.0, list + offset)?;
+                let variable = clip_box.u8()? == 2;
+                let bounds = self.values(&mut clip_box, &[Field::FWord; 4], variable, coords)?;
+                return Some([bounds[0], bounds[1], bounds[2], bounds[3]]);
+            }
+        }
+        None
+    }
+
+    /// The offset of one of the version 1 header's subtables: the base glyph
+    /// list, layer list, clip list, delta set index map or variation store
+    fn v1_offset(&self, which: usize) -> Option<usize> {
+        if self.version < 1 {
+            return None;
+        }
+        let offset = Fields::new(self.table.0, 14 + which * 4)?.u32()? as usize;
+        if offset == 0 || offset >= self.table.0.len() {
+            None
+        } else {
+            Some(offset)
+        }
+    }
+
+    /// Read `fields`, then if they're `variable`, the var index base for
+    /// their deltas
+    fn values(&self, reader: &mut Fields, fields: &[Field], variable: bool, coords: &[f32]) -> Option<Vec<f32>> {
+        let mut values = fields.iter()
+            .map(|&field| reader.value(field))
+            .collect::<Option<Vec<f32>>>()?;
+        if variable {
+            let var_index_base = reader.u32()?;
+            for (idx, (value, &field)) in values.iter_mut().zip(fields).enumerate() {
+                *value += self.delta(var_index_base, idx as u32, coords) / field.delta_scale();
+            }
+        }
+        Some(values)
+    }
+
+    /// The raw delta for the value `field` places after `var_index_base`
+    fn delta(&self, var_index_base: u32, field: u32, coords: &[f32]) -> f32 {
+        if var_index_base == NO_VARIATION_INDEX || coords.is_empty() {
+            return 0.;
+        }
+        let store: ItemVariationStore = match self.v1_offset(4) {
+            Some(offset) => self.table.at_offset(offset),
+            None => return 0.,
+        };
+        let idx = var_index_base.wrapping_add(field);
+        let (outer, inner) = match self.v1_offset(3) {
+            Some(offset) => self.table.at_offset::<DeltaSetIndexMap>(offset).index(idx),
+            None => ((idx >> 16) as u16, idx as u16),
+        };
+        store.delta(outer, inner, coords)
+    }
+
+    fn paint(&self, offset: usize, coords: &[f32], depth: usize, remaining: &mut usize) -> Option<Paint> {
+        use self::Field::*;
+
+        if depth > MAX_PAINT_DEPTH || *remaining == 0 {
+            return None;
+        }
+        *remaining -= 1;
+
+        let mut fields = Fields::new(self.table.0, offset)?;
+        let format = fields.u8()?;
+        // Most paints come in pairs, with the odd one being variable
+        let variable = format % 2 == 1 && format >= 3 && format != 11;
+        let child = |fields: &mut Fields| fields.u24().map(|child| offset + child);
+
+        Some(match format {
+            1 => {
+                let num_layers = fields.u8()? as usize;
+                let first_layer = fields.u32()? as usize;
+                let list = self.v1_offset(1)?;
+                let layers = (first_layer..first_layer + num_layers)
+                    .filter_map(|idx| {
+                        let layer_offset = Fields::new(self.table.0, list + 4 + idx * 4)?.u32()? as usize;
+                        self.paint(list + layer_offset, coords, depth + 1, remaining)
+                    })
+                    .collect();
+                Paint::ColrLayers(layers)
+            },
+            2 | 3 => {
+                let palette_index = fields.u16()?;
+                let alpha = self.values(&mut fields, &[F2Dot14], variable, coords)?[0];
+                Paint::Solid { palette_index, alpha }
+            },
+            4 | 5 => {
+                let color_line = self.color_line(child(&mut fields)?, variable, coords)?;
+                let v = self.values(&mut fields, &[FWord; 6], variable, coords)?;
+                Paint::LinearGradient {
+                    color_line,
+                    p0: Point { x: v[0], y: v[1] },
+                    p1: Point { x: v[2], y: v[3] },
+                    p2: Point { x: v[4], y: v[5] },
+                }
+            },
+            6 | 7 => {
+                let color_line = self.color_line(child(&mut fields)?, variable, coords)?;
+                let v = self.values(&mut fields, &[FWord, FWord, UFWord, FWord, FWord, UFWord], variable, coords)?;
+                Paint::RadialGradient {
+                    color_line,
+                    c0: Point { x: v[0], y: v[1] },
+                    r0: v[2],
+                    c1: Point { x: v[3], y: v[4] },
+                    r1: v[5],
+                }
+            },
+            8 | 9 => {
+                let color_line = self.color_line(child(&mut fields)?, variable, coords)?;
+                let v = self.values(&mut fields, &[FWord, FWord, F2Dot14, F2Dot14], variable, coords)?;
+                // Angles are stored in half turns
+                Paint::SweepGradient {
+                    color_line,
+                    center: Point { x: v[0], y: v[1] },
+                    start_angle: v[2] * 180.,
+                    end_angle: v[3] * 180.,
+                }
+            },
+            10 => {
+                let paint = child(&mut fields)?;
+                let glyph_id = fields.u16()?;
+                Paint::Glyph {
+                    glyph_id,
+                    paint: Box::new(self.paint(paint, coords, depth + 1, remaining)?),
+                }
+            },
+            11 => Paint::ColrGlyph(fields.u16()?),
+            12 | 13 => {
+                let paint = child(&mut fields)?;
+                let mut transform = Fields::new(self.table.0, child(&mut fields)?)?;
+                let v = self.values(&mut transform, &[Fixed; 6], variable, coords)?;
+                Paint::Transform {
+                    affine: Affine {
+                        square: [[v[0], v[2]], [v[1], v[3]]],
+                        translation: [v[4], v[5]],
+                    },
+                    paint: Box::new(self.paint(paint, coords, depth + 1, remaining)?),
+                }
+            },
+            14 ..= 31 => {
+                let paint = child(&mut fields)?;
+                let kinds: &[Field] = match format {
+                    14 | 15 => &[FWord, FWord],
+                    16 | 17 | 28 | 29 => &[F2Dot14, F2Dot14],
+                    18 | 19 | 30 | 31 => &[F2Dot14, F2Dot14, FWord, FWord],
+                    20 | 21 | 24 | 25 => &[F2Dot14],
+                    _ => &[F2Dot14, FWord, FWord],
+                };
+                let v = self.values(&mut fields, kinds, variable, coords)?;
+                let affine = match format {
+                    14 | 15 => Affine::translation(v[0], v[1]),
+                    16 ..= 19 => Affine::scale(v[0], v[1]),
+                    20 ..= 23 => Affine::scale(v[0], v[0]),
+                    24 ..= 27 => Affine::rotation(v[0] * PI),
+                    _ => Affine::shear(-(v[0] * PI).tan(), (v[1] * PI).tan()),
+                };
+                // Each kind but translation also comes around a center
+                let affine = match format {
+                    18 | 19 | 22 | 23 | 26 | 27 | 30 | 31 => {
+                        let (x, y) = (v[v.len() - 2], v[v.len() - 1]);
+                        Affine::translation(x, y) * affine * Affine::translation(-x, -y)
+                    },
+                    _ => affine,
+                };
+                Paint::Transform {
+                    affine,
+                    paint: Box::new(self.paint(paint, coords, depth + 1, remaining)?),
+                }
+            },
+            32 => {
+                let source = child(&mut fields)?;
+                let mode = CompositeMode::from_u8(fields.u8()?)?;
+                let backdrop = child(&mut fields)?;
+                Paint::Composite {
+                    source: Box::new(self.paint(source, coords, depth + 1, remaining)?),
+                    mode,
+                    backdrop: Box::new(self.paint(backdrop, coords, depth + 1, remaining)?),
+                }
+            },
+            _ => return None,
+        })
+    }
+
+    fn color_line(&self, offset: usize, variable: bool, coords: &[f32]) -> Option<ColorLine> {
+        let mut fields = Fields::new(self.table.0, offset)?;
+        let extend = match fields.u8()? {
+            1 => Extend::Repeat,
+            2 => Extend::Reflect,
+            _ => Extend::Pad,
+        };
+        let num_stops = fields.u16()?;
+        let mut stops = (0..num_stops)
+            .map(|_| {
+                let mut offset = fields.value(Field::F2Dot14)?;
+                let palette_index = fields.u16()?;
+                let mut alpha = fields.value(Field::F2Dot14)?;
+                if variable {
+                    let var_index_base = fields.u32()?;
+                    offset += self.delta(var_index_base, 0, coords) / Field::F2Dot14.delta_scale();
+                    alpha += self.delta(var_index_base, 1, coords) / Field::F2Dot14.delta_scale();
+                }
+                Some(ColorStop { offset, palette_index, alpha })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        stops.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap_or(::std::cmp::Ordering::Equal));
+        Some(ColorLine { extend, stops })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+    use tables::variation_store::tests::build_store;
+
+    pub fn push_u24(buf: &mut Vec<u8>, val: usize) {
+        buf.extend_from_slice(&[(val >> 16) as u8, (val >> 8) as u8, val as u8]);
+    }
+
+    pub fn f2dot14(val: f32) -> u16 {
+        (val * 16384.).round() as i16 as u16
+    }
+
+    /// A paint whose first field is the offset to `child`, followed by
+    /// `fields`. Most paints, and gradients with their color lines, look
+    /// like this.
+    pub fn paint_with_child(format: u8, fields: &[u16], child: Vec<u8>) -> Vec<u8> {
+        let mut paint = vec![format];
+        push_u24(&mut paint, 4 + fields.len() * 2);
+        for &field in fields {
+            push_u16(&mut paint, field);
+        }
+        paint.extend(child);
+        paint
+    }
+
+    pub fn paint_solid(palette_index: u16, alpha: f32) -> Vec<u8> {
+        let mut paint = vec![2];
+        push_u16(&mut paint, palette_index);
+        push_u16(&mut paint, f2dot14(alpha));
+        paint
+    }
+
+    pub fn paint_glyph(glyph_id: u16, child: Vec<u8>) -> Vec<u8> {
+        paint_with_child(10, &[glyph_id], child)
+    }
+
+    pub fn paint_composite(source: Vec<u8>, mode: u8, backdrop: Vec<u8>) -> Vec<u8> {
+        let mut paint = vec![32];
+        push_u24(&mut paint, 8);
+        paint.push(mode);
+        push_u24(&mut paint, 8 + source.len());
+        paint.extend(source);
+        paint.extend(backdrop);
+        paint
+    }
+
+    /// Stops are `(offset, palette_index, alpha)`. Variable color lines
+    /// have a `var_index_base`.
+    pub fn color_line(extend: u8, stops: &[(f32, u16, f32)], var_index_base: Option<u32>) -> Vec<u8> {
+        let mut line = vec![extend];
+        push_u16(&mut line, stops.len() as u16);
+        for &(offset, palette_index, alpha) in stops {
+            push_u16(&mut line, f2dot14(offset));
+            push_u16(&mut line, palette_index);
+            push_u16(&mut line, f2dot14(alpha));
+            if let Some(var_index_base) = var_index_base {
+                push_u32(&mut line, var_index_base);
+            }
+        }
+        line
+    }
+
+    /// A version 1 `COLR` with a paint per base glyph, a layer list, clip
+    /// boxes `(first_glyph, last_glyph, [x_min, y_min, x_max, y_max])`, and
+    /// an item variation store with `deltas` (see `build_store`)
+    pub fn build_colr_v1(base_glyphs: &[(u16, Vec<u8>)], layers: &[Vec<u8>], clips: &[(u16, u16, [i16; 4])],
+                         deltas: &[(i16, i16)]) -> Vec<u8> {
+        let mut base_list = Vec::new();
+        push_u32(&mut base_list, base_glyphs.len() as u32);
+        let mut offset = 4 + base_glyphs.len() * 6;
+        for &(glyph_id, ref paint) in base_glyphs {
+            push_u16(&mut base_list, glyph_id);
+            push_u32(&mut base_list, offset as u32);
+            offset += paint.len();
+        }
+        for &(_, ref paint) in base_glyphs {
+            base_list.extend_from_slice(paint);
+        }
+
+        let mut layer_list = Vec::new();
+        push_u32(&mut layer_list, layers.len() as u32);
+        let mut offset = 4 + layers.len() * 4;
+        for paint in layers {
+            push_u32(&mut layer_list, offset as u32);
+            offset += paint.len();
+        }
+        for paint in layers {
+            layer_list.extend_from_slice(paint);
+        }
+
+        let mut clip_list = vec![1];
+        push_u32(&mut clip_list, clips.len() as u32);
+        for (idx, &(first, last, _)) in clips.iter().enumerate() {
+            push_u16(&mut clip_list, first);
+            push_u16(&mut clip_list, last);
+            push_u24(&mut clip_list, 5 + clips.len() * 7 + idx * 9);
+        }
+        for &(_, _, bounds) in clips {
+            clip_list.push(1);
+            for &val in &bounds {
+                push_u16(&mut clip_list, val as u16);
+            }
+        }
+
+        let store = if deltas.is_empty() { Vec::new() } else { build_store(deltas) };
+        let mut colr = Vec::new();
+        push_u16(&mut colr, 1);
+        push_u16(&mut colr, 0);
+        push_u32(&mut colr, 0);
+        push_u32(&mut colr, 0);
+        push_u16(&mut colr, 0);
+        let base_offset = 34;
+        let layer_offset = base_offset + base_list.len();
+        let clip_offset = layer_offset + layer_list.len();
+        let store_offset = clip_offset + clip_list.len();
+        push_u32(&mut colr, base_offset as u32);
+        push_u32(&mut colr, layer_offset as u32);
+        push_u32(&mut colr, clip_offset as u32);
+        push_u32(&mut colr, 0);
+        push_u32(&mut colr, if store.is_empty() { 0 } else { store_offset as u32 });
+        colr.extend(base_list);
+        colr.extend(layer_list);
+        colr.extend(clip_list);
+        colr.extend(store);
+        colr
+    }
+
+    /// A version 0 `COLR` with a base glyph per `(glyph_id, layers)`, each
+    /// layer `(glyph_id, palette_index)`
+    pub fn build_colr(base_glyphs: &[(u16, Vec<(u16, u16)>)]) -> Vec<u8> {
+        let mut colr = Vec::new();
+        let num_layers: usize = base_glyphs.iter().map(|base| base.1.len()).sum();
+        push_u16(&mut colr, 0);
+        push_u16(&mut colr, base_glyphs.len() as u16);
+        push_u32(&mut colr, 14);
+        push_u32(&mut colr, 14 + base_glyphs.len() as u32 * 6);
+        push_u16(&mut colr, num_layers as u16);
+
+        let mut first_layer = 0;
+        for &(glyph_id, ref layers) in base_glyphs {
+            push_u16(&mut colr, glyph_id);
+            push_u16(&mut colr, first_layer);
+            push_u16(&mut colr, layers.len() as u16);
+            first_layer += layers.len() as u16;
+        }
+        for &(_, ref layers) in base_glyphs {
+            for &(glyph_id, palette_index) in layers {
+                push_u16(&mut colr, glyph_id);
+                push_u16(&mut colr, palette_index);
+            }
+        }
+        colr
+    }
+
+    #[test]
+    fn layers() {
+        let colr = build_colr(&[
+            (3, vec![(10, 0), (11, 1)]),
+            (7, vec![(12, FOREGROUND_PALETTE_INDEX)]),
+        ]);
+        let colr = Colr::parse(&colr).1;
+        assert_eq!(colr.version(), 0);
+        assert_eq!(colr.layers(3), Some(vec![
+            LayerRecord { glyph_id: 10, palette_index: 0 },
+            LayerRecord { glyph_id: 11, palette_index: 1 },
+        ]));
+        assert_eq!(colr.layers(7).unwrap()[0].palette_index, FOREGROUND_PALETTE_INDEX);
+        assert_eq!(colr.layers(5), None);
+    }
+
+    #[test]
+    fn render_layers() {
+        use font::Font;
+        use image::Rgba;
+        use tables::cpal::tests::build_cpal;
+        use test_utils::{font_buf, with_tables};
+
+        let font_buf = font_buf();
+        let font = Font::from_buffer(&font_buf).unwrap();
+        let glyph = |c| font.get_glyph_id(c).unwrap() as u16;
+        let colr = build_colr(&[(glyph('A'), vec![(glyph('L'), 1), (glyph('-'), FOREGROUND_PALETTE_INDEX)])]);
+        let cpal = build_cpal(&[
+            (0, 0xFFFF, vec![[0, 255, 0, 255], [255, 0, 0, 255]]),
+            (0, 0xFFFF, vec![[0, 255, 0, 255], [0, 255, 0, 255]]),
+        ], &[]);
+        let buf = with_tables(&font_buf, &[(b"COLR", colr), (b"CPAL", cpal)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let blue = Rgba { data: [0, 0, 255, 255] };
+        let image = font.render_color_glyph(glyph('A') as u32, 32, 0, blue).unwrap();
+
+        let count = |color: [u8; 4]| image.pixels().filter(|pixel| pixel.data == color).count();
+        assert!(count([255, 0, 0, 255]) > 0);
+        // The hyphen is drawn over the stem
+        assert!(count([0, 0, 255, 255]) > 0);
+        assert!(count([0, 0, 0, 0]) > 0);
+        assert_eq!(count([0, 255, 0, 255]), 0);
+        // Top-down, so the bar of the L is at the bottom
+        let (width, height) = image.dimensions();
+        assert_eq!(image.get_pixel(width / 2, height - 2).data, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(width / 2, 1).data, [0, 0, 0, 0]);
+
+        let other = font.render_color_glyph(glyph('A') as u32, 32, 1, blue).unwrap();
+        assert!(other.pixels().any(|pixel| pixel.data == [0, 255, 0, 255]));
+        assert!(font.render_color_glyph(glyph('B') as u32, 32, 0, blue).is_none());
+        assert!(font.render_color_glyph(glyph('A') as u32, 32, 2, blue).is_none());
+    }
+
+    #[test]
+    fn paint_graph() {
+        let red_glyph = paint_glyph(5, paint_solid(0, 0.5));
+        let mut gradient_fields = vec![0, 0, 100, 0, 0, 100];
+        // The var index base, whose deltas come from the store's items
+        gradient_fields.extend_from_slice(&[0, 0]);
+        let gradient = paint_with_child(5, &gradient_fields, color_line(1, &[(1., 2, 1.), (0., 1, 1.)], Some(NO_VARIATION_INDEX)));
+        let rotated = paint_with_child(26, &[f2dot14(0.5), 10, 20], paint_glyph(6, gradient));
+        let mut layers = vec![1, 2];
+        push_u32(&mut layers, 0);
+        let colr = build_colr_v1(
+            &[
+                (3, layers),
+                (4, paint_composite(vec![11, 0, 3], 23, vec![11, 0, 3])),
+            ],
+            &[red_glyph, rotated],
+            &[(3, 4, [-10, -20, 300, 400])],
+            &[(50, 0), (-20, 0)],
+        );
+        let colr = Colr::parse(&colr).1;
+        assert_eq!(colr.version(), 1);
+        assert_eq!(colr.layers(3), None);
+        assert_eq!(colr.clip_box(4, &[]), Some([-10., -20., 300., 400.]));
+        assert_eq!(colr.clip_box(5, &[]), None);
+
+        let layers = match colr.base_glyph_paint(3, &[]) {
+            Some(Paint::ColrLayers(layers)) => layers,
+            paint => panic!("Expected layers, got {:?}", paint),
+        };
+        assert_eq!(layers[0], Paint::Glyph {
+            glyph_id: 5,
+            paint: Box::new(Paint::Solid { palette_index: 0, alpha: 0.5 }),
+        });
+        let (affine, paint) = match layers[1] {
+            Paint::Transform { affine, ref paint } => (affine, paint),
+            ref paint => panic!("Expected a transform, got {:?}", paint),
+        };
+        // A quarter turn around (10, 20)
+        let point = affine * Point { x: 11., y: 20. };
+        assert!((point.x - 10.).abs() < 1e-4 && (point.y - 21.).abs() < 1e-4);
+        let gradient = match **paint {
+            Paint::Glyph { glyph_id: 6, ref paint } => paint,
+            ref paint => panic!("Expected a glyph, got {:?}", paint),
+        };
+        match **gradient {
+            Paint::LinearGradient { ref color_line, p0, p1, .. } => {
+                assert_eq!(color_line.extend, Extend::Repeat);
+                assert_eq!(color_line.stops.iter().map(|stop| stop.palette_index).collect::<Vec<_>>(), vec![1, 2]);
+                assert_eq!((p0, p1), (Point { x: 0., y: 0. }, Point { x: 100., y: 0. }));
+            },
+            ref paint => panic!("Expected a gradient, got {:?}", paint),
+        }
+
+        // The first two fields vary
+        let varied = colr.base_glyph_paint(3, &[1.]).unwrap();
+        let varied = match varied {
+            Paint::ColrLayers(ref layers) => match layers[1] {
+                Paint::Transform { ref paint, .. } => match **paint {
+                    Paint::Glyph { ref paint, .. } => (**paint).clone(),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        match varied {
+            Paint::LinearGradient { p0, p1, .. } => {
+                assert_eq!((p0, p1), (Point { x: 50., y: -20. }, Point { x: 100., y: 0. }));
+            },
+            paint => panic!("Expected a gradient, got {:?}", paint),
+        }
+
+        assert_eq!(colr.base_glyph_paint(4, &[]), Some(Paint::Composite {
+            source: Box::new(Paint::ColrGlyph(3)),
+            mode: CompositeMode::Multiply,
+            backdrop: Box::new(Paint::ColrGlyph(3)),
+        }));
+        assert_eq!(colr.base_glyph_paint(5, &[]), None);
+    }
+}
@@ -0,0 +1,225 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::name::Name;
+use image::Rgba;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/cpal
+
+/// Name ID for "no name"
+const NO_NAME: u16 = 0xFFFF;
+
+/// Color palettes for `COLR` glyphs
+#[derive(Debug, Parse)]
+pub struct Cpal<'a> {
+    table: BufView<'a, u8>,
+    version: u16,
+    num_palette_entries: u16,
+    num_palettes: u16,
+    num_color_records: u16,
+    color_records_array_offset: u32,
+}
+
+impl<'a> PrimaryTable for Cpal<'a> {
+    fn tag() -> TableTag {
+        TableTag::ColorPalette
+    }
+}
+
+#[derive(Debug, Parse, Clone, Copy, PartialEq, Eq)]
+pub struct ColorRecord {
+    pub blue: u8,
+    pub green: u8,
+    pub red: u8,
+    pub alpha: u8,
+}
+
+impl ColorRecord {
+    pub fn to_rgba(&self) -> Rgba<u8> {
+        Rgba { data: [self.red, self.green, self.blue, self.alpha] }
+    }
+}
+
+bitflags! {
+    pub struct PaletteType: u32 {
+        const USABLE_WITH_LIGHT_BACKGROUND = 0x0001;
+        const USABLE_WITH_DARK_BACKGROUND = 0x0002;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub colors: Vec<ColorRecord>,
+    pub palette_type: PaletteType,
+    pub label_name_id: Option<u16>,
+    pub name: Option<String>,
+}
+
+/// The name of a palette entry, e.g. "outline"
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteEntryLabel {
+    pub name_id: u16,
+    pub name: Option<String>,
+}
+
+impl<'a> Cpal<'a> {
+    /// The number of colors in each palette
+    pub fn num_palette_entries(&self) -> u16 {
+        self.num_palette_entries
+    }
+
+    pub fn num_palettes(&self) -> u16 {
+        self.num_palettes
+    }
+
+    /// The colors of palette `idx`
+    pub fn colors(&self, idx: usize) -> Option<Vec<ColorRecord>> {
+        if idx >= self.num_palettes as usize {
+            return None;
+        }
+        let first = self.u16_at(12 + idx * 2)? as usize;
+        if first + self.num_palette_entries as usize > self.num_color_records as usize {
+            return None;
+        }
+        let start = self.color_records_array_offset as usize + first * 4;
+        let records = self.table.0.get(start..start + self.num_palette_entries as usize * 4)?;
+        Some(records.chunks(4).map(|record| ColorRecord::parse(record).1).collect())
+    }
+
+    /// Every palette, with names looked up in `names`
+    pub fn palettes(&self, names: Option<&Name<'a>>) -> Vec<Palette> {
+        let types = self.version_1_array(0);
+        let labels = self.version_1_array(1);
+        (0..self.num_palettes as usize)
+            .filter_map(|idx| {
+                let colors = self.colors(idx)?;
+                let palette_type = types
+                    .and_then(|offset| self.table.0.get(offset + idx * 4..offset + idx * 4 + 4))
+                    .map_or(0, |buf| u32::parse(buf).1);
+                let label_name_id = labels
+                    .and_then(|offset| self.u16_at(offset + idx * 2))
+                    .filter(|&name_id| name_id != NO_NAME);
+                Some(Palette {
+                    colors,
+                    palette_type: PaletteType::from_bits_truncate(palette_type),
+                    label_name_id,
+                    name: label_name_id.and_then(|name_id| names.and_then(|names| names.string(name_id))),
+                })
+            })
+            .collect()
+    }
+
+    /// The label of each palette entry, with names looked up in `names`.
+    /// Empty if the table doesn't have them.
+    pub fn entry_labels(&self, names: Option<&Name<'a>>) -> Vec<Option<PaletteEntryLabel>> {
+        let offset = match self.version_1_array(2) {
+            Some(offset) => offset,
+            None => return Vec::new(),
+        };
+        (0..self.num_palette_entries as usize)
+            .map(|idx| {
+                let name_id = self.u16_at(offset + idx * 2).filter(|&name_id| name_id != NO_NAME)?;
+                Some(PaletteEntryLabel {
+                    name_id,
+                    name: names.and_then(|names| names.string(name_id)),
+                })
+            })
+            .collect()
+    }
+
+    /// The offset of one of the optional arrays at the end of a version 1
+    /// header: palette types, palette labels or entry labels
+    fn version_1_array(&self, which: usize) -> Option<usize> {
+        if self.version < 1 {
+            return None;
+        }
+        let field = 12 + self.num_palettes as usize * 2 + which * 4;
+        let offset = u32::parse(self.table.0.get(field..field + 4)?).1 as usize;
+        if offset == 0 {
+            None
+        } else {
+            Some(offset)
+        }
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        self.table.0.get(offset..offset + 2).map(|buf| u16::parse(buf).1)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// A version 1 `CPAL` with the given palettes of RGBA colors, each with
+    /// a type and label name ID
+    pub fn build_cpal(palettes: &[(u32, u16, Vec<[u8; 4]>)], entry_labels: &[u16]) -> Vec<u8> {
+        let num_entries = palettes[0].2.len();
+        let header_size = 12 + palettes.len() * 2 + 12;
+        let colors_offset = header_size;
+        let types_offset = colors_offset + palettes.len() * num_entries * 4;
+        let labels_offset = types_offset + palettes.len() * 4;
+        let entry_labels_offset = labels_offset + palettes.len() * 2;
+
+        let mut cpal = Vec::new();
+        push_u16(&mut cpal, 1);
+        push_u16(&mut cpal, num_entries as u16);
+        push_u16(&mut cpal, palettes.len() as u16);
+        push_u16(&mut cpal, (palettes.len() * num_entries) as u16);
+        push_u32(&mut cpal, colors_offset as u32);
+        for idx in 0..palettes.len() {
+            push_u16(&mut cpal, (idx * num_entries) as u16);
+        }
+        push_u32(&mut cpal, types_offset as u32);
+        push_u32(&mut cpal, labels_offset as u32);
+        push_u32(&mut cpal, if entry_labels.is_empty() { 0 } else { entry_labels_offset as u32 });
+
+        for &(_, _, ref colors) in palettes {
+            for color in colors {
+                cpal.extend_from_slice(&[color[2], color[1], color[0], color[3]]);
+            }
+        }
+        for &(palette_type, _, _) in palettes {
+            push_u32(&mut cpal, palette_type);
+        }
+        for &(_, label, _) in palettes {
+            push_u16(&mut cpal, label);
+        }
+        for &label in entry_labels {
+            push_u16(&mut cpal, label);
+        }
+        cpal
+    }
+
+    #[test]
+    fn palettes() {
+        use font::{Font, GetTable};
+        use test_utils::font_buf;
+
+        let cpal = build_cpal(&[
+            (1, 1, vec![[255, 0, 0, 255], [0, 0, 255, 128]]),
+            (2, NO_NAME, vec![[0, 255, 0, 255], [10, 20, 30, 40]]),
+        ], &[NO_NAME, 2]);
+        let cpal = Cpal::parse(&cpal).1;
+        let font_buf = font_buf();
+        let font = Font::from_buffer(&font_buf).unwrap();
+        let names: Name = font.get_table().unwrap();
+
+        assert_eq!((cpal.num_palettes(), cpal.num_palette_entries()), (2, 2));
+        assert_eq!(cpal.colors(1).unwrap()[1].to_rgba().data, [10, 20, 30, 40]);
+        assert_eq!(cpal.colors(2), None);
+
+        let palettes = cpal.palettes(Some(&names));
+        assert_eq!(palettes.len(), 2);
+        assert_eq!(palettes[0].colors[0], ColorRecord { red: 255, green: 0, blue: 0, alpha: 255 });
+        assert_eq!(palettes[0].palette_type, PaletteType::USABLE_WITH_LIGHT_BACKGROUND);
+        assert_eq!(palettes[0].label_name_id, Some(1));
+        assert_eq!(palettes[0].name, Some("DejaVu Sans Mono".to_string()));
+        assert_eq!(palettes[1].palette_type, PaletteType::USABLE_WITH_DARK_BACKGROUND);
+        assert_eq!((palettes[1].label_name_id, palettes[1].name.clone()), (None, None));
+
+        let labels = cpal.entry_labels(Some(&names));
+        assert_eq!(labels[0], None);
+        assert_eq!(labels[1].as_ref().unwrap().name, Some("Book".to_string()));
+    }
+}
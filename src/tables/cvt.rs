@@ -0,0 +1,21 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/cvt
+
+/// Distances, in font units, that instructions refer to by index
+#[derive(Debug, Parse)]
+pub struct Cvt<'a>(BufView<'a, u8>);
+
+impl<'a> PrimaryTable for Cvt<'a> {
+    fn tag() -> TableTag {
+        TableTag::ControlValue
+    }
+}
+
+impl<'a> Cvt<'a> {
+    pub fn values(&self) -> Vec<i16> {
+        let len = (self.0).0.len() / 2;
+        (0..len).map(|idx| self.0.at_offset(idx * 2)).collect()
+    }
+}
@@ -0,0 +1,318 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::eblc::{BigGlyphMetrics, BitmapSize, Eblc, GlyphLocation, SmallGlyphMetrics};
+use image::GrayImage;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/ebdt
+
+/// Composites can nest, but not this deep in any real font
+const MAX_COMPONENT_DEPTH: usize = 8;
+
+/// The glyph images for the strikes in `EBLC`
+#[derive(Debug, Parse)]
+pub struct Ebdt<'a>(BufView<'a, u8>);
+
+impl<'a> PrimaryTable for Ebdt<'a> {
+    fn tag() -> TableTag {
+        TableTag::EBDT
+    }
+}
+
+#[derive(Debug, Parse)]
+struct EbdtComponent {
+    glyph_id: u16,
+    x_offset: i8,
+    y_offset: i8,
+}
+
+/// A decoded glyph image
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphBitmap {
+    pub metrics: BigGlyphMetrics,
+    /// One byte per pixel, from 0 to 255, in rows from the top
+    pub data: Vec<u8>,
+}
+
+impl GlyphBitmap {
+    /// The bitmap as an image, with row 0 at the top
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_raw(self.metrics.width as u32, self.metrics.height as u32, self.data.clone())
+            .expect("Bitmap data doesn't match its metrics")
+    }
+}
+
+impl<'a> Ebdt<'a> {
+    pub fn glyph_bitmap(&self, eblc: &Eblc<'a>, strike: &BitmapSize, glyph_id: u16) -> Option<GlyphBitmap> {
+        self.decode(eblc, strike, glyph_id, 0)
+    }
+
+    fn decode(&self, eblc: &Eblc<'a>, strike: &BitmapSize, glyph_id: u16, depth: usize) -> Option<GlyphBitmap> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return None;
+        }
+        let GlyphLocation { image_format, offset, length, metrics } = eblc.glyph_location(strike, glyph_id)?;
+        let data = self.0 .0.get(offset..offset + length)?;
+        let big_size = BigGlyphMetrics::approx_file_size();
+        let small_size = SmallGlyphMetrics::approx_file_size();
+
+        let (data, metrics) = match image_format {
+            1 | 2 | 8 => {
+                if data.len() < small_size {
+                    return None;
+                }
+                let (data, metrics) = SmallGlyphMetrics::parse(data);
+                (data, metrics.into())
+            },
+            5 => (data, metrics?),
+            6 | 7 | 9 => {
+                if data.len() < big_size {
+                    return None;
+                }
+                BigGlyphMetrics::parse(data)
+            },
+            _ => return None,
+        };
+
+        let data = match image_format {
+            1 | 6 => unpack_bits(data, &metrics, strike.bit_depth, true)?,
+            2 | 5 | 7 => unpack_bits(data, &metrics, strike.bit_depth, false)?,
+            _ => {
+                // Format 8 pads its small metrics out to a word
+                let data = if image_format == 8 { data.get(1..)? } else { data };
+                let num_components = u16::parse(data.get(..2)?).1 as usize;
+                let component_size = EbdtComponent::approx_file_size();
+                let components = data.get(2..2 + num_components * component_size)?;
+
+                let mut canvas = vec![0; metrics.width as usize * metrics.height as usize];
+                for component in components.chunks(component_size) {
+                    let component = EbdtComponent::parse(component).1;
+                    let bitmap = self.decode(eblc, strike, component.glyph_id, depth + 1)?;
+                    paste(&mut canvas, &metrics, &bitmap, component.x_offset as i32, component.y_offset as i32);
+                }
+                canvas
+            },
+        };
+
+        Some(GlyphBitmap { metrics, data })
+    }
+}
+
+/// Expand `bit_depth` bits per pixel, most significant first, to a byte
+/// each. Byte aligned images pad each row to a whole byte.
+fn unpack_bits(data: &[u8], metrics: &BigGlyphMetrics, bit_depth: u8, byte_aligned: bool) -> Option<Vec<u8>> {
+    if ![1, 2, 4, 8].contains(&bit_depth) {
+        return None;
+    }
+    let (width, height, bit_depth) = (metrics.width as usize, metrics.height as usize, bit_depth as usize);
+    let row_bits = if byte_aligned {
+        (width * bit_depth + 7) / 8 * 8
+    } else {
+        width * bit_depth
+    };
+    if data.len() * 8 < row_bits * height {
+        return None;
+    }
+
+    let max = (1u16 << bit_depth) - 1;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let bit = y * row_bits + x * bit_depth;
+            // Pixels never straddle bytes since the depth divides 8
+            let value = (data[bit / 8] >> (8 - bit_depth - bit % 8)) as u16 & max;
+            pixels.push((value * 255 / max) as u8);
+        }
+    }
+    Some(pixels)
+}
+
+/// Draw a component onto a composite, with its top left `(x, y)` pixels
+/// from the composite's
+fn paste(canvas: &mut [u8], metrics: &BigGlyphMetrics, bitmap: &GlyphBitmap, x: i32, y: i32) {
+    let (width, height) = (metrics.width as i32, metrics.height as i32);
+    let (component_width, component_height) = (bitmap.metrics.width as i32, bitmap.metrics.height as i32);
+    for row in 0..component_height {
+        for col in 0..component_width {
+            let (canvas_x, canvas_y) = (x + col, y + row);
+            if canvas_x < 0 || canvas_y < 0 || canvas_x >= width || canvas_y >= height {
+                continue;
+            }
+            let pixel = &mut canvas[(canvas_y * width + canvas_x) as usize];
+            *pixel = (*pixel).max(bitmap.data[(row * component_width + col) as usize]);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+    use tables::eblc::tests::{big_metrics, build_eblc, sub_header};
+
+    /// An `EBLC`/`EBDT` pair with one strike where glyph `first + idx` is
+    /// `images[idx]`, each `(image_format, image)`
+    pub fn build_strike(ppem: u8, bit_depth: u8, first: u16, images: &[(u16, Vec<u8>)]) -> (Vec<u8>, Vec<u8>) {
+        let mut ebdt = Vec::new();
+        push_u16(&mut ebdt, 2);
+        push_u16(&mut ebdt, 0);
+
+        let mut subtables = Vec::new();
+        for (idx, &(image_format, ref image)) in images.iter().enumerate() {
+            let glyph_id = first + idx as u16;
+            let mut subtable = sub_header(1, image_format, ebdt.len() as u32);
+            push_u32(&mut subtable, 0);
+            push_u32(&mut subtable, image.len() as u32);
+            subtables.push((glyph_id, glyph_id, subtable));
+            ebdt.extend_from_slice(image);
+        }
+        (build_eblc(ppem, bit_depth, &subtables), ebdt)
+    }
+
+    /// Small metrics for a `width` by `height` image
+    fn small_metrics(width: u8, height: u8) -> Vec<u8> {
+        vec![height, width, 0, height, width + 1]
+    }
+
+    fn decode(eblc: &[u8], ebdt: &[u8], glyph_id: u16) -> Option<GlyphBitmap> {
+        let eblc = Eblc::parse(eblc).1;
+        let ebdt = Ebdt::parse(ebdt).1;
+        let strike = eblc.strikes().remove(0);
+        ebdt.glyph_bitmap(&eblc, &strike, glyph_id)
+    }
+
+    #[test]
+    fn mono_formats() {
+        // A 3x2 image:
+        // #.#
+        // .#.
+        let mut format1 = small_metrics(3, 2);
+        format1.extend_from_slice(&[0b1010_0000, 0b0100_0000]);
+        let mut format2 = small_metrics(3, 2);
+        format2.extend_from_slice(&[0b1010_1000]);
+        let mut format6 = big_metrics(3, 2);
+        format6.extend_from_slice(&[0b1010_0000, 0b0100_0000]);
+        let mut format7 = big_metrics(3, 2);
+        format7.extend_from_slice(&[0b1010_1000]);
+
+        let (eblc, ebdt) = build_strike(8, 1, 1, &[(1, format1), (2, format2), (6, format6), (7, format7)]);
+        let expected = vec![255, 0, 255, 0, 255, 0];
+        for glyph_id in 1..5 {
+            let bitmap = decode(&eblc, &ebdt, glyph_id).unwrap();
+            assert_eq!((bitmap.metrics.width, bitmap.metrics.height), (3, 2));
+            assert_eq!(bitmap.data, expected);
+        }
+        let bitmap = decode(&eblc, &ebdt, 3).unwrap();
+        assert_eq!(bitmap.metrics.vert_advance, 3);
+        assert_eq!(bitmap.to_image().get_pixel(1, 1).data, [255]);
+    }
+
+    #[test]
+    fn gray_and_shared_metrics() {
+        // Format 5 gets its metrics from the index
+        let mut subtable = sub_header(2, 5, 4);
+        push_u32(&mut subtable, 1);
+        subtable.extend(big_metrics(2, 2));
+        let eblc = build_eblc(8, 2, &[(1, 2, subtable)]);
+        let mut ebdt = vec![0, 2, 0, 0];
+        ebdt.extend_from_slice(&[0b1101_0010, 0b0011_1100]);
+
+        assert_eq!(decode(&eblc, &ebdt, 1).unwrap().data, vec![255, 85, 0, 170]);
+        assert_eq!(decode(&eblc, &ebdt, 2).unwrap().data, vec![0, 255, 255, 0]);
+        assert_eq!(decode(&eblc, &ebdt, 3), None);
+    }
+
+    #[test]
+    fn composites() {
+        let mut dot = big_metrics(1, 1);
+        dot.push(0b1000_0000);
+        let mut bar = big_metrics(3, 1);
+        bar.push(0b1110_0000);
+        // Small metrics, a pad byte, then the components
+        let mut format8 = small_metrics(3, 3);
+        format8.push(0);
+        push_u16(&mut format8, 2);
+        format8.extend_from_slice(&[0, 1, 1, 0]);
+        format8.extend_from_slice(&[0, 2, 0, 2]);
+        let mut format9 = big_metrics(3, 3);
+        push_u16(&mut format9, 2);
+        format9.extend_from_slice(&[0, 3, 0, 0]);
+        format9.extend_from_slice(&[0, 2, 0, 2]);
+
+        let (eblc, ebdt) = build_strike(8, 1, 1, &[(6, dot), (6, bar), (8, format8), (9, format9)]);
+        assert_eq!(decode(&eblc, &ebdt, 3).unwrap().data, vec![
+            0, 255, 0,
+            0, 0, 0,
+            255, 255, 255,
+        ]);
+        assert_eq!(decode(&eblc, &ebdt, 4).unwrap().data, vec![
+            0, 255, 0,
+            0, 0, 0,
+            255, 255, 255,
+        ]);
+    }
+
+    #[test]
+    fn render_glyph_uses_strike() {
+        use font::Font;
+        use test_utils::{font_buf, with_tables};
+
+        let font_buf = font_buf();
+        let glyph_id = Font::from_buffer(&font_buf).unwrap().get_glyph_id('A').unwrap();
+        let mut image = big_metrics(2, 2);
+        image.push(0b1000_0000);
+        let (eblc, ebdt) = build_strike(12, 1, glyph_id as u16, &[(7, image)]);
+        let buf = with_tables(&font_buf, &[(b"EBLC", eblc), (b"EBDT", ebdt)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let bitmap = font.render_glyph(font.get_glyph('A').unwrap(), 12, [0., 0.]);
+        assert_eq!((bitmap.width(), bitmap.height()), (2, 2));
+        // Flipped so the bottom row comes first
+        assert_eq!(bitmap.into_raw(), vec![0, 0, 255, 0]);
+
+        let outline = font.render_glyph(font.get_glyph('A').unwrap(), 13, [0., 0.]);
+        assert!(outline.height() > 2);
+    }
+
+    #[test]
+    fn embedded_glyph_position() {
+        use font::{Font, GetTable};
+        use parse::primitives::FontUnit;
+        use render::compositor::RenderedText;
+        use tables::head::Head;
+        use test_utils::{font_buf, with_tables};
+
+        let font_buf = font_buf();
+        let glyph_id = Font::from_buffer(&font_buf).unwrap().get_glyph_id('A').unwrap();
+        // 2 by 2, one pixel right of the origin and its top 5 above the
+        // baseline, with the top left pixel on
+        let mut image = vec![2, 2, 1, 5, 4, 0, 0, 3];
+        image.push(0b1000_0000);
+        let (eblc, ebdt) = build_strike(12, 1, glyph_id as u16, &[(7, image)]);
+        let buf = with_tables(&font_buf, &[(b"EBLC", eblc), (b"EBDT", ebdt)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let glyph = font.embedded_bitmap_glyph(glyph_id, 12).unwrap();
+        assert_eq!((glyph.left_bearing, glyph.top_bearing), (1., 5.));
+
+        let head: Head = font.get_table().unwrap();
+        let metrics = font.text_render_metrics().unwrap();
+        let ascent = metrics.ascent.to_pixels(head.units_per_em, 12).round() as u32;
+        let mut text = RenderedText::new_left_to_right(metrics, 12, head.units_per_em);
+        let mut placement_metrics = font.placement_metrics('A', 12).unwrap();
+        assert_eq!(placement_metrics.bitmap_bearings, Some([1., 5.]));
+        // 2.25 pixels, so the next glyph starts a quarter pixel in, which a
+        // bitmap can't follow
+        placement_metrics.horiz_advance = Some(FontUnit(384));
+        for _ in 0..2 {
+            let offset = text.subpixel_offset(&placement_metrics);
+            let image = font.render_glyph(font.get_glyph('A').unwrap(), 12, offset);
+            text.add_glyph(image, placement_metrics.clone());
+        }
+
+        let on: Vec<(u32, u32)> = text.img.enumerate_pixels()
+            .filter(|&(_, _, pixel)| pixel.data[0] == 255)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(on, vec![(1, ascent - 5), (3, ascent - 5)]);
+    }
+}
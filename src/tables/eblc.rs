@@ -0,0 +1,325 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/eblc
+
+/// Where the glyph images of each bitmap strike are in `EBDT`
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Eblc<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    num_sizes: u32,
+}
+
+impl<'a> PrimaryTable for Eblc<'a> {
+    fn tag() -> TableTag {
+        TableTag::EBLC
+    }
+}
+
+#[derive(Debug, Parse, Clone, Copy, PartialEq)]
+pub struct SbitLineMetrics {
+    pub ascender: i8,
+    pub descender: i8,
+    pub width_max: u8,
+    caret_slope_numerator: i8,
+    caret_slope_denominator: i8,
+    caret_offset: i8,
+    min_origin_sb: i8,
+    min_advance_sb: i8,
+    max_before_bl: i8,
+    min_after_bl: i8,
+    pad1: i8,
+    pad2: i8,
+}
+
+/// A strike: the bitmaps for one size
+#[derive(Debug, Parse, Clone, PartialEq)]
+pub struct BitmapSize {
+    index_subtable_array_offset: u32,
+    index_tables_size: u32,
+    number_of_index_subtables: u32,
+    color_ref: u32,
+    pub hori: SbitLineMetrics,
+    pub vert: SbitLineMetrics,
+    pub start_glyph_index: u16,
+    pub end_glyph_index: u16,
+    pub ppem_x: u8,
+    pub ppem_y: u8,
+    /// Bits per pixel: 1, 2, 4 or 8
+    pub bit_depth: u8,
+    flags: i8,
+}
+
+#[derive(Debug, Parse)]
+struct IndexSubTableRecord {
+    first_glyph_index: u16,
+    last_glyph_index: u16,
+    additional_offset_to_index_subtable: u32,
+}
+
+#[derive(Debug, Parse)]
+struct IndexSubHeader {
+    index_format: u16,
+    image_format: u16,
+    image_data_offset: u32,
+}
+
+#[derive(Debug, Parse, Clone, Copy, PartialEq)]
+pub struct BigGlyphMetrics {
+    pub height: u8,
+    pub width: u8,
+    pub hori_bearing_x: i8,
+    pub hori_bearing_y: i8,
+    pub hori_advance: u8,
+    pub vert_bearing_x: i8,
+    pub vert_bearing_y: i8,
+    pub vert_advance: u8,
+}
+
+/// Metrics for one direction only
+#[derive(Debug, Parse, Clone, Copy, PartialEq)]
+pub struct SmallGlyphMetrics {
+    pub height: u8,
+    pub width: u8,
+    pub bearing_x: i8,
+    pub bearing_y: i8,
+    pub advance: u8,
+}
+
+impl From<SmallGlyphMetrics> for BigGlyphMetrics {
+    fn from(small: SmallGlyphMetrics) -> BigGlyphMetrics {
+        BigGlyphMetrics {
+            height: small.height,
+            width: small.width,
+            hori_bearing_x: small.bearing_x,
+            hori_bearing_y: small.bearing_y,
+            hori_advance: small.advance,
+            vert_bearing_x: 0,
+            vert_bearing_y: 0,
+            vert_advance: 0,
+        }
+    }
+}
+
+/// Where a glyph's image is in the data table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphLocation {
+    pub image_format: u16,
+    pub offset: usize,
+    pub length: usize,
+    /// Set when all the subtable's glyphs share metrics, which the images
+    /// then leave out
+    pub metrics: Option<BigGlyphMetrics>,
+}
+
+impl<'a> Eblc<'a> {
+    pub fn strikes(&self) -> Vec<BitmapSize> {
+        strikes(self.table.0, self.num_sizes)
+    }
+
+    /// Where the glyph's image is in `EBDT`, if the strike has it
+    pub fn glyph_location(&self, strike: &BitmapSize, glyph_id: u16) -> Option<GlyphLocation> {
+        glyph_location(self.table.0, strike, glyph_id)
+    }
+}
+
+/// The strikes of an `EBLC`-style table. Shared with `CBLC`.
+pub(crate) fn strikes(table: &[u8], num_sizes: u32) -> Vec<BitmapSize> {
+    let size = BitmapSize::approx_file_size();
+    (0..num_sizes as usize)
+        .map(|idx| 8 + idx * size)
+        .take_while(|&offset| offset + size <= table.len())
+        .map(|offset| BitmapSize::parse(&table[offset..]).1)
+        .collect()
+}
+
+/// Find a glyph in a strike of an `EBLC`-style table
+pub(crate) fn glyph_location(table: &[u8], strike: &BitmapSize, glyph_id: u16) -> Option<GlyphLocation> {
+    if glyph_id < strike.start_glyph_index || glyph_id > strike.end_glyph_index {
+        return None;
+    }
+    let array_offset = strike.index_subtable_array_offset as usize;
+    let record_size = IndexSubTableRecord::approx_file_size();
+    let record = (0..strike.number_of_index_subtables as usize)
+        .map(|idx| table.get(array_offset + idx * record_size..array_offset + (idx + 1) * record_size))
+        .take_while(Option::is_some)
+        .map(|buf| IndexSubTableRecord::parse(buf.unwrap()).1)
+        .find(|record| record.first_glyph_index <= glyph_id && glyph_id <= record.last_glyph_index)?;
+
+    let subtable = table.get(array_offset + record.additional_offset_to_index_subtable as usize..)?;
+    if subtable.len() < IndexSubHeader::approx_file_size() {
+        return None;
+    }
+    let (subtable, header) = IndexSubHeader::parse(subtable);
+    let image_data_offset = header.image_data_offset as usize;
+    let idx = (glyph_id - record.first_glyph_index) as usize;
+    let location = |start: usize, end: usize, metrics: Option<BigGlyphMetrics>| if end < start {
+        None
+    } else {
+        Some(GlyphLocation {
+            image_format: header.image_format,
+            offset: image_data_offset + start,
+            length: end - start,
+            metrics,
+        })
+    };
+
+    match header.index_format {
+        // Offsets for every glyph, then one for the end
+        1 | 3 => {
+            let offset_size = if header.index_format == 1 { 4 } else { 2 };
+            let read = |idx: usize| {
+                let buf = subtable.get(idx * offset_size..(idx + 1) * offset_size)?;
+                Some(if offset_size == 4 { u32::parse(buf).1 as usize } else { u16::parse(buf).1 as usize })
+            };
+            location(read(idx)?, read(idx + 1)?, None)
+        },
+        // Same sized images
+        2 => {
+            if subtable.len() < 4 + BigGlyphMetrics::approx_file_size() {
+                return None;
+            }
+            let (subtable, image_size) = u32::parse(subtable);
+            let metrics = BigGlyphMetrics::parse(subtable).1;
+            let image_size = image_size as usize;
+            location(idx * image_size, (idx + 1) * image_size, Some(metrics))
+        },
+        // Glyph ID and offset pairs, for sparse subtables
+        4 => {
+            let num_glyphs = u32::parse(subtable.get(..4)?).1 as usize;
+            let pairs = subtable.get(4..4 + (num_glyphs + 1) * 4)?;
+            let pair = |idx: usize| {
+                let (rest, id) = u16::parse(&pairs[idx * 4..]);
+                (id, u16::parse(rest).1 as usize)
+            };
+            let idx = (0..num_glyphs).find(|&idx| pair(idx).0 == glyph_id)?;
+            location(pair(idx).1, pair(idx + 1).1, None)
+        },
+        // Same sized images for a sparse list of glyphs
+        5 => {
+            let metrics_size = BigGlyphMetrics::approx_file_size();
+            if subtable.len() < 8 + metrics_size {
+                return None;
+            }
+            let (subtable, image_size) = u32::parse(subtable);
+            let (subtable, metrics) = BigGlyphMetrics::parse(subtable);
+            let (subtable, num_glyphs) = u32::parse(subtable);
+            let glyphs = subtable.get(..num_glyphs as usize * 2)?;
+            let idx = glyphs.chunks(2).position(|id| u16::parse(id).1 == glyph_id)?;
+            let image_size = image_size as usize;
+            location(idx * image_size, (idx + 1) * image_size, Some(metrics))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// Big metrics for a `width` by `height` image sitting on the baseline
+    pub fn big_metrics(width: u8, height: u8) -> Vec<u8> {
+        vec![height, width, 0, height, width + 1, 0, 0, height + 1]
+    }
+
+    /// An `EBLC` with one strike and the given index subtables, each
+    /// `(first_glyph, last_glyph, subtable)`
+    pub fn build_eblc(ppem: u8, bit_depth: u8, subtables: &[(u16, u16, Vec<u8>)]) -> Vec<u8> {
+        let array_offset = 8 + 48;
+        let array_size = subtables.len() * 8;
+        let mut eblc = Vec::new();
+        push_u16(&mut eblc, 2);
+        push_u16(&mut eblc, 0);
+        push_u32(&mut eblc, 1);
+
+        push_u32(&mut eblc, array_offset as u32);
+        push_u32(&mut eblc, (array_size + subtables.iter().map(|subtable| subtable.2.len()).sum::<usize>()) as u32);
+        push_u32(&mut eblc, subtables.len() as u32);
+        push_u32(&mut eblc, 0);
+        eblc.extend_from_slice(&[ppem, 0, ppem, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        eblc.extend_from_slice(&[0; 12]);
+        push_u16(&mut eblc, subtables.iter().map(|subtable| subtable.0).min().unwrap_or(0));
+        push_u16(&mut eblc, subtables.iter().map(|subtable| subtable.1).max().unwrap_or(0));
+        eblc.extend_from_slice(&[ppem, ppem, bit_depth, 1]);
+
+        let mut offset = array_size;
+        for &(first, last, ref subtable) in subtables {
+            push_u16(&mut eblc, first);
+            push_u16(&mut eblc, last);
+            push_u32(&mut eblc, offset as u32);
+            offset += subtable.len();
+        }
+        for subtable in subtables {
+            eblc.extend_from_slice(&subtable.2);
+        }
+        eblc
+    }
+
+    /// The start of an index subtable
+    pub fn sub_header(index_format: u16, image_format: u16, image_data_offset: u32) -> Vec<u8> {
+        let mut header = Vec::new();
+        push_u16(&mut header, index_format);
+        push_u16(&mut header, image_format);
+        push_u32(&mut header, image_data_offset);
+        header
+    }
+
+    #[test]
+    fn index_formats() {
+        let mut format1 = sub_header(1, 1, 100);
+        for &offset in &[0, 10, 25] {
+            push_u32(&mut format1, offset);
+        }
+        let mut format2 = sub_header(2, 5, 200);
+        push_u32(&mut format2, 6);
+        format2.extend(big_metrics(3, 2));
+        let mut format3 = sub_header(3, 6, 300);
+        for &offset in &[0, 4, 4, 9] {
+            push_u16(&mut format3, offset);
+        }
+        let mut format4 = sub_header(4, 7, 400);
+        push_u32(&mut format4, 2);
+        for &(glyph, offset) in &[(20, 0), (23, 7), (0, 15)] {
+            push_u16(&mut format4, glyph);
+            push_u16(&mut format4, offset);
+        }
+        let mut format5 = sub_header(5, 5, 500);
+        push_u32(&mut format5, 8);
+        format5.extend(big_metrics(4, 2));
+        push_u32(&mut format5, 2);
+        push_u16(&mut format5, 30);
+        push_u16(&mut format5, 32);
+
+        let eblc = build_eblc(12, 1, &[
+            (1, 2, format1),
+            (5, 7, format2),
+            (10, 12, format3),
+            (20, 23, format4),
+            (30, 32, format5),
+        ]);
+        let eblc = Eblc::parse(&eblc).1;
+        let strikes = eblc.strikes();
+        assert_eq!(strikes.len(), 1);
+        let strike = &strikes[0];
+        assert_eq!((strike.ppem_y, strike.bit_depth), (12, 1));
+        assert_eq!((strike.start_glyph_index, strike.end_glyph_index), (1, 32));
+
+        let location = |glyph_id| eblc.glyph_location(strike, glyph_id)
+            .map(|location| (location.image_format, location.offset, location.length));
+        assert_eq!(location(2), Some((1, 110, 15)));
+        assert_eq!(location(7), Some((5, 212, 6)));
+        assert_eq!(eblc.glyph_location(strike, 6).unwrap().metrics.unwrap().width, 3);
+        assert_eq!(location(11), Some((6, 304, 0)));
+        assert_eq!(location(12), Some((6, 304, 5)));
+        assert_eq!(location(23), Some((7, 407, 8)));
+        assert_eq!(location(21), None);
+        assert_eq!(location(32), Some((5, 508, 8)));
+        assert_eq!(location(31), None);
+        assert_eq!(location(3), None);
+        assert_eq!(location(40), None);
+    }
+}
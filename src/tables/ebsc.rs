@@ -0,0 +1,75 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::eblc::SbitLineMetrics;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/ebsc
+
+/// Sizes to draw by scaling another size's strike
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Ebsc<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    num_sizes: u32,
+}
+
+impl<'a> PrimaryTable for Ebsc<'a> {
+    fn tag() -> TableTag {
+        TableTag::EmbeddedBitmapScalingControl
+    }
+}
+
+#[derive(Debug, Parse, Clone, PartialEq)]
+pub struct BitmapScale {
+    pub hori: SbitLineMetrics,
+    pub vert: SbitLineMetrics,
+    pub ppem_x: u8,
+    pub ppem_y: u8,
+    /// The size of the strike to scale
+    pub substitute_ppem_x: u8,
+    pub substitute_ppem_y: u8,
+}
+
+impl<'a> Ebsc<'a> {
+    pub fn scales(&self) -> Vec<BitmapScale> {
+        let size = BitmapScale::approx_file_size();
+        (0..self.num_sizes as usize)
+            .map(|idx| 8 + idx * size)
+            .take_while(|&offset| offset + size <= self.table.0.len())
+            .map(|offset| self.table.at_offset(offset))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use font::Font;
+    use tables::ebdt::tests::build_strike;
+    use tables::eblc::tests::big_metrics;
+    use test_utils::{font_buf, push_u16, push_u32, with_tables};
+
+    #[test]
+    fn scaled_strike() {
+        let font_buf = font_buf();
+        let glyph_id = Font::from_buffer(&font_buf).unwrap().get_glyph_id('A').unwrap();
+        let mut image = big_metrics(2, 1);
+        image.push(0b0100_0000);
+        let (eblc, ebdt) = build_strike(10, 1, glyph_id as u16, &[(7, image)]);
+
+        let mut ebsc = Vec::new();
+        push_u16(&mut ebsc, 2);
+        push_u16(&mut ebsc, 0);
+        push_u32(&mut ebsc, 1);
+        ebsc.extend_from_slice(&[0; 24]);
+        ebsc.extend_from_slice(&[20, 20, 10, 10]);
+        let buf = with_tables(&font_buf, &[(b"EBLC", eblc), (b"EBDT", ebdt), (b"EBSC", ebsc)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let bitmap = font.embedded_bitmap(glyph_id, 20).unwrap();
+        assert_eq!((bitmap.width(), bitmap.height()), (4, 2));
+        assert_eq!(bitmap.into_raw(), vec![0, 0, 255, 255, 0, 0, 255, 255]);
+        assert!(font.embedded_bitmap(glyph_id, 10).is_some());
+        assert!(font.embedded_bitmap(glyph_id, 15).is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use parse::{BufView, DynArr, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::name::Name;
+
+// https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6feat.html
+
+/// The names of the AAT features and settings that `morx` uses
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Feat<'a> {
+    table: BufView<'a, u8>,
+    version: u32,
+    feature_name_count: u16,
+    reserved1: u16,
+    reserved2: u32,
+    #[arr_len_src = "feature_name_count"]
+    names: DynArr<'a, FeatureName>,
+}
+
+impl<'a> PrimaryTable for Feat<'a> {
+    fn tag() -> TableTag {
+        TableTag::LayoutFeature
+    }
+}
+
+#[derive(Debug, Parse)]
+struct FeatureName {
+    feature: u16,
+    n_settings: u16,
+    setting_table_offset: u32,
+    feature_flags: u16,
+    name_index: u16,
+}
+
+#[derive(Debug, Parse)]
+struct SettingName {
+    setting: u16,
+    name_index: u16,
+}
+
+/// Only one setting of the feature can be on at a time
+const EXCLUSIVE: u16 = 0x8000;
+/// The low byte of the flags is the index of the default setting
+const HAS_DEFAULT_INDEX: u16 = 0x4000;
+
+/// An AAT feature type (e.g. 1 for ligatures) and its settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct AatFeatureInfo {
+    pub feature_type: u16,
+    pub name_id: u16,
+    pub name: Option<String>,
+    /// Only one of the settings can be on at a time. Otherwise each setting
+    /// is an on/off pair, even for on and odd for off.
+    pub exclusive: bool,
+    pub default_setting: Option<u16>,
+    pub settings: Vec<AatSettingInfo>,
+}
+
+/// A selector for a feature, e.g. 2 (common ligatures on) for ligatures
+#[derive(Debug, Clone, PartialEq)]
+pub struct AatSettingInfo {
+    pub setting: u16,
+    pub name_id: u16,
+    pub name: Option<String>,
+}
+
+impl<'a> Feat<'a> {
+    /// Every feature, with names looked up in `names`
+    pub fn info(&self, names: Option<&Name<'a>>) -> Vec<AatFeatureInfo> {
+        let name = |name_id| names.and_then(|names| names.string(name_id));
+        self.names.iter()
+            .map(|feature| {
+                let settings: DynArr<SettingName> = {
+                    use std::marker::PhantomData;
+                    let start = feature.setting_table_offset as usize;
+                    let end = start + feature.n_settings as usize * SettingName::approx_file_size();
+                    DynArr(&self.table.0[start..end], PhantomData)
+                };
+                let settings: Vec<AatSettingInfo> = settings
+                    .map(|setting| AatSettingInfo {
+                        setting: setting.setting,
+                        name_id: setting.name_index,
+                        name: name(setting.name_index),
+                    })
+                    .collect();
+                let default_idx = if feature.feature_flags & HAS_DEFAULT_INDEX != 0 {
+                    (feature.feature_flags & 0xFF) as usize
+                } else {
+                    0
+                };
+                AatFeatureInfo {
+                    feature_type: feature.feature,
+                    name_id: feature.name_index,
+                    name: name(feature.name_index),
+                    exclusive: feature.feature_flags & EXCLUSIVE != 0,
+                    default_setting: settings.get(default_idx).map(|setting| setting.setting),
+                    settings,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    #[test]
+    fn feature_names() {
+        let mut buf = Vec::new();
+        push_u32(&mut buf, 0x0001_0000);
+        push_u16(&mut buf, 1);
+        push_u16(&mut buf, 0);
+        push_u32(&mut buf, 0);
+        // Ligatures, two settings at offset 24, the second is the default
+        push_u16(&mut buf, 1);
+        push_u16(&mut buf, 2);
+        push_u32(&mut buf, 24);
+        push_u16(&mut buf, HAS_DEFAULT_INDEX | 1);
+        push_u16(&mut buf, 256);
+        for &(setting, name) in &[(2, 257), (3, 258)] {
+            push_u16(&mut buf, setting);
+            push_u16(&mut buf, name);
+        }
+
+        let feat = Feat::parse(&buf).1;
+        let info = feat.info(None);
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].feature_type, 1);
+        assert_eq!(info[0].name_id, 256);
+        assert!(!info[0].exclusive);
+        assert_eq!(info[0].default_setting, Some(3));
+        let settings: Vec<(u16, u16)> = info[0].settings.iter()
+            .map(|setting| (setting.setting, setting.name_id))
+            .collect();
+        assert_eq!(settings, vec![(2, 257), (3, 258)]);
+    }
+}
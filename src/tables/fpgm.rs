@@ -0,0 +1,21 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/fpgm
+
+/// Instructions run once, mostly to define functions for the other
+/// programs
+#[derive(Debug, Parse)]
+pub struct FontProgram<'a>(BufView<'a, u8>);
+
+impl<'a> PrimaryTable for FontProgram<'a> {
+    fn tag() -> TableTag {
+        TableTag::FontProgram
+    }
+}
+
+impl<'a> FontProgram<'a> {
+    pub fn instructions(&self) -> &'a [u8] {
+        (self.0).0
+    }
+}
@@ -0,0 +1,236 @@
+use parse::{BufView, Parse};
+use parse::primitives::Fixed;
+use tables::{PrimaryTable, TableTag};
+use tables::layout::Tag;
+use tables::name::Name;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/fvar
+
+/// The axes of a variable font and its named instances
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Fvar<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    axes_array_offset: u16,
+    reserved: u16,
+    axis_count: u16,
+    axis_size: u16,
+    instance_count: u16,
+    instance_size: u16,
+}
+
+impl<'a> PrimaryTable for Fvar<'a> {
+    fn tag() -> TableTag {
+        TableTag::FontVariation
+    }
+}
+
+#[derive(Debug, Parse)]
+struct VariationAxisRecord {
+    axis_tag: Tag,
+    min_value: Fixed,
+    default_value: Fixed,
+    max_value: Fixed,
+    flags: u16,
+    axis_name_id: u16,
+}
+
+/// The axis shouldn't be shown in user interfaces
+const HIDDEN_AXIS: u16 = 0x0001;
+
+/// A design axis, like weight (`wght`) or width (`wdth`), in user space
+/// units
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariationAxis {
+    pub tag: Tag,
+    pub min_value: f32,
+    pub default_value: f32,
+    pub max_value: f32,
+    pub hidden: bool,
+    pub name_id: u16,
+    pub name: Option<String>,
+}
+
+impl VariationAxis {
+    /// Map a user space value to the normalized -1 to 1 range, where 0 is
+    /// the default. Doesn't apply `avar`.
+    pub fn normalize(&self, value: f32) -> f32 {
+        let value = value.max(self.min_value).min(self.max_value);
+        let normalized = if value < self.default_value {
+            -(self.default_value - value) / (self.default_value - self.min_value)
+        } else if value > self.default_value {
+            (value - self.default_value) / (self.max_value - self.default_value)
+        } else {
+            0.
+        };
+        round_to_f2dot14(normalized.max(-1.).min(1.))
+    }
+}
+
+/// Normalized coordinates are stored as 2.14 fixed point numbers
+pub(crate) fn round_to_f2dot14(value: f32) -> f32 {
+    (value * 16384.).round() / 16384.
+}
+
+/// A predefined position in the design space, e.g. "Bold Condensed"
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedInstance {
+    pub subfamily_name_id: u16,
+    pub name: Option<String>,
+    pub postscript_name_id: Option<u16>,
+    /// User space coordinates, in the order of the axes
+    pub coordinates: Vec<f32>,
+}
+
+/// Set an axis to a user space value, e.g. `wght` to 650
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Variation {
+    pub tag: Tag,
+    pub value: f32,
+}
+
+impl Variation {
+    pub fn new(tag: &[u8; 4], value: f32) -> Variation {
+        Variation {
+            tag: Tag::new(tag),
+            value,
+        }
+    }
+}
+
+impl<'a> Fvar<'a> {
+    /// The axes, with names looked up in `names`
+    pub fn axes(&self, names: Option<&Name<'a>>) -> Vec<VariationAxis> {
+        (0..self.axis_count as usize)
+            .map(|idx| {
+                let offset = self.axes_array_offset as usize + idx * self.axis_size as usize;
+                let record: VariationAxisRecord = self.table.at_offset(offset);
+                VariationAxis {
+                    tag: record.axis_tag,
+                    min_value: record.min_value.to_f32(),
+                    default_value: record.default_value.to_f32(),
+                    max_value: record.max_value.to_f32(),
+                    hidden: record.flags & HIDDEN_AXIS != 0,
+                    name_id: record.axis_name_id,
+                    name: names.and_then(|names| names.string(record.axis_name_id)),
+                }
+            })
+            .collect()
+    }
+
+    /// The named instances, with names looked up in `names`
+    pub fn instances(&self, names: Option<&Name<'a>>) -> Vec<NamedInstance> {
+        let start = self.axes_array_offset as usize + self.axis_count as usize * self.axis_size as usize;
+        // The PostScript name ID is optional, the record size says if it's there
+        let has_postscript_name = self.instance_size as usize >= 6 + 4 * self.axis_count as usize;
+        (0..self.instance_count as usize)
+            .map(|idx| {
+                let record: BufView<u8> = self.table.at_offset(start + idx * self.instance_size as usize);
+                let subfamily_name_id: u16 = record.at_offset(0);
+                let coordinates = (0..self.axis_count as usize)
+                    .map(|axis| record.at_offset::<Fixed>(4 + axis * 4).to_f32())
+                    .collect();
+                let postscript_name_id = if has_postscript_name {
+                    Some(record.at_offset(4 + self.axis_count as usize * 4))
+                } else {
+                    None
+                };
+                NamedInstance {
+                    subfamily_name_id,
+                    name: names.and_then(|names| names.string(subfamily_name_id)),
+                    postscript_name_id,
+                    coordinates,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// An `fvar` with `wght` 100 to 900 (default 400) and `wdth` 75 to 100
+    /// (default 100), and a "Bold" instance at (700, 100)
+    pub fn build_fvar() -> Vec<u8> {
+        let mut fvar = Vec::new();
+        push_u32(&mut fvar, 0x0001_0000);
+        push_u16(&mut fvar, 16);
+        push_u16(&mut fvar, 2);
+        push_u16(&mut fvar, 2);
+        push_u16(&mut fvar, 20);
+        push_u16(&mut fvar, 1);
+        push_u16(&mut fvar, 14);
+        for &(tag, min, default, max, name) in &[(b"wght", 100, 400, 900, 256), (b"wdth", 75, 100, 100, 257)] {
+            fvar.extend_from_slice(tag);
+            for &val in &[min, default, max] {
+                push_u32(&mut fvar, val << 16);
+            }
+            push_u16(&mut fvar, 0);
+            push_u16(&mut fvar, name);
+        }
+        push_u16(&mut fvar, 258);
+        push_u16(&mut fvar, 0);
+        push_u32(&mut fvar, 700 << 16);
+        push_u32(&mut fvar, 100 << 16);
+        push_u16(&mut fvar, 259);
+        fvar
+    }
+
+    #[test]
+    fn axes_and_instances() {
+        let fvar = build_fvar();
+        let fvar = Fvar::parse(&fvar).1;
+
+        let axes = fvar.axes(None);
+        assert_eq!(axes.len(), 2);
+        assert_eq!(axes[0].tag, Tag::new(b"wght"));
+        assert_eq!(axes[0].default_value, 400.);
+        assert_eq!(axes[1].min_value, 75.);
+        assert_eq!(axes[0].normalize(650.), 0.5);
+        assert_eq!(axes[0].normalize(250.), -0.5);
+        assert_eq!(axes[0].normalize(1000.), 1.);
+        assert_eq!(axes[1].normalize(87.5), -0.5);
+
+        let instances = fvar.instances(None);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].subfamily_name_id, 258);
+        assert_eq!(instances[0].coordinates, vec![700., 100.]);
+        assert_eq!(instances[0].postscript_name_id, Some(259));
+    }
+
+    #[test]
+    fn font_at_user_coordinates() {
+        use font::Font;
+        use test_utils::{load_font_buf, with_tables, ROBOTO};
+
+        // wght 0.5 maps to 0.25
+        let mut avar = Vec::new();
+        for &val in &[1, 0, 0, 2, 4] {
+            push_u16(&mut avar, val);
+        }
+        for &val in &[-16384i16, -16384, 0, 0, 8192, 4096, 16384, 16384] {
+            push_u16(&mut avar, val as u16);
+        }
+        push_u16(&mut avar, 0);
+
+        let buf = with_tables(&load_font_buf(ROBOTO), &[(b"fvar", build_fvar()), (b"avar", avar)]);
+        let font = Font::from_buffer(&buf).unwrap();
+        assert_eq!(font.normalized_coords(), &[] as &[f32]);
+        assert_eq!(font.variation_axes().unwrap().len(), 2);
+
+        let instance = font.with_variations(&[Variation::new(b"wght", 650.), Variation::new(b"wdth", 85.)]).unwrap();
+        assert_eq!(instance.normalized_coords(), &[0.25, round_to_f2dot14(-0.6)]);
+
+        let bold = font.with_named_instance(0).unwrap();
+        let wght = round_to_f2dot14(0.6);
+        let wght = round_to_f2dot14(0.25 + 0.75 * (wght - 0.5) / 0.5);
+        assert_eq!(bold.normalized_coords(), &[wght, 0.]);
+
+        let plain = Font::from_buffer(&load_font_buf(ROBOTO)).unwrap().variation_axes().is_none();
+        assert!(plain);
+    }
+}
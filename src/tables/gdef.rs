@@ -0,0 +1,102 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::layout::{ClassDef, Coverage};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/gdef
+
+#[allow(dead_code)]
+#[derive(Debug, Parse, Clone)]
+pub struct GDEF<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    glyph_class_def_offset: u16,
+    attach_list_offset: u16,
+    lig_caret_list_offset: u16,
+    mark_attach_class_def_offset: u16,
+    /// Only present in version 1.2 and up
+    rest: BufView<'a, u8>,
+}
+
+impl<'a> PrimaryTable for GDEF<'a> {
+    fn tag() -> TableTag {
+        TableTag::GlyphDefinition
+    }
+}
+
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum GlyphClass {
+    /// Glyphs that aren't given a class by GDEF
+    Unclassified = 0,
+    /// Single character, spacing glyph
+    Base = 1,
+    /// Multiple character, spacing glyph
+    Ligature = 2,
+    /// Non-spacing combining glyph
+    Mark = 3,
+    /// Part of single character, spacing glyph
+    Component = 4,
+}
+
+impl<'a> GDEF<'a> {
+    fn class_def(&self, offset: u16) -> Option<ClassDef<'a>> {
+        if offset == 0 {
+            None
+        } else {
+            self.table.checked_at_offset(offset as usize)
+        }
+    }
+
+    pub fn glyph_class(&self, glyph_id: u16) -> GlyphClass {
+        use num_traits::FromPrimitive;
+        self.class_def(self.glyph_class_def_offset)
+            .and_then(|def| GlyphClass::from_u16(def.class(glyph_id)))
+            .unwrap_or(GlyphClass::Unclassified)
+    }
+
+    pub fn mark_attach_class(&self, glyph_id: u16) -> u16 {
+        self.class_def(self.mark_attach_class_def_offset)
+            .map(|def| def.class(glyph_id))
+            .unwrap_or(0)
+    }
+
+    /// Whether the glyph is in the mark glyph set with index `set_idx`
+    pub fn is_in_mark_glyph_set(&self, set_idx: u16, glyph_id: u16) -> bool {
+        if self.minor_version < 2 {
+            return false;
+        }
+        let sets_offset: u16 = self.rest.at_offset(0);
+        if sets_offset == 0 {
+            return false;
+        }
+        let sets: BufView<u8> = self.table.at_offset(sets_offset as usize);
+        let format: u16 = sets.at_offset(0);
+        let count: u16 = sets.at_offset(2);
+        if format != 1 || set_idx >= count {
+            return false;
+        }
+        let coverage_offset: u32 = sets.at_offset(4 + 4 * set_idx as usize);
+        let coverage: Coverage = sets.at_offset(coverage_offset as usize);
+        coverage.contains(glyph_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::*;
+    use test_utils::font_buf;
+
+    #[test]
+    fn glyph_classes() {
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let gdef: GDEF = font.get_table().unwrap();
+
+        let a = font.get_glyph_id('a').unwrap() as u16;
+        let acute = font.get_glyph_id('\u{301}').unwrap() as u16;
+        assert_eq!(gdef.glyph_class(a), GlyphClass::Base);
+        assert_eq!(gdef.glyph_class(acute), GlyphClass::Mark);
+    }
+}
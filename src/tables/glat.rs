@@ -0,0 +1,130 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// Graphite glyph attributes
+// https://github.com/silnrsi/graphite/blob/master/doc/GTF.txt
+
+/// Where each glyph's attributes are in `Glat`
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Gloc<'a> {
+    version: u32,
+    flags: u16,
+    num_attribs: u16,
+    locations: BufView<'a, u8>,
+}
+
+impl<'a> PrimaryTable for Gloc<'a> {
+    fn tag() -> TableTag {
+        TableTag::Gloc
+    }
+}
+
+/// Locations are u32s instead of u16s
+const LONG_FORMAT: u16 = 0x0001;
+
+impl<'a> Gloc<'a> {
+    pub fn num_attribs(&self) -> u16 {
+        self.num_attribs
+    }
+
+    /// Byte range of the glyph's attributes in `Glat`
+    fn range(&self, glyph_id: u16) -> Option<(usize, usize)> {
+        let idx = glyph_id as usize;
+        let (size, read): (usize, fn(&BufView<'a, u8>, usize) -> usize) =
+            if self.flags & LONG_FORMAT != 0 {
+                (4, |view, offset| view.at_offset::<u32>(offset) as usize)
+            } else {
+                (2, |view, offset| view.at_offset::<u16>(offset) as usize)
+            };
+        if (idx + 2) * size > self.locations.0.len() {
+            return None;
+        }
+        Some((read(&self.locations, idx * size), read(&self.locations, (idx + 1) * size)))
+    }
+}
+
+/// The attribute values of each glyph, stored as runs of consecutive
+/// attributes
+#[derive(Debug, Parse)]
+pub struct Glat<'a> {
+    table: BufView<'a, u8>,
+    version: u32,
+}
+
+impl<'a> PrimaryTable for Glat<'a> {
+    fn tag() -> TableTag {
+        TableTag::Glat
+    }
+}
+
+/// Set in version 3's compression field when glyphs start with octaboxes
+const HAS_OCTABOXES: u32 = 0x0000_0001;
+
+impl<'a> Glat<'a> {
+    /// The value of attribute `attr` for the glyph, 0 if it isn't set
+    pub fn attribute(&self, gloc: &Gloc<'a>, glyph_id: u16, attr: u16) -> i16 {
+        let (mut offset, end) = match gloc.range(glyph_id) {
+            Some(range) => range,
+            None => return 0,
+        };
+        if self.version >= 0x0003_0000 {
+            let compression: u32 = self.table.at_offset(4);
+            if compression & HAS_OCTABOXES != 0 {
+                // Collision boxes: a bitmap of sub-boxes, 4 diagonal bounds,
+                // then 8 bytes for each sub-box
+                let bitmap: u16 = self.table.at_offset(offset);
+                offset += 6 + 8 * bitmap.count_ones() as usize;
+            }
+        }
+
+        let wide = self.version >= 0x0002_0000;
+        while offset < end {
+            let (first, count, values) = if wide {
+                let first: u16 = self.table.at_offset(offset);
+                let count: u16 = self.table.at_offset(offset + 2);
+                (first, count, offset + 4)
+            } else {
+                let first: u8 = self.table.at_offset(offset);
+                let count: u8 = self.table.at_offset(offset + 1);
+                (first as u16, count as u16, offset + 2)
+            };
+            if attr >= first && attr - first < count {
+                return self.table.at_offset(values + (attr - first) as usize * 2);
+            }
+            offset = values + count as usize * 2;
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    #[test]
+    fn attribute_runs() {
+        // Glyph 0 has attributes 1..=2, glyph 1 has attribute 5
+        let mut glat = Vec::new();
+        push_u32(&mut glat, 0x0002_0000);
+        for &val in &[1, 2, 10, 20, 5, 1, 50] {
+            push_u16(&mut glat, val);
+        }
+        let mut gloc = Vec::new();
+        push_u32(&mut gloc, 0x0001_0000);
+        push_u16(&mut gloc, 0);
+        push_u16(&mut gloc, 6);
+        for &val in &[4, 12, 18] {
+            push_u16(&mut gloc, val);
+        }
+
+        let glat = Glat::parse(&glat).1;
+        let gloc = Gloc::parse(&gloc).1;
+        assert_eq!(glat.attribute(&gloc, 0, 1), 10);
+        assert_eq!(glat.attribute(&gloc, 0, 2), 20);
+        assert_eq!(glat.attribute(&gloc, 0, 5), 0);
+        assert_eq!(glat.attribute(&gloc, 1, 5), 50);
+        assert_eq!(glat.attribute(&gloc, 2, 5), 0);
+    }
+}
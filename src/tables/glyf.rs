@@ -1,6 +1,7 @@
 use parse::{Parse, BufView, DynArr};
 use tables::{PrimaryTable, TableTag};
-use math::Affine;
+use math::{Affine, Point};
+use render::{DrawCommand, FlattenedDrawCommands};
 
 // TODO: Glyph coordinate points are in `FontUnit`s
 
@@ -57,6 +58,8 @@ impl<'a> Glyf<'a> {
 
         Some(Glyph {
             header, desc,
+            deltas: None,
+            glyph_id: None,
         })
     }
 }
@@ -73,11 +76,56 @@ pub struct Header {
 pub struct Glyph<'a> {
     pub header: Header,
     pub desc: Description<'a>,
+    /// `gvar` deltas for the points (or component offsets), then the four
+    /// phantom points. Set when loaded through a `Font` with variations.
+    pub(crate) deltas: Option<Vec<(f32, f32)>>,
+    /// Set when loaded through a `Font`
+    pub(crate) glyph_id: Option<u32>,
 }
 
 pub enum Description<'a> {
     Simple(SimpleGlyph<'a>),
     Composite(CompositeGlyph<'a>),
+    /// An outline from a table other than `glyf`, like `CFF `
+    Cubic(Vec<DrawCommand>),
+}
+
+impl<'a> Glyph<'a> {
+    /// A glyph for an outline that isn't stored in `glyf`. `None` if it is
+    /// empty.
+    pub(crate) fn from_commands(commands: Vec<DrawCommand>) -> Option<Glyph<'a>> {
+        let mut points: Vec<Point> = Vec::new();
+        let mut number_of_contours = 0;
+        for (start, end) in FlattenedDrawCommands::from_commands(commands.iter().cloned()) {
+            // A new contour starts wherever the last one didn't end
+            if points.last() != Some(&start) {
+                number_of_contours += 1;
+                points.push(start);
+            }
+            points.push(end);
+        }
+        if points.is_empty() {
+            return None;
+        }
+
+        let x_min = points.iter().map(|point| point.x).fold(::std::f32::INFINITY, f32::min);
+        let y_min = points.iter().map(|point| point.y).fold(::std::f32::INFINITY, f32::min);
+        let x_max = points.iter().map(|point| point.x).fold(::std::f32::NEG_INFINITY, f32::max);
+        let y_max = points.iter().map(|point| point.y).fold(::std::f32::NEG_INFINITY, f32::max);
+        let header = Header {
+            number_of_contours,
+            x_min: x_min.floor() as i16,
+            y_min: y_min.floor() as i16,
+            x_max: x_max.ceil() as i16,
+            y_max: y_max.ceil() as i16,
+        };
+        Some(Glyph {
+            header,
+            desc: Description::Cubic(commands),
+            deltas: None,
+            glyph_id: None,
+        })
+    }
 }
 
 pub struct SimpleGlyph<'a> {
@@ -150,6 +198,16 @@ impl<'a> SimpleGlyph<'a> {
         }
     }
 
+    /// The index of the last point in each contour
+    /// The glyph program
+    pub fn instructions(&self) -> &'a [u8] {
+        self.instructions.0
+    }
+
+    pub fn contour_end_points(&self) -> impl 'a + Iterator<Item = u16> {
+        self.end_points_of_contours.iter()
+    }
+
     pub fn contour_lengths(&self) -> impl 'a + Iterator<Item = u16> {
         self.end_points_of_contours.iter()
             .map(|val| val + 1) // To fix off-by-one error for first contour
@@ -164,6 +222,56 @@ impl<'a> SimpleGlyph<'a> {
             lengths: self.contour_lengths(),
         }
     }
+
+    /// Serialize the glyph with its points moved to `coords`
+    pub(crate) fn encode(&self, coords: &[Coordinate]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_i16(&mut buf, self.end_points_of_contours.len() as i16);
+        push_i16(&mut buf, coords.iter().map(|coord| coord.x).min().unwrap_or(0));
+        push_i16(&mut buf, coords.iter().map(|coord| coord.y).min().unwrap_or(0));
+        push_i16(&mut buf, coords.iter().map(|coord| coord.x).max().unwrap_or(0));
+        push_i16(&mut buf, coords.iter().map(|coord| coord.y).max().unwrap_or(0));
+        buf.extend_from_slice(self.end_points_of_contours.0);
+        push_i16(&mut buf, self.instruction_length as i16);
+        buf.extend_from_slice(self.instructions.0);
+
+        // No repeats, every point gets its own flag
+        let mut flags = Vec::with_capacity(coords.len());
+        let mut xs = Vec::new();
+        let mut ys = Vec::new();
+        let (mut x, mut y) = (0i16, 0i16);
+        for coord in coords {
+            let mut flag = if coord.on_curve { SimpleFlags::ON_CURVE_POINT } else { SimpleFlags::empty() };
+            flag |= encode_delta(&mut xs, coord.x.wrapping_sub(x), SimpleFlags::X_SHORT_VEC, SimpleFlags::X_IS_SAME);
+            flag |= encode_delta(&mut ys, coord.y.wrapping_sub(y), SimpleFlags::Y_SHORT_VEC, SimpleFlags::Y_IS_SAME);
+            flags.push(flag.bits());
+            x = coord.x;
+            y = coord.y;
+        }
+        buf.extend(flags);
+        buf.extend(xs);
+        buf.extend(ys);
+        buf
+    }
+}
+
+/// Write a coordinate delta in the smallest form, returning its flags.
+/// `same` doubles as the positive sign for short deltas.
+fn encode_delta(buf: &mut Vec<u8>, delta: i16, short: SimpleFlags, same: SimpleFlags) -> SimpleFlags {
+    if delta == 0 {
+        same
+    } else if delta.abs() < 256 && delta != i16::min_value() {
+        buf.push(delta.abs() as u8);
+        if delta > 0 { short | same } else { short }
+    } else {
+        push_i16(buf, delta);
+        SimpleFlags::empty()
+    }
+}
+
+fn push_i16(buf: &mut Vec<u8>, val: i16) {
+    buf.push((val >> 8) as u8);
+    buf.push(val as u8);
 }
 
 bitflags! {
@@ -329,6 +437,97 @@ impl<'a> CompositeGlyph<'a> {
             has_more: true,
         }
     }
+
+    /// Each component's `ROUND_XY_TO_GRID` and `USE_MY_METRICS` flags, and
+    /// the instructions run once the components are in place
+    pub(crate) fn hinting_info(&self) -> (Vec<(bool, bool)>, &'a [u8]) {
+        let mut flags = Vec::new();
+        let mut components = self.components;
+        loop {
+            let (rest, header) = CompositeComponentHeader::parse(components);
+            let component_flags = header.flags;
+            flags.push((
+                component_flags.contains(CompositeFlags::ROUND_XY_TO_GRID),
+                component_flags.contains(CompositeFlags::USE_MY_METRICS),
+            ));
+            let args_size = if component_flags.contains(CompositeFlags::ARG_1_AND_2_ARE_WORDS) { 4 } else { 2 };
+            let transform_size = if component_flags.contains(CompositeFlags::WE_HAVE_A_SCALE) {
+                2
+            } else if component_flags.contains(CompositeFlags::WE_HAVE_AN_X_AND_Y_SCALE) {
+                4
+            } else if component_flags.contains(CompositeFlags::WE_HAVE_A_TWO_BY_TWO) {
+                8
+            } else {
+                0
+            };
+            components = &rest[args_size + transform_size..];
+            if !component_flags.contains(CompositeFlags::MORE_COMPONENTS) {
+                let instructions = if component_flags.contains(CompositeFlags::WE_HAVE_INSTRUCTIONS) {
+                    let (rest, instruction_length) = u16::parse(components);
+                    &rest[..instruction_length as usize]
+                } else {
+                    &[]
+                };
+                return (flags, instructions);
+            }
+        }
+    }
+
+    /// Serialize the glyph with the given bounding box
+    /// (`[x_min, y_min, x_max, y_max]`) and components moved to `offsets`.
+    /// Components positioned by matching points are left alone.
+    pub(crate) fn encode(&self, bounds: [i16; 4], offsets: &[(i16, i16)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_i16(&mut buf, -1);
+        for &bound in &bounds {
+            push_i16(&mut buf, bound);
+        }
+
+        let mut components = self.components;
+        let mut idx = 0;
+        loop {
+            let (rest, header) = CompositeComponentHeader::parse(components);
+            let flags = header.flags;
+            let args_size = if flags.contains(CompositeFlags::ARG_1_AND_2_ARE_WORDS) { 4 } else { 2 };
+            let transform_size = if flags.contains(CompositeFlags::WE_HAVE_A_SCALE) {
+                2
+            } else if flags.contains(CompositeFlags::WE_HAVE_AN_X_AND_Y_SCALE) {
+                4
+            } else if flags.contains(CompositeFlags::WE_HAVE_A_TWO_BY_TWO) {
+                8
+            } else {
+                0
+            };
+            let (args, rest) = rest.split_at(args_size);
+            let (transform, rest) = rest.split_at(transform_size);
+
+            match offsets.get(idx) {
+                Some(&(x, y)) if flags.contains(CompositeFlags::ARGS_ARE_XY_VALUES) => {
+                    push_i16(&mut buf, (flags | CompositeFlags::ARG_1_AND_2_ARE_WORDS).bits() as i16);
+                    push_i16(&mut buf, header.glyph_index as i16);
+                    push_i16(&mut buf, x);
+                    push_i16(&mut buf, y);
+                },
+                _ => {
+                    push_i16(&mut buf, flags.bits() as i16);
+                    push_i16(&mut buf, header.glyph_index as i16);
+                    buf.extend_from_slice(args);
+                },
+            }
+            buf.extend_from_slice(transform);
+
+            components = rest;
+            idx += 1;
+            if !flags.contains(CompositeFlags::MORE_COMPONENTS) {
+                if flags.contains(CompositeFlags::WE_HAVE_INSTRUCTIONS) {
+                    let (_, instruction_length) = u16::parse(components);
+                    buf.extend_from_slice(&components[..2 + instruction_length as usize]);
+                }
+                break;
+            }
+        }
+        buf
+    }
 }
 
 #[derive(Parse)]
@@ -0,0 +1,420 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::name::Name;
+use tables::layout::{resolve_extension, LayoutInfo, ClassDef, Coverage, LayoutTable, SequenceContext};
+use std::ops::Deref;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/gpos
+
+#[derive(Debug, Parse, Clone)]
+pub struct GPOS<'a>(LayoutTable<'a>);
+
+impl<'a> PrimaryTable for GPOS<'a> {
+    fn tag() -> TableTag {
+        TableTag::GlyphPositioning
+    }
+}
+
+impl<'a> Deref for GPOS<'a> {
+    type Target = LayoutTable<'a>;
+
+    fn deref(&self) -> &LayoutTable<'a> {
+        &self.0
+    }
+}
+
+impl<'a> GPOS<'a> {
+    /// Everything in the table, with feature names looked up in `names`
+    pub fn info(&self, names: Option<&Name<'a>>) -> Option<LayoutInfo> {
+        self.0.info(9, names)
+    }
+}
+
+/// A single GPOS subtable, with extension subtables already resolved
+#[derive(Debug)]
+pub enum PosSubtable<'a> {
+    Single(SinglePos<'a>),
+    Pair(PairPos<'a>),
+    Cursive(CursivePos<'a>),
+    MarkToBase(MarkAttachPos<'a>),
+    MarkToLigature(MarkAttachPos<'a>),
+    MarkToMark(MarkAttachPos<'a>),
+    Context(SequenceContext<'a>),
+    ChainContext(SequenceContext<'a>),
+}
+
+impl<'a> PosSubtable<'a> {
+    pub(crate) fn new(lookup_type: u16, subtable: BufView<'a, u8>) -> Option<PosSubtable<'a>> {
+        let subtable = match lookup_type {
+            1 => PosSubtable::Single(SinglePos(subtable)),
+            2 => PosSubtable::Pair(PairPos(subtable)),
+            3 => PosSubtable::Cursive(CursivePos(subtable)),
+            4 => PosSubtable::MarkToBase(subtable.at_offset(0)),
+            5 => PosSubtable::MarkToLigature(subtable.at_offset(0)),
+            6 => PosSubtable::MarkToMark(subtable.at_offset(0)),
+            7 => PosSubtable::Context(SequenceContext::new(subtable, false)),
+            8 => PosSubtable::ChainContext(SequenceContext::new(subtable, true)),
+            9 => {
+                let (lookup_type, subtable) = resolve_extension(&subtable);
+                // Extensions can't point at other extensions
+                if lookup_type == 9 {
+                    return None;
+                }
+                return PosSubtable::new(lookup_type, subtable);
+            },
+            _ => return None,
+        };
+        Some(subtable)
+    }
+}
+
+bitflags! {
+    #[derive(Parse)]
+    pub struct ValueFormat: u16 {
+        const X_PLACEMENT = 0x0001;
+        const Y_PLACEMENT = 0x0002;
+        const X_ADVANCE = 0x0004;
+        const Y_ADVANCE = 0x0008;
+        const X_PLACEMENT_DEVICE = 0x0010;
+        const Y_PLACEMENT_DEVICE = 0x0020;
+        const X_ADVANCE_DEVICE = 0x0040;
+        const Y_ADVANCE_DEVICE = 0x0080;
+        const RESERVED = 0xFF00;
+    }
+}
+
+impl ValueFormat {
+    /// Size of a value record with this format, in bytes
+    pub fn record_size(&self) -> usize {
+        (self.bits() & 0xFF).count_ones() as usize * i16::approx_file_size()
+    }
+}
+
+/// Adjustments to a glyph's position, in font units.
+///
+/// Device tables are ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValueRecord {
+    pub x_placement: i16,
+    pub y_placement: i16,
+    pub x_advance: i16,
+    pub y_advance: i16,
+}
+
+impl ValueRecord {
+    pub fn parse_with_format(buf: &[u8], format: ValueFormat) -> ValueRecord {
+        let mut record = ValueRecord::default();
+        let mut offset = 0;
+        {
+            let mut read_if = |flag: ValueFormat| -> i16 {
+                if format.contains(flag) {
+                    let val = i16::parse(&buf[offset..]).1;
+                    offset += 2;
+                    val
+                } else {
+                    0
+                }
+            };
+            record.x_placement = read_if(ValueFormat::X_PLACEMENT);
+            record.y_placement = read_if(ValueFormat::Y_PLACEMENT);
+            record.x_advance = read_if(ValueFormat::X_ADVANCE);
+            record.y_advance = read_if(ValueFormat::Y_ADVANCE);
+        }
+        record
+    }
+}
+
+#[derive(Debug, Parse)]
+struct SinglePosHeader<'a> {
+    format: u16,
+    coverage_offset: u16,
+    value_format: ValueFormat,
+    rest: BufView<'a, u8>,
+}
+
+#[derive(Debug)]
+pub struct SinglePos<'a>(BufView<'a, u8>);
+
+impl<'a> SinglePos<'a> {
+    pub fn adjustment(&self, glyph_id: u16) -> Option<ValueRecord> {
+        let header: SinglePosHeader = self.0.at_offset(0);
+        let coverage: Coverage = self.0.at_offset(header.coverage_offset as usize);
+        let idx = coverage.index(glyph_id)?;
+        let record_buf = match header.format {
+            1 => header.rest.0,
+            2 => {
+                let value_count: u16 = header.rest.at_offset(0);
+                if idx >= value_count {
+                    return None;
+                }
+                &header.rest.0[(2 + idx as usize * header.value_format.record_size())..]
+            },
+            _ => return None,
+        };
+        Some(ValueRecord::parse_with_format(record_buf, header.value_format))
+    }
+}
+
+#[derive(Debug, Parse)]
+struct PairPosHeader<'a> {
+    format: u16,
+    coverage_offset: u16,
+    value_format1: ValueFormat,
+    value_format2: ValueFormat,
+    rest: BufView<'a, u8>,
+}
+
+#[derive(Debug)]
+pub struct PairPos<'a>(BufView<'a, u8>);
+
+impl<'a> PairPos<'a> {
+    pub fn coverage(&self) -> Coverage<'a> {
+        let header: PairPosHeader = self.0.at_offset(0);
+        self.0.at_offset(header.coverage_offset as usize)
+    }
+
+    /// Adjustments for the first and second glyph of the pair, and whether the
+    /// second glyph's value format is non-empty.
+    pub fn adjustment(&self, first: u16, second: u16) -> Option<(ValueRecord, ValueRecord, bool)> {
+        let header: PairPosHeader = self.0.at_offset(0);
+        let idx = self.coverage().index(first)?;
+        let vf1 = header.value_format1;
+        let vf2 = header.value_format2;
+        let has_second = vf2.record_size() > 0;
+        match header.format {
+            1 => {
+                let pair_set_count: u16 = header.rest.at_offset(0);
+                if idx >= pair_set_count {
+                    return None;
+                }
+                let pair_set_offset: u16 = header.rest.at_offset(2 + 2 * idx as usize);
+                let pair_set: BufView<u8> = self.0.at_offset(pair_set_offset as usize);
+                let pair_value_count: u16 = pair_set.at_offset(0);
+                let record_size = 2 + vf1.record_size() + vf2.record_size();
+
+                // Records are ordered by the second glyph
+                let (mut lo, mut hi) = (0, pair_value_count as usize);
+                while lo < hi {
+                    let mid = (lo + hi) / 2;
+                    let record_start = 2 + mid * record_size;
+                    let second_glyph: u16 = pair_set.at_offset(record_start);
+                    if second_glyph < second {
+                        lo = mid + 1;
+                    } else if second_glyph > second {
+                        hi = mid;
+                    } else {
+                        let values = &pair_set.0[(record_start + 2)..];
+                        let value1 = ValueRecord::parse_with_format(values, vf1);
+                        let value2 = ValueRecord::parse_with_format(&values[vf1.record_size()..], vf2);
+                        return Some((value1, value2, has_second));
+                    }
+                }
+                None
+            },
+            2 => {
+                let class_def1: ClassDef = self.0.at_offset(header.rest.at_offset::<u16>(0) as usize);
+                let class_def2: ClassDef = self.0.at_offset(header.rest.at_offset::<u16>(2) as usize);
+                let class1_count: u16 = header.rest.at_offset(4);
+                let class2_count: u16 = header.rest.at_offset(6);
+                let class1 = class_def1.class(first);
+                let class2 = class_def2.class(second);
+                if class1 >= class1_count || class2 >= class2_count {
+                    return None;
+                }
+                let record_size = vf1.record_size() + vf2.record_size();
+                let record_idx = class1 as usize * class2_count as usize + class2 as usize;
+                let values = &header.rest.0[(8 + record_idx * record_size)..];
+                let value1 = ValueRecord::parse_with_format(values, vf1);
+                let value2 = ValueRecord::parse_with_format(&values[vf1.record_size()..], vf2);
+                Some((value1, value2, has_second))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// An attachment point, in font units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub x: i16,
+    pub y: i16,
+}
+
+/// All three anchor formats start with the coordinates. Format 2's contour
+/// point and format 3's device tables are ignored.
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct AnchorHeader {
+    format: u16,
+    x_coordinate: i16,
+    y_coordinate: i16,
+}
+
+fn anchor_at(table: &BufView<u8>, offset: u16) -> Option<Anchor> {
+    if offset == 0 {
+        return None;
+    }
+    let header: AnchorHeader = table.at_offset(offset as usize);
+    Some(Anchor {
+        x: header.x_coordinate,
+        y: header.y_coordinate,
+    })
+}
+
+#[derive(Debug, Parse)]
+struct EntryExitRecord {
+    entry_anchor_offset: u16,
+    exit_anchor_offset: u16,
+}
+
+#[derive(Debug)]
+pub struct CursivePos<'a>(BufView<'a, u8>);
+
+impl<'a> CursivePos<'a> {
+    /// The entry and exit anchors of a glyph
+    pub fn entry_exit(&self, glyph_id: u16) -> Option<(Option<Anchor>, Option<Anchor>)> {
+        let coverage_offset: u16 = self.0.at_offset(2);
+        let coverage: Coverage = self.0.at_offset(coverage_offset as usize);
+        let idx = coverage.index(glyph_id)?;
+        let count: u16 = self.0.at_offset(4);
+        if idx >= count {
+            return None;
+        }
+        let record: EntryExitRecord = self.0.at_offset(6 + idx as usize * EntryExitRecord::approx_file_size());
+        Some((anchor_at(&self.0, record.entry_anchor_offset),
+              anchor_at(&self.0, record.exit_anchor_offset)))
+    }
+}
+
+/// Mark-to-base, mark-to-ligature and mark-to-mark attachment all share
+/// the same layout.
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct MarkAttachPos<'a> {
+    table: BufView<'a, u8>,
+    format: u16,
+    mark_coverage_offset: u16,
+    /// Base, ligature, or mark2 coverage
+    target_coverage_offset: u16,
+    mark_class_count: u16,
+    mark_array_offset: u16,
+    /// Base array, ligature array, or mark2 array
+    target_array_offset: u16,
+}
+
+#[derive(Debug, Parse)]
+struct MarkRecord {
+    mark_class: u16,
+    mark_anchor_offset: u16,
+}
+
+impl<'a> MarkAttachPos<'a> {
+    pub fn mark_coverage(&self) -> Coverage<'a> {
+        self.table.at_offset(self.mark_coverage_offset as usize)
+    }
+
+    pub fn target_coverage(&self) -> Coverage<'a> {
+        self.table.at_offset(self.target_coverage_offset as usize)
+    }
+
+    /// The class and anchor of a mark glyph
+    pub fn mark(&self, mark_glyph: u16) -> Option<(u16, Anchor)> {
+        let idx = self.mark_coverage().index(mark_glyph)?;
+        let mark_array: BufView<u8> = self.table.at_offset(self.mark_array_offset as usize);
+        let mark_count: u16 = mark_array.at_offset(0);
+        if idx >= mark_count {
+            return None;
+        }
+        let record: MarkRecord = mark_array.at_offset(2 + idx as usize * MarkRecord::approx_file_size());
+        let anchor = anchor_at(&mark_array, record.mark_anchor_offset)?;
+        Some((record.mark_class, anchor))
+    }
+
+    /// The anchor on a base (or mark2) glyph for a mark class
+    pub fn target_anchor(&self, target_glyph: u16, mark_class: u16) -> Option<Anchor> {
+        if mark_class >= self.mark_class_count {
+            return None;
+        }
+        let idx = self.target_coverage().index(target_glyph)?;
+        let array: BufView<u8> = self.table.at_offset(self.target_array_offset as usize);
+        let count: u16 = array.at_offset(0);
+        if idx >= count {
+            return None;
+        }
+        let record_start = 2 + 2 * (idx as usize * self.mark_class_count as usize + mark_class as usize);
+        anchor_at(&array, array.at_offset(record_start))
+    }
+
+    /// The anchor on a ligature glyph for a mark class, on the given component.
+    ///
+    /// Also returns the number of components of the ligature.
+    pub fn ligature_anchor(&self, lig_glyph: u16, component: u16, mark_class: u16) -> Option<(Anchor, u16)> {
+        if mark_class >= self.mark_class_count {
+            return None;
+        }
+        let idx = self.target_coverage().index(lig_glyph)?;
+        let lig_array: BufView<u8> = self.table.at_offset(self.target_array_offset as usize);
+        let count: u16 = lig_array.at_offset(0);
+        if idx >= count {
+            return None;
+        }
+        let attach_offset: u16 = lig_array.at_offset(2 + 2 * idx as usize);
+        let lig_attach: BufView<u8> = lig_array.at_offset(attach_offset as usize);
+        let component_count: u16 = lig_attach.at_offset(0);
+        if component_count == 0 {
+            return None;
+        }
+        let component = component.min(component_count - 1);
+        let record_start = 2 + 2 * (component as usize * self.mark_class_count as usize + mark_class as usize);
+        let anchor = anchor_at(&lig_attach, lig_attach.at_offset(record_start))?;
+        Some((anchor, component_count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::*;
+    use tables::layout::{Tag, DEFAULT_LANGUAGE};
+    use test_utils::{load_font_buf, ROBOTO};
+
+    #[test]
+    fn value_format_size() {
+        let format = ValueFormat::X_PLACEMENT | ValueFormat::X_ADVANCE;
+        assert_eq!(format.record_size(), 4);
+
+        let buf: &[u8] = &[0xFF, 0xF6, 0x00, 0x14];
+        let record = ValueRecord::parse_with_format(buf, format);
+        assert_eq!(record, ValueRecord { x_placement: -10, y_placement: 0, x_advance: 20, y_advance: 0 });
+    }
+
+    #[test]
+    fn kern_pair() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let gpos: GPOS = font.get_table().unwrap();
+
+        let a = font.get_glyph_id('A').unwrap() as u16;
+        let v = font.get_glyph_id('V').unwrap() as u16;
+
+        let lang_sys = gpos.lang_sys(Tag::new(b"latn"), DEFAULT_LANGUAGE).unwrap();
+        let kern = gpos.find_feature(&lang_sys, Tag::new(b"kern")).unwrap();
+        let lookups = gpos.lookup_list().unwrap();
+
+        let adjustment = kern.lookup_indices()
+            .filter_map(|idx| lookups.lookup(idx))
+            .flat_map(|lookup| {
+                let lookup_type = lookup.lookup_type;
+                lookup.subtables()
+                    .filter_map(move |subtable| PosSubtable::new(lookup_type, subtable))
+            })
+            .filter_map(|subtable| match subtable {
+                PosSubtable::Pair(pair) => pair.adjustment(a, v),
+                _ => None,
+            })
+            .next()
+            .expect("Roboto should kern 'AV'");
+        // 'A' followed by 'V' is pulled together
+        assert!((adjustment.0).x_advance < 0);
+    }
+}
@@ -0,0 +1,71 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// Graphite's feature table, not to be confused with AAT's `feat`
+// https://github.com/silnrsi/graphite/blob/master/doc/GTF.txt
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct GraphiteFeat<'a> {
+    table: BufView<'a, u8>,
+    version: u32,
+    num_feat: u16,
+    reserved1: u16,
+    reserved2: u32,
+    features: BufView<'a, u8>,
+}
+
+impl<'a> PrimaryTable for GraphiteFeat<'a> {
+    fn tag() -> TableTag {
+        TableTag::Feat
+    }
+}
+
+/// A feature the font's rules can check, and the values it can be set to
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphiteFeature {
+    /// Usually four characters, like an OpenType tag
+    pub id: u32,
+    pub label_name_id: u16,
+    /// The first setting's value
+    pub default_value: i16,
+    /// (value, label name id)
+    pub settings: Vec<(i16, u16)>,
+}
+
+impl<'a> GraphiteFeat<'a> {
+    /// The features, in the order rules refer to them
+    pub fn features(&self) -> Vec<GraphiteFeature> {
+        // Version 2 widened the id to 32 bits
+        let long_ids = self.version >= 0x0002_0000;
+        let record_size = if long_ids { 16 } else { 12 };
+        (0..self.num_feat as usize)
+            .map(|idx| {
+                let record: BufView<u8> = self.features.at_offset(idx * record_size);
+                let (id, rest) = if long_ids {
+                    (record.at_offset::<u32>(0), 4)
+                } else {
+                    (record.at_offset::<u16>(0) as u32, 2)
+                };
+                let num_settings: u16 = record.at_offset(rest);
+                let rest = if long_ids { rest + 4 } else { rest + 2 };
+                let settings_offset: u32 = record.at_offset(rest);
+                // Skip the flags
+                let label_name_id: u16 = record.at_offset(rest + 6);
+
+                let settings: Vec<(i16, u16)> = (0..num_settings as usize)
+                    .map(|setting| {
+                        let offset = settings_offset as usize + setting * 4;
+                        (self.table.at_offset(offset), self.table.at_offset(offset + 2))
+                    })
+                    .collect();
+                GraphiteFeature {
+                    id,
+                    label_name_id,
+                    default_value: settings.first().map(|setting| setting.0).unwrap_or(0),
+                    settings,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,272 @@
+use parse::{BufView, DynArr, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::name::Name;
+use tables::layout::{resolve_extension, LayoutInfo, Coverage, LayoutTable, SequenceContext};
+use std::ops::Deref;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/gsub
+
+#[derive(Debug, Parse, Clone)]
+pub struct GSUB<'a>(LayoutTable<'a>);
+
+impl<'a> PrimaryTable for GSUB<'a> {
+    fn tag() -> TableTag {
+        TableTag::GlyphSubstitution
+    }
+}
+
+impl<'a> Deref for GSUB<'a> {
+    type Target = LayoutTable<'a>;
+
+    fn deref(&self) -> &LayoutTable<'a> {
+        &self.0
+    }
+}
+
+impl<'a> GSUB<'a> {
+    /// Everything in the table, with feature names looked up in `names`
+    pub fn info(&self, names: Option<&Name<'a>>) -> Option<LayoutInfo> {
+        self.0.info(7, names)
+    }
+}
+
+/// A single GSUB subtable, with extension subtables already resolved
+#[derive(Debug)]
+pub enum SubstSubtable<'a> {
+    Single(SingleSubst<'a>),
+    Multiple(MultipleSubst<'a>),
+    Alternate(AlternateSubst<'a>),
+    Ligature(LigatureSubst<'a>),
+    Context(SequenceContext<'a>),
+    ChainContext(SequenceContext<'a>),
+    ReverseChainSingle(ReverseChainSingleSubst<'a>),
+}
+
+impl<'a> SubstSubtable<'a> {
+    pub(crate) fn new(lookup_type: u16, subtable: BufView<'a, u8>) -> Option<SubstSubtable<'a>> {
+        let subtable = match lookup_type {
+            1 => SubstSubtable::Single(subtable.at_offset(0)),
+            2 => SubstSubtable::Multiple(subtable.at_offset(0)),
+            3 => SubstSubtable::Alternate(subtable.at_offset(0)),
+            4 => SubstSubtable::Ligature(subtable.at_offset(0)),
+            5 => SubstSubtable::Context(SequenceContext::new(subtable, false)),
+            6 => SubstSubtable::ChainContext(SequenceContext::new(subtable, true)),
+            7 => {
+                let (lookup_type, subtable) = resolve_extension(&subtable);
+                // Extensions can't point at other extensions
+                if lookup_type == 7 {
+                    return None;
+                }
+                return SubstSubtable::new(lookup_type, subtable);
+            },
+            8 => SubstSubtable::ReverseChainSingle(subtable.at_offset(0)),
+            _ => return None,
+        };
+        Some(subtable)
+    }
+}
+
+/// Header shared by the subtables that start with a format and a coverage
+#[derive(Debug, Parse)]
+struct CoveredHeader<'a> {
+    table: BufView<'a, u8>,
+    format: u16,
+    coverage_offset: u16,
+    rest: BufView<'a, u8>,
+}
+
+impl<'a> CoveredHeader<'a> {
+    fn coverage(&self) -> Coverage<'a> {
+        self.table.at_offset(self.coverage_offset as usize)
+    }
+
+    /// The `idx`th offset of the offset array that follows a count at the
+    /// start of `rest`
+    fn offset_array_at<T: Parse<'a>>(&self, idx: u16) -> Option<T> {
+        let count: u16 = self.rest.at_offset(0);
+        if idx >= count {
+            return None;
+        }
+        let offset: u16 = self.rest.at_offset(2 + 2 * idx as usize);
+        Some(self.table.at_offset(offset as usize))
+    }
+}
+
+#[derive(Debug, Parse)]
+pub struct SingleSubst<'a>(CoveredHeader<'a>);
+
+impl<'a> SingleSubst<'a> {
+    pub fn substitute(&self, glyph_id: u16) -> Option<u16> {
+        let header = &self.0;
+        let idx = header.coverage().index(glyph_id)?;
+        match header.format {
+            1 => {
+                // Addition is modulo 65536
+                let delta_glyph_id: i16 = header.rest.at_offset(0);
+                Some(glyph_id.wrapping_add(delta_glyph_id as u16))
+            },
+            2 => {
+                let glyph_count: u16 = header.rest.at_offset(0);
+                if idx >= glyph_count {
+                    return None;
+                }
+                Some(header.rest.at_offset(2 + 2 * idx as usize))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Parse)]
+pub struct MultipleSubst<'a>(CoveredHeader<'a>);
+
+impl<'a> MultipleSubst<'a> {
+    /// The glyphs to replace `glyph_id` with. May be empty, which deletes the glyph.
+    pub(crate) fn substitute(&self, glyph_id: u16) -> Option<DynArr<'a, u16>> {
+        let header = &self.0;
+        let idx = header.coverage().index(glyph_id)?;
+        let sequence: GlyphSequence = header.offset_array_at(idx)?;
+        Some(sequence.glyphs)
+    }
+}
+
+/// Used for both `Sequence` and `AlternateSet` tables
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct GlyphSequence<'a> {
+    glyph_count: u16,
+    #[arr_len_src = "glyph_count"]
+    glyphs: DynArr<'a, u16>,
+}
+
+#[derive(Debug, Parse)]
+pub struct AlternateSubst<'a>(CoveredHeader<'a>);
+
+impl<'a> AlternateSubst<'a> {
+    pub(crate) fn alternates(&self, glyph_id: u16) -> Option<DynArr<'a, u16>> {
+        let header = &self.0;
+        let idx = header.coverage().index(glyph_id)?;
+        let alternates: GlyphSequence = header.offset_array_at(idx)?;
+        Some(alternates.glyphs)
+    }
+}
+
+#[derive(Debug, Parse)]
+pub struct LigatureSubst<'a>(CoveredHeader<'a>);
+
+impl<'a> LigatureSubst<'a> {
+    /// Ligatures starting with `glyph_id`, in order of preference
+    pub fn ligatures(&self, glyph_id: u16) -> Vec<Ligature<'a>> {
+        let header = &self.0;
+        let set: LigatureSet = match header.coverage().index(glyph_id)
+            .and_then(|idx| header.offset_array_at(idx)) {
+            Some(set) => set,
+            None => return Vec::new(),
+        };
+        let table = set.table;
+        set.ligature_offsets
+            .map(|offset| table.at_offset(offset as usize))
+            .collect()
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct LigatureSet<'a> {
+    table: BufView<'a, u8>,
+    ligature_count: u16,
+    #[arr_len_src = "ligature_count"]
+    ligature_offsets: DynArr<'a, u16>,
+}
+
+#[derive(Debug, Parse)]
+pub struct Ligature<'a> {
+    pub ligature_glyph: u16,
+    component_count: u16,
+    /// Doesn't include the first component
+    rest: BufView<'a, u8>,
+}
+
+impl<'a> Ligature<'a> {
+    /// Every component after the first
+    pub(crate) fn components(&self) -> DynArr<'a, u16> {
+        let len = (self.component_count as usize).saturating_sub(1) * u16::approx_file_size();
+        DynArr::parse(&self.rest.0[..len]).1
+    }
+}
+
+#[derive(Debug, Parse)]
+pub struct ReverseChainSingleSubst<'a>(CoveredHeader<'a>);
+
+impl<'a> ReverseChainSingleSubst<'a> {
+    pub fn coverage(&self) -> Coverage<'a> {
+        self.0.coverage()
+    }
+
+    fn coverages_at(&self, offset: usize) -> (usize, Vec<Coverage<'a>>) {
+        let rest = &self.0.rest;
+        let count: u16 = rest.at_offset(offset);
+        let coverages = (0..count as usize)
+            .map(|idx| rest.at_offset::<u16>(offset + 2 + 2 * idx))
+            .map(|cov_offset| self.0.table.at_offset(cov_offset as usize))
+            .collect();
+        (offset + 2 + 2 * count as usize, coverages)
+    }
+
+    /// Closest glyph first
+    pub fn backtrack_coverages(&self) -> Vec<Coverage<'a>> {
+        self.coverages_at(0).1
+    }
+
+    pub fn lookahead_coverages(&self) -> Vec<Coverage<'a>> {
+        let (next, _) = self.coverages_at(0);
+        self.coverages_at(next).1
+    }
+
+    pub fn substitute(&self, coverage_idx: u16) -> Option<u16> {
+        let (next, _) = self.coverages_at(0);
+        let (next, _) = self.coverages_at(next);
+        let glyph_count: u16 = self.0.rest.at_offset(next);
+        if coverage_idx >= glyph_count {
+            return None;
+        }
+        Some(self.0.rest.at_offset(next + 2 + 2 * coverage_idx as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::*;
+    use tables::layout::{Tag, DEFAULT_LANGUAGE};
+    use test_utils::{load_font_buf, ROBOTO};
+
+    #[test]
+    fn ligature_fi() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let gsub: GSUB = font.get_table().unwrap();
+
+        let f = font.get_glyph_id('f').unwrap() as u16;
+        let i = font.get_glyph_id('i').unwrap() as u16;
+
+        let lang_sys = gsub.lang_sys(Tag::new(b"latn"), DEFAULT_LANGUAGE).unwrap();
+        let liga = gsub.find_feature(&lang_sys, Tag::new(b"liga")).unwrap();
+        let lookups = gsub.lookup_list().unwrap();
+
+        let found = liga.lookup_indices()
+            .filter_map(|idx| lookups.lookup(idx))
+            .flat_map(|lookup| {
+                let lookup_type = lookup.lookup_type;
+                lookup.subtables()
+                    .filter_map(move |subtable| SubstSubtable::new(lookup_type, subtable))
+            })
+            .any(|subtable| match subtable {
+                SubstSubtable::Ligature(ligs) => ligs.ligatures(f)
+                    .iter()
+                    .any(|lig| lig.components().collect::<Vec<_>>() == vec![i]),
+                _ => false,
+            });
+        assert!(found);
+    }
+}
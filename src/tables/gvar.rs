@@ -0,0 +1,504 @@
+use parse::{BufView, Parse};
+use parse::primitives::F2Dot14;
+use tables::{PrimaryTable, TableTag};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/gvar
+// https://docs.microsoft.com/en-us/typography/opentype/spec/otvarcommonformats
+
+/// Per-glyph deltas for the outline points at each region of the design
+/// space
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Gvar<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    axis_count: u16,
+    shared_tuple_count: u16,
+    shared_tuples_offset: u32,
+    glyph_count: u16,
+    flags: u16,
+    glyph_variation_data_array_offset: u32,
+}
+
+impl<'a> PrimaryTable for Gvar<'a> {
+    fn tag() -> TableTag {
+        TableTag::GlyphVariation
+    }
+}
+
+/// The offsets array holds `Offset32`s instead of halved `Offset16`s
+const LONG_OFFSETS: u16 = 0x0001;
+
+// Tuple variation count flags
+const SHARED_POINT_NUMBERS: u16 = 0x8000;
+const COUNT_MASK: u16 = 0x0FFF;
+
+// Tuple index flags
+const EMBEDDED_PEAK_TUPLE: u16 = 0x8000;
+const INTERMEDIATE_REGION: u16 = 0x4000;
+const PRIVATE_POINT_NUMBERS: u16 = 0x2000;
+const TUPLE_INDEX_MASK: u16 = 0x0FFF;
+
+// Packed point number flags
+const POINTS_ARE_WORDS: u8 = 0x80;
+const POINT_RUN_COUNT_MASK: u8 = 0x7F;
+
+// Packed delta flags
+const DELTAS_ARE_ZERO: u8 = 0x80;
+const DELTAS_ARE_WORDS: u8 = 0x40;
+const DELTA_RUN_COUNT_MASK: u8 = 0x3F;
+
+#[derive(Debug, Parse)]
+struct TupleVariationHeader {
+    variation_data_size: u16,
+    tuple_index: u16,
+}
+
+impl<'a> Gvar<'a> {
+    fn glyph_data(&self, glyph_id: u32) -> Option<&'a [u8]> {
+        if glyph_id >= self.glyph_count as u32 {
+            return None;
+        }
+        let idx = glyph_id as usize;
+        // Offsets start right after the header
+        let (start, end) = if self.flags & LONG_OFFSETS != 0 {
+            let start: u32 = self.table.checked_at_offset(20 + idx * 4)?;
+            let end: u32 = self.table.checked_at_offset(20 + idx * 4 + 4)?;
+            (start as usize, end as usize)
+        } else {
+            let start: u16 = self.table.checked_at_offset(20 + idx * 2)?;
+            let end: u16 = self.table.checked_at_offset(20 + idx * 2 + 2)?;
+            (start as usize * 2, end as usize * 2)
+        };
+        let base = self.glyph_variation_data_array_offset as usize;
+        if start >= end {
+            return None;
+        }
+        self.table.0.get(base + start..base + end)
+    }
+
+    /// `None` if the tuple is cut off
+    fn tuple(&self, buf: &'a [u8]) -> Option<(&'a [u8], Vec<f32>)> {
+        let size = self.axis_count as usize * F2Dot14::approx_file_size();
+        let tuple = buf.get(..size)?
+            .chunks(F2Dot14::approx_file_size())
+            .map(|coord| F2Dot14::parse(coord).1 .0)
+            .collect();
+        Some((&buf[size..], tuple))
+    }
+
+    fn shared_tuple(&self, idx: u16) -> Option<Vec<f32>> {
+        let offset = self.shared_tuples_offset as usize + idx as usize * self.axis_count as usize * 2;
+        self.tuple(self.table.0.get(offset..)?).map(|(_, tuple)| tuple)
+    }
+
+    /// The deltas for each of a glyph's `points` at the normalized `coords`.
+    ///
+    /// `points` are the glyph's outline points (or component offsets for
+    /// composite glyphs) followed by the four phantom points. `contour_ends`
+    /// is the last point index of each contour; points in a contour that a
+    /// region doesn't mention are inferred from their neighbours. Points
+    /// outside of any contour get no delta from such regions.
+    ///
+    /// Returns `None` if the glyph has no variations, or its data is
+    /// malformed.
+    pub fn glyph_deltas(&self, glyph_id: u32, coords: &[f32], points: &[(f32, f32)],
+                        contour_ends: &[usize]) -> Option<Vec<(f32, f32)>> {
+        let data = self.glyph_data(glyph_id)?;
+        if data.len() < 4 {
+            return None;
+        }
+        let (buf, tuple_count) = u16::parse(data);
+        let (mut headers, data_offset) = u16::parse(buf);
+        let mut serialized = data.get(data_offset as usize..)?;
+
+        let shared_points = if tuple_count & SHARED_POINT_NUMBERS != 0 {
+            let (rest, points) = unpack_points(serialized)?;
+            serialized = rest;
+            points
+        } else {
+            None
+        };
+
+        let mut deltas = vec![(0., 0.); points.len()];
+        for _ in 0..(tuple_count & COUNT_MASK) {
+            if headers.len() < TupleVariationHeader::approx_file_size() {
+                return None;
+            }
+            let (rest, header) = TupleVariationHeader::parse(headers);
+            headers = rest;
+            let peak = if header.tuple_index & EMBEDDED_PEAK_TUPLE != 0 {
+                let (rest, peak) = self.tuple(headers)?;
+                headers = rest;
+                peak
+            } else {
+                self.shared_tuple(header.tuple_index & TUPLE_INDEX_MASK)?
+            };
+            let intermediate = if header.tuple_index & INTERMEDIATE_REGION != 0 {
+                let (rest, start) = self.tuple(headers)?;
+                let (rest, end) = self.tuple(rest)?;
+                headers = rest;
+                Some((start, end))
+            } else {
+                None
+            };
+
+            let size = header.variation_data_size as usize;
+            let tuple_data = &serialized[..size.min(serialized.len())];
+            serialized = &serialized[size.min(serialized.len())..];
+
+            let scalar = region_scalar(coords, &peak, intermediate.as_ref());
+            if scalar == 0. {
+                continue;
+            }
+
+            let (tuple_data, point_numbers) = if header.tuple_index & PRIVATE_POINT_NUMBERS != 0 {
+                unpack_points(tuple_data)?
+            } else {
+                (tuple_data, shared_points.clone())
+            };
+            let count = point_numbers.as_ref().map_or(points.len(), |points| points.len());
+            let (tuple_data, xs) = unpack_deltas(tuple_data, count);
+            let (_, ys) = unpack_deltas(tuple_data, count);
+
+            let tuple_deltas = match point_numbers {
+                None => xs.into_iter().zip(ys).map(Some).collect(),
+                Some(numbers) => {
+                    let mut tuple_deltas = vec![None; points.len()];
+                    for (&point, delta) in numbers.iter().zip(xs.into_iter().zip(ys)) {
+                        if let Some(slot) = tuple_deltas.get_mut(point as usize) {
+                            *slot = Some(delta);
+                        }
+                    }
+                    infer_deltas(&mut tuple_deltas, points, contour_ends);
+                    tuple_deltas
+                },
+            };
+
+            for (total, delta) in deltas.iter_mut().zip(tuple_deltas) {
+                if let Some((dx, dy)) = delta {
+                    total.0 += dx * scalar;
+                    total.1 += dy * scalar;
+                }
+            }
+        }
+
+        Some(deltas)
+    }
+}
+
+/// How much a region applies at `coords`, from 0 to 1
+pub(crate) fn region_scalar(coords: &[f32], peak: &[f32], intermediate: Option<&(Vec<f32>, Vec<f32>)>) -> f32 {
+    let mut scalar = 1.;
+    for (idx, &peak) in peak.iter().enumerate() {
+        let coord = coords.get(idx).cloned().unwrap_or(0.);
+        if peak == 0. || coord == peak {
+            continue;
+        }
+        if coord == 0. {
+            return 0.;
+        }
+        let (start, end) = match intermediate {
+            Some(&(ref start, ref end)) => (start[idx], end[idx]),
+            None if peak > 0. => (0., peak),
+            None => (peak, 0.),
+        };
+        // Invalid regions are ignored
+        if start > peak || peak > end || (start < 0. && end > 0.) {
+            continue;
+        }
+        if coord <= start || coord >= end {
+            return 0.;
+        }
+        scalar *= if coord < peak {
+            (coord - start) / (peak - start)
+        } else {
+            (end - coord) / (end - peak)
+        };
+    }
+    scalar
+}
+
+/// Packed point numbers. The points are `None` for every point, and the
+/// whole thing is `None` if the numbers are cut off.
+pub(crate) fn unpack_points(buf: &[u8]) -> Option<(&[u8], Option<Vec<u16>>)> {
+    let first = *buf.get(0)?;
+    let (mut buf, count) = if first == 0 {
+        return Some((&buf[1..], None));
+    } else if first & POINTS_ARE_WORDS != 0 {
+        let second = *buf.get(1)?;
+        (&buf[2..], ((first & POINT_RUN_COUNT_MASK) as usize) << 8 | second as usize)
+    } else {
+        (&buf[1..], first as usize)
+    };
+
+    let mut points = Vec::with_capacity(count);
+    let mut point: u16 = 0;
+    while points.len() < count {
+        let control = *buf.get(0)?;
+        buf = &buf[1..];
+        let run_count = (control & POINT_RUN_COUNT_MASK) as usize + 1;
+        for _ in 0..run_count.min(count - points.len()) {
+            let delta = if control & POINTS_ARE_WORDS != 0 {
+                let delta = u16::parse(buf.get(..2)?).1;
+                buf = &buf[2..];
+                delta
+            } else {
+                let delta = *buf.get(0)?;
+                buf = &buf[1..];
+                delta as u16
+            };
+            point = point.wrapping_add(delta);
+            points.push(point);
+        }
+    }
+    Some((buf, Some(points)))
+}
+
+/// `count` packed deltas
+pub(crate) fn unpack_deltas(buf: &[u8], count: usize) -> (&[u8], Vec<f32>) {
+    let mut buf = buf;
+    let mut deltas = Vec::with_capacity(count);
+    while deltas.len() < count && !buf.is_empty() {
+        let (rest, control) = u8::parse(buf);
+        buf = rest;
+        let run_count = (control & DELTA_RUN_COUNT_MASK) as usize + 1;
+        for _ in 0..run_count.min(count - deltas.len()) {
+            let delta = if control & DELTAS_ARE_ZERO != 0 {
+                0
+            } else if control & DELTAS_ARE_WORDS != 0 && buf.len() >= 2 {
+                let (rest, delta) = i16::parse(buf);
+                buf = rest;
+                delta
+            } else if control & DELTAS_ARE_WORDS == 0 && !buf.is_empty() {
+                let (rest, delta) = i8::parse(buf);
+                buf = rest;
+                delta as i16
+            } else {
+                break;
+            };
+            deltas.push(delta as f32);
+        }
+    }
+    // Truncated data means the rest are zero
+    deltas.resize(count, 0.);
+    (buf, deltas)
+}
+
+/// Interpolate deltas for the points of each contour a region didn't
+/// mention (IUP)
+fn infer_deltas(deltas: &mut [Option<(f32, f32)>], points: &[(f32, f32)], contour_ends: &[usize]) {
+    let mut start = 0;
+    for &end in contour_ends {
+        if end >= deltas.len() || end < start {
+            break;
+        }
+        infer_contour(&mut deltas[start..end + 1], &points[start..end + 1]);
+        start = end + 1;
+    }
+}
+
+fn infer_contour(deltas: &mut [Option<(f32, f32)>], points: &[(f32, f32)]) {
+    let touched: Vec<usize> = (0..deltas.len()).filter(|&idx| deltas[idx].is_some()).collect();
+    if touched.is_empty() || touched.len() == deltas.len() {
+        return;
+    }
+
+    let len = deltas.len();
+    for (idx, &prev) in touched.iter().enumerate() {
+        let next = touched[(idx + 1) % touched.len()];
+        let prev_delta = deltas[prev].unwrap();
+        let next_delta = deltas[next].unwrap();
+        // The untouched points between `prev` and `next`, wrapping around
+        let mut point = (prev + 1) % len;
+        while point != next {
+            let dx = infer_delta(points[point].0, points[prev].0, points[next].0, prev_delta.0, next_delta.0);
+            let dy = infer_delta(points[point].1, points[prev].1, points[next].1, prev_delta.1, next_delta.1);
+            deltas[point] = Some((dx, dy));
+            point = (point + 1) % len;
+        }
+    }
+}
+
+fn infer_delta(coord: f32, prev: f32, next: f32, prev_delta: f32, next_delta: f32) -> f32 {
+    if prev == next {
+        return if prev_delta == next_delta { prev_delta } else { 0. };
+    }
+    let (low, high, low_delta, high_delta) = if prev < next {
+        (prev, next, prev_delta, next_delta)
+    } else {
+        (next, prev, next_delta, prev_delta)
+    };
+    if coord <= low {
+        low_delta
+    } else if coord >= high {
+        high_delta
+    } else {
+        low_delta + (high_delta - low_delta) * (coord - low) / (high - low)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// A `gvar` over one axis with a single region at `peak` for `glyph_id`,
+    /// moving the listed points (or every point when `points` is `None`)
+    pub fn build_gvar(glyph_count: u16, glyph_id: u16, peak: i16,
+                      points: Option<&[u16]>, deltas: &[(i16, i16)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        push_u16(&mut data, 1);
+        push_u16(&mut data, 4 + 2 + 2 + 2);
+        let mut serialized = Vec::new();
+        match points {
+            None => serialized.push(0),
+            Some(points) => {
+                serialized.push(points.len() as u8);
+                serialized.push(POINTS_ARE_WORDS | (points.len() as u8 - 1));
+                let mut last = 0;
+                for &point in points {
+                    push_u16(&mut serialized, point - last);
+                    last = point;
+                }
+            },
+        }
+        for coord in 0..2 {
+            serialized.push(DELTAS_ARE_WORDS | (deltas.len() as u8 - 1));
+            for delta in deltas {
+                let val = if coord == 0 { delta.0 } else { delta.1 };
+                push_u16(&mut serialized, val as u16);
+            }
+        }
+        push_u16(&mut data, serialized.len() as u16);
+        push_u16(&mut data, EMBEDDED_PEAK_TUPLE | PRIVATE_POINT_NUMBERS);
+        push_u16(&mut data, peak as u16);
+        data.extend(serialized);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+
+        let mut gvar = Vec::new();
+        push_u16(&mut gvar, 1);
+        push_u16(&mut gvar, 0);
+        push_u16(&mut gvar, 1);
+        push_u16(&mut gvar, 0);
+        push_u32(&mut gvar, 0);
+        push_u16(&mut gvar, glyph_count);
+        push_u16(&mut gvar, LONG_OFFSETS);
+        push_u32(&mut gvar, 20 + (glyph_count as u32 + 1) * 4);
+        for glyph in 0..(glyph_count as u32 + 1) {
+            push_u32(&mut gvar, if glyph > glyph_id as u32 { data.len() as u32 } else { 0 });
+        }
+        gvar.extend(data);
+        gvar
+    }
+
+    #[test]
+    fn packed_points_and_deltas() {
+        let (rest, points) = unpack_points(&[3, 0x02, 1, 2, 3, 0xFF]).unwrap();
+        assert_eq!(points, Some(vec![1, 3, 6]));
+        assert_eq!(rest, &[0xFF]);
+        assert_eq!(unpack_points(&[0]), Some((&[][..], None)));
+        assert_eq!(unpack_points(&[3, 0x02, 1]), None);
+
+        let (_, deltas) = unpack_deltas(&[0x01, 0xFF, 0x05, 0x81, 0x40, 0x01, 0x00], 5);
+        assert_eq!(deltas, vec![-1., 5., 0., 0., 256.]);
+    }
+
+    #[test]
+    fn region_scalars() {
+        assert_eq!(region_scalar(&[0.5], &[1.], None), 0.5);
+        assert_eq!(region_scalar(&[-0.5], &[1.], None), 0.);
+        assert_eq!(region_scalar(&[0.], &[1.], None), 0.);
+        assert_eq!(region_scalar(&[0.5], &[0.], None), 1.);
+        let intermediate = (vec![0.25], vec![1.]);
+        assert_eq!(region_scalar(&[0.375], &[0.5], Some(&intermediate)), 0.5);
+        assert_eq!(region_scalar(&[0.75], &[0.5], Some(&intermediate)), 0.5);
+        assert_eq!(region_scalar(&[0.2], &[0.5], Some(&intermediate)), 0.);
+    }
+
+    #[test]
+    fn interpolate_untouched_points() {
+        // A square with the right side moved 100 units right
+        let points = [(0., 0.), (50., 0.), (100., 0.), (100., 100.), (0., 100.), (0., 0.), (0., 0.), (0., 0.), (0., 0.)];
+        let gvar = build_gvar(1, 0, 16384, Some(&[0, 2, 3, 4]), &[(0, 0), (100, 0), (100, 0), (0, 0)]);
+        let gvar = Gvar::parse(&gvar).1;
+        let deltas = gvar.glyph_deltas(0, &[0.5], &points, &[4]).unwrap();
+        assert_eq!(&deltas[..5], &[(0., 0.), (25., 0.), (50., 0.), (50., 0.), (0., 0.)]);
+        // Phantom points aren't in a contour
+        assert_eq!(deltas[5], (0., 0.));
+
+        assert!(gvar.glyph_deltas(1, &[0.5], &points, &[4]).is_none());
+    }
+
+    #[test]
+    fn malformed_data() {
+        let points = [(0., 0.); 5];
+        let buf = build_gvar(1, 0, 16384, None, &[(1, 1); 5]);
+        // Cut off anywhere, from the offsets array to the deltas
+        for len in 0..buf.len() {
+            let gvar = Gvar::parse(&buf[..len.max(20)]).1;
+            let _ = gvar.glyph_deltas(0, &[1.], &points, &[0]);
+        }
+        assert!(Gvar::parse(&buf[..24]).1.glyph_deltas(0, &[1.], &points, &[0]).is_none());
+
+        // The serialized data starts past the end of the glyph's data
+        let mut bad_offset = buf.clone();
+        bad_offset[30] = 0xFF;
+        assert!(Gvar::parse(&bad_offset).1.glyph_deltas(0, &[1.], &points, &[0]).is_none());
+
+        // A shared tuple that isn't there
+        let mut bad_tuple = buf.clone();
+        bad_tuple[34] &= !(EMBEDDED_PEAK_TUPLE >> 8) as u8;
+        bad_tuple[35] = 0xFF;
+        assert!(Gvar::parse(&bad_tuple).1.glyph_deltas(0, &[1.], &points, &[0]).is_none());
+    }
+
+    #[test]
+    fn varied_glyph_metrics() {
+        use font::{Font, GetTable};
+        use tables::fvar::Variation;
+        use tables::fvar::tests::build_fvar;
+        use tables::glyf::Description;
+        use tables::hmtx::HMTX;
+        use tables::maxp::MaxP;
+        use test_utils::{font_buf, with_tables};
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let maxp: MaxP = font.get_table().unwrap();
+        let glyph_id = font.get_glyph_id('I').unwrap();
+        let num_points = match font.get_glyph_for_id(glyph_id).unwrap().desc {
+            Description::Simple(glyph) => glyph.coordinates().count(),
+            _ => panic!("Should be simple"),
+        };
+        let default = font.placement_metrics_for_glyph_id(glyph_id, 12).unwrap();
+        let hmtx: HMTX = font.get_table().unwrap();
+        let advance = hmtx.advance_width(glyph_id).0;
+
+        // At full weight the outline moves 20 units right, and the advance
+        // grows by 40
+        let mut deltas = vec![(20, 0); num_points];
+        deltas.extend_from_slice(&[(0, 0), (40, 0), (0, 0), (0, 0)]);
+        let gvar = build_gvar(maxp.num_glyphs, glyph_id as u16, 16384, None, &deltas);
+        let buf = with_tables(&buf, &[(b"fvar", build_fvar()), (b"gvar", gvar)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let bold = font.with_variations(&[Variation::new(b"wght", 900.)]).unwrap();
+        let metrics = bold.placement_metrics_for_glyph_id(glyph_id, 12).unwrap();
+        assert_eq!(metrics.left_bearing.0, default.left_bearing.0 + 20);
+        assert_eq!(metrics.horiz_advance.unwrap().0, advance + 40);
+        assert_eq!(metrics.shift[0].0, default.shift[0].0 - 20.);
+
+        // Halfway there
+        let semibold = font.with_variations(&[Variation::new(b"wght", 650.)]).unwrap();
+        let metrics = semibold.placement_metrics_for_glyph_id(glyph_id, 12).unwrap();
+        assert_eq!(metrics.horiz_advance.unwrap().0, advance + 20);
+
+        let regular = font.placement_metrics_for_glyph_id(glyph_id, 12).unwrap();
+        assert_eq!(regular.left_bearing.0, default.left_bearing.0);
+        assert_eq!(regular.horiz_advance.map(|advance| advance.0), default.horiz_advance.map(|advance| advance.0));
+    }
+}
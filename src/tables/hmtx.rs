@@ -39,6 +39,16 @@ impl<'a> HMTX<'a> {
             self.left_bearings.at(idx).into()
         }
     }
+
+    /// Glyphs past the end of the long metrics share the last advance width.
+    /// 0 if there aren't any.
+    pub fn advance_width(&self, glyph_id: u32) -> FontUnit<u16> {
+        let len = self.horiz_metrics.len();
+        if len == 0 {
+            return FontUnit(0);
+        }
+        self.horiz_metrics.at((glyph_id as usize).min(len - 1)).advance_width
+    }
 }
 
 #[derive(Debug, Parse)]
@@ -69,3 +79,20 @@ impl From<LongHorizMetric> for HorizMetric {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_advance_widths() {
+        // Two long metrics, then one left bearing
+        let buf = [0, 10, 0, 1, 0, 20, 0, 2, 0, 3];
+        let hmtx = HMTX::parse_metrics(&buf, 2);
+        assert_eq!(hmtx.advance_width(1).0, 20);
+        assert_eq!(hmtx.advance_width(2).0, 20);
+
+        let hmtx = HMTX::parse_metrics(&buf, 0);
+        assert_eq!(hmtx.advance_width(0).0, 0);
+    }
+}
@@ -0,0 +1,117 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::variation_store::{DeltaSetIndexMap, ItemVariationStore};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/hvar
+
+/// Horizontal metric variations
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct HVAR<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    item_variation_store_offset: u32,
+    advance_width_mapping_offset: u32,
+    lsb_mapping_offset: u32,
+    rsb_mapping_offset: u32,
+}
+
+impl<'a> PrimaryTable for HVAR<'a> {
+    fn tag() -> TableTag {
+        TableTag::HorizontalMetricsVariation
+    }
+}
+
+impl<'a> HVAR<'a> {
+    fn delta(&self, mapping_offset: u32, glyph_id: u32, coords: &[f32]) -> f32 {
+        let store: ItemVariationStore = self.table.at_offset(self.item_variation_store_offset as usize);
+        let (outer, inner) = if mapping_offset != 0 {
+            let map: DeltaSetIndexMap = self.table.at_offset(mapping_offset as usize);
+            map.index(glyph_id)
+        } else {
+            (0, glyph_id as u16)
+        };
+        store.delta(outer, inner, coords)
+    }
+
+    /// How much the glyph's advance width changes at `coords`
+    pub fn advance_width_delta(&self, glyph_id: u32, coords: &[f32]) -> f32 {
+        self.delta(self.advance_width_mapping_offset, glyph_id, coords)
+    }
+
+    /// How much the glyph's left side bearing changes at `coords`. `None` if
+    /// it should come from the varied outline instead.
+    pub fn lsb_delta(&self, glyph_id: u32, coords: &[f32]) -> Option<f32> {
+        if self.lsb_mapping_offset == 0 {
+            return None;
+        }
+        Some(self.delta(self.lsb_mapping_offset, glyph_id, coords))
+    }
+
+    /// How much the glyph's right side bearing changes at `coords`. `None`
+    /// if it should come from the varied outline instead.
+    pub fn rsb_delta(&self, glyph_id: u32, coords: &[f32]) -> Option<f32> {
+        if self.rsb_mapping_offset == 0 {
+            return None;
+        }
+        Some(self.delta(self.rsb_mapping_offset, glyph_id, coords))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+    use tables::variation_store::tests::build_store;
+
+    /// An `HVAR` with advance deltas indexed directly by glyph id
+    pub fn build_hvar(deltas: &[(i16, i16)]) -> Vec<u8> {
+        let mut hvar = Vec::new();
+        push_u16(&mut hvar, 1);
+        push_u16(&mut hvar, 0);
+        push_u32(&mut hvar, 20);
+        push_u32(&mut hvar, 0);
+        push_u32(&mut hvar, 0);
+        push_u32(&mut hvar, 0);
+        hvar.extend(build_store(deltas));
+        hvar
+    }
+
+    #[test]
+    fn advance_deltas() {
+        let hvar = build_hvar(&[(0, 0), (60, -30)]);
+        let hvar = HVAR::parse(&hvar).1;
+        assert_eq!(hvar.advance_width_delta(1, &[0.5]), 30.);
+        assert_eq!(hvar.advance_width_delta(1, &[-1.]), -30.);
+        assert_eq!(hvar.advance_width_delta(0, &[1.]), 0.);
+        assert_eq!(hvar.lsb_delta(1, &[1.]), None);
+    }
+
+    #[test]
+    fn varied_advances() {
+        use font::Font;
+        use tables::fvar::Variation;
+        use tables::fvar::tests::build_fvar;
+        use test_utils::{font_buf, with_tables};
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let glyph_id = font.get_glyph_id('I').unwrap();
+        let advance = font.advance_width(glyph_id).unwrap().0;
+
+        let mut deltas = vec![(0, 0); glyph_id as usize + 1];
+        deltas[glyph_id as usize] = (100, -40);
+        let buf = with_tables(&buf, &[(b"fvar", build_fvar()), (b"HVAR", build_hvar(&deltas))]);
+        let font = Font::from_buffer(&buf).unwrap();
+        assert_eq!(font.advance_width(glyph_id).unwrap().0, advance);
+
+        let bold = font.with_variations(&[Variation::new(b"wght", 650.)]).unwrap();
+        assert_eq!(bold.advance_width(glyph_id).unwrap().0, advance + 50);
+        let metrics = bold.placement_metrics_for_glyph_id(glyph_id, 12).unwrap();
+        assert_eq!(metrics.horiz_advance.unwrap().0, advance + 50);
+
+        let light = font.with_variations(&[Variation::new(b"wght", 100.)]).unwrap();
+        assert_eq!(light.advance_width(glyph_id).unwrap().0, advance - 40);
+    }
+}
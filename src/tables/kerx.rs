@@ -0,0 +1,252 @@
+use parse::{BufView, DynArr, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::aat::{Lookup, StateTable};
+
+// https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6kerx.html
+
+/// Extended kerning, AAT's equivalent of the kerning parts of `GPOS`
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Kerx<'a> {
+    version: u16,
+    padding: u16,
+    n_tables: u32,
+    subtables: BufView<'a, u8>,
+}
+
+impl<'a> PrimaryTable for Kerx<'a> {
+    fn tag() -> TableTag {
+        TableTag::ExtendedKerning
+    }
+}
+
+#[derive(Debug, Parse)]
+struct SubtableHeader {
+    length: u32,
+    coverage: u32,
+    tuple_count: u32,
+}
+
+/// The subtable kerns vertical text
+pub(crate) const COVERAGE_VERTICAL: u32 = 0x8000_0000;
+/// Values move glyphs perpendicular to the direction of the text
+pub(crate) const COVERAGE_CROSS_STREAM: u32 = 0x4000_0000;
+/// Values are offsets to variation tuples
+pub(crate) const COVERAGE_VARIATION: u32 = 0x2000_0000;
+
+#[derive(Debug)]
+pub(crate) struct Subtable<'a> {
+    pub coverage: u32,
+    /// Number of values per kerning value in format 1
+    pub tuple_count: u32,
+    pub kind: SubtableKind<'a>,
+}
+
+#[derive(Debug)]
+pub(crate) enum SubtableKind<'a> {
+    /// Sorted list of kerning pairs
+    Pairs(PairSubtable<'a>),
+    /// State machine that kerns a stack of glyphs
+    Contextual(ContextualSubtable<'a>),
+    /// Values indexed by the sum of the left and right class offsets
+    ClassArray(ClassArraySubtable<'a>),
+    /// State machine that attaches glyphs by anchor or control point
+    Anchor(AnchorSubtable<'a>),
+    /// Values indexed by the sum of the left and right indices
+    IndexArray(IndexArraySubtable<'a>),
+    Unknown,
+}
+
+impl<'a> Kerx<'a> {
+    pub(crate) fn subtables(&self) -> Vec<Subtable<'a>> {
+        let mut offset = 0;
+        (0..self.n_tables)
+            .map(|_| {
+                let header: SubtableHeader = self.subtables.at_offset(offset);
+                let start = offset;
+                let end = offset + header.length as usize;
+                offset = end;
+                // Formats 2 and 6 have offsets from the start of the subtable
+                let whole = BufView(&self.subtables.0[start..end], ::std::marker::PhantomData);
+                let body = &self.subtables.0[(start + SubtableHeader::approx_file_size())..end];
+                let kind = match header.coverage & 0xFF {
+                    0 => SubtableKind::Pairs(PairSubtable::parse(body).1),
+                    1 => SubtableKind::Contextual(ContextualSubtable::parse(body).1),
+                    2 => SubtableKind::ClassArray(ClassArraySubtable {
+                        table: whole,
+                        header: ClassArrayHeader::parse(body).1,
+                    }),
+                    4 => SubtableKind::Anchor(AnchorSubtable::parse(body).1),
+                    6 => SubtableKind::IndexArray(IndexArraySubtable {
+                        table: whole,
+                        header: IndexArrayHeader::parse(body).1,
+                    }),
+                    _ => SubtableKind::Unknown,
+                };
+                Subtable {
+                    coverage: header.coverage,
+                    tuple_count: header.tuple_count,
+                    kind,
+                }
+            })
+            .collect()
+    }
+}
+
+impl<'a> Subtable<'a> {
+    /// The kerning between two glyphs, for the formats that store pairs.
+    /// `None` for the state machine formats.
+    pub fn pair_kerning(&self, left: u16, right: u16) -> Option<i32> {
+        match &self.kind {
+            SubtableKind::Pairs(pairs) => Some(pairs.kerning(left, right)),
+            SubtableKind::ClassArray(array) => Some(array.kerning(left, right)),
+            SubtableKind::IndexArray(array) => Some(array.kerning(left, right)),
+            _ => None,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub(crate) struct PairSubtable<'a> {
+    n_pairs: u32,
+    search_range: u32,
+    entry_selector: u32,
+    range_shift: u32,
+    #[arr_len_src = "n_pairs"]
+    pairs: DynArr<'a, KerningPair>,
+}
+
+#[derive(Debug, Parse)]
+struct KerningPair {
+    left: u16,
+    right: u16,
+    value: i16,
+}
+
+impl<'a> PairSubtable<'a> {
+    pub fn kerning(&self, left: u16, right: u16) -> i32 {
+        self.pairs
+            .binary_search_by(|pair| (pair.left, pair.right).cmp(&(left, right)))
+            .map(|pair| pair.value as i32)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Parse)]
+pub(crate) struct ContextualSubtable<'a> {
+    pub machine: StateTable<'a>,
+    value_table_offset: u32,
+}
+
+impl<'a> ContextualSubtable<'a> {
+    /// The `idx`th kerning value. The last value of each list is odd.
+    pub fn value(&self, idx: usize) -> i16 {
+        self.machine.table().at_offset(self.value_table_offset as usize + idx * 2)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct ClassArrayHeader {
+    row_width: u32,
+    left_class_table_offset: u32,
+    right_class_table_offset: u32,
+    kerning_array_offset: u32,
+}
+
+#[derive(Debug)]
+pub(crate) struct ClassArraySubtable<'a> {
+    table: BufView<'a, u8>,
+    header: ClassArrayHeader,
+}
+
+impl<'a> ClassArraySubtable<'a> {
+    pub fn kerning(&self, left: u16, right: u16) -> i32 {
+        let left_classes: Lookup = self.table.at_offset(self.header.left_class_table_offset as usize);
+        let right_classes: Lookup = self.table.at_offset(self.header.right_class_table_offset as usize);
+        // The left class is a row offset and the right class a column
+        // offset, together they are the offset of the value in the subtable
+        let offset = left_classes.value_u16(left).unwrap_or(0) as usize +
+            right_classes.value_u16(right).unwrap_or(0) as usize;
+        if offset < self.header.kerning_array_offset as usize || offset + 2 > self.table.0.len() {
+            return 0;
+        }
+        self.table.at_offset::<i16>(offset) as i32
+    }
+}
+
+#[derive(Debug, Parse)]
+pub(crate) struct AnchorSubtable<'a> {
+    pub machine: StateTable<'a>,
+    flags: u32,
+}
+
+/// How an anchor subtable's actions find the points to line up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AnchorAction {
+    /// Indices of points in the glyph outlines
+    ControlPoints,
+    /// Indices of points in the `ankr` table
+    AnchorPoints,
+    /// The coordinates themselves
+    Coordinates,
+}
+
+impl<'a> AnchorSubtable<'a> {
+    pub fn action_type(&self) -> Option<AnchorAction> {
+        match self.flags >> 30 {
+            0 => Some(AnchorAction::ControlPoints),
+            1 => Some(AnchorAction::AnchorPoints),
+            2 => Some(AnchorAction::Coordinates),
+            _ => None,
+        }
+    }
+
+    /// The `idx`th value of the action data
+    pub fn data(&self, idx: usize) -> u16 {
+        let offset = (self.flags & 0x00FF_FFFF) as usize;
+        self.machine.table().at_offset(offset + idx * 2)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct IndexArrayHeader {
+    flags: u32,
+    row_count: u16,
+    column_count: u16,
+    row_index_table_offset: u32,
+    column_index_table_offset: u32,
+    kerning_array_offset: u32,
+    kerning_vector_offset: u32,
+}
+
+/// The index lookups have 32-bit values and the kerning values are 32-bit
+const VALUES_ARE_LONG: u32 = 0x0000_0001;
+
+#[derive(Debug)]
+pub(crate) struct IndexArraySubtable<'a> {
+    table: BufView<'a, u8>,
+    header: IndexArrayHeader,
+}
+
+impl<'a> IndexArraySubtable<'a> {
+    pub fn kerning(&self, left: u16, right: u16) -> i32 {
+        let rows: Lookup = self.table.at_offset(self.header.row_index_table_offset as usize);
+        let columns: Lookup = self.table.at_offset(self.header.column_index_table_offset as usize);
+        let long = self.header.flags & VALUES_ARE_LONG != 0;
+        let value_size = if long { 4 } else { 2 };
+        let idx = rows.value(left, value_size).unwrap_or(0) as usize +
+            columns.value(right, value_size).unwrap_or(0) as usize;
+        let offset = self.header.kerning_array_offset as usize + idx * value_size;
+        if offset + value_size > self.table.0.len() {
+            return 0;
+        }
+        if long {
+            self.table.at_offset::<i32>(offset)
+        } else {
+            self.table.at_offset::<i16>(offset) as i32
+        }
+    }
+}
@@ -0,0 +1,1039 @@
+// Structures shared by the OpenType layout tables (GSUB and GPOS)
+// https://docs.microsoft.com/en-us/typography/opentype/spec/chapter2
+
+use parse::{BufView, DynArr, Parse};
+use tables::name::Name;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A four byte tag naming a script, language system, or feature.
+///
+/// Tags less than 4 chars have trailing spaces.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Parse)]
+pub struct Tag(pub [u8; 4]);
+
+impl Tag {
+    pub fn new(tag: &[u8; 4]) -> Tag {
+        Tag(*tag)
+    }
+}
+impl<'a> From<&'a [u8; 4]> for Tag {
+    fn from(tag: &'a [u8; 4]) -> Tag {
+        Tag(*tag)
+    }
+}
+impl fmt::Debug for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tag({})", self)
+    }
+}
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &byte in self.0.iter() {
+            write!(f, "{}", byte as char)?;
+        }
+        Ok(())
+    }
+}
+
+pub const DEFAULT_SCRIPT: Tag = Tag(*b"DFLT");
+pub const DEFAULT_LANGUAGE: Tag = Tag(*b"dflt");
+
+/// The header shared by GSUB and GPOS
+#[allow(dead_code)]
+#[derive(Debug, Parse, Clone)]
+pub struct LayoutTable<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    script_list_offset: u16,
+    feature_list_offset: u16,
+    lookup_list_offset: u16,
+}
+
+impl<'a> LayoutTable<'a> {
+    pub fn script_list(&self) -> Option<ScriptList<'a>> {
+        self.table.checked_at_offset(self.script_list_offset as usize)
+    }
+
+    pub fn feature_list(&self) -> Option<FeatureList<'a>> {
+        self.table.checked_at_offset(self.feature_list_offset as usize)
+    }
+
+    pub fn lookup_list(&self) -> Option<LookupList<'a>> {
+        self.table.checked_at_offset(self.lookup_list_offset as usize)
+    }
+
+    /// Find the language system to use for a script and language.
+    ///
+    /// Falls back to the script's default language system, and then to the
+    /// `DFLT` script if the requested script isn't present.
+    pub fn lang_sys(&self, script: Tag, language: Tag) -> Option<LangSys<'a>> {
+        let scripts = self.script_list()?;
+        let script = scripts.script(script)
+            .or_else(|| scripts.script(DEFAULT_SCRIPT))
+            .or_else(|| scripts.script(Tag(*b"latn")))?;
+
+        script.lang_sys(language)
+            .or_else(|| script.default_lang_sys())
+    }
+
+    /// The feature with the given tag that is enabled in the language system
+    pub fn find_feature(&self, lang_sys: &LangSys<'a>, tag: Tag) -> Option<Feature<'a>> {
+        let features = self.feature_list()?;
+        lang_sys.feature_indices()
+            .map(|idx| features.record(idx as usize))
+            .find(|record| record.tag == tag)
+            .and_then(|record| features.feature_at(&record))
+    }
+}
+
+/// Used for script, language system, and feature records
+#[derive(Debug, Parse, Clone, Copy, PartialEq)]
+pub struct TagOffsetRecord {
+    pub tag: Tag,
+    pub offset: u16,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct ScriptList<'a> {
+    table: BufView<'a, u8>,
+    script_count: u16,
+    #[arr_len_src = "script_count"]
+    script_records: DynArr<'a, TagOffsetRecord>,
+}
+
+impl<'a> ScriptList<'a> {
+    pub(crate) fn records(&self) -> DynArr<'a, TagOffsetRecord> {
+        self.script_records.iter()
+    }
+
+    pub fn script(&self, tag: Tag) -> Option<Script<'a>> {
+        // Records are sorted alphabetically by tag
+        self.script_records
+            .binary_search_by(|record| record.tag.cmp(&tag))
+            .and_then(|record| self.script_at(&record))
+    }
+
+    pub fn script_at(&self, record: &TagOffsetRecord) -> Option<Script<'a>> {
+        self.table.checked_at_offset(record.offset as usize)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Script<'a> {
+    table: BufView<'a, u8>,
+    default_lang_sys_offset: u16,
+    lang_sys_count: u16,
+    #[arr_len_src = "lang_sys_count"]
+    lang_sys_records: DynArr<'a, TagOffsetRecord>,
+}
+
+impl<'a> Script<'a> {
+    pub fn default_lang_sys(&self) -> Option<LangSys<'a>> {
+        if self.default_lang_sys_offset == 0 {
+            return None;
+        }
+        self.table.checked_at_offset(self.default_lang_sys_offset as usize)
+    }
+
+    pub(crate) fn records(&self) -> DynArr<'a, TagOffsetRecord> {
+        self.lang_sys_records.iter()
+    }
+
+    pub fn lang_sys(&self, tag: Tag) -> Option<LangSys<'a>> {
+        self.lang_sys_records
+            .binary_search_by(|record| record.tag.cmp(&tag))
+            .and_then(|record| self.lang_sys_at(&record))
+    }
+
+    pub fn lang_sys_at(&self, record: &TagOffsetRecord) -> Option<LangSys<'a>> {
+        self.table.checked_at_offset(record.offset as usize)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct LangSys<'a> {
+    /// Reserved, always null
+    lookup_order_offset: u16,
+    required_feature_index: u16,
+    feature_index_count: u16,
+    #[arr_len_src = "feature_index_count"]
+    feature_indices: DynArr<'a, u16>,
+}
+
+impl<'a> LangSys<'a> {
+    /// Index into the `FeatureList` of the feature that must always be applied
+    pub fn required_feature_index(&self) -> Option<u16> {
+        if self.required_feature_index == 0xFFFF {
+            None
+        } else {
+            Some(self.required_feature_index)
+        }
+    }
+
+    pub(crate) fn feature_indices(&self) -> DynArr<'a, u16> {
+        self.feature_indices.iter()
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct FeatureList<'a> {
+    table: BufView<'a, u8>,
+    feature_count: u16,
+    #[arr_len_src = "feature_count"]
+    feature_records: DynArr<'a, TagOffsetRecord>,
+}
+
+impl<'a> FeatureList<'a> {
+    pub(crate) fn records(&self) -> DynArr<'a, TagOffsetRecord> {
+        self.feature_records.iter()
+    }
+
+    pub fn record(&self, idx: usize) -> TagOffsetRecord {
+        self.feature_records.at(idx)
+    }
+
+    pub fn feature_at(&self, record: &TagOffsetRecord) -> Option<Feature<'a>> {
+        self.table.checked_at_offset(record.offset as usize)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Feature<'a> {
+    table: BufView<'a, u8>,
+    feature_params_offset: u16,
+    lookup_index_count: u16,
+    #[arr_len_src = "lookup_index_count"]
+    lookup_list_indices: DynArr<'a, u16>,
+}
+
+impl<'a> Feature<'a> {
+    pub(crate) fn lookup_indices(&self) -> DynArr<'a, u16> {
+        self.lookup_list_indices.iter()
+    }
+
+    /// The feature parameters for stylistic sets and character variants.
+    ///
+    /// The format of the parameters depends on the feature, so the tag from
+    /// the feature's record is needed.
+    pub fn params(&self, tag: Tag) -> Option<FeatureParams> {
+        if self.feature_params_offset == 0 {
+            return None;
+        }
+        let params: BufView<u8> = self.table.checked_at_offset(self.feature_params_offset as usize)?;
+        let is_numbered = |prefix: &[u8]| {
+            &tag.0[..2] == prefix && tag.0[2].is_ascii_digit() && tag.0[3].is_ascii_digit()
+        };
+
+        if is_numbered(b"ss") {
+            let ss: StylisticSetParams = params.checked_at_offset(0)?;
+            Some(FeatureParams::StylisticSet {
+                ui_name_id: ss.ui_name_id,
+            })
+        } else if is_numbered(b"cv") {
+            let cv: CharacterVariantHeader = params.checked_at_offset(0)?;
+            let characters = (0..cv.char_count as usize)
+                .filter_map(|idx| {
+                    let start = CharacterVariantHeader::approx_file_size() + 3 * idx;
+                    let bytes: [u8; 3] = [
+                        params.checked_at_offset(start)?,
+                        params.checked_at_offset(start + 1)?,
+                        params.checked_at_offset(start + 2)?,
+                    ];
+                    let code_point = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+                    ::std::char::from_u32(code_point)
+                })
+                .collect();
+            Some(FeatureParams::CharacterVariant(CharacterVariantParams {
+                label_name_id: cv.feat_ui_label_name_id,
+                tooltip_name_id: cv.feat_ui_tooltip_text_name_id,
+                sample_text_name_id: cv.sample_text_name_id,
+                num_named_parameters: cv.num_named_parameters,
+                first_param_label_name_id: cv.first_param_ui_label_name_id,
+                characters,
+            }))
+        } else {
+            None
+        }
+    }
+}
+
+/// Extra information attached to some features
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureParams {
+    /// `ss01` through `ss20`
+    StylisticSet {
+        /// Name ID of the user-facing name of the set, e.g. "Single-storey a"
+        ui_name_id: u16,
+    },
+    /// `cv01` through `cv99`
+    CharacterVariant(CharacterVariantParams),
+}
+
+/// Name IDs are 0 if the font doesn't provide that string
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharacterVariantParams {
+    pub label_name_id: u16,
+    pub tooltip_name_id: u16,
+    pub sample_text_name_id: u16,
+    /// How many alternates there are that have a name
+    pub num_named_parameters: u16,
+    /// Name ID of the first named alternate, the rest follow consecutively
+    pub first_param_label_name_id: u16,
+    /// Characters that the feature has alternates for
+    pub characters: Vec<char>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct StylisticSetParams {
+    version: u16, // = 0
+    ui_name_id: u16,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct CharacterVariantHeader {
+    format: u16, // = 0
+    feat_ui_label_name_id: u16,
+    feat_ui_tooltip_text_name_id: u16,
+    sample_text_name_id: u16,
+    num_named_parameters: u16,
+    first_param_ui_label_name_id: u16,
+    char_count: u16,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse, Clone)]
+pub struct LookupList<'a> {
+    table: BufView<'a, u8>,
+    lookup_count: u16,
+    #[arr_len_src = "lookup_count"]
+    lookup_offsets: DynArr<'a, u16>,
+}
+
+impl<'a> LookupList<'a> {
+    pub fn len(&self) -> usize {
+        self.lookup_offsets.len()
+    }
+
+    pub fn lookup(&self, idx: u16) -> Option<Lookup<'a>> {
+        if idx as usize >= self.lookup_offsets.len() {
+            return None;
+        }
+        let offset = self.lookup_offsets.at(idx as usize);
+        self.table.checked_at_offset(offset as usize)
+    }
+}
+
+bitflags! {
+    #[derive(Parse)]
+    pub struct LookupFlag: u16 {
+        /// Only used by GPOS cursive attachment
+        const RIGHT_TO_LEFT = 0x0001;
+        const IGNORE_BASE_GLYPHS = 0x0002;
+        const IGNORE_LIGATURES = 0x0004;
+        const IGNORE_MARKS = 0x0008;
+        const USE_MARK_FILTERING_SET = 0x0010;
+        const RESERVED = 0x00E0;
+        const MARK_ATTACHMENT_TYPE_MASK = 0xFF00;
+    }
+}
+
+impl LookupFlag {
+    /// Only marks with this attachment class should be processed, if non-zero
+    pub fn mark_attachment_type(&self) -> u16 {
+        (*self & LookupFlag::MARK_ATTACHMENT_TYPE_MASK).bits() >> 8
+    }
+}
+
+#[derive(Debug, Parse)]
+pub struct Lookup<'a> {
+    table: BufView<'a, u8>,
+    pub lookup_type: u16,
+    pub lookup_flag: LookupFlag,
+    sub_table_count: u16,
+    #[arr_len_src = "sub_table_count"]
+    subtable_offsets: DynArr<'a, u16>,
+    /// Holds `markFilteringSet` if `USE_MARK_FILTERING_SET` is set
+    rest: BufView<'a, u8>,
+}
+
+impl<'a> Lookup<'a> {
+    /// The subtables of this lookup, each starting at its format field
+    pub(crate) fn subtables(&self) -> impl 'a + Iterator<Item = BufView<'a, u8>> {
+        let table = self.table.clone();
+        self.subtable_offsets.iter()
+            .map(move |offset| table.at_offset(offset as usize))
+    }
+
+    /// Index into GDEF's mark glyph sets
+    pub fn mark_filtering_set(&self) -> Option<u16> {
+        if self.lookup_flag.contains(LookupFlag::USE_MARK_FILTERING_SET) {
+            Some(self.rest.at_offset(0))
+        } else {
+            None
+        }
+    }
+}
+
+/// Resolves an extension subtable (GSUB type 7, GPOS type 9) to the lookup
+/// type and subtable that it wraps.
+pub(crate) fn resolve_extension<'a>(subtable: &BufView<'a, u8>) -> (u16, BufView<'a, u8>) {
+    let ext: ExtensionFormat1 = subtable.at_offset(0);
+    (ext.extension_lookup_type, subtable.at_offset(ext.extension_offset as usize))
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct ExtensionFormat1 {
+    format: u16, // = 1
+    extension_lookup_type: u16,
+    extension_offset: u32,
+}
+
+#[derive(Debug, Parse, Clone)]
+pub struct Coverage<'a>(BufView<'a, u8>);
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct CoverageFormat1<'a> {
+    format: u16, // = 1
+    glyph_count: u16,
+    #[arr_len_src = "glyph_count"]
+    glyph_array: DynArr<'a, u16>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct CoverageFormat2<'a> {
+    format: u16, // = 2
+    range_count: u16,
+    #[arr_len_src = "range_count"]
+    range_records: DynArr<'a, RangeRecord>,
+}
+
+#[derive(Debug, Parse)]
+struct RangeRecord {
+    start_glyph_id: u16,
+    end_glyph_id: u16,
+    /// For coverage this is the start coverage index, for class
+    /// definitions it's the class
+    value: u16,
+}
+
+impl RangeRecord {
+    fn cmp_glyph(&self, glyph_id: u16) -> Ordering {
+        if glyph_id < self.start_glyph_id {
+            Ordering::Greater
+        } else if self.end_glyph_id < glyph_id {
+            Ordering::Less
+        } else {
+            Ordering::Equal
+        }
+    }
+}
+
+impl<'a> Coverage<'a> {
+    /// The coverage index of the glyph, or `None` if it isn't covered
+    pub fn index(&self, glyph_id: u16) -> Option<u16> {
+        let format: u16 = self.0.at_offset(0);
+        match format {
+            1 => {
+                let cov: CoverageFormat1 = self.0.at_offset(0);
+                cov.glyph_array
+                    .binary_search_idx_by(|glyph| glyph.cmp(&glyph_id))
+                    .map(|idx| idx as u16)
+            },
+            2 => {
+                let cov: CoverageFormat2 = self.0.at_offset(0);
+                cov.range_records
+                    .binary_search_by(|range| range.cmp_glyph(glyph_id))
+                    .map(|range| range.value + (glyph_id - range.start_glyph_id))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, glyph_id: u16) -> bool {
+        self.index(glyph_id).is_some()
+    }
+
+    /// Every covered glyph, in coverage index order
+    pub fn glyphs(&self) -> Vec<u16> {
+        let format: u16 = self.0.at_offset(0);
+        match format {
+            1 => {
+                let cov: CoverageFormat1 = self.0.at_offset(0);
+                cov.glyph_array.collect()
+            },
+            2 => {
+                let cov: CoverageFormat2 = self.0.at_offset(0);
+                cov.range_records
+                    .flat_map(|range| range.start_glyph_id..(range.end_glyph_id + 1))
+                    .collect()
+            },
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Parse, Clone)]
+pub struct ClassDef<'a>(BufView<'a, u8>);
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct ClassDefFormat1<'a> {
+    format: u16, // = 1
+    start_glyph_id: u16,
+    glyph_count: u16,
+    #[arr_len_src = "glyph_count"]
+    class_value_array: DynArr<'a, u16>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+struct ClassDefFormat2<'a> {
+    format: u16, // = 2
+    class_range_count: u16,
+    #[arr_len_src = "class_range_count"]
+    class_range_records: DynArr<'a, RangeRecord>,
+}
+
+impl<'a> ClassDef<'a> {
+    /// Glyphs not assigned a class are in class 0
+    pub fn class(&self, glyph_id: u16) -> u16 {
+        let format: u16 = self.0.at_offset(0);
+        match format {
+            1 => {
+                let def: ClassDefFormat1 = self.0.at_offset(0);
+                if glyph_id < def.start_glyph_id {
+                    return 0;
+                }
+                let idx = (glyph_id - def.start_glyph_id) as usize;
+                if idx < def.class_value_array.len() {
+                    def.class_value_array.at(idx)
+                } else {
+                    0
+                }
+            },
+            2 => {
+                let def: ClassDefFormat2 = self.0.at_offset(0);
+                def.class_range_records
+                    .binary_search_by(|range| range.cmp_glyph(glyph_id))
+                    .map(|range| range.value)
+                    .unwrap_or(0)
+            },
+            _ => 0,
+        }
+    }
+}
+
+/// Which nested lookup to apply to which glyph of a contextual match
+#[derive(Debug, Parse, Clone, Copy)]
+pub struct SequenceLookupRecord {
+    /// Index into the matched input sequence
+    pub sequence_index: u16,
+    pub lookup_list_index: u16,
+}
+
+/// A single contextual rule, with the input sequence given either as glyph
+/// ids, classes, or coverage tables depending on the subtable format.
+#[derive(Debug, Clone)]
+pub(crate) struct ContextRule<'a> {
+    /// Doesn't include the first glyph of the input
+    pub(crate) backtrack: DynArr<'a, u16>,
+    pub(crate) input: DynArr<'a, u16>,
+    pub(crate) lookahead: DynArr<'a, u16>,
+    pub(crate) lookups: DynArr<'a, SequenceLookupRecord>,
+}
+
+/// How the values in a `ContextRule` should be matched against glyphs
+#[derive(Debug, Clone)]
+pub(crate) enum ContextMatch<'a> {
+    Glyphs,
+    Classes {
+        backtrack: ClassDef<'a>,
+        input: ClassDef<'a>,
+        lookahead: ClassDef<'a>,
+    },
+    /// Format 3 has no rules, every position is a coverage table
+    Coverages {
+        table: BufView<'a, u8>,
+        backtrack: DynArr<'a, u16>,
+        input: DynArr<'a, u16>,
+        lookahead: DynArr<'a, u16>,
+        lookups: DynArr<'a, SequenceLookupRecord>,
+    },
+}
+
+/// Shared parsing for the sequence context (GSUB 5, GPOS 7) and chained
+/// sequence context (GSUB 6, GPOS 8) subtables.
+#[derive(Debug, Clone)]
+pub struct SequenceContext<'a> {
+    table: BufView<'a, u8>,
+    chained: bool,
+    format: u16,
+}
+
+impl<'a> SequenceContext<'a> {
+    pub(crate) fn new(table: BufView<'a, u8>, chained: bool) -> SequenceContext<'a> {
+        let format = table.at_offset(0);
+        SequenceContext {
+            table,
+            chained,
+            format,
+        }
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        self.table.at_offset(offset)
+    }
+
+    fn u16_arr_at(&self, offset: usize) -> (usize, DynArr<'a, u16>) {
+        let count = self.u16_at(offset) as usize;
+        let start = offset + 2;
+        let arr = DynArr::parse(&self.table.0[start..(start + count * 2)]).1;
+        (start + count * 2, arr)
+    }
+
+    fn class_def_at(&self, offset_pos: usize) -> ClassDef<'a> {
+        let offset = self.u16_at(offset_pos);
+        self.table.at_offset(offset as usize)
+    }
+
+    /// What the rules of this subtable are matched against
+    pub(crate) fn match_kind(&self) -> Option<ContextMatch<'a>> {
+        let kind = match (self.format, self.chained) {
+            (1, _) => ContextMatch::Glyphs,
+            (2, false) => {
+                let input = self.class_def_at(4);
+                ContextMatch::Classes {
+                    backtrack: input.clone(),
+                    input: input.clone(),
+                    lookahead: input,
+                }
+            },
+            (2, true) => ContextMatch::Classes {
+                backtrack: self.class_def_at(4),
+                input: self.class_def_at(6),
+                lookahead: self.class_def_at(8),
+            },
+            (3, false) => {
+                let glyph_count = self.u16_at(2) as usize;
+                let seq_lookup_count = self.u16_at(4) as usize;
+                let input_start = 6;
+                let lookups_start = input_start + glyph_count * 2;
+                ContextMatch::Coverages {
+                    table: self.table.clone(),
+                    backtrack: DynArr::parse(&[]).1,
+                    input: DynArr::parse(&self.table.0[input_start..lookups_start]).1,
+                    lookahead: DynArr::parse(&[]).1,
+                    lookups: DynArr::parse(&self.table.0[lookups_start..
+                        (lookups_start + seq_lookup_count * SequenceLookupRecord::approx_file_size())]).1,
+                }
+            },
+            (3, true) => {
+                let (next, backtrack) = self.u16_arr_at(2);
+                let (next, input) = self.u16_arr_at(next);
+                let (next, lookahead) = self.u16_arr_at(next);
+                let seq_lookup_count = self.u16_at(next) as usize;
+                let lookups_start = next + 2;
+                ContextMatch::Coverages {
+                    table: self.table.clone(),
+                    backtrack,
+                    input,
+                    lookahead,
+                    lookups: DynArr::parse(&self.table.0[lookups_start..
+                        (lookups_start + seq_lookup_count * SequenceLookupRecord::approx_file_size())]).1,
+                }
+            },
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    /// The coverage table checked against the first input glyph
+    pub fn coverage(&self) -> Coverage<'a> {
+        if self.format == 3 {
+            // The first input coverage offset
+            let first_input = if self.chained {
+                let backtrack_count = self.u16_at(2) as usize;
+                4 + backtrack_count * 2 + 2
+            } else {
+                6
+            };
+            self.table.at_offset(self.u16_at(first_input) as usize)
+        } else {
+            self.table.at_offset(self.u16_at(2) as usize)
+        }
+    }
+
+    /// The rules that should be tried for the first input glyph, in order.
+    ///
+    /// `first_glyph` is the glyph id, `first_class` its class in the input
+    /// class definition (for format 2). Format 3 has no rules.
+    pub(crate) fn rules(&self, coverage_idx: u16, first_class: u16) -> Vec<ContextRule<'a>> {
+        let (count_pos, set_idx) = match self.format {
+            1 => (4, coverage_idx),
+            2 if self.chained => (10, first_class),
+            2 => (6, first_class),
+            _ => return Vec::new(),
+        };
+        let set_count = self.u16_at(count_pos);
+        if set_idx >= set_count {
+            return Vec::new();
+        }
+        let set_offset = self.u16_at(count_pos + 2 + 2 * set_idx as usize) as usize;
+        if set_offset == 0 {
+            return Vec::new();
+        }
+
+        let set: BufView<u8> = self.table.at_offset(set_offset);
+        let rule_count: u16 = set.at_offset(0);
+        (0..rule_count as usize)
+            .map(|idx| {
+                let rule_offset: u16 = set.at_offset(2 + 2 * idx);
+                let rule = SequenceContext {
+                    table: set.at_offset(rule_offset as usize),
+                    chained: self.chained,
+                    format: self.format,
+                };
+                rule.parse_rule()
+            })
+            .collect()
+    }
+
+    /// Parse `self.table` as a (chained) sequence rule
+    fn parse_rule(&self) -> ContextRule<'a> {
+        let empty = DynArr::parse(&[]).1;
+        let (backtrack, next) = if self.chained {
+            let (next, backtrack) = self.u16_arr_at(0);
+            (backtrack, next)
+        } else {
+            (empty.clone(), 0)
+        };
+        let (input, lookahead, next) = if self.chained {
+            let glyph_count = self.u16_at(next) as usize;
+            let input_len = glyph_count.saturating_sub(1) * 2;
+            let input_start = next + 2;
+            let input = DynArr::parse(&self.table.0[input_start..(input_start + input_len)]).1;
+            let (next, lookahead) = self.u16_arr_at(input_start + input_len);
+            (input, lookahead, next)
+        } else {
+            let glyph_count = self.u16_at(0) as usize;
+            // Unchained rules have the lookup count before the input sequence
+            let input_len = glyph_count.saturating_sub(1) * 2;
+            let input_start = 4;
+            let input = DynArr::parse(&self.table.0[input_start..(input_start + input_len)]).1;
+            (input, empty.clone(), input_start + input_len)
+        };
+        let (seq_lookup_count, lookups_start) = if self.chained {
+            (self.u16_at(next) as usize, next + 2)
+        } else {
+            (self.u16_at(2) as usize, next)
+        };
+        let lookups_len = seq_lookup_count * SequenceLookupRecord::approx_file_size();
+        let lookups = DynArr::parse(&self.table.0[lookups_start..(lookups_start + lookups_len)]).1;
+
+        ContextRule {
+            backtrack,
+            input,
+            lookahead,
+            lookups,
+        }
+    }
+}
+
+/// Summary of what a GSUB or GPOS table supports
+#[derive(Debug, Clone)]
+pub struct LayoutInfo {
+    pub scripts: Vec<ScriptInfo>,
+    /// In the same order as the feature list, so `LangSysInfo::features`
+    /// indexes into this
+    pub features: Vec<FeatureInfo>,
+    /// Indexed by `FeatureInfo::lookups`
+    pub lookups: Vec<LookupInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptInfo {
+    pub tag: Tag,
+    /// Used for languages that don't have their own language system
+    pub default_language: Option<LangSysInfo>,
+    pub languages: Vec<LangSysInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LangSysInfo {
+    /// `dflt` for a script's default language system
+    pub tag: Tag,
+    pub required_feature: Option<u16>,
+    pub features: Vec<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeatureInfo {
+    pub tag: Tag,
+    pub lookups: Vec<u16>,
+    pub params: Option<FeatureParams>,
+    /// The strings from the `name` table that `params` refers to
+    pub names: FeatureNames,
+}
+
+/// User-facing strings for a feature, if the font has them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeatureNames {
+    pub label: Option<String>,
+    pub tooltip: Option<String>,
+    pub sample_text: Option<String>,
+    /// Names of a character variant's alternates
+    pub param_labels: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LookupInfo {
+    /// The type of the subtables, extensions are resolved to the type they wrap
+    pub lookup_type: u16,
+    pub flag: LookupFlag,
+    pub mark_filtering_set: Option<u16>,
+    pub subtable_count: u16,
+}
+
+impl LayoutInfo {
+    /// The features of a language system, including the required feature
+    pub fn features_for<'i>(&'i self, lang_sys: &'i LangSysInfo) -> impl 'i + Iterator<Item = &'i FeatureInfo> {
+        lang_sys.required_feature.iter()
+            .chain(lang_sys.features.iter())
+            .filter_map(move |&idx| self.features.get(idx as usize))
+    }
+
+    /// Every feature tag in the table, sorted and without duplicates
+    pub fn feature_tags(&self) -> Vec<Tag> {
+        let mut tags: Vec<Tag> = self.features.iter().map(|feature| feature.tag).collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+}
+
+impl<'a> LangSys<'a> {
+    fn info(&self, tag: Tag) -> LangSysInfo {
+        LangSysInfo {
+            tag,
+            required_feature: self.required_feature_index(),
+            features: self.feature_indices().collect(),
+        }
+    }
+}
+
+impl<'a> LayoutTable<'a> {
+    /// Collect everything in the table.
+    ///
+    /// `extension_type` is the lookup type used for extension subtables.
+    /// Feature names are only filled in if `names` is given.
+    pub(crate) fn info(&self, extension_type: u16, names: Option<&Name<'a>>) -> Option<LayoutInfo> {
+        let script_list = self.script_list()?;
+        let scripts = script_list.records()
+            .filter_map(|record| {
+                let script = script_list.script_at(&record)?;
+                Some(ScriptInfo {
+                    tag: record.tag,
+                    default_language: script.default_lang_sys()
+                        .map(|lang_sys| lang_sys.info(DEFAULT_LANGUAGE)),
+                    languages: script.records()
+                        .filter_map(|record| Some(script.lang_sys_at(&record)?.info(record.tag)))
+                        .collect(),
+                })
+            })
+            .collect();
+
+        let feature_list = self.feature_list()?;
+        let features = feature_list.records()
+            .filter_map(|record| {
+                let feature = feature_list.feature_at(&record)?;
+                let params = feature.params(record.tag);
+                let names = match (names, params.as_ref()) {
+                    (Some(names), Some(params)) => feature_names(names, params),
+                    _ => FeatureNames::default(),
+                };
+                Some(FeatureInfo {
+                    tag: record.tag,
+                    lookups: feature.lookup_indices().collect(),
+                    params,
+                    names,
+                })
+            })
+            .collect();
+
+        let lookup_list = self.lookup_list()?;
+        let lookups = (0..lookup_list.len())
+            .filter_map(|idx| lookup_list.lookup(idx as u16))
+            .map(|lookup| {
+                let lookup_type = if lookup.lookup_type == extension_type {
+                    lookup.subtables()
+                        .next()
+                        .map(|subtable| resolve_extension(&subtable).0)
+                        .unwrap_or(extension_type)
+                } else {
+                    lookup.lookup_type
+                };
+                LookupInfo {
+                    lookup_type,
+                    flag: lookup.lookup_flag,
+                    mark_filtering_set: lookup.mark_filtering_set(),
+                    subtable_count: lookup.sub_table_count,
+                }
+            })
+            .collect();
+
+        Some(LayoutInfo {
+            scripts,
+            features,
+            lookups,
+        })
+    }
+}
+
+fn feature_names<'a>(names: &Name<'a>, params: &FeatureParams) -> FeatureNames {
+    // Name ID 0 is the copyright notice, so it means "no name" here
+    let string = |name_id: u16| if name_id == 0 { None } else { names.string(name_id) };
+    match params {
+        FeatureParams::StylisticSet { ui_name_id } => FeatureNames {
+            label: string(*ui_name_id),
+            ..Default::default()
+        },
+        FeatureParams::CharacterVariant(cv) => FeatureNames {
+            label: string(cv.label_name_id),
+            tooltip: string(cv.tooltip_name_id),
+            sample_text: string(cv.sample_text_name_id),
+            param_labels: (0..cv.num_named_parameters)
+                .filter_map(|idx| string(cv.first_param_label_name_id.wrapping_add(idx)))
+                .collect(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::*;
+    use tables::gsub::GSUB;
+    use test_utils::{load_font_buf, ROBOTO};
+
+    #[test]
+    fn tag_display() {
+        assert_eq!(format!("{}", Tag::new(b"liga")), "liga");
+        assert_eq!(format!("{:?}", Tag::new(b"cvt ")), "Tag(cvt )");
+    }
+
+    #[test]
+    fn script_lookup() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let gsub: GSUB = font.get_table().unwrap();
+
+        let scripts = gsub.script_list().unwrap();
+        assert!(scripts.script(Tag::new(b"latn")).is_some());
+        assert!(scripts.script(Tag::new(b"zzzz")).is_none());
+
+        let lang_sys = gsub.lang_sys(Tag::new(b"latn"), DEFAULT_LANGUAGE).unwrap();
+        assert!(gsub.find_feature(&lang_sys, Tag::new(b"liga")).is_some());
+    }
+
+    #[test]
+    fn coverage_formats() {
+        // Format 1: glyphs 3, 7, 9
+        let fmt1: &[u8] = &[0, 1, 0, 3, 0, 3, 0, 7, 0, 9];
+        let cov = Coverage::parse(fmt1).1;
+        assert_eq!(cov.index(3), Some(0));
+        assert_eq!(cov.index(9), Some(2));
+        assert_eq!(cov.index(8), None);
+
+        // Format 2: glyphs 10..=12 starting at index 0, 20..=20 at index 3
+        let fmt2: &[u8] = &[0, 2, 0, 2, 0, 10, 0, 12, 0, 0, 0, 20, 0, 20, 0, 3];
+        let cov = Coverage::parse(fmt2).1;
+        assert_eq!(cov.index(11), Some(1));
+        assert_eq!(cov.index(20), Some(3));
+        assert_eq!(cov.index(13), None);
+        assert_eq!(cov.glyphs(), vec![10, 11, 12, 20]);
+    }
+
+    #[test]
+    fn class_def_formats() {
+        // Format 1: glyphs 5, 6, 7 in classes 1, 0, 2
+        let fmt1: &[u8] = &[0, 1, 0, 5, 0, 3, 0, 1, 0, 0, 0, 2];
+        let def = ClassDef::parse(fmt1).1;
+        assert_eq!(def.class(5), 1);
+        assert_eq!(def.class(7), 2);
+        assert_eq!(def.class(8), 0);
+        assert_eq!(def.class(4), 0);
+
+        let fmt2: &[u8] = &[0, 2, 0, 1, 0, 10, 0, 20, 0, 4];
+        let def = ClassDef::parse(fmt2).1;
+        assert_eq!(def.class(15), 4);
+        assert_eq!(def.class(21), 0);
+    }
+
+    #[test]
+    fn layout_info() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let info = font.gsub_info().unwrap();
+
+        let latn = info.scripts.iter().find(|script| script.tag == Tag::new(b"latn")).unwrap();
+        let lang_sys = latn.default_language.as_ref().unwrap();
+        let liga = info.features_for(lang_sys)
+            .find(|feature| feature.tag == Tag::new(b"liga"))
+            .unwrap();
+        assert!(!liga.lookups.is_empty());
+        // Ligature substitution
+        assert_eq!(info.lookups[liga.lookups[0] as usize].lookup_type, 4);
+
+        assert!(info.feature_tags().contains(&Tag::new(b"ss01")));
+    }
+
+    #[test]
+    fn feature_params() {
+        // Feature with params at offset 6 and one lookup
+        let ss: &[u8] = &[0, 6, 0, 1, 0, 3, /* params */ 0, 0, 1, 0];
+        let feature = Feature::parse(ss).1;
+        assert_eq!(feature.params(Tag::new(b"ss01")), Some(FeatureParams::StylisticSet {
+            ui_name_id: 256,
+        }));
+        assert_eq!(feature.params(Tag::new(b"liga")), None);
+
+        let cv: &[u8] = &[
+            0, 4, 0, 0,
+            0, 0, 1, 44, 0, 0, 0, 0, 0, 2, 1, 45, 0, 1,
+            0, 0, 0x61,
+        ];
+        let feature = Feature::parse(cv).1;
+        match feature.params(Tag::new(b"cv01")) {
+            Some(FeatureParams::CharacterVariant(params)) => {
+                assert_eq!(params.label_name_id, 300);
+                assert_eq!(params.num_named_parameters, 2);
+                assert_eq!(params.first_param_label_name_id, 301);
+                assert_eq!(params.characters, vec!['a']);
+            },
+            other => panic!("Expected character variant params, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn offsets_past_the_end() {
+        // Version 1.0, with every list past the end of the table
+        let buf = [0, 1, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        let table = LayoutTable::parse(&buf).1;
+        assert!(table.script_list().is_none());
+        assert!(table.lookup_list().is_none());
+        assert!(table.lang_sys(Tag::new(b"latn"), DEFAULT_LANGUAGE).is_none());
+        assert!(table.info(7, None).is_none());
+    }
+}
@@ -32,11 +32,40 @@ impl<'a> Loca<'a> {
         }
     }
 
+    /// The start and end of the glyph's data in `glyf`, `None` if it has no
+    /// outline
+    pub fn range(&self, idx: usize) -> Option<(u32, u32)> {
+        let offset = self.at(idx)?;
+        Some((offset, self.at_inner(idx + 1)))
+    }
+
     fn at_inner(&self, idx: usize) -> u32 {
         use self::Loca::*;
         match self {
-            Short(arr) => arr.0.at(idx) as u32,
+            // Short offsets are stored divided by two
+            Short(arr) => arr.0.at(idx) as u32 * 2,
             Long(arr) => arr.0.at(idx),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::marker::PhantomData;
+
+    #[test]
+    fn short_offsets_are_halved() {
+        // Glyph 1 has no outline
+        let buf = [0, 0, 0, 5, 0, 5, 0, 9];
+        let loca = Loca::Short(S(DynArr(&buf, PhantomData)));
+        assert_eq!(loca.at(0), Some(0));
+        assert_eq!(loca.range(0), Some((0, 10)));
+        assert_eq!(loca.at(1), None);
+        assert_eq!(loca.range(2), Some((10, 18)));
+
+        let buf = [0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 18];
+        let loca = Loca::Long(L(DynArr(&buf, PhantomData)));
+        assert_eq!(loca.range(1), Some((10, 18)));
+    }
+}
@@ -39,8 +39,8 @@ pub struct MaxPV1Ext {
     max_composite_points: u16,
     max_composite_contours: u16,
     max_zones: u16,
-    max_twilight_points: u16,
-    max_storage: u16,
+    pub(crate) max_twilight_points: u16,
+    pub(crate) max_storage: u16,
     max_function_defs: u16,
     max_instruction_defs: u16,
     max_stack_elements: u16,
@@ -7,9 +7,42 @@ pub mod glyf;
 pub mod name;
 pub mod hhea;
 pub mod hmtx;
+pub mod hvar;
 pub mod vhea;
 pub mod vmtx;
+pub mod vvar;
 pub mod os2;
+pub mod layout;
+pub mod gdef;
+pub mod gsub;
+pub mod gvar;
+pub mod gpos;
+pub mod aat;
+pub mod ankr;
+pub mod avar;
+pub mod cbdt;
+pub mod cblc;
+pub mod cff;
+pub mod cff2;
+pub mod colr;
+pub mod cpal;
+pub mod cvt;
+pub mod ebdt;
+pub mod eblc;
+pub mod ebsc;
+pub mod feat;
+pub mod fpgm;
+pub mod fvar;
+pub mod glat;
+pub mod graphite_feat;
+pub mod kerx;
+pub mod morx;
+pub mod mvar;
+pub mod prep;
+pub mod sbix;
+pub mod silf;
+pub mod svg;
+pub mod variation_store;
 
 pub enum ParseTableErrorInner {
     TableNotFound,
@@ -122,6 +155,10 @@ pub enum TableTag {
     GlyphDefinition = u32_code!(b"GDEF"),
     Baseline = u32_code!(b"BASE"),
     Justification = u32_code!(b"JSTF"),
+    HorizontalMetricsVariation = u32_code!(b"HVAR"),
+    VerticalMetricsVariation = u32_code!(b"VVAR"),
+    MetricsVariation = u32_code!(b"MVAR"),
+    StyleAttributes = u32_code!(b"STAT"),
 
     // Graphite
     Silf = u32_code!(b"Silf"),
@@ -150,6 +187,11 @@ pub enum TableTag {
     EBDT = u32_code!(b"EBDT"),
     EBLC = u32_code!(b"EBLC"),
     EmbeddedBitmapScalingControl = u32_code!(b"EBSC"),
+    ColorBitmapData = u32_code!(b"CBDT"),
+    ColorBitmapLocation = u32_code!(b"CBLC"),
+    ColorTable = u32_code!(b"COLR"),
+    ColorPalette = u32_code!(b"CPAL"),
+    ScalableVectorGraphics = u32_code!(b"SVG "),
 
     // Who knows what
     MATH = u32_code!(b"MATH"),
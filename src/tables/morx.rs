@@ -0,0 +1,192 @@
+use parse::{BufView, DynArr, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::aat::{Lookup, StateTable};
+
+// https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6morx.html
+
+/// Extended glyph metamorphosis, AAT's equivalent of `GSUB`
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Morx<'a> {
+    version: u16,
+    unused: u16,
+    n_chains: u32,
+    chains: BufView<'a, u8>,
+}
+
+impl<'a> PrimaryTable for Morx<'a> {
+    fn tag() -> TableTag {
+        TableTag::ExtendedMetamorphosis
+    }
+}
+
+impl<'a> Morx<'a> {
+    pub(crate) fn chains(&self) -> Vec<Chain<'a>> {
+        let mut offset = 0;
+        (0..self.n_chains)
+            .map(|_| {
+                let chain: Chain = self.chains.at_offset(offset);
+                offset += chain.chain_length as usize;
+                chain
+            })
+            .collect()
+    }
+}
+
+/// A list of subtables, applied in order, that are turned on and off by
+/// feature flags
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub(crate) struct Chain<'a> {
+    pub default_flags: u32,
+    chain_length: u32,
+    n_feature_entries: u32,
+    n_subtables: u32,
+    #[arr_len_src = "n_feature_entries"]
+    features: DynArr<'a, FeatureEntry>,
+    subtables: BufView<'a, u8>,
+}
+
+/// How a feature setting changes the chain's flags
+#[derive(Debug, Parse, Clone, Copy)]
+pub(crate) struct FeatureEntry {
+    pub feature_type: u16,
+    pub feature_setting: u16,
+    pub enable_flags: u32,
+    /// ANDed with the flags, so these are the flags to keep
+    pub disable_flags: u32,
+}
+
+#[derive(Debug, Parse)]
+struct SubtableHeader {
+    length: u32,
+    coverage: u32,
+    sub_feature_flags: u32,
+}
+
+/// The subtable only applies to vertical text
+pub(crate) const COVERAGE_VERTICAL: u32 = 0x8000_0000;
+/// Process the glyphs in reverse
+pub(crate) const COVERAGE_DESCENDING: u32 = 0x4000_0000;
+/// The subtable applies to both horizontal and vertical text
+pub(crate) const COVERAGE_ANY_ORIENTATION: u32 = 0x2000_0000;
+/// `COVERAGE_DESCENDING` is relative to the logical order instead of the
+/// layout order
+pub(crate) const COVERAGE_LOGICAL: u32 = 0x1000_0000;
+
+#[derive(Debug)]
+pub(crate) struct Subtable<'a> {
+    pub coverage: u32,
+    /// The subtable is only applied if these share a bit with the chain's flags
+    pub sub_feature_flags: u32,
+    pub kind: SubtableKind<'a>,
+}
+
+#[derive(Debug)]
+pub(crate) enum SubtableKind<'a> {
+    /// Reorders up to four glyphs at each end of a marked range
+    Rearrangement(StateTable<'a>),
+    /// Substitutes the current or marked glyph
+    Contextual(ContextualSubtable<'a>),
+    Ligature(LigatureSubtable<'a>),
+    /// Substitutes every glyph using a lookup table
+    Noncontextual(Lookup<'a>),
+    /// Inserts glyphs before or after the current or marked glyph
+    Insertion(InsertionSubtable<'a>),
+    Unknown,
+}
+
+impl<'a> Chain<'a> {
+    pub fn features(&self) -> DynArr<'a, FeatureEntry> {
+        self.features.clone()
+    }
+
+    pub fn subtables(&self) -> Vec<Subtable<'a>> {
+        let mut offset = 0;
+        (0..self.n_subtables)
+            .map(|_| {
+                let header: SubtableHeader = self.subtables.at_offset(offset);
+                let start = offset + SubtableHeader::approx_file_size();
+                let end = offset + header.length as usize;
+                offset = end;
+                let body = &self.subtables.0[start..end];
+                let kind = match header.coverage & 0xFF {
+                    0 => SubtableKind::Rearrangement(StateTable::parse(body).1),
+                    1 => SubtableKind::Contextual(ContextualSubtable::parse(body).1),
+                    2 => SubtableKind::Ligature(LigatureSubtable::parse(body).1),
+                    4 => SubtableKind::Noncontextual(Lookup::parse(body).1),
+                    5 => SubtableKind::Insertion(InsertionSubtable::parse(body).1),
+                    _ => SubtableKind::Unknown,
+                };
+                Subtable {
+                    coverage: header.coverage,
+                    sub_feature_flags: header.sub_feature_flags,
+                    kind,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Parse)]
+pub(crate) struct ContextualSubtable<'a> {
+    pub machine: StateTable<'a>,
+    substitution_table_offset: u32,
+}
+
+impl<'a> ContextualSubtable<'a> {
+    /// Look the glyph up in the `idx`th substitution table
+    pub fn substitute(&self, idx: u16, glyph_id: u16) -> Option<u16> {
+        let tables: BufView<u8> = self.machine.table()
+            .at_offset(self.substitution_table_offset as usize);
+        let offset: u32 = tables.at_offset(idx as usize * 4);
+        let lookup: Lookup = tables.at_offset(offset as usize);
+        lookup.value_u16(glyph_id)
+    }
+}
+
+#[derive(Debug, Parse)]
+pub(crate) struct LigatureSubtable<'a> {
+    pub machine: StateTable<'a>,
+    lig_action_offset: u32,
+    component_offset: u32,
+    ligature_offset: u32,
+}
+
+/// The last action for the ligature
+pub(crate) const LIG_ACTION_LAST: u32 = 0x8000_0000;
+/// Store the ligature glyph in place of the current component
+pub(crate) const LIG_ACTION_STORE: u32 = 0x4000_0000;
+
+impl<'a> LigatureSubtable<'a> {
+    pub fn action(&self, idx: usize) -> u32 {
+        self.machine.table().at_offset(self.lig_action_offset as usize + idx * 4)
+    }
+
+    pub fn component(&self, idx: usize) -> u16 {
+        self.machine.table().at_offset(self.component_offset as usize + idx * 2)
+    }
+
+    pub fn ligature(&self, idx: usize) -> u16 {
+        self.machine.table().at_offset(self.ligature_offset as usize + idx * 2)
+    }
+}
+
+#[derive(Debug, Parse)]
+pub(crate) struct InsertionSubtable<'a> {
+    pub machine: StateTable<'a>,
+    insertion_action_offset: u32,
+}
+
+impl<'a> InsertionSubtable<'a> {
+    /// The glyphs to insert, starting from the `idx`th entry of the
+    /// insertion list
+    pub fn glyphs(&self, idx: u16, count: usize) -> Vec<u16> {
+        let actions: BufView<u16> = self.machine.table()
+            .at_offset::<BufView<u8>>(self.insertion_action_offset as usize)
+            .cast();
+        (0..count)
+            .map(|i| actions.at(idx as usize + i))
+            .collect()
+    }
+}
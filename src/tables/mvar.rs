@@ -0,0 +1,128 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::layout::Tag;
+use tables::variation_store::ItemVariationStore;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/mvar
+
+/// Font-wide metric variations, like ascender or underline position
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct MVAR<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    reserved: u16,
+    value_record_size: u16,
+    value_record_count: u16,
+    item_variation_store_offset: u16,
+}
+
+impl<'a> PrimaryTable for MVAR<'a> {
+    fn tag() -> TableTag {
+        TableTag::MetricsVariation
+    }
+}
+
+#[derive(Debug, Parse)]
+struct ValueRecord {
+    value_tag: Tag,
+    delta_set_outer_index: u16,
+    delta_set_inner_index: u16,
+}
+
+// Some of the value tags
+
+/// `OS/2.sTypoAscender`
+pub const HORIZ_ASCENDER: Tag = Tag(*b"hasc");
+/// `OS/2.sTypoDescender`
+pub const HORIZ_DESCENDER: Tag = Tag(*b"hdsc");
+/// `OS/2.sTypoLineGap`
+pub const HORIZ_LINE_GAP: Tag = Tag(*b"hlgp");
+/// `OS/2.sxHeight`
+pub const X_HEIGHT: Tag = Tag(*b"xhgt");
+/// `OS/2.sCapHeight`
+pub const CAP_HEIGHT: Tag = Tag(*b"cpht");
+/// `post.underlinePosition`
+pub const UNDERLINE_OFFSET: Tag = Tag(*b"undo");
+/// `post.underlineThickness`
+pub const UNDERLINE_SIZE: Tag = Tag(*b"unds");
+/// `OS/2.yStrikeoutPosition`
+pub const STRIKEOUT_OFFSET: Tag = Tag(*b"stro");
+/// `OS/2.yStrikeoutSize`
+pub const STRIKEOUT_SIZE: Tag = Tag(*b"strs");
+
+impl<'a> MVAR<'a> {
+    /// How much the metric for `tag` changes at `coords`
+    pub fn delta(&self, tag: Tag, coords: &[f32]) -> f32 {
+        if self.item_variation_store_offset == 0 {
+            return 0.;
+        }
+        let records: BufView<u8> = self.table.at_offset(12);
+        let record = (0..self.value_record_count as usize)
+            .map(|idx| records.at_offset::<ValueRecord>(idx * self.value_record_size as usize))
+            .find(|record| record.value_tag == tag);
+        match record {
+            Some(record) => {
+                let store: ItemVariationStore = self.table.at_offset(self.item_variation_store_offset as usize);
+                store.delta(record.delta_set_outer_index, record.delta_set_inner_index, coords)
+            },
+            None => 0.,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::push_u16;
+    use tables::variation_store::tests::build_store;
+
+    /// An `MVAR` moving the ascender and x-height with `build_store`'s
+    /// first two items
+    pub fn build_mvar(ascender: (i16, i16), x_height: (i16, i16)) -> Vec<u8> {
+        let mut mvar = Vec::new();
+        push_u16(&mut mvar, 1);
+        push_u16(&mut mvar, 0);
+        push_u16(&mut mvar, 0);
+        push_u16(&mut mvar, 8);
+        push_u16(&mut mvar, 2);
+        push_u16(&mut mvar, 12 + 2 * 8);
+        for (idx, tag) in [HORIZ_ASCENDER, X_HEIGHT].iter().enumerate() {
+            mvar.extend_from_slice(&tag.0);
+            push_u16(&mut mvar, 0);
+            push_u16(&mut mvar, idx as u16);
+        }
+        mvar.extend(build_store(&[ascender, x_height]));
+        mvar
+    }
+
+    #[test]
+    fn metric_deltas() {
+        let mvar = build_mvar((100, -50), (20, 0));
+        let mvar = MVAR::parse(&mvar).1;
+        assert_eq!(mvar.delta(HORIZ_ASCENDER, &[1.]), 100.);
+        assert_eq!(mvar.delta(HORIZ_ASCENDER, &[-0.5]), -25.);
+        assert_eq!(mvar.delta(X_HEIGHT, &[0.5]), 10.);
+        assert_eq!(mvar.delta(UNDERLINE_OFFSET, &[1.]), 0.);
+    }
+
+    #[test]
+    fn varied_text_metrics() {
+        use font::Font;
+        use tables::fvar::Variation;
+        use tables::fvar::tests::build_fvar;
+        use test_utils::{font_buf, with_tables};
+
+        let buf = font_buf();
+        let font = Font::from_buffer(&buf).unwrap();
+        let default = font.text_render_metrics().unwrap();
+
+        let buf = with_tables(&buf, &[(b"fvar", build_fvar()), (b"MVAR", build_mvar((100, -50), (20, 0)))]);
+        let font = Font::from_buffer(&buf).unwrap().with_variations(&[Variation::new(b"wght", 900.)]).unwrap();
+        let metrics = font.text_render_metrics().unwrap();
+        assert_eq!(metrics.ascent.0, default.ascent.0 + 100);
+        assert_eq!(metrics.descent.0, default.descent.0);
+        assert_eq!(font.metric_variation(X_HEIGHT), 20.);
+    }
+}
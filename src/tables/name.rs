@@ -2,6 +2,8 @@
 use byte_slice_cast::{AsSliceOf, Error};
 use std::str::Utf8Error;
 use widestring::WideString;
+use parse::{BufView, DynArr, Parse};
+use tables::{PrimaryTable, TableTag};
 
 // TODO: Handle format 1 name tables
 
@@ -88,6 +90,123 @@ pub struct NameRecord {
     pub name: NameString,
 }
 
+/// The `name` table, without decoding any strings up front
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Name<'a> {
+    table: BufView<'a, u8>,
+    format: u16,
+    count: u16,
+    storage_offset: u16,
+    #[arr_len_src = "count"]
+    records: DynArr<'a, RawNameRecord>,
+}
+
+impl<'a> PrimaryTable for Name<'a> {
+    fn tag() -> TableTag {
+        TableTag::Name
+    }
+}
+
+#[derive(Debug, Parse)]
+struct RawNameRecord {
+    platform_id: u16,
+    platform_specific_id: u16,
+    language_id: u16,
+    name_id: u16,
+    length: u16,
+    offset: u16,
+}
+
+impl RawNameRecord {
+    /// Lower is better. `None` for encodings we can't decode.
+    fn preference(&self) -> Option<u8> {
+        const WINDOWS_ENGLISH_US: u16 = 0x409;
+        match (self.platform_id, self.platform_specific_id) {
+            (3, 1) | (3, 10) if self.language_id == WINDOWS_ENGLISH_US => Some(0),
+            (3, 1) | (3, 10) => Some(1),
+            (0, _) => Some(2),
+            (1, 0) if self.language_id == 0 => Some(3),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Name<'a> {
+    /// Get the string for a name ID, preferring US English Windows names.
+    ///
+    /// Returns `None` if there is no name we know how to decode.
+    pub fn string(&self, name_id: u16) -> Option<String> {
+        use encoding::{DecoderTrap, Encoding};
+        use encoding::all::MAC_ROMAN;
+
+        let record = self.records.iter()
+            .filter(|record| record.name_id == name_id)
+            .filter_map(|record| record.preference().map(|pref| (pref, record)))
+            .min_by_key(|&(pref, _)| pref)
+            .map(|(_, record)| record)?;
+
+        let start = self.storage_offset as usize + record.offset as usize;
+        let end = start + record.length as usize;
+        if end > self.table.0.len() {
+            return None;
+        }
+        let raw = &self.table.0[start..end];
+
+        match record.platform_id {
+            1 => MAC_ROMAN.decode(raw, DecoderTrap::Replace).ok(),
+            _ => {
+                // UTF-16BE
+                let wide: Vec<u16> = raw.chunks(2)
+                    .filter(|bytes| bytes.len() == 2)
+                    .map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+                    .collect();
+                Some(String::from_utf16_lossy(&wide))
+            },
+        }
+    }
+
+    /// Serialize the table with the names in `replacements` swapped for
+    /// US English Windows strings. Other records for those ids are dropped.
+    pub(crate) fn encode_with(&self, replacements: &[(u16, String)]) -> Vec<u8> {
+        const WINDOWS_ENGLISH_US: u16 = 0x409;
+
+        let mut records: Vec<(u16, u16, u16, u16, Vec<u8>)> = self.records.clone()
+            .filter(|record| replacements.iter().all(|&(name_id, _)| name_id != record.name_id))
+            .map(|record| {
+                let start = self.storage_offset as usize + record.offset as usize;
+                let end = (start + record.length as usize).min(self.table.0.len());
+                let raw = self.table.0.get(start..end).unwrap_or(&[]).to_vec();
+                (record.platform_id, record.platform_specific_id, record.language_id, record.name_id, raw)
+            })
+            .collect();
+        for &(name_id, ref string) in replacements {
+            let raw = string.encode_utf16()
+                .flat_map(|unit| vec![(unit >> 8) as u8, unit as u8])
+                .collect();
+            records.push((3, 1, WINDOWS_ENGLISH_US, name_id, raw));
+        }
+        records.sort_by_key(|record| (record.0, record.1, record.2, record.3));
+
+        let storage_offset = 6 + records.len() * 12;
+        let mut buf = Vec::new();
+        let mut storage = Vec::new();
+        for &val in &[0, records.len() as u16, storage_offset as u16] {
+            buf.extend_from_slice(&[(val >> 8) as u8, val as u8]);
+        }
+        for &(platform_id, platform_specific_id, language_id, name_id, ref raw) in &records {
+            let fields = [platform_id, platform_specific_id, language_id, name_id,
+                          raw.len() as u16, storage.len() as u16];
+            for &val in &fields {
+                buf.extend_from_slice(&[(val >> 8) as u8, val as u8]);
+            }
+            storage.extend_from_slice(raw);
+        }
+        buf.extend(storage);
+        buf
+    }
+}
+
 #[derive(Debug)]
 pub enum NameIdentifier {
     Copyright = 0,
@@ -321,3 +440,20 @@ pub enum WindowsLanguageIdentifier {
 pub enum IsoEncodingIdentifier {
     // TODO: Copy in the list (https://docs.microsoft.com/en-us/typography/opentype/spec/name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use font::*;
+    use test_utils::{load_font_buf, ROBOTO};
+
+    #[test]
+    fn name_strings() {
+        let buf = load_font_buf(ROBOTO);
+        let font = Font::from_buffer(&buf).unwrap();
+        let name: Name = font.get_table().unwrap();
+
+        assert_eq!(name.string(NameIdentifier::FontFamily as u16), Some("Roboto".to_string()));
+        assert_eq!(name.string(0xFFFF), None);
+    }
+}
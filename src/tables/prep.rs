@@ -0,0 +1,21 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/prep
+
+/// Instructions run whenever the size changes, usually to adjust the
+/// control values
+#[derive(Debug, Parse)]
+pub struct ControlValueProgram<'a>(BufView<'a, u8>);
+
+impl<'a> PrimaryTable for ControlValueProgram<'a> {
+    fn tag() -> TableTag {
+        TableTag::ControlValueProgram
+    }
+}
+
+impl<'a> ControlValueProgram<'a> {
+    pub fn instructions(&self) -> &'a [u8] {
+        (self.0).0
+    }
+}
@@ -0,0 +1,194 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/sbix
+
+/// Standard bitmap graphics: PNG, JPEG or TIFF glyph images per size
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Sbix<'a> {
+    table: BufView<'a, u8>,
+    version: u16,
+    flags: u16,
+    num_strikes: u32,
+}
+
+impl<'a> PrimaryTable for Sbix<'a> {
+    fn tag() -> TableTag {
+        TableTag::ExtendedBitmaps
+    }
+}
+
+/// The images for one size
+#[derive(Debug, Clone)]
+pub struct SbixStrike<'a> {
+    pub ppem: u16,
+    /// The density the images were made for
+    pub ppi: u16,
+    num_glyphs: u16,
+    data: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbixGlyph<'a> {
+    /// Pixels from the glyph origin to the image's left edge
+    pub origin_offset_x: i16,
+    /// Pixels from the glyph origin up to the image's bottom edge
+    pub origin_offset_y: i16,
+    /// `png `, `jpg ` or `tiff`. Other types aren't images.
+    pub graphic_type: [u8; 4],
+    pub data: &'a [u8],
+}
+
+impl<'a> Sbix<'a> {
+    /// Whether outlines should be drawn over the images
+    pub fn draw_outlines(&self) -> bool {
+        self.flags & 2 != 0
+    }
+
+    /// `num_glyphs` is from `maxp`
+    pub fn strikes(&self, num_glyphs: u16) -> Vec<SbixStrike<'a>> {
+        let table = self.table.0;
+        (0..self.num_strikes as usize)
+            .filter_map(|idx| {
+                let offset = u32::parse(table.get(8 + idx * 4..12 + idx * 4)?).1 as usize;
+                let data = table.get(offset..)?;
+                if data.len() < 4 {
+                    return None;
+                }
+                let (rest, ppem) = u16::parse(data);
+                let ppi = u16::parse(rest).1;
+                Some(SbixStrike { ppem, ppi, num_glyphs, data })
+            })
+            .collect()
+    }
+}
+
+impl<'a> SbixStrike<'a> {
+    /// The glyph's image, following `dupe` references. `None` if the
+    /// strike has nothing for it.
+    pub fn glyph(&self, glyph_id: u16) -> Option<SbixGlyph<'a>> {
+        let glyph = self.raw_glyph(glyph_id)?;
+        if &glyph.graphic_type == b"dupe" {
+            let glyph_id = u16::parse(glyph.data.get(..2)?).1;
+            return self.raw_glyph(glyph_id);
+        }
+        Some(glyph)
+    }
+
+    fn raw_glyph(&self, glyph_id: u16) -> Option<SbixGlyph<'a>> {
+        if glyph_id >= self.num_glyphs {
+            return None;
+        }
+        let offset = |idx: usize| self.data.get(4 + idx * 4..8 + idx * 4)
+            .map(|buf| u32::parse(buf).1 as usize);
+        let start = offset(glyph_id as usize)?;
+        let end = offset(glyph_id as usize + 1)?;
+        // Empty glyphs have no header either
+        if end < start + 8 {
+            return None;
+        }
+        let data = self.data.get(start..end)?;
+        let (data, origin_offset_x) = i16::parse(data);
+        let (data, origin_offset_y) = i16::parse(data);
+        let mut graphic_type = [0; 4];
+        graphic_type.copy_from_slice(&data[..4]);
+
+        Some(SbixGlyph {
+            origin_offset_x,
+            origin_offset_y,
+            graphic_type,
+            data: &data[4..],
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// An `sbix` with a strike per `(ppem, glyphs)`, where each glyph is
+    /// `(glyph_id, origin_offset, graphic_type, data)`
+    pub fn build_sbix(num_glyphs: u16, strikes: &[(u16, Vec<(u16, (i16, i16), &[u8; 4], Vec<u8>)>)]) -> Vec<u8> {
+        let mut sbix = Vec::new();
+        push_u16(&mut sbix, 1);
+        push_u16(&mut sbix, 1);
+        push_u32(&mut sbix, strikes.len() as u32);
+        let mut strike_data = Vec::new();
+        for &(ppem, ref glyphs) in strikes {
+            push_u32(&mut sbix, (8 + strikes.len() * 4 + strike_data.len()) as u32);
+
+            let mut strike = Vec::new();
+            push_u16(&mut strike, ppem);
+            push_u16(&mut strike, 72);
+            let mut images = Vec::new();
+            for glyph_id in 0..num_glyphs + 1 {
+                push_u32(&mut strike, (4 + (num_glyphs as usize + 1) * 4 + images.len()) as u32);
+                if let Some(&(_, (x, y), graphic_type, ref data)) = glyphs.iter().find(|glyph| glyph.0 == glyph_id) {
+                    push_u16(&mut images, x as u16);
+                    push_u16(&mut images, y as u16);
+                    images.extend_from_slice(graphic_type);
+                    images.extend_from_slice(data);
+                }
+            }
+            strike.extend(images);
+            strike_data.extend(strike);
+        }
+        sbix.extend(strike_data);
+        sbix
+    }
+
+    #[test]
+    fn strikes_and_glyphs() {
+        let sbix = build_sbix(4, &[
+            (20, vec![(1, (2, -3), b"png ", vec![1, 2, 3]), (3, (0, 0), b"dupe", vec![0, 1])]),
+            (40, vec![(2, (0, 0), b"jpg ", vec![4])]),
+        ]);
+        let sbix = Sbix::parse(&sbix).1;
+        assert!(!sbix.draw_outlines());
+        let strikes = sbix.strikes(4);
+        assert_eq!(strikes.iter().map(|strike| strike.ppem).collect::<Vec<_>>(), vec![20, 40]);
+
+        let glyph = strikes[0].glyph(1).unwrap();
+        assert_eq!((glyph.origin_offset_x, glyph.origin_offset_y), (2, -3));
+        assert_eq!(&glyph.graphic_type, b"png ");
+        assert_eq!(glyph.data, &[1, 2, 3]);
+        assert_eq!(strikes[0].glyph(3), Some(glyph));
+        assert_eq!(strikes[0].glyph(2), None);
+        assert_eq!(strikes[0].glyph(4), None);
+        assert_eq!(strikes[1].glyph(2).unwrap().data, &[4]);
+    }
+
+    #[test]
+    fn font_color_bitmap() {
+        use font::Font;
+        use tables::cbdt::tests::png;
+        use tables::maxp::MaxP;
+        use font::GetTable;
+        use test_utils::{font_buf, with_tables};
+
+        let font_buf = font_buf();
+        let font = Font::from_buffer(&font_buf).unwrap();
+        let glyph_id = font.get_glyph_id('A').unwrap() as u16;
+        let maxp: MaxP = font.get_table().unwrap();
+        let sbix = build_sbix(maxp.num_glyphs, &[
+            (16, vec![(glyph_id, (1, -2), b"png ", png(4, 4, [0, 0, 255, 255]))]),
+            (32, vec![(glyph_id, (2, -4), b"png ", png(8, 8, [0, 255, 0, 255]))]),
+        ]);
+        let buf = with_tables(&font_buf, &[(b"sbix", sbix)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        // Shrinks the bigger strike
+        let glyph = font.color_bitmap(glyph_id as u32, 24).unwrap();
+        assert_eq!(glyph.image.dimensions(), (6, 6));
+        assert_eq!(glyph.image.get_pixel(2, 2).data, [0, 255, 0, 255]);
+        assert_eq!((glyph.left_bearing, glyph.top_bearing), (1.5, 3.));
+
+        let glyph = font.color_bitmap(glyph_id as u32, 16).unwrap();
+        assert_eq!(glyph.image.get_pixel(2, 2).data, [0, 0, 255, 255]);
+        assert_eq!((glyph.left_bearing, glyph.top_bearing), (1., 2.));
+        // Grows the biggest when none are big enough
+        assert_eq!(font.color_bitmap(glyph_id as u32, 64).unwrap().image.dimensions(), (16, 16));
+    }
+}
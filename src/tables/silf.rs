@@ -0,0 +1,627 @@
+use parse::{BufView, DynArr, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// Graphite's rules
+// https://github.com/silnrsi/graphite/blob/master/doc/GTF.txt
+
+/// The Graphite rules, one subtable for each writing system the font
+/// supports. Only versions 2 and up are supported.
+#[derive(Debug, Parse)]
+pub struct Silf<'a> {
+    table: BufView<'a, u8>,
+    version: u32,
+}
+
+impl<'a> PrimaryTable for Silf<'a> {
+    fn tag() -> TableTag {
+        TableTag::Silf
+    }
+}
+
+/// Reads the fields of a structure one after another
+struct Cursor<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8], offset: usize) -> Cursor<'a> {
+        Cursor { buf, offset }
+    }
+
+    fn read<T: Parse<'a>>(&mut self) -> T {
+        let val = T::parse(&self.buf[self.offset..]).1;
+        self.offset += T::approx_file_size();
+        val
+    }
+
+    fn read_vec<T: Parse<'a>>(&mut self, count: usize) -> Vec<T> {
+        (0..count).map(|_| self.read()).collect()
+    }
+
+    fn skip(&mut self, bytes: usize) {
+        self.offset += bytes;
+    }
+}
+
+impl<'a> Silf<'a> {
+    pub(crate) fn subtables(&self) -> Vec<Subtable<'a>> {
+        if self.version < 0x0002_0000 {
+            return Vec::new();
+        }
+        let mut header = Cursor::new(self.table.0, 4);
+        if self.version >= 0x0003_0000 {
+            // Compiler version
+            header.skip(4);
+        }
+        let num_sub: u16 = header.read();
+        header.skip(2);
+        let offsets: Vec<u32> = header.read_vec(num_sub as usize);
+        offsets.into_iter()
+            .map(|offset| Subtable::parse(&self.table.0[offset as usize..], self.version))
+            .collect()
+    }
+
+    /// The subtable to use for the text
+    pub(crate) fn first_subtable(&self) -> Option<Subtable<'a>> {
+        self.subtables().into_iter().next()
+    }
+}
+
+/// The rules for one writing system
+#[derive(Debug)]
+pub(crate) struct Subtable<'a> {
+    /// Glyphs past this have no class in any pass
+    pub max_glyph_id: u16,
+    /// Index of the first positioning pass
+    pub pos_pass: u8,
+    /// Before which pass to reorder for right to left text, 0xFF if the font
+    /// doesn't want the text reordered
+    pub bidi_pass: u8,
+    /// Glyph attribute with the real glyph of a pseudo glyph
+    pub attr_pseudo: u8,
+    /// Number of user defined slot attributes
+    pub num_user_attrs: u8,
+    /// 1 for right to left scripts
+    pub direction: u8,
+    /// (character, glyph) for characters that map to pseudo glyphs
+    pub pseudos: Vec<(u32, u16)>,
+    pub classes: ClassMap<'a>,
+    pub passes: Vec<Pass<'a>>,
+}
+
+impl<'a> Subtable<'a> {
+    fn parse(buf: &'a [u8], version: u32) -> Subtable<'a> {
+        let mut cursor = Cursor::new(buf, 0);
+        if version >= 0x0003_0000 {
+            // Rule version, pass offset and pseudo offset
+            cursor.skip(8);
+        }
+        let max_glyph_id: u16 = cursor.read();
+        // Extra ascent and descent
+        cursor.skip(4);
+        let num_passes: u8 = cursor.read();
+        // First substitution pass, earlier passes are for line breaking
+        cursor.skip(1);
+        let pos_pass: u8 = cursor.read();
+        // First justification pass
+        cursor.skip(1);
+        let bidi_pass: u8 = cursor.read();
+        // Flags, max pre and post context
+        cursor.skip(3);
+        let attr_pseudo: u8 = cursor.read();
+        // Break weight, bidi class, mirrored glyph and skipped passes
+        // attributes
+        cursor.skip(4);
+        let num_just_levels: u8 = cursor.read();
+        cursor.skip(8 * num_just_levels as usize);
+        // Ligature component attribute
+        cursor.skip(2);
+        let num_user_attrs: u8 = cursor.read();
+        // Max components per ligature
+        cursor.skip(1);
+        let direction: u8 = cursor.read();
+        // Collision attribute and reserved bytes
+        cursor.skip(4);
+        let num_critical_features: u8 = cursor.read();
+        cursor.skip(2 * num_critical_features as usize + 1);
+        let num_script_tags: u8 = cursor.read();
+        cursor.skip(4 * num_script_tags as usize);
+        // Line break glyph
+        cursor.skip(2);
+        let pass_offsets: Vec<u32> = cursor.read_vec(num_passes as usize + 1);
+        let num_pseudo: u16 = cursor.read();
+        // Binary search header
+        cursor.skip(6);
+        let pseudos = (0..num_pseudo)
+            .map(|_| {
+                let character: u32 = cursor.read();
+                let glyph: u16 = cursor.read();
+                (character, glyph)
+            })
+            .collect();
+        let classes = ClassMap {
+            table: BufView(&buf[cursor.offset..], ::std::marker::PhantomData),
+            num_class: cursor.read(),
+            num_linear: cursor.read(),
+            long_offsets: version >= 0x0004_0000,
+        };
+        let passes = pass_offsets.windows(2)
+            .map(|range| Pass::parse(buf, range[0] as usize))
+            .collect();
+
+        Subtable {
+            max_glyph_id,
+            pos_pass,
+            bidi_pass,
+            attr_pseudo,
+            num_user_attrs,
+            direction,
+            pseudos,
+            classes,
+            passes,
+        }
+    }
+
+    /// The pseudo glyph a character maps to, if any
+    pub fn pseudo_glyph(&self, character: char) -> Option<u16> {
+        self.pseudos.iter()
+            .find(|pseudo| pseudo.0 == character as u32)
+            .map(|pseudo| pseudo.1)
+    }
+}
+
+/// The glyph classes rules refer to.
+///
+/// Output classes are lists of glyphs, so they map an index to a glyph.
+/// Input classes are sorted (glyph, index) pairs so the index of a glyph can
+/// be found quickly.
+#[derive(Debug)]
+pub(crate) struct ClassMap<'a> {
+    table: BufView<'a, u8>,
+    num_class: u16,
+    /// The first `num_linear` classes are output classes
+    num_linear: u16,
+    long_offsets: bool,
+}
+
+impl<'a> ClassMap<'a> {
+    /// Byte offset of the class from the start of the class map
+    fn class_offset(&self, class: u16) -> usize {
+        if self.long_offsets {
+            self.table.at_offset::<u32>(4 + class as usize * 4) as usize
+        } else {
+            self.table.at_offset::<u16>(4 + class as usize * 2) as usize
+        }
+    }
+
+    /// The `index`th glyph of the class, 0 if there isn't one
+    pub fn glyph(&self, class: u16, index: u16) -> u16 {
+        if class >= self.num_class {
+            return 0;
+        }
+        let start = self.class_offset(class);
+        let end = self.class_offset(class + 1);
+        if class < self.num_linear {
+            if (index as usize) < (end - start) / 2 {
+                return self.table.at_offset(start + index as usize * 2);
+            }
+        } else {
+            // An input class used as an output class, look for the index
+            let mut pair = start + 8;
+            while pair + 4 <= end {
+                if self.table.at_offset::<u16>(pair + 2) == index {
+                    return self.table.at_offset(pair);
+                }
+                pair += 4;
+            }
+        }
+        0
+    }
+
+    /// The index of the glyph in the class
+    pub fn index(&self, class: u16, glyph_id: u16) -> Option<u16> {
+        if class >= self.num_class {
+            return None;
+        }
+        let start = self.class_offset(class);
+        let end = self.class_offset(class + 1);
+        if class < self.num_linear {
+            (0..(end - start) / 2)
+                .find(|&idx| self.table.at_offset::<u16>(start + idx * 2) == glyph_id)
+                .map(|idx| idx as u16)
+        } else {
+            let num_ids: u16 = self.table.at_offset(start);
+            let pairs = &self.table.0[(start + 8)..(start + 8 + num_ids as usize * 4)];
+            let pairs: DynArr<LookupPair> = DynArr(pairs, ::std::marker::PhantomData);
+            pairs.binary_search_by(|pair| pair.glyph_id.cmp(&glyph_id))
+                .map(|pair| pair.index)
+        }
+    }
+}
+
+#[derive(Debug, Parse)]
+struct LookupPair {
+    glyph_id: u16,
+    index: u16,
+}
+
+/// One pass over the glyphs, running a state machine to find rules that
+/// match and then running the first rule whose constraint passes
+#[derive(Debug)]
+pub(crate) struct Pass<'a> {
+    pub flags: u8,
+    /// How many times rules can match at the same place before moving on
+    pub max_rule_loop: u8,
+    num_rows: u16,
+    num_transitional: u16,
+    num_success: u16,
+    num_columns: u16,
+    /// (first glyph, last glyph, column)
+    ranges: Vec<(u16, u16, u16)>,
+    /// The rules each success state matches
+    success_rules: Vec<Vec<u16>>,
+    pub min_pre_context: u8,
+    pub max_pre_context: u8,
+    start_states: Vec<u16>,
+    pub rules: Vec<Rule<'a>>,
+    transitions: BufView<'a, u8>,
+    /// Code that decides whether to run the pass at all
+    pub constraint: &'a [u8],
+}
+
+/// The pass is run with the glyphs in reverse order
+pub(crate) const PASS_REVERSE: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rule<'a> {
+    /// Number of slots the rule matches. Longer rules are tried first.
+    pub sort: u16,
+    /// Number of slots matched before the current slot
+    pub pre_context: u8,
+    pub constraint: &'a [u8],
+    pub action: &'a [u8],
+}
+
+impl<'a> Pass<'a> {
+    /// `subtable` is the whole Silf subtable, code offsets are relative to it
+    fn parse(subtable: &'a [u8], offset: usize) -> Pass<'a> {
+        let mut cursor = Cursor::new(subtable, offset);
+        let flags: u8 = cursor.read();
+        let max_rule_loop: u8 = cursor.read();
+        // Max rule context and max backup
+        cursor.skip(2);
+        let num_rules: u16 = cursor.read();
+        // FSM offset
+        cursor.skip(2);
+        let pass_code: u32 = cursor.read();
+        let rule_code: u32 = cursor.read();
+        let action_code: u32 = cursor.read();
+        // Debug offset
+        cursor.skip(4);
+        let num_rows: u16 = cursor.read();
+        let num_transitional: u16 = cursor.read();
+        let num_success: u16 = cursor.read();
+        let num_columns: u16 = cursor.read();
+        let num_ranges: u16 = cursor.read();
+        cursor.skip(6);
+        let ranges = (0..num_ranges)
+            .map(|_| (cursor.read(), cursor.read(), cursor.read()))
+            .collect();
+        let rule_map_offsets: Vec<u16> = cursor.read_vec(num_success as usize + 1);
+        let rule_map: Vec<u16> = cursor.read_vec(*rule_map_offsets.last().unwrap() as usize);
+        let success_rules = rule_map_offsets.windows(2)
+            .map(|range| rule_map[range[0] as usize..range[1] as usize].to_vec())
+            .collect();
+        let min_pre_context: u8 = cursor.read();
+        let max_pre_context: u8 = cursor.read();
+        let start_states = cursor.read_vec(max_pre_context.saturating_sub(min_pre_context) as usize + 1);
+        let sort_keys: Vec<u16> = cursor.read_vec(num_rules as usize);
+        let pre_contexts: Vec<u8> = cursor.read_vec(num_rules as usize);
+        // Collision threshold
+        cursor.skip(1);
+        let pass_constraint_len: u16 = cursor.read();
+        let constraint_offsets: Vec<u16> = cursor.read_vec(num_rules as usize + 1);
+        let action_offsets: Vec<u16> = cursor.read_vec(num_rules as usize + 1);
+        let transitions = BufView(&subtable[cursor.offset..], ::std::marker::PhantomData);
+
+        let code = |start: u32, from: u16, to: u16| {
+            &subtable[(start as usize + from as usize)..(start as usize + to as usize)]
+        };
+        let rules = (0..num_rules as usize)
+            .map(|idx| Rule {
+                sort: sort_keys[idx],
+                pre_context: pre_contexts[idx],
+                constraint: code(rule_code, constraint_offsets[idx], constraint_offsets[idx + 1]),
+                action: code(action_code, action_offsets[idx], action_offsets[idx + 1]),
+            })
+            .collect();
+
+        Pass {
+            flags,
+            max_rule_loop,
+            num_rows,
+            num_transitional,
+            num_success,
+            num_columns,
+            ranges,
+            success_rules,
+            min_pre_context,
+            max_pre_context,
+            start_states,
+            rules,
+            transitions,
+            constraint: code(pass_code, 0, pass_constraint_len),
+        }
+    }
+
+    /// The state machine column of the glyph, `None` if no rule uses it
+    pub fn column(&self, glyph_id: u16) -> Option<u16> {
+        self.ranges.iter()
+            .find(|range| range.0 <= glyph_id && glyph_id <= range.1)
+            .map(|range| range.2)
+    }
+
+    /// The state to start in when there are `context` slots before the
+    /// current one
+    pub fn start_state(&self, context: u8) -> u16 {
+        self.start_states[(self.max_pre_context - context) as usize]
+    }
+
+    /// Only transitional states have rows in the transition table
+    pub fn is_transitional(&self, state: u16) -> bool {
+        state < self.num_transitional
+    }
+
+    pub fn transition(&self, state: u16, column: u16) -> u16 {
+        if column >= self.num_columns {
+            return 0;
+        }
+        let idx = state as usize * self.num_columns as usize + column as usize;
+        self.transitions.at_offset(idx * 2)
+    }
+
+    /// The rules matched on reaching the state, empty if it isn't a success
+    /// state
+    pub fn rules_for_state(&self, state: u16) -> &[u16] {
+        let success_start = self.num_rows - self.num_success;
+        if state < success_start {
+            return &[];
+        }
+        self.success_rules.get((state - success_start) as usize)
+            .map(|rules| rules.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// A rule for `build_pass`: its glyph sequence, constraint and action
+    pub struct TestRule<'r> {
+        pub glyphs: &'r [u16],
+        pub constraint: &'r [u8],
+        pub action: &'r [u8],
+    }
+
+    /// A pass whose state machine matches each rule's glyph sequence.
+    /// Offsets are relative to `pass_start` in the subtable.
+    fn build_pass(rules: &[TestRule], flags: u8, pass_start: usize) -> Vec<u8> {
+        // One column per distinct glyph
+        let mut glyphs: Vec<u16> = rules.iter()
+            .flat_map(|rule| rule.glyphs.iter().cloned())
+            .collect();
+        glyphs.sort();
+        glyphs.dedup();
+        let column = |glyph: u16| glyphs.iter().position(|&g| g == glyph).unwrap();
+
+        // A trie of the sequences, state 0 is the start
+        let mut rows: Vec<Vec<u16>> = vec![vec![0; glyphs.len()]];
+        let mut matches: Vec<Vec<u16>> = vec![Vec::new()];
+        for (rule_idx, rule) in rules.iter().enumerate() {
+            let mut state = 0;
+            for &glyph in rule.glyphs {
+                let col = column(glyph);
+                if rows[state][col] == 0 {
+                    rows.push(vec![0; glyphs.len()]);
+                    matches.push(Vec::new());
+                    rows[state][col] = (rows.len() - 1) as u16;
+                }
+                state = rows[state][col] as usize;
+            }
+            matches[state].push(rule_idx as u16);
+        }
+        // Success states have to come last, every state keeps its row so
+        // success states can also be transitional
+        let order: Vec<usize> = (0..rows.len()).filter(|&state| matches[state].is_empty())
+            .chain((0..rows.len()).filter(|&state| !matches[state].is_empty()))
+            .collect();
+        let mut renumbered = vec![0; rows.len()];
+        for (new, &old) in order.iter().enumerate() {
+            renumbered[old] = new as u16;
+        }
+        let rows: Vec<Vec<u16>> = order.iter()
+            .map(|&old| rows[old].iter()
+                 .map(|&state| if state == 0 { 0 } else { renumbered[state as usize] })
+                 .collect())
+            .collect();
+        let matches: Vec<Vec<u16>> = order.iter().map(|&old| matches[old].clone()).collect();
+        let num_rows = rows.len() as u16;
+        let first_success = matches.iter().position(|rules| !rules.is_empty()).unwrap();
+
+        let mut pass = Vec::new();
+        pass.push(flags);
+        // Max rule loop, max context, max backup
+        pass.extend_from_slice(&[5, 4, 0]);
+        push_u16(&mut pass, rules.len() as u16);
+        push_u16(&mut pass, 0);
+        let code_offsets = pass.len();
+        for _ in 0..4 {
+            push_u32(&mut pass, 0);
+        }
+        push_u16(&mut pass, num_rows);
+        push_u16(&mut pass, num_rows);
+        push_u16(&mut pass, num_rows - first_success as u16);
+        push_u16(&mut pass, glyphs.len() as u16);
+        push_u16(&mut pass, glyphs.len() as u16);
+        for _ in 0..3 {
+            push_u16(&mut pass, 0);
+        }
+        for (col, &glyph) in glyphs.iter().enumerate() {
+            push_u16(&mut pass, glyph);
+            push_u16(&mut pass, glyph);
+            push_u16(&mut pass, col as u16);
+        }
+        let mut rule_map = Vec::new();
+        push_u16(&mut pass, 0);
+        for rules in &matches[first_success..] {
+            rule_map.extend_from_slice(rules);
+            push_u16(&mut pass, rule_map.len() as u16);
+        }
+        for &rule in &rule_map {
+            push_u16(&mut pass, rule);
+        }
+        // No pre-context, one start state
+        pass.extend_from_slice(&[0, 0]);
+        push_u16(&mut pass, 0);
+        for rule in rules {
+            push_u16(&mut pass, rule.glyphs.len() as u16);
+        }
+        for _ in rules {
+            pass.push(0);
+        }
+        // Collision threshold and pass constraint length
+        pass.push(0);
+        push_u16(&mut pass, 0);
+        let mut offset = 0;
+        push_u16(&mut pass, 0);
+        for rule in rules {
+            offset += rule.constraint.len();
+            push_u16(&mut pass, offset as u16);
+        }
+        let mut offset = 0;
+        push_u16(&mut pass, 0);
+        for rule in rules {
+            offset += rule.action.len();
+            push_u16(&mut pass, offset as u16);
+        }
+        for row in &rows {
+            for &state in row {
+                push_u16(&mut pass, state);
+            }
+        }
+        pass.push(0);
+
+        let pass_code = (pass_start + pass.len()) as u32;
+        for rule in rules {
+            pass.extend_from_slice(rule.constraint);
+        }
+        let action_code = (pass_start + pass.len()) as u32;
+        for rule in rules {
+            pass.extend_from_slice(rule.action);
+        }
+        for (idx, &offset) in [pass_code, pass_code, action_code].iter().enumerate() {
+            let at = code_offsets + idx * 4;
+            pass[at..(at + 4)].copy_from_slice(&[
+                (offset >> 24) as u8, (offset >> 16) as u8, (offset >> 8) as u8, offset as u8,
+            ]);
+        }
+        pass
+    }
+
+    /// A version 2 `Silf` table with one subtable. `classes` are output
+    /// classes, `passes` are (rules, flags) and the positioning passes start
+    /// at `pos_pass`.
+    pub fn build_silf(classes: &[&[u16]], passes: &[(&[TestRule], u8)], pos_pass: u8) -> Vec<u8> {
+        let mut sub = Vec::new();
+        push_u16(&mut sub, 0xFFFF);
+        push_u32(&mut sub, 0);
+        sub.push(passes.len() as u8);
+        // Substitution, positioning, justification and bidi passes
+        sub.extend_from_slice(&[0, pos_pass, passes.len() as u8, 0xFF]);
+        // Flags, max pre and post context
+        sub.extend_from_slice(&[0, 0, 0]);
+        // Pseudo, break, direction, mirror and skip passes attributes
+        sub.extend_from_slice(&[0, 1, 2, 3, 0]);
+        // No justification levels
+        sub.push(0);
+        push_u16(&mut sub, 0);
+        // One user attribute, max components, direction, collision
+        // attribute, reserved
+        sub.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0]);
+        // No critical features, reserved, no script tags, line break glyph
+        sub.extend_from_slice(&[0, 0, 0]);
+        push_u16(&mut sub, 0);
+        let pass_offsets_at = sub.len();
+        for _ in 0..(passes.len() + 1) {
+            push_u32(&mut sub, 0);
+        }
+        push_u16(&mut sub, 0);
+        for _ in 0..3 {
+            push_u16(&mut sub, 0);
+        }
+
+        push_u16(&mut sub, classes.len() as u16);
+        push_u16(&mut sub, classes.len() as u16);
+        let mut offset = 4 + 2 * (classes.len() + 1);
+        push_u16(&mut sub, offset as u16);
+        for class in classes {
+            offset += class.len() * 2;
+            push_u16(&mut sub, offset as u16);
+        }
+        for class in classes {
+            for &glyph in class.iter() {
+                push_u16(&mut sub, glyph);
+            }
+        }
+
+        let mut pass_offsets = Vec::new();
+        for &(rules, flags) in passes {
+            pass_offsets.push(sub.len() as u32);
+            let start = sub.len();
+            let pass = build_pass(rules, flags, start);
+            sub.extend_from_slice(&pass);
+        }
+        pass_offsets.push(sub.len() as u32);
+        for (idx, offset) in pass_offsets.into_iter().enumerate() {
+            let at = pass_offsets_at + idx * 4;
+            sub[at..(at + 4)].copy_from_slice(&[
+                (offset >> 24) as u8, (offset >> 16) as u8, (offset >> 8) as u8, offset as u8,
+            ]);
+        }
+
+        let mut silf = Vec::new();
+        push_u32(&mut silf, 0x0002_0000);
+        push_u16(&mut silf, 1);
+        push_u16(&mut silf, 0);
+        push_u32(&mut silf, 12);
+        silf.extend_from_slice(&sub);
+        silf
+    }
+
+    #[test]
+    fn pass_state_machine() {
+        let silf = build_silf(&[&[7, 8]], &[(&[
+            TestRule { glyphs: &[3, 4], constraint: &[], action: &[50] },
+            TestRule { glyphs: &[3, 5], constraint: &[], action: &[49] },
+        ], 0)], 1);
+        let silf = Silf::parse(&silf).1;
+        let subtable = silf.first_subtable().unwrap();
+        assert_eq!(subtable.passes.len(), 1);
+        assert_eq!(subtable.pos_pass, 1);
+        assert_eq!(subtable.classes.glyph(0, 1), 8);
+        assert_eq!(subtable.classes.index(0, 7), Some(0));
+        assert_eq!(subtable.classes.glyph(1, 0), 0);
+
+        let pass = &subtable.passes[0];
+        assert_eq!(pass.column(6), None);
+        let state = pass.transition(pass.start_state(0), pass.column(3).unwrap());
+        assert!(pass.rules_for_state(state).is_empty());
+        let matched = pass.transition(state, pass.column(5).unwrap());
+        assert_eq!(pass.rules_for_state(matched), &[1]);
+        assert_eq!(pass.rules[1].action, &[49]);
+        assert_eq!(pass.rules[1].sort, 2);
+    }
+}
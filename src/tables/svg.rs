@@ -0,0 +1,208 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/svg
+
+/// SVG documents for color glyphs
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct Svg<'a> {
+    table: BufView<'a, u8>,
+    version: u16,
+    svg_document_list_offset: u32,
+    reserved: u32,
+}
+
+impl<'a> PrimaryTable for Svg<'a> {
+    fn tag() -> TableTag {
+        TableTag::ScalableVectorGraphics
+    }
+}
+
+/// A document and the range of glyphs it has
+#[derive(Debug, Parse, Clone, Copy, PartialEq, Eq)]
+pub struct SvgDocumentRecord {
+    pub start_glyph_id: u16,
+    pub end_glyph_id: u16,
+    svg_doc_offset: u32,
+    svg_doc_length: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgGlyph {
+    /// The whole document, which may have other glyphs too
+    pub document: String,
+    /// The `id` of the element that draws the glyph, e.g. `glyph12`
+    pub element_id: String,
+}
+
+impl<'a> Svg<'a> {
+    pub fn records(&self) -> Vec<SvgDocumentRecord> {
+        let list = self.svg_document_list_offset as usize;
+        let num_entries = match self.table.0.get(list..list + 2) {
+            Some(buf) => u16::parse(buf).1 as usize,
+            None => return Vec::new(),
+        };
+        let size = SvgDocumentRecord::approx_file_size();
+        (0..num_entries)
+            .filter_map(|idx| {
+                let start = list + 2 + idx * size;
+                self.table.0.get(start..start + size).map(|buf| SvgDocumentRecord::parse(buf).1)
+            })
+            .collect()
+    }
+
+    /// The raw bytes of a document, which may be gzipped
+    pub fn document_data(&self, record: &SvgDocumentRecord) -> Option<&'a [u8]> {
+        let start = self.svg_document_list_offset as usize + record.svg_doc_offset as usize;
+        self.table.0.get(start..start + record.svg_doc_length as usize)
+    }
+
+    /// The decompressed document with the glyph in it. `None` if there isn't
+    /// one, or it's broken.
+    pub fn glyph_document(&self, glyph_id: u16) -> Option<SvgGlyph> {
+        let records = self.records();
+        let idx = records
+            .binary_search_by(|record| {
+                use std::cmp::Ordering;
+                if record.end_glyph_id < glyph_id {
+                    Ordering::Less
+                } else if record.start_glyph_id > glyph_id {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .ok()?;
+        let data = self.document_data(&records[idx])?;
+        let data = if data.starts_with(&[0x1F, 0x8B]) {
+            gunzip(data)?
+        } else {
+            data.to_vec()
+        };
+        Some(SvgGlyph {
+            document: String::from_utf8(data).ok()?,
+            element_id: format!("glyph{}", glyph_id),
+        })
+    }
+}
+
+/// Decompress a gzip member
+fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    // Only deflate is defined
+    if data.len() < 18 || data[2] != 8 {
+        return None;
+    }
+    let flags = data[3];
+    let mut start = 10;
+    if flags & FEXTRA != 0 {
+        let len = data.get(start..start + 2)?;
+        start += 2 + (len[0] as usize | (len[1] as usize) << 8);
+    }
+    for &flag in &[FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            start += data.get(start..)?.iter().position(|&byte| byte == 0)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        start += 2;
+    }
+    // The CRC and size come after the compressed data
+    let end = data.len() - 8;
+    let decompressed = ::inflate::inflate_bytes(data.get(start..end)?).ok()?;
+    let size = data[end + 4..].iter().rev().fold(0, |size, &byte| size << 8 | byte as u32);
+    if decompressed.len() as u32 != size {
+        return None;
+    }
+    Some(decompressed)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// An `SVG ` table with a document per `(start_glyph_id, end_glyph_id,
+    /// data)`
+    pub fn build_svg(documents: &[(u16, u16, Vec<u8>)]) -> Vec<u8> {
+        let mut svg = Vec::new();
+        push_u16(&mut svg, 0);
+        push_u32(&mut svg, 10);
+        push_u32(&mut svg, 0);
+        push_u16(&mut svg, documents.len() as u16);
+        let mut offset = 2 + documents.len() * 12;
+        for &(start, end, ref data) in documents {
+            push_u16(&mut svg, start);
+            push_u16(&mut svg, end);
+            push_u32(&mut svg, offset as u32);
+            push_u32(&mut svg, data.len() as u32);
+            offset += data.len();
+        }
+        for &(_, _, ref data) in documents {
+            svg.extend_from_slice(data);
+        }
+        svg
+    }
+
+    /// A gzip member holding `data` in a stored deflate block, with a file
+    /// name. The CRC isn't filled in.
+    pub fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut gzip = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 255];
+        gzip.extend_from_slice(b"glyphs.svg\0");
+        gzip.push(1);
+        let len = data.len() as u16;
+        gzip.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        gzip.extend_from_slice(data);
+        gzip.extend_from_slice(&[0; 4]);
+        let size = data.len() as u32;
+        gzip.extend_from_slice(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8]);
+        gzip
+    }
+
+    #[test]
+    fn glyph_documents() {
+        let plain = b"<svg><path id=\"glyph3\" d=\"M0 0H10V-10z\"/></svg>".to_vec();
+        let zipped = b"<svg><g id=\"glyph7\"/><g id=\"glyph9\"/></svg>".to_vec();
+        let mut broken = gzip(b"<svg/>");
+        let len = broken.len();
+        broken[len - 4] = 7;
+        let svg = build_svg(&[(3, 3, plain.clone()), (5, 9, gzip(&zipped)), (10, 10, broken)]);
+        let svg = Svg::parse(&svg).1;
+
+        assert_eq!(svg.records().len(), 3);
+        assert_eq!(svg.document_data(&svg.records()[0]), Some(&plain[..]));
+        let glyph = svg.glyph_document(3).unwrap();
+        assert_eq!(glyph.document.as_bytes(), &plain[..]);
+        assert_eq!(glyph.element_id, "glyph3");
+        let glyph = svg.glyph_document(7).unwrap();
+        assert_eq!(glyph.document.as_bytes(), &zipped[..]);
+        assert_eq!(glyph.element_id, "glyph7");
+        assert_eq!(svg.glyph_document(4), None);
+        assert_eq!(svg.glyph_document(10), None);
+        assert_eq!(svg.glyph_document(11), None);
+    }
+
+    #[test]
+    fn font_svg_glyph() {
+        use font::Font;
+        use image::Rgba;
+        use test_utils::{font_buf, with_tables};
+
+        let document = br#"<svg><path id="glyph3" d="M0 0H1024V-2048H0z" fill="currentColor"/></svg>"#;
+        let svg = build_svg(&[(3, 3, gzip(document))]);
+        let font_buf = font_buf();
+        let buf = with_tables(&font_buf, &[(b"SVG ", svg)]);
+        let font = Font::from_buffer(&buf).unwrap();
+
+        let blue = Rgba { data: [0, 0, 255, 255] };
+        let image = font.render_color_glyph(3, 16, 0, blue).unwrap();
+        assert_eq!(image.dimensions(), (8, 16));
+        assert!(image.pixels().all(|pixel| *pixel == blue));
+        assert!(font.render_svg_glyph(4, 16, blue).is_none());
+    }
+}
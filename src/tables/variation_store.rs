@@ -0,0 +1,205 @@
+use parse::{BufView, Parse};
+use parse::primitives::F2Dot14;
+use tables::gvar::region_scalar;
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/otvarcommonformats#item-variation-store
+
+/// Deltas for values outside of the outlines (advances, font metrics, ...),
+/// shared by `HVAR`, `VVAR` and `MVAR`
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct ItemVariationStore<'a> {
+    table: BufView<'a, u8>,
+    format: u16,
+    variation_region_list_offset: u32,
+    item_variation_data_count: u16,
+}
+
+#[derive(Debug, Parse)]
+struct RegionAxisCoordinates {
+    start_coord: F2Dot14,
+    peak_coord: F2Dot14,
+    end_coord: F2Dot14,
+}
+
+// Word delta count flags
+const LONG_WORDS: u16 = 0x8000;
+const WORD_DELTA_COUNT_MASK: u16 = 0x7FFF;
+
+impl<'a> ItemVariationStore<'a> {
+    fn region_scalar(&self, region: u16, coords: &[f32]) -> f32 {
+        let list: BufView<u8> = self.table.at_offset(self.variation_region_list_offset as usize);
+        let axis_count: u16 = list.at_offset(0);
+        let region_count: u16 = list.at_offset(2);
+        if region >= region_count {
+            return 0.;
+        }
+        let region_offset = 4 + region as usize * axis_count as usize * 6;
+        let mut start = Vec::with_capacity(axis_count as usize);
+        let mut peak = Vec::with_capacity(axis_count as usize);
+        let mut end = Vec::with_capacity(axis_count as usize);
+        for axis in 0..axis_count as usize {
+            let coords: RegionAxisCoordinates = list.at_offset(region_offset + axis * 6);
+            start.push(coords.start_coord.0);
+            peak.push(coords.peak_coord.0);
+            end.push(coords.end_coord.0);
+        }
+        region_scalar(coords, &peak, Some(&(start, end)))
+    }
+
+    /// The scalars at `coords` of the regions one item variation data
+    /// subtable uses, in order. CFF2 `blend`s apply deltas with these.
+    pub(crate) fn region_scalars(&self, outer: u16, coords: &[f32]) -> Option<Vec<f32>> {
+        if outer >= self.item_variation_data_count {
+            return None;
+        }
+        let data_offset: u32 = self.table.at_offset(8 + outer as usize * 4);
+        let data: BufView<u8> = self.table.at_offset(data_offset as usize);
+        let region_index_count: u16 = data.at_offset(4);
+        let at_default = coords.iter().all(|&coord| coord == 0.);
+        Some((0..region_index_count as usize)
+            .map(|idx| if at_default {
+                0.
+            } else {
+                self.region_scalar(data.at_offset(6 + idx * 2), coords)
+            })
+            .collect())
+    }
+
+    /// The delta for an item at the normalized `coords`
+    pub fn delta(&self, outer: u16, inner: u16, coords: &[f32]) -> f32 {
+        if outer >= self.item_variation_data_count || coords.iter().all(|&coord| coord == 0.) {
+            return 0.;
+        }
+        let data_offset: u32 = self.table.at_offset(8 + outer as usize * 4);
+        let data: BufView<u8> = self.table.at_offset(data_offset as usize);
+        let item_count: u16 = data.at_offset(0);
+        let word_delta_count: u16 = data.at_offset(2);
+        let region_index_count: u16 = data.at_offset(4);
+        if inner >= item_count {
+            return 0.;
+        }
+
+        // Word deltas come first, then the short ones
+        let long_words = word_delta_count & LONG_WORDS != 0;
+        let word_count = (word_delta_count & WORD_DELTA_COUNT_MASK) as usize;
+        if word_count > region_index_count as usize {
+            return 0.;
+        }
+        let (word_size, short_size) = if long_words { (4, 2) } else { (2, 1) };
+        let row_size = word_count * word_size + (region_index_count as usize - word_count) * short_size;
+        let row_offset = 6 + region_index_count as usize * 2 + inner as usize * row_size;
+
+        let mut delta = 0.;
+        let mut offset = row_offset;
+        for idx in 0..region_index_count as usize {
+            let region: u16 = data.at_offset(6 + idx * 2);
+            let (value, size) = match (idx < word_count, long_words) {
+                (true, true) => (data.at_offset::<i32>(offset) as f32, 4),
+                (true, false) | (false, true) => (data.at_offset::<i16>(offset) as f32, 2),
+                (false, false) => (data.at_offset::<i8>(offset) as f32, 1),
+            };
+            offset += size;
+            if value != 0. {
+                delta += value * self.region_scalar(region, coords);
+            }
+        }
+        delta
+    }
+}
+
+// Entry format flags
+const INNER_INDEX_BIT_COUNT_MASK: u8 = 0x0F;
+const MAP_ENTRY_SIZE_MASK: u8 = 0x30;
+
+/// Maps glyph ids to (outer, inner) indices in an `ItemVariationStore`
+#[derive(Debug, Parse)]
+pub struct DeltaSetIndexMap<'a> {
+    table: BufView<'a, u8>,
+    format: u8,
+    entry_format: u8,
+}
+
+impl<'a> DeltaSetIndexMap<'a> {
+    pub fn index(&self, idx: u32) -> (u16, u16) {
+        let (map_count, entries) = if self.format == 0 {
+            (self.table.at_offset::<u16>(2) as u32, 4)
+        } else {
+            (self.table.at_offset::<u32>(2), 6)
+        };
+        if map_count == 0 {
+            return (0, idx as u16);
+        }
+        // Ids past the end use the last entry
+        let idx = idx.min(map_count - 1) as usize;
+        let entry_size = ((self.entry_format & MAP_ENTRY_SIZE_MASK) >> 4) as usize + 1;
+        let entry = (0..entry_size)
+            .map(|byte| self.table.at_offset::<u8>(entries + idx * entry_size + byte))
+            .fold(0u32, |entry, byte| entry << 8 | byte as u32);
+        let inner_bits = (self.entry_format & INNER_INDEX_BIT_COUNT_MASK) as u32 + 1;
+        ((entry >> inner_bits) as u16, (entry & ((1 << inner_bits) - 1)) as u16)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use test_utils::{push_u16, push_u32};
+
+    /// A store over one axis with regions peaking at 1 and -1, and one item
+    /// per entry of `deltas` moving that much at each
+    pub fn build_store(deltas: &[(i16, i16)]) -> Vec<u8> {
+        let mut store = Vec::new();
+        push_u16(&mut store, 1);
+        push_u32(&mut store, 12);
+        push_u16(&mut store, 1);
+        push_u32(&mut store, 12 + 16);
+        // Regions
+        push_u16(&mut store, 1);
+        push_u16(&mut store, 2);
+        for &val in &[0, 16384, 16384, -16384i16, -16384, 0] {
+            push_u16(&mut store, val as u16);
+        }
+        // Item data, the first region's deltas are words
+        push_u16(&mut store, deltas.len() as u16);
+        push_u16(&mut store, 1);
+        push_u16(&mut store, 2);
+        push_u16(&mut store, 0);
+        push_u16(&mut store, 1);
+        for &(up, down) in deltas {
+            push_u16(&mut store, up as u16);
+            store.push(down as i8 as u8);
+        }
+        store
+    }
+
+    #[test]
+    fn item_deltas() {
+        let store = build_store(&[(100, -20), (-300, 50)]);
+        let store = ItemVariationStore::parse(&store).1;
+        assert_eq!(store.delta(0, 0, &[1.]), 100.);
+        assert_eq!(store.delta(0, 0, &[0.5]), 50.);
+        assert_eq!(store.delta(0, 0, &[-0.5]), -10.);
+        assert_eq!(store.delta(0, 1, &[-1.]), 50.);
+        assert_eq!(store.delta(0, 1, &[0.]), 0.);
+        assert_eq!(store.delta(0, 2, &[1.]), 0.);
+        assert_eq!(store.delta(1, 0, &[1.]), 0.);
+
+        // More word deltas than regions
+        let mut store = build_store(&[(100, -20)]);
+        store[31] = 3;
+        let store = ItemVariationStore::parse(&store).1;
+        assert_eq!(store.delta(0, 0, &[1.]), 0.);
+    }
+
+    #[test]
+    fn index_map() {
+        // Two byte entries with 4 inner bits
+        let map = [0, 0x13, 0, 3, 0x00, 0x05, 0x00, 0x12, 0x01, 0x00];
+        let map = DeltaSetIndexMap::parse(&map).1;
+        assert_eq!(map.index(0), (0, 5));
+        assert_eq!(map.index(1), (1, 2));
+        assert_eq!(map.index(2), (16, 0));
+        assert_eq!(map.index(7), (16, 0));
+    }
+}
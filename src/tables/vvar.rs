@@ -0,0 +1,61 @@
+use parse::{BufView, Parse};
+use tables::{PrimaryTable, TableTag};
+use tables::variation_store::{DeltaSetIndexMap, ItemVariationStore};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/vvar
+
+/// Vertical metric variations
+#[allow(dead_code)]
+#[derive(Debug, Parse)]
+pub struct VVAR<'a> {
+    table: BufView<'a, u8>,
+    major_version: u16,
+    minor_version: u16,
+    item_variation_store_offset: u32,
+    advance_height_mapping_offset: u32,
+    tsb_mapping_offset: u32,
+    bsb_mapping_offset: u32,
+    v_org_mapping_offset: u32,
+}
+
+impl<'a> PrimaryTable for VVAR<'a> {
+    fn tag() -> TableTag {
+        TableTag::VerticalMetricsVariation
+    }
+}
+
+impl<'a> VVAR<'a> {
+    fn delta(&self, mapping_offset: u32, glyph_id: u32, coords: &[f32]) -> f32 {
+        let store: ItemVariationStore = self.table.at_offset(self.item_variation_store_offset as usize);
+        let (outer, inner) = if mapping_offset != 0 {
+            let map: DeltaSetIndexMap = self.table.at_offset(mapping_offset as usize);
+            map.index(glyph_id)
+        } else {
+            (0, glyph_id as u16)
+        };
+        store.delta(outer, inner, coords)
+    }
+
+    /// How much the glyph's advance height changes at `coords`
+    pub fn advance_height_delta(&self, glyph_id: u32, coords: &[f32]) -> f32 {
+        self.delta(self.advance_height_mapping_offset, glyph_id, coords)
+    }
+
+    /// How much the glyph's top side bearing changes at `coords`. `None` if
+    /// it should come from the varied outline instead.
+    pub fn tsb_delta(&self, glyph_id: u32, coords: &[f32]) -> Option<f32> {
+        if self.tsb_mapping_offset == 0 {
+            return None;
+        }
+        Some(self.delta(self.tsb_mapping_offset, glyph_id, coords))
+    }
+
+    /// How much the glyph's bottom side bearing changes at `coords`. `None`
+    /// if it should come from the varied outline instead.
+    pub fn bsb_delta(&self, glyph_id: u32, coords: &[f32]) -> Option<f32> {
+        if self.bsb_mapping_offset == 0 {
+            return None;
+        }
+        Some(self.delta(self.bsb_mapping_offset, glyph_id, coords))
+    }
+}